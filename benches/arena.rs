@@ -0,0 +1,86 @@
+use std::time::{Duration, Instant};
+
+use ferus::lexer::{Literal};
+use ferus::expr::{Expr, parse};
+use ferus::expr::arena::{ExprArena, Node};
+use ferus::expr::visit::{ExprVisitor};
+use ferus::optimize::{fold_constants};
+
+// a list of `count` small expressions with some arithmetic to walk
+fn program(count: usize) -> String {
+    let elements: Vec<String> = (0..count)
+        .map(|i| format!("let val x = {} in if x < 3 then (x + 1) * 2 else fst (x, 4 - 2) end", i))
+        .collect();
+    format!("[{}]", elements.join(", "))
+}
+
+struct Sum(i64);
+
+impl<'a> ExprVisitor<'a> for Sum {
+    fn visit_lit(&mut self, lit: &Literal<'a>) {
+        if let Literal::Integer(i) = lit {
+            self.0 += i
+        }
+    }
+}
+
+fn sum_boxed(expr: &Expr) -> i64 {
+    let mut sum = Sum(0);
+    sum.visit_expr(expr);
+    sum.0
+}
+
+fn sum_arena(arena: &ExprArena) -> i64 {
+    arena.nodes().map(|(_, node)| if let Node::Lit(Literal::Integer(i)) = node { *i } else { 0 }).sum()
+}
+
+fn time<T>(f: impl FnOnce() -> T) -> (T, Duration) {
+    let start = Instant::now();
+    let res = f();
+    (res, start.elapsed())
+}
+
+// parsing into boxes and walking them against also copying the tree into
+// an arena and walking that, then folding, resolving and evaluating either
+// and freeing it, run with `cargo bench`
+fn main() {
+    const WALKS: usize = 100;
+    for &count in &[100, 1_000, 10_000] {
+        let source = program(count);
+        let (expr, parsing) = time(|| parse(&source).unwrap());
+        let (boxed, boxed_walks) = time(|| (0..WALKS).map(|_| sum_boxed(&expr)).sum::<i64>());
+        let ((arena, root), copying) = time(|| {
+            let mut arena = ExprArena::new();
+            let root = arena.alloc(&expr);
+            (arena, root)
+        });
+        let (flat, arena_walks) = time(|| (0..WALKS).map(|_| sum_arena(&arena)).sum::<i64>());
+        assert_eq!(boxed, flat);
+
+        let copy = expr.clone();
+        let (boxed, boxed_fold) = time(|| fold_constants(copy));
+        let mut folded = arena.clone();
+        let ((), arena_fold) = time(|| folded.fold_constants());
+        assert_eq!(boxed, folded.expr(root));
+        let (boxed, boxed_free) = time(|| expr.free_variables());
+        let (flat, arena_free) = time(|| arena.free_variables(root));
+        assert_eq!(boxed, flat);
+        let copy = expr.clone();
+        let (boxed, boxed_eval) = time(|| copy.eval().unwrap().to_string());
+        let (flat, arena_eval) = time(|| arena.eval(root).unwrap().to_string());
+        assert_eq!(boxed, flat);
+        let nodes = arena.len();
+        let ((), boxed_drop) = time(|| drop(expr));
+        let ((), arena_drop) = time(|| drop(arena));
+        println!(
+            "{:>6} elements, {:>6} nodes: parse {:>9.2?}, {} walks boxed {:>9.2?} arena {:>9.2?} \
+             (copying {:>9.2?}), free boxed {:>9.2?} arena {:>9.2?}",
+            count, nodes, parsing, WALKS, boxed_walks, arena_walks, copying, boxed_drop, arena_drop,
+        );
+        println!(
+            "{:>6} elements: fold boxed {:>9.2?} arena {:>9.2?}, free variables boxed {:>9.2?} arena {:>9.2?}, \
+             eval boxed {:>9.2?} arena {:>9.2?}",
+            count, boxed_fold, arena_fold, boxed_free, arena_free, boxed_eval, arena_eval,
+        );
+    }
+}
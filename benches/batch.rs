@@ -0,0 +1,40 @@
+use std::time::{Duration, Instant};
+
+use ferus::expr::{parse};
+use ferus::vm::{compile, compile_batch};
+
+// small rules like the ones a rules engine loads by the thousand
+fn rules(count: usize) -> Vec<String> {
+    (0..count)
+        .map(|i| format!(
+            "let val limit = {} in fn order => if #total order > limit andalso #items order < {} \
+             then {{discount = limit div 10, rule = {}}} else {{discount = 0, rule = {}}} end",
+            i * 10, i % 7 + 1, i, i,
+        ))
+        .collect()
+}
+
+fn per_second(count: usize, took: Duration) -> f64 {
+    count as f64 / took.as_secs_f64()
+}
+
+fn main() {
+    for &count in &[100, 1_000, 10_000] {
+        let rules = rules(count);
+        let sources: Vec<&str> = rules.iter().map(String::as_str).collect();
+
+        let start = Instant::now();
+        let one_at_a_time: Vec<_> = sources.iter().map(|source| parse(source).map(|expr| compile(&expr))).collect();
+        let sequential = start.elapsed();
+
+        let start = Instant::now();
+        let batched = compile_batch(&sources);
+        let batch = start.elapsed();
+
+        assert_eq!(one_at_a_time, batched);
+        println!(
+            "{:>6} rules: one at a time {:>10.0}/s, batch {:>10.0}/s",
+            count, per_second(count, sequential), per_second(count, batch),
+        );
+    }
+}
@@ -0,0 +1,42 @@
+use std::time::{Duration, Instant};
+
+use ferus::expr::{Expr, parse};
+use ferus::expr::eval::{Env, EvalConfig, EvalStrategy};
+use ferus::expr::eval::closures::{compile};
+
+const PROGRAMS: [(&str, &str); 3] = [
+    ("fib", "let fun fib n = if n < 2 then n else fib (n - 1) + fib (n - 2) in fib 20 end"),
+    ("count", "let fun count n = fn acc => if n = 0 then acc else count (n - 1) (acc + 1) in count 20000 0 end"),
+    ("rules", "let val age = 30 val member = true in \
+               if member andalso age >= 18 andalso age < 65 then age * 2 + 1 else age - 1 end"),
+];
+
+fn time<T>(runs: usize, mut f: impl FnMut() -> T) -> (T, Duration) {
+    let start = Instant::now();
+    let mut res = f();
+    for _ in 1..runs {
+        res = f();
+    }
+    (res, start.elapsed() / runs as u32)
+}
+
+// walking the tree against compiling it to closures on every evaluation and
+// against compiling it once and running that, run with `cargo bench`
+fn main() {
+    let closures = EvalConfig{ strategy: EvalStrategy::Closures, ..EvalConfig::default() };
+    for &(name, source) in &PROGRAMS {
+        let expr = parse(source).unwrap();
+        let runs = if name == "rules" { 100_000 } else { 20 };
+        let eval = |expr: &Expr<'static>, config| expr.clone().eval_with(config).unwrap().to_string();
+        let (tree, tree_time) = time(runs, || eval(&expr, EvalConfig::default()));
+        let (each, each_time) = time(runs, || eval(&expr, closures));
+        let program = compile(&expr);
+        let (once, once_time) = time(runs, || program.run(&mut Env::with_config(closures)).unwrap().to_string());
+        assert_eq!((&tree, &tree), (&each, &once));
+        println!(
+            "{:>6}: tree {:>10.3?}, closures {:>10.3?} ({:.1}x), compiled once {:>10.3?} ({:.1}x)",
+            name, tree_time, each_time, tree_time.as_secs_f64() / each_time.as_secs_f64(),
+            once_time, tree_time.as_secs_f64() / once_time.as_secs_f64(),
+        );
+    }
+}
@@ -0,0 +1,96 @@
+use std::time::{Duration, Instant};
+
+use ferus::expr::{parse};
+use ferus::lexer::{Literal};
+use ferus::vm::{Instr, Program, compile, run};
+
+// rules over variables a host binds, the shapes `compile` fuses
+fn rules(count: usize) -> Vec<String> {
+    (0..count)
+        .map(|i| format!(
+            "let val age = {} val member = {} val vip = {} in \
+             if member andalso vip orelse age >= 18 andalso age < 65 andalso age <> 40 \
+             then age * 2 + 1 else age - {} end",
+            i % 90, i % 3 == 0, i % 5 == 0, i % 7,
+        ))
+        .collect()
+}
+
+// the instructions `compile` gave each fused one before it fused them, at
+// `at`. the targets of jumps are moved to where `starts` says they went.
+// the loads go without names, which are only shown
+fn expand(instr: &Instr<'static>, at: usize, starts: &[usize]) -> Vec<Instr<'static>> {
+    let load = |slot| Instr::Load(slot, "");
+    match *instr {
+        Instr::LoadBinary(slot, operation, i) => {
+            vec![load(slot), Instr::Push(Literal::Integer(i)), Instr::Binary(operation)]
+        },
+        Instr::LoadOrElse(left, right) => vec![
+            load(left), Instr::JumpIfFalse(at + 4), Instr::Push(Literal::Boolean(true)), Instr::Jump(at + 6),
+            load(right), Instr::Bool,
+        ],
+        Instr::LoadAndAlso(left, right) => vec![
+            load(left), Instr::JumpIfFalse(at + 5), load(right), Instr::Bool, Instr::Jump(at + 6),
+            Instr::Push(Literal::Boolean(false)),
+        ],
+        Instr::Jump(to) => vec![Instr::Jump(starts[to])],
+        Instr::JumpIfFalse(to) => vec![Instr::JumpIfFalse(starts[to])],
+        Instr::Try(to) => vec![Instr::Try(starts[to])],
+        Instr::Match(ref pattern, to) => vec![Instr::Match(pattern.clone(), starts[to])],
+        ref instr => vec![instr.clone()],
+    }
+}
+
+// `program` as it was compiled before fusing
+fn unfused(program: &Program<'static>) -> Program<'static> {
+    let blocks = program.blocks.iter()
+        .map(|block| {
+            let mut starts = vec![];
+            let mut len = 0;
+            for instr in block.iter() {
+                starts.push(len);
+                len += match instr {
+                    Instr::LoadBinary(..) => 3,
+                    Instr::LoadOrElse(..) | Instr::LoadAndAlso(..) => 6,
+                    _ => 1,
+                };
+            }
+            starts.push(len);
+            block.iter().zip(starts.iter()).flat_map(|(instr, at)| expand(instr, *at, &starts)).collect()
+        })
+        .collect();
+    Program { blocks, constants: program.constants.clone() }
+}
+
+fn time_runs(programs: &[Program<'static>], runs: usize) -> (Vec<String>, Duration) {
+    let start = Instant::now();
+    let mut values = vec![];
+    for _ in 0..runs {
+        values = programs.iter().map(|program| run(program).unwrap()).collect();
+    }
+    let took = start.elapsed();
+    (values.iter().map(|value| value.to_string()).collect(), took)
+}
+
+// running rules compiled with and without fused instructions, which
+// should agree and be no slower fused, run with `cargo bench`
+fn main() {
+    const RUNS: usize = 100;
+    for &count in &[100, 1_000, 10_000] {
+        let sources: Vec<&'static str> = rules(count).into_iter()
+            .map(|source| &*Box::leak(source.into_boxed_str()))
+            .collect();
+        let fused: Vec<Program<'static>> = sources.iter().map(|source| compile(&parse(source).unwrap())).collect();
+        let plain: Vec<Program<'static>> = fused.iter().map(unfused).collect();
+        let size = |programs: &[Program]| programs.iter().map(|program| program.blocks[0].len()).sum::<usize>();
+        assert!(size(&fused) < size(&plain));
+        let (fused_values, fused_time) = time_runs(&fused, RUNS);
+        let (plain_values, plain_time) = time_runs(&plain, RUNS);
+        assert_eq!(fused_values, plain_values);
+        println!(
+            "{:>6} rules: fused {:>8.2}ms ({} instructions), unfused {:>8.2}ms ({} instructions)",
+            count, fused_time.as_secs_f64() * 1e3 / RUNS as f64, size(&fused),
+            plain_time.as_secs_f64() * 1e3 / RUNS as f64, size(&plain),
+        );
+    }
+}
@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// whatever the text, `parse_str` gives back a tree or an error that can be
+// printed, it never panics and never runs out of stack
+fuzz_target!(|data: &[u8]| {
+    if let Ok(source) = std::str::from_utf8(data) {
+        let _ = ferus::parse_str(source).map_err(|err| err.to_string());
+    }
+});
@@ -0,0 +1,218 @@
+use crate::expr::{Expr};
+use crate::expr::owned::{OwnedExpr};
+use crate::expr::step::{Progress};
+use crate::lexer::{Span};
+use crate::locale::{message};
+use crate::render::{Rendering};
+
+// one picture of an evaluation: the expression about to take a step with
+// the redex marked, or the value at the end
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub source: String,
+    pub redex: Option<Span>,
+    // what the step out of this frame printed
+    pub printed: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Ending {
+    Value,
+    // the last frame marks the node that could not reduce
+    Stuck,
+    // a `raise` of the value nothing handled
+    Raised(String),
+    OutOfSteps,
+}
+
+// what a run cost, for `ferus run --stats`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Stats {
+    pub steps: usize,
+    // the deepest a redex sat inside nodes waiting on its value
+    pub peak_depth: usize,
+    // the steps that made a value
+    pub allocated: usize,
+    // the biggest the expression grew, in nodes, everything the machine
+    // keeps alive is in it
+    pub peak_residency: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Animation {
+    pub frames: Vec<Frame>,
+    pub ending: Ending,
+    pub stats: Stats,
+}
+
+// runs the small step machine for at most `max_steps` steps
+pub fn animate(expr: &Expr, max_steps: usize) -> Animation {
+    let mut expr: OwnedExpr = expr.clone().into_owned();
+    let mut frames = vec![];
+    let mut stats = Stats::default();
+    for _ in 0..max_steps {
+        let current = expr.as_expr();
+        stats.peak_residency = stats.peak_residency.max(current.size() as usize);
+        match current.step() {
+            Progress::Step(step) => {
+                let (source, redex) = current.to_source_marking(step.redex);
+                frames.push(Frame { source, redex, printed: step.printed });
+                stats.steps += 1;
+                stats.peak_depth = stats.peak_depth.max(step.depth);
+                stats.allocated += step.value as usize;
+                expr = step.expr;
+            },
+            Progress::Value => {
+                frames.push(Frame { source: current.to_source(), redex: None, printed: None });
+                return Animation { frames, ending: Ending::Value, stats }
+            },
+            Progress::Raised => {
+                let raised = match &expr {
+                    OwnedExpr::Raise(value) => value.to_string(),
+                    expr => expr.to_string(),
+                };
+                frames.push(Frame { source: current.to_source(), redex: None, printed: None });
+                return Animation { frames, ending: Ending::Raised(raised), stats }
+            },
+            Progress::Stuck(id) => {
+                let (source, redex) = current.to_source_marking(id);
+                frames.push(Frame { source, redex, printed: None });
+                return Animation { frames, ending: Ending::Stuck, stats }
+            },
+        }
+    }
+    Animation { frames, ending: Ending::OutOfSteps, stats }
+}
+
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+impl Frame {
+    // the text before, inside and after the redex
+    fn split(&self) -> (&str, &str, &str) {
+        match self.redex {
+            Some(span) => (&self.source[..span.start], &self.source[span.start..span.end], &self.source[span.end..]),
+            None => (&self.source, "", ""),
+        }
+    }
+    // frame `number` for a terminal, the redex in reverse video or named
+    // out loud
+    pub fn render(&self, number: usize, rendering: Rendering) -> String {
+        let (before, redex, after) = self.split();
+        match rendering {
+            Rendering::Visual if redex.is_empty() => self.source.clone(),
+            Rendering::Visual => format!("{}\x1b[7m{}\x1b[0m{}", before, redex, after),
+            Rendering::Linear if redex.is_empty() => message("animate-step", &[&number, &self.source]),
+            Rendering::Linear => format!(
+                "{}\n{}",
+                message("animate-step", &[&number, &self.source]),
+                message("animate-redex", &[&redex])
+            ),
+        }
+    }
+    // a picture of the frame for slides, one line of monospace text over a
+    // highlight behind the redex
+    pub fn svg(&self) -> String {
+        const CHAR: f64 = 9.6;
+        const PAD: f64 = 16.0;
+        let (before, redex, _) = self.split();
+        let width = PAD * 2.0 + CHAR * self.source.chars().count() as f64;
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"48\" viewBox=\"0 0 {} 48\">\n",
+            width, width
+        );
+        svg.push_str(&format!("<rect width=\"{}\" height=\"48\" fill=\"white\"/>\n", width));
+        if !redex.is_empty() {
+            svg.push_str(&format!(
+                "<rect x=\"{}\" y=\"10\" width=\"{}\" height=\"28\" rx=\"4\" fill=\"#ffe08a\"/>\n",
+                PAD + CHAR * before.chars().count() as f64,
+                CHAR * redex.chars().count() as f64
+            ));
+        }
+        svg.push_str(&format!(
+            "<text x=\"{}\" y=\"30\" font-family=\"monospace\" font-size=\"16\" xml:space=\"preserve\">{}</text>\n",
+            PAD, escape(&self.source)
+        ));
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+impl Stats {
+    pub fn render(&self) -> String {
+        [
+            message("stats-steps", &[&self.steps]),
+            message("stats-depth", &[&self.peak_depth]),
+            message("stats-allocated", &[&self.allocated]),
+            message("stats-residency", &[&self.peak_residency]),
+        ].join("\n")
+    }
+    pub fn json(&self) -> String {
+        format!(
+            "{{\"steps\": {}, \"peak_depth\": {}, \"allocated\": {}, \"peak_residency\": {}}}\n",
+            self.steps, self.peak_depth, self.allocated, self.peak_residency
+        )
+    }
+}
+
+impl Animation {
+    // every frame on a page of its own, for showing in a browser
+    pub fn html(&self) -> String {
+        let mut html = String::from(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>ferus</title>\n<style>\n\
+             section { min-height: 100vh; display: flex; flex-direction: column; justify-content: center; }\n\
+             pre { font-size: 2em; margin: 0 2em; white-space: pre-wrap; }\n\
+             mark { background: #ffe08a; }\n\
+             </style>\n</head>\n<body>\n"
+        );
+        for (i, frame) in self.frames.iter().enumerate() {
+            let (before, redex, after) = frame.split();
+            let code = if redex.is_empty() {
+                escape(before)
+            } else {
+                format!("{}<mark>{}</mark>{}", escape(before), escape(redex), escape(after))
+            };
+            html.push_str(&format!("<section id=\"step-{}\">\n<pre>{}</pre>\n", i + 1, code));
+            if let Some(printed) = &frame.printed {
+                html.push_str(&format!("<pre>&gt; {}</pre>\n", escape(printed)));
+            }
+            html.push_str("</section>\n");
+        }
+        html.push_str("</body>\n</html>\n");
+        html
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::{parse};
+
+    #[test]
+    fn animate_unit() {
+        let animation = animate(&parse("(fn x => x * 2) (1 + 2)").unwrap(), 100);
+        assert_eq!(animation.ending, Ending::Value);
+        let frames: Vec<String> = animation.frames.iter()
+            .map(|frame| frame.render(1, Rendering::Visual).replace("\x1b[7m", "[").replace("\x1b[0m", "]"))
+            .collect();
+        assert_eq!(frames, vec!["(fn x => x * 2) ([1 + 2])", "[(fn x => x * 2) 3]", "[3 * 2]", "6"]);
+        assert_eq!(animation.frames[2].render(3, Rendering::Linear), "step 3: 3 * 2\nreducing `3 * 2`");
+        assert!(animation.frames[0].svg().contains("<rect x=\"179.2\" y=\"10\" width=\"48\""));
+        assert!(animation.html().contains("<pre>(fn x =&gt; x * 2) (<mark>1 + 2</mark>)</pre>"));
+
+        assert_eq!(animate(&parse("(print 1; 1 + true)").unwrap(), 100).ending, Ending::Stuck);
+        let raised = animate(&parse("1 + 2 div 0").unwrap(), 100);
+        assert_eq!(raised.ending, Ending::Raised("Div".to_string()));
+        assert_eq!(raised.frames.last().unwrap().source, "raise Div");
+        let looping = animate(&parse("let fun f n = f n in f 1 end").unwrap(), 10);
+        assert_eq!((looping.ending, looping.frames.len()), (Ending::OutOfSteps, 10));
+
+        let stats = animate(&parse("(fn x => x * 2) (1 + 2)").unwrap(), 100).stats;
+        assert_eq!(stats, Stats { steps: 3, peak_depth: 2, allocated: 2, peak_residency: 10 });
+        assert_eq!(stats.json(), "{\"steps\": 3, \"peak_depth\": 2, \"allocated\": 2, \"peak_residency\": 10}\n");
+        // every pending `1 +` is a frame deeper
+        let deep = animate(&parse("let fun f n = if n = 0 then 0 else 1 + f (n - 1) in f 3 end").unwrap(), 100).stats;
+        assert_eq!(deep.render().lines().nth(1), Some("peak stack depth: 5"));
+    }
+}
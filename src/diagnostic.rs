@@ -0,0 +1,131 @@
+use crate::lexer::{Span};
+use crate::error::{render_snippet};
+use crate::render::{Rendering};
+
+const PRIMARY: &str = "\x1b[1;31m";
+const SECONDARY: &str = "\x1b[1;34m";
+const RESET: &str = "\x1b[0m";
+
+// a part of the source a diagnostic points at and what to say about it
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+    // the part the diagnostic is about, underlined with `^`, rather than one
+    // that explains it, underlined with `-`
+    pub primary: bool,
+}
+
+// the source snippets under an error or warning. the lines the labels are
+// on are printed once each, in order, with every label on a line underlined
+// beneath it and its message after the underline
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Diagnostic {
+    pub labels: Vec<Label>,
+}
+
+impl Diagnostic {
+    pub fn new(span: Span, message: String) -> Diagnostic {
+        Diagnostic { labels: vec![Label { span, message, primary: true }] }
+    }
+    // another part of the source that explains the first, like where the
+    // construct a missing keyword would close started
+    pub fn label(mut self, span: Span, message: String) -> Diagnostic {
+        self.labels.push(Label { span, message, primary: false });
+        self
+    }
+    pub fn render(&self, source: &str, rendering: Rendering, colors: bool) -> String {
+        match rendering {
+            Rendering::Visual => self.visual(source, colors),
+            // each label said out loud in turn, the primary one first
+            Rendering::Linear => {
+                let mut labels: Vec<&Label> = self.labels.iter().collect();
+                labels.sort_by_key(|label| !label.primary);
+                let said: Vec<String> = labels.iter()
+                    .map(|label| match label.message.as_str() {
+                        "" => render_snippet(source, label.span, rendering),
+                        message => format!("{}\n{}", render_snippet(source, label.span, rendering), message),
+                    })
+                    .collect();
+                said.join("\n")
+            },
+        }
+    }
+    fn visual(&self, source: &str, colors: bool) -> String {
+        let mut marks: Vec<(usize, usize, &Label)> = self.labels.iter()
+            .map(|label| {
+                let (line, col) = label.span.line_col(source);
+                (line, col, label)
+            })
+            .collect();
+        marks.sort_by_key(|(line, col, label)| (*line, !label.primary, *col));
+        let mut lines: Vec<usize> = marks.iter().map(|(line, _, _)| *line).collect();
+        lines.dedup();
+        // a single line needs no number, the message above says which it is
+        let numbered = lines.len() > 1;
+        let width = lines.last().filter(|_| numbered).map_or(1, |last| last.to_string().len());
+        let mut out = vec![];
+        for line in lines {
+            let text = source.lines().nth(line - 1).unwrap_or("");
+            let number = if numbered { line.to_string() } else { String::new() };
+            out.push(format!("{:>width$} | {}", number, text, width = width));
+            for (_, col, label) in marks.iter().filter(|(l, _, _)| *l == line) {
+                // only as far as the end of the line for a span running on
+                let marked = &source[label.span.start..label.span.end];
+                let len = marked.split('\n').next().unwrap_or("").chars().count().max(1);
+                let mut underline = if label.primary { "^" } else { "-" }.repeat(len);
+                if !label.message.is_empty() {
+                    underline = format!("{} {}", underline, label.message);
+                }
+                if colors {
+                    let color = if label.primary { PRIMARY } else { SECONDARY };
+                    underline = format!("{}{}{}", color, underline, RESET);
+                }
+                out.push(format!("{:width$} | {}{}", "", " ".repeat(col - 1), underline, width = width));
+            }
+        }
+        out.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diagnostic_unit() {
+        let source = "if x < 1 1 else 2";
+        let diagnostic = Diagnostic::new(Span::new(9, 10), "expected `then`".to_string())
+            .label(Span::new(0, 2), "`if` started here".to_string());
+        assert_eq!(diagnostic.render(source, Rendering::Visual, false), concat!(
+            "  | if x < 1 1 else 2\n",
+            "  |          ^ expected `then`\n",
+            "  | -- `if` started here",
+        ));
+        assert_eq!(diagnostic.render(source, Rendering::Visual, true), concat!(
+            "  | if x < 1 1 else 2\n",
+            "  |          \x1b[1;31m^ expected `then`\x1b[0m\n",
+            "  | \x1b[1;34m-- `if` started here\x1b[0m",
+        ));
+        assert_eq!(diagnostic.render(source, Rendering::Linear, false), concat!(
+            "line 1: if x < 1 1 else 2\nat column 10: `1`\nexpected `then`\n",
+            "line 1: if x < 1 1 else 2\nat column 1: `if`\n`if` started here",
+        ));
+
+        // lines apart are numbered, the primary one need not come first
+        let source = "let val x = 1 in\n  x +\n  2";
+        let diagnostic = Diagnostic::new(Span::new(25, 26), String::new())
+            .label(Span::new(0, 3), "`let` started here".to_string());
+        assert_eq!(diagnostic.render(source, Rendering::Visual, false), concat!(
+            "1 | let val x = 1 in\n",
+            "  | --- `let` started here\n",
+            "3 |   2\n",
+            "  |   ^",
+        ));
+        // as `error::snippet` always printed a single caret
+        let caret = Diagnostic::new(Span::new(4, 5), String::new());
+        assert_eq!(caret.render("x + + 2", Rendering::Visual, false), "  | x + + 2\n  |     ^");
+    }
+}
@@ -0,0 +1,8 @@
+pub mod brackets;
+pub mod folding;
+pub mod highlight;
+pub mod inlay;
+pub mod refactor;
+pub mod selection;
+pub mod signature;
+pub mod workspace;
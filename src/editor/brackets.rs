@@ -0,0 +1,165 @@
+use crate::lexer::{self, Delimiter, Direction, Reserved, Span, Token};
+
+// the constructs whose keywords or delimiters come in matching sets
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash)]
+pub enum Shape {
+    Paren,
+    Bracket,
+    Brace,
+    Let,
+    If,
+    While,
+}
+
+// one construct as the lexer sees it. `middle` is the `in` of a `let` or the
+// `then` of an `if`, `close` is the `end`, `else`, `do` or closing delimiter
+// and is `None` until it is typed. `span` covers all of it, an unclosed
+// construct runs up to whatever closed the one around it
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Construct {
+    pub shape: Shape,
+    pub open: Span,
+    pub middle: Option<Span>,
+    pub close: Option<Span>,
+    pub span: Span,
+}
+
+enum Role {
+    Open(Shape),
+    Middle(Shape),
+    Close(Shape),
+}
+
+fn role(token: &Token) -> Option<Role> {
+    use Role::*;
+    let role = match token {
+        Token::Delim(Delimiter::Paren(Direction::Left)) => Open(Shape::Paren),
+        Token::Delim(Delimiter::Paren(Direction::Right)) => Close(Shape::Paren),
+        Token::Delim(Delimiter::Bracket(Direction::Left)) => Open(Shape::Bracket),
+        Token::Delim(Delimiter::Bracket(Direction::Right)) => Close(Shape::Bracket),
+        Token::Delim(Delimiter::Brace(Direction::Left)) => Open(Shape::Brace),
+        Token::Delim(Delimiter::Brace(Direction::Right)) => Close(Shape::Brace),
+        Token::Keyword(Reserved::Let) => Open(Shape::Let),
+        Token::Keyword(Reserved::In) => Middle(Shape::Let),
+        Token::Keyword(Reserved::End) => Close(Shape::Let),
+        Token::Keyword(Reserved::If) => Open(Shape::If),
+        Token::Keyword(Reserved::Then) => Middle(Shape::If),
+        Token::Keyword(Reserved::Else) => Close(Shape::If),
+        Token::Keyword(Reserved::While) => Open(Shape::While),
+        Token::Keyword(Reserved::Do) => Close(Shape::While),
+        _ => return None,
+    };
+    Some(role)
+}
+
+// every construct of a source, worked out once from its tokens so an editor
+// can ask about any number of cursor positions without lexing again
+#[derive(Debug, Clone)]
+pub struct Brackets {
+    // in the order they open
+    constructs: Vec<Construct>,
+}
+
+impl Brackets {
+    pub fn new(source: &str) -> Brackets {
+        let mut constructs: Vec<Construct> = vec![];
+        let mut open: Vec<usize> = vec![];
+        for (span, token) in lexer::spanned(source) {
+            match role(&token) {
+                Some(Role::Open(shape)) => {
+                    open.push(constructs.len());
+                    constructs.push(Construct { shape, open: span, middle: None, close: None, span });
+                },
+                Some(Role::Middle(shape)) => if let Some(&top) = open.last() {
+                    let construct = &mut constructs[top];
+                    if construct.shape == shape && construct.middle.is_none() {
+                        construct.middle = Some(span)
+                    }
+                },
+                // a closer that matches nothing is ignored, one that skips
+                // over unclosed constructs ends them where it is
+                Some(Role::Close(shape)) => {
+                    if let Some(depth) = open.iter().rposition(|&i| constructs[i].shape == shape) {
+                        for i in open.drain(depth + 1..) {
+                            constructs[i].span.end = span.start
+                        }
+                        let construct = &mut constructs[open[depth]];
+                        construct.close = Some(span);
+                        construct.span.end = span.end;
+                        open.pop();
+                    }
+                },
+                None => {},
+            }
+        }
+        for i in open {
+            constructs[i].span.end = source.len()
+        }
+        Brackets { constructs }
+    }
+    pub fn constructs(&self) -> &[Construct] {
+        &self.constructs
+    }
+    // the other end of the delimiter or keyword at `offset`: the closer of
+    // an opener, the opener of a closer and the opener of a middle keyword
+    pub fn matching(&self, offset: usize) -> Option<Span> {
+        let contains = |span: &Span| span.start <= offset && offset < span.end;
+        self.constructs.iter().find_map(|construct| {
+            if contains(&construct.open) {
+                construct.close
+            } else if construct.middle.iter().chain(construct.close.iter()).any(contains) {
+                Some(construct.open)
+            } else {
+                None
+            }
+        })
+    }
+    // the innermost construct around `offset`, its own keywords and
+    // delimiters count as inside it
+    pub fn enclosing(&self, offset: usize) -> Option<&Construct> {
+        self.constructs.iter().rev()
+            .find(|construct| construct.span.start <= offset && offset < construct.span.end)
+    }
+}
+
+pub fn matching_delimiter(source: &str, offset: usize) -> Option<Span> {
+    Brackets::new(source).matching(offset)
+}
+
+pub fn enclosing_construct(source: &str, offset: usize) -> Option<Construct> {
+    Brackets::new(source).enclosing(offset).cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn brackets_unit() {
+        let source = "let val x = (1 + [2]) in if x then f (x) else 0 end";
+        let at = |text: &str| source.find(text).unwrap();
+        let span = |text: &str| Span::new(at(text), at(text) + text.len());
+        assert_eq!(matching_delimiter(source, 0), Some(span("end")));
+        assert_eq!(matching_delimiter(source, at("in")), Some(span("let")));
+        assert_eq!(matching_delimiter(source, at("]")), Some(span("[")));
+        assert_eq!(matching_delimiter(source, at("else")), Some(span("if")));
+        assert_eq!(matching_delimiter(source, at("x")), None);
+
+        let construct = enclosing_construct(source, at("f (")).unwrap();
+        assert_eq!((construct.shape, construct.middle), (Shape::If, Some(span("then"))));
+        assert_eq!(construct.span, Span::new(at("if"), at("else") + 4));
+        assert_eq!(enclosing_construct(source, at("2")).unwrap().shape, Shape::Bracket);
+        assert_eq!(enclosing_construct(source, at("0 end")).unwrap().shape, Shape::Let);
+
+        // half typed code still matches what it can, and a stray closer or
+        // a run of characters that does not lex is skipped
+        let source = "(let val y = [1 @ 2) =< ] end";
+        let brackets = Brackets::new(source);
+        assert_eq!(brackets.matching(0), Some(Span::new(19, 20)));
+        assert_eq!(brackets.matching(24), None);
+        assert_eq!(brackets.matching(1), None);
+        let unclosed = &brackets.constructs()[2];
+        assert_eq!((unclosed.shape, unclosed.span), (Shape::Bracket, Span::new(13, 19)));
+        assert_eq!(brackets.enclosing(26).map(|construct| construct.shape), None);
+    }
+}
@@ -0,0 +1,49 @@
+use crate::editor::brackets::{Brackets};
+use crate::expr::{parse_indexed};
+use crate::lexer::{Span};
+
+// the spans an editor can fold away: every `let … end`, `handle` arm and
+// other expression that runs over more than one line. only the outermost
+// of the ones starting on the same line is kept, since folding is by line,
+// and they come in the order they start. source that does not parse yet
+// folds its constructs as `Brackets` sees them
+pub fn folding_ranges(source: &str) -> Vec<Span> {
+    let spans: Vec<Span> = match parse_indexed(source) {
+        // ids are handed out in pre-order, so a node comes before the nodes
+        // inside it
+        Ok((_, table)) => table.iter().map(|(_, span)| span).collect(),
+        Err(_) => Brackets::new(source).constructs().iter().map(|construct| construct.span).collect(),
+    };
+    let mut ranges: Vec<Span> = vec![];
+    for span in spans {
+        let line = span.line_col(source).0;
+        let folded = ranges.last().map(|last| last.line_col(source).0);
+        if source[span.start..span.end].contains('\n') && folded != Some(line) {
+            ranges.push(span)
+        }
+    }
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folding_unit() {
+        let source = "let fun f n =\n  (print n;\n   n)\nin\n  f 1 handle Fail =>\n    2\nend";
+        let slices: Vec<&str> = folding_ranges(source).into_iter()
+            .map(|span| &source[span.start..span.end])
+            .collect();
+        assert_eq!(slices, vec![
+            source,
+            "(print n;\n   n)",
+            "f 1 handle Fail =>\n    2",
+        ]);
+        // half typed code still folds what it can
+        let source = "let val x = (1,\n 2) in\n  x";
+        let spans = folding_ranges(source);
+        assert_eq!(spans, vec![Span::new(0, source.len())]);
+        assert_eq!(folding_ranges("1 + 2"), vec![]);
+    }
+}
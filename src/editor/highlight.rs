@@ -0,0 +1,131 @@
+use crate::animate::{escape};
+use crate::lexer::{self, Reserved, Span, Token};
+
+// what a token is colored as
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash)]
+pub enum TokenClass {
+    Keyword,
+    Literal,
+    Identifier,
+    Operator,
+    // `( ) [ ] { } ; ,`
+    Punctuation,
+    Comment,
+}
+
+impl TokenClass {
+    // the css class `html` gives it
+    pub fn name(self) -> &'static str {
+        match self {
+            TokenClass::Keyword => "keyword",
+            TokenClass::Literal => "literal",
+            TokenClass::Identifier => "identifier",
+            TokenClass::Operator => "operator",
+            TokenClass::Punctuation => "punctuation",
+            TokenClass::Comment => "comment",
+        }
+    }
+}
+
+fn class(token: &Token) -> Option<TokenClass> {
+    use Reserved::*;
+    let class = match token {
+        Token::Keyword(Add) | Token::Keyword(Sub) | Token::Keyword(Mult) | Token::Keyword(Divide)
+            | Token::Keyword(Equal) | Token::Keyword(NotEqual) | Token::Keyword(LessThan)
+            | Token::Keyword(LessEqual) | Token::Keyword(GreaterThan) | Token::Keyword(GreaterEqual)
+            | Token::Keyword(Arrow) | Token::Keyword(Cons) | Token::Keyword(Neg) | Token::Keyword(Colon)
+            | Token::Keyword(TypeArrow) | Token::Keyword(Bar) | Token::Keyword(Select) => TokenClass::Operator,
+        // the words, `div` and `andalso` too
+        Token::Keyword(_) => TokenClass::Keyword,
+        Token::Lit(_) | Token::OutOfRange(_) => TokenClass::Literal,
+        Token::Name(_) => TokenClass::Identifier,
+        Token::Delim(_) => TokenClass::Punctuation,
+        Token::Space(_) | Token::Error(_) | Token::EndOfFile => return None,
+    };
+    Some(class)
+}
+
+// the `(* *)` comments in `gap`, which starts at `offset` and holds nothing
+// the lexer makes a token of. one left open runs to the end of the gap
+fn comments(gap: &str, offset: usize, spans: &mut Vec<(Span, TokenClass)>) {
+    let mut depth = 0;
+    let mut start = 0;
+    let mut i = 0;
+    while i < gap.len() {
+        let rest = &gap[i..];
+        if rest.starts_with("(*") {
+            if depth == 0 {
+                start = i
+            }
+            depth += 1;
+            i += 2;
+        } else if 0 < depth && rest.starts_with("*)") {
+            depth -= 1;
+            i += 2;
+            if depth == 0 {
+                spans.push((Span::new(offset + start, offset + i), TokenClass::Comment))
+            }
+        } else {
+            i += rest.chars().next().map_or(1, char::len_utf8)
+        }
+    }
+    if 0 < depth {
+        spans.push((Span::new(offset + start, offset + gap.len()), TokenClass::Comment))
+    }
+}
+
+// every token and comment of `source` with what it is, in order. it only
+// lexes, so source that does not parse is classified all the same, and
+// what does not lex is left out
+pub fn highlight(source: &str) -> Vec<(Span, TokenClass)> {
+    let mut spans = vec![];
+    let mut end = 0;
+    for (span, token) in lexer::spanned(source) {
+        comments(&source[end..span.start], end, &mut spans);
+        if let Some(class) = class(&token) {
+            spans.push((span, class))
+        }
+        end = span.end;
+    }
+    comments(&source[end..], end, &mut spans);
+    spans
+}
+
+// `source` as html, each token in a `<span>` with the class of what it is
+pub fn html(source: &str) -> String {
+    let mut html = String::new();
+    let mut end = 0;
+    for (span, class) in highlight(source) {
+        html.push_str(&escape(&source[end..span.start]));
+        html.push_str(&format!("<span class=\"{}\">{}</span>", class.name(), escape(&source[span.start..span.end])));
+        end = span.end;
+    }
+    html.push_str(&escape(&source[end..]));
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn highlight_unit() {
+        let source = "(* id *) let val x = [1, 2] in x :: nil div 2 andalso #\"a\" (* (* nested *) *) end (* open";
+        let classified: Vec<(&str, TokenClass)> = highlight(source).into_iter()
+            .map(|(span, class)| (&source[span.start..span.end], class))
+            .collect();
+        use TokenClass::*;
+        assert_eq!(classified, vec![
+            ("(* id *)", Comment), ("let", Keyword), ("val", Keyword), ("x", Identifier), ("=", Operator),
+            ("[", Punctuation), ("1", Literal), (",", Punctuation), ("2", Literal), ("]", Punctuation),
+            ("in", Keyword), ("x", Identifier), ("::", Operator), ("nil", Keyword), ("div", Keyword),
+            ("2", Literal), ("andalso", Keyword), ("#\"a\"", Literal), ("(* (* nested *) *)", Comment),
+            ("end", Keyword), ("(* open", Comment),
+        ]);
+        assert_eq!(
+            html("x < 1 (* & *)"),
+            "<span class=\"identifier\">x</span> <span class=\"operator\">&lt;</span> \
+             <span class=\"literal\">1</span> <span class=\"comment\">(* &amp; *)</span>",
+        );
+    }
+}
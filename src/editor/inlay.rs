@@ -0,0 +1,169 @@
+use crate::editor::signature::{Scope, bind};
+use crate::expr::{Expr};
+use crate::expr::ids::{NodeId, NodeTable};
+use crate::expr::recover::{parse_recovering};
+use crate::lexer::{self, Span, Token};
+use crate::optimize::mono::{Ty, node_types};
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum HintKind {
+    // the type of a `let val` binder, shown after its name
+    Type,
+    // the parameter an argument is passed for, shown before the argument
+    Parameter,
+}
+
+// text an editor shows in the source without it being there
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct InlayHint {
+    pub offset: usize,
+    pub kind: HintKind,
+    pub label: String,
+}
+
+impl InlayHint {
+    // the hint as it reads in place, `: int` or `n: `
+    pub fn text(&self) -> String {
+        match self.kind {
+            HintKind::Type => format!(": {}", self.label),
+            HintKind::Parameter => format!("{}: ", self.label),
+        }
+    }
+}
+
+struct Hinter<'s> {
+    tokens: Vec<(Span, Token<'s>)>,
+    table: NodeTable,
+    types: Vec<Option<Ty>>,
+    hints: Vec<InlayHint>,
+}
+
+impl<'s> Hinter<'s> {
+    fn span(&self, id: u32) -> Option<Span> {
+        self.table.span(NodeId(id))
+    }
+    // where the binder `name` of the `let` whose bound expression starts at
+    // `start` ends. names have no spans of their own
+    fn name_end(&self, name: &str, start: usize) -> Option<usize> {
+        self.tokens.iter().rev()
+            .find(|(span, token)| span.end <= start && *token == Token::Name(name))
+            .map(|(span, _)| span.end)
+    }
+    fn walk<'e, 'a>(&mut self, expr: &'e Expr<'a>, id: u32, scope: &mut Scope<'e, 'a>) {
+        match expr {
+            // `f a b` is `(f a) b`, the partial applications inside are not
+            // calls of their own
+            Expr::App{ .. } => return self.application(expr, id, scope),
+            Expr::Let{ bindings, .. } => {
+                // the binders are the first children
+                let mut binder_id = id + 1;
+                for (name, binder) in bindings {
+                    // an annotated binder already says its type
+                    if !matches!(binder, Expr::Annot{ .. }) {
+                        let ty = self.types.get(binder_id as usize).cloned().flatten();
+                        let end = self.span(binder_id).and_then(|span| self.name_end(name, span.start));
+                        if let (Some(ty), Some(offset)) = (ty, end) {
+                            self.hints.push(InlayHint { offset, kind: HintKind::Type, label: ty.to_string() })
+                        }
+                    }
+                    binder_id += binder.size();
+                }
+            },
+            _ => {},
+        }
+        let mut child_id = id + 1;
+        for (i, child) in expr.children().into_iter().enumerate() {
+            let depth = scope.len();
+            bind(expr, i, scope);
+            self.walk(child, child_id, scope);
+            scope.truncate(depth);
+            child_id += child.size();
+        }
+    }
+    fn application<'e, 'a>(&mut self, expr: &'e Expr<'a>, id: u32, scope: &mut Scope<'e, 'a>) {
+        let mut args = vec![];
+        let (mut head, mut head_id) = (expr, id);
+        while let Expr::App{ left, right } = head {
+            args.push((&**right, head_id + 1 + left.size()));
+            head = left;
+            head_id += 1;
+        }
+        args.reverse();
+        let params = match head {
+            Expr::Var(name) => scope.iter().rev()
+                .find(|(bound, _)| bound == name)
+                .and_then(|(_, known)| known.as_ref())
+                .map_or(vec![], |known| known.params.clone()),
+            _ => vec![],
+        };
+        self.walk(head, head_id, scope);
+        for (i, &(arg, arg_id)) in args.iter().enumerate() {
+            let offset = self.span(arg_id).map(|span| span.start);
+            match (params.get(i), offset) {
+                // `f n` needs no `n: `
+                (Some(param), _) if matches!(arg, Expr::Var(name) if name == param) => {},
+                (Some(param), Some(offset)) => {
+                    self.hints.push(InlayHint { offset, kind: HintKind::Parameter, label: param.to_string() })
+                },
+                _ => {},
+            }
+            self.walk(arg, arg_id, scope);
+        }
+    }
+}
+
+// the inferred types of `let val` binders and the parameter names of the
+// arguments of functions whose definitions are in sight, in source order.
+// a binder gets no hint when its type does not follow from the literals
+// and operators around it. broken source goes through error recovery
+pub fn inlay_hints(source: &str) -> Vec<InlayHint> {
+    let recovered = parse_recovering(source);
+    let (expr, table) = match (recovered.expr, recovered.table) {
+        (Some(expr), Some(table)) => (expr, table),
+        _ => return vec![],
+    };
+    let types = node_types(&expr);
+    let mut hinter = Hinter { tokens: lexer::spanned(source), table, types, hints: vec![] };
+    hinter.walk(&expr, 0, &mut vec![]);
+    let mut hints = hinter.hints;
+    hints.sort_by_key(|hint| hint.offset);
+    hints
+}
+
+// `source` with `hints` written into it, for a terminal
+pub fn inlaid(source: &str, hints: &[InlayHint]) -> String {
+    let mut out = String::new();
+    let mut start = 0;
+    for hint in hints {
+        out.push_str(&source[start..hint.offset]);
+        out.push_str(&hint.text());
+        start = hint.offset;
+    }
+    out.push_str(&source[start..]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inlay_unit() {
+        let source = "let val n = 2 * 3 in let fun scale k = fn x => k * x in scale n (scale 2 1) end end";
+        assert_eq!(
+            inlaid(source, &inlay_hints(source)),
+            "let val n: int = 2 * 3 in let fun scale k = fn x => k * x in scale k: n x: (scale k: 2 x: 1) end end",
+        );
+        let hints = inlay_hints(source);
+        assert_eq!(hints[0], InlayHint { offset: 9, kind: HintKind::Type, label: "int".to_string() });
+        assert_eq!(hints[1].kind, HintKind::Parameter);
+
+        // no type when nothing says it, none when the annotation does
+        let source = "fn f => let val y = f 1 in let val z : int = y in z end end";
+        assert_eq!(inlaid(source, &inlay_hints(source)), source);
+
+        // the missing `end` is assumed
+        let source = "let val b = 1 < 2 in b";
+        assert_eq!(inlaid(source, &inlay_hints(source)), "let val b: bool = 1 < 2 in b");
+    }
+}
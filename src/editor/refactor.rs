@@ -0,0 +1,505 @@
+use std::fmt;
+
+use std::collections::BTreeSet;
+
+use crate::expr::{BinaryOp, Expr, UnaryOp, parse, parse_indexed};
+use crate::expr::ids::{NodeId, NodeTable};
+use crate::expr::visit::{ExprVisitor, walk_expr};
+use crate::lexer::{self, Span, Token};
+use crate::locale::{message};
+
+// why a refactoring would not keep the program doing what it did
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum RefactorError {
+    // only a program that parses cleanly is refactored
+    Unparsed,
+    // the selection is not exactly the span of an expression
+    NotAnExpression(Span),
+    InvalidName(String),
+    // evaluating the selection prints, raises, loops or calls a function
+    Effects(Span),
+    // what has effects that would run in another order, like something
+    // evaluated before a selection the new binding would run ahead of
+    Reordered(Span),
+    // the name is already used where the binding would go, or the one a
+    // definition uses is bound where it would go
+    Captured(String),
+    // no `let` is around the selection
+    NoBinding(Span),
+    // a definition with effects that is not used exactly once
+    Uses {
+        span: Span,
+        count: usize,
+    },
+    // a definition with effects whose one use is not always evaluated, or
+    // only under a function
+    Deferred(Span),
+}
+
+impl fmt::Display for RefactorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // the way `--span` takes them
+        let at = |span: &Span| format!("{}..{}", span.start, span.end);
+        let text = match self {
+            RefactorError::Unparsed => message("refactor-unparsed", &[]),
+            RefactorError::NotAnExpression(span) => message("refactor-not-an-expression", &[&at(span)]),
+            RefactorError::InvalidName(name) => message("refactor-invalid-name", &[name]),
+            RefactorError::Effects(span) => message("refactor-effects", &[&at(span)]),
+            RefactorError::Reordered(span) => message("refactor-reordered", &[&at(span)]),
+            RefactorError::Captured(name) => message("refactor-captured", &[name]),
+            RefactorError::NoBinding(span) => message("refactor-no-binding", &[&at(span)]),
+            RefactorError::Uses{ span, count } => message("refactor-uses", &[&at(span), count]),
+            RefactorError::Deferred(span) => message("refactor-deferred", &[&at(span)]),
+        };
+        write!(f, "{}", text)
+    }
+}
+
+// whether evaluating `expr` can do anything but make a value or fail.
+// a function is only a value, what it would do when called does not count
+fn effect_free(expr: &Expr) -> bool {
+    use Expr::*;
+    match expr {
+        Unary{ operation: UnaryOp::Print, .. } | App{ .. } | Raise(_) => false,
+        While{ .. } | Handle{ .. } | Error(_) => false,
+        Lambda{ .. } => true,
+        Funs{ body, .. } => effect_free(body),
+        expr => expr.children().into_iter().all(effect_free),
+    }
+}
+
+// whether the `i`th child of `parent` is evaluated every time `parent` is,
+// a binding put around anything else would run it when it did not run
+fn always_evaluated(parent: &Expr, i: usize) -> bool {
+    use Expr::*;
+    match parent {
+        Let{ bindings, .. } => i < bindings.len(),
+        Handle{ .. } | IfThenElse{ .. } => i == 0,
+        Lambda{ .. } | Funs{ .. } | While{ .. } => false,
+        Binary{ operation: BinaryOp::AndAlso, .. } | Binary{ operation: BinaryOp::OrElse, .. } => i == 0,
+        _ => true,
+    }
+}
+
+// one node on the way down to another, with the children evaluated before
+// the next one on the way and whether that one always is
+struct Step {
+    id: NodeId,
+    before: Vec<NodeId>,
+    always: bool,
+}
+
+// the nodes from the root of `expr` down to `target`, not counting it
+fn path(expr: &Expr, target: NodeId) -> Vec<Step> {
+    let mut steps = vec![];
+    let (mut node, mut id) = (expr, 0);
+    while id < target.0 {
+        let mut child_id = id + 1;
+        let mut before = vec![];
+        let mut next = None;
+        for (i, child) in node.children().into_iter().enumerate() {
+            if child_id <= target.0 && target.0 < child_id + child.size() {
+                next = Some((i, child, child_id));
+                break
+            }
+            before.push(NodeId(child_id));
+            child_id += child.size();
+        }
+        let (i, child, child_id) = match next {
+            Some(next) => next,
+            None => break,
+        };
+        steps.push(Step { id: NodeId(id), before, always: always_evaluated(node, i) });
+        node = child;
+        id = child_id;
+    }
+    steps
+}
+
+// the first of what `steps` evaluate ahead of where they lead that has effects
+fn prior(expr: &Expr, table: &NodeTable, steps: &[Step]) -> Option<Span> {
+    steps.iter()
+        .flat_map(|step| step.before.iter())
+        .find(|&&id| !expr.node(id).is_some_and(effect_free))
+        .and_then(|&id| table.span(id))
+}
+
+// `span` without the whitespace around it
+fn trim(source: &str, span: Span) -> Span {
+    let text = &source[span.start..span.end];
+    let start = span.start + (text.len() - text.trim_start().len());
+    Span::new(start, start + text.trim().len())
+}
+
+// whether the text of `expr` can go anywhere an expression can without
+// parentheses
+fn closed(expr: &Expr) -> bool {
+    use Expr::*;
+    matches!(expr, Var(_) | Lit(_) | Seq(_) | List(_) | Tuple{ .. } | Record(_) | Let{ .. } | Funs{ .. })
+        || matches!(expr, Construct{ argument: None, .. })
+}
+
+fn reparsed(text: String) -> Result<String, RefactorError> {
+    match parse(&text) {
+        Ok(_) => Ok(text),
+        Err(_) => Err(RefactorError::Unparsed),
+    }
+}
+
+// `source` with the expression at `span` bound to `name` by a new `let val`
+// and used through it. the binding goes around the innermost part of the
+// program the selection is always evaluated in, which is also as far out as
+// nothing binds its variables. it is refused when the selection has effects
+// or anything evaluated before it in there does, since it would then run
+// first, or when `name` would hide a variable. that leaves the order of
+// two failures, like a division by zero ahead of an overflow, as the only
+// thing that can change
+pub fn extract(source: &str, span: Span, name: &str) -> Result<String, RefactorError> {
+    let (expr, table) = parse_indexed(source).map_err(|_| RefactorError::Unparsed)?;
+    let span = trim(source, span);
+    let target = table.id(span).ok_or(RefactorError::NotAnExpression(span))?;
+    match lexer::spanned(name).as_slice() {
+        [(_, Token::Name(found))] if *found == name && name.starts_with(|c: char| c.is_lowercase()) => {},
+        _ => return Err(RefactorError::InvalidName(name.to_string())),
+    }
+    let selection = expr.node(target).ok_or(RefactorError::NotAnExpression(span))?;
+    if !effect_free(selection) {
+        return Err(RefactorError::Effects(span))
+    }
+    let mut steps = path(&expr, target);
+    // the binding stays inside what is not always evaluated
+    let scope = match steps.iter().rposition(|step| !step.always) {
+        Some(last) => {
+            steps.drain(..=last);
+            steps.first().map_or(target, |step| step.id)
+        },
+        None => NodeId(0),
+    };
+    if let Some(prior) = prior(&expr, &table, &steps) {
+        return Err(RefactorError::Reordered(prior))
+    }
+    let scoped = expr.node(scope).ok_or(RefactorError::NotAnExpression(span))?;
+    if scoped.names().contains(name) {
+        return Err(RefactorError::Captured(name.to_string()))
+    }
+
+    let outer = table.span(scope).ok_or(RefactorError::NotAnExpression(span))?;
+    reparsed(format!(
+        "{}let val {} = {} in {}{}{} end{}",
+        &source[..outer.start],
+        name,
+        &source[span.start..span.end],
+        &source[outer.start..span.start],
+        name,
+        &source[span.end..outer.end],
+        &source[outer.end..],
+    ))
+}
+
+// the uses of `name` in a tree with the given id for its root, and whether
+// any of them would see one of `free` bound differently than it is outside
+struct Uses<'f, 'a> {
+    name: &'a str,
+    free: &'f BTreeSet<&'a str>,
+    next: u32,
+    scope: Vec<&'a str>,
+    uses: Vec<NodeId>,
+    captured: Option<&'a str>,
+}
+
+impl<'f, 'a> ExprVisitor<'a> for Uses<'f, 'a> {
+    fn visit_expr(&mut self, expr: &Expr<'a>) {
+        use Expr::*;
+        let id = NodeId(self.next);
+        self.next += 1;
+        match expr {
+            Var(name) if *name == self.name && !self.scope.contains(name) => {
+                self.uses.push(id);
+                if self.captured.is_none() {
+                    self.captured = self.scope.iter().find(|bound| self.free.contains(*bound)).cloned();
+                }
+            },
+            Let{ bindings, body } => {
+                for (name, binder) in bindings {
+                    self.visit_expr(binder);
+                    self.scope.push(name);
+                }
+                self.visit_expr(body);
+                self.scope.truncate(self.scope.len() - bindings.len());
+            },
+            Lambda{ name, body } => {
+                self.scope.push(name);
+                self.visit_expr(body);
+                self.scope.pop();
+            },
+            Funs{ defs, body } => {
+                self.scope.extend(defs.iter().map(|def| def.name));
+                for def in defs {
+                    self.scope.push(def.argument);
+                    self.visit_expr(&def.body);
+                    self.scope.pop();
+                }
+                self.visit_expr(body);
+                self.scope.truncate(self.scope.len() - defs.len());
+            },
+            Handle{ expr, rules } => {
+                self.visit_expr(expr);
+                for rule in rules {
+                    let names = rule.pattern.names();
+                    let count = names.len();
+                    self.scope.extend(names);
+                    self.visit_expr(&rule.body);
+                    self.scope.truncate(self.scope.len() - count);
+                }
+            },
+            _ => walk_expr(self, expr),
+        }
+    }
+}
+
+// `source` with the binding of the innermost `let` around `span` gone and
+// each use of what it binds replaced by its definition, the binding `span`
+// is in when the `let` has more than one. it is refused when a variable of
+// the definition would be bound to something else at a use. a definition
+// with effects has to be used exactly once, where it is always evaluated and
+// nothing with effects is evaluated before it
+pub fn inline(source: &str, span: Span) -> Result<String, RefactorError> {
+    let (expr, table) = parse_indexed(source).map_err(|_| RefactorError::Unparsed)?;
+    let span = trim(source, span);
+    let inner = table.iter()
+        .filter(|(_, outer)| outer.start <= span.start && span.end <= outer.end)
+        .filter(|(id, _)| matches!(expr.node(*id), Some(Expr::Let{ .. })))
+        .last();
+    let (let_id, outer) = inner.ok_or(RefactorError::NoBinding(span))?;
+    let (bindings, body) = match expr.node(let_id) {
+        Some(Expr::Let{ bindings, body }) => (bindings, &**body),
+        _ => return Err(RefactorError::NoBinding(span)),
+    };
+    // the binders are the first children, the body comes after them
+    let mut binder_ids = vec![];
+    let mut next = let_id.0 + 1;
+    for (_, binder) in bindings {
+        binder_ids.push(NodeId(next));
+        next += binder.size();
+    }
+    let body_id = NodeId(next);
+    let binder_spans: Option<Vec<Span>> = binder_ids.iter().map(|id| table.span(*id)).collect();
+    let (binder_spans, body_span) = match (binder_spans, table.span(body_id)) {
+        (Some(binder_spans), Some(body_span)) => (binder_spans, body_span),
+        _ => return Err(RefactorError::NoBinding(span)),
+    };
+    // a binding runs up to the end of its binder
+    let chosen = match binder_spans.iter().position(|binder_span| span.end <= binder_span.end) {
+        Some(chosen) => chosen,
+        None if bindings.len() == 1 => 0,
+        None => return Err(RefactorError::NoBinding(span)),
+    };
+    let (name, binder) = (bindings[chosen].0, &bindings[chosen].1);
+    let (binder_id, binder_span) = (binder_ids[chosen], binder_spans[chosen]);
+    // the first node of what the binding scopes over
+    let rest_id = NodeId(binder_id.0 + binder.size());
+    let only = bindings.len() == 1;
+
+    let free = binder.free_variables();
+    let mut uses = Uses { name, free: &free, next: rest_id.0, scope: vec![], uses: vec![], captured: None };
+    // a later binding of the same name hides this one from there on
+    for (bound, later) in &bindings[chosen + 1..] {
+        later.accept(&mut uses);
+        uses.scope.push(bound);
+    }
+    body.accept(&mut uses);
+    if let Some(captured) = uses.captured {
+        return Err(RefactorError::Captured(captured.to_string()))
+    }
+    if !effect_free(binder) {
+        let used = match uses.uses.as_slice() {
+            [used] => *used,
+            _ => return Err(RefactorError::Uses { span: binder_span, count: uses.uses.len() }),
+        };
+        // past the binding, where the `let` evaluates the bindings after it
+        // and then its body whatever they come to
+        let steps: Vec<Step> = path(&expr, used).into_iter()
+            .filter(|step| step.id == let_id || rest_id <= step.id)
+            .map(|mut step| {
+                if step.id == let_id {
+                    step.before.retain(|id| rest_id <= *id);
+                    step.always = true;
+                }
+                step
+            })
+            .collect();
+        if steps.iter().any(|step| !step.always) {
+            return Err(RefactorError::Deferred(binder_span))
+        }
+        if let Some(prior) = prior(&expr, &table, &steps) {
+            return Err(RefactorError::Reordered(prior))
+        }
+    }
+
+    // the text replacing `id` needs parentheses unless what it is in keeps it
+    // apart. the body takes the place of a `let` left without bindings
+    let fits = |id: NodeId| {
+        let mut steps = path(&expr, id);
+        if only && steps.last().is_some_and(|step| step.id == let_id) {
+            steps.pop();
+        }
+        match steps.last().and_then(|step| expr.node(step.id)) {
+            None => true,
+            Some(parent) => matches!(parent, Expr::Let{ .. } | Expr::Lambda{ .. } | Expr::Funs{ .. })
+                || matches!(parent, Expr::IfThenElse{ .. } | Expr::While{ .. } | Expr::Seq(_) | Expr::List(_))
+                || matches!(parent, Expr::Tuple{ .. } | Expr::Record(_)),
+        }
+    };
+    let definition = match binder {
+        Expr::Annot{ ty, .. } => {
+            let typed = table.span(NodeId(binder_id.0 + 1)).unwrap_or(binder_span);
+            format!("({} : {})", &source[typed.start..typed.end], ty)
+        },
+        _ => source[binder_span.start..binder_span.end].to_string(),
+    };
+    let (mut inlined, mut start, end) = if only {
+        (String::new(), body_span.start, body_span.end)
+    } else {
+        // the other bindings stay, this one goes from its `val` on
+        let from = if chosen == 0 { outer.start } else { binder_spans[chosen - 1].end };
+        let val = from + source[from..binder_span.start].find("val").unwrap_or(0);
+        (source[outer.start..val].trim_end().to_string(), binder_span.end, outer.end)
+    };
+    for &used in uses.uses.iter() {
+        let span = table.span(used).unwrap_or(body_span);
+        inlined.push_str(&source[start..span.start]);
+        if closed(binder) || fits(used) {
+            inlined.push_str(&definition);
+        } else {
+            inlined.push_str(&format!("({})", definition));
+        }
+        start = span.end;
+    }
+    inlined.push_str(&source[start..end]);
+    if only && !(closed(body) || fits(body_id)) {
+        inlined = format!("({})", inlined);
+    }
+    reparsed(format!("{}{}{}", &source[..outer.start], inlined, &source[outer.end..]))
+}
+
+// `before` and `after` line by line, the lines only in `before` marked with
+// `-` and those only in `after` with `+`, each run of changes with the line
+// around it and where in `before` it starts
+pub fn preview(before: &str, after: &str) -> String {
+    let (old, new): (Vec<&str>, Vec<&str>) = (before.lines().collect(), after.lines().collect());
+    // the longest common subsequence of the lines from `i` and `j` on
+    let mut common = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+    let mut lines: Vec<(char, usize, &str)> = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push((' ', i, old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || common[i + 1][j] >= common[i][j + 1]) {
+            lines.push(('-', i, old[i]));
+            i += 1;
+        } else {
+            lines.push(('+', i, new[j]));
+            j += 1;
+        }
+    }
+    let changed = |k: usize| lines.get(k).is_some_and(|(mark, _, _)| *mark != ' ');
+    let mut out = String::new();
+    let mut shown = None;
+    for (k, (mark, line, text)) in lines.iter().enumerate() {
+        if !(changed(k) || changed(k + 1) || (0 < k && changed(k - 1))) {
+            continue
+        }
+        if !matches!(shown, Some(shown) if shown + 1 == k) {
+            out.push_str(&format!("@@ line {}\n", line + 1));
+        }
+        out.push_str(&format!("{} {}\n", mark, text));
+        shown = Some(k);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extract_unit() {
+        let at = |source: &str, selected: &str| {
+            let start = source.find(selected).unwrap();
+            Span::new(start, start + selected.len())
+        };
+        let source = "let val x = (1 + 2) * 3 in x end";
+        assert_eq!(
+            extract(source, at(source, "(1 + 2)"), "tmp"),
+            Ok("let val tmp = (1 + 2) in let val x = tmp * 3 in x end end".to_string()),
+        );
+        // not past what binds `n`, nor out of the branch it is evaluated in
+        let source = "fn n => if n > 0 then print (n * 2) else 0";
+        assert_eq!(
+            extract(source, at(source, "n * 2"), "twice"),
+            Ok("fn n => if n > 0 then let val twice = n * 2 in print (twice) end else 0".to_string()),
+        );
+        let printed = at(source, "print (n * 2)");
+        assert_eq!(extract(source, printed, "p"), Err(RefactorError::Effects(printed)));
+        assert_eq!(extract(source, at(source, "n > 0"), "n"), Err(RefactorError::Captured("n".to_string())));
+        assert_eq!(extract(source, at(source, "n > 0"), "Big"), Err(RefactorError::InvalidName("Big".to_string())));
+        assert_eq!(extract(source, at(source, "n >"), "t"), Err(RefactorError::NotAnExpression(at(source, "n >"))));
+
+        // the print would come after the division instead of before it
+        let source = "(print 1; 10 div 2)";
+        let reordered = Err(RefactorError::Reordered(at(source, "print 1")));
+        assert_eq!(extract(source, at(source, " 10 div 2"), "half"), reordered);
+    }
+
+    #[test]
+    fn inline_unit() {
+        let at = |source: &str, selected: &str| {
+            let start = source.find(selected).unwrap();
+            Span::new(start, start + selected.len())
+        };
+        let source = "let val tmp = (1 + 2) in let val x = tmp * 3 in x end end";
+        assert_eq!(inline(source, at(source, "tmp")), Ok("let val x = (1 + 2) * 3 in x end".to_string()));
+        let source = "f (let val d = 2 * k in d + d end)";
+        assert_eq!(inline(source, at(source, "d =")), Ok("f ((2 * k) + (2 * k))".to_string()));
+        let source = "let val n : int = 3 in [n] end";
+        assert_eq!(inline(source, at(source, "n :")), Ok("[(3 : int)]".to_string()));
+
+        // `k` would be the parameter, the print would run twice or after `g 1`
+        let source = "let val y = k + 1 in fn k => y end";
+        assert_eq!(inline(source, at(source, "y =")), Err(RefactorError::Captured("k".to_string())));
+        let source = "let val p = print 1 in (p, p) end";
+        let twice = RefactorError::Uses { span: at(source, "print 1"), count: 2 };
+        assert_eq!(inline(source, at(source, "p =")), Err(twice));
+        let source = "let val p = print 1 in (g 1, p) end";
+        assert_eq!(inline(source, at(source, "p =")), Err(RefactorError::Reordered(at(source, "g 1"))));
+        let source = "let val p = print 1 in fn u => p end";
+        assert_eq!(inline(source, at(source, "p =")), Err(RefactorError::Deferred(at(source, "print 1"))));
+        assert_eq!(inline("f 1", Span::new(0, 1)), Err(RefactorError::NoBinding(Span::new(0, 1))));
+        // one binding of several goes, up to a later one of the same name
+        let source = "let val a = 1 + 2 val b = a * a val a = 0 in a + b end";
+        let inlined = "let val b = (1 + 2) * (1 + 2) val a = 0 in a + b end";
+        assert_eq!(inline(source, at(source, "a =")), Ok(inlined.to_string()));
+        assert_eq!(inline(source, at(source, "b =")), Err(RefactorError::Captured("a".to_string())));
+        let source = "let val a = 1 val b = a * 2 in b end";
+        assert_eq!(inline(source, at(source, "b =")), Ok("let val a = 1 in a * 2 end".to_string()));
+        let source = "let val p = print 1 val q = print 2 in (q, p) end";
+        assert_eq!(inline(source, at(source, "p =")), Err(RefactorError::Reordered(at(source, "print 2"))));
+        assert_eq!(inline(source, at(source, "in (")), Err(RefactorError::NoBinding(at(source, "in ("))));
+
+        assert_eq!(
+            preview("a\nb\nc\nd\ne\n", "a\nb\nC\nd\ne\n"),
+            "@@ line 2\n  b\n- c\n+ C\n  d\n",
+        );
+    }
+}
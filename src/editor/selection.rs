@@ -0,0 +1,48 @@
+use crate::editor::brackets::{Brackets};
+use crate::expr::recover::{parse_recovering};
+use crate::lexer::{self, Span};
+
+// what "expand selection" steps through at `offset`: the token there, then
+// every node of the tree around it out to the whole program, each span
+// strictly larger than the one before. a cursor right after a token counts
+// as on it. broken source goes through error recovery, and when even that
+// gives up the constructs `Brackets` sees stand in for the nodes
+pub fn selection_ranges(source: &str, offset: usize) -> Vec<Span> {
+    let tokens: Vec<Span> = lexer::spanned(source).into_iter().map(|(span, _)| span).collect();
+    let token = tokens.iter().find(|span| span.start <= offset && offset < span.end)
+        .or_else(|| tokens.iter().find(|span| span.end == offset))
+        .cloned();
+    let mut spans: Vec<Span> = match parse_recovering(source).table {
+        Some(table) => table.iter().map(|(_, span)| span).collect(),
+        None => Brackets::new(source).constructs().iter().map(|construct| construct.span).collect(),
+    };
+    spans.extend(token);
+    let inner = token.unwrap_or_else(|| Span::new(offset, offset));
+    spans.retain(|span| span.start <= inner.start && inner.end <= span.end && span.start < span.end);
+    spans.sort_by_key(|span| span.end - span.start);
+    spans.dedup();
+    spans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selection_unit() {
+        let slices = |source: &'static str, at: &str| -> Vec<&'static str> {
+            selection_ranges(source, source.find(at).unwrap()).into_iter()
+                .map(|span| &source[span.start..span.end])
+                .collect()
+        };
+        let source = "let val x = 1 in f (x + 2) end";
+        assert_eq!(slices(source, "x +"), vec!["x", "x + 2", "(x + 2)", "f (x + 2)", source]);
+        // the missing `then` is assumed, the spans are still the source's
+        let source = "if ok 1 else g 2";
+        assert_eq!(slices(source, "2"), vec!["2", "g 2", source]);
+        // past what recovery can fix
+        let source = "[1, (2 + ) ]";
+        assert_eq!(slices(source, "2"), vec!["2", "(2 + )", source]);
+        assert_eq!(selection_ranges("", 0), vec![]);
+    }
+}
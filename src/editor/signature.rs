@@ -0,0 +1,234 @@
+use std::fmt;
+
+use crate::expr::{Expr, TypeExpr};
+use crate::expr::ids::{NodeId, NodeTable};
+use crate::expr::recover::{parse_recovering};
+use crate::optimize::mono::{Ty, infer_with, node_types};
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Param {
+    // `None` when the function's definition is not in sight
+    pub name: Option<String>,
+    pub ty: Option<String>,
+}
+
+// the function applied where the cursor is, what it takes and which of its
+// arguments the cursor is on. a type is only there when an annotation says
+// it or it follows from the literals and operators around it
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Signature {
+    pub name: String,
+    pub params: Vec<Param>,
+    pub result: Option<String>,
+    pub active: usize,
+}
+
+// `f (n : int) (m : ?) : ?`
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        for param in self.params.iter() {
+            let name = param.name.as_deref().unwrap_or("_");
+            write!(f, " ({} : {})", name, param.ty.as_deref().unwrap_or("?"))?;
+        }
+        write!(f, " : {}", self.result.as_deref().unwrap_or("?"))
+    }
+}
+
+// what a scope knows about a function bound in it
+#[derive(Clone)]
+pub(crate) struct Function<'e, 'a> {
+    pub(crate) params: Vec<&'a str>,
+    pub(crate) body: &'e Expr<'a>,
+    pub(crate) annotation: Option<&'e TypeExpr>,
+}
+
+// the curried function `expr` is, when it is one
+pub(crate) fn function<'e, 'a>(
+    expr: &'e Expr<'a>,
+    annotation: Option<&'e TypeExpr>,
+    mut params: Vec<&'a str>,
+) -> Option<Function<'e, 'a>> {
+    match expr {
+        Expr::Annot{ expr, ty } if params.is_empty() && annotation.is_none() => function(expr, Some(ty), params),
+        Expr::Lambda{ name, body } => {
+            params.push(name);
+            function(body, annotation, params)
+        },
+        _ if params.is_empty() => None,
+        body => Some(Function { params, body, annotation }),
+    }
+}
+
+// the argument types of an arrow type and what is left after `n` of them
+fn arrows(mut ty: &TypeExpr, n: usize) -> (Vec<&TypeExpr>, Option<&TypeExpr>) {
+    let mut from = vec![];
+    while from.len() < n {
+        match ty {
+            TypeExpr::Arrow(arg, to) => {
+                from.push(&**arg);
+                ty = to
+            },
+            _ => return (from, None),
+        }
+    }
+    (from, Some(ty))
+}
+
+struct Finder<'s> {
+    source: &'s str,
+    table: NodeTable,
+    types: Vec<Option<Ty>>,
+    offset: usize,
+}
+
+pub(crate) type Scope<'e, 'a> = Vec<(&'a str, Option<Function<'e, 'a>>)>;
+
+impl<'s> Finder<'s> {
+    fn span(&self, id: u32) -> Option<(usize, usize)> {
+        self.table.span(NodeId(id)).map(|span| (span.start, span.end))
+    }
+    fn contains(&self, id: u32) -> bool {
+        self.span(id).is_some_and(|(start, end)| start <= self.offset && self.offset <= end)
+    }
+    // the innermost application around the cursor in the subtree `expr`
+    // with pre-order id `id`
+    fn find<'e, 'a>(&self, expr: &'e Expr<'a>, id: u32, scope: &mut Scope<'e, 'a>) -> Option<Signature> {
+        if !self.contains(id) {
+            return None
+        }
+        if let Expr::App{ .. } = expr {
+            return self.application(expr, id, scope)
+        }
+        let mut child_id = id + 1;
+        for (i, child) in expr.children().into_iter().enumerate() {
+            let depth = scope.len();
+            bind(expr, i, scope);
+            let found = self.find(child, child_id, scope);
+            scope.truncate(depth);
+            if found.is_some() {
+                return found
+            }
+            child_id += child.size();
+        }
+        None
+    }
+    fn application<'e, 'a>(&self, expr: &'e Expr<'a>, id: u32, scope: &mut Scope<'e, 'a>) -> Option<Signature> {
+        // `f a b` is `(f a) b`, the arguments come off the outside first
+        let mut args = vec![];
+        let (mut head, mut head_id) = (expr, id);
+        while let Expr::App{ left, right } = head {
+            args.push((&**right, head_id + 1 + left.size()));
+            head = left;
+            head_id += 1;
+        }
+        args.reverse();
+        for &(arg, arg_id) in args.iter().chain(Some(&(head, head_id))) {
+            if let Some(found) = self.find(arg, arg_id, scope) {
+                return Some(found)
+            }
+        }
+        let (name, known) = match head {
+            Expr::Var(name) => {
+                let known = scope.iter().rev().find(|(bound, _)| bound == name).and_then(|(_, known)| known.clone());
+                (name.to_string(), known)
+            },
+            Expr::Lambda{ .. } => ("fn".to_string(), function(head, None, vec![])),
+            _ => {
+                let (start, end) = self.span(head_id)?;
+                (self.source[start..end].to_string(), None)
+            },
+        };
+        let arity = known.as_ref().map_or(0, |known| known.params.len()).max(args.len());
+        let annotation = known.as_ref().and_then(|known| known.annotation);
+        let (from, rest) = annotation.map_or((vec![], None), |ty| arrows(ty, arity));
+        // without an annotation an argument that is already there tells
+        // what its parameter takes
+        let argument = |i: usize| args.get(i).and_then(|(_, arg_id)| self.types.get(*arg_id as usize).cloned().flatten());
+        let param = |i: usize| from.get(i).map_or_else(|| argument(i), |ty| Ty::of_annotation(ty));
+        let params = (0..arity).map(|i| Param {
+            name: known.as_ref().and_then(|known| known.params.get(i)).map(|name| name.to_string()),
+            ty: from.get(i).map(|ty| ty.to_string()).or_else(|| argument(i).map(|ty| ty.to_string())),
+        }).collect();
+        let result = match (rest, &known) {
+            (Some(ty), _) => Some(ty.to_string()),
+            (None, Some(known)) if known.params.len() == arity => {
+                let bindings: Vec<(&str, Option<Ty>)> = known.params.iter().enumerate()
+                    .map(|(i, name)| (*name, param(i)))
+                    .collect();
+                infer_with(known.body, &bindings).map(|ty| ty.to_string())
+            },
+            _ => None,
+        };
+        let active = args.iter()
+            .position(|(_, arg_id)| self.span(*arg_id).is_some_and(|(_, end)| self.offset <= end))
+            .unwrap_or(args.len() - 1);
+        Some(Signature { name, params, result, active })
+    }
+}
+
+// brings what the `i`th child of `parent` can see into scope, shadowing the
+// functions of the same names
+pub(crate) fn bind<'e, 'a>(parent: &'e Expr<'a>, i: usize, scope: &mut Scope<'e, 'a>) {
+    match parent {
+        // a binder sees the ones before it, the body all of them
+        Expr::Let{ bindings, .. } => {
+            for (name, binder) in bindings.iter().take(i) {
+                scope.push((name, function(binder, None, vec![])))
+            }
+        },
+        Expr::Lambda{ name, .. } => scope.push((name, None)),
+        Expr::Funs{ defs, .. } => {
+            for def in defs {
+                scope.push((def.name, function(&def.body, None, vec![def.argument])))
+            }
+            if let Some(def) = defs.get(i) {
+                scope.push((def.argument, None))
+            }
+        },
+        Expr::Handle{ rules, .. } if 0 < i => {
+            for name in rules[i - 1].pattern.names() {
+                scope.push((name, None))
+            }
+        },
+        _ => {},
+    }
+}
+
+// the signature of the function applied around `offset`, the innermost one
+// when applications nest. broken source goes through error recovery
+pub fn signature_help(source: &str, offset: usize) -> Option<Signature> {
+    let recovered = parse_recovering(source);
+    let expr = recovered.expr?;
+    let finder = Finder { source, table: recovered.table?, types: node_types(&expr), offset };
+    finder.find(&expr, 0, &mut vec![])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature_unit() {
+        let help = |source: &str, at: &str| {
+            signature_help(source, source.find(at).unwrap()).map(|signature| {
+                (signature.to_string(), signature.active)
+            })
+        };
+        let source = "let fun add x = fn y => x * y + 1 in add 2 (add 3 4) end";
+        assert_eq!(help(source, "(add"), Some(("add (x : int) (y : ?) : int".to_string(), 1)));
+        assert_eq!(help(source, "4"), Some(("add (x : int) (y : int) : int".to_string(), 1)));
+        assert_eq!(help(source, "3"), Some(("add (x : int) (y : int) : int".to_string(), 0)));
+        assert_eq!(help(source, "1"), None);
+
+        // an annotation beats what the arguments say, a parameter hides the
+        // function of the same name
+        let source = "let val f : int -> bool -> int = fn a => fn b => a in fn g => f (g 1) x end";
+        assert_eq!(help(source, "x"), Some(("f (a : int) (b : bool) : int".to_string(), 1)));
+        assert_eq!(help(source, "1"), Some(("g (_ : int) : ?".to_string(), 0)));
+
+        // the missing `end` is assumed
+        let source = "let fun twice f = fn x => f (f x) in twice (fn n => n) 1";
+        assert_eq!(help(source, "1"), Some(("twice (f : ?) (x : int) : ?".to_string(), 1)));
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::expr::{Decl, parse_program};
+use crate::lexer::{self, Reserved, Span, Token};
+use crate::optimize::mono::{Ty, infer_with};
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum SymbolKind {
+    Val,
+    Fun,
+    Datatype,
+    Constructor,
+}
+
+// a top level declaration of one of the files of a workspace
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub file: PathBuf,
+    // the name where it is declared
+    pub span: Span,
+    // when it follows from an annotation or the literals and operators of
+    // the declaration, `? -> int` for a function whose result does
+    pub ty: Option<String>,
+}
+
+struct Indexed {
+    text: String,
+    symbols: Vec<Symbol>,
+}
+
+// the declarations of a set of program files (see `expr::parse_program`),
+// for finding one by name across all of them. a file is only indexed again
+// when its text changes, and one that stops parsing keeps the symbols of
+// its last version that did so an editor can still jump around while it
+// is being typed in
+#[derive(Default)]
+pub struct Workspace {
+    files: BTreeMap<PathBuf, Indexed>,
+}
+
+impl Workspace {
+    pub fn new() -> Workspace {
+        Workspace::default()
+    }
+    // whether `text` was indexed, false when it is what `path` already had
+    // or does not parse
+    pub fn update<P: AsRef<Path>>(&mut self, path: P, text: &str) -> bool {
+        let path = path.as_ref();
+        if self.files.get(path).is_some_and(|indexed| indexed.text == text) {
+            return false
+        }
+        match index(path, text) {
+            Some(symbols) => {
+                self.files.insert(path.to_path_buf(), Indexed { text: text.to_string(), symbols });
+                true
+            },
+            None => false,
+        }
+    }
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> io::Result<bool> {
+        let text = fs::read_to_string(&path)?;
+        Ok(self.update(path, &text))
+    }
+    pub fn remove<P: AsRef<Path>>(&mut self, path: P) {
+        self.files.remove(path.as_ref());
+    }
+    pub fn symbols(&self) -> impl Iterator<Item = &Symbol> {
+        self.files.values().flat_map(|indexed| indexed.symbols.iter())
+    }
+    // the symbols whose names have the letters of `query` in order, any case,
+    // best matches first: the fewest letters skipped, then the shortest name
+    pub fn workspace_symbols(&self, query: &str) -> Vec<&Symbol> {
+        let mut found: Vec<(usize, &Symbol)> = self.symbols()
+            .filter_map(|symbol| fuzzy(query, &symbol.name).map(|score| (score, symbol)))
+            .collect();
+        found.sort_by_key(|(score, symbol)| (*score, symbol.name.len()));
+        found.into_iter().map(|(_, symbol)| symbol).collect()
+    }
+}
+
+// how many letters of `name` are skipped matching `query` against it, and
+// none when it does not match
+fn fuzzy(query: &str, name: &str) -> Option<usize> {
+    let mut letters = name.chars().map(|c| c.to_ascii_lowercase());
+    let mut skipped = 0;
+    for wanted in query.chars().map(|c| c.to_ascii_lowercase()) {
+        loop {
+            match letters.next() {
+                Some(c) if c == wanted => break,
+                Some(_) => skipped += 1,
+                None => return None,
+            }
+        }
+    }
+    Some(skipped)
+}
+
+// where the names of the top level declarations are. a `let` opens a scope
+// its `end` closes, only names outside of all of them count
+struct Names<'a> {
+    tokens: Vec<(Span, Token<'a>)>,
+    depths: Vec<usize>,
+    next: usize,
+}
+
+impl<'a> Names<'a> {
+    fn new(source: &'a str) -> Names<'a> {
+        let tokens = lexer::spanned(source);
+        let mut depth = 0usize;
+        let depths = tokens.iter().map(|(_, token)| {
+            let at = depth;
+            match token {
+                Token::Keyword(Reserved::Let) => depth += 1,
+                Token::Keyword(Reserved::End) => depth = depth.saturating_sub(1),
+                _ => {},
+            }
+            at
+        }).collect();
+        Names { tokens, depths, next: 0 }
+    }
+    // the span of the next `name` right after one of `after` (and a `rec`)
+    // at the top level
+    fn seek(&mut self, after: &[Reserved], name: &str) -> Option<Span> {
+        while self.next < self.tokens.len() {
+            let i = self.next;
+            self.next += 1;
+            match &self.tokens[i].1 {
+                Token::Keyword(keyword) if self.depths[i] == 0 && after.contains(keyword) => {},
+                _ => continue,
+            }
+            let mut j = i + 1;
+            if let Some((_, Token::Keyword(Reserved::Rec))) = self.tokens.get(j) {
+                j += 1;
+            }
+            if let Some((span, Token::Name(found))) = self.tokens.get(j) {
+                if *found == name {
+                    self.next = j + 1;
+                    return Some(*span)
+                }
+            }
+        }
+        None
+    }
+}
+
+fn index(path: &Path, text: &str) -> Option<Vec<Symbol>> {
+    let program = parse_program(text).ok()?;
+    let mut names = Names::new(text);
+    let mut symbols = vec![];
+    let mut bindings: Vec<(&str, Option<Ty>)> = vec![];
+    let symbol = |name: &str, kind, span: Option<Span>, ty: Option<String>| Symbol {
+        name: name.to_string(),
+        kind,
+        file: path.to_path_buf(),
+        span: span.unwrap_or(Span::new(0, 0)),
+        ty,
+    };
+    for decl in program.iter() {
+        match decl {
+            Decl::Val{ name, binder } => {
+                let ty = infer_with(binder, &bindings);
+                let span = names.seek(&[Reserved::Val], name);
+                symbols.push(symbol(name, SymbolKind::Val, span, ty.as_ref().map(Ty::to_string)));
+                bindings.push((name, ty));
+            },
+            Decl::Fun(defs) => {
+                let mut after: &[Reserved] = &[Reserved::Fun, Reserved::Val];
+                for def in defs {
+                    let mut scope = bindings.clone();
+                    scope.push((def.argument, None));
+                    let ty = infer_with(&def.body, &scope).map(|result| format!("? -> {}", result));
+                    let span = names.seek(after, def.name);
+                    symbols.push(symbol(def.name, SymbolKind::Fun, span, ty));
+                    after = &[Reserved::And];
+                }
+                bindings.extend(defs.iter().map(|def| (def.name, None)));
+            },
+            Decl::Datatype(datatype) => {
+                let span = names.seek(&[Reserved::Datatype], datatype.name);
+                symbols.push(symbol(datatype.name, SymbolKind::Datatype, span, None));
+                for (i, constructor) in datatype.constructors.iter().enumerate() {
+                    let after = if i == 0 { Reserved::Equal } else { Reserved::Bar };
+                    let ty = match &constructor.argument {
+                        Some(argument) => format!("{} -> {}", argument, datatype.name),
+                        None => datatype.name.to_string(),
+                    };
+                    let span = names.seek(&[after], constructor.name);
+                    symbols.push(symbol(constructor.name, SymbolKind::Constructor, span, Some(ty)));
+                }
+            },
+            Decl::Infix(_) | Decl::Expr(_) => {},
+        }
+    }
+    Some(symbols)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn workspace_symbols_unit() {
+        let mut workspace = Workspace::new();
+        let shapes = "datatype Shape = Circle of int | Dot\nval radius = 2 * 3\nfun area s = radius * radius";
+        let main = "val answer : bool = true;\nlet val area = 1 in area end;\nfun go n = n and again n = go n";
+        assert!(workspace.update("shapes.sml", shapes));
+        assert!(workspace.update("main.sml", main));
+        assert!(!workspace.update("main.sml", main));
+
+        let found: Vec<(&str, &str, Option<&str>)> = workspace.workspace_symbols("ar").into_iter()
+            .map(|symbol| (symbol.name.as_str(), symbol.file.to_str().unwrap(), symbol.ty.as_deref()))
+            .collect();
+        assert_eq!(found, vec![
+            ("area", "shapes.sml", Some("? -> int")),
+            ("answer", "main.sml", Some("bool")),
+        ]);
+        let circle = workspace.workspace_symbols("circ")[0];
+        assert_eq!((circle.kind, circle.ty.as_deref()), (SymbolKind::Constructor, Some("int -> Shape")));
+        assert_eq!(&shapes[circle.span.start..circle.span.end], "Circle");
+        // the `area` inside the `let` is not a declaration of the file
+        let again = workspace.workspace_symbols("AGN")[0];
+        assert_eq!((again.name.as_str(), again.span), ("again", Span::new(main.len() - 14, main.len() - 9)));
+        assert_eq!(workspace.workspace_symbols("").len(), 8);
+
+        // broken text keeps what was there, a fix replaces it
+        assert!(!workspace.update("shapes.sml", "val radius = "));
+        assert_eq!(workspace.workspace_symbols("radius").len(), 1);
+        assert!(workspace.update("shapes.sml", "val diameter = 4"));
+        assert!(workspace.workspace_symbols("radius").is_empty());
+        workspace.remove("main.sml");
+        assert_eq!(workspace.symbols().count(), 1);
+    }
+}
@@ -0,0 +1,485 @@
+use std::fmt;
+use std::io;
+use std::fs;
+use std::mem;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+use crate::error::{ParseError};
+use crate::expr::{Decl, Expr, UnaryOp, parse, parse_program};
+use crate::expr::eval::{Env, EvalConfig, Error, Output, Value};
+use crate::session::{Session};
+use crate::locale::{message};
+
+// borrows from the texts of the engine that made it, see `Engine`
+#[derive(Debug)]
+pub enum EngineError<'e> {
+    Io(io::Error),
+    Parse(ParseError<'e>),
+    Undefined(String),
+    Eval {
+        name: &'e str,
+        error: Box<Error<'e>>,
+    },
+    // a binding uses a builtin the policy of the engine does not allow, or
+    // called one while it ran
+    Denied {
+        name: &'e str,
+        operation: UnaryOp,
+    },
+}
+
+impl<'e> EngineError<'e> {
+    // a builtin denied while `name` ran is reported as if it had been
+    // found when loading
+    fn eval(name: &'e str, error: Error<'e>) -> EngineError<'e> {
+        match error {
+            Error::Denied(operation) => EngineError::Denied{ name, operation },
+            error => EngineError::Eval{ name, error: Box::new(error) },
+        }
+    }
+}
+
+impl<'e> fmt::Display for EngineError<'e> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use EngineError::*;
+        match self {
+            Io(err) => write!(f, "{}", message("engine-io", &[err])),
+            Parse(err) => write!(f, "{}", err),
+            Undefined(name) => write!(f, "{}", message("engine-undefined", &[name])),
+            Eval{ name, error } => write!(f, "{}", message("engine-eval", &[name, error])),
+            Denied{ name, operation } => write!(f, "{}", message("engine-denied", &[name, operation])),
+        }
+    }
+}
+
+impl<'e> From<io::Error> for EngineError<'e> {
+    fn from(err: io::Error) -> EngineError<'e> {
+        EngineError::Io(err)
+    }
+}
+
+impl<'e> From<ParseError<'e>> for EngineError<'e> {
+    fn from(err: ParseError<'e>) -> EngineError<'e> {
+        EngineError::Parse(err)
+    }
+}
+
+// what a (re)load did to the top level bindings
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Reloaded<'e> {
+    pub added: Vec<&'e str>,
+    pub changed: Vec<&'e str>,
+    pub removed: Vec<&'e str>,
+    // bindings from any script that were re-evaluated because something
+    // they read changed
+    pub refreshed: Vec<&'e str>,
+}
+
+// what a script may do besides computing, each granted by a `Policy`
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash)]
+pub enum Capability {
+    // `print`, to the output of the engine
+    Output,
+}
+
+impl Capability {
+    // the one a builtin needs, if any
+    fn of(operation: UnaryOp) -> Option<Capability> {
+        match operation {
+            UnaryOp::Print => Some(Capability::Output),
+            _ => None,
+        }
+    }
+}
+
+// what an embedder lets the scripts of an `Engine` do, all in one place. a
+// script using a builtin it is not allowed is refused before any of it
+// runs, and calling one anyway, say through a function the host passed in,
+// fails when the call is made. the limits stop an evaluation that goes on
+// for too long
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Policy {
+    // the builtins scripts may use, all of them when none
+    pub builtins: Option<BTreeSet<UnaryOp>>,
+    pub capabilities: BTreeSet<Capability>,
+    // the steps, stack depth and time each evaluation gets, and the width
+    // of its integers. its builtins are only ever narrowed by the others
+    pub limits: EvalConfig,
+}
+
+impl Default for Policy {
+    // everything, as an engine without a policy
+    fn default() -> Policy {
+        let capabilities = Some(Capability::Output).into_iter().collect();
+        Policy { builtins: None, capabilities, limits: EvalConfig::default() }
+    }
+}
+
+impl Policy {
+    // every builtin that only computes and no capabilities
+    pub fn sandboxed(limits: EvalConfig) -> Policy {
+        Policy { builtins: None, capabilities: BTreeSet::new(), limits }
+    }
+    pub fn allows(&self, operation: UnaryOp) -> bool {
+        self.builtins.as_ref().is_none_or(|builtins| builtins.contains(&operation))
+            && Capability::of(operation).is_none_or(|capability| self.capabilities.contains(&capability))
+            && self.limits.builtins.allows(operation)
+    }
+    // the config every evaluation of the engine runs with
+    fn config(&self) -> EvalConfig {
+        use UnaryOp::*;
+        let builtins = [Not, Fst, Snd, Print, Neg, Ord, Chr].iter().copied().filter(|op| self.allows(*op)).collect();
+        EvalConfig { builtins, ..self.limits }
+    }
+    // the first builtin in `expr` that is not allowed
+    fn denied(&self, expr: &Expr) -> Option<UnaryOp> {
+        match expr {
+            Expr::Unary{ operation, .. } if !self.allows(*operation) => Some(*operation),
+            _ => expr.children().into_iter().find_map(|child| self.denied(child)),
+        }
+    }
+}
+
+type Hook = Box<dyn FnMut(&Path, &Result<Reloaded, EngineError>)>;
+
+// an embeddable interpreter over a set of script files. a script exports
+// every function it declares with `fun`, hosts call them by name with
+// `call`, and `fun main args = ...` is the entry point `main` runs.
+//
+// the engine owns every text its bindings borrow from. inside, they are
+// borrowed as 'static, which holds since a script's text is kept as long
+// as the engine, and the one of a call until the next. what is handed out
+// borrows from the engine instead, so a host can not keep it past the
+// drop either
+#[derive(Default)]
+pub struct Engine {
+    // before the texts, so it is dropped first
+    session: Session<'static>,
+    scripts: HashMap<PathBuf, Script>,
+    // the texts of the last `eval`, the arguments of the last `main` and
+    // the text of a reload under way, what was handed out last may borrow
+    // from them until the next call
+    scratch: Vec<Box<str>>,
+    hooks: Vec<Hook>,
+    policy: Policy,
+}
+
+#[derive(Default)]
+struct Script {
+    // the text of each declaration by name, to tell what a reload changed
+    decls: HashMap<&'static str, String>,
+    // every version of the script, the newest last. an unchanged binding
+    // borrows from the one it was first read from, and its names and
+    // closures from any since, so none of them goes before the engine
+    versions: Vec<Box<str>>,
+}
+
+// `text` as the session borrows it, see `Engine`. a box does not move what
+// it holds when it is moved itself
+fn erase(text: &str) -> &'static str {
+    // SAFETY: only called on texts the engine owns in a box, which it keeps
+    // for as long as the 'static borrow is reachable from the engine
+    unsafe { &*(text as *const str) }
+}
+
+// what the session holds, for no longer than the engine is borrowed. the
+// lifetimes only differ in name, they can not be shortened by subtyping as
+// a value is invariant in its lifetime
+fn value<'e>(value: Value<'static>) -> Value<'e> {
+    // SAFETY: same type but for the lifetime, which gets shorter
+    unsafe { mem::transmute::<Value<'static>, Value<'e>>(value) }
+}
+
+fn error<'e>(error: EngineError<'static>) -> EngineError<'e> {
+    // SAFETY: as for `value`
+    unsafe { mem::transmute::<EngineError<'static>, EngineError<'e>>(error) }
+}
+
+impl Engine {
+    pub fn new() -> Engine {
+        Engine::default()
+    }
+    // an engine whose scripts `print` to `output` instead of stdout
+    pub fn with_output(output: Output) -> Engine {
+        Engine { session: Session::with_output(output), ..Engine::default() }
+    }
+    // an engine whose scripts may only do what `policy` allows, printing to
+    // `output` or stdout when it lets them print
+    pub fn with_policy(policy: Policy, output: Option<Output>) -> Engine {
+        let env = match output {
+            Some(output) => Env::with_output(policy.config(), output),
+            None => Env::with_config(policy.config()),
+        };
+        Engine { session: Session::with_env(env), policy, ..Engine::default() }
+    }
+    pub fn policy(&self) -> &Policy {
+        &self.policy
+    }
+    fn check(&self, name: &'static str, expr: &Expr) -> Result<(), EngineError<'static>> {
+        match self.policy.denied(expr) {
+            Some(operation) => Err(EngineError::Denied{ name, operation }),
+            None => Ok(()),
+        }
+    }
+    // called after every load or reload, whether it worked or not
+    pub fn on_reload<F>(&mut self, hook: F)
+    where F: FnMut(&Path, &Result<Reloaded, EngineError>) + 'static
+    {
+        self.hooks.push(Box::new(hook))
+    }
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<Reloaded<'_>, EngineError<'_>> {
+        self.reload(path)
+    }
+    // re-parses the script at `path` and re-evaluates only the declarations
+    // whose text changed (plus whatever reads them). the swap is all or
+    // nothing: if any step fails the engine keeps its previous state
+    pub fn reload<P: AsRef<Path>>(&mut self, path: P) -> Result<Reloaded<'_>, EngineError<'_>> {
+        self.scratch.clear();
+        let path = path.as_ref();
+        let res = self.try_reload(path);
+        for hook in self.hooks.iter_mut() {
+            hook(path, &res)
+        }
+        res.map_err(error)
+    }
+    pub fn lookup(&self, name: &str) -> Option<&Value<'_>> {
+        // SAFETY: as for `value`, behind a shared borrow of the engine
+        self.session.lookup(name).map(|found| unsafe { mem::transmute::<&Value<'static>, &Value<'_>>(found) })
+    }
+    pub fn eval(&mut self, source: &str) -> Result<Value<'_>, EngineError<'_>> {
+        self.scratch.clear();
+        self.scratch.push(source.into());
+        let source = erase(&self.scratch[0]);
+        let res = parse(source).map_err(EngineError::from).and_then(|expr| {
+            self.check("<eval>", &expr)?;
+            self.session.eval(expr).map_err(|error| EngineError::eval("<eval>", error))
+        });
+        res.map(value).map_err(error)
+    }
+    // every function the loaded scripts export, in definition order
+    pub fn exports(&self) -> Vec<&str> {
+        self.session.functions()
+    }
+    // calls the top level function `name` with `args`, one at a time
+    pub fn call(&mut self, name: &str, args: &[Value<'static>]) -> Result<Value<'_>, EngineError<'_>> {
+        self.scratch.clear();
+        self.apply(name, args.to_vec()).map(value).map_err(error)
+    }
+    fn apply(&mut self, name: &str, args: Vec<Value<'static>>) -> Result<Value<'static>, EngineError<'static>> {
+        let name = match self.session.binding(name) {
+            Some(binding) => binding.name,
+            None => return Err(EngineError::Undefined(name.to_string())),
+        };
+        let function = self.session.lookup(name).cloned()
+            .ok_or_else(|| EngineError::Undefined(name.to_string()))?;
+        self.session.call(function, args).map_err(|error| EngineError::eval(name, error))
+    }
+    // runs the script entry point, `main` gets the arguments as a list of
+    // strings. they are the engine's until the next call, like an `eval`'s
+    pub fn main(&mut self, args: &[&str]) -> Result<Value<'_>, EngineError<'_>> {
+        self.scratch.clear();
+        self.scratch.extend(args.iter().map(|arg| Box::<str>::from(*arg)));
+        let args = self.scratch.iter().map(|arg| Value::String(erase(arg))).collect();
+        self.apply("main", vec![Value::List(args)]).map(value).map_err(error)
+    }
+    fn try_reload(&mut self, path: &Path) -> Result<Reloaded<'static>, EngineError<'static>> {
+        let text = fs::read_to_string(path)?;
+        // kept with the script once the reload goes through
+        self.scratch.push(text.into());
+        let source = erase(&self.scratch[0]);
+        let decls = parse_program(source)?;
+        // all of the script is checked before any of it runs
+        for decl in &decls {
+            match decl {
+                Decl::Val{ name, binder } => self.check(name, binder)?,
+                Decl::Fun(defs) => {
+                    for def in defs {
+                        self.check(def.name, &def.body)?
+                    }
+                },
+                Decl::Datatype(_) | Decl::Infix(_) | Decl::Expr(_) => {},
+            }
+        }
+        let empty = HashMap::new();
+        let old = self.scripts.get(path).map_or(&empty, |script| &script.decls);
+        let mut next = self.session.clone();
+        let mut texts = HashMap::new();
+        let mut reloaded = Reloaded::default();
+        for decl in decls {
+            let names: Vec<(&'static str, String)> = match decl {
+                Decl::Val{ name, ref binder } => vec![(name, binder.to_string())],
+                Decl::Fun(ref defs) => defs.iter().map(|def| (def.name, def.to_string())).collect(),
+                Decl::Datatype(_) | Decl::Infix(_) | Decl::Expr(_) => vec![],
+            };
+            let mut dirty = false;
+            for (name, text) in names {
+                match old.get(name) {
+                    None => {
+                        reloaded.added.push(name);
+                        dirty = true
+                    },
+                    Some(old_text) if *old_text != text => {
+                        reloaded.changed.push(name);
+                        dirty = true
+                    },
+                    Some(_) => {},
+                }
+                texts.insert(name, text);
+            }
+            if !dirty {
+                continue
+            }
+            match decl {
+                Decl::Val{ name, binder } => {
+                    next.define_val(name, *binder).map_err(|error| EngineError::eval(name, error))?;
+                },
+                Decl::Fun(defs) => {
+                    next.define_funs(defs);
+                },
+                Decl::Datatype(_) | Decl::Infix(_) | Decl::Expr(_) => {},
+            }
+        }
+        for name in old.keys() {
+            if !texts.contains_key(name) {
+                next.remove(name);
+                reloaded.removed.push(name);
+            }
+        }
+        for (name, res) in next.refresh() {
+            res.map_err(|error| EngineError::eval(name, error))?;
+            reloaded.refreshed.push(name);
+        }
+        reloaded.removed.sort();
+        self.session = next;
+        let script = self.scripts.entry(path.to_path_buf()).or_default();
+        script.decls = texts;
+        script.versions.extend(self.scratch.pop());
+        Ok(reloaded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+    use std::cell::Cell;
+
+    #[test]
+    fn engine_reload_unit() {
+        let path = std::env::temp_dir().join(format!("ferus-engine-{}.sml", std::process::id()));
+        let mut engine = Engine::new();
+        let failures = Rc::new(Cell::new(0));
+        let counter = failures.clone();
+        engine.on_reload(move |_, res| if res.is_err() { counter.set(counter.get() + 1) });
+
+        fs::write(&path, "val base = 10\nval bonus = base * 2\nfun score n = n + bonus\nval keep = 7").unwrap();
+        let loaded = engine.load(&path).unwrap();
+        assert_eq!(loaded.added, vec!["base", "bonus", "score", "keep"]);
+        assert_eq!(engine.eval("score 1").unwrap().to_string(), "21");
+
+        fs::write(&path, "val base = 1\nval bonus = base * 2\nfun score n = n + bonus").unwrap();
+        let reloaded = engine.reload(&path).unwrap();
+        assert_eq!(reloaded.changed, vec!["base"]);
+        assert_eq!(reloaded.removed, vec!["keep"]);
+        assert_eq!(reloaded.refreshed, vec!["bonus", "score"]);
+        assert_eq!(engine.eval("score 1").unwrap().to_string(), "3");
+        assert!(engine.lookup("keep").is_none());
+
+        // a broken script leaves the engine as it was
+        fs::write(&path, "val base = 1 + true\nval bonus = base * 2\nfun score n = n + bonus").unwrap();
+        assert!(engine.reload(&path).is_err());
+        fs::write(&path, "val base =").unwrap();
+        assert!(engine.reload(&path).is_err());
+        assert_eq!(engine.eval("score 1").unwrap().to_string(), "3");
+        assert_eq!(failures.get(), 2);
+
+        // every version that went through is kept, older names and
+        // closures still point into them
+        assert_eq!(engine.scripts[&path].versions.len(), 2);
+        fs::write(&path, "val base = 2\nval bonus = base * 3\nfun score n = bonus + n").unwrap();
+        engine.reload(&path).unwrap();
+        assert_eq!(engine.scripts[&path].versions.len(), 3);
+        assert_eq!(engine.eval("score 1").unwrap().to_string(), "7");
+        assert_eq!(engine.eval("score 2").unwrap().to_string(), "8");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn engine_call_unit() {
+        let path = std::env::temp_dir().join(format!("ferus-engine-call-{}.sml", std::process::id()));
+        let mut engine = Engine::new();
+        fs::write(&path, "val rate = 3\nfun scale n = fn m => n * m * rate\nfun ping u = 1\nfun main args = 0 :: args").unwrap();
+        engine.load(&path).unwrap();
+        assert_eq!(engine.exports(), vec!["scale", "ping", "main"]);
+        let res = engine.call("scale", &[Value::Integer(2), Value::Integer(5)]).unwrap();
+        assert_eq!(res.to_string(), "30");
+        // what the engine hands out borrows from it
+        drop(res);
+        assert_eq!(engine.call("ping", &[]).unwrap().to_string(), "1");
+        assert_eq!(engine.main(&["a", "b"]).unwrap().to_string(), "[0, a, b]");
+        // the arguments of a run are dropped by the next
+        assert_eq!(engine.main(&["c"]).unwrap().to_string(), "[0, c]");
+        assert_eq!(engine.scratch.len(), 1);
+        match engine.call("missing", &[]) {
+            Err(EngineError::Undefined(name)) => assert_eq!(name, "missing"),
+            res => panic!("{:?}", res),
+        }
+        match engine.call("rate", &[Value::Integer(1)]) {
+            Err(EngineError::Eval{ name: "rate", .. }) => {},
+            res => panic!("{:?}", res),
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn engine_policy_unit() {
+        use std::cell::RefCell;
+        use crate::expr::eval::{Limit};
+
+        let path = std::env::temp_dir().join(format!("ferus-engine-policy-{}.sml", std::process::id()));
+        let limits = EvalConfig{ max_steps: Some(10_000), ..EvalConfig::default() };
+        let mut engine = Engine::with_policy(Policy::sandboxed(limits), None);
+        fs::write(&path, "fun double n = n * 2\nfun shout n = print n").unwrap();
+        match engine.load(&path) {
+            Err(EngineError::Denied{ name: "shout", operation: UnaryOp::Print }) => {},
+            res => panic!("{:?}", res),
+        }
+        // nothing of a refused script is loaded
+        assert!(engine.lookup("double").is_none());
+        fs::write(&path, "fun double n = n * 2\nfun spin n = spin n").unwrap();
+        engine.load(&path).unwrap();
+        assert_eq!(engine.eval("double (fst (4, 5))").unwrap().to_string(), "8");
+        match engine.call("spin", &[Value::Integer(1)]) {
+            Err(EngineError::Eval{ error, .. }) => assert!(matches!(*error, Error::OutOfFuel(Limit::Steps(10_000)))),
+            res => panic!("{:?}", res),
+        }
+
+        let only = Policy { builtins: Some(vec![UnaryOp::Not].into_iter().collect()), ..Policy::default() };
+        let mut engine = Engine::with_policy(only, None);
+        assert_eq!(engine.eval("not false").unwrap().to_string(), "true");
+        assert!(matches!(engine.eval("~1"), Err(EngineError::Denied{ operation: UnaryOp::Neg, .. })));
+        assert!(Policy::default().allows(UnaryOp::Print));
+
+        // what the host hands in is held to the policy when it is called,
+        // both the builtins and the limits
+        let printed = Rc::new(RefCell::new(vec![]));
+        let mut engine = Engine::with_policy(Policy::sandboxed(limits), Some(printed.clone()));
+        fs::write(&path, "fun twice f = f (f 1)").unwrap();
+        engine.load(&path).unwrap();
+        let shout = parse("fn x => (print x; x)").unwrap().eval().unwrap();
+        match engine.call("twice", &[shout]) {
+            Err(EngineError::Denied{ name: "twice", operation: UnaryOp::Print }) => {},
+            res => panic!("{:?}", res),
+        }
+        assert!(printed.borrow().is_empty());
+        let spin = parse("let fun spin n = spin n in spin end").unwrap().eval().unwrap();
+        match engine.call("twice", &[spin]) {
+            Err(EngineError::Eval{ error, .. }) => assert!(matches!(*error, Error::OutOfFuel(Limit::Steps(10_000)))),
+            res => panic!("{:?}", res),
+        }
+        let double = parse("fn x => x * 2").unwrap().eval().unwrap();
+        assert_eq!(engine.call("twice", &[double]).unwrap().to_string(), "4");
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,307 @@
+use std::fmt;
+use combine::easy::{self, Errors, Info};
+
+use crate::lexer::{Delimiter, Direction, Reserved, Span, Token, spanned};
+use crate::expr::{MAX_NESTING, MAX_DEPTH};
+use crate::diagnostic::{Diagnostic, Label};
+use crate::locale::{message};
+use crate::render::{Rendering, colors, rendering};
+use crate::runtime::real::{Real};
+use crate::runtime::width::{Width};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError<'a> {
+    pub source: &'a str,
+    pub unexpected: Option<Token<'a>>,
+    pub span: Span,
+    pub expected: Vec<String>,
+    pub messages: Vec<String>,
+    // the kind of mistake as a stable name, `teach::Lessons` are keyed by it
+    pub code: &'static str,
+}
+
+fn describe<'a>(info: &Info<Token<'a>, Token<'a>>) -> String {
+    match info {
+        Info::Token(tok) | Info::Range(tok) => format!("`{}`", tok),
+        Info::Owned(s) => s.clone(),
+        Info::Static(s) => s.to_string(),
+    }
+}
+
+impl<'a> ParseError<'a> {
+    pub fn new(source: &'a str, errors: Errors<Token<'a>, Token<'a>, usize>) -> ParseError<'a> {
+        let mut unexpected = None;
+        let mut expected = vec![];
+        let mut messages = vec![];
+        let mut code = "E0001";
+        for error in errors.errors {
+            match error {
+                easy::Error::Unexpected(Info::Token(tok)) => unexpected = Some(tok),
+                // what `Tokenizer` gives back where nothing lexes
+                easy::Error::Unexpected(Info::Static("parse")) => code = "E0010",
+                easy::Error::Unexpected(info) => {
+                    messages.push(message("unexpected-info", &[&describe(&info)]))
+                },
+                // the parser names its messages by their catalog key
+                easy::Error::Message(Info::Static(key @ "chained-comparison")) => {
+                    code = "E0003";
+                    messages.push(message(key, &[]))
+                },
+                easy::Error::Message(Info::Static(key @ "duplicate-label")) => {
+                    code = "E0009";
+                    messages.push(message(key, &[]))
+                },
+                easy::Error::Message(Info::Static(key @ "too-deep")) => {
+                    code = "E0011";
+                    messages.push(message(key, &[&MAX_NESTING]))
+                },
+                easy::Error::Expected(info) => {
+                    let info = describe(&info);
+                    if !expected.contains(&info) {
+                        expected.push(info)
+                    }
+                },
+                easy::Error::Message(info) => messages.push(describe(&info)),
+                easy::Error::Other(err) => messages.push(err.to_string()),
+            }
+        }
+        if code == "E0001" && unexpected == Some(Token::EndOfFile) {
+            code = "E0002"
+        }
+        if let Some(Token::OutOfRange(text)) = unexpected {
+            let width = Width::default();
+            code = "E0008";
+            if text.chars().all(|c| c.is_ascii_digit()) {
+                messages.push(message("out-of-range", &[&width.max(), &width]))
+            } else {
+                messages.push(message("real-out-of-range", &[&Real::MAX]))
+            }
+        }
+        let start = errors.position.min(source.len());
+        let span = if code == "E0010" {
+            let span = Span::of_unlexable(source, start);
+            messages.insert(0, message("not-a-token", &[&&source[span.start..span.end]]));
+            span
+        } else {
+            Span::of_token(source, start)
+        };
+        ParseError { source, unexpected, span, expected, messages, code }
+    }
+    // a tree deeper than `parse_str` gives back, there is no telling where
+    pub fn too_deep(source: &'a str) -> ParseError<'a> {
+        let messages = vec![message("too-deep", &[&MAX_DEPTH])];
+        ParseError { source, unexpected: None, span: Span::new(0, 0), expected: vec![], messages, code: "E0011" }
+    }
+    // a tree `NodeTable` could not line up with the tokens it came from
+    pub fn unindexed(source: &'a str) -> ParseError<'a> {
+        let messages = vec![message("unindexed", &[])];
+        ParseError { source, unexpected: None, span: Span::new(0, 0), expected: vec![], messages, code: "E0012" }
+    }
+    // the parser ran out of tokens, more input could still make this parse
+    pub fn is_incomplete(&self) -> bool {
+        self.unexpected == Some(Token::EndOfFile)
+    }
+    pub fn line_col(&self) -> (usize, usize) {
+        self.span.line_col(self.source)
+    }
+    pub fn code(&self) -> &'static str {
+        self.code
+    }
+    // where the construct started that what the parser wanted, or what it
+    // found in its place, would have gone on with or closed
+    pub fn label(&self) -> Option<Label> {
+        let (at, opened, closer) = opener(self.source, self.span.start)?;
+        if self.expected.contains(&format!("`{}`", closer)) || self.unexpected.iter().any(closes) {
+            Some(Label { span: at, message: message("started-here", &[&opened]), primary: false })
+        } else {
+            None
+        }
+    }
+    // the snippet with the error's span and its label marked
+    pub fn diagnostic(&self) -> Diagnostic {
+        let mut diagnostic = Diagnostic::new(self.span, String::new());
+        diagnostic.labels.extend(self.label());
+        diagnostic
+    }
+}
+
+// whether `token` only ever goes on with or closes a construct
+fn closes(token: &Token) -> bool {
+    use Reserved::*;
+    match token {
+        Token::Keyword(keyword) => matches!(keyword, Then | Else | In | End | Do),
+        Token::Delim(Delimiter::Paren(dir) | Delimiter::Bracket(dir) | Delimiter::Brace(dir)) => {
+            *dir == Direction::Right
+        },
+        Token::EndOfFile => true,
+        _ => false,
+    }
+}
+
+// the innermost construct still open at byte offset `at` of `source`, where
+// it started, the token that started it and the one that would go on with
+// it or close it
+fn opener<'a>(source: &'a str, at: usize) -> Option<(Span, Token<'a>, Token<'a>)> {
+    use Reserved::*;
+    use Token::{Delim, Keyword};
+    let close = |delim| Delim(delim);
+    let mut open: Vec<(Span, Token<'a>, Token<'a>)> = vec![];
+    for (span, token) in spanned(source).into_iter().take_while(|(span, _)| span.start < at) {
+        let closer = match &token {
+            Keyword(If) => Some(Keyword(Then)),
+            Keyword(Let) => Some(Keyword(In)),
+            Keyword(While) => Some(Keyword(Do)),
+            Delim(Delimiter::Paren(Direction::Left)) => Some(close(Delimiter::Paren(Direction::Right))),
+            Delim(Delimiter::Bracket(Direction::Left)) => Some(close(Delimiter::Bracket(Direction::Right))),
+            Delim(Delimiter::Brace(Direction::Left)) => Some(close(Delimiter::Brace(Direction::Right))),
+            _ => None,
+        };
+        if let Some(closer) = closer {
+            open.push((span, token, closer));
+            continue
+        }
+        let top = match open.last_mut() {
+            Some(top) if top.2 == token => top,
+            _ => continue,
+        };
+        // `then` and `in` go on with what they are part of, the rest close it
+        match token {
+            Keyword(Then) => top.2 = Keyword(Else),
+            Keyword(In) => top.2 = Keyword(End),
+            _ => {
+                open.pop();
+            },
+        }
+    }
+    open.pop()
+}
+
+// the line holding `span` with a caret under it
+pub(crate) fn snippet(f: &mut fmt::Formatter, source: &str, span: Span) -> fmt::Result {
+    write!(f, "{}", Diagnostic::new(span, String::new()).render(source, rendering(), colors()))
+}
+
+pub(crate) fn render_snippet(source: &str, span: Span, rendering: Rendering) -> String {
+    let (line, col) = span.line_col(source);
+    let text = source.lines().nth(line - 1).unwrap_or("");
+    let marked = &source[span.start..span.end];
+    match rendering {
+        Rendering::Visual => {
+            let width = marked.chars().count().max(1);
+            format!("  | {}\n  | {}{}", text, " ".repeat(col - 1), "^".repeat(width))
+        },
+        // the column and the marked text said out loud instead of pointed at
+        Rendering::Linear => {
+            let at = if marked.is_empty() {
+                message("snippet-at-end", &[&line])
+            } else {
+                message("snippet-at", &[&col, &marked])
+            };
+            format!("{}\n{}", message("snippet-line", &[&line, &text.trim()]), at)
+        },
+    }
+}
+
+impl<'a> fmt::Display for ParseError<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (line, col) = self.line_col();
+        write!(f, "{}", message("parse-error", &[&line, &col]))?;
+        match self.unexpected {
+            Some(Token::EndOfFile) => writeln!(f, ": {}", message("unexpected-eof", &[]))?,
+            Some(ref tok) => writeln!(f, ": {}", message("unexpected", &[tok]))?,
+            None => writeln!(f)?,
+        }
+        write!(f, "{}", self.diagnostic().render(self.source, rendering(), colors()))?;
+        if !self.expected.is_empty() {
+            write!(f, "\n{}", message("expected-one-of", &[&self.expected.join(", ")]))?;
+        }
+        for note in self.messages.iter() {
+            write!(f, "\n{}", message("note", &[note]))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::{parse};
+    use crate::lexer::{Reserved, Span, Token};
+
+    #[test]
+    fn parse_error_unit() {
+        let source = "let val x = 1 in\nx + + 2 end";
+        let err = parse(source).unwrap_err();
+        assert_eq!(err.unexpected, Some(Token::Keyword(Reserved::Add)));
+        assert_eq!(err.span, Span::new(21, 22));
+        assert_eq!(err.line_col(), (2, 5));
+        assert!(err.expected.contains(&"expression".to_string()));
+        let rendered = err.to_string();
+        assert!(rendered.starts_with("parse error at 2:5: unexpected `+`\n  | x + + 2 end\n  |     ^"));
+    }
+
+    #[test]
+    fn render_snippet_linear_unit() {
+        let source = "let val x = 1 in\nx + + 2 end";
+        let span = parse(source).unwrap_err().span;
+        assert_eq!(render_snippet(source, span, Rendering::Linear), "line 2: x + + 2 end\nat column 5: `+`");
+        let source = "if true then 1";
+        let span = parse(source).unwrap_err().span;
+        let rendered = render_snippet(source, span, Rendering::Linear);
+        assert_eq!(rendered, "line 1: if true then 1\nat the end of line 1");
+    }
+
+    #[test]
+    fn parse_error_started_here_unit() {
+        let source = "let val x = 1 in\nif x < 1 1 else 2 end";
+        let err = parse(source).unwrap_err();
+        let started = Label { span: Span::new(17, 19), message: "`if` started here".to_string(), primary: false };
+        assert_eq!(err.label(), Some(started));
+        let snippet = "\n  | if x < 1 1 else 2 end\n  |            ^^^^\n  | -- `if` started here";
+        assert!(err.to_string().contains(snippet));
+        let labelled = |source| parse(source).unwrap_err().label().map(|label| label.span);
+        assert_eq!(labelled("let val x = (1, 2 in x end"), Some(Span::new(12, 13)));
+        assert_eq!(labelled("let val x = 1 x end"), Some(Span::new(0, 3)));
+        assert_eq!(labelled("if a then (1) 2"), Some(Span::new(0, 2)));
+        assert_eq!(labelled("[1, (2]"), Some(Span::new(4, 5)));
+        assert_eq!(labelled("1 + + 2"), None);
+    }
+
+    #[test]
+    fn parse_error_chained_comparison_unit() {
+        let err = parse("1 < 2 <= 3").unwrap_err();
+        assert_eq!(err.unexpected, Some(Token::Keyword(Reserved::LessEqual)));
+        assert_eq!(err.span, Span::new(6, 8));
+        assert!(err.messages[0].starts_with("comparisons do not chain"));
+        assert!(parse("(1 < 2) = (3 >= 4)").is_ok());
+    }
+
+    #[test]
+    fn parse_error_out_of_range_unit() {
+        let err = parse("1 + 99999999999999999999").unwrap_err();
+        assert_eq!(err.unexpected, Some(Token::OutOfRange("99999999999999999999")));
+        assert_eq!((err.code(), err.span), ("E0008", Span::new(4, 24)));
+        assert!(err.messages.iter().any(|m| m.starts_with("integer literals can be at most")));
+        let err = parse("1.5e400").unwrap_err();
+        assert_eq!((err.code(), err.span), ("E0008", Span::new(0, 7)));
+        assert!(err.messages.iter().any(|m| m.starts_with("real literals can be at most")));
+    }
+
+    #[test]
+    fn parse_error_duplicate_label_unit() {
+        let err = parse("{x = 1, y = 2, x = 3}").unwrap_err();
+        assert_eq!(err.code(), "E0009");
+        assert!(err.messages.iter().any(|m| m.starts_with("a record can not have two fields")));
+        let err = parse("f () handle Point {x, x = y} => y").unwrap_err();
+        assert_eq!(err.code(), "E0009");
+        assert!(parse("{x = 1, y = 2}").is_ok());
+    }
+
+    #[test]
+    fn parse_error_incomplete_unit() {
+        assert!(parse("if true then 1").unwrap_err().is_incomplete());
+        assert!(!parse("if true then 1 end").unwrap_err().is_incomplete());
+        assert!(parse("1 + (* still open").unwrap_err().is_incomplete());
+        assert!(parse("(* a *) 1 + (* b (* c *) *) 2").is_ok());
+    }
+}
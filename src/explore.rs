@@ -0,0 +1,210 @@
+use std::collections::BTreeSet;
+
+use crate::error::{render_snippet};
+use crate::expr::{Expr};
+use crate::expr::ids::{NodeId, NodeTable};
+use crate::expr::pretty::{label};
+use crate::lexer::{Span};
+use crate::locale::{message};
+use crate::optimize::mono::{Ty, node_types};
+use crate::render::{rendering};
+
+// what `ferus explore` reacts to, the terminal side maps keys onto these
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Hash)]
+pub enum Key {
+    Up,
+    Down,
+    // opens the selected node, or steps into it when it is already open
+    Right,
+    // closes the selected node, or steps out to its parent
+    Left,
+    Toggle,
+    // switches between the tree and the source with the selection marked
+    Source,
+    Quit,
+}
+
+struct Node {
+    label: String,
+    span: Span,
+    ty: Option<Ty>,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    depth: usize,
+    // nodes in the subtree and how deep it goes below this one
+    size: usize,
+    height: usize,
+}
+
+// the state of the explorer, independent of any terminal: which nodes are
+// open, which one is selected and whether the source is showing. nodes are
+// numbered like the `NodeId`s of the table it was built from
+pub struct Explorer<'a> {
+    source: &'a str,
+    nodes: Vec<Node>,
+    open: BTreeSet<usize>,
+    selected: usize,
+    showing_source: bool,
+}
+
+impl<'a> Explorer<'a> {
+    pub fn new(source: &'a str, expr: &Expr<'a>, table: &NodeTable) -> Explorer<'a> {
+        fn walk(expr: &Expr, parent: Option<usize>, depth: usize, nodes: &mut Vec<Node>, table: &NodeTable,
+                types: &mut dyn Iterator<Item = Option<Ty>>) -> usize {
+            let index = nodes.len();
+            nodes.push(Node {
+                label: label(expr),
+                span: table.span(NodeId(index as u32)).unwrap_or(Span::new(0, 0)),
+                ty: types.next().flatten(),
+                parent,
+                children: vec![],
+                depth,
+                size: 1,
+                height: 0,
+            });
+            for child in expr.children() {
+                let child = walk(child, Some(index), depth + 1, nodes, table, types);
+                nodes[index].children.push(child);
+                nodes[index].size += nodes[child].size;
+                nodes[index].height = nodes[index].height.max(nodes[child].height + 1);
+            }
+            index
+        }
+        let mut nodes = vec![];
+        walk(expr, None, 0, &mut nodes, table, &mut node_types(expr).into_iter());
+        let open = Some(0).into_iter().collect();
+        Explorer { source, nodes, open, selected: 0, showing_source: false }
+    }
+    pub fn selected(&self) -> NodeId {
+        NodeId(self.selected as u32)
+    }
+    pub fn is_open(&self, id: NodeId) -> bool {
+        self.open.contains(&(id.0 as usize))
+    }
+    // the nodes with a row in the tree, in pre-order
+    fn rows(&self) -> Vec<usize> {
+        fn walk(explorer: &Explorer, node: usize, rows: &mut Vec<usize>) {
+            rows.push(node);
+            if explorer.open.contains(&node) {
+                for child in explorer.nodes[node].children.iter() {
+                    walk(explorer, *child, rows)
+                }
+            }
+        }
+        let mut rows = vec![];
+        walk(self, 0, &mut rows);
+        rows
+    }
+    // false once the user asked to quit
+    pub fn handle(&mut self, key: Key) -> bool {
+        let node = &self.nodes[self.selected];
+        let rows = self.rows();
+        let row = rows.iter().position(|node| *node == self.selected).unwrap_or(0);
+        match key {
+            Key::Up => self.selected = rows[row.saturating_sub(1)],
+            Key::Down => self.selected = rows[(row + 1).min(rows.len() - 1)],
+            Key::Right if node.children.is_empty() => {},
+            Key::Right if self.open.contains(&self.selected) => self.selected = node.children[0],
+            Key::Left if !node.children.is_empty() && self.open.contains(&self.selected) => {
+                self.open.remove(&self.selected);
+            },
+            Key::Left => self.selected = node.parent.unwrap_or(self.selected),
+            Key::Right | Key::Toggle => {
+                if !self.open.remove(&self.selected) && !node.children.is_empty() {
+                    self.open.insert(self.selected);
+                }
+            },
+            Key::Source => self.showing_source = !self.showing_source,
+            Key::Quit => return false,
+        }
+        true
+    }
+    // the screen as `height` lines: the tree or the source on top, what is
+    // known about the selected node below it
+    pub fn render(&self, height: usize) -> Vec<String> {
+        let node = &self.nodes[self.selected];
+        let (line, col) = node.span.line_col(self.source);
+        let mut details = vec![
+            String::new(),
+            message("explore-span", &[&node.span.start, &node.span.end, &line, &col]),
+            match &node.ty {
+                Some(ty) => message("explore-type", &[ty]),
+                None => message("explore-type-unknown", &[]),
+            },
+            message("explore-size", &[&node.size, &node.height]),
+        ];
+        if !self.showing_source {
+            details.extend(render_snippet(self.source, node.span, rendering()).lines().map(str::to_string));
+        }
+        details.push(message("explore-help", &[]));
+        let room = height.saturating_sub(details.len()).max(1);
+        let mut screen = if self.showing_source { self.source_lines(room) } else { self.tree_lines(room) };
+        screen.resize(room, String::new());
+        screen.extend(details);
+        screen
+    }
+    fn tree_lines(&self, room: usize) -> Vec<String> {
+        let rows = self.rows();
+        let row = rows.iter().position(|node| *node == self.selected).unwrap_or(0);
+        let first = row.saturating_sub(room / 2).min(rows.len().saturating_sub(room));
+        rows[first..].iter().take(room).map(|index| {
+            let node = &self.nodes[*index];
+            let marker = match () {
+                _ if node.children.is_empty() => ' ',
+                _ if self.open.contains(index) => '-',
+                _ => '+',
+            };
+            let cursor = if *index == self.selected { '>' } else { ' ' };
+            format!("{} {}{} {}", cursor, "  ".repeat(node.depth), marker, node.label)
+        }).collect()
+    }
+    // numbered source lines, the ones the selected node covers marked
+    fn source_lines(&self, room: usize) -> Vec<String> {
+        let span = self.nodes[self.selected].span;
+        let (start, _) = span.line_col(self.source);
+        let (end, _) = Span::new(span.end, span.end).line_col(self.source);
+        let lines: Vec<&str> = self.source.lines().collect();
+        let first = (start - 1).saturating_sub(room / 2).min(lines.len().saturating_sub(room));
+        lines.iter().enumerate().skip(first).take(room).map(|(i, text)| {
+            let marker = if start <= i + 1 && i < end { '>' } else { ' ' };
+            format!("{} {:>4} | {}", marker, i + 1, text)
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::{parse_indexed};
+
+    #[test]
+    fn explorer_unit() {
+        let source = "let val x = 1 + 2\nin (x, true) end";
+        let (expr, table) = parse_indexed(source).unwrap();
+        let mut explorer = Explorer::new(source, &expr, &table);
+        let tree = |explorer: &Explorer| explorer.render(20)[..5].to_vec();
+        assert_eq!(tree(&explorer), vec!["> - let x =", "    + +", "    + tuple", "", ""]);
+
+        for key in [Key::Down, Key::Right, Key::Right, Key::Down] {
+            assert!(explorer.handle(key));
+        }
+        assert_eq!(explorer.selected(), NodeId(3));
+        let screen = explorer.render(14);
+        assert_eq!(screen[..6].to_vec(), vec!["  - let x =", "    - +", "        1", ">       2", "    + tuple", ""]);
+        assert_eq!(screen[8..11].to_vec(), vec!["span 16..17, line 1, column 17", "type: int", "1 nodes, 0 deep"]);
+        // a screen too short for the whole tree keeps the selection in view
+        assert_eq!(explorer.render(9)[..2].to_vec(), vec!["        1", ">       2"]);
+
+        // stepping out goes to the parent, and again closes it
+        explorer.handle(Key::Left);
+        explorer.handle(Key::Left);
+        assert!(!explorer.is_open(NodeId(1)));
+        assert_eq!(explorer.render(3)[0], ">   + +");
+
+        explorer.handle(Key::Down);
+        explorer.handle(Key::Source);
+        let screen = explorer.render(10);
+        assert_eq!(screen[..2].to_vec(), vec!["     1 | let val x = 1 + 2", ">    2 | in (x, true) end"]);
+        assert!(!explorer.handle(Key::Quit));
+    }
+}
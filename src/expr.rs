@@ -1,30 +1,74 @@
 use std::fmt;
+use std::cell::{Cell, RefCell};
+use std::rc::{Rc};
+use std::thread;
 use combine::{
-    Parser, Stream, satisfy, satisfy_map, choice, between,
-    chainl1, attempt, optional, value, sep_by1
+    EasyParser, Parser, Stream, parser, satisfy, satisfy_map, choice, between,
+    chainl1, attempt, optional, value, many, many1, sep_by, sep_by1, not_followed_by, look_ahead
 };
+use combine::error::{Commit, Info, StreamError, ParseError as _};
+use combine::stream::{StreamErrorFor};
 
 pub mod pretty;
 pub mod eval;
+pub mod ids;
+pub mod owned;
+pub mod scope;
+pub mod source;
+pub mod format;
+pub mod lint;
+pub mod recover;
+pub mod visit;
+pub mod subst;
+pub mod step;
+pub mod trace;
+pub mod derivation;
+pub mod audit;
+pub mod calls;
+pub mod spanless;
+pub mod debruijn;
+pub mod columnar;
+pub mod sql;
+pub mod explain;
+pub mod operators;
+pub mod truth;
+pub mod sensitivity;
+pub mod arena;
+pub mod provenance;
+pub mod wire;
+pub mod sexp;
+#[cfg(test)]
+pub mod arbitrary;
 
-use crate::lexer::{Literal, Direction, Delimiter, Reserved, Token};
+use crate::lexer::{Literal, Direction, Delimiter, Reserved, Span, Token, Tokenizer};
+use crate::error::{ParseError};
+use ids::{NodeTable};
+use operators::{Assoc, Infix, Operators};
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum UnaryOp {
     Not,
     Fst,
     Snd,
     Print,
+    Neg,
+    // a character's code point and the character with a code point
+    Ord,
+    Chr,
 }
 
 impl UnaryOp {
     fn precedence(self) -> usize {
         use UnaryOp::*;
         match self {
-            Not => 9,
-            Fst => 9,
-            Snd => 9,
-            Print => 9,
+            Not => 7,
+            Fst => 7,
+            Snd => 7,
+            Print => 7,
+            Neg => 7,
+            Ord => 7,
+            Chr => 7,
         }
     }
 }
@@ -37,20 +81,30 @@ impl fmt::Display for UnaryOp {
             Fst => "fst",
             Snd => "snd",
             Print => "print",
+            Neg => "~",
+            Ord => "ord",
+            Chr => "chr",
         };
         write!(f, "{}", name)
     }
 }
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BinaryOp {
     Add,
     Sub,
     Mult,
     Div,
     Mod,
+    // `/`, which only divides reals
+    Divide,
     Equal,
+    NotEqual,
     LessThan,
+    LessEqual,
+    GreaterThan,
+    GreaterEqual,
     OrElse,
     AndAlso,
 }
@@ -64,8 +118,13 @@ impl fmt::Display for BinaryOp {
             Mult => "*",
             Div => "div",
             Mod => "mod",
+            Divide => "/",
             Equal => "=",
+            NotEqual => "<>",
             LessThan => "<",
+            LessEqual => "<=",
+            GreaterThan => ">",
+            GreaterEqual => ">=",
             OrElse => "orelse",
             AndAlso => "andalso",
         };
@@ -74,30 +133,191 @@ impl fmt::Display for BinaryOp {
 }
 
 impl BinaryOp {
+    // the result of a comparison operator, `None` for everything else
+    pub fn compare<T: Ord>(self, left: T, right: T) -> Option<bool> {
+        use BinaryOp::*;
+        match self {
+            Equal => Some(left == right),
+            NotEqual => Some(left != right),
+            LessThan => Some(left < right),
+            LessEqual => Some(left <= right),
+            GreaterThan => Some(left > right),
+            GreaterEqual => Some(left >= right),
+            _ => None,
+        }
+    }
     fn precedence(self) -> usize {
         use BinaryOp::*;
         match self {
-            Add => 4,
-            Sub => 4,
-            Mult => 5,
-            Div => 5,
-            Mod => 5,
+            Add => 5,
+            Sub => 5,
+            Mult => 6,
+            Div => 6,
+            Mod => 6,
+            Divide => 6,
             Equal => 3,
+            NotEqual => 3,
             LessThan => 3,
+            LessEqual => 3,
+            GreaterThan => 3,
+            GreaterEqual => 3,
             OrElse => 1,
             AndAlso => 2,
         }
     }
 }
 
-#[derive(Debug, Clone)]
+// the types an annotation can name
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "kind", content = "value"))]
+pub enum TypeExpr {
+    Unit,
+    Int,
+    Bool,
+    String,
+    Char,
+    Real,
+    Tuple(Box<TypeExpr>, Box<TypeExpr>),
+    List(Box<TypeExpr>),
+    Arrow(Box<TypeExpr>, Box<TypeExpr>),
+    // a `datatype`
+    Named(std::string::String),
+}
+
+impl TypeExpr {
+    // `->` binds loosest and `list` tightest, both infix operators group to
+    // the right
+    fn write(&self, f: &mut fmt::Formatter, prec: usize) -> fmt::Result {
+        use TypeExpr::*;
+        let level = match self {
+            Arrow(..) => 0,
+            Tuple(..) => 1,
+            _ => 2,
+        };
+        if level < prec {
+            write!(f, "(")?;
+        }
+        match self {
+            Unit => write!(f, "unit")?,
+            Int => write!(f, "int")?,
+            Bool => write!(f, "bool")?,
+            String => write!(f, "string")?,
+            Char => write!(f, "char")?,
+            Real => write!(f, "real")?,
+            Named(name) => write!(f, "{}", name)?,
+            Tuple(fst, snd) => {
+                fst.write(f, 2)?;
+                write!(f, " * ")?;
+                snd.write(f, 1)?
+            },
+            List(elem) => {
+                elem.write(f, 2)?;
+                write!(f, " list")?
+            },
+            Arrow(from, to) => {
+                from.write(f, 1)?;
+                write!(f, " -> ")?;
+                to.write(f, 0)?
+            },
+        }
+        if level < prec {
+            write!(f, ")")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for TypeExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Definition<'a> {
-    name: &'a str,
-    argument: &'a str,
-    body: Box<Expr<'a>>
+    pub name: &'a str,
+    pub argument: &'a str,
+    pub body: Box<Expr<'a>>
+}
+
+impl<'a> fmt::Display for Definition<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} = {}", self.name, self.argument, self.body)
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "kind", content = "value"))]
+pub enum Pattern<'a> {
+    Wildcard,
+    Var(&'a str),
+    Construct {
+        name: &'a str,
+        argument: Option<Box<Pattern<'a>>>,
+    },
+    // `{x = p, y}` matches a record with exactly these fields, a field
+    // without a pattern binds its label
+    Record(Vec<(&'a str, Pattern<'a>)>),
+}
+
+impl<'a> Pattern<'a> {
+    // the variables the pattern binds, left to right
+    pub fn names(&self) -> Vec<&'a str> {
+        match self {
+            Pattern::Wildcard => vec![],
+            Pattern::Var(name) => vec![name],
+            Pattern::Construct{ argument: Some(argument), .. } => argument.names(),
+            Pattern::Construct{ argument: None, .. } => vec![],
+            Pattern::Record(fields) => fields.iter().flat_map(|(_, pattern)| pattern.names()).collect(),
+        }
+    }
+}
+
+// `{x = 1, y = true}`, how records and record patterns are written
+pub(crate) fn fields<T, F>(f: &mut fmt::Formatter, fields: &[(&str, T)], mut field: F) -> fmt::Result
+where F: FnMut(&mut fmt::Formatter, &T) -> fmt::Result
+{
+    write!(f, "{{")?;
+    for (i, (label, value)) in fields.iter().enumerate() {
+        if 0 < i {
+            write!(f, ", ")?;
+        }
+        write!(f, "{} = ", label)?;
+        field(f, value)?;
+    }
+    write!(f, "}}")
+}
+
+impl<'a> fmt::Display for Pattern<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Pattern::Wildcard => write!(f, "_"),
+            Pattern::Var(name) => write!(f, "{}", name),
+            Pattern::Construct{ name, argument: None } => write!(f, "{}", name),
+            Pattern::Construct{ name, argument: Some(argument) } => match **argument {
+                Pattern::Construct{ argument: Some(_), .. } => write!(f, "{} ({})", name, argument),
+                _ => write!(f, "{} {}", name, argument),
+            },
+            Pattern::Record(fields) => self::fields(f, fields, |f, pattern| write!(f, "{}", pattern)),
+        }
+    }
+}
+
+// one `pat => expr` arm of a `handle`
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rule<'a> {
+    #[cfg_attr(feature = "serde", serde(borrow))]
+    pub pattern: Pattern<'a>,
+    pub body: Box<Expr<'a>>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "kind", content = "value"))]
 pub enum Expr<'a> {
     Var(&'a str),
     Lit(Literal<'a>),
@@ -119,9 +339,10 @@ pub enum Expr<'a> {
         fst: Box<Expr<'a>>,
         snd: Box<Expr<'a>>,
     },
+    // `let val x = 1 val y = x in body end`, never without a binding. each
+    // binding scopes over the ones after it and the body
     Let {
-        name: &'a str,
-        binder: Box<Expr<'a>>,
+        bindings: Vec<(&'a str, Expr<'a>)>,
         body: Box<Expr<'a>>,
     },
     Lambda {
@@ -133,122 +354,174 @@ pub enum Expr<'a> {
         right: Box<Expr<'a>>,
     },
     Seq(Vec<Expr<'a>>),
+    List(Vec<Expr<'a>>),
+    Cons {
+        head: Box<Expr<'a>>,
+        tail: Box<Expr<'a>>,
+    },
     Funs{
         defs: Vec<Definition<'a>>,
         body: Box<Expr<'a>>,
     },
+    // `expr : ty`, and what `let val x : ty = expr` leaves as the binder
+    Annot {
+        expr: Box<Expr<'a>>,
+        ty: TypeExpr,
+    },
+    // a capitalized name, applied to its argument when it takes one
+    Construct {
+        name: &'a str,
+        argument: Option<Box<Expr<'a>>>,
+    },
+    Raise(Box<Expr<'a>>),
+    // the first rule whose pattern matches what `expr` raised runs instead
+    Handle {
+        expr: Box<Expr<'a>>,
+        rules: Vec<Rule<'a>>,
+    },
+    // runs `body` for as long as `condition` holds, then is unit
+    While {
+        condition: Box<Expr<'a>>,
+        body: Box<Expr<'a>>,
+    },
+    // `{x = 1, y = true}`, the fields in the order they are written, which
+    // is the order they are evaluated in
+    Record(Vec<(&'a str, Expr<'a>)>),
+    // `#x point`, the field of a record
+    Select {
+        label: &'a str,
+        record: Box<Expr<'a>>,
+    },
+    // where error recovery gave up on the source in `span` and carried on
+    // after it, never in a tree that parsed cleanly
+    Error(Span),
 }
 
-impl<'a> fmt::Display for Expr<'a> {
+// how an `Expr::Error` is written out
+pub const ERROR: &str = "<error>";
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Constructor<'a> {
+    pub name: &'a str,
+    // the type after `of`
+    pub argument: Option<TypeExpr>,
+}
+
+impl<'a> fmt::Display for Constructor<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        fn parens<F>(f: &mut fmt::Formatter, inner: usize, outer: usize, cb: F) -> fmt::Result
-        where F: FnOnce(&mut fmt::Formatter) -> fmt::Result
-        {
-            if inner < outer {
-                write!(f, "(")?;
-                cb(f)?;
-                write!(f, ")")
-            } else {
-                cb(f)
-            }
-        }
-        fn draw<'a>(f: &mut fmt::Formatter, expr: &Expr<'a>, prec: usize) -> fmt::Result {
-            use Expr::*;
-            match expr {
-                Var(name) => write!(f, "{}", name),
-                Lit(lit) => write!(f, "{}", lit),
-                Unary{ operation, child } => {
-                    let op_prec = operation.precedence();
-                    parens(f, op_prec, prec, |g| {
-                        write!(g, "{} ", operation)?;
-                        draw(g, child, op_prec)
-                    })
-                },
-                Binary{ left, operation, right } => {
-                    let op_prec = operation.precedence();
-                    parens(f, op_prec, prec, |g| {
-                        draw(g, left, op_prec)?;
-                        write!(g, " {} ", operation)?;
-                        draw(g, right, op_prec)
-                    })
-                },
-                IfThenElse{ condition, if_branch, else_branch } => {
-                    parens(f, 0, prec, |g| {
-                        write!(g, "if ")?;
-                        draw(g, condition, 0)?;
-                        write!(g, " then ")?;
-                        draw(g, if_branch, 0)?;
-                        write!(g, " else ")?;
-                        draw(g, else_branch, 0)
-                    })
-                },
-                Tuple{ fst, snd } => {
-                    write!(f, "(")?;
-                    draw(f, fst, 0)?;
-                    draw(f, snd, 0)?;
-                    write!(f, ")")
-                },
-                Let{ name, binder, body } => {
-                    parens(f, 0, prec, |g| {
-                        write!(g, "let val {} = ", name)?;
-                        draw(g, binder, 0)?;
-                        write!(g, " in ")?;
-                        draw(g, body, 0)?;
-                        write!(g, " end")
-                    })
-                },
-                Lambda{ name, body } => {
-                    parens(f, 0, prec, |g| {
-                        write!(g, "fn {} => ", name)?;
-                        draw(g, body, 0)
-                    })
-                },
-                App{ left, right } => {
-                    parens(f, 9, prec, |g| {
-                        draw(g, left, 10)?;
-                        write!(g, " ")?;
-                        draw(g, right, 10)
-                    })
-                },
-                Seq(sequence) => {
-                    parens(f, 0, prec, |g| {
-                        for (i, expr) in sequence.iter().enumerate() {
-                            if i < sequence.len() - 1 {
-                                draw(g, expr, 0)?;
-                                write!(g, " ; ")?;
-                            } else {
-                                draw(g, expr, 0)?;
-                            }
-                        }
-                        Ok(())
-                    })
-                },
-                Funs{ defs, body } => {
-                    fn func<'a>(h: &mut fmt::Formatter, fun: &Definition<'a>) -> fmt::Result {
-                        write!(h, "{} {} = ", fun.name, fun.argument)?;
-                        draw(h, &fun.body, 0)
-                    }
-                    parens(f, 0, prec, |g| {
-                        write!(g, "let fun ")?;
-                        for (i, function) in defs.iter().enumerate() {
-                            if i < defs.len() - 1 {
-                                func(g, function)?;
-                                write!(g, " and ")?;
-                            } else {
-                                func(g, function)?;
-                            }
-                        }
-                        write!(g, " in ")?;
-                        draw(g, body, 0)?;
-                        write!(g, " end")
-                    })
-                },
-            }
+        match &self.argument {
+            Some(ty) => write!(f, "{} of {}", self.name, ty),
+            None => write!(f, "{}", self.name),
         }
-        draw(f, self, 0)
     }
 }
 
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Datatype<'a> {
+    pub name: &'a str,
+    pub constructors: Vec<Constructor<'a>>,
+}
+
+impl<'a> fmt::Display for Datatype<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let constructors: Vec<String> = self.constructors.iter().map(Constructor::to_string).collect();
+        write!(f, "datatype {} = {}", self.name, constructors.join(" | "))
+    }
+}
+
+// `infix 5 plus` or `infixr 5 append`, its names being binary operators
+// on the level of its precedence for the rest of the program, applied to
+// the pair of their operands
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Fixity<'a> {
+    pub assoc: Assoc,
+    pub precedence: usize,
+    pub names: Vec<&'a str>,
+}
+
+impl<'a> fmt::Display for Fixity<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let keyword = if self.assoc == Assoc::Right { Reserved::Infixr } else { Reserved::Infix };
+        write!(f, "{} {} {}", keyword, self.precedence, self.names.join(" "))
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Decl<'a> {
+    Val {
+        name: &'a str,
+        binder: Box<Expr<'a>>,
+    },
+    Fun(Vec<Definition<'a>>),
+    Datatype(Datatype<'a>),
+    Infix(Fixity<'a>),
+    Expr(Expr<'a>),
+}
+
+// the declarations of a script, in order
+pub type Program<'a> = Vec<Decl<'a>>;
+
+// a program as one expression, each declaration scoping over the ones after
+// it. the value is the last expression's, or unit when the program ends in
+// a declaration, and an expression before that is bound to `it`
+pub fn nest<'a>(mut program: Program<'a>) -> Expr<'a> {
+    let body = match program.last() {
+        Some(Decl::Expr(_)) => match program.pop() {
+            Some(Decl::Expr(expr)) => expr,
+            _ => unreachable!(),
+        },
+        _ => Expr::Lit(Literal::Unit),
+    };
+    program.into_iter().rev().fold(body, |body, decl| match decl {
+        Decl::Val{ name, binder } => Expr::Let{ bindings: vec![(name, *binder)], body: Box::new(body) },
+        Decl::Fun(defs) => Expr::Funs{ defs, body: Box::new(body) },
+        // constructors need no bindings
+        Decl::Datatype(_) | Decl::Infix(_) => body,
+        Decl::Expr(expr) => Expr::Let{ bindings: vec![("it", expr)], body: Box::new(body) },
+    })
+}
+
+impl<'a> fmt::Display for Expr<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_source())
+    }
+}
+
+// how deeply expressions, types and patterns may nest. each level takes a
+// few dozen stack frames of the parser, this many fit in `PARSE_STACK`
+pub const MAX_NESTING: usize = 200;
+
+thread_local! {
+    // the levels `nested` is in on this thread
+    static NESTING: Cell<usize> = const { Cell::new(0) };
+}
+
+// `p`, or an error when it is nested in `MAX_NESTING` others already
+fn nested<'a, Input, P>(mut p: P) -> impl Parser<Input, Output = P::Output>
+where Input: Stream<Item = Token<'a>>, P: Parser<Input>
+{
+    parser(move |input: &mut Input| {
+        let depth = NESTING.with(Cell::get);
+        if MAX_NESTING <= depth {
+            let error = StreamErrorFor::<Input>::message_static_message("too-deep");
+            return Err(Commit::Commit(Input::Error::from_error(input.position(), error).into()))
+        }
+        NESTING.with(|nesting| nesting.set(depth + 1));
+        let res = p.parse_stream(input).into_result();
+        NESTING.with(|nesting| nesting.set(depth));
+        res
+    })
+}
+
+// `operands` joined from the right, `a :: (b :: c)`. `chainr1` does the
+// same as it parses but recurses once per operator to do it
+fn fold_right<T>(mut operands: Vec<T>, join: impl Fn(T, T) -> T) -> T {
+    let last = operands.pop().expect("`sep_by1` parses at least one operand");
+    operands.into_iter().rev().fold(last, |right, left| join(left, right))
+}
+
 parser!{
     pub fn token['a, Input](t: Token<'a>)(Input) -> ()
     where [ Input: Stream<Item = Token<'a>> ]
@@ -268,6 +541,18 @@ parser!{
     }
 }
 
+parser!{
+    // constructors are told apart from variables by their capital letter
+    pub fn constructor_name['a, Input]()(Input) -> &'a str
+    where [ Input: Stream<Item = Token<'a>> ]
+    {
+        satisfy_map(|t| match t {
+            Token::Name(n) if n.starts_with(char::is_uppercase) => Some(n),
+            _ => None
+        })
+    }
+}
+
 parser!{
     pub fn space['a, Input]()(Input) -> ()
     where [ Input: Stream<Item = Token<'a>> ]
@@ -290,23 +575,163 @@ parser!{
     }
 }
 
+// <decl> ::= <topd>EOF | <prog>
+// <scrp> ::= <topd> <scrp> | EOF
+// <prgm> ::= <topd> <semi> <prgm> | <expn> ; <prgm> | <expn>EOF | EOF
+// <semi> ::= ; | ε
+// <topd> ::= val <name> <ascr> = <expn> | val rec <recf> | fun <funs> | datatype <name> = <ctrs>
+// <topd> ::= infix <prec> <nams> | infixr <prec> <nams>
+// <prec> ::= 0 | 1 | ... | 9 | ε
+// <nams> ::= <nams> <name> | <name>
+// <ctrs> ::= <ctrs> | <ctor> | <ctor>
+// <ctor> ::= <cnam> of <type> | <cnam>
 // <prog> ::= <expn>EOF
-// <expn> ::= let val <name> = <expn> in <expn> end | let fun <funs> in <expn> end
-// <expn> ::= if <expn> then <expn> else <expn>
-// <expn> ::= fn <name> => <expn> | <disj>
+// <expn> ::= let <vals> in <expn> end | let fun <funs> in <expn> end
+// <expn> ::= let val rec <recf> in <expn> end
+// <expn> ::= if <expn> then <expn> else <expn> | while <expn> do <expn>
+// <expn> ::= fn <name> => <expn> | raise <expn> | <hndl>
+// <hndl> ::= <annt> handle <mtch> | <annt>
+// <mtch> ::= <mtch> | <rule> | <rule>
+// <rule> ::= <patn> => <expn>
+// <annt> ::= <infx> : <type> | <infx>
+// <ascr> ::= : <type> | ε
+// <vals> ::= <vals> val <name> <ascr> = <expn> | val <name> <ascr> = <expn>
 // <funs> ::= <funs> and <func> | <func>
 // <func> ::= <name> <name> = <expn>
+// <recf> ::= <name> = fn <name> => <expn>
+// <infx> ::= <disj>, with the usual levels of `operators::Operators`
 // <disj> ::= <disj> orelse <conj> | <conj>
 // <conj> ::= <conj> andalso <cmpn> | <cmpn>
-// <cmpn> ::= <addn> = <addn> | <addn> < <addn> | <addn>
+// <cmpn> ::= <cons> <cmpo> <cons> | <cons>
+// <cmpo> ::= = | <> | < | <= | > | >=
+// <cons> ::= <addn> :: <cons> | <addn>
 // <addn> ::= <addn> + <mult> | <addn> - <mult> | <mult>
-// <mult> ::= <mult> * <unar> | <mult> div <unar> | <mult> mod <unar> | <unar>
-// <unar> ::= not <appn> | fst <appn> | snd <appn> | print <appn>
-// <appn> ::= <appn> <atom> | <atom>
-// <atom> ::= <name> | <numn> | true | false | ( <seqn> ) | ( <expn> , <expn> )
+// <mult> ::= <mult> * <unar> | <mult> div <unar> | <mult> mod <unar> | <mult> / <unar> | <unar>
+// <unar> ::= not <appn> | fst <appn> | snd <appn> | print <appn> | ~ <appn> | - <appn>
+// <unar> ::= ord <appn> | chr <appn> | # <name> <appn>
+// <appn> ::= <appn> <atom> | <cnam> <atom> | <atom>
+// <atom> ::= <name> | <cnam> | <numn> | true | false | nil | ( <seqn> ) | ( <expn> , <expn> ) | [ <list> ]
+// <atom> ::= { <flds> }
+// <flds> ::= <flds> , <name> = <expn> | <name> = <expn>
+// <list> ::= <list> , <expn> | <expn> | ε
 // <seqn> ::= <seqn> ; <expn> | <expn>
+// <type> ::= <tprd> -> <type> | <tprd>
+// <tprd> ::= <tpst> * <tprd> | <tpst>
+// <tpst> ::= <tpst> list | <tatm>
+// <tatm> ::= unit | int | bool | string | char | real | <name> | ( <type> )
+// <patn> ::= <cnam> <patm> | <patm>
+// <patm> ::= _ | <name> | <cnam> | ( <patn> ) | { <pfds> }
+// <pfds> ::= <pfds> , <pfld> | <pfld>
+// <pfld> ::= <name> = <patn> | <name>
 // <name> ::= a | b | c | ...
+// <cnam> ::= A | B | C | ...
 // <numn> ::= 0 | 1 | 2 | ...
+parser!{
+    pub fn decl['a, Input]()(Input) -> Decl<'a>
+    where [ Input: Stream<Item = Token<'a>> ]
+    {
+        let top_level = (top(), optional(space()), token(Token::EndOfFile)).map(|(decl, _, _)| decl);
+        (optional(space()), choice!(top_level, prog().map(Decl::Expr))).map(|(_, decl)| decl)
+    }
+}
+
+parser!{
+    pub fn script['a, Input]()(Input) -> Program<'a>
+    where [ Input: Stream<Item = Token<'a>> ]
+    {
+        (optional(space()), many(top().map(declared)), token(Token::EndOfFile)).map(|(_, decls, _)| decls)
+    }
+}
+
+parser!{
+    // a whole source file. a declaration may end with a `;`, an expression
+    // has to unless it is the last thing in the file
+    pub fn program['a, Input]()(Input) -> Program<'a>
+    where [ Input: Stream<Item = Token<'a>> ]
+    {
+        let semicolon = || lex(token(Token::Delim(Delimiter::Semicolon)));
+        let declaration = (top(), optional(semicolon())).map(|(decl, _)| declared(decl));
+        let end = choice!(semicolon(), look_ahead(token(Token::EndOfFile)));
+        let expression = (expn(), end).map(|(expr, _)| Decl::Expr(expr));
+        (optional(space()), many(choice!(declaration, expression)), token(Token::EndOfFile))
+            .map(|(_, decls, _)| decls)
+    }
+}
+
+parser!{
+    pub fn top['a, Input]()(Input) -> Decl<'a>
+    where [ Input: Stream<Item = Token<'a>> ]
+    {
+        use Token::*;
+        use Decl::*;
+        let val = struct_parser!{
+            Val {
+                _: token(Keyword(Reserved::Val)),
+                _: space(),
+                name: name(),
+                binder: binding(),
+            }
+        };
+        let val_rec = struct_parser!{
+            Fun(
+                _: attempt((token(Keyword(Reserved::Val)), space(), token(Keyword(Reserved::Rec)))),
+                _: space(),
+                recf().map(|def| vec![def])
+            )
+        };
+        let fun = struct_parser!{
+            Fun(
+                _: token(Keyword(Reserved::Fun)),
+                _: space(),
+                funs()
+            )
+        };
+        let datatype = struct_parser!{
+            Datatype(
+                _: token(Keyword(Reserved::Datatype)),
+                _: space(),
+                datatype()
+            )
+        };
+        let assoc = satisfy_map(|t| match t {
+            Keyword(Reserved::Infix) => Some(Assoc::Left),
+            Keyword(Reserved::Infixr) => Some(Assoc::Right),
+            _ => None,
+        });
+        // like in Standard ML, 0 when left out
+        let precedence = satisfy_map(|t| match t {
+            Lit(Literal::Integer(digit)) if (0..=9).contains(&digit) => Some(digit as usize),
+            _ => None,
+        });
+        let fixity = (assoc, space(), optional((precedence, space())), many1(lex(name())))
+            .map(|(assoc, _, precedence, names)| {
+                Infix(Fixity { assoc, precedence: precedence.map_or(0, |(digit, _)| digit), names })
+            });
+        choice!(val_rec, val, fun, datatype, fixity)
+    }
+}
+
+parser!{
+    pub fn datatype['a, Input]()(Input) -> Datatype<'a>
+    where [ Input: Stream<Item = Token<'a>> ]
+    {
+        use Token::*;
+        let constructor = struct_parser!{
+            Constructor {
+                name: lex(constructor_name()),
+                argument: optional((token(Keyword(Reserved::Of)), ty()).map(|(_, ty)| ty)),
+            }
+        };
+        struct_parser!{
+            Datatype {
+                name: name(),
+                _: lex(token(Keyword(Reserved::Equal))),
+                constructors: sep_by1(constructor, lex(token(Keyword(Reserved::Bar)))),
+            }
+        }
+    }
+}
+
 parser!{
     pub fn prog['a, Input]()(Input) -> Expr<'a>
     where [ Input: Stream<Item = Token<'a>> ]
@@ -331,6 +756,14 @@ parser!{
                 else_branch: expn().map(Box::new)
             }
         };
+        let while_do = struct_parser!{
+            While {
+                _: token(Keyword(Reserved::While)),
+                condition: expn().map(Box::new),
+                _: token(Keyword(Reserved::Do)),
+                body: expn().map(Box::new)
+            }
+        };
         let lambda = struct_parser!{
             Lambda {
                 _: token(Keyword(Reserved::Fn)),
@@ -341,15 +774,32 @@ parser!{
                 body: expn().map(Box::new),
             }
         };
-        let let_val = struct_parser!{
-            Let {
-                _: token(Keyword(Reserved::Let)),
-                _: space(),
-                _: token(Keyword(Reserved::Val)),
+        let bindings = sep_by1::<Vec<_>, _, _, _>(
+            (name(), binding()),
+            (token(Keyword(Reserved::Val)), space()),
+        );
+        let let_val = (
+            // only backtrack over the shared `let` prefix so errors
+            // inside the binding are reported where they happen
+            attempt((token(Keyword(Reserved::Let)), space(), token(Keyword(Reserved::Val)))),
+            space(),
+            bindings,
+            token(Keyword(Reserved::In)),
+            expn(),
+            token(Keyword(Reserved::End)),
+        ).map(|(_, _, bindings, _, body, _)| Let{
+            bindings: bindings.into_iter().map(|(name, binder)| (name, *binder)).collect(),
+            body: Box::new(body),
+        });
+        let let_rec = struct_parser!{
+            Funs {
+                _: attempt((
+                    token(Keyword(Reserved::Let)), space(),
+                    token(Keyword(Reserved::Val)), space(),
+                    token(Keyword(Reserved::Rec))
+                )),
                 _: space(),
-                name: name(),
-                _: lex(token(Keyword(Reserved::Equal))),
-                binder: expn().map(Box::new),
+                defs: recf().map(|def| vec![def]),
                 _: token(Keyword(Reserved::In)),
                 body: expn().map(Box::new),
                 _: token(Keyword(Reserved::End)),
@@ -367,116 +817,300 @@ parser!{
                 _: token(Keyword(Reserved::End)),
             }
         };
-        lex(choice!(if_then_else, lambda, attempt(let_val), functions, disj()))
+        let raise = struct_parser!{
+            Raise(
+                _: token(Keyword(Reserved::Raise)),
+                expn().map(Box::new)
+            )
+        };
+        nested(lex(choice!(if_then_else, while_do, lambda, let_rec, let_val, functions, raise, handle())))
     }
 }
 
 parser!{
-    pub fn funs['a, Input]()(Input) -> Vec<Definition<'a>>
+    pub fn handle['a, Input]()(Input) -> Expr<'a>
     where [ Input: Stream<Item = Token<'a>> ]
     {
         use Token::*;
-        let function = struct_parser!{
-            Definition {
-                name: name(),
-                _: space(),
-                argument: name(),
-                _: space(),
-                _: token(Keyword(Reserved::Equal)),
+        let rule = struct_parser!{
+            Rule {
+                pattern: lex(pattern()),
+                _: token(Keyword(Reserved::Arrow)),
                 body: expn().map(Box::new),
             }
         };
-        let and = token(Keyword(Reserved::And));
-        sep_by1(function, lex(and))
+        let rules = (token(Keyword(Reserved::Handle)), sep_by1(rule, token(Keyword(Reserved::Bar))))
+            .map(|(_, rules)| rules);
+        (annot(), optional(rules)).map(|(expr, rules)| match rules {
+            Some(rules) => Expr::Handle{ expr: Box::new(expr), rules },
+            None => expr,
+        })
+    }
+}
+
+fn annotated(expr: Expr, ty: Option<TypeExpr>) -> Expr {
+    match ty {
+        Some(ty) => Expr::Annot{ expr: Box::new(expr), ty },
+        None => expr,
+    }
+}
+
+parser!{
+    pub fn annot['a, Input]()(Input) -> Expr<'a>
+    where [ Input: Stream<Item = Token<'a>> ]
+    {
+        let ascription = (token(Token::Keyword(Reserved::Colon)), ty()).map(|(_, ty)| ty);
+        (infix(0), optional(ascription)).map(|(expr, ty)| annotated(expr, ty))
+    }
+}
+
+parser!{
+    // the `: ty = expr` after the name a `val` binds, the type ends up on
+    // the binder
+    pub fn binding['a, Input]()(Input) -> Box<Expr<'a>>
+    where [ Input: Stream<Item = Token<'a>> ]
+    {
+        let colon = token(Token::Keyword(Reserved::Colon));
+        let ascription = attempt((optional(space()), colon, ty())).map(|(_, _, ty)| ty);
+        let equal = lex(token(Token::Keyword(Reserved::Equal)));
+        (optional(ascription), equal, expn()).map(|(ty, _, binder)| Box::new(annotated(binder, ty)))
+    }
+}
+
+parser!{
+    pub fn ty['a, Input]()(Input) -> TypeExpr
+    where [ Input: Stream<Item = Token<'a>> ]
+    {
+        let arrow = lex(token(Token::Keyword(Reserved::TypeArrow)));
+        nested(sep_by1(ty_product(), arrow))
+            .map(|types| fold_right(types, |from, to| TypeExpr::Arrow(Box::new(from), Box::new(to))))
+    }
+}
+
+parser!{
+    pub fn ty_product['a, Input]()(Input) -> TypeExpr
+    where [ Input: Stream<Item = Token<'a>> ]
+    {
+        let times = lex(token(Token::Keyword(Reserved::Mult)));
+        sep_by1(ty_postfix(), times)
+            .map(|types| fold_right(types, |fst, snd| TypeExpr::Tuple(Box::new(fst), Box::new(snd))))
+    }
+}
+
+parser!{
+    pub fn ty_postfix['a, Input]()(Input) -> TypeExpr
+    where [ Input: Stream<Item = Token<'a>> ]
+    {
+        let list = lex(satisfy(|t| t == Token::Name("list")));
+        (ty_atom(), many::<Vec<_>, _, _>(list))
+            .map(|(ty, lists)| lists.iter().fold(ty, |ty, _| TypeExpr::List(Box::new(ty))))
     }
 }
 
 parser!{
-    pub fn disj['a, Input]()(Input) -> Expr<'a>
+    pub fn ty_atom['a, Input]()(Input) -> TypeExpr
     where [ Input: Stream<Item = Token<'a>> ]
     {
-        let binary = satisfy_map(|t| match t {
-            Token::Keyword(Reserved::OrElse) => Some(BinaryOp::OrElse),
+        use Direction::*;
+        let base = satisfy_map(|t| match t {
+            Token::Name("unit") => Some(TypeExpr::Unit),
+            Token::Name("int") => Some(TypeExpr::Int),
+            Token::Name("bool") => Some(TypeExpr::Bool),
+            Token::Name("string") => Some(TypeExpr::String),
+            Token::Name("char") => Some(TypeExpr::Char),
+            Token::Name("real") => Some(TypeExpr::Real),
+            Token::Name(name) if name != "list" => Some(TypeExpr::Named(name.to_string())),
             _ => None
-        }).map(|op| move |left, right| Expr::Binary {
-            left: Box::new(left),
-            operation: op,
-            right: Box::new(right)
         });
-        chainl1(conj(), binary)
+        let paren = |dir| token(Token::Delim(Delimiter::Paren(dir)));
+        lex(choice!(base, between(paren(Left), paren(Right), ty())).expected("type"))
     }
 }
 
 parser!{
-    pub fn conj['a, Input]()(Input) -> Expr<'a>
+    pub fn pattern['a, Input]()(Input) -> Pattern<'a>
     where [ Input: Stream<Item = Token<'a>> ]
     {
-        let binary = satisfy_map(|t| match t {
-            Token::Keyword(Reserved::AndAlso) => Some(BinaryOp::AndAlso),
-            _ => None
-        }).map(|op| move |left, right| Expr::Binary {
-            left: Box::new(left),
-            operation: op,
-            right: Box::new(right)
+        let construct = (lex(constructor_name()), optional(pattern_atom())).map(|(name, argument)| {
+            Pattern::Construct{ name, argument: argument.map(Box::new) }
         });
-        chainl1(cmp(), binary)
+        nested(choice!(construct, pattern_atom()))
     }
 }
 
 parser!{
-    pub fn cmp['a, Input]()(Input) -> Expr<'a>
+    pub fn pattern_atom['a, Input]()(Input) -> Pattern<'a>
     where [ Input: Stream<Item = Token<'a>> ]
     {
-        use Expr::*;
-        let comparison = satisfy_map(|t| match t {
-            Token::Keyword(Reserved::Equal) => Some(BinaryOp::Equal),
-            Token::Keyword(Reserved::LessThan) => Some(BinaryOp::LessThan),
-            _ => None
+        use Direction::*;
+        let wildcard = token(Token::Keyword(Reserved::Wildcard)).map(|_| Pattern::Wildcard);
+        let construct = constructor_name().map(|name| Pattern::Construct{ name, argument: None });
+        let variable = name().map(Pattern::Var);
+        let paren = |dir| token(Token::Delim(Delimiter::Paren(dir)));
+        let nested = between(paren(Left), paren(Right), lex(pattern()));
+        let equal = token(Token::Keyword(Reserved::Equal));
+        let field = (lex(name()), optional((equal, lex(pattern())))).map(|(label, pattern)| match pattern {
+            Some((_, pattern)) => (label, pattern),
+            None => (label, Pattern::Var(label)),
         });
-        let binary = struct_parser!{
-            Binary {
-                left: add().map(Box::new),
-                operation: comparison,
-                right: add().map(Box::new),
+        let record = labeled(field).map(Pattern::Record);
+        lex(choice!(wildcard, construct, variable, nested, record).expected("pattern"))
+    }
+}
+
+parser!{
+    // the braces around the fields of a record or a record pattern, a label
+    // may only be used once
+    pub fn labeled['a, Input, P, T](field: P)(Input) -> Vec<(&'a str, T)>
+    where [ Input: Stream<Item = Token<'a>>, P: Parser<Input, Output = (&'a str, T)> ]
+    {
+        use Direction::*;
+        let brace = |dir| token(Token::Delim(Delimiter::Brace(dir)));
+        let comma = token(Token::Delim(Delimiter::Comma));
+        between(brace(Left), brace(Right), sep_by1(field, comma)).and_then(|fields: Vec<(&'a str, T)>| {
+            let duplicate = fields.iter().enumerate()
+                .any(|(i, (label, _))| fields[..i].iter().any(|(other, _)| other == label));
+            if duplicate {
+                Err(StreamErrorFor::<Input>::message_static_message("duplicate-label"))
+            } else {
+                Ok(fields)
+            }
+        })
+    }
+}
+
+parser!{
+    pub fn funs['a, Input]()(Input) -> Vec<Definition<'a>>
+    where [ Input: Stream<Item = Token<'a>> ]
+    {
+        use Token::*;
+        let function = struct_parser!{
+            Definition {
+                name: name(),
+                _: space(),
+                argument: name(),
+                _: space(),
+                _: token(Keyword(Reserved::Equal)),
+                body: expn().map(Box::new),
             }
         };
-        choice!(attempt(binary), add())
+        let and = token(Keyword(Reserved::And));
+        sep_by1(function, lex(and))
     }
 }
 
 parser!{
-    pub fn add['a, Input]()(Input) -> Expr<'a>
+    pub fn recf['a, Input]()(Input) -> Definition<'a>
     where [ Input: Stream<Item = Token<'a>> ]
     {
-        let binary = satisfy_map(|t| match t {
-            Token::Keyword(Reserved::Add) => Some(BinaryOp::Add),
-            Token::Keyword(Reserved::Sub) => Some(BinaryOp::Sub),
-            _ => None
-        }).map(|op| move |left, right| Expr::Binary {
-            left: Box::new(left),
-            operation: op,
-            right: Box::new(right)
+        use Token::*;
+        struct_parser!{
+            Definition {
+                name: name(),
+                _: lex(token(Keyword(Reserved::Equal))),
+                _: token(Keyword(Reserved::Fn)),
+                _: space(),
+                argument: name(),
+                _: space(),
+                _: token(Keyword(Reserved::Arrow)),
+                body: expn().map(Box::new),
+            }
+        }
+    }
+}
+
+thread_local! {
+    // the table `infix` parses with on this thread
+    static OPERATORS: RefCell<Rc<Operators>> = RefCell::new(Rc::new(Operators::default()));
+}
+
+// `f`, what it parses on this thread having the binary operators of
+// `operators` instead of the usual ones
+pub fn with_operators<T>(operators: Operators, f: impl FnOnce() -> T) -> T {
+    fixities_scoped(|| {
+        OPERATORS.with(|current| current.replace(Rc::new(operators)));
+        f()
+    })
+}
+
+// `f`, the fixity declarations it parses lasting until it returns or
+// panics
+fn fixities_scoped<T>(f: impl FnOnce() -> T) -> T {
+    struct Restore(Option<Rc<Operators>>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            if let Some(outer) = self.0.take() {
+                OPERATORS.with(|current| current.replace(outer));
+            }
+        }
+    }
+    let _restore = Restore(Some(OPERATORS.with(|current| Rc::clone(&current.borrow()))));
+    f()
+}
+
+// `decl`, what is parsed after it having the operators it declares. only
+// called once a loop over declarations has taken it, no parser backtracks
+// past that point, so no declaration outlives a branch that failed
+fn declared(decl: Decl) -> Decl {
+    if let Decl::Infix(ref fixity) = decl {
+        OPERATORS.with(|current| {
+            let mut operators = Operators::clone(&current.borrow());
+            operators.declare(fixity);
+            current.replace(Rc::new(operators))
         });
-        chainl1(mult(), binary)
     }
+    decl
 }
 
+// a name a fixity declaration made infix, which can not be an argument
+fn is_infix_name(t: &Token) -> bool {
+    matches!(t, Token::Name(_)) && OPERATORS.with(|current| {
+        current.borrow().levels.iter().any(|level| level.infix(t).is_some())
+    })
+}
+
+// the operands of `first` and `rest` joined by the operators between them
+fn join_infix<'a>(assoc: Assoc, first: Expr<'a>, rest: Vec<(Infix<'a>, Expr<'a>)>) -> Expr<'a> {
+    if assoc != Assoc::Right {
+        return rest.into_iter().fold(first, |left, (infix, right)| infix.join(left, right))
+    }
+    let (infixes, mut operands): (Vec<Infix<'a>>, Vec<Expr<'a>>) = rest.into_iter().unzip();
+    operands.insert(0, first);
+    let last = operands.pop().expect("there is an operand after every operator");
+    operands.into_iter().zip(infixes).rev().fold(last, |right, (left, infix)| infix.join(left, right))
+}
 
 parser!{
-    pub fn mult['a, Input]()(Input) -> Expr<'a>
+    // the levels of the operator table from `level` on, each one's operands
+    // being what the next one parses and the last one's being `nega`
+    pub fn infix['a, Input](level: usize)(Input) -> Expr<'a>
     where [ Input: Stream<Item = Token<'a>> ]
     {
-        let binary = satisfy_map(|t| match t {
-            Token::Keyword(Reserved::Mult) => Some(BinaryOp::Mult),
-            Token::Keyword(Reserved::Div) => Some(BinaryOp::Div),
-            Token::Keyword(Reserved::Mod) => Some(BinaryOp::Mod),
-            _ => None
-        }).map(|op| move |left, right| Expr::Binary {
-            left: Box::new(left),
-            operation: op,
-            right: Box::new(right)
+        let operators = OPERATORS.with(|operators| Rc::clone(&operators.borrow()));
+        let (level, tighter) = (*level, *level + 1 < operators.levels.len());
+        let assoc = operators.levels.get(level).map_or(Assoc::Left, |level| level.assoc);
+        let operator = move |t: Token<'a>| operators.levels.get(level).and_then(|level| level.infix(&t));
+        let operand = move || parser(move |input: &mut Input| if tighter {
+            infix(level + 1).parse_stream(input).into_result()
+        } else {
+            nega().parse_stream(input).into_result()
         });
-        chainl1(nega(), binary)
+        let mut chain = (operand(), many((satisfy_map(operator.clone()), operand())))
+            .map(move |(first, rest)| join_infix(assoc, first, rest));
+        // comparisons do not associate, `a < b < c` is an error pointing at
+        // the second operator rather than a type error at runtime
+        let same_level = operator.clone();
+        let chained = not_followed_by(satisfy(move |t| same_level(t).is_some()).map(Info::Token))
+            .message("chained-comparison");
+        let mut single = (operand(), optional((satisfy_map(operator), operand(), chained)))
+            .map(|(left, rest)| match rest {
+                Some((infix, right, ())) => infix.join(left, right),
+                None => left,
+            });
+        parser(move |input: &mut Input| match assoc {
+            Assoc::Neither => single.parse_stream(input).into_result(),
+            _ => chain.parse_stream(input).into_result(),
+        })
     }
 }
 
@@ -490,16 +1124,31 @@ parser!{
             Token::Keyword(Reserved::Fst) => Some(UnaryOp::Fst),
             Token::Keyword(Reserved::Snd) => Some(UnaryOp::Snd),
             Token::Keyword(Reserved::Print) => Some(UnaryOp::Print),
+            Token::Keyword(Reserved::Neg) => Some(UnaryOp::Neg),
+            Token::Keyword(Reserved::Ord) => Some(UnaryOp::Ord),
+            Token::Keyword(Reserved::Chr) => Some(UnaryOp::Chr),
+            // a leading `-` can only be a negation here
+            Token::Keyword(Reserved::Sub) => Some(UnaryOp::Neg),
             _ => None
         });
         let unary = struct_parser!{
             Unary {
                 operation: operation,
-                _: space(),
+                _: optional(space()),
                 child: appn().map(Box::new)
             }
         };
-        choice!(attempt(unary), appn())
+        let select = struct_parser!{
+            Select {
+                _: token(Token::Keyword(Reserved::Select)),
+                _: optional(space()),
+                label: name(),
+                _: optional(space()),
+                record: appn().map(Box::new)
+            }
+        };
+        // the operand of a binary operator may start with a space
+        (optional(space()), choice!(attempt(unary), select, appn())).map(|(_, expr)| expr)
     }
 }
 
@@ -507,53 +1156,211 @@ parser!{
     pub fn appn['a, Input]()(Input) -> Expr<'a>
     where [ Input: Stream<Item = Token<'a>> ]
     {
-        let binary = value(|left, right| Expr::App {
-            left: Box::new(left),
-            right: Box::new(right)
-        });
+        // `f x plus y` is `plus (f x, y)`, though `plus (x, y)` still applies
+        // `plus` like any other function
+        let infix_name = satisfy(|t| is_infix_name(&t)).map(Info::Token);
+        let binary = not_followed_by(infix_name).with(value(|left, right| match left {
+            // a constructor takes the first thing it is applied to
+            Expr::Construct{ name, argument: None } => Expr::Construct{ name, argument: Some(Box::new(right)) },
+            left => Expr::App {
+                left: Box::new(left),
+                right: Box::new(right)
+            },
+        }));
         chainl1(atom(), binary).message("function application")
     }
 }
 
-parser!{
-    pub fn seqn['a, Input]()(Input) -> Expr<'a>
-    where [ Input: Stream<Item = Token<'a>> ]
-    {
-        let semicolon = token(Token::Delim(Delimiter::Semicolon));
-        sep_by1(expn(), semicolon).map(Expr::Seq)
-    }
-}
-
 parser!{
     pub fn atom['a, Input]()(Input) -> Expr<'a>
     where [ Input: Stream<Item = Token<'a>> ]
     {
         use Direction::*;
         use Expr::*;
+        let construct = constructor_name().map(|name| Construct{ name, argument: None });
         let variable = name().map(Var);
         let literal = satisfy_map(|t| match t {
             Token::Lit(lit) => Some(Lit(lit)),
+            Token::Keyword(Reserved::Nil) => Some(List(vec![])),
+            // only ever in the tokens error recovery hands the parser
+            Token::Error(span) => Some(Error(span)),
             _ => None
         });
         let paren = |dir| token(Token::Delim(Delimiter::Paren(dir)));
-        let sequence = between(paren(Left), paren(Right), lex(seqn()));
-        let tuple = struct_parser!{
-            Tuple {
-                _: paren(Left),
-                fst: lex(expn().map(Box::new)),
-                _: token(Token::Delim(Delimiter::Comma)),
-                snd: lex(expn().map(Box::new)),
-                _: paren(Right),
-            }
+        let comma = || token(Token::Delim(Delimiter::Comma));
+        let semicolon = token(Token::Delim(Delimiter::Semicolon));
+        // what comes after the first expression tells a tuple from a
+        // sequence, backtracking to try the other would take time
+        // exponential in how deeply parentheses nest
+        let second = (comma(), lex(expn())).map(|(_, snd)| (true, vec![snd]));
+        let rest = many((semicolon, lex(expn())).map(|(_, expr)| expr)).map(|rest| (false, rest));
+        let group = (paren(Left), lex(expn()), choice!(second, rest), paren(Right))
+            .map(|(_, fst, (tuple, mut rest), _)| match rest.pop() {
+                Some(snd) if tuple => Tuple{ fst: Box::new(fst), snd: Box::new(snd) },
+                last => Seq(std::iter::once(fst).chain(rest).chain(last).collect()),
+            });
+        let bracket = |dir| token(Token::Delim(Delimiter::Bracket(dir)));
+        let list = between(bracket(Left), bracket(Right), lex(sep_by(expn(), comma()))).map(List);
+        let equal = token(Token::Keyword(Reserved::Equal));
+        let field = (lex(name()), equal, expn()).map(|(label, _, expr)| (label, expr));
+        let record = labeled(field).map(Record);
+        lex(choice!(construct, variable, literal, group, list, record).expected("expression"))
+    }
+}
+
+pub fn parse<'a>(source: &'a str) -> Result<Expr<'a>, ParseError<'a>> {
+    prog().easy_parse(Tokenizer::new(source))
+        .map(|(expr, _)| expr)
+        .map_err(|err| ParseError::new(source, err))
+}
+
+// the stack `parse_str` parses on, `MAX_NESTING` levels take a good part
+// of it in a debug build
+const PARSE_STACK: usize = 64 << 20;
+
+// how deep a tree `parse_str` gives back, operators and applications nest
+// it without nesting the parser. what walks a tree recursively, even to
+// drop it, takes stack in proportion
+pub const MAX_DEPTH: usize = 2000;
+
+// how many nodes down the deepest leaf of `ty` is, or `limit` if that is
+// further
+fn ty_depth(ty: &TypeExpr, limit: usize) -> usize {
+    let mut deepest = 0;
+    let mut stack = vec![(ty, 1)];
+    while let Some((ty, depth)) = stack.pop() {
+        deepest = deepest.max(depth);
+        if limit <= deepest {
+            return limit
+        }
+        match ty {
+            TypeExpr::Tuple(fst, snd) | TypeExpr::Arrow(fst, snd) => {
+                stack.extend(vec![(&**fst, depth + 1), (&**snd, depth + 1)])
+            },
+            TypeExpr::List(element) => stack.push((element, depth + 1)),
+            _ => {},
+        }
+    }
+    deepest
+}
+
+// the same for `expr`, counting the types of its annotations below them
+fn depth(expr: &Expr, limit: usize) -> usize {
+    let mut deepest = 0;
+    let mut stack = vec![(expr, 1)];
+    while let Some((expr, depth)) = stack.pop() {
+        let below = match expr {
+            Expr::Annot{ ty, .. } => ty_depth(ty, limit),
+            _ => 0,
         };
-        lex(choice!(variable, literal, attempt(sequence), tuple))
+        deepest = deepest.max(depth + below);
+        if limit <= deepest {
+            return limit
+        }
+        stack.extend(expr.children().into_iter().map(|child| (child, depth + 1)));
     }
+    deepest
+}
+
+// drops `expr` a node at a time where dropping it the usual way recurses
+// as deep as it goes
+fn dismantle(expr: Expr) {
+    use Expr::*;
+    let mut stack = vec![expr];
+    let mut types = vec![];
+    while let Some(expr) = stack.pop() {
+        match expr {
+            Var(_) | Lit(_) | Error(_) => {},
+            Unary{ child, .. } | Lambda{ body: child, .. } | Raise(child) => stack.push(*child),
+            Annot{ expr, ty } => {
+                stack.push(*expr);
+                types.push(ty)
+            },
+            Select{ record, .. } => stack.push(*record),
+            Binary{ left, right, .. } | App{ left, right } => stack.extend(vec![*left, *right]),
+            Cons{ head, tail } => stack.extend(vec![*head, *tail]),
+            Tuple{ fst, snd } => stack.extend(vec![*fst, *snd]),
+            While{ condition, body } => stack.extend(vec![*condition, *body]),
+            Let{ bindings, body } => {
+                stack.extend(bindings.into_iter().map(|(_, binder)| binder));
+                stack.push(*body)
+            },
+            IfThenElse{ condition, if_branch, else_branch } => {
+                stack.extend(vec![*condition, *if_branch, *else_branch])
+            },
+            Seq(sequence) | List(sequence) => stack.extend(sequence),
+            Funs{ defs, body } => {
+                stack.extend(defs.into_iter().map(|def| *def.body));
+                stack.push(*body)
+            },
+            Construct{ argument, .. } => stack.extend(argument.map(|argument| *argument)),
+            Handle{ expr, rules } => {
+                stack.push(*expr);
+                stack.extend(rules.into_iter().map(|rule| *rule.body))
+            },
+            Record(fields) => stack.extend(fields.into_iter().map(|(_, expr)| expr)),
+        }
+    }
+    while let Some(ty) = types.pop() {
+        match ty {
+            TypeExpr::Tuple(fst, snd) | TypeExpr::Arrow(fst, snd) => types.extend(vec![*fst, *snd]),
+            TypeExpr::List(element) => types.push(*element),
+            _ => {},
+        }
+    }
+}
+
+// `parse` for input that can not be trusted, like a fuzzer's. however it
+// nests and however long its tokens are it is an error rather than a
+// panic or an overflowing stack, and what comes back is no deeper than
+// `MAX_DEPTH`
+pub fn parse_str<'a>(source: &'a str) -> Result<Expr<'a>, ParseError<'a>> {
+    // the thread it parses on has the operators of this one
+    let operators = OPERATORS.with(|operators| Operators::clone(&operators.borrow()));
+    let parse = || {
+        let expr = with_operators(operators.clone(), || parse(source))?;
+        if depth(&expr, MAX_DEPTH + 1) <= MAX_DEPTH {
+            return Ok(expr)
+        }
+        dismantle(expr);
+        Err(ParseError::too_deep(source))
+    };
+    thread::scope(|scope| match thread::Builder::new().stack_size(PARSE_STACK).spawn_scoped(scope, parse) {
+        Ok(handle) => handle.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)),
+        // where there are no threads, like in a browser
+        Err(_) => parse(),
+    })
+}
+
+// every tree that comes out of the parser should line up with its tokens,
+// one that does not is an error rather than a tree without ids
+pub fn parse_indexed<'a>(source: &'a str) -> Result<(Expr<'a>, NodeTable), ParseError<'a>> {
+    let expr = parse(source)?;
+    let table = NodeTable::new(source, &expr).ok_or_else(|| ParseError::unindexed(source))?;
+    Ok((expr, table))
+}
+
+pub fn parse_decl<'a>(source: &'a str) -> Result<Decl<'a>, ParseError<'a>> {
+    fixities_scoped(|| decl().easy_parse(Tokenizer::new(source)))
+        .map(|(decl, _)| decl)
+        .map_err(|err| ParseError::new(source, err))
+}
+
+pub fn parse_script<'a>(source: &'a str) -> Result<Program<'a>, ParseError<'a>> {
+    fixities_scoped(|| script().easy_parse(Tokenizer::new(source)))
+        .map(|(decls, _)| decls)
+        .map_err(|err| ParseError::new(source, err))
+}
+
+pub fn parse_program<'a>(source: &'a str) -> Result<Program<'a>, ParseError<'a>> {
+    fixities_scoped(|| program().easy_parse(Tokenizer::new(source)))
+        .map(|(decls, _)| decls)
+        .map_err(|err| ParseError::new(source, err))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::lexer::{Tokenizer};
 
     #[test]
     fn parse_success_unit() {
@@ -573,11 +1380,217 @@ mod tests {
         let tests = vec![
             "(1 + 2) * 3",
             "let val x = 1 in let val y = 2 in x + y end end",
-            "fn x => fn y => x (x (x y))"
+            "fn x => fn y => x (x (x y))",
+            "[1, 2 + 3, []]",
+            "(1 :: 2 :: []) :: [3] :: []"
         ];
         for test in tests {
             let res = prog().parse(Tokenizer::new(test));
             assert_eq!(Ok(test.to_string()), res.map(|(e, _)| e.to_string()))
         }
     }
+
+    #[test]
+    fn parse_decl_unit() {
+        match decl().parse(Tokenizer::new("val x = 1 + 2")) {
+            Ok((Decl::Val{ name, binder }, _)) => {
+                assert_eq!(name, "x");
+                assert_eq!(binder.to_string(), "1 + 2")
+            },
+            Ok((decl, _)) => panic!("expected a val declaration, got {:?}", decl),
+            Err(err) => panic!("{:?}", err),
+        }
+        match decl().parse(Tokenizer::new("val rec f = fn n => f n")) {
+            Ok((Decl::Fun(defs), _)) => assert_eq!(defs.len(), 1),
+            Ok((decl, _)) => panic!("expected a recursive declaration, got {:?}", decl),
+            Err(err) => panic!("{:?}", err),
+        }
+        match decl().parse(Tokenizer::new("fun f n = g n and g n = f n")) {
+            Ok((Decl::Fun(defs), _)) => assert_eq!(defs.len(), 2),
+            Ok((decl, _)) => panic!("expected a fun declaration, got {:?}", decl),
+            Err(err) => panic!("{:?}", err),
+        }
+        match decl().parse(Tokenizer::new("let val x = 1 in x end")) {
+            Ok((Decl::Expr(_), _)) => {},
+            Ok((decl, _)) => panic!("expected an expression, got {:?}", decl),
+            Err(err) => panic!("{:?}", err),
+        }
+    }
+
+    #[test]
+    fn parse_program_unit() {
+        let source = "val x = 1; fun f n = n + x\ndatatype t = A | B\nprint (f 1);\n(x; f 2)";
+        let kinds: Vec<String> = parse_program(source).unwrap().iter().map(|decl| match decl {
+            Decl::Val{ name, .. } => format!("val {}", name),
+            Decl::Fun(defs) => format!("fun {}", defs[0].name),
+            Decl::Datatype(datatype) => format!("datatype {}", datatype.name),
+            Decl::Infix(fixity) => fixity.to_string(),
+            Decl::Expr(expr) => expr.to_string(),
+        }).collect();
+        assert_eq!(kinds, vec!["val x", "fun f", "datatype t", "print (f 1)", "(x; f 2)"]);
+        assert_eq!(parse_program("1 + 2;").unwrap().len(), 1);
+        assert_eq!(parse_program(" ").unwrap().len(), 0);
+        // expressions in the middle need their `;`
+        assert!(parse_program("print 1\nval x = 2").is_err());
+        assert!(parse_program("val x = 1;;").is_err());
+
+        let nested = |source| nest(parse_program(source).unwrap()).to_string();
+        assert_eq!(nested("val x = 1; fun f n = n + x; f 2"), "let val x = 1 in let fun f n = n + x in f 2 end end");
+        assert_eq!(nested("print 1; datatype t = A"), "let val it = print 1 in () end");
+        assert_eq!(nested(""), "()");
+    }
+
+    #[test]
+    fn parse_fixity_unit() {
+        let source = "fun plus p = fst p + snd p; fun cat p = fst p :: snd p\n\
+            infix 5 plus; infixr 4 cat\n\
+            1 plus 2 * 3 plus 4 cat 5 cat []";
+        let program = parse_program(source).unwrap();
+        match &program[2] {
+            Decl::Infix(fixity) => assert_eq!(fixity.to_string(), "infix 5 plus"),
+            decl => panic!("expected a fixity, got {:?}", decl),
+        }
+        let expr = nest(program);
+        assert!(expr.to_string().ends_with("in cat (plus (plus (1, 2 * 3), 4), cat (5, [])) end end"));
+        assert_eq!(expr.eval().unwrap().to_string(), "[11, 5]");
+
+        // only for the rest of the program, and only names
+        assert!(matches!(parse("a plus b"), Ok(Expr::App{ .. })));
+        assert_eq!(parse("plus (1, 2)").unwrap().to_string(), "plus (1, 2)");
+        assert!(parse_program("infix 5 plus; fun f x = x plus").is_err());
+        assert!(parse_program("infix 10 plus").is_err());
+        assert!(parse_program("infix 5 +").is_err());
+
+        // a declaration parsed on its own, or in a branch that fails, does
+        // not declare anything
+        let plus = Token::Name("plus");
+        assert!(matches!(decl().parse(Tokenizer::new("infix 5 plus")), Ok((Decl::Infix(_), _))));
+        assert!(top().skip(token(Token::Name("nothing"))).parse(Tokenizer::new("infix 5 plus")).is_err());
+        assert!(!is_infix_name(&plus));
+        // and the outer table is back even when the parse panics
+        let mut operators = Operators::default();
+        operators.declare(&Fixity{ assoc: Assoc::Left, precedence: 5, names: vec!["plus"] });
+        let panicked = std::panic::catch_unwind(|| with_operators(operators, || panic!("parser bug")));
+        assert!(panicked.is_err());
+        assert!(!is_infix_name(&plus));
+    }
+
+    #[test]
+    fn parse_annot_unit() {
+        let ty = |source| match parse(source) {
+            Ok(Expr::Annot{ ty, .. }) => ty.to_string(),
+            other => panic!("expected an annotation, got {:?}", other),
+        };
+        assert_eq!(ty("x : int"), "int");
+        assert_eq!(ty("f : (int * int) list -> bool -> unit"), "(int * int) list -> bool -> unit");
+        assert_eq!(ty("f : (int -> int) -> (int * (bool * string))"), "(int -> int) -> int * bool * string");
+        // the binder keeps its annotation
+        match parse("let val x : bool = true in x end") {
+            Ok(Expr::Let{ bindings, .. }) => assert_eq!(bindings[0].1.to_string(), "true : bool"),
+            other => panic!("expected a let, got {:?}", other),
+        }
+        // a binder further along keeps its own
+        match parse("let val x = 1 val y : int = x in y end") {
+            Ok(Expr::Let{ bindings, .. }) => assert_eq!(bindings[1].1.to_string(), "x : int"),
+            other => panic!("expected a let, got {:?}", other),
+        }
+        for source in ["(1 + 2 : int) * 3", "fn x => (x : int list)", "(if b then 1 else 2) : int"] {
+            assert_eq!(parse(source).unwrap().to_string(), source);
+        }
+        assert!(parse("x : 1").is_err());
+    }
+
+    #[test]
+    fn parse_datatype_unit() {
+        let source = "datatype shape = Circle of int | Rect of int * int | Dot\nval s : shape = Rect (2, 3)";
+        let program = parse_script(source).unwrap();
+        match &program[0] {
+            Decl::Datatype(datatype) => {
+                assert_eq!(datatype.to_string(), "datatype shape = Circle of int | Rect of int * int | Dot");
+                assert_eq!(datatype.constructors[2], Constructor { name: "Dot", argument: None });
+            },
+            decl => panic!("expected a datatype, got {:?}", decl),
+        }
+        match &program[1] {
+            Decl::Val{ binder, .. } => assert_eq!(binder.to_string(), "Rect (2, 3) : shape"),
+            decl => panic!("expected a val, got {:?}", decl),
+        }
+        // a constructor takes one argument, anything after is an application
+        match parse("Some f x").unwrap() {
+            Expr::App{ left, .. } => assert_eq!(*left, Expr::Construct {
+                name: "Some",
+                argument: Some(Box::new(Expr::Var("f"))),
+            }),
+            expr => panic!("expected an application, got {:?}", expr),
+        }
+        for source in ["Some (Some 1) :: None :: []", "f Red (Circle 1)"] {
+            assert_eq!(parse(source).unwrap().to_string(), source);
+        }
+        assert!(parse_decl("datatype t = a | B").is_err());
+    }
+
+    #[test]
+    fn parse_handle_unit() {
+        let source = "f x handle Fail (Some msg) => msg | _ => raise Div";
+        let (expr, table) = parse_indexed(source).unwrap();
+        match &expr {
+            Expr::Handle{ rules, .. } => {
+                let patterns: Vec<String> = rules.iter().map(|rule| rule.pattern.to_string()).collect();
+                assert_eq!(patterns, vec!["Fail (Some msg)", "_"]);
+                assert_eq!(rules[0].pattern.names(), vec!["msg"]);
+            },
+            expr => panic!("expected a handle, got {:?}", expr),
+        }
+        // rule bodies get ids after the handled expression, in order
+        let span = table.span(ids::NodeId(5)).unwrap();
+        assert_eq!(&source[span.start..span.end], "raise Div");
+        assert_eq!(expr.to_string(), source);
+        assert!(parse("x handle").is_err());
+    }
+
+    #[test]
+    fn parse_str_unit() {
+        let source = "let val x = (1, 2) in fst x :: [snd x] end : int list";
+        assert_eq!(parse_str(source), parse(source));
+
+        // what does not lex is an error at it
+        fn err(source: &str) -> ParseError<'_> {
+            parse_str(source).unwrap_err()
+        }
+        let spans: Vec<(&str, Span)> = ["1 + $", "x =< y", "f \"a\""].iter()
+            .map(|source| (err(source).code(), err(source).span))
+            .collect();
+        assert_eq!(spans, vec![("E0010", Span::new(4, 5)), ("E0010", Span::new(2, 4)), ("E0010", Span::new(2, 3))]);
+        assert!(err("x =< y").messages[0].starts_with("`=<` is not a token"));
+
+        // nesting that would take too much stack, while parsing or after
+        let parens = format!("{}1{}", "(".repeat(100_000), ")".repeat(100_000));
+        assert_eq!(err(&parens).code(), "E0011");
+        assert_eq!(err(&"[".repeat(100_000)).code(), "E0011");
+        assert_eq!(err(&format!("{}1", "1 + ".repeat(3000))).code(), "E0011");
+        assert_eq!(err(&format!("fn x => x : {}int", "int -> ".repeat(3000))).code(), "E0011");
+        assert!(parse_str(&format!("{}nil", "1 :: ".repeat(1000))).is_ok());
+        // unclosed parentheses used to be tried both as a tuple and a sequence
+        assert_eq!(err(&"(".repeat(40)).code(), "E0002");
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde_roundtrip_unit() {
+        let source = "let fun f n = if n < 1 then [] else ~n :: f (n - 1) in (f 3, ()) end";
+        let expr = parse(source).unwrap();
+        let json = serde_json::to_string(&expr).unwrap();
+        assert_eq!(serde_json::from_str::<Expr>(&json).unwrap(), expr);
+        // variants are tagged by name so other languages can read them
+        let json = serde_json::to_value(parse("x + 1").unwrap()).unwrap();
+        assert_eq!(json["kind"], "Binary");
+        assert_eq!(json["value"]["operation"], "Add");
+        assert_eq!(json["value"]["right"], serde_json::json!({
+            "kind": "Lit", "value": { "kind": "Integer", "value": 1 }
+        }));
+        let token = Token::Keyword(Reserved::Then);
+        let json = serde_json::to_string(&token).unwrap();
+        assert_eq!(json, r#"{"kind":"Keyword","value":"Then"}"#);
+        assert_eq!(serde_json::from_str::<Token>(&json).unwrap(), token);
+    }
 }
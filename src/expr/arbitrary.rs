@@ -0,0 +1,240 @@
+use quickcheck::{Arbitrary, Gen};
+
+use crate::lexer::{Literal};
+use crate::expr::{UnaryOp, BinaryOp, Definition, Expr, Pattern, Rule};
+use crate::expr::visit::{ExprFolder, fold_children};
+
+// the types a generated program is built out of
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+enum Ty {
+    Int,
+    Bool,
+    // a pair of integers
+    Pair,
+}
+
+const TYPES: [Ty; 3] = [Ty::Int, Ty::Bool, Ty::Pair];
+const NAMES: [&str; 5] = ["a", "b", "x", "y", "z"];
+// apart from the variables, a function is only ever called
+const FUNCTIONS: [&str; 2] = ["f", "g"];
+
+// a random closed program that type checks, for property tests. it is
+// built for the type it should have out of the variables in scope that
+// have it, so it evaluates to a value unless it divides by zero. names are
+// bound again over ones in scope, and `fun`s recurse on a smaller integer
+// each time so they stop
+#[derive(Debug, Clone)]
+pub struct WellTyped(pub Expr<'static>);
+
+impl Arbitrary for WellTyped {
+    fn arbitrary(g: &mut Gen) -> WellTyped {
+        let depth = 1 + usize::arbitrary(g) % 5;
+        let ty = *g.choose(&TYPES).unwrap();
+        WellTyped(Generator { g, scope: vec![], functions: vec![] }.expr(ty, depth))
+    }
+    // what of the program still is one, a closed part of it
+    fn shrink(&self) -> Box<dyn Iterator<Item = WellTyped>> {
+        let parts: Vec<WellTyped> = self.0.children().into_iter()
+            .filter(|child| child.free_variables().is_empty())
+            .map(|child| WellTyped(child.clone()))
+            .collect();
+        Box::new(parts.into_iter())
+    }
+}
+
+struct Generator<'g> {
+    g: &'g mut Gen,
+    scope: Vec<(&'static str, Ty)>,
+    // the functions from integers in scope, by the type they give back
+    functions: Vec<(&'static str, Ty)>,
+}
+
+impl<'g> Generator<'g> {
+    fn below(&mut self, n: usize) -> usize {
+        usize::arbitrary(self.g) % n
+    }
+    fn boxed(&mut self, ty: Ty, depth: usize) -> Box<Expr<'static>> {
+        Box::new(self.expr(ty, depth))
+    }
+    fn expr(&mut self, ty: Ty, depth: usize) -> Expr<'static> {
+        use Expr::*;
+        // a name bound again only has the type of its innermost binding
+        let bound: Vec<&'static str> = self.scope.iter()
+            .enumerate()
+            .filter(|(i, (name, t))| *t == ty && self.scope[i + 1..].iter().all(|(other, _)| other != name))
+            .map(|(_, (name, _))| *name)
+            .collect();
+        let callable: Vec<&'static str> = self.functions.iter()
+            .filter(|(_, t)| *t == ty)
+            .map(|(name, _)| *name)
+            .collect();
+        if depth == 0 || self.below(4) == 0 {
+            return match (self.g.choose(&bound), self.g.choose(&callable)) {
+                (_, Some(name)) if self.below(3) == 0 => {
+                    let argument = self.leaf(Ty::Int);
+                    call(name, argument)
+                },
+                (Some(name), _) if self.below(2) == 0 => Var(name),
+                _ => self.leaf(ty),
+            }
+        }
+        let depth = depth - 1;
+        // a few ways to build any type
+        match self.below(10) {
+            0 => {
+                let (condition, if_branch) = (self.boxed(Ty::Bool, depth), self.boxed(ty, depth));
+                return IfThenElse{ condition, if_branch, else_branch: self.boxed(ty, depth) }
+            },
+            1 => {
+                // one to three bindings, at times over a name in scope,
+                // whatever its type
+                let mut bindings = vec![];
+                for _ in 0..=self.below(3) {
+                    let shadowed: Vec<&'static str> = self.scope.iter().map(|(name, _)| *name).collect();
+                    let name = match self.g.choose(&shadowed) {
+                        Some(name) if self.below(2) == 0 => name,
+                        _ => *self.g.choose(&NAMES).unwrap(),
+                    };
+                    let bound = *self.g.choose(&TYPES).unwrap();
+                    bindings.push((name, self.expr(bound, depth)));
+                    self.scope.push((name, bound));
+                }
+                let body = self.boxed(ty, depth);
+                self.scope.truncate(self.scope.len() - bindings.len());
+                return Let{ bindings, body }
+            },
+            2 => {
+                let name = *self.g.choose(&NAMES).unwrap();
+                let bound = *self.g.choose(&TYPES).unwrap();
+                self.scope.push((name, bound));
+                let body = self.boxed(ty, depth);
+                self.scope.pop();
+                let left = Box::new(Lambda{ name, body });
+                return App{ left, right: self.boxed(bound, depth) }
+            },
+            3 => {
+                let name = *self.g.choose(&FUNCTIONS).unwrap();
+                let result = *self.g.choose(&TYPES).unwrap();
+                let def = self.function(name, result, depth);
+                self.functions.push((name, result));
+                // often called right away, the body might not get to it
+                let body = if result == ty && self.below(2) == 0 {
+                    let argument = self.expr(Ty::Int, depth);
+                    Box::new(call(name, argument))
+                } else {
+                    self.boxed(ty, depth)
+                };
+                self.functions.pop();
+                return Funs{ defs: vec![def], body }
+            },
+            4 | 5 if !callable.is_empty() => {
+                let name = *self.g.choose(&callable).unwrap();
+                let argument = self.expr(Ty::Int, depth);
+                return call(name, argument)
+            },
+            _ => {},
+        }
+        match ty {
+            Ty::Int => match self.below(5) {
+                0 => Unary{ operation: UnaryOp::Neg, child: self.boxed(Ty::Int, depth) },
+                1 => {
+                    let operation = if self.below(2) == 0 { UnaryOp::Fst } else { UnaryOp::Snd };
+                    Unary{ operation, child: self.boxed(Ty::Pair, depth) }
+                },
+                // dividing by zero raises, this catches it at times
+                2 => {
+                    let expr = self.boxed(Ty::Int, depth);
+                    let pattern = Pattern::Construct{ name: "Div", argument: None };
+                    Handle{ expr, rules: vec![Rule { pattern, body: self.boxed(Ty::Int, depth) }] }
+                },
+                _ => {
+                    use BinaryOp::*;
+                    let operation = *self.g.choose(&[Add, Sub, Mult, Div, Mod]).unwrap();
+                    Binary{ left: self.boxed(Ty::Int, depth), operation, right: self.boxed(Ty::Int, depth) }
+                },
+            },
+            Ty::Bool => match self.below(3) {
+                0 => Unary{ operation: UnaryOp::Not, child: self.boxed(Ty::Bool, depth) },
+                1 => {
+                    let operation = if self.below(2) == 0 { BinaryOp::AndAlso } else { BinaryOp::OrElse };
+                    Binary{ left: self.boxed(Ty::Bool, depth), operation, right: self.boxed(Ty::Bool, depth) }
+                },
+                _ => {
+                    use BinaryOp::*;
+                    let operation = *self.g.choose(&[Equal, NotEqual, LessThan, LessEqual, GreaterThan, GreaterEqual])
+                        .unwrap();
+                    Binary{ left: self.boxed(Ty::Int, depth), operation, right: self.boxed(Ty::Int, depth) }
+                },
+            },
+            Ty::Pair => Tuple{ fst: self.boxed(Ty::Int, depth), snd: self.boxed(Ty::Int, depth) },
+        }
+    }
+    // `fun name n = if n <= 0 then ... else let val r = name (n - 1) in ... end`,
+    // reading what is in scope where it is defined. only the call on `n - 1`
+    // reaches it from its body, no other one could be sure to stop
+    fn function(&mut self, name: &'static str, result: Ty, depth: usize) -> Definition<'static> {
+        use Expr::*;
+        let argument = *self.g.choose(&NAMES).unwrap();
+        let hidden: Vec<(&'static str, Ty)> = self.functions.iter().filter(|(f, _)| *f == name).cloned().collect();
+        self.functions.retain(|(f, _)| *f != name);
+        self.scope.push((argument, Ty::Int));
+        let zero = Box::new(Lit(Literal::Integer(0)));
+        let operation = BinaryOp::LessEqual;
+        let condition = Box::new(Binary{ left: Box::new(Var(argument)), operation, right: zero });
+        let base = self.boxed(result, depth);
+        let one = Box::new(Lit(Literal::Integer(1)));
+        let smaller = Box::new(Binary{ left: Box::new(Var(argument)), operation: BinaryOp::Sub, right: one });
+        let binder = Box::new(App{ left: Box::new(Var(name)), right: smaller });
+        let rest = *self.g.choose(&NAMES).unwrap();
+        self.scope.push((rest, result));
+        let step = Box::new(Let{ bindings: vec![(rest, *binder)], body: self.boxed(result, depth) });
+        self.scope.pop();
+        self.scope.pop();
+        self.functions.extend(hidden);
+        let body = Box::new(IfThenElse{ condition, if_branch: base, else_branch: step });
+        Definition { name, argument, body }
+    }
+    fn leaf(&mut self, ty: Ty) -> Expr<'static> {
+        match ty {
+            Ty::Int => Expr::Lit(Literal::Integer(self.below(100) as i64)),
+            Ty::Bool => Expr::Lit(Literal::Boolean(bool::arbitrary(self.g))),
+            Ty::Pair => Expr::Tuple{ fst: Box::new(self.leaf(Ty::Int)), snd: Box::new(self.leaf(Ty::Int)) },
+        }
+    }
+}
+
+// `name` on `argument` made small, the function recurses as many times
+fn call(name: &'static str, argument: Expr<'static>) -> Expr<'static> {
+    let ten = Box::new(Expr::Lit(Literal::Integer(10)));
+    let right = Box::new(Expr::Binary{ left: Box::new(argument), operation: BinaryOp::Mod, right: ten });
+    Expr::App{ left: Box::new(Expr::Var(name)), right }
+}
+
+// the parser keeps parentheses as a sequence of one expression, a tree
+// built without them has none
+struct Ungroup;
+
+impl<'a> ExprFolder<'a> for Ungroup {
+    fn fold_expr(&mut self, expr: Expr<'a>) -> Expr<'a> {
+        match expr {
+            Expr::Seq(mut sequence) if sequence.len() == 1 => self.fold_expr(sequence.remove(0)),
+            expr => fold_children(self, expr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use quickcheck::{QuickCheck};
+    use super::*;
+    use crate::expr::{parse};
+
+    #[test]
+    fn source_roundtrip_property() {
+        fn roundtrip(program: WellTyped) -> bool {
+            let source = program.0.to_source();
+            parse(&source).map(|expr| expr.fold(&mut Ungroup)) == Ok(program.0)
+        }
+        QuickCheck::new().tests(300).quickcheck(roundtrip as fn(WellTyped) -> bool);
+    }
+}
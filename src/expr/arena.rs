@@ -0,0 +1,477 @@
+use std::ops::{Index};
+use std::collections::{BTreeSet};
+
+use crate::lexer::{Literal, Span};
+use crate::expr::{UnaryOp, BinaryOp, TypeExpr, Definition, Pattern, Rule, Expr};
+use crate::optimize::{fold_unary_literal, fold_binary_literals};
+
+pub mod eval;
+
+// where a node is in its `ExprArena`
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash)]
+pub struct ExprId(pub u32);
+
+// the children of a `Seq` or a `List`, a run of the ids an `ExprArena`
+// keeps next to its nodes, so those nodes own no vector of their own
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash)]
+pub struct Children {
+    pub start: u32,
+    pub len: u32,
+}
+
+// a node of an `ExprArena`, an `Expr` whose children are ids in the same
+// arena instead of boxes of their own
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Node<'a> {
+    Var(&'a str),
+    Lit(Literal<'a>),
+    Unary { operation: UnaryOp, child: ExprId },
+    Binary { left: ExprId, operation: BinaryOp, right: ExprId },
+    IfThenElse { condition: ExprId, if_branch: ExprId, else_branch: ExprId },
+    Tuple { fst: ExprId, snd: ExprId },
+    Let { bindings: Vec<(&'a str, ExprId)>, body: ExprId },
+    Lambda { name: &'a str, body: ExprId },
+    App { left: ExprId, right: ExprId },
+    Seq(Children),
+    List(Children),
+    Cons { head: ExprId, tail: ExprId },
+    // each definition's name, argument and body
+    Funs { defs: Vec<(&'a str, &'a str, ExprId)>, body: ExprId },
+    Annot { expr: ExprId, ty: TypeExpr },
+    Construct { name: &'a str, argument: Option<ExprId> },
+    Raise(ExprId),
+    Handle { expr: ExprId, rules: Vec<(Pattern<'a>, ExprId)> },
+    While { condition: ExprId, body: ExprId },
+    Record(Vec<(&'a str, ExprId)>),
+    Select { label: &'a str, record: ExprId },
+    Error(Span),
+}
+
+// trees kept in one vector, children before their parents, rather than
+// each node in an allocation of its own. walking every node is going
+// through the vector in order, and the whole of it is freed at once
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct ExprArena<'a> {
+    nodes: Vec<Node<'a>>,
+    // what the `Children` of the nodes point into
+    children: Vec<ExprId>,
+}
+
+// where a variable's value is while evaluating, `depth` bindings out from
+// the innermost one and `index` into that binding. a `let`, a `fn` or the
+// argument of a function binds one name, a `fun` all its definitions at
+// once and a pattern all its variables
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash)]
+pub struct Slot {
+    pub depth: u32,
+    pub index: u32,
+}
+
+impl<'a> ExprArena<'a> {
+    pub fn new() -> ExprArena<'a> {
+        ExprArena::default()
+    }
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+    // every node, each after its children
+    pub fn nodes(&self) -> impl Iterator<Item = (ExprId, &Node<'a>)> {
+        self.nodes.iter().enumerate().map(|(i, node)| (ExprId(i as u32), node))
+    }
+    pub fn push(&mut self, node: Node<'a>) -> ExprId {
+        self.nodes.push(node);
+        ExprId(self.nodes.len() as u32 - 1)
+    }
+    pub fn var(&mut self, name: &'a str) -> ExprId {
+        self.push(Node::Var(name))
+    }
+    pub fn lit(&mut self, lit: Literal<'a>) -> ExprId {
+        self.push(Node::Lit(lit))
+    }
+    pub fn unary(&mut self, operation: UnaryOp, child: ExprId) -> ExprId {
+        self.push(Node::Unary{ operation, child })
+    }
+    pub fn binary(&mut self, left: ExprId, operation: BinaryOp, right: ExprId) -> ExprId {
+        self.push(Node::Binary{ left, operation, right })
+    }
+    pub fn if_then_else(&mut self, condition: ExprId, if_branch: ExprId, else_branch: ExprId) -> ExprId {
+        self.push(Node::IfThenElse{ condition, if_branch, else_branch })
+    }
+    pub fn app(&mut self, left: ExprId, right: ExprId) -> ExprId {
+        self.push(Node::App{ left, right })
+    }
+    // keeps `ids` for a `Seq` or a `List` to point to
+    pub fn children_of(&mut self, ids: &[ExprId]) -> Children {
+        let start = self.children.len() as u32;
+        self.children.extend_from_slice(ids);
+        Children { start, len: ids.len() as u32 }
+    }
+    pub fn ids(&self, children: Children) -> &[ExprId] {
+        &self.children[children.start as usize..(children.start + children.len) as usize]
+    }
+    // `expr` and everything under it, the id being the root's
+    pub fn alloc(&mut self, expr: &Expr<'a>) -> ExprId {
+        use Expr::*;
+        let node = match expr {
+            Var(name) => Node::Var(name),
+            Lit(lit) => Node::Lit(*lit),
+            Unary{ operation, child } => Node::Unary{ operation: *operation, child: self.alloc(child) },
+            Binary{ left, operation, right } => {
+                Node::Binary{ left: self.alloc(left), operation: *operation, right: self.alloc(right) }
+            },
+            IfThenElse{ condition, if_branch, else_branch } => Node::IfThenElse {
+                condition: self.alloc(condition),
+                if_branch: self.alloc(if_branch),
+                else_branch: self.alloc(else_branch),
+            },
+            Tuple{ fst, snd } => Node::Tuple{ fst: self.alloc(fst), snd: self.alloc(snd) },
+            Let{ bindings, body } => Node::Let {
+                bindings: bindings.iter().map(|(name, binder)| (*name, self.alloc(binder))).collect(),
+                body: self.alloc(body),
+            },
+            Lambda{ name, body } => Node::Lambda{ name, body: self.alloc(body) },
+            App{ left, right } => Node::App{ left: self.alloc(left), right: self.alloc(right) },
+            Seq(sequence) => {
+                let ids: Vec<ExprId> = sequence.iter().map(|expr| self.alloc(expr)).collect();
+                Node::Seq(self.children_of(&ids))
+            },
+            List(elements) => {
+                let ids: Vec<ExprId> = elements.iter().map(|expr| self.alloc(expr)).collect();
+                Node::List(self.children_of(&ids))
+            },
+            Cons{ head, tail } => Node::Cons{ head: self.alloc(head), tail: self.alloc(tail) },
+            Funs{ defs, body } => Node::Funs {
+                defs: defs.iter().map(|def| (def.name, def.argument, self.alloc(&def.body))).collect(),
+                body: self.alloc(body),
+            },
+            Annot{ expr, ty } => Node::Annot{ expr: self.alloc(expr), ty: ty.clone() },
+            Construct{ name, argument } => Node::Construct {
+                name,
+                argument: argument.as_ref().map(|argument| self.alloc(argument)),
+            },
+            Raise(expr) => Node::Raise(self.alloc(expr)),
+            Handle{ expr, rules } => Node::Handle {
+                expr: self.alloc(expr),
+                rules: rules.iter().map(|rule| (rule.pattern.clone(), self.alloc(&rule.body))).collect(),
+            },
+            While{ condition, body } => Node::While{ condition: self.alloc(condition), body: self.alloc(body) },
+            Record(fields) => Node::Record(fields.iter().map(|(label, expr)| (*label, self.alloc(expr))).collect()),
+            Select{ label, record } => Node::Select{ label, record: self.alloc(record) },
+            Error(span) => Node::Error(*span),
+        };
+        self.push(node)
+    }
+    // the tree under `id` with a box for every node again
+    pub fn expr(&self, id: ExprId) -> Expr<'a> {
+        let boxed = |id: ExprId| Box::new(self.expr(id));
+        match &self[id] {
+            Node::Var(name) => Expr::Var(name),
+            Node::Lit(lit) => Expr::Lit(*lit),
+            Node::Unary{ operation, child } => Expr::Unary{ operation: *operation, child: boxed(*child) },
+            Node::Binary{ left, operation, right } => {
+                Expr::Binary{ left: boxed(*left), operation: *operation, right: boxed(*right) }
+            },
+            Node::IfThenElse{ condition, if_branch, else_branch } => Expr::IfThenElse {
+                condition: boxed(*condition),
+                if_branch: boxed(*if_branch),
+                else_branch: boxed(*else_branch),
+            },
+            Node::Tuple{ fst, snd } => Expr::Tuple{ fst: boxed(*fst), snd: boxed(*snd) },
+            Node::Let{ bindings, body } => Expr::Let {
+                bindings: bindings.iter().map(|(name, binder)| (*name, self.expr(*binder))).collect(),
+                body: boxed(*body),
+            },
+            Node::Lambda{ name, body } => Expr::Lambda{ name, body: boxed(*body) },
+            Node::App{ left, right } => Expr::App{ left: boxed(*left), right: boxed(*right) },
+            Node::Seq(sequence) => Expr::Seq(self.ids(*sequence).iter().map(|id| self.expr(*id)).collect()),
+            Node::List(elements) => Expr::List(self.ids(*elements).iter().map(|id| self.expr(*id)).collect()),
+            Node::Cons{ head, tail } => Expr::Cons{ head: boxed(*head), tail: boxed(*tail) },
+            Node::Funs{ defs, body } => Expr::Funs {
+                defs: defs.iter()
+                    .map(|(name, argument, body)| Definition { name, argument, body: boxed(*body) })
+                    .collect(),
+                body: boxed(*body),
+            },
+            Node::Annot{ expr, ty } => Expr::Annot{ expr: boxed(*expr), ty: ty.clone() },
+            Node::Construct{ name, argument } => Expr::Construct{ name, argument: argument.map(boxed) },
+            Node::Raise(expr) => Expr::Raise(boxed(*expr)),
+            Node::Handle{ expr, rules } => Expr::Handle {
+                expr: boxed(*expr),
+                rules: rules.iter()
+                    .map(|(pattern, body)| Rule { pattern: pattern.clone(), body: boxed(*body) })
+                    .collect(),
+            },
+            Node::While{ condition, body } => Expr::While{ condition: boxed(*condition), body: boxed(*body) },
+            Node::Record(fields) => Expr::Record(fields.iter().map(|(label, id)| (*label, self.expr(*id))).collect()),
+            Node::Select{ label, record } => Expr::Select{ label, record: boxed(*record) },
+            Node::Error(span) => Expr::Error(*span),
+        }
+    }
+    // the children of `id` in the order `Expr::children` has them
+    pub fn children(&self, id: ExprId) -> Vec<ExprId> {
+        match &self[id] {
+            Node::Var(_) | Node::Lit(_) | Node::Error(_) => vec![],
+            Node::Unary{ child, .. } => vec![*child],
+            Node::Binary{ left, right, .. } | Node::App{ left, right } => vec![*left, *right],
+            Node::Cons{ head, tail } => vec![*head, *tail],
+            Node::IfThenElse{ condition, if_branch, else_branch } => vec![*condition, *if_branch, *else_branch],
+            Node::While{ condition, body } => vec![*condition, *body],
+            Node::Tuple{ fst, snd } => vec![*fst, *snd],
+            Node::Let{ bindings, body } => bindings.iter().map(|(_, binder)| *binder).chain(Some(*body)).collect(),
+            Node::Lambda{ body, .. } | Node::Select{ record: body, .. } => vec![*body],
+            Node::Seq(children) | Node::List(children) => self.ids(*children).to_vec(),
+            Node::Funs{ defs, body } => defs.iter().map(|(_, _, body)| *body).chain(Some(*body)).collect(),
+            Node::Annot{ expr, .. } | Node::Raise(expr) => vec![*expr],
+            Node::Construct{ argument, .. } => argument.iter().copied().collect(),
+            Node::Handle{ expr, rules } => {
+                Some(*expr).into_iter().chain(rules.iter().map(|(_, body)| *body)).collect()
+            },
+            Node::Record(fields) => fields.iter().map(|(_, id)| *id).collect(),
+        }
+    }
+    // `optimize::fold_constants` of every tree in the arena, in place. a
+    // node comes after its children, so going through the nodes in order
+    // sees each with its children folded already. what folding drops is
+    // left in the arena where nothing points to it
+    pub fn fold_constants(&mut self) {
+        for i in 0..self.nodes.len() {
+            if let Some(node) = self.folded(ExprId(i as u32)) {
+                self.nodes[i] = node
+            }
+        }
+    }
+    // what `id` folds to when it is not itself already
+    fn folded(&mut self, id: ExprId) -> Option<Node<'a>> {
+        let literal = |id: ExprId| match self[id] {
+            Node::Lit(lit) => Some(lit),
+            _ => None,
+        };
+        match self[id] {
+            Node::Unary{ operation: operation @ UnaryOp::Fst, child }
+                | Node::Unary{ operation: operation @ UnaryOp::Snd, child } => match self[child] {
+                Node::Tuple{ fst, snd } if literal(fst).is_some() && literal(snd).is_some() => {
+                    Some(self[if operation == UnaryOp::Fst { fst } else { snd }].clone())
+                },
+                _ => None,
+            },
+            Node::Unary{ operation, child } => fold_unary_literal(operation, literal(child)?).map(Node::Lit),
+            Node::Binary{ left, operation, right } => {
+                fold_binary_literals(literal(left), operation, literal(right)).map(Node::Lit)
+            },
+            Node::IfThenElse{ condition, if_branch, else_branch } => match literal(condition) {
+                Some(Literal::Boolean(true)) => Some(self[if_branch].clone()),
+                Some(Literal::Boolean(false)) => Some(self[else_branch].clone()),
+                _ => None,
+            },
+            // a loop that never runs
+            Node::While{ condition, .. } if literal(condition) == Some(Literal::Boolean(false)) => {
+                Some(Node::Lit(Literal::Unit))
+            },
+            Node::Seq(sequence) => {
+                // a unit literal before the end of a sequence does nothing
+                let last = (sequence.len as usize).saturating_sub(1);
+                let kept: Vec<ExprId> = self.ids(sequence).iter().enumerate()
+                    .filter(|(i, id)| *i == last || literal(**id) != Some(Literal::Unit))
+                    .map(|(_, id)| *id)
+                    .collect();
+                match kept.len() {
+                    1 => Some(self[kept[0]].clone()),
+                    len if len == sequence.len as usize => None,
+                    _ => Some(Node::Seq(self.children_of(&kept))),
+                }
+            },
+            Node::Cons{ head, tail } => match self[tail] {
+                Node::List(elements) => {
+                    let ids: Vec<ExprId> = Some(head).into_iter().chain(self.ids(elements).iter().copied()).collect();
+                    Some(Node::List(self.children_of(&ids)))
+                },
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+    // the slot of every variable under `root` by its id, `None` for the
+    // variables nothing binds and for every node that is no variable
+    pub fn resolve(&self, root: ExprId) -> Vec<Option<Slot>> {
+        let mut resolver = Resolver { arena: self, names: vec![], bindings: vec![], slots: vec![], free: None };
+        resolver.slots.resize(self.len(), None);
+        resolver.walk(root);
+        resolver.slots
+    }
+    // `Expr::free_variables` of the tree under `root`
+    pub fn free_variables(&self, root: ExprId) -> BTreeSet<&'a str> {
+        let mut resolver = Resolver {
+            arena: self, names: vec![], bindings: vec![], slots: vec![], free: Some(BTreeSet::new())
+        };
+        resolver.walk(root);
+        resolver.free.unwrap()
+    }
+}
+
+struct Resolver<'r, 'a> {
+    arena: &'r ExprArena<'a>,
+    // the names in scope, innermost last, and where each binding starts
+    names: Vec<&'a str>,
+    bindings: Vec<usize>,
+    // empty when only the free variables are asked for
+    slots: Vec<Option<Slot>>,
+    free: Option<BTreeSet<&'a str>>,
+}
+
+impl<'r, 'a> Resolver<'r, 'a> {
+    fn bind(&mut self, names: impl IntoIterator<Item = &'a str>) {
+        self.bindings.push(self.names.len());
+        self.names.extend(names);
+    }
+    fn unbind(&mut self) {
+        let start = self.bindings.pop().unwrap();
+        self.names.truncate(start);
+    }
+    fn lookup(&self, name: &str) -> Option<Slot> {
+        let at = self.names.iter().rposition(|bound| *bound == name)?;
+        let binding = self.bindings.iter().rposition(|start| *start <= at).unwrap();
+        let depth = (self.bindings.len() - 1 - binding) as u32;
+        Some(Slot { depth, index: (at - self.bindings[binding]) as u32 })
+    }
+    fn walk(&mut self, id: ExprId) {
+        let arena = self.arena;
+        match &arena[id] {
+            Node::Var(name) => match (self.lookup(name), &mut self.free) {
+                (None, Some(free)) => {
+                    free.insert(name);
+                },
+                (slot, None) => self.slots[id.0 as usize] = slot,
+                (Some(_), Some(_)) => {},
+            },
+            // one binding each, like nested lets
+            Node::Let{ bindings, body } => {
+                for (name, binder) in bindings {
+                    self.walk(*binder);
+                    self.bind(Some(*name));
+                }
+                self.walk(*body);
+                for _ in bindings {
+                    self.unbind();
+                }
+            },
+            Node::Lambda{ name, body } => {
+                self.bind(Some(*name));
+                self.walk(*body);
+                self.unbind();
+            },
+            Node::Funs{ defs, body } => {
+                self.bind(defs.iter().map(|(name, _, _)| *name));
+                for (_, argument, def) in defs {
+                    self.bind(Some(*argument));
+                    self.walk(*def);
+                    self.unbind();
+                }
+                self.walk(*body);
+                self.unbind();
+            },
+            Node::Handle{ expr, rules } => {
+                self.walk(*expr);
+                for (pattern, body) in rules {
+                    self.bind(pattern.names());
+                    self.walk(*body);
+                    self.unbind();
+                }
+            },
+            // what `children` gives without a vector for each node
+            Node::Lit(_) | Node::Error(_) | Node::Construct{ argument: None, .. } => {},
+            Node::Unary{ child, .. } | Node::Annot{ expr: child, .. } | Node::Raise(child)
+                | Node::Select{ record: child, .. } | Node::Construct{ argument: Some(child), .. } => {
+                self.walk(*child)
+            },
+            Node::Binary{ left, right, .. } | Node::App{ left, right } | Node::Cons{ head: left, tail: right }
+                | Node::Tuple{ fst: left, snd: right } | Node::While{ condition: left, body: right } => {
+                self.walk(*left);
+                self.walk(*right);
+            },
+            Node::IfThenElse{ condition, if_branch, else_branch } => {
+                self.walk(*condition);
+                self.walk(*if_branch);
+                self.walk(*else_branch);
+            },
+            Node::Seq(children) | Node::List(children) => {
+                for child in arena.ids(*children) {
+                    self.walk(*child)
+                }
+            },
+            Node::Record(fields) => {
+                for (_, child) in fields {
+                    self.walk(*child)
+                }
+            },
+        }
+    }
+}
+
+impl<'a> Index<ExprId> for ExprArena<'a> {
+    type Output = Node<'a>;
+    fn index(&self, id: ExprId) -> &Node<'a> {
+        &self.nodes[id.0 as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::{parse};
+    use crate::optimize::{fold_constants};
+
+    #[test]
+    fn expr_arena_unit() {
+        let source = "let fun f n = if n < 2 then n else f (n - 1) in \
+            (f 10 handle Fail msg => 0, {x = [1, 2], y = #x r}) : int * int end";
+        let expr = parse(source).unwrap();
+        let mut arena = ExprArena::new();
+        let root = arena.alloc(&expr);
+        assert_eq!(arena.expr(root), expr);
+        assert_eq!(root, ExprId(arena.len() as u32 - 1));
+        // the same nodes in the same order as the boxed tree
+        let children: Vec<String> = arena.children(root).into_iter().map(|id| arena.expr(id).to_string()).collect();
+        let boxed: Vec<String> = expr.children().into_iter().map(Expr::to_string).collect();
+        assert_eq!(children, boxed);
+        assert!(arena.nodes().all(|(id, _)| arena.children(id).iter().all(|child| *child < id)));
+
+        // built by hand, `1 + x` applied to itself
+        let mut arena = ExprArena::new();
+        let (one, x) = (arena.lit(Literal::Integer(1)), arena.var("x"));
+        let sum = arena.binary(one, BinaryOp::Add, x);
+        let app = arena.app(sum, sum);
+        assert_eq!(arena.expr(app).to_string(), "(1 + x) (1 + x)");
+        assert_eq!(arena.len(), 4);
+
+        // folding in place agrees with folding the boxed tree
+        let sources = [
+            "1 + 2 * 3", "if 1 < 2 andalso true then x else y", "fst (1, true)", "(1 + 1) :: [2]", "((); 3 * 3)",
+            "fn x => x + (10 div 5)", "1 div 0", "while 1 > 2 do print 1", "[not true, chr 97, ~1.5]",
+        ];
+        for source in &sources {
+            let expr = parse(source).unwrap();
+            let mut arena = ExprArena::new();
+            let root = arena.alloc(&expr);
+            arena.fold_constants();
+            assert_eq!(arena.expr(root), fold_constants(expr), "{}", source);
+        }
+
+        let expr = parse("let val x = 1 in fn y => let fun f n = g (x, (y, n)) in f end end").unwrap();
+        let mut arena = ExprArena::new();
+        let root = arena.alloc(&expr);
+        let slots = arena.resolve(root);
+        let vars: Vec<(&str, Option<(u32, u32)>)> = arena.nodes()
+            .filter_map(|(id, node)| match node {
+                Node::Var(name) => Some((*name, slots[id.0 as usize].map(|slot| (slot.depth, slot.index)))),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(vars, vec![
+            ("g", None), ("x", Some((3, 0))), ("y", Some((2, 0))), ("n", Some((0, 0))), ("f", Some((0, 0))),
+        ]);
+        assert_eq!(arena.free_variables(root), expr.free_variables());
+    }
+}
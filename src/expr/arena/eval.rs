@@ -0,0 +1,295 @@
+use std::fmt;
+use std::rc::{Rc};
+
+use crate::lexer::{Literal};
+use crate::expr::{UnaryOp, BinaryOp};
+use crate::expr::arena::{ExprArena, ExprId, Node, Slot};
+use crate::expr::eval::{CHR, chr};
+use crate::locale::{message};
+use crate::runtime::real::{Real};
+use crate::runtime::width::{Width};
+
+// what evaluating an arena comes to, printed the way `eval::Value` is
+#[derive(Debug, Clone)]
+pub enum Flat<'a> {
+    Unit,
+    Integer(i64),
+    Boolean(bool),
+    String(&'a str),
+    Char(char),
+    Real(Real),
+    Tuple(Rc<(Flat<'a>, Flat<'a>)>),
+    List(Rc<[Flat<'a>]>),
+    Function(Rc<Function<'a>>),
+}
+
+// a `fn`, or the definition at `index` of a `fun`, with the bindings around
+// it
+#[derive(Debug)]
+pub struct Function<'a> {
+    node: ExprId,
+    index: usize,
+    env: Env<'a>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum FlatError<'a> {
+    NotFound(&'a str),
+    // what `eval` runs and this does not, like `print` or a `handle`
+    Unsupported(String),
+    TypeError(String),
+    // the exception nothing handled
+    Raised(&'static str),
+}
+
+impl<'a> fmt::Display for Flat<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Flat::Unit => write!(f, "()"),
+            Flat::Integer(i) => write!(f, "{}", i),
+            Flat::Boolean(b) => write!(f, "{}", b),
+            Flat::String(s) => write!(f, "{}", s),
+            Flat::Char(c) => write!(f, "{}", Literal::Char(*c)),
+            Flat::Real(x) => write!(f, "{}", x),
+            Flat::Tuple(pair) => write!(f, "({}, {})", pair.0, pair.1),
+            Flat::List(elements) => {
+                let elements: Vec<String> = elements.iter().map(Flat::to_string).collect();
+                write!(f, "[{}]", elements.join(", "))
+            },
+            Flat::Function(_) => write!(f, "fn"),
+        }
+    }
+}
+
+impl<'a> fmt::Display for FlatError<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            FlatError::NotFound(name) => message("not-found", &[name]),
+            FlatError::Unsupported(source) => message("arena-unsupported", &[source]),
+            FlatError::TypeError(source) => message("arena-type-error", &[source]),
+            FlatError::Raised(exception) => message("uncaught", &[exception]),
+        };
+        write!(f, "{}", text)
+    }
+}
+
+// the bindings in scope, innermost first, shared by the closures that
+// captured them
+type Env<'a> = Option<Rc<Binding<'a>>>;
+
+#[derive(Debug)]
+struct Binding<'a> {
+    values: Values<'a>,
+    next: Env<'a>,
+}
+
+#[derive(Debug)]
+enum Values<'a> {
+    One(Flat<'a>),
+    // the definitions of the `fun` at the node, made into functions as
+    // they are looked up so none has to hold the binding it is in
+    Functions(ExprId),
+}
+
+impl<'a> ExprArena<'a> {
+    // evaluates the tree under `root` with every variable resolved to its
+    // slot first, for the integers, booleans, tuples, lists and functions
+    // `eval_columns` and the vm also cover. there is no `EvalConfig`, so no
+    // limits either
+    pub fn eval(&self, root: ExprId) -> Result<Flat<'a>, FlatError<'a>> {
+        let slots = self.resolve(root);
+        Eval { arena: self, slots: &slots }.eval(root, &None)
+    }
+}
+
+struct Eval<'r, 'a> {
+    arena: &'r ExprArena<'a>,
+    slots: &'r [Option<Slot>],
+}
+
+impl<'r, 'a> Eval<'r, 'a> {
+    fn type_error(&self, id: ExprId) -> FlatError<'a> {
+        FlatError::TypeError(self.arena.expr(id).to_string())
+    }
+    fn boolean(&self, id: ExprId, env: &Env<'a>) -> Result<bool, FlatError<'a>> {
+        match self.eval(id, env)? {
+            Flat::Boolean(b) => Ok(b),
+            _ => Err(self.type_error(id)),
+        }
+    }
+    fn lookup(&self, slot: Slot, env: &Env<'a>) -> Flat<'a> {
+        let mut binding = env.as_ref().unwrap();
+        for _ in 0..slot.depth {
+            binding = binding.next.as_ref().unwrap();
+        }
+        match &binding.values {
+            Values::One(value) => value.clone(),
+            Values::Functions(node) => {
+                let env = Some(binding.clone());
+                Flat::Function(Rc::new(Function { node: *node, index: slot.index as usize, env }))
+            },
+        }
+    }
+    fn apply(&self, function: &Function<'a>, argument: Flat<'a>) -> Result<Flat<'a>, FlatError<'a>> {
+        let body = match &self.arena[function.node] {
+            Node::Lambda{ body, .. } => *body,
+            Node::Funs{ defs, .. } => defs[function.index].2,
+            _ => unreachable!(),
+        };
+        let env = Some(Rc::new(Binding { values: Values::One(argument), next: function.env.clone() }));
+        self.eval(body, &env)
+    }
+    fn eval(&self, id: ExprId, env: &Env<'a>) -> Result<Flat<'a>, FlatError<'a>> {
+        let bind = |values| Some(Rc::new(Binding { values, next: env.clone() }));
+        match &self.arena[id] {
+            Node::Var(name) => match self.slots[id.0 as usize] {
+                Some(slot) => Ok(self.lookup(slot, env)),
+                None => Err(FlatError::NotFound(name)),
+            },
+            Node::Lit(lit) => Ok(match *lit {
+                Literal::Unit => Flat::Unit,
+                Literal::Integer(i) => Flat::Integer(i),
+                Literal::Boolean(b) => Flat::Boolean(b),
+                Literal::String(s) => Flat::String(s),
+                Literal::Char(c) => Flat::Char(c),
+                Literal::Real(x) => Flat::Real(x),
+            }),
+            Node::Unary{ operation, child } => {
+                let value = self.eval(*child, env)?;
+                match (operation, value) {
+                    (UnaryOp::Not, Flat::Boolean(b)) => Ok(Flat::Boolean(!b)),
+                    (UnaryOp::Fst, Flat::Tuple(pair)) => Ok(pair.0.clone()),
+                    (UnaryOp::Snd, Flat::Tuple(pair)) => Ok(pair.1.clone()),
+                    (UnaryOp::Neg, Flat::Integer(i)) => {
+                        Width::default().negate(i).map(Flat::Integer).map_err(FlatError::Raised)
+                    },
+                    (UnaryOp::Neg, Flat::Real(x)) => Ok(Flat::Real(x.negate())),
+                    (UnaryOp::Ord, Flat::Char(c)) => Ok(Flat::Integer(c as i64)),
+                    (UnaryOp::Chr, Flat::Integer(i)) => chr(i).map(Flat::Char).ok_or(FlatError::Raised(CHR)),
+                    (UnaryOp::Print, _) => Err(FlatError::Unsupported(self.arena.expr(id).to_string())),
+                    _ => Err(self.type_error(*child)),
+                }
+            },
+            Node::Binary{ left, operation: BinaryOp::OrElse, right } => {
+                Ok(Flat::Boolean(self.boolean(*left, env)? || self.boolean(*right, env)?))
+            },
+            Node::Binary{ left, operation: BinaryOp::AndAlso, right } => {
+                Ok(Flat::Boolean(self.boolean(*left, env)? && self.boolean(*right, env)?))
+            },
+            Node::Binary{ left, operation, right } => {
+                match (self.eval(*left, env)?, self.eval(*right, env)?) {
+                    (Flat::Integer(l), Flat::Integer(r)) if *operation != BinaryOp::Divide => {
+                        match Width::default().arithmetic(*operation, l, r) {
+                            Some(res) => res.map(Flat::Integer).map_err(FlatError::Raised),
+                            None => Ok(Flat::Boolean(operation.compare(l, r).unwrap())),
+                        }
+                    },
+                    (Flat::Char(l), Flat::Char(r)) if operation.compare(l, r).is_some() => {
+                        Ok(Flat::Boolean(operation.compare(l, r).unwrap()))
+                    },
+                    (Flat::Real(l), Flat::Real(r)) => match l.arithmetic(*operation, r) {
+                        Some(res) => res.map(Flat::Real).map_err(FlatError::Raised),
+                        None => operation.compare(l, r).map(Flat::Boolean).ok_or_else(|| self.type_error(id)),
+                    },
+                    _ => Err(self.type_error(id)),
+                }
+            },
+            Node::IfThenElse{ condition, if_branch, else_branch } => {
+                if self.boolean(*condition, env)? {
+                    self.eval(*if_branch, env)
+                } else {
+                    self.eval(*else_branch, env)
+                }
+            },
+            Node::Tuple{ fst, snd } => {
+                let fst = self.eval(*fst, env)?;
+                Ok(Flat::Tuple(Rc::new((fst, self.eval(*snd, env)?))))
+            },
+            // a binding each, every binder under the ones before it
+            Node::Let{ bindings, body } => {
+                let mut inner = env.clone();
+                for (_, binder) in bindings {
+                    let value = self.eval(*binder, &inner)?;
+                    inner = Some(Rc::new(Binding { values: Values::One(value), next: inner }));
+                }
+                self.eval(*body, &inner)
+            },
+            Node::Lambda{ .. } => Ok(Flat::Function(Rc::new(Function { node: id, index: 0, env: env.clone() }))),
+            Node::App{ left, right } => match self.eval(*left, env)? {
+                Flat::Function(function) => {
+                    let argument = self.eval(*right, env)?;
+                    self.apply(&function, argument)
+                },
+                _ => Err(self.type_error(*left)),
+            },
+            Node::Seq(sequence) => {
+                let (last, front) = self.arena.ids(*sequence).split_last().unwrap();
+                for id in front {
+                    if let Flat::Unit = self.eval(*id, env)? {} else {
+                        return Err(self.type_error(*id))
+                    }
+                }
+                self.eval(*last, env)
+            },
+            Node::List(elements) => {
+                let values = self.arena.ids(*elements).iter()
+                    .map(|id| self.eval(*id, env))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Flat::List(values.into()))
+            },
+            Node::Cons{ head, tail } => {
+                let head = self.eval(*head, env)?;
+                match self.eval(*tail, env)? {
+                    Flat::List(elements) => {
+                        Ok(Flat::List(Some(head).into_iter().chain(elements.iter().cloned()).collect()))
+                    },
+                    _ => Err(self.type_error(*tail)),
+                }
+            },
+            Node::Funs{ body, .. } => self.eval(*body, &bind(Values::Functions(id))),
+            // annotations are only checked statically
+            Node::Annot{ expr, .. } => self.eval(*expr, env),
+            Node::Construct{ .. } | Node::Raise(_) | Node::Handle{ .. } | Node::While{ .. } | Node::Record(_)
+                | Node::Select{ .. } | Node::Error(_) => Err(FlatError::Unsupported(self.arena.expr(id).to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::{parse};
+
+    #[test]
+    fn arena_eval_unit() {
+        // the same as the tree walker, value or error
+        let sources = [
+            "let val x = 3 in if x < 4 andalso x <> 0 then (x + 1) * 2 else x end",
+            "let fun even n = if n = 0 then true else odd (n - 1) and odd n = if n = 0 then false else even (n - 1) \
+             in (even 10, odd 7) end",
+            "let val add = fn x => fn y => x + y in let val x = 10 in add 1 2 :: [x, ~x] end end",
+            "let fun fib n = if n < 2 then n else fib (n - 1) + fib (n - 2) in fib 15 end",
+            "(fst (#\"a\", 1.5), (snd (1, 2.0 / 4.0), ord #\"b\" > 97))",
+            "((); 1 div 0)",
+            "y + 1",
+            "chr 1000000000",
+        ];
+        for source in &sources {
+            let expr = parse(source).unwrap();
+            let mut arena = ExprArena::new();
+            let root = arena.alloc(&expr);
+            let flat = arena.eval(root).map(|value| value.to_string()).map_err(|err| err.to_string());
+            let boxed = expr.eval().map(|value| value.to_string()).map_err(|err| err.to_string());
+            assert_eq!(flat, boxed, "{}", source);
+        }
+
+        let eval = |source| {
+            let mut arena = ExprArena::new();
+            let root = arena.alloc(&parse(source).unwrap());
+            arena.eval(root).map(|value| value.to_string())
+        };
+        assert_eq!(eval("1 + true"), Err(FlatError::TypeError("1 + true".to_string())));
+        assert_eq!(eval("raise Div"), Err(FlatError::Unsupported("raise Div".to_string())));
+        assert_eq!(eval("fn x => x").unwrap(), "fn");
+    }
+}
@@ -0,0 +1,107 @@
+use std::fmt;
+
+use crate::expr::{Expr};
+use crate::expr::eval::{Env, EvalConfig, Error, Output, Value};
+use crate::expr::ids::{NodeId, NodeTable};
+use crate::expr::trace::{quote};
+use crate::locale::{message};
+
+// what a program can do to the world outside of it
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Hash)]
+pub enum EffectKind {
+    Print,
+}
+
+impl EffectKind {
+    fn name(self) -> &'static str {
+        match self {
+            EffectKind::Print => "print",
+        }
+    }
+}
+
+// one effect a program had, in the order they happened, for a service to
+// review what a script it ran did
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Effect {
+    // the pre-order id of the node that had it, the span of which
+    // `NodeTable::span` has
+    pub node: NodeId,
+    pub kind: EffectKind,
+    // printed
+    pub arguments: Vec<String>,
+    // false when it was attempted and failed, like a print to a closed
+    // output
+    pub done: bool,
+}
+
+impl fmt::Display for Effect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let arguments = self.arguments.join(", ");
+        let key = if self.done { "audit-effect" } else { "audit-effect-failed" };
+        write!(f, "{}", message(key, &[&self.kind.name(), &arguments]))
+    }
+}
+
+impl<'a> Expr<'a> {
+    // evaluates the expression and records every effect it has, printing
+    // to `output` or stdout
+    pub fn audit(self, config: EvalConfig, output: Option<Output>) -> (Result<Value<'a>, Error<'a>>, Vec<Effect>) {
+        let mut env = Env::audited(config, output);
+        let res = self.eval_ctx(&mut env);
+        (res, env.effects())
+    }
+}
+
+// the effects as json for other tools, in the shape of `Trace::json`
+pub fn json(effects: &[Effect], table: Option<&NodeTable>) -> String {
+    let mut json = String::from("{\"effects\": [\n");
+    for (i, effect) in effects.iter().enumerate() {
+        let arguments: Vec<String> = effect.arguments.iter().map(|argument| quote(argument)).collect();
+        json.push_str(&format!("  {{\"effect\": \"{}\", \"node\": {}", effect.kind.name(), effect.node.0));
+        if let Some(span) = table.and_then(|table| table.span(effect.node)) {
+            json.push_str(&format!(", \"span\": [{}, {}]", span.start, span.end));
+        }
+        json.push_str(&format!(", \"arguments\": [{}], \"done\": {}}}", arguments.join(", "), effect.done));
+        json.push_str(if i + 1 < effects.len() { ",\n" } else { "\n" });
+    }
+    json.push_str("]}\n");
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+    use std::cell::RefCell;
+    use crate::expr::{parse_indexed};
+
+    #[test]
+    fn audit_unit() {
+        let source = "let fun greet n = print n in (greet 1; print (2, 3); 4) end";
+        let (expr, table) = parse_indexed(source).unwrap();
+        let printed = Rc::new(RefCell::new(vec![]));
+        let (res, effects) = expr.audit(EvalConfig::default(), Some(printed.clone()));
+        assert_eq!(res.unwrap().to_string(), "4");
+        assert_eq!(String::from_utf8_lossy(&printed.borrow()), "1\n(2, 3)\n");
+        let spans: Vec<&str> = effects.iter()
+            .map(|effect| table.span(effect.node).map_or("", |span| &source[span.start..span.end]))
+            .collect();
+        assert_eq!(spans, vec!["print n", "print (2, 3)"]);
+        assert_eq!(effects[1].arguments, vec!["(2, 3)"]);
+        assert_eq!(effects[0].to_string(), "print 1");
+        assert_eq!(json(&effects, Some(&table)), concat!(
+            "{\"effects\": [\n",
+            "  {\"effect\": \"print\", \"node\": 1, \"span\": [18, 25], \"arguments\": [\"1\"], \"done\": true},\n",
+            "  {\"effect\": \"print\", \"node\": 7, \"span\": [39, 51], ",
+            "\"arguments\": [\"(2, 3)\"], \"done\": true}\n",
+            "]}\n",
+        ));
+
+        // an evaluation that is not audited keeps no log
+        let (expr, _) = parse_indexed("print 1").unwrap();
+        let mut env = Env::with_output(EvalConfig::default(), Rc::new(RefCell::new(vec![])));
+        expr.eval_ctx(&mut env).unwrap();
+        assert!(env.effects().is_empty());
+    }
+}
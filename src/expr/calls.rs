@@ -0,0 +1,205 @@
+use crate::lexer::{self, Span, Token};
+use crate::expr::{Expr};
+use crate::expr::ids::{NodeId, NodeTable};
+use crate::expr::visit::{ExprVisitor, walk_expr};
+
+// a function the program names, with `fun` or `let val f = fn ...`
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Function<'a> {
+    pub name: &'a str,
+    // the name where it is defined
+    pub span: Span,
+    // the node of its body, a `fun`'s or the `fn`'s
+    pub body: NodeId,
+}
+
+// `callee` applied in the body of `caller`, or outside of every function
+// when there is none
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct Call {
+    pub caller: Option<usize>,
+    pub callee: usize,
+    // the name of the callee at the call
+    pub span: Span,
+}
+
+// who calls whom in a whole program, functions are indices into
+// `functions`. only a named function applied by that name is a call, one
+// passed around is called through a parameter nothing can see through
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct CallGraph<'a> {
+    pub functions: Vec<Function<'a>>,
+    pub calls: Vec<Call>,
+}
+
+impl<'a> CallGraph<'a> {
+    pub fn new(source: &'a str, expr: &Expr<'a>, table: &NodeTable) -> CallGraph<'a> {
+        let mut graph = Graph {
+            tokens: lexer::spanned(source),
+            table,
+            next: 0,
+            scope: vec![],
+            caller: None,
+            graph: CallGraph::default(),
+        };
+        expr.accept(&mut graph);
+        graph.graph
+    }
+    // the functions called `name`, in the order they are defined
+    pub fn find(&self, name: &str) -> Vec<usize> {
+        (0..self.functions.len()).filter(|&i| self.functions[i].name == name).collect()
+    }
+    // the calls of `function`, in source order
+    pub fn callers_of(&self, function: usize) -> Vec<&Call> {
+        self.calls.iter().filter(|call| call.callee == function).collect()
+    }
+    // the calls `function` makes itself, not the ones of the functions
+    // defined inside it, in source order
+    pub fn callees_of(&self, function: usize) -> Vec<&Call> {
+        self.calls.iter().filter(|call| call.caller == Some(function)).collect()
+    }
+}
+
+struct Graph<'t, 'a> {
+    tokens: Vec<(Span, Token<'a>)>,
+    table: &'t NodeTable,
+    // the pre-order id of the next node visited
+    next: u32,
+    // what each name in scope is, shadowing by anything else hides a function
+    scope: Vec<(&'a str, Option<usize>)>,
+    caller: Option<usize>,
+    graph: CallGraph<'a>,
+}
+
+impl<'t, 'a> Graph<'t, 'a> {
+    // a new function whose body is the next node visited. names have no
+    // spans of their own, it is the last one before the body
+    fn define(&mut self, name: &'a str) -> usize {
+        let body = NodeId(self.next);
+        let start = self.table.span(body).map_or(0, |span| span.start);
+        let span = self.tokens.iter().rev()
+            .find(|(span, token)| span.end <= start && *token == Token::Name(name))
+            .map_or(Span::new(start, start), |(span, _)| *span);
+        self.graph.functions.push(Function { name, span, body });
+        self.graph.functions.len() - 1
+    }
+    // visits the body of `function`
+    fn enter(&mut self, function: usize, body: &Expr<'a>) {
+        let caller = self.caller.replace(function);
+        self.visit_expr(body);
+        self.caller = caller;
+    }
+    fn resolve(&self, name: &str) -> Option<usize> {
+        self.scope.iter().rev().find(|(bound, _)| *bound == name).and_then(|(_, function)| *function)
+    }
+}
+
+impl<'t, 'a> ExprVisitor<'a> for Graph<'t, 'a> {
+    fn visit_expr(&mut self, expr: &Expr<'a>) {
+        use Expr::*;
+        let id = NodeId(self.next);
+        self.next += 1;
+        match expr {
+            // `f a b` is `(f a) b`, only the inner application has the name
+            App{ left, .. } => {
+                let callee = match **left {
+                    Var(name) => self.resolve(name),
+                    _ => None,
+                };
+                if let (Some(callee), Some(span)) = (callee, self.table.span(NodeId(id.0 + 1))) {
+                    self.graph.calls.push(Call { caller: self.caller, callee, span })
+                }
+                walk_expr(self, expr)
+            },
+            Let{ bindings, body } => {
+                for (name, binder) in bindings {
+                    let lambda = match binder {
+                        Annot{ expr, .. } => matches!(**expr, Lambda{ .. }),
+                        binder => matches!(binder, Lambda{ .. }),
+                    };
+                    let function = if lambda {
+                        let function = self.define(name);
+                        self.enter(function, binder);
+                        Some(function)
+                    } else {
+                        self.visit_expr(binder);
+                        None
+                    };
+                    self.scope.push((name, function));
+                }
+                self.visit_expr(body);
+                self.scope.truncate(self.scope.len() - bindings.len());
+            },
+            Lambda{ name, body } => {
+                self.scope.push((name, None));
+                self.visit_expr(body);
+                self.scope.pop();
+            },
+            Funs{ defs, body } => {
+                let mut functions = vec![];
+                let mut next = self.next;
+                for def in defs {
+                    let saved = std::mem::replace(&mut self.next, next);
+                    functions.push(self.define(def.name));
+                    self.next = saved;
+                    next += def.body.size();
+                }
+                let names = defs.iter().map(|def| def.name);
+                self.scope.extend(names.zip(functions.iter().map(|&function| Some(function))));
+                for (def, function) in defs.iter().zip(functions) {
+                    self.scope.push((def.argument, None));
+                    self.enter(function, &def.body);
+                    self.scope.pop();
+                }
+                self.visit_expr(body);
+                self.scope.truncate(self.scope.len() - defs.len());
+            },
+            Handle{ expr, rules } => {
+                self.visit_expr(expr);
+                for rule in rules {
+                    let names = rule.pattern.names();
+                    let count = names.len();
+                    self.scope.extend(names.into_iter().map(|name| (name, None)));
+                    self.visit_expr(&rule.body);
+                    self.scope.truncate(self.scope.len() - count);
+                }
+            },
+            _ => walk_expr(self, expr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::{parse_indexed};
+
+    #[test]
+    fn call_graph_unit() {
+        let source = "let fun even n = if n = 0 then true else odd (n - 1) \
+                      and odd n = if n = 0 then false else even (n - 1) in \
+                      let val check = fn n => (even n, print (odd n)) in \
+                      let val even = 3 in check even end end end";
+        let (expr, table) = parse_indexed(source).unwrap();
+        let graph = CallGraph::new(source, &expr, &table);
+        let names: Vec<&str> = graph.functions.iter().map(|function| function.name).collect();
+        assert_eq!(names, vec!["even", "odd", "check"]);
+        let even = graph.find("even")[0];
+        assert_eq!(&source[graph.functions[even].span.start..graph.functions[even].span.end], "even");
+
+        let callers = |function: usize| -> Vec<Option<&str>> {
+            graph.callers_of(function).into_iter()
+                .map(|call| call.caller.map(|caller| graph.functions[caller].name))
+                .collect()
+        };
+        assert_eq!(callers(even), vec![Some("odd"), Some("check")]);
+        assert_eq!(callers(graph.find("odd")[0]), vec![Some("even"), Some("check")]);
+        // the last `even` is a number, `check` is called from the top
+        assert_eq!(callers(graph.find("check")[0]), vec![None]);
+
+        let callees: Vec<&str> = graph.callees_of(graph.find("check")[0]).into_iter()
+            .map(|call| &source[call.span.start..call.span.end])
+            .collect();
+        assert_eq!(callees, vec!["even", "odd"]);
+    }
+}
@@ -0,0 +1,247 @@
+use std::fmt;
+use std::borrow::Cow;
+
+use crate::lexer::{Literal};
+use crate::expr::{UnaryOp, BinaryOp, Expr};
+use crate::expr::eval::{OVERFLOW};
+use crate::locale::{message};
+use crate::runtime::width::{Width};
+
+// an input of `eval_columns`, its value in every row
+#[derive(Debug, Copy, Clone)]
+pub enum Column<'c> {
+    Int(&'c [i64]),
+    Bool(&'c [bool]),
+}
+
+// what `eval_columns` gives back, the value of every row
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Values {
+    Int(Vec<i64>),
+    Bool(Vec<bool>),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ColumnError<'a> {
+    NotFound(&'a str),
+    // an input with a different number of rows than the first one
+    Length(&'a str),
+    // what is neither an integer nor a boolean or works on something else,
+    // like a function, a tuple or a string
+    Unsupported(String),
+    TypeError(String),
+    // the first row whose evaluation raised, and what
+    Raised {
+        row: usize,
+        exception: &'static str,
+    },
+}
+
+impl<'a> fmt::Display for ColumnError<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            ColumnError::NotFound(name) => message("not-found", &[name]),
+            ColumnError::Length(name) => message("columnar-length", &[name]),
+            ColumnError::Unsupported(source) => message("columnar-unsupported", &[source]),
+            ColumnError::TypeError(source) => message("columnar-type-error", &[source]),
+            ColumnError::Raised{ row, exception } => message("columnar-raised", &[exception, row]),
+        };
+        write!(f, "{}", text)
+    }
+}
+
+// a column being worked on, the inputs are only borrowed
+#[derive(Clone)]
+enum Col<'c> {
+    Int(Cow<'c, [i64]>),
+    Bool(Cow<'c, [bool]>),
+}
+
+struct Columns<'c, 'a> {
+    rows: usize,
+    width: Width,
+    scope: Vec<(&'a str, Col<'c>)>,
+}
+
+// applies `op` to the rows of `left` and `right` that are active, the
+// others are left at 0 or false
+fn zip<T, R, F>(left: &[T], right: &[T], active: &[bool], op: F) -> Result<Vec<R>, ColumnError<'static>>
+where T: Copy, R: Copy + Default, F: Fn(T, T) -> Result<R, &'static str>
+{
+    let mut out = vec![R::default(); active.len()];
+    for row in 0..active.len() {
+        if active[row] {
+            out[row] = op(left[row], right[row]).map_err(|exception| ColumnError::Raised{ row, exception })?;
+        }
+    }
+    Ok(out)
+}
+
+fn and(active: &[bool], condition: &[bool], wanted: bool) -> Vec<bool> {
+    active.iter().zip(condition).map(|(active, condition)| *active && *condition == wanted).collect()
+}
+
+// `if_val` in the rows where `condition` holds and `else_val` in the others
+fn pick<T: Copy>(condition: &[bool], if_val: &[T], else_val: &[T]) -> Vec<T> {
+    condition.iter().zip(if_val.iter().zip(else_val)).map(|(c, (a, b))| if *c { *a } else { *b }).collect()
+}
+
+impl<'c, 'a> Columns<'c, 'a> {
+    // evaluates `expr` for the rows that are `active`, what it is in the
+    // others does not matter and nothing they would raise counts. that is
+    // what lets both branches of an `if` run over the whole column
+    fn eval(&mut self, expr: &Expr<'a>, active: &[bool]) -> Result<Col<'c>, ColumnError<'a>> {
+        use Expr::*;
+        let type_error = || ColumnError::TypeError(expr.to_source());
+        match expr {
+            Lit(Literal::Integer(i)) => match active.iter().position(|active| *active) {
+                Some(row) if !self.width.contains(*i) => Err(ColumnError::Raised{ row, exception: OVERFLOW }),
+                _ => Ok(Col::Int(Cow::Owned(vec![*i; self.rows]))),
+            },
+            Lit(Literal::Boolean(b)) => Ok(Col::Bool(Cow::Owned(vec![*b; self.rows]))),
+            Var(name) => match self.scope.iter().rev().find(|(bound, _)| bound == name) {
+                Some((_, col)) => Ok(col.clone()),
+                None => Err(ColumnError::NotFound(name)),
+            },
+            Unary{ operation: UnaryOp::Not, child } => match self.eval(child, active)? {
+                Col::Bool(b) => Ok(Col::Bool(Cow::Owned(b.iter().map(|b| !b).collect()))),
+                Col::Int(_) => Err(type_error()),
+            },
+            Unary{ operation: UnaryOp::Neg, child } => match self.eval(child, active)? {
+                Col::Int(i) => {
+                    let width = self.width;
+                    Ok(Col::Int(Cow::Owned(zip(&i, &i, active, |i, _| width.negate(i))?)))
+                },
+                Col::Bool(_) => Err(type_error()),
+            },
+            Binary{ left, operation: operation @ (BinaryOp::AndAlso | BinaryOp::OrElse), right } => {
+                let left = match self.eval(left, active)? {
+                    Col::Bool(left) => left,
+                    Col::Int(_) => return Err(type_error()),
+                };
+                // the right side only runs where the left one does not decide
+                let orelse = *operation == BinaryOp::OrElse;
+                let right = match self.eval(right, &and(active, &left, !orelse))? {
+                    Col::Bool(right) => right,
+                    Col::Int(_) => return Err(type_error()),
+                };
+                let res = left.iter().zip(right.iter()).map(|(l, r)| if *l == orelse { *l } else { *r }).collect();
+                Ok(Col::Bool(Cow::Owned(res)))
+            },
+            // like the evaluator, booleans are not compared
+            Binary{ left, operation, right } => match (self.eval(left, active)?, self.eval(right, active)?) {
+                (Col::Int(_), Col::Int(_)) if *operation == BinaryOp::Divide => Err(type_error()),
+                (Col::Int(l), Col::Int(r)) => {
+                    let (width, operation) = (self.width, *operation);
+                    if operation.compare(0, 0).is_some() {
+                        let res = zip(&l, &r, active, |l, r| Ok(operation.compare(l, r).unwrap_or(false)))?;
+                        Ok(Col::Bool(Cow::Owned(res)))
+                    } else {
+                        let res = zip(&l, &r, active, |l, r| width.arithmetic(operation, l, r).unwrap_or(Ok(0)))?;
+                        Ok(Col::Int(Cow::Owned(res)))
+                    }
+                },
+                _ => Err(type_error()),
+            },
+            IfThenElse{ condition, if_branch, else_branch } => {
+                let condition = match self.eval(condition, active)? {
+                    Col::Bool(condition) => condition,
+                    Col::Int(_) => return Err(type_error()),
+                };
+                let if_val = self.eval(if_branch, &and(active, &condition, true))?;
+                let else_val = self.eval(else_branch, &and(active, &condition, false))?;
+                match (if_val, else_val) {
+                    (Col::Int(a), Col::Int(b)) => Ok(Col::Int(Cow::Owned(pick(&condition, &a, &b)))),
+                    (Col::Bool(a), Col::Bool(b)) => Ok(Col::Bool(Cow::Owned(pick(&condition, &a, &b)))),
+                    _ => Err(type_error()),
+                }
+            },
+            Let{ bindings, body } => {
+                let depth = self.scope.len();
+                let res = bindings.iter()
+                    .try_for_each(|(name, binder)| {
+                        let binder = self.eval(binder, active)?;
+                        self.scope.push((name, binder));
+                        Ok(())
+                    })
+                    .and_then(|()| self.eval(body, active));
+                self.scope.truncate(depth);
+                res
+            },
+            // parentheses
+            Seq(sequence) if sequence.len() == 1 => self.eval(&sequence[0], active),
+            Annot{ expr, .. } => self.eval(expr, active),
+            _ => Err(ColumnError::Unsupported(expr.to_source())),
+        }
+    }
+}
+
+// evaluates `expr` once for every row of `inputs`, its free variables
+// bound to their values in the row, like `Expr::eval` would but a node at a
+// time over the whole columns instead of a row at a time. only integers
+// and booleans fit in a column. when rows raise, the error is that of the
+// first of them
+pub fn eval_columns<'a>(expr: &Expr<'a>, inputs: &[(&'a str, Column)]) -> Result<Values, ColumnError<'a>> {
+    let len = |column: &Column| match column {
+        Column::Int(column) => column.len(),
+        Column::Bool(column) => column.len(),
+    };
+    // an expression with no inputs still has a value
+    let rows = inputs.first().map_or(1, |(_, column)| len(column));
+    if let Some((name, _)) = inputs.iter().find(|(_, column)| len(column) != rows) {
+        return Err(ColumnError::Length(name))
+    }
+    let scope = inputs.iter()
+        .map(|(name, column)| match column {
+            Column::Int(column) => (*name, Col::Int(Cow::Borrowed(*column))),
+            Column::Bool(column) => (*name, Col::Bool(Cow::Borrowed(*column))),
+        })
+        .collect();
+    let mut columns = Columns { rows, width: Width::default(), scope };
+    match columns.eval(expr, &vec![true; rows])? {
+        Col::Int(values) => Ok(Values::Int(values.into_owned())),
+        Col::Bool(values) => Ok(Values::Bool(values.into_owned())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::{parse};
+    use crate::expr::eval::{Env, EvalConfig, Value};
+
+    #[test]
+    fn eval_columns_unit() {
+        let price = [120, 80, 300, 45, 0];
+        let member = [true, false, false, true, true];
+        let inputs = [("price", Column::Int(&price)), ("member", Column::Bool(&member))];
+        let source = "let val discount = if member then price div 10 else 0 in price - discount >= 100 end";
+        let expr = parse(source).unwrap();
+        let res = eval_columns(&expr, &inputs);
+        assert_eq!(res, Ok(Values::Bool(vec![true, false, true, false, false])));
+        // the same as a row at a time
+        for row in 0..price.len() {
+            let mut env = Env::with_config(EvalConfig::default());
+            env.define("price", Value::Integer(price[row]));
+            env.define("member", Value::Boolean(member[row]));
+            let value = expr.clone().eval_ctx(&mut env).unwrap().to_string();
+            assert_eq!(value, matches!(&res, Ok(Values::Bool(res)) if res[row]).to_string());
+        }
+
+        // only rows that get to a division by zero raise
+        let guarded = parse("if price > 0 andalso 1000 div price < 10 then 1 else price").unwrap();
+        assert_eq!(eval_columns(&guarded, &inputs), Ok(Values::Int(vec![1, 80, 1, 45, 0])));
+        let unguarded = parse("(1000 div price, member)").unwrap();
+        let unsupported = ColumnError::Unsupported("(1000 div price, member)".into());
+        assert_eq!(eval_columns(&unguarded, &inputs), Err(unsupported));
+        assert_eq!(
+            eval_columns(&parse("1000 div price").unwrap(), &inputs),
+            Err(ColumnError::Raised{ row: 4, exception: "Div" }),
+        );
+        let mistyped = parse("price + member").unwrap();
+        assert_eq!(eval_columns(&mistyped, &inputs), Err(ColumnError::TypeError("price + member".into())));
+        assert_eq!(eval_columns(&parse("price + tax").unwrap(), &inputs), Err(ColumnError::NotFound("tax")));
+        let short = [("price", Column::Int(&price)), ("member", Column::Bool(&member[..2]))];
+        assert_eq!(eval_columns(&expr, &short), Err(ColumnError::Length("member")));
+    }
+}
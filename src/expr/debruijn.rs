@@ -0,0 +1,235 @@
+use crate::lexer::{Literal, Span};
+use crate::expr::{UnaryOp, BinaryOp, Expr, Pattern, TypeExpr};
+
+// `Pattern` with its variables left nameless, they are bound left to right
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub enum CorePattern<'a> {
+    Wildcard,
+    Bind,
+    Construct {
+        name: &'a str,
+        argument: Option<Box<CorePattern<'a>>>,
+    },
+    Record(Vec<(&'a str, CorePattern<'a>)>),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct CoreRule<'a> {
+    pub pattern: CorePattern<'a>,
+    pub body: Box<CoreExpr<'a>>,
+}
+
+// `Expr` with no names for what it binds. a variable is how many binders
+// out the one it refers to is, 0 for the innermost, so trees that only
+// differ in the names of their binders are equal and nothing can capture.
+// only free variables keep their names. labels and constructors are not
+// variables and stay as they are
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub enum CoreExpr<'a> {
+    BoundVar(usize),
+    FreeVar(&'a str),
+    Lit(Literal<'a>),
+    Unary {
+        operation: UnaryOp,
+        child: Box<CoreExpr<'a>>,
+    },
+    Binary {
+        left: Box<CoreExpr<'a>>,
+        operation: BinaryOp,
+        right: Box<CoreExpr<'a>>,
+    },
+    IfThenElse {
+        condition: Box<CoreExpr<'a>>,
+        if_branch: Box<CoreExpr<'a>>,
+        else_branch: Box<CoreExpr<'a>>,
+    },
+    Tuple {
+        fst: Box<CoreExpr<'a>>,
+        snd: Box<CoreExpr<'a>>,
+    },
+    // each of `binders` is under one more binder than the one before it,
+    // and `body` under one more than the last
+    Let {
+        binders: Vec<CoreExpr<'a>>,
+        body: Box<CoreExpr<'a>>,
+    },
+    Lambda(Box<CoreExpr<'a>>),
+    App {
+        left: Box<CoreExpr<'a>>,
+        right: Box<CoreExpr<'a>>,
+    },
+    Seq(Vec<CoreExpr<'a>>),
+    List(Vec<CoreExpr<'a>>),
+    Cons {
+        head: Box<CoreExpr<'a>>,
+        tail: Box<CoreExpr<'a>>,
+    },
+    // the functions of a `fun ... and ...` are bound in order, in `body` and
+    // in each of `defs`, where its argument comes after them all
+    Funs {
+        defs: Vec<CoreExpr<'a>>,
+        body: Box<CoreExpr<'a>>,
+    },
+    Annot {
+        expr: Box<CoreExpr<'a>>,
+        ty: TypeExpr,
+    },
+    Construct {
+        name: &'a str,
+        argument: Option<Box<CoreExpr<'a>>>,
+    },
+    Raise(Box<CoreExpr<'a>>),
+    Handle {
+        expr: Box<CoreExpr<'a>>,
+        rules: Vec<CoreRule<'a>>,
+    },
+    While {
+        condition: Box<CoreExpr<'a>>,
+        body: Box<CoreExpr<'a>>,
+    },
+    Record(Vec<(&'a str, CoreExpr<'a>)>),
+    Select {
+        label: &'a str,
+        record: Box<CoreExpr<'a>>,
+    },
+    Error(Span),
+}
+
+impl<'a> Pattern<'a> {
+    fn to_core(&self) -> CorePattern<'a> {
+        match self {
+            Pattern::Wildcard => CorePattern::Wildcard,
+            Pattern::Var(_) => CorePattern::Bind,
+            Pattern::Construct{ name, argument } => CorePattern::Construct {
+                name,
+                argument: argument.as_ref().map(|argument| Box::new(argument.to_core())),
+            },
+            Pattern::Record(fields) => {
+                CorePattern::Record(fields.iter().map(|(label, pattern)| (*label, pattern.to_core())).collect())
+            },
+        }
+    }
+}
+
+// the binders in scope, innermost last
+type Scope<'a> = Vec<&'a str>;
+
+fn convert<'a>(expr: &Expr<'a>, scope: &mut Scope<'a>) -> CoreExpr<'a> {
+    use CoreExpr as Core;
+    let go = |expr: &Expr<'a>, scope: &mut Scope<'a>| Box::new(convert(expr, scope));
+    match expr {
+        Expr::Var(name) => match scope.iter().rev().position(|bound| bound == name) {
+            Some(index) => Core::BoundVar(index),
+            None => Core::FreeVar(name),
+        },
+        Expr::Lit(lit) => Core::Lit(*lit),
+        Expr::Unary{ operation, child } => Core::Unary{ operation: *operation, child: go(child, scope) },
+        Expr::Binary{ left, operation, right } => Core::Binary {
+            left: go(left, scope),
+            operation: *operation,
+            right: go(right, scope),
+        },
+        Expr::IfThenElse{ condition, if_branch, else_branch } => Core::IfThenElse {
+            condition: go(condition, scope),
+            if_branch: go(if_branch, scope),
+            else_branch: go(else_branch, scope),
+        },
+        Expr::Tuple{ fst, snd } => Core::Tuple{ fst: go(fst, scope), snd: go(snd, scope) },
+        Expr::Let{ bindings, body } => {
+            let binders = bindings.iter()
+                .map(|(name, binder)| {
+                    let binder = convert(binder, scope);
+                    scope.push(name);
+                    binder
+                })
+                .collect();
+            let body = go(body, scope);
+            scope.truncate(scope.len() - bindings.len());
+            Core::Let{ binders, body }
+        },
+        Expr::Lambda{ name, body } => {
+            scope.push(name);
+            let body = go(body, scope);
+            scope.pop();
+            Core::Lambda(body)
+        },
+        Expr::App{ left, right } => Core::App{ left: go(left, scope), right: go(right, scope) },
+        Expr::Seq(sequence) => Core::Seq(sequence.iter().map(|expr| convert(expr, scope)).collect()),
+        Expr::List(elements) => Core::List(elements.iter().map(|expr| convert(expr, scope)).collect()),
+        Expr::Cons{ head, tail } => Core::Cons{ head: go(head, scope), tail: go(tail, scope) },
+        Expr::Funs{ defs, body } => {
+            scope.extend(defs.iter().map(|def| def.name));
+            let defs: Vec<CoreExpr<'a>> = defs.iter().map(|def| {
+                scope.push(def.argument);
+                let body = convert(&def.body, scope);
+                scope.pop();
+                body
+            }).collect();
+            let body = go(body, scope);
+            scope.truncate(scope.len() - defs.len());
+            Core::Funs{ defs, body }
+        },
+        Expr::Annot{ expr, ty } => Core::Annot{ expr: go(expr, scope), ty: ty.clone() },
+        Expr::Construct{ name, argument } => Core::Construct {
+            name,
+            argument: argument.as_ref().map(|argument| go(argument, scope)),
+        },
+        Expr::Raise(expr) => Core::Raise(go(expr, scope)),
+        Expr::Handle{ expr, rules } => {
+            let expr = go(expr, scope);
+            let rules = rules.iter().map(|rule| {
+                let names = rule.pattern.names();
+                let count = names.len();
+                scope.extend(names);
+                let body = Box::new(convert(&rule.body, scope));
+                scope.truncate(scope.len() - count);
+                CoreRule { pattern: rule.pattern.to_core(), body }
+            }).collect();
+            Core::Handle{ expr, rules }
+        },
+        Expr::While{ condition, body } => Core::While{ condition: go(condition, scope), body: go(body, scope) },
+        Expr::Record(fields) => {
+            Core::Record(fields.iter().map(|(label, expr)| (*label, convert(expr, scope))).collect())
+        },
+        Expr::Select{ label, record } => Core::Select{ label, record: go(record, scope) },
+        Expr::Error(span) => Core::Error(*span),
+    }
+}
+
+// the nameless form of `expr`, see `CoreExpr`
+pub fn to_debruijn<'a>(expr: &Expr<'a>) -> CoreExpr<'a> {
+    convert(expr, &mut vec![])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::{parse};
+
+    #[test]
+    fn debruijn_unit() {
+        let core = |source: &'static str| to_debruijn(&parse(source).unwrap());
+        let app = |left, right| CoreExpr::App{ left: Box::new(left), right: Box::new(right) };
+        let lambda = |body| CoreExpr::Lambda(Box::new(body));
+        assert_eq!(
+            core("fn x => fn y => x y z"),
+            lambda(lambda(app(app(CoreExpr::BoundVar(1), CoreExpr::BoundVar(0)), CoreExpr::FreeVar("z")))),
+        );
+
+        // the names of binders do not matter, which binder a name finds does
+        assert_eq!(core("let val a = 1 in fn b => a + b end"), core("let val x = 1 in fn y => x + y end"));
+        assert_ne!(core("fn a => fn b => a"), core("fn a => fn b => b"));
+        assert_eq!(
+            core("let fun even n = odd n and odd m = even m in even 2 end"),
+            core("let fun e x = o x and o y = e y in e 2 end"),
+        );
+        assert_ne!(
+            core("let fun even n = odd n and odd m = even m in even 2 end"),
+            core("let fun even n = even n and odd m = odd m in even 2 end"),
+        );
+        assert_eq!(
+            core("raise E handle Pair {a, b = x} => a + x | _ => y"),
+            core("raise E handle Pair {a = p, b = q} => p + q | _ => y"),
+        );
+    }
+}
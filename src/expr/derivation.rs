@@ -0,0 +1,236 @@
+use crate::expr::{Expr};
+use crate::expr::eval::{Error, Value};
+use crate::expr::ids::{NodeId};
+use crate::expr::pretty::{label};
+use crate::expr::trace::{Event};
+use crate::locale::{message};
+use crate::render::{Rendering, rendering};
+
+// why a node evaluated to what it did in big step terms: `expr ⇓ value`
+// holds by `rule` once every premise does. premises are in the order the
+// evaluator went through them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Derivation {
+    pub rule: String,
+    pub node: NodeId,
+    // the node as source, free of whatever the environment held
+    pub expr: String,
+    // the value printed, or the error it failed with
+    pub value: Result<String, String>,
+    pub premises: Vec<Derivation>,
+}
+
+impl<'a> Expr<'a> {
+    // evaluates the expression and gives the derivation of its value, built
+    // from what `trace` records so it agrees with the evaluator
+    pub fn derive(&self) -> (Result<Value<'a>, Error<'a>>, Derivation) {
+        let (res, trace) = self.clone().trace();
+        // the derivations entered and not left yet, the root at the bottom
+        let mut open: Vec<Derivation> = vec![];
+        let mut done = None;
+        for event in trace.events {
+            match event {
+                Event::Enter(node) => {
+                    let expr = self.node(node).map_or_else(String::new, |expr| expr.to_source());
+                    let value = Ok(String::new());
+                    open.push(Derivation { rule: String::new(), node, expr, value, premises: vec![] })
+                },
+                Event::Exit{ value, .. } => {
+                    let mut derivation = open.pop().unwrap();
+                    derivation.value = value;
+                    derivation.rule = self.node(derivation.node)
+                        .map_or_else(String::new, |expr| rule(expr, &derivation));
+                    match open.last_mut() {
+                        Some(parent) => parent.premises.push(derivation),
+                        None => done = Some(derivation),
+                    }
+                },
+                Event::Bind{ .. } | Event::Unbind(_) => {},
+            }
+        }
+        (res, done.unwrap())
+    }
+}
+
+// the name of the rule that concludes `derivation` of `expr`
+fn rule(expr: &Expr, derivation: &Derivation) -> String {
+    use Expr::*;
+    let name = match expr {
+        Var(_) => "var".to_string(),
+        Lit(_) => "lit".to_string(),
+        Error(_) => "error".to_string(),
+        Unary{ .. } | Binary{ .. } => label(expr),
+        IfThenElse{ .. } => match derivation.premises.first().map(|premise| &premise.value) {
+            Some(Ok(value)) if value == "true" => "if-true".to_string(),
+            Some(Ok(value)) if value == "false" => "if-false".to_string(),
+            _ => "if".to_string(),
+        },
+        While{ .. } => "while".to_string(),
+        Tuple{ .. } => "tuple".to_string(),
+        Let{ .. } => "let".to_string(),
+        Lambda{ .. } => "fn".to_string(),
+        App{ .. } => "app".to_string(),
+        Seq(_) => "seq".to_string(),
+        List(_) => "list".to_string(),
+        Cons{ .. } => "cons".to_string(),
+        Funs{ .. } => "fun".to_string(),
+        Annot{ .. } => "annot".to_string(),
+        Construct{ .. } => "construct".to_string(),
+        Raise(_) => "raise".to_string(),
+        Handle{ .. } => "handle".to_string(),
+        Record(_) => "record".to_string(),
+        Select{ .. } => "select".to_string(),
+    };
+    // the rules that pass a failure up are told apart by a mark
+    match (&derivation.value, expr) {
+        (Err(_), Raise(_)) | (Ok(_), _) => name,
+        (Err(_), _) => format!("{}↑", name),
+    }
+}
+
+impl Derivation {
+    // `expr ⇓ value`, or `expr ⇑ error` when it failed
+    pub fn conclusion(&self) -> String {
+        match &self.value {
+            Ok(value) => format!("{} ⇓ {}", self.expr, value),
+            Err(err) => format!("{} ⇑ {}", self.expr, err),
+        }
+    }
+    // the derivation drawn the way `pretty` draws a tree, the conclusion
+    // over its premises
+    pub fn pretty(&self) -> String {
+        if rendering() == Rendering::Linear {
+            return self.pretty_linear()
+        }
+        fn draw(derivation: &Derivation, lines: &mut Vec<String>) {
+            lines.push(format!("{}  [{}]", derivation.conclusion(), derivation.rule));
+            for (i, premise) in derivation.premises.iter().enumerate() {
+                let last = i + 1 == derivation.premises.len();
+                lines.push("│  ".to_string());
+                let top = lines.len();
+                draw(premise, lines);
+                lines[top].insert_str(0, if last { "└──" } else { "├──" });
+                for line in &mut lines[top + 1..] {
+                    line.insert_str(0, if last { "   " } else { "│  " });
+                }
+            }
+        }
+        let mut lines = vec![];
+        draw(self, &mut lines);
+        lines.join("\n")
+    }
+    pub fn pretty_linear(&self) -> String {
+        fn walk(derivation: &Derivation, parent: Option<(usize, usize)>, lines: &mut Vec<String>) {
+            let node = lines.len() + 1;
+            let label = format!("{} [{}]", derivation.conclusion(), derivation.rule);
+            lines.push(match parent {
+                None => message("tree-root", &[&node, &label]),
+                Some((parent, nth)) => message("tree-child", &[&node, &label, &nth, &parent]),
+            });
+            for (i, premise) in derivation.premises.iter().enumerate() {
+                walk(premise, Some((node, i + 1)), lines)
+            }
+        }
+        let mut lines = vec![];
+        walk(self, None, &mut lines);
+        lines.join("\n")
+    }
+    // the derivation as a `mathpar` of nested `\inferrule*`, for a document
+    // that uses the mathpartir package
+    pub fn latex(&self) -> String {
+        fn text(text: &str) -> String {
+            let mut escaped = String::new();
+            for c in text.chars() {
+                match c {
+                    '\\' => escaped.push_str("\\textbackslash{}"),
+                    '{' | '}' | '$' | '&' | '#' | '_' | '%' => {
+                        escaped.push('\\');
+                        escaped.push(c)
+                    },
+                    '^' => escaped.push_str("\\^{}"),
+                    '~' => escaped.push_str("\\~{}"),
+                    c => escaped.push(c),
+                }
+            }
+            format!("\\texttt{{{}}}", escaped)
+        }
+        fn walk(derivation: &Derivation, indent: usize, latex: &mut String) {
+            let pad = "  ".repeat(indent);
+            latex.push_str(&format!("{}\\inferrule*[right={}]\n{}{{", pad, text(&derivation.rule), pad));
+            for (i, premise) in derivation.premises.iter().enumerate() {
+                latex.push_str(if i == 0 { "\n" } else { " \\\\\n" });
+                walk(premise, indent + 1, latex);
+            }
+            if !derivation.premises.is_empty() {
+                latex.push_str(&format!("\n{}", pad));
+            }
+            let conclusion = match &derivation.value {
+                Ok(value) => format!("{} \\Downarrow {}", text(&derivation.expr), text(value)),
+                Err(err) => format!("{} \\Uparrow {}", text(&derivation.expr), text(err)),
+            };
+            latex.push_str(&format!("}}\n{}{{{}}}", pad, conclusion));
+        }
+        let mut latex = String::from("\\begin{mathpar}\n");
+        walk(self, 1, &mut latex);
+        latex.push_str("\n\\end{mathpar}\n");
+        latex
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::expr::{parse};
+
+    #[test]
+    fn derivation_unit() {
+        let (res, derivation) = parse("if 1 < 2 then 3 else 4").unwrap().derive();
+        assert_eq!(res.unwrap().to_string(), "3");
+        assert_eq!(derivation.pretty(), [
+            "if 1 < 2 then 3 else 4 ⇓ 3  [if-true]",
+            "│  ",
+            "├──1 < 2 ⇓ true  [<]",
+            "│  │  ",
+            "│  ├──1 ⇓ 1  [lit]",
+            "│  │  ",
+            "│  └──2 ⇓ 2  [lit]",
+            "│  ",
+            "└──3 ⇓ 3  [lit]",
+        ].join("\n"));
+        assert_eq!(derivation.latex(), [
+            "\\begin{mathpar}",
+            "  \\inferrule*[right=\\texttt{if-true}]",
+            "  {",
+            "    \\inferrule*[right=\\texttt{<}]",
+            "    {",
+            "      \\inferrule*[right=\\texttt{lit}]",
+            "      {}",
+            "      {\\texttt{1} \\Downarrow \\texttt{1}} \\\\",
+            "      \\inferrule*[right=\\texttt{lit}]",
+            "      {}",
+            "      {\\texttt{2} \\Downarrow \\texttt{2}}",
+            "    }",
+            "    {\\texttt{1 < 2} \\Downarrow \\texttt{true}} \\\\",
+            "    \\inferrule*[right=\\texttt{lit}]",
+            "    {}",
+            "    {\\texttt{3} \\Downarrow \\texttt{3}}",
+            "  }",
+            "  {\\texttt{if 1 < 2 then 3 else 4} \\Downarrow \\texttt{3}}",
+            "\\end{mathpar}",
+            "",
+        ].join("\n"));
+
+        // a function body is derived under the application that called it,
+        // once per call
+        let (_, derivation) = parse("let fun f n = n * 2 in f (f 1) end").unwrap().derive();
+        assert_eq!(derivation.conclusion(), "let fun f n = n * 2 in f (f 1) end ⇓ 4");
+        let body = &derivation.premises[0];
+        assert_eq!((body.rule.as_str(), body.premises.len()), ("app", 3));
+        assert_eq!(body.premises[2].conclusion(), "n * 2 ⇓ 4");
+
+        let (_, derivation) = parse("1 + (raise Div)").unwrap().derive();
+        assert_eq!(derivation.rule, "+↑");
+        let raised = &derivation.premises[1];
+        assert_eq!((raised.rule.as_str(), raised.premises[0].rule.as_str()), ("seq↑", "raise"));
+        assert!(derivation.conclusion().starts_with("1 + (raise Div) ⇑ "));
+    }
+}
@@ -1,24 +1,92 @@
 use std::fmt;
-use std::collections::HashMap;
-use crate::lexer::{Literal};
-use crate::expr::{UnaryOp, BinaryOp, Definition, Expr};
+use std::convert::TryFrom;
+use std::iter::FromIterator;
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::vec;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeSet, HashMap};
+use std::time::{Duration, Instant};
+use crate::lexer::{Literal, Span};
+use crate::expr::{self, UnaryOp, BinaryOp, Definition, Expr, Pattern};
+use crate::expr::ids::{NodeId};
+use crate::expr::trace::{Event};
+use crate::expr::audit::{Effect, EffectKind};
+use crate::locale::{message};
+use crate::runtime::real::{Real};
+use crate::runtime::width::{Width};
+
+pub mod closures;
+
+use self::closures::{Compiled};
 
 #[derive(Debug, Clone)]
 pub struct Closure<'a> {
     formal: &'a str,
     body: Expr<'a>,
+    // where the body sits in the tree being traced
+    id: NodeId,
+    context: Env<'a>,
+}
+
+// the definitions of a `fun ... and ...` under what they read of the
+// environment they were made in, with the ids of their bodies like a
+// closure's. the group is put
+// back in that environment on each call rather than kept in it, so no value
+// holds itself
+#[derive(Debug)]
+pub struct Functions<'a> {
+    defs: Vec<(Definition<'a>, NodeId)>,
     context: Env<'a>,
 }
 
+impl<'a> Functions<'a> {
+    // `ids` are the ids of the definitions' bodies, if any
+    fn new(defs: Vec<Definition<'a>>, ids: &[NodeId], context: Env<'a>) -> Rc<Functions<'a>> {
+        let context = context.only(&Definition::free_variables(&defs));
+        let defs = defs.into_iter()
+            .enumerate()
+            .map(|(i, def)| (def, ids.get(i).cloned().unwrap_or(NodeId(0))))
+            .collect();
+        Rc::new(Functions { defs, context })
+    }
+}
+
+// the names of the group bound to its functions
+fn bound<'a>(functions: &Rc<Functions<'a>>) -> Vec<(&'a str, Value<'a>)> {
+    (0..functions.defs.len())
+        .map(|index| (functions.defs[index].0.name, Value::Function(functions.clone(), index)))
+        .collect()
+}
+
+// a call of the function `index` of the group, as a closure over where the
+// group was made with the group itself in scope
+fn unroll<'a>(functions: &Rc<Functions<'a>>, index: usize) -> Closure<'a> {
+    let (def, id) = &functions.defs[index];
+    let mut context = functions.context.clone();
+    context.context.extend(bound(functions));
+    Closure { formal: def.argument, body: (*def.body).clone(), id: *id, context }
+}
+
 #[derive(Debug, Clone)]
 pub enum Value<'a> {
     Unit,
     Integer(i64),
     Boolean(bool),
     String(&'a str),
+    Char(char),
+    Real(Real),
     Tuple{ fst: Box<Value<'a>>, snd: Box<Value<'a>> },
-    Abstraction(Closure<'a>),
-    Function(Definition<'a>),
+    List(Vec<Value<'a>>),
+    // boxed, a closure is far larger than any other value
+    Abstraction(Box<Closure<'a>>),
+    // one of a group of `fun ... and ...` definitions, by its index
+    Function(Rc<Functions<'a>>, usize),
+    // a lambda or a `fun` evaluated by closures, see `EvalStrategy`
+    Compiled(Compiled<'a>),
+    Data{ constructor: &'a str, argument: Option<Box<Value<'a>>> },
+    // sorted by label, however the fields were written
+    Record(Vec<(&'a str, Value<'a>)>),
 }
 
 impl<'a> fmt::Display for Value<'a> {
@@ -29,17 +97,36 @@ impl<'a> fmt::Display for Value<'a> {
             Integer(i) => write!(f, "{}", i),
             Boolean(b) => write!(f, "{}", b),
             String(s) => write!(f, "{}", s),
+            Char(c) => write!(f, "{}", Literal::Char(c)),
+            Real(x) => write!(f, "{}", x),
             Tuple{ ref fst, ref snd } => write!(f, "({}, {})", fst, snd),
-            Abstraction(Closure{ formal, ref body, ref context }) => {
+            List(ref elements) => {
+                write!(f, "[")?;
+                let mut iter = elements.iter();
+                if let Some(value) = iter.next() {
+                    write!(f, "{}", value)?;
+                    for value in iter {
+                        write!(f, ", {}", value)?;
+                    }
+                }
+                write!(f, "]")
+            },
+            Abstraction(ref closure) => {
+                let Closure{ formal, ref body, ref context, .. } = **closure;
                 if context.empty() {
                     write!(f, "fn {} => {}", formal, body)
                 } else {
                     write!(f, "fn {} => {} [{}]", formal, body, context)
                 }
             },
-            Function(Definition{ name, argument, ref body }) => {
-                write!(f, "{} {} = {}", name, argument, body)
-            }
+            Function(ref functions, index) => write!(f, "{}", functions.defs[index].0),
+            Compiled(ref function) => write!(f, "{}", function),
+            Data{ constructor, argument: None } => write!(f, "{}", constructor),
+            Data{ constructor, argument: Some(ref argument) } => match **argument {
+                Data{ argument: Some(_), .. } => write!(f, "{} ({})", constructor, argument),
+                _ => write!(f, "{} {}", constructor, argument),
+            },
+            Record(ref fields) => expr::fields(f, fields, |f, value| write!(f, "{}", value)),
         }
     }
 }
@@ -69,6 +156,14 @@ impl<'a> Value<'a> {
             _ => Err(TypeError{ expr: self, should: Type::Integer })
         }
     }
+    fn character(self) -> Result<char, Error<'a>> {
+        use Value::*;
+        use Error::*;
+        match self {
+            Char(c) => Ok(c),
+            _ => Err(TypeError{ expr: self, should: Type::Char })
+        }
+    }
     fn tuple(self) -> Result<(Value<'a>, Value<'a>), Error<'a>> {
         use Value::*;
         use Error::*;
@@ -77,6 +172,32 @@ impl<'a> Value<'a> {
             _ => Err(TypeError{ expr: self, should: Type::Tuple })
         }
     }
+    // calls a function value, under the environment it was made in
+    pub fn apply(self, argument: Value<'a>, env: &mut Env<'a>) -> Result<Value<'a>, Error<'a>> {
+        use Value::*;
+        use Error::*;
+        env.start();
+        match self {
+            Abstraction(closure) => {
+                let Closure{ formal, body, id, mut context } = under_caller(*closure, env);
+                context.extend(formal, argument, |env2| body.eval_at(id, env2))
+            },
+            Function(functions, index) => {
+                let Closure{ formal, body, id, mut context } = under_caller(unroll(&functions, index), env);
+                context.extend(formal, argument, |env2| body.eval_at(id, env2))
+            },
+            function @ Compiled(_) => closures::call(function, argument, env),
+            _ => Err(TypeError{ expr: self, should: Type::Function }),
+        }
+    }
+    fn list(self) -> Result<Vec<Value<'a>>, Error<'a>> {
+        use Value::*;
+        use Error::*;
+        match self {
+            List(elements) => Ok(elements),
+            _ => Err(TypeError{ expr: self, should: Type::List })
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash)]
@@ -84,14 +205,220 @@ pub enum Type {
     Unit,
     Boolean,
     Integer,
+    Char,
+    Real,
     Function,
     Tuple,
+    List,
+    Record,
+    // a record with this label
+    Field(String),
 }
 
 #[derive(Debug)]
 pub enum Error<'a> {
     NotFound(&'a str),
     TypeError{ expr: Value<'a>, should: Type },
+    // a `raise` no `handle` caught
+    Raised(Value<'a>),
+    // what error recovery left in place of the source in the span, see
+    // `Expr::Error`
+    Unparsed(Span),
+    // evaluation went past one of the limits of its `EvalConfig`
+    OutOfFuel(Limit),
+    // a builtin the `EvalConfig` does not allow was called
+    Denied(UnaryOp),
+}
+
+// the limit of an `EvalConfig` that stopped an evaluation
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Limit {
+    Steps(u64),
+    Depth(usize),
+    Timeout(Duration),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            Limit::Steps(max) => message("limit-steps", &[max]),
+            Limit::Depth(max) => message("limit-depth", &[max]),
+            Limit::Timeout(timeout) => message("limit-timeout", &[&format!("{:?}", timeout)]),
+        };
+        write!(f, "{}", text)
+    }
+}
+
+// the builtins an evaluation may call, see `EvalConfig`. one bit per
+// builtin so the config stays `Copy`
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct Builtins(u8);
+
+impl Builtins {
+    pub const ALL: Builtins = Builtins(!0);
+    pub const NONE: Builtins = Builtins(0);
+    pub fn allows(self, operation: UnaryOp) -> bool {
+        self.0 & 1 << operation as u8 != 0
+    }
+}
+
+impl Default for Builtins {
+    fn default() -> Builtins {
+        Builtins::ALL
+    }
+}
+
+impl FromIterator<UnaryOp> for Builtins {
+    fn from_iter<I: IntoIterator<Item = UnaryOp>>(operations: I) -> Builtins {
+        Builtins(operations.into_iter().fold(0, |bits, operation| bits | 1 << operation as u8))
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Type::*;
+        let key = match *self {
+            Unit => "type-unit",
+            Boolean => "type-boolean",
+            Integer => "type-integer",
+            Char => "type-char",
+            Real => "type-real",
+            Function => "type-function",
+            Tuple => "type-tuple",
+            List => "type-list",
+            Record => "type-record",
+            Field(ref label) => return write!(f, "{}", message("type-field", &[label])),
+        };
+        write!(f, "{}", message(key, &[]))
+    }
+}
+
+impl<'a> fmt::Display for Error<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::NotFound(name) => write!(f, "{}", message("not-found", &[name])),
+            Error::TypeError{ expr, should } => write!(f, "{}", message("type-error", &[should, expr])),
+            Error::Raised(value) => write!(f, "{}", message("uncaught", &[value])),
+            Error::Unparsed(_) => write!(f, "{}", message("unparsed", &[])),
+            Error::OutOfFuel(limit) => write!(f, "{}", limit),
+            Error::Denied(operation) => write!(f, "{}", message("denied", &[operation])),
+        }
+    }
+}
+
+impl<'a> Error<'a> {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::NotFound(_) => "E0101",
+            Error::TypeError{ .. } => "E0102",
+            Error::Raised(_) => "E0103",
+            Error::Unparsed(_) => "E0104",
+            Error::OutOfFuel(_) => "E0105",
+            Error::Denied(_) => "E0106",
+        }
+    }
+}
+
+// what dividing by zero raises
+pub const DIV: &str = "Div";
+// what an integer too large for the width raises, see `EvalConfig`
+pub const OVERFLOW: &str = "Overflow";
+// what `print` raises when the output will not take any more
+pub const IO: &str = "Io";
+// what `chr` of a number that is no character's raises
+pub const CHR: &str = "Chr";
+
+// an exception without an argument, the ones the evaluator raises itself
+fn raised<'a>(constructor: &'a str) -> Error<'a> {
+    Error::Raised(Value::Data{ constructor, argument: None })
+}
+
+// the character with code point `i`, if there is one
+pub fn chr(i: i64) -> Option<char> {
+    u32::try_from(i).ok().and_then(std::char::from_u32)
+}
+
+// an operator other than `orelse` and `andalso` applied to its operands,
+// characters compare like integers but nothing else works on them. reals
+// have `/` and the arithmetic and comparisons of integers, except for `div`
+// and `mod`, and never mix with integers
+fn binary<'a>(operation: BinaryOp, left: Value<'a>, right: Value<'a>, width: Width)
+    -> Result<Value<'a>, Error<'a>>
+{
+    match (&left, &right) {
+        (Value::Char(l), Value::Char(r)) => if let Some(b) = operation.compare(l, r) {
+            return Ok(Value::Boolean(b))
+        },
+        (Value::Real(l), Value::Real(r)) => {
+            if let Some(res) = l.arithmetic(operation, *r) {
+                return res.map(Value::Real).map_err(raised)
+            }
+            if let Some(b) = operation.compare(l, r) {
+                return Ok(Value::Boolean(b))
+            }
+        },
+        _ => {},
+    }
+    if operation == BinaryOp::Divide {
+        let expr = if let Value::Real(_) = left { right } else { left };
+        return Err(Error::TypeError{ expr, should: Type::Real })
+    }
+    let (left, right) = (left.integer()?, right.integer()?);
+    match width.arithmetic(operation, left, right) {
+        Some(res) => res.map(Value::Integer).map_err(raised),
+        None => Ok(Value::Boolean(operation.compare(left, right).unwrap())),
+    }
+}
+
+// whether evaluating `expr` and throwing away what comes of it, error or
+// value, can not be told from not evaluating it. it prints nothing and
+// stops, since it calls nothing and does not loop
+fn forcible(expr: &Expr) -> bool {
+    match expr {
+        Expr::Unary{ operation: UnaryOp::Print, .. } | Expr::App{ .. } | Expr::While{ .. } => false,
+        expr => expr.children().into_iter().all(forcible),
+    }
+}
+
+// what the branch taken came to, unless the other one ran out of what the
+// limits allow, which it would have whichever was taken
+fn taken<'a, T>(taken: Result<T, Error<'a>>, other: Result<T, Error<'a>>) -> Result<T, Error<'a>> {
+    match other {
+        Err(err @ Error::OutOfFuel(_)) => Err(err),
+        _ => taken,
+    }
+}
+
+// the field of a record value labeled `label`
+pub fn select<'a, T>(fields: &'a [(&str, T)], label: &str) -> Option<&'a T> {
+    fields.binary_search_by(|(field, _)| (*field).cmp(label)).ok().map(|i| &fields[i].1)
+}
+
+impl<'a> Pattern<'a> {
+    // the variables of the pattern bound to the parts of `value` they stand
+    // for, `None` when the value does not fit
+    pub fn bind(&self, value: &Value<'a>) -> Option<Vec<(&'a str, Value<'a>)>> {
+        match (self, value) {
+            (Pattern::Wildcard, _) => Some(vec![]),
+            (Pattern::Var(name), value) => Some(vec![(name, value.clone())]),
+            (Pattern::Construct{ name, argument }, Value::Data{ constructor, argument: value }) => {
+                match (argument, value) {
+                    _ if name != constructor => None,
+                    (None, None) => Some(vec![]),
+                    (Some(pattern), Some(value)) => pattern.bind(value),
+                    _ => None,
+                }
+            },
+            (Pattern::Record(patterns), Value::Record(fields)) if patterns.len() == fields.len() => {
+                let mut bindings = vec![];
+                for (label, pattern) in patterns {
+                    bindings.extend(pattern.bind(select(fields, label)?)?);
+                }
+                Some(bindings)
+            },
+            _ => None,
+        }
+    }
 }
 
 impl<'a> Literal<'a> {
@@ -102,13 +429,100 @@ impl<'a> Literal<'a> {
             Literal::Integer(i) => Integer(i),
             Literal::Boolean(b) => Boolean(b),
             Literal::String(s)  => String(s),
+            Literal::Char(c)    => Char(c),
+            Literal::Real(x)    => Real(x),
         }
     }
 }
 
+// how an evaluation is carried out
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Hash, Default)]
+pub enum EvalStrategy {
+    // matches on each node every time it is evaluated
+    #[default]
+    Tree,
+    // compiles the tree to rust closures first, see `closures.rs`, which
+    // pays off for any evaluation that runs long. a host evaluating a small
+    // tree many times compiles it once with `closures::compile` instead. an
+    // environment that traces or audits walks the tree whatever its config
+    Closures,
+}
+
+// how the tree walker evaluates, the default is what the crate was built
+// with
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub struct EvalConfig {
+    pub width: Width,
+    // how many nodes an evaluation may evaluate, then it fails with
+    // `Error::OutOfFuel`. none of the limits is there by default
+    pub max_steps: Option<u64>,
+    // how many evaluations may be under way inside one another, a call in
+    // tail position ends the one it is in
+    pub max_stack_depth: Option<usize>,
+    // how long an evaluation may run, checked every so many steps. the one
+    // thing an evaluation depends on besides the program and its inputs,
+    // whether it times out can change from run to run where `max_steps`
+    // can not
+    pub timeout: Option<Duration>,
+    // for a host evaluating a policy over secrets: both branches of an `if`
+    // and both sides of `andalso` and `orelse` are evaluated and the result
+    // picked after, so how long it takes does not tell which was taken.
+    // only for branches that have no effects and call nothing, evaluating
+    // those can not be seen any other way
+    pub constant_time: bool,
+    pub strategy: EvalStrategy,
+    // the builtins the program may call, calling any other fails with
+    // `Error::Denied` however the call was reached. all of them by default
+    pub builtins: Builtins,
+}
+
 #[derive(Debug, Clone)]
 pub struct Env<'a> {
-    context: HashMap<&'a str, Value<'a>>
+    context: HashMap<&'a str, Value<'a>>,
+    // shared with every closure made under this environment so calls run
+    // with the same config and land in the same trace
+    shared: Rc<Shared<'a>>,
+}
+
+// where `print` writes, an embedder keeps its own handle on the writer to
+// read back what the program printed
+pub type Output = Rc<RefCell<dyn Write>>;
+
+// what stays the same for a whole evaluation, behind one pointer to keep
+// closures small
+struct Shared<'a> {
+    config: EvalConfig,
+    trace: Option<RefCell<Vec<Event<'a>>>>,
+    // the effects the evaluation had, when it is audited
+    audit: Option<RefCell<Vec<Effect>>>,
+    // stdout when there is none
+    output: Option<Output>,
+    // how far the evaluation under way is, for the limits of the config
+    steps: Cell<u64>,
+    depth: Cell<usize>,
+    deadline: Cell<Option<Instant>>,
+}
+
+impl<'a> Shared<'a> {
+    fn new(config: EvalConfig, trace: Option<RefCell<Vec<Event<'a>>>>, output: Option<Output>) -> Shared<'a> {
+        Shared {
+            config, trace, audit: None, output,
+            steps: Cell::new(0), depth: Cell::new(0), deadline: Cell::new(None),
+        }
+    }
+}
+
+impl<'a> fmt::Debug for Shared<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Shared")
+            .field("config", &self.config)
+            .field("trace", &self.trace)
+            .field("audit", &self.audit)
+            .field("output", &self.output.as_ref().map(|_| "..."))
+            .field("steps", &self.steps)
+            .field("depth", &self.depth)
+            .finish()
+    }
 }
 
 impl<'a> fmt::Display for Env<'a> {
@@ -124,19 +538,118 @@ impl<'a> fmt::Display for Env<'a> {
     }
 }
 
+impl<'a> Default for Env<'a> {
+    fn default() -> Env<'a> {
+        Env::new()
+    }
+}
+
 impl<'a> Env<'a> {
-    fn new() -> Env<'a> {
-        Env { context: HashMap::new() }
+    pub fn new() -> Env<'a> {
+        Env::with_config(EvalConfig::default())
+    }
+    pub fn with_config(config: EvalConfig) -> Env<'a> {
+        Env { context: HashMap::new(), shared: Rc::new(Shared::new(config, None, None)) }
+    }
+    // an environment whose `print`s go to `output` instead of stdout
+    pub fn with_output(config: EvalConfig, output: Output) -> Env<'a> {
+        Env { context: HashMap::new(), shared: Rc::new(Shared::new(config, None, Some(output))) }
+    }
+    // an environment that records what evaluating under it does, see
+    // `Expr::trace`
+    pub fn traced() -> Env<'a> {
+        let shared = Shared::new(EvalConfig::default(), Some(RefCell::new(vec![])), None);
+        Env { context: HashMap::new(), shared: Rc::new(shared) }
+    }
+    // an environment that keeps a log of the effects evaluating under it
+    // has, see `Expr::audit`. it evaluates the way a traced one does, so
+    // the effects have node ids
+    pub fn audited(config: EvalConfig, output: Option<Output>) -> Env<'a> {
+        let mut shared = Shared::new(config, None, output);
+        shared.audit = Some(RefCell::new(vec![]));
+        Env { context: HashMap::new(), shared: Rc::new(shared) }
+    }
+    pub fn effects(&self) -> Vec<Effect> {
+        self.shared.audit.as_ref().map(|audit| audit.borrow().clone()).unwrap_or_default()
+    }
+    pub fn events(&self) -> Vec<Event<'a>> {
+        self.shared.trace.as_ref().map(|trace| trace.borrow().clone()).unwrap_or_default()
+    }
+    fn record<F: FnOnce() -> Event<'a>>(&self, event: F) {
+        if let Some(trace) = &self.shared.trace {
+            trace.borrow_mut().push(event());
+        }
+    }
+    // starts the limits over, unless an evaluation is already under way
+    fn start(&self) {
+        let shared = &*self.shared;
+        if shared.depth.get() == 0 {
+            shared.steps.set(0);
+            shared.deadline.set(shared.config.timeout.map(|timeout| Instant::now() + timeout));
+        }
+    }
+    // counts a step against the limits
+    // whether both of `branches` are to be evaluated whichever is taken
+    fn forcing(&self, branches: &[&Expr]) -> bool {
+        self.shared.config.constant_time && branches.iter().all(|branch| forcible(branch))
+    }
+    fn step(&self) -> Result<(), Error<'a>> {
+        let shared = &*self.shared;
+        let steps = shared.steps.get() + 1;
+        shared.steps.set(steps);
+        if let Some(max) = shared.config.max_steps.filter(|max| steps > *max) {
+            return Err(Error::OutOfFuel(Limit::Steps(max)))
+        }
+        // the clock is slow next to a step
+        if steps.is_multiple_of(1024) {
+            if let (Some(deadline), Some(timeout)) = (shared.deadline.get(), shared.config.timeout) {
+                if Instant::now() >= deadline {
+                    return Err(Error::OutOfFuel(Limit::Timeout(timeout)))
+                }
+            }
+        }
+        Ok(())
     }
     fn empty(&self) -> bool {
         self.context.is_empty()
     }
-    fn lookup(&self, name: &'a str) -> Option<&Value<'a>> {
+    // the bindings of `names`, under the same config and trace
+    fn only(&self, names: &BTreeSet<&'a str>) -> Env<'a> {
+        let context = names.iter()
+            .filter_map(|name| self.context.get_key_value(name))
+            .map(|(name, value)| (*name, value.clone()))
+            .collect();
+        Env { context, shared: self.shared.clone() }
+    }
+    fn print(&self, value: &Value<'a>) -> io::Result<()> {
+        match &self.shared.output {
+            Some(output) => writeln!(output.borrow_mut(), "{}", value),
+            None => writeln!(io::stdout(), "{}", value),
+        }
+    }
+    pub fn lookup(&self, name: &str) -> Option<&Value<'a>> {
         self.context.get(name)
     }
+    pub fn define(&mut self, name: &'a str, value: Value<'a>) {
+        // an insert keeps the old key, the name is the one given
+        self.context.remove(name);
+        self.context.insert(name, value);
+    }
+    pub fn remove(&mut self, name: &str) -> Option<Value<'a>> {
+        self.context.remove(name)
+    }
+    // defines a `fun ... and ...` group, which sees what is defined so far
+    pub fn define_functions(&mut self, defs: Vec<Definition<'a>>) {
+        // top level functions are not part of any traced tree
+        let functions = Functions::new(defs, &[], self.clone());
+        for (name, value) in bound(&functions) {
+            self.define(name, value)
+        }
+    }
     fn extend<A, F>(&mut self, name: &'a str, value: Value<'a>, cb: F) -> A
     where F: FnOnce(&mut Env<'a>) -> A
     {
+        self.record(|| Event::Bind{ name, value: value.to_string() });
         let old = self.context.insert(name, value);
         let res = cb(self);
         // variable shadowing
@@ -144,133 +657,369 @@ impl<'a> Env<'a> {
             Some(old_value) => self.context.insert(name, old_value),
             None => self.context.remove(name),
         };
+        self.record(|| Event::Unbind(name));
         res
     }
-    fn add_definitions<A, F>(&mut self, definitions: Vec<Definition<'a>>, cb: F) -> A
+    fn extend_all<A, F>(&mut self, mut bindings: Vec<(&'a str, Value<'a>)>, cb: F) -> A
     where F: FnOnce(&mut Env<'a>) -> A
     {
-        use Value::*;
-        let mut old_map = HashMap::new();
-        for def in definitions {
-            old_map.insert(def.name, self.context.insert(def.name, Function(def)));
+        match bindings.pop() {
+            Some((name, value)) => self.extend(name, value, |env2| env2.extend_all(bindings, cb)),
+            None => cb(self),
+        }
+    }
+    // `ids` are the ids of the definitions' bodies, if any
+    fn add_definitions<A, F>(&mut self, definitions: Vec<Definition<'a>>, ids: &[NodeId], cb: F) -> A
+    where F: FnOnce(&mut Env<'a>) -> A
+    {
+        let mut olds = vec![];
+        let functions = Functions::new(definitions, ids, self.clone());
+        for (name, value) in bound(&functions) {
+            self.record(|| Event::Bind{ name, value: value.to_string() });
+            olds.push((name, self.context.insert(name, value)));
         }
         let res = cb(self);
-        for (name, old) in old_map {
+        // backwards so a name defined twice gets its outer value back
+        for (name, old) in olds.into_iter().rev() {
             match old {
                 Some(old_value) => self.context.insert(name, old_value),
                 None => self.context.remove(name),
             };
+            self.record(|| Event::Unbind(name));
         }
         res
     }
 }
 
+// what is left of evaluating a node once all it does outside of a tail
+// position is done
+enum Tail<'a> {
+    Done(Value<'a>),
+    Eval(Expr<'a>),
+    // the bindings go in the environment the expression is evaluated in
+    Bind(Vec<(&'a str, Value<'a>)>, Expr<'a>),
+    // the body of the closure under its own environment
+    Call(Closure<'a>, Value<'a>),
+}
+
+// the binders left in `bindings` of a `let`, each bound for the rest, then
+// its body. `ids` are theirs, in the same order
+fn let_in<'a>(bindings: &mut vec::IntoIter<(&'a str, Expr<'a>)>, body: Expr<'a>, ids: &[NodeId], env1: &mut Env<'a>)
+    -> Result<Value<'a>, Error<'a>>
+{
+    let at = ids.first().cloned().unwrap_or(NodeId(0));
+    let rest = ids.get(1..).unwrap_or(&[]);
+    match bindings.next() {
+        Some((name, binder)) => {
+            let value = binder.eval_at(at, env1)?;
+            env1.extend(name, value, |env2| let_in(bindings, body, rest, env2))
+        },
+        None => body.eval_at(at, env1),
+    }
+}
+
+// `closure` as called from `env`, its body runs with the config, limits and
+// output of the caller whichever environment the closure was made in
+fn under_caller<'a>(mut closure: Closure<'a>, env: &Env<'a>) -> Closure<'a> {
+    closure.context.shared = Rc::clone(&env.shared);
+    closure
+}
+
+// puts back in `env` what `hidden` says was there
+fn restore<'a, I>(env: &mut Env<'a>, hidden: I)
+where I: IntoIterator<Item = (&'a str, Option<Value<'a>>)>
+{
+    for (name, old) in hidden {
+        match old {
+            Some(old_value) => env.context.insert(name, old_value),
+            None => env.context.remove(name),
+        };
+    }
+}
+
 impl<'a> Expr<'a> {
     pub fn eval_ctx(self, env1: &mut Env<'a>) -> Result<Value<'a>, Error<'a>> {
+        let shared = &env1.shared;
+        if shared.config.strategy == EvalStrategy::Closures && shared.trace.is_none() && shared.audit.is_none() {
+            return closures::compile(&self).run(env1)
+        }
+        env1.start();
+        self.eval_at(NodeId(0), env1)
+    }
+    // evaluates the node numbered `id` in the tree being traced, ids are only
+    // worked out when the environment records a trace
+    fn eval_at(self, id: NodeId, env1: &mut Env<'a>) -> Result<Value<'a>, Error<'a>> {
+        let depth = env1.shared.depth.get();
+        if let Some(max) = env1.shared.config.max_stack_depth.filter(|max| depth >= *max) {
+            return Err(self::Error::OutOfFuel(Limit::Depth(max)))
+        }
+        env1.shared.depth.set(depth + 1);
+        // kept apart so the untraced path costs as little stack as it can
+        let res = match (&env1.shared.trace, &env1.shared.audit) {
+            (None, None) => self.eval_loop(env1),
+            _ => self.eval_traced(id, env1),
+        };
+        env1.shared.depth.set(depth);
+        res
+    }
+    // the untraced path. a node in tail position, like a branch of an `if`,
+    // the body of a `let` or that of the function a call in tail position
+    // calls, is evaluated by going around the loop instead of recursing, so
+    // tail calls take no stack
+    fn eval_loop(self, env1: &mut Env<'a>) -> Result<Value<'a>, Error<'a>> {
+        let mut expr = self;
+        // what the bindings made along the way hid in `env1`, only the first
+        // one for each name
+        let mut hidden: Vec<(&'a str, Option<Value<'a>>)> = vec![];
+        // the environment of the closure called last, `env1` until one is
+        let mut context: Option<Env<'a>> = None;
+        let res = loop {
+            let called = context.is_some();
+            let env = match context.as_mut() {
+                Some(env) => env,
+                None => &mut *env1,
+            };
+            if let Err(err) = env.step() {
+                break Err(err)
+            }
+            match expr.eval_tail(env) {
+                Ok(Tail::Done(value)) => break Ok(value),
+                Ok(Tail::Eval(next)) => expr = next,
+                Ok(Tail::Bind(bindings, next)) => {
+                    for (name, value) in bindings {
+                        let old = env.context.insert(name, value);
+                        if !called && hidden.iter().all(|(hid, _)| *hid != name) {
+                            hidden.push((name, old));
+                        }
+                    }
+                    expr = next;
+                },
+                Ok(Tail::Call(closure, argument)) => {
+                    // nothing evaluated from here on sees `env1`
+                    restore(env1, hidden.drain(..));
+                    let Closure{ formal, body, context: mut inner, .. } = under_caller(closure, env1);
+                    inner.context.insert(formal, argument);
+                    context = Some(inner);
+                    expr = body;
+                },
+                Err(err) => break Err(err),
+            }
+        };
+        restore(env1, hidden);
+        res
+    }
+    // evaluates what of `self` is not in a tail position
+    fn eval_tail(self, env1: &mut Env<'a>) -> Result<Tail<'a>, Error<'a>> {
+        use Expr::*;
+        use Value::{Abstraction, Function, Compiled};
+        use self::Error::{TypeError, Raised};
+        match self {
+            IfThenElse{ condition, if_branch, else_branch } => {
+                let condition_val = condition.eval_at(NodeId(0), env1)?.boolean()?;
+                if env1.forcing(&[&if_branch, &else_branch]) {
+                    let if_val = if_branch.eval_at(NodeId(0), env1);
+                    let else_val = else_branch.eval_at(NodeId(0), env1);
+                    let res = if condition_val { taken(if_val, else_val) } else { taken(else_val, if_val) };
+                    return res.map(Tail::Done)
+                }
+                if condition_val {
+                    Ok(Tail::Eval(*if_branch))
+                } else {
+                    Ok(Tail::Eval(*else_branch))
+                }
+            },
+            // each binder sees the ones before it, the loop binds all of
+            // them for the body
+            Let{ bindings, body } => {
+                let mut values = Vec::with_capacity(bindings.len());
+                let mut hidden = vec![];
+                for (name, binder) in bindings {
+                    let value = match binder.eval_at(NodeId(0), env1) {
+                        Ok(value) => value,
+                        Err(err) => {
+                            restore(env1, hidden.into_iter().rev());
+                            return Err(err)
+                        },
+                    };
+                    hidden.push((name, env1.context.insert(name, value.clone())));
+                    values.push((name, value));
+                }
+                restore(env1, hidden.into_iter().rev());
+                Ok(Tail::Bind(values, *body))
+            },
+            App{ left, right } => match left.eval_at(NodeId(0), env1)? {
+                Abstraction(closure) => {
+                    let right_val = right.eval_at(NodeId(0), env1)?;
+                    Ok(Tail::Call(*closure, right_val))
+                },
+                Function(functions, index) => {
+                    let right_val = right.eval_at(NodeId(0), env1)?;
+                    Ok(Tail::Call(unroll(&functions, index), right_val))
+                },
+                function @ Compiled(_) => {
+                    let right_val = right.eval_at(NodeId(0), env1)?;
+                    function.apply(right_val, env1).map(Tail::Done)
+                },
+                val => Err(TypeError{ expr: val, should: Type::Function }),
+            },
+            Seq(mut sequence) => match sequence.pop() {
+                Some(last) => {
+                    for expr in sequence {
+                        expr.eval_at(NodeId(0), env1)?.unit()?;
+                    }
+                    Ok(Tail::Eval(last))
+                },
+                None => Seq(sequence).eval_node(&[], env1).map(Tail::Done),
+            },
+            Funs{ defs, body } => {
+                let functions = Functions::new(defs, &[], env1.clone());
+                Ok(Tail::Bind(bound(&functions), *body))
+            },
+            Annot{ expr, .. } => Ok(Tail::Eval(*expr)),
+            Handle{ expr, rules } => match expr.eval_at(NodeId(0), env1) {
+                Err(Raised(value)) => {
+                    for rule in rules {
+                        if let Some(bindings) = rule.pattern.bind(&value) {
+                            return Ok(Tail::Bind(bindings, *rule.body))
+                        }
+                    }
+                    Err(Raised(value))
+                },
+                res => res.map(Tail::Done),
+            },
+            expr => expr.eval_node(&[], env1).map(Tail::Done),
+        }
+    }
+    fn eval_traced(self, id: NodeId, env1: &mut Env<'a>) -> Result<Value<'a>, Error<'a>> {
+        let mut next = id.0 + 1;
+        let ids: Vec<NodeId> = self.children().into_iter()
+            .map(|child| {
+                let child_id = NodeId(next);
+                next += child.size();
+                child_id
+            })
+            .collect();
+        env1.step()?;
+        env1.record(|| Event::Enter(id));
+        let res = self.eval_node(&ids, env1);
+        env1.record(|| {
+            let value = match res {
+                Ok(ref value) => Ok(value.to_string()),
+                Err(ref err) => Err(err.to_string()),
+            };
+            Event::Exit{ node: id, value }
+        });
+        res
+    }
+    // `ids` holds the ids of the children, empty when not tracing
+    fn eval_node(self, ids: &[NodeId], env1: &mut Env<'a>) -> Result<Value<'a>, Error<'a>> {
+        let at = |i: usize| ids.get(i).cloned().unwrap_or(NodeId(0));
         use UnaryOp::*;
         use BinaryOp::*;
         use Expr::*;
         use Value::*;
-        use Error::*;
+        use self::Error::{NotFound, TypeError, Raised};
         match self {
             Var(name) => match env1.lookup(name) {
                 Some(value) => Ok(value.clone()),
                 None => Err(NotFound(name))
             },
+            // only a config narrower than the build's lets a literal through
+            // the lexer that does not fit
+            Lit(Literal::Integer(i)) if !env1.shared.config.width.contains(i) => Err(raised(OVERFLOW)),
             Lit(lit) => Ok(lit.into_value()),
-            Unary{ operation, child } => match operation {
-                Not => {
-                    let b = child.eval_ctx(env1)?.boolean()?;
-                    Ok(Boolean(!b))
-                },
-                Fst => {
-                    let tuple = child.eval_ctx(env1)?.tuple()?;
-                    Ok(tuple.0)
-                },
-                Snd => {
-                    let tuple = child.eval_ctx(env1)?.tuple()?;
-                    Ok(tuple.1)
-                },
-                Print => {
-                    let val = child.eval_ctx(env1)?;
-                    println!("{}", val);
-                    Ok(Unit)
-                },
+            Unary{ operation, child } => {
+                let val = child.eval_at(at(0), env1)?;
+                if !env1.shared.config.builtins.allows(operation) {
+                    return Err(self::Error::Denied(operation))
+                }
+                match operation {
+                    Not => Ok(Boolean(!val.boolean()?)),
+                    Fst => Ok(val.tuple()?.0),
+                    Snd => Ok(val.tuple()?.1),
+                    Print => {
+                        let res = env1.print(&val);
+                        if let Some(audit) = &env1.shared.audit {
+                            // a node's only child comes right after it
+                            let node = NodeId(at(0).0.saturating_sub(1));
+                            let effect = Effect {
+                                node, kind: EffectKind::Print, arguments: vec![val.to_string()], done: res.is_ok()
+                            };
+                            audit.borrow_mut().push(effect);
+                        }
+                        match res {
+                            Ok(()) => Ok(Unit),
+                            Err(_) => Err(raised(IO)),
+                        }
+                    },
+                    Neg => match val {
+                        Value::Real(x) => Ok(Value::Real(x.negate())),
+                        val => env1.shared.config.width.negate(val.integer()?).map(Integer).map_err(raised),
+                    },
+                    Ord => Ok(Integer(val.character()? as i64)),
+                    Chr => Ok(Value::Char(chr(val.integer()?).ok_or_else(|| raised(CHR))?)),
+                }
             },
-            Binary{ left, operation, right } => match operation {
-                Add => {
-                    let left_val = left.eval_ctx(env1)?.integer()?;
-                    let right_val = right.eval_ctx(env1)?.integer()?;
-                    Ok(Integer(left_val + right_val))
-                },
-                Sub => {
-                    let left_val = left.eval_ctx(env1)?.integer()?;
-                    let right_val = right.eval_ctx(env1)?.integer()?;
-                    Ok(Integer(left_val - right_val))
-                },
-                Mult => {
-                    let left_val = left.eval_ctx(env1)?.integer()?;
-                    let right_val = right.eval_ctx(env1)?.integer()?;
-                    Ok(Integer(left_val * right_val))
-                },
-                Div => {
-                    let left_val = left.eval_ctx(env1)?.integer()?;
-                    let right_val = right.eval_ctx(env1)?.integer()?;
-                    Ok(Integer(left_val / right_val))
-                },
-                Mod => {
-                    let left_val = left.eval_ctx(env1)?.integer()?;
-                    let right_val = right.eval_ctx(env1)?.integer()?;
-                    Ok(Integer(left_val % right_val))
-                },
-                Equal => {
-                    let left_val = left.eval_ctx(env1)?.integer()?;
-                    let right_val = right.eval_ctx(env1)?.integer()?;
-                    Ok(Boolean(left_val == right_val))
-                },
-                LessThan => {
-                    let left_val = left.eval_ctx(env1)?.integer()?;
-                    let right_val = right.eval_ctx(env1)?.integer()?;
-                    Ok(Boolean(left_val < right_val))
-                },
-                OrElse => {
-                    let left_val = left.eval_ctx(env1)?.boolean()?;
-                    // rust short circuits even under the result monad :)
-                    Ok(Boolean(left_val || right.eval_ctx(env1)?.boolean()?))
-                },
-                AndAlso => {
-                    let left_val = left.eval_ctx(env1)?.boolean()?;
-                    Ok(Boolean(left_val && right.eval_ctx(env1)?.boolean()?))
-                },
+            Binary{ left, operation: OrElse, right } => {
+                let left_val = left.eval_at(at(0), env1)?.boolean()?;
+                if env1.forcing(&[&right]) {
+                    let right_val = right.eval_at(at(1), env1).and_then(Value::boolean);
+                    return if left_val { taken(Ok(true), right_val) } else { right_val }.map(Boolean)
+                }
+                // rust short circuits even under the result monad :)
+                Ok(Boolean(left_val || right.eval_at(at(1), env1)?.boolean()?))
+            },
+            Binary{ left, operation: AndAlso, right } => {
+                let left_val = left.eval_at(at(0), env1)?.boolean()?;
+                if env1.forcing(&[&right]) {
+                    let right_val = right.eval_at(at(1), env1).and_then(Value::boolean);
+                    return if left_val { right_val } else { taken(Ok(false), right_val) }.map(Boolean)
+                }
+                Ok(Boolean(left_val && right.eval_at(at(1), env1)?.boolean()?))
+            },
+            Binary{ left, operation, right } => {
+                let left_val = left.eval_at(at(0), env1)?;
+                let right_val = right.eval_at(at(1), env1)?;
+                binary(operation, left_val, right_val, env1.shared.config.width)
             },
             IfThenElse{ condition, if_branch, else_branch } => {
-                if condition.eval_ctx(env1)?.boolean()? {
-                    if_branch.eval_ctx(env1)
+                let condition_val = condition.eval_at(at(0), env1)?.boolean()?;
+                if env1.forcing(&[&if_branch, &else_branch]) {
+                    let if_val = if_branch.eval_at(at(1), env1);
+                    let else_val = else_branch.eval_at(at(2), env1);
+                    return if condition_val { taken(if_val, else_val) } else { taken(else_val, if_val) }
+                }
+                if condition_val {
+                    if_branch.eval_at(at(1), env1)
                 } else {
-                    else_branch.eval_ctx(env1)
+                    else_branch.eval_at(at(2), env1)
+                }
+            },
+            // the body runs for its effects, like the front of a sequence
+            While{ condition, body } => {
+                while condition.clone().eval_at(at(0), env1)?.boolean()? {
+                    body.clone().eval_at(at(1), env1)?.unit()?;
                 }
+                Ok(Unit)
             },
             Expr::Tuple{ fst, snd } => {
-                let fst_val = fst.eval_ctx(env1)?;
-                let snd_val = snd.eval_ctx(env1)?;
+                let fst_val = fst.eval_at(at(0), env1)?;
+                let snd_val = snd.eval_at(at(1), env1)?;
                 Ok(Value::Tuple{ fst: Box::new(fst_val), snd: Box::new(snd_val) })
             },
-            Let{ name, binder, body } => {
-                let binder_val = binder.eval_ctx(env1)?;
-                env1.extend(name, binder_val, |env2| body.eval_ctx(env2))
-            },
+            Let{ bindings, body } => let_in(&mut bindings.into_iter(), *body, ids, env1),
+            // a closure only holds on to what its body reads
             Lambda{ name, body } => {
-                Ok(Abstraction(Closure{ formal: name, body: *body, context: env1.clone() }))
+                let mut free = body.free_variables();
+                free.remove(name);
+                let context = env1.only(&free);
+                Ok(Abstraction(Box::new(Closure{ formal: name, body: *body, id: at(0), context })))
             },
             App{ left, right } => {
-                match left.eval_ctx(env1)? {
-                    Abstraction(Closure{ formal, body, mut context }) => {
-                        let right_val = right.eval_ctx(env1)?;
-                        context.extend(formal, right_val, |env2| body.eval_ctx(env2))
-                    },
-                    Function(Definition{ argument, body, .. }) => {
-                        let right_val = right.eval_ctx(env1)?;
-                        env1.extend(argument, right_val, |env2| body.eval_ctx(env2))
+                match left.eval_at(at(0), env1)? {
+                    function @ Abstraction(_) | function @ Function(..) | function @ Compiled(_) => {
+                        let right_val = right.eval_at(at(1), env1)?;
+                        function.apply(right_val, env1)
                     },
                     val => Err(TypeError{ expr: val, should: Type::Function }),
                 }
@@ -279,20 +1028,75 @@ impl<'a> Expr<'a> {
                 let seq_len = sequence.len();
                 for (i, expr) in sequence.into_iter().enumerate() {
                     if i < seq_len - 1 {
-                        expr.eval_ctx(env1)?.unit()?;
+                        expr.eval_at(at(i), env1)?.unit()?;
                     } else {
-                        return expr.eval_ctx(env1)
+                        return expr.eval_at(at(i), env1)
                     }
                 }
                 unreachable!()
             },
+            Expr::List(elements) => {
+                let mut values = Vec::with_capacity(elements.len());
+                for (i, expr) in elements.into_iter().enumerate() {
+                    values.push(expr.eval_at(at(i), env1)?);
+                }
+                Ok(Value::List(values))
+            },
+            Cons{ head, tail } => {
+                let head_val = head.eval_at(at(0), env1)?;
+                let mut tail_val = tail.eval_at(at(1), env1)?.list()?;
+                tail_val.insert(0, head_val);
+                Ok(Value::List(tail_val))
+            },
             Funs{ defs, body } => {
-                env1.add_definitions(defs, |env2| body.eval_ctx(env2))
+                let body_id = at(defs.len());
+                env1.add_definitions(defs, ids, |env2| body.eval_at(body_id, env2))
+            },
+            // annotations are only checked statically
+            Annot{ expr, .. } => expr.eval_at(at(0), env1),
+            Construct{ name, argument } => {
+                let argument = match argument {
+                    Some(argument) => Some(Box::new(argument.eval_at(at(0), env1)?)),
+                    None => None,
+                };
+                Ok(Data{ constructor: name, argument })
             },
+            Expr::Record(fields) => {
+                let mut values = Vec::with_capacity(fields.len());
+                for (i, (label, expr)) in fields.into_iter().enumerate() {
+                    values.push((label, expr.eval_at(at(i), env1)?));
+                }
+                values.sort_by_key(|(label, _)| *label);
+                Ok(Value::Record(values))
+            },
+            Select{ label, record } => {
+                let val = record.eval_at(at(0), env1)?;
+                let field = match &val {
+                    Value::Record(fields) => select(fields, label).cloned(),
+                    _ => None,
+                };
+                field.ok_or_else(|| TypeError{ expr: val, should: Type::Field(label.to_string()) })
+            },
+            Raise(expr) => Err(Raised(expr.eval_at(at(0), env1)?)),
+            Handle{ expr, rules } => match expr.eval_at(at(0), env1) {
+                Err(Raised(value)) => {
+                    for (i, rule) in rules.into_iter().enumerate() {
+                        if let Some(bindings) = rule.pattern.bind(&value) {
+                            return env1.extend_all(bindings, |env2| rule.body.eval_at(at(i + 1), env2))
+                        }
+                    }
+                    Err(Raised(value))
+                },
+                res => res,
+            },
+            Expr::Error(span) => Err(self::Error::Unparsed(span)),
         }
     }
     pub fn eval(self) -> Result<Value<'a>, Error<'a>> {
-        let mut env = Env::new();
+        self.eval_with(EvalConfig::default())
+    }
+    pub fn eval_with(self, config: EvalConfig) -> Result<Value<'a>, Error<'a>> {
+        let mut env = Env::with_config(config);
         self.eval_ctx(&mut env)
     }
 }
@@ -302,6 +1106,12 @@ mod tests {
     use combine::Parser;
     use crate::lexer::{Tokenizer};
     use crate::expr::{prog};
+    use std::cell::RefCell;
+    use std::io::{self, Write};
+    use std::rc::Rc;
+    use crate::runtime::width::{Width};
+    use std::time::{Duration};
+    use super::{Env, EvalConfig};
 
     #[test]
     fn eval_unit() {
@@ -326,4 +1136,212 @@ mod tests {
         "#;
         test(power, "2 16", 2_i64.pow(16));
     }
+
+    #[test]
+    fn eval_rec_unit() {
+        fn test(input: &'static str, output: i64) {
+            let (expr, _) = prog().parse(Tokenizer::new(input)).unwrap();
+            let evaled = expr.eval().and_then(|v| v.integer()).unwrap();
+            assert_eq!(evaled, output)
+        }
+        test("let val rec fact = fn n => if n = 0 then 1 else n * fact (n - 1) in fact 10 end", 3628800);
+        test("let fun fib n = if n < 2 then n else fib (n - 1) + fib (n - 2) in fib 6 end", 8);
+    }
+
+    #[test]
+    fn eval_tail_unit() {
+        let eval = |input: &str| {
+            let (expr, _) = prog().parse(Tokenizer::new(input)).unwrap();
+            expr.eval().map(|v| v.to_string()).map_err(|err| err.to_string())
+        };
+        // far deeper than the stack would go if each call took some of it
+        let count = "let fun count n = fn acc => if n = 0 then acc else count (n - 1) (acc + 1) in \
+                     count 100000 0 end";
+        assert_eq!(eval(count), Ok("100000".to_string()));
+        let parity = "let fun even n = if n = 0 then true else odd (n - 1) \
+                      and odd n = if n = 0 then false else even (n - 1) in (even 100001, odd 100001) end";
+        assert_eq!(eval(parity), Ok("(false, true)".to_string()));
+        let closures = "let val rec down = fn n => if n = 0 then 0 else let val m = n - 1 in down m end in \
+                        down 100000 handle Div => 1 end";
+        assert_eq!(eval(closures), Ok("0".to_string()));
+
+        // what a tail call binds is gone once it returns
+        let mut env = Env::new();
+        let (expr, _) = prog().parse(Tokenizer::new("let fun f n = n in let val x = 2 in f x end end")).unwrap();
+        assert_eq!(expr.eval_ctx(&mut env).unwrap().to_string(), "2");
+        assert!(env.empty());
+    }
+
+    #[test]
+    fn eval_limits_unit() {
+        let eval = |input, config| {
+            let (expr, _) = prog().parse(Tokenizer::new(input)).unwrap();
+            let mut env = Env::with_config(config);
+            expr.eval_ctx(&mut env).map(|v| v.to_string()).map_err(|err| (err.code(), err.to_string()))
+        };
+        let forever = "let fun spin n = spin (n + 1) in spin 0 end";
+        let steps = EvalConfig{ max_steps: Some(10_000), ..EvalConfig::default() };
+        assert_eq!(eval(forever, steps), Err(("E0105", "gave up after 10000 steps".to_string())));
+        assert_eq!(eval("let fun f n = n + 1 in f 1 end", steps), Ok("2".to_string()));
+        let timeout = EvalConfig{ timeout: Some(Duration::from_millis(20)), ..EvalConfig::default() };
+        assert_eq!(eval(forever, timeout), Err(("E0105", "gave up after running for 20ms".to_string())));
+
+        // only calls that are not in tail position go deeper
+        let depth = EvalConfig{ max_stack_depth: Some(50), ..EvalConfig::default() };
+        let deep = "let fun sum n = if n = 0 then 0 else n + sum (n - 1) in sum 1000 end";
+        assert_eq!(eval(deep, depth), Err(("E0105", "gave up 50 evaluations deep".to_string())));
+        let count = "let fun count n = fn acc => if n = 0 then acc else count (n - 1) (acc + 1) in count 1000 0 end";
+        assert_eq!(eval(count, depth), Ok("1000".to_string()));
+    }
+
+    #[test]
+    fn eval_constant_time_unit() {
+        let constant = EvalConfig{ constant_time: true, ..EvalConfig::default() };
+        let eval = |input: &str, config: EvalConfig| {
+            let (expr, _) = prog().parse(Tokenizer::new(input)).unwrap();
+            let printed = Rc::new(RefCell::new(vec![]));
+            let res = expr.eval_ctx(&mut Env::with_output(config, printed.clone())).map(|v| v.to_string());
+            let printed = String::from_utf8_lossy(&printed.borrow()).to_string();
+            (res.map_err(|err| err.code()), printed)
+        };
+        // the fewest steps the evaluation can be given
+        let needs = |input: &str, config: EvalConfig| {
+            (1..100).find(|max| eval(input, EvalConfig{ max_steps: Some(*max), ..config }).0.is_ok())
+        };
+        let branch = |secret| format!("let val x = 5 in if {} then x + 1 else (x * 2) div x - 1 end", secret);
+        let logic = |secret| format!("let val x = 5 in {} andalso x > 2 orelse x < 3 end", secret);
+        assert_eq!(needs(&branch("true"), constant), needs(&branch("false"), constant));
+        assert_eq!(needs(&logic("true"), constant), needs(&logic("false"), constant));
+        assert_ne!(needs(&branch("true"), EvalConfig::default()), needs(&branch("false"), EvalConfig::default()));
+
+        // what the branch not taken does is not seen
+        assert_eq!(eval("if 1 < 2 then 1 else 1 div 0", constant), (Ok("1".to_string()), String::new()));
+        assert_eq!(eval("false andalso 1 div 0 = 0", constant), (Ok("false".to_string()), String::new()));
+        assert_eq!(eval("true orelse x", constant), (Ok("true".to_string()), String::new()));
+        assert!(eval("if 1 < 2 then 1 div 0 else 2", constant).0.is_err());
+        assert_eq!(eval("if 1 < 2 then 1 else (print 2; 3)", constant), (Ok("1".to_string()), String::new()));
+    }
+
+    #[test]
+    fn eval_list_unit() {
+        let tests = vec![
+            ("[1, 2, 3]", "[1, 2, 3]"),
+            ("1 :: 2 :: nil", "[1, 2]"),
+            ("(1 + 1) :: [3]", "[2, 3]"),
+            ("[] :: [[1]]", "[[], [1]]"),
+            ("nil", "[]"),
+        ];
+        for (input, output) in tests {
+            let (expr, _) = prog().parse(Tokenizer::new(input)).unwrap();
+            assert_eq!(expr.eval().unwrap().to_string(), output)
+        }
+    }
+
+    #[test]
+    fn eval_neg_unit() {
+        let tests = vec![
+            ("~3", -3),
+            ("let val x = 4 in ~(x + 1) end", -5),
+            ("-2 * 3", -6),
+            ("10 - ~2", 12),
+            ("1 + -1", 0),
+        ];
+        for (input, output) in tests {
+            let (expr, _) = prog().parse(Tokenizer::new(input)).unwrap();
+            assert_eq!(expr.eval().and_then(|v| v.integer()).unwrap(), output, "{}", input)
+        }
+    }
+
+    #[test]
+    fn eval_raise_unit() {
+        let tests = vec![
+            ("(10 div 0) handle Div => ~1", "~1"),
+            ("(raise Fail (1, 2)) handle Div => (0, 0) | Fail p => p", "(1, 2)"),
+            ("let fun find n = if n = 3 then raise Found n else find (n + 1) in find 0 handle Found k => k end",
+             "3"),
+            ("((raise Oops) handle Div => 0) handle _ => 5", "5"),
+            ("1 + 2 handle _ => 0", "3"),
+        ];
+        for (input, output) in tests {
+            let (expr, _) = prog().parse(Tokenizer::new(input)).unwrap();
+            let (expected, _) = prog().parse(Tokenizer::new(output)).unwrap();
+            assert_eq!(expr.eval().unwrap().to_string(), expected.eval().unwrap().to_string(), "{}", input)
+        }
+        // a handle that has no rule for the value passes it on
+        let (expr, _) = prog().parse(Tokenizer::new("(raise Some 1) handle None => 0")).unwrap();
+        let err = expr.eval().unwrap_err();
+        assert_eq!((err.code(), err.to_string()), ("E0103", "uncaught exception `Some 1`".to_string()));
+    }
+
+    #[test]
+    fn eval_width_unit() {
+        let eval = |input, width| {
+            let (expr, _) = prog().parse(Tokenizer::new(input)).unwrap();
+            let config = EvalConfig{ width, ..EvalConfig::default() };
+            expr.eval_with(config).map(|v| v.to_string()).map_err(|err| err.to_string())
+        };
+        let overflow = Err("uncaught exception `Overflow`".to_string());
+        assert_eq!(eval("2147483647 + 1", Width::I64), Ok("2147483648".to_string()));
+        assert_eq!(eval("2147483647 + 1", Width::I32), overflow);
+        assert_eq!(eval("~2147483647 - 1", Width::I32), Ok("-2147483648".to_string()));
+        assert_eq!(eval("~(~2147483647 - 1)", Width::I32), overflow);
+        let huge = "let fun square n = n * n in square (square (square (square (square (square 2))))) end";
+        assert_eq!(eval(huge, Width::I64), overflow);
+        assert_eq!(eval(&format!("({}) handle Overflow => 0", huge), Width::I32), Ok("0".to_string()));
+    }
+
+    #[test]
+    fn eval_reals_unit() {
+        let eval = |input| {
+            let (expr, _) = prog().parse(Tokenizer::new(input)).unwrap();
+            expr.eval().map(|v| v.to_string()).map_err(|err| err.to_string())
+        };
+        assert_eq!(eval("(7.0 / 2.0 - 0.5, ~1e~3 < 0.0)"), Ok("(3.0, true)".to_string()));
+        assert_eq!(eval("1e308 * 10.0 handle Overflow => ~1.0"), Ok("-1.0".to_string()));
+        // reals and integers never mix, and only reals divide with `/`
+        for input in &["1 + 1.5", "7 / 2", "7.0 div 2.0", "~1.5 / 2"] {
+            assert!(eval(input).unwrap_err().starts_with("expected"), "{}", input);
+        }
+    }
+
+    #[test]
+    fn eval_records_unit() {
+        let eval = |input| {
+            let (expr, _) = prog().parse(Tokenizer::new(input)).unwrap();
+            expr.eval().map(|v| v.to_string()).map_err(|err| err.to_string())
+        };
+        // fields are evaluated as written and kept sorted by label
+        let input = "(print 1; {y = (print 2; true), x = 1})";
+        assert_eq!(eval(input), Ok("{x = 1, y = true}".to_string()));
+        assert_eq!(eval("#x {y = 2, x = #y {y = 1}} + 1"), Ok("2".to_string()));
+        let input = "(raise E {code = Io, why = 3}) handle E {why, code = Div} => 0 | E {code = Io, why} => why";
+        assert_eq!(eval(input), Ok("3".to_string()));
+        // a pattern needs every label of the record
+        assert_eq!(eval("(raise E {x = 1, y = 2}) handle E {x} => x | _ => 0"), Ok("0".to_string()));
+        assert_eq!(eval("#z {x = 1}"), Err("expected a record with a `z` field but found `{x = 1}`".to_string()));
+        assert_eq!(eval("#z 1"), Err("expected a record with a `z` field but found `1`".to_string()));
+    }
+
+    #[test]
+    fn eval_output_unit() {
+        let printed = Rc::new(RefCell::new(vec![]));
+        let mut env = Env::with_output(EvalConfig::default(), printed.clone());
+        let (expr, _) = prog().parse(Tokenizer::new("(print 1; print (2, true); 3)")).unwrap();
+        assert_eq!(expr.eval_ctx(&mut env).unwrap().to_string(), "3");
+        assert_eq!(String::from_utf8(printed.borrow().clone()).unwrap(), "1\n(2, true)\n");
+
+        // an output that takes nothing raises `Io`
+        struct Full;
+        impl Write for Full {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::Error::other("full"))
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        let mut env = Env::with_output(EvalConfig::default(), Rc::new(RefCell::new(Full)));
+        let (expr, _) = prog().parse(Tokenizer::new("print 1 handle Io => ()")).unwrap();
+        assert_eq!(expr.eval_ctx(&mut env).unwrap().to_string(), "()");
+    }
 }
@@ -0,0 +1,639 @@
+use std::fmt;
+use std::rc::Rc;
+
+use crate::lexer::{Literal};
+use crate::expr::{UnaryOp, BinaryOp, Definition, Expr, Pattern};
+use crate::expr::eval::{Env, Error, Limit, Type, Value, binary, chr, forcible, raised, select, taken};
+use crate::expr::eval::{CHR, IO, OVERFLOW};
+
+// the tree turned into rust closures once, so evaluating it does not match
+// on every node it goes through again. variables are worked out to where
+// their values will be ahead of time: a slot of the frame of the function
+// the variable is in, what the function captured when it was made, or a
+// name to look up in the environment when no binder of the tree binds it.
+// calls in tail position take no stack, like the tree walker's.
+//
+// it does not trace, see `EvalStrategy::Closures`. a `fun` sees the scope
+// it is defined in instead of the caller's, which only matters to one that
+// is called where its own name is not in scope
+
+type Code<'a> = Box<dyn Fn(&mut Frame<'a>, &mut Env<'a>) -> Result<Value<'a>, Error<'a>> + 'a>;
+// code in tail position, which leaves a call it ends with to its caller
+type TailCode<'a> = Box<dyn Fn(&mut Frame<'a>, &mut Env<'a>) -> Result<Tail<'a>, Error<'a>> + 'a>;
+
+enum Tail<'a> {
+    Done(Value<'a>),
+    // the function and its argument
+    Call(Value<'a>, Value<'a>),
+}
+
+// where the value of a variable is when the code runs
+#[derive(Debug, Copy, Clone)]
+enum Place<'a> {
+    // a slot of the frame of the function the variable is in
+    Slot(usize),
+    // one of the values its group of functions captured
+    Captured(usize),
+    // a function of the group it is in, `f` in `fun f n = f n`
+    Sibling(usize),
+    // in the environment, bound by nothing in the tree
+    Free(&'a str),
+}
+
+struct Frame<'a> {
+    slots: Vec<Value<'a>>,
+    // the group of the function this is a call of, an empty one at the top
+    group: Rc<Group<'a>>,
+}
+
+// the functions a lambda or a `fun ... and ...` makes each time it is
+// evaluated, with what they captured. the code is only compiled once
+struct Group<'a> {
+    functions: Rc<[Function<'a>]>,
+    captured: Vec<Value<'a>>,
+}
+
+struct Function<'a> {
+    shown: Shown<'a>,
+    // how many slots a call of it needs, its argument is in the first
+    size: usize,
+    body: TailCode<'a>,
+}
+
+// what a function was in the tree, to show it the way the tree walker does
+enum Shown<'a> {
+    Lambda(&'a str, Expr<'a>),
+    Definition(Definition<'a>),
+}
+
+// a function value made by compiled code, `Value::Compiled`
+#[derive(Clone)]
+pub struct Compiled<'a> {
+    group: Rc<Group<'a>>,
+    index: usize,
+}
+
+impl<'a> fmt::Display for Compiled<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.group.functions[self.index].shown {
+            Shown::Lambda(formal, body) => write!(f, "fn {} => {}", formal, body),
+            Shown::Definition(def) => write!(f, "{}", def),
+        }
+    }
+}
+
+impl<'a> fmt::Debug for Compiled<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Compiled").field(&format_args!("{}", self)).finish()
+    }
+}
+
+fn load<'a>(place: Place<'a>, frame: &Frame<'a>, env: &Env<'a>) -> Result<Value<'a>, Error<'a>> {
+    match place {
+        Place::Slot(slot) => Ok(frame.slots[slot].clone()),
+        Place::Captured(index) => Ok(frame.group.captured[index].clone()),
+        Place::Sibling(index) => Ok(Value::Compiled(Compiled { group: frame.group.clone(), index })),
+        Place::Free(name) => env.lookup(name).cloned().ok_or(self::Error::NotFound(name)),
+    }
+}
+
+// makes the functions of a group, capturing what `captures` says from the
+// frame they are made in
+fn group<'a>(
+    functions: &Rc<[Function<'a>]>,
+    captures: &[Place<'a>],
+    frame: &Frame<'a>,
+    env: &Env<'a>,
+) -> Result<Rc<Group<'a>>, Error<'a>> {
+    let captured = captures.iter().map(|place| load(*place, frame, env)).collect::<Result<_, _>>()?;
+    Ok(Rc::new(Group { functions: functions.clone(), captured }))
+}
+
+fn is_function(value: &Value) -> bool {
+    matches!(value, Value::Abstraction(_) | Value::Function(..) | Value::Compiled(_))
+}
+
+// calls `function` on `argument` and whatever it calls in tail position
+// after, values of the tree walker's through it
+pub(super) fn call<'a>(mut function: Value<'a>, mut argument: Value<'a>, env: &mut Env<'a>)
+    -> Result<Value<'a>, Error<'a>>
+{
+    let depth = env.shared.depth.get();
+    if let Some(max) = env.shared.config.max_stack_depth.filter(|max| depth >= *max) {
+        return Err(self::Error::OutOfFuel(Limit::Depth(max)))
+    }
+    env.shared.depth.set(depth + 1);
+    let res = loop {
+        let Compiled{ group, index } = match function {
+            Value::Compiled(compiled) => compiled,
+            function => break function.apply(argument, env),
+        };
+        let code = &group.functions[index];
+        let mut slots = vec![Value::Unit; code.size];
+        slots[0] = argument;
+        let mut frame = Frame { slots, group: group.clone() };
+        match (code.body)(&mut frame, env) {
+            Ok(Tail::Done(value)) => break Ok(value),
+            Ok(Tail::Call(next, next_argument)) => {
+                function = next;
+                argument = next_argument;
+            },
+            Err(err) => break Err(err),
+        }
+    };
+    env.shared.depth.set(depth);
+    res
+}
+
+// the variables of `pattern` in the order `Pattern::bind` binds them
+fn variables<'a>(pattern: &Pattern<'a>, names: &mut Vec<&'a str>) {
+    match pattern {
+        Pattern::Wildcard => {},
+        Pattern::Var(name) => names.push(name),
+        Pattern::Construct{ argument, .. } => if let Some(argument) = argument {
+            variables(argument, names)
+        },
+        Pattern::Record(fields) => for (_, pattern) in fields {
+            variables(pattern, names)
+        },
+    }
+}
+
+// the binders in scope in one function being compiled
+#[derive(Default)]
+struct Scope<'a> {
+    // innermost last, a variable's slot is the last one with its name
+    slots: Vec<&'a str>,
+    // the most slots in scope at once
+    size: usize,
+    siblings: Vec<&'a str>,
+    // where each captured value is in the enclosing scope
+    captured: Vec<(&'a str, Place<'a>)>,
+}
+
+struct Compiler<'a> {
+    // the function being compiled last, the top level first
+    scopes: Vec<Scope<'a>>,
+}
+
+impl<'a> Compiler<'a> {
+    fn resolve(&mut self, level: usize, name: &'a str) -> Place<'a> {
+        let scope = &self.scopes[level];
+        if let Some(slot) = scope.slots.iter().rposition(|bound| *bound == name) {
+            return Place::Slot(slot)
+        }
+        if let Some(index) = scope.siblings.iter().position(|sibling| *sibling == name) {
+            return Place::Sibling(index)
+        }
+        if let Some(index) = scope.captured.iter().position(|(captured, _)| *captured == name) {
+            return Place::Captured(index)
+        }
+        if level == 0 {
+            return Place::Free(name)
+        }
+        match self.resolve(level - 1, name) {
+            Place::Free(name) => Place::Free(name),
+            outer => {
+                let captured = &mut self.scopes[level].captured;
+                captured.push((name, outer));
+                Place::Captured(captured.len() - 1)
+            },
+        }
+    }
+    fn scope(&mut self) -> &mut Scope<'a> {
+        self.scopes.last_mut().unwrap()
+    }
+    fn bind(&mut self, name: &'a str) -> usize {
+        let scope = self.scope();
+        scope.slots.push(name);
+        scope.size = scope.size.max(scope.slots.len());
+        scope.slots.len() - 1
+    }
+    fn unbind(&mut self, count: usize) {
+        let scope = self.scope();
+        scope.slots.truncate(scope.slots.len() - count);
+    }
+    // compiles the functions of a group, each `(argument, body, shown)`, and
+    // says what to capture for them where they are made
+    fn functions(&mut self, functions: Vec<(&'a str, &Expr<'a>, Shown<'a>)>, siblings: Vec<&'a str>)
+        -> (Rc<[Function<'a>]>, Rc<[Place<'a>]>)
+    {
+        self.scopes.push(Scope { siblings, ..Scope::default() });
+        let mut compiled = vec![];
+        for (argument, body, shown) in functions {
+            self.scope().slots = vec![argument];
+            self.scope().size = 1;
+            let body = self.tail(body);
+            compiled.push(Function { shown, size: self.scope().size, body });
+        }
+        let scope = self.scopes.pop().unwrap();
+        (compiled.into(), scope.captured.into_iter().map(|(_, place)| place).collect())
+    }
+    fn tail(&mut self, expr: &Expr<'a>) -> TailCode<'a> {
+        use Expr::*;
+        match expr {
+            IfThenElse{ condition, if_branch, else_branch } => {
+                // forcible branches make no calls, so they come to a value
+                let force = forcible(if_branch) && forcible(else_branch);
+                let (condition, if_branch, else_branch) =
+                    (self.expr(condition), self.tail(if_branch), self.tail(else_branch));
+                Box::new(move |frame, env| {
+                    env.step()?;
+                    let condition_val = condition(frame, env)?.boolean()?;
+                    if force && env.shared.config.constant_time {
+                        let if_val = if_branch(frame, env);
+                        let else_val = else_branch(frame, env);
+                        return if condition_val { taken(if_val, else_val) } else { taken(else_val, if_val) }
+                    }
+                    if condition_val { if_branch(frame, env) } else { else_branch(frame, env) }
+                })
+            },
+            Let{ bindings, body } => {
+                // each binder is compiled with the ones before it in scope
+                let binders: Vec<(Code<'a>, usize)> = bindings.iter()
+                    .map(|(name, binder)| (self.expr(binder), self.bind(name)))
+                    .collect();
+                let body = self.tail(body);
+                self.unbind(binders.len());
+                Box::new(move |frame, env| {
+                    env.step()?;
+                    for (binder, slot) in &binders {
+                        frame.slots[*slot] = binder(frame, env)?;
+                    }
+                    body(frame, env)
+                })
+            },
+            App{ left, right } => {
+                let (left, right) = (self.expr(left), self.expr(right));
+                Box::new(move |frame, env| {
+                    env.step()?;
+                    let function = left(frame, env)?;
+                    if !is_function(&function) {
+                        return Err(self::Error::TypeError{ expr: function, should: Type::Function })
+                    }
+                    Ok(Tail::Call(function, right(frame, env)?))
+                })
+            },
+            Seq(sequence) if !sequence.is_empty() => {
+                let (last, front) = sequence.split_last().unwrap();
+                let front: Vec<Code<'a>> = front.iter().map(|expr| self.expr(expr)).collect();
+                let last = self.tail(last);
+                Box::new(move |frame, env| {
+                    env.step()?;
+                    for expr in &front {
+                        expr(frame, env)?.unit()?;
+                    }
+                    last(frame, env)
+                })
+            },
+            Funs{ defs, body } => {
+                let siblings: Vec<&'a str> = defs.iter().map(|def| def.name).collect();
+                let functions = defs.iter()
+                    .map(|def| (def.argument, &*def.body, Shown::Definition(def.clone())))
+                    .collect();
+                let (functions, captures) = self.functions(functions, siblings.clone());
+                let slots: Vec<usize> = siblings.iter().map(|name| self.bind(name)).collect();
+                let body = self.tail(body);
+                self.unbind(slots.len());
+                Box::new(move |frame, env| {
+                    env.step()?;
+                    let group = group(&functions, &captures, frame, env)?;
+                    for (index, slot) in slots.iter().enumerate() {
+                        frame.slots[*slot] = Value::Compiled(Compiled { group: group.clone(), index });
+                    }
+                    body(frame, env)
+                })
+            },
+            Annot{ expr, .. } => {
+                let expr = self.tail(expr);
+                Box::new(move |frame, env| {
+                    env.step()?;
+                    expr(frame, env)
+                })
+            },
+            Handle{ expr, rules } => {
+                let expr = self.expr(expr);
+                let rules: Vec<(Pattern<'a>, Vec<usize>, TailCode<'a>)> = rules.iter()
+                    .map(|rule| {
+                        // the first of a name bound twice is the one seen,
+                        // so it is bound last
+                        let mut names = vec![];
+                        variables(&rule.pattern, &mut names);
+                        let slots: Vec<usize> = names.iter().rev().map(|name| self.bind(name)).collect();
+                        let body = self.tail(&rule.body);
+                        self.unbind(slots.len());
+                        (rule.pattern.clone(), slots, body)
+                    })
+                    .collect();
+                Box::new(move |frame, env| {
+                    env.step()?;
+                    match expr(frame, env) {
+                        Err(self::Error::Raised(value)) => {
+                            for (pattern, slots, body) in &rules {
+                                if let Some(bindings) = pattern.bind(&value) {
+                                    for (slot, (_, value)) in slots.iter().zip(bindings.into_iter().rev()) {
+                                        frame.slots[*slot] = value;
+                                    }
+                                    return body(frame, env)
+                                }
+                            }
+                            Err(self::Error::Raised(value))
+                        },
+                        res => res.map(Tail::Done),
+                    }
+                })
+            },
+            expr => {
+                let code = self.expr(expr);
+                Box::new(move |frame, env| code(frame, env).map(Tail::Done))
+            },
+        }
+    }
+    fn expr(&mut self, expr: &Expr<'a>) -> Code<'a> {
+        use UnaryOp::*;
+        use BinaryOp::*;
+        use Expr::*;
+        match expr {
+            Var(name) => match self.resolve(self.scopes.len() - 1, name) {
+                Place::Slot(slot) => Box::new(move |frame, env| {
+                    env.step()?;
+                    Ok(frame.slots[slot].clone())
+                }),
+                place => Box::new(move |frame, env| {
+                    env.step()?;
+                    load(place, frame, env)
+                }),
+            },
+            Lit(lit) => {
+                let (lit, value) = (*lit, lit.into_value());
+                Box::new(move |_, env| {
+                    env.step()?;
+                    match lit {
+                        Literal::Integer(i) if !env.shared.config.width.contains(i) => Err(raised(OVERFLOW)),
+                        _ => Ok(value.clone()),
+                    }
+                })
+            },
+            Unary{ operation, child } => {
+                let (operation, child) = (*operation, self.expr(child));
+                Box::new(move |frame, env| {
+                    env.step()?;
+                    let val = child(frame, env)?;
+                    if !env.shared.config.builtins.allows(operation) {
+                        return Err(self::Error::Denied(operation))
+                    }
+                    match operation {
+                        Not => Ok(Value::Boolean(!val.boolean()?)),
+                        Fst => Ok(val.tuple()?.0),
+                        Snd => Ok(val.tuple()?.1),
+                        Print => env.print(&val).map(|()| Value::Unit).map_err(|_| raised(IO)),
+                        Neg => match val {
+                            Value::Real(x) => Ok(Value::Real(x.negate())),
+                            val => env.shared.config.width.negate(val.integer()?).map(Value::Integer).map_err(raised),
+                        },
+                        Ord => Ok(Value::Integer(val.character()? as i64)),
+                        Chr => Ok(Value::Char(chr(val.integer()?).ok_or_else(|| raised(CHR))?)),
+                    }
+                })
+            },
+            Binary{ left, operation: operation @ OrElse, right }
+                | Binary{ left, operation: operation @ AndAlso, right } =>
+            {
+                let (force, orelse) = (forcible(right), *operation == OrElse);
+                let (left, right) = (self.expr(left), self.expr(right));
+                Box::new(move |frame, env| {
+                    env.step()?;
+                    let left_val = left(frame, env)?.boolean()?;
+                    if force && env.shared.config.constant_time {
+                        let right_val = right(frame, env).and_then(Value::boolean);
+                        let res = if left_val == orelse { taken(Ok(orelse), right_val) } else { right_val };
+                        return res.map(Value::Boolean)
+                    }
+                    if left_val == orelse {
+                        Ok(Value::Boolean(orelse))
+                    } else {
+                        right(frame, env)?.boolean().map(Value::Boolean)
+                    }
+                })
+            },
+            Binary{ left, operation, right } => {
+                let (operation, left, right) = (*operation, self.expr(left), self.expr(right));
+                Box::new(move |frame, env| {
+                    env.step()?;
+                    let left_val = left(frame, env)?;
+                    let right_val = right(frame, env)?;
+                    binary(operation, left_val, right_val, env.shared.config.width)
+                })
+            },
+            While{ condition, body } => {
+                let (condition, body) = (self.expr(condition), self.expr(body));
+                Box::new(move |frame, env| {
+                    env.step()?;
+                    while condition(frame, env)?.boolean()? {
+                        body(frame, env)?.unit()?;
+                    }
+                    Ok(Value::Unit)
+                })
+            },
+            Seq(sequence) if sequence.is_empty() => Box::new(|_, env| {
+                env.step()?;
+                Ok(Value::Unit)
+            }),
+            Tuple{ fst, snd } => {
+                let (fst, snd) = (self.expr(fst), self.expr(snd));
+                Box::new(move |frame, env| {
+                    env.step()?;
+                    let fst_val = fst(frame, env)?;
+                    let snd_val = snd(frame, env)?;
+                    Ok(Value::Tuple{ fst: Box::new(fst_val), snd: Box::new(snd_val) })
+                })
+            },
+            Lambda{ name, body } => {
+                let shown = Shown::Lambda(name, (**body).clone());
+                let (functions, captures) = self.functions(vec![(name, body, shown)], vec![]);
+                Box::new(move |frame, env| {
+                    env.step()?;
+                    Ok(Value::Compiled(Compiled { group: group(&functions, &captures, frame, env)?, index: 0 }))
+                })
+            },
+            List(elements) => {
+                let elements: Vec<Code<'a>> = elements.iter().map(|expr| self.expr(expr)).collect();
+                Box::new(move |frame, env| {
+                    env.step()?;
+                    let mut values = Vec::with_capacity(elements.len());
+                    for expr in &elements {
+                        values.push(expr(frame, env)?);
+                    }
+                    Ok(Value::List(values))
+                })
+            },
+            Cons{ head, tail } => {
+                let (head, tail) = (self.expr(head), self.expr(tail));
+                Box::new(move |frame, env| {
+                    env.step()?;
+                    let head_val = head(frame, env)?;
+                    let mut tail_val = tail(frame, env)?.list()?;
+                    tail_val.insert(0, head_val);
+                    Ok(Value::List(tail_val))
+                })
+            },
+            Construct{ name, argument } => {
+                let (name, argument) = (*name, argument.as_ref().map(|argument| self.expr(argument)));
+                Box::new(move |frame, env| {
+                    env.step()?;
+                    let argument = match &argument {
+                        Some(argument) => Some(Box::new(argument(frame, env)?)),
+                        None => None,
+                    };
+                    Ok(Value::Data{ constructor: name, argument })
+                })
+            },
+            Record(fields) => {
+                let fields: Vec<(&'a str, Code<'a>)> = fields.iter()
+                    .map(|(label, expr)| (*label, self.expr(expr)))
+                    .collect();
+                Box::new(move |frame, env| {
+                    env.step()?;
+                    let mut values = Vec::with_capacity(fields.len());
+                    for (label, expr) in &fields {
+                        values.push((*label, expr(frame, env)?));
+                    }
+                    values.sort_by_key(|(label, _)| *label);
+                    Ok(Value::Record(values))
+                })
+            },
+            Select{ label, record } => {
+                let (label, record) = (*label, self.expr(record));
+                Box::new(move |frame, env| {
+                    env.step()?;
+                    let val = record(frame, env)?;
+                    let field = match &val {
+                        Value::Record(fields) => select(fields, label).cloned(),
+                        _ => None,
+                    };
+                    field.ok_or_else(|| self::Error::TypeError{ expr: val, should: Type::Field(label.to_string()) })
+                })
+            },
+            Raise(expr) => {
+                let expr = self.expr(expr);
+                Box::new(move |frame, env| {
+                    env.step()?;
+                    Err(self::Error::Raised(expr(frame, env)?))
+                })
+            },
+            Error(span) => {
+                let span = *span;
+                Box::new(move |_, env| {
+                    env.step()?;
+                    Err(self::Error::Unparsed(span))
+                })
+            },
+            // the ones with a tail position, run until they come to a value
+            expr => {
+                let tail = self.tail(expr);
+                Box::new(move |frame, env| match tail(frame, env)? {
+                    Tail::Done(value) => Ok(value),
+                    Tail::Call(function, argument) => call(function, argument, env),
+                })
+            },
+        }
+    }
+}
+
+// a tree compiled to closures, which can be run any number of times
+pub struct Program<'a> {
+    size: usize,
+    code: Code<'a>,
+}
+
+pub fn compile<'a>(expr: &Expr<'a>) -> Program<'a> {
+    let mut compiler = Compiler { scopes: vec![Scope::default()] };
+    let code = compiler.expr(expr);
+    Program { size: compiler.scopes[0].size, code }
+}
+
+impl<'a> Program<'a> {
+    // runs the program under `env`, whose variables are the free ones of
+    // the tree and whose config it keeps to but for the strategy
+    pub fn run(&self, env: &mut Env<'a>) -> Result<Value<'a>, Error<'a>> {
+        env.start();
+        let depth = env.shared.depth.get();
+        if let Some(max) = env.shared.config.max_stack_depth.filter(|max| depth >= *max) {
+            return Err(self::Error::OutOfFuel(Limit::Depth(max)))
+        }
+        env.shared.depth.set(depth + 1);
+        let top = Rc::new(Group { functions: Rc::new([]), captured: vec![] });
+        let mut frame = Frame { slots: vec![Value::Unit; self.size], group: top };
+        let res = (self.code)(&mut frame, env);
+        env.shared.depth.set(depth);
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::{parse};
+    use crate::expr::eval::{EvalConfig, EvalStrategy};
+
+    #[test]
+    fn closures_unit() {
+        let closures = EvalConfig{ strategy: EvalStrategy::Closures, ..EvalConfig::default() };
+        let eval = |input: &str, config: EvalConfig| {
+            let res = parse(input).unwrap().eval_with(config);
+            res.map(|value| value.to_string()).map_err(|err| err.to_string())
+        };
+        let sources = [
+            "let fun fib n = if n < 2 then n else fib (n - 1) + fib (n - 2) in fib 15 end",
+            "let fun even n = n = 0 orelse odd (n - 1) and odd n = n <> 0 andalso even (n - 1) in \
+             (even 10, odd 7) end",
+            "let val add = fn x => fn y => x + y val inc = add 1 in [inc 1, inc 2, (add 2) 2] end",
+            "let val x = 1 in let val f = fn y => x + y in let val x = 10 in f x end end end",
+            "let fun count n = fn acc => if n = 0 then acc else count (n - 1) (acc + 1) in count 100000 0 end",
+            "(raise Fail {msg = 1, code = 2}) handle Fail {msg = m, code = c} => m * 10 + c | _ => 0",
+            "let val r = {b = 2, a = #\"x\"} in (#a r, (#b r :: [3], (~(1.5 / 2.0), ord #\"a\"))) end",
+            "let fun f n = if n = 0 then raise Stop n else f (n - 1) in f 3 handle Go => 0 end",
+            "1 div 0",
+            "fst 1",
+            "y",
+            "(1; 2)",
+            "while true do ()",
+        ];
+        for source in &sources {
+            let limits = EvalConfig{ max_steps: Some(1_000_000), ..EvalConfig::default() };
+            let compiled = EvalConfig{ strategy: EvalStrategy::Closures, ..limits };
+            assert_eq!(eval(source, compiled), eval(source, limits), "{}", source);
+        }
+
+        // a function value comes back showing its source, and the tree
+        // walker can call it
+        let function = parse("fn x => x + 1").unwrap().eval_with(closures).unwrap();
+        assert_eq!(function.to_string(), "fn x => x + 1");
+        assert_eq!(function.apply(Value::Integer(1), &mut Env::new()).unwrap().to_string(), "2");
+
+        // compiled once and run with the environment's values for what the
+        // tree leaves free
+        let expr = parse("rate * 2").unwrap();
+        let program = compile(&expr);
+        let mut env = Env::with_config(closures);
+        let values: Vec<String> = (1..4)
+            .map(|rate| {
+                env.define("rate", Value::Integer(rate));
+                program.run(&mut env).unwrap().to_string()
+            })
+            .collect();
+        assert_eq!(values, vec!["2", "4", "6"]);
+        let depth = EvalConfig{ max_stack_depth: Some(50), ..closures };
+        assert_eq!(
+            eval("let fun f n = if n = 0 then 0 else 1 + f (n - 1) in f 1000 end", depth),
+            Err(self::Error::OutOfFuel(Limit::Depth(50)).to_string()),
+        );
+        // a builtin the config leaves out fails when it is called, the same
+        // as under the tree walker
+        let quiet = EvalConfig{ builtins: vec![UnaryOp::Not].into_iter().collect(), ..closures };
+        let denied = Err(self::Error::Denied(UnaryOp::Print).to_string());
+        assert_eq!(eval("if not true then print 1 else ()", quiet), Ok("()".to_string()));
+        assert_eq!(eval("(fn x => print x) 1", quiet), denied);
+        assert_eq!(eval("(fn x => print x) 1", EvalConfig{ strategy: EvalStrategy::Tree, ..quiet }), denied);
+    }
+}
@@ -0,0 +1,209 @@
+use std::fmt;
+
+use crate::expr::{BinaryOp, Expr};
+use crate::optimize::{fold_constants};
+
+// evaluation steps, one per node the evaluator visits. when `bounded` it is
+// the most the node can take, otherwise the fewest, since a call or a loop
+// takes however long it takes
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct Cost {
+    pub steps: u64,
+    pub bounded: bool,
+}
+
+impl Cost {
+    fn step() -> Cost {
+        Cost { steps: 1, bounded: true }
+    }
+    fn unbounded(self) -> Cost {
+        Cost { bounded: false, ..self }
+    }
+    fn then(self, other: Cost) -> Cost {
+        Cost { steps: self.steps.saturating_add(other.steps), bounded: self.bounded && other.bounded }
+    }
+    // the dearer of two things only one of which runs
+    fn or(self, other: Cost) -> Cost {
+        Cost { steps: self.steps.max(other.steps), bounded: self.bounded && other.bounded }
+    }
+}
+
+impl fmt::Display for Cost {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.bounded {
+            write!(f, "{}", self.steps)
+        } else {
+            write!(f, "{}+", self.steps)
+        }
+    }
+}
+
+// which of the children of a node run when it does
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Shape {
+    All,
+    // `andalso` and `orelse`, the second only when the first does not decide
+    ShortCircuit,
+    // `if` and `handle`, the first and then one of the others
+    OneBranch,
+    // a function, its body runs each time it is called and not before
+    PerCall,
+}
+
+// how `Expr::explain` expects a node to be evaluated
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Plan {
+    pub operator: String,
+    // of this node and what runs of its children
+    pub cost: Cost,
+    pub shape: Shape,
+    // the literal `optimize::fold_constants` turns the node into, when it is
+    // not one already
+    pub folded: Option<String>,
+    pub children: Vec<Plan>,
+}
+
+impl Plan {
+    fn write(&self, f: &mut fmt::Formatter, indent: usize) -> fmt::Result {
+        write!(f, "{}{}  cost {}", "  ".repeat(indent), self.operator, self.cost)?;
+        match self.shape {
+            Shape::All => {},
+            Shape::ShortCircuit => write!(f, "  short-circuits")?,
+            Shape::OneBranch => write!(f, "  one branch")?,
+            Shape::PerCall => write!(f, "  per call")?,
+        }
+        if let Some(folded) = &self.folded {
+            write!(f, "  folds to {}", folded)?
+        }
+        writeln!(f)?;
+        for child in &self.children {
+            child.write(f, indent + 1)?
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+fn operator(expr: &Expr) -> String {
+    use Expr::*;
+    match expr {
+        Var(name) => name.to_string(),
+        Lit(lit) => lit.to_string(),
+        Unary{ operation, .. } => operation.to_string(),
+        Binary{ operation, .. } => operation.to_string(),
+        IfThenElse{ .. } => "if".to_string(),
+        Tuple{ .. } => "tuple".to_string(),
+        Let{ bindings, .. } => {
+            let names: Vec<&str> = bindings.iter().map(|(name, _)| *name).collect();
+            format!("let {}", names.join(" "))
+        },
+        Lambda{ name, .. } => format!("fn {}", name),
+        App{ .. } => "apply".to_string(),
+        Seq(_) => "seq".to_string(),
+        List(_) => "list".to_string(),
+        Cons{ .. } => "::".to_string(),
+        Funs{ defs, .. } => {
+            let names: Vec<&str> = defs.iter().map(|def| def.name).collect();
+            format!("fun {}", names.join(" "))
+        },
+        Annot{ ty, .. } => format!(": {}", ty),
+        Construct{ name, .. } => name.to_string(),
+        Raise(_) => "raise".to_string(),
+        Handle{ .. } => "handle".to_string(),
+        While{ .. } => "while".to_string(),
+        Record(_) => "record".to_string(),
+        Select{ label, .. } => format!("#{}", label),
+        Error(_) => crate::expr::ERROR.to_string(),
+    }
+}
+
+fn sum(plans: &[Plan]) -> Cost {
+    plans.iter().fold(Cost { steps: 0, bounded: true }, |cost, plan| cost.then(plan.cost))
+}
+
+// the body of `expr` when it is a `fn`, in parentheses or not
+fn function<'e, 'a>(expr: &'e Expr<'a>) -> Option<&'e Expr<'a>> {
+    match expr {
+        Expr::Lambda{ body, .. } => Some(body),
+        Expr::Seq(sequence) if sequence.len() == 1 => function(&sequence[0]),
+        _ => None,
+    }
+}
+
+impl<'a> Expr<'a> {
+    // a tree like this one saying what each node costs to evaluate, which
+    // of its children run and what it folds to, for finding out why an
+    // expression is slow and where it stops early
+    pub fn explain(&self) -> Plan {
+        use Expr::*;
+        let children: Vec<Plan> = self.children().into_iter().map(Expr::explain).collect();
+        let (shape, cost) = match self {
+            Binary{ operation: BinaryOp::AndAlso, .. } | Binary{ operation: BinaryOp::OrElse, .. } => {
+                (Shape::ShortCircuit, children[0].cost.then(children[1].cost))
+            },
+            IfThenElse{ .. } | Handle{ .. } => {
+                let branches = children[1..].iter().map(|child| child.cost).reduce(Cost::or);
+                (Shape::OneBranch, children[0].cost.then(branches.unwrap_or(Cost::step())))
+            },
+            // a closure is made, its body waits for a call
+            Lambda{ .. } => (Shape::PerCall, Cost { steps: 0, bounded: true }),
+            Funs{ .. } => (Shape::All, children.last().map_or(Cost::step(), |body| body.cost)),
+            // calling a function literal runs its body right there
+            App{ left, .. } if function(left).is_some() => {
+                let body = function(left).map_or(Cost::step(), |body| body.explain().cost);
+                (Shape::All, body.then(children[1].cost))
+            },
+            // and the call itself, what it runs is not known
+            App{ .. } => (Shape::All, sum(&children).then(Cost::step()).unbounded()),
+            While{ .. } => (Shape::All, children[0].cost.unbounded()),
+            _ => (Shape::All, sum(&children)),
+        };
+        let folded = match fold_constants(self.clone()) {
+            Lit(lit) if !matches!(self, Lit(_)) => Some(lit.to_string()),
+            _ => None,
+        };
+        let operator = operator(self);
+        Plan { operator, cost: Cost::step().then(cost), shape, folded, children }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::{parse};
+
+    #[test]
+    fn explain_unit() {
+        let expr = parse("x > 10 andalso (if 2 * 3 = 6 then y else f y)").unwrap();
+        assert_eq!(expr.explain().to_string(), concat!(
+            "andalso  cost 15+  short-circuits\n",
+            "  >  cost 3\n",
+            "    x  cost 1\n",
+            "    10  cost 1\n",
+            "  seq  cost 11+\n",
+            "    if  cost 10+  one branch\n",
+            "      =  cost 5  folds to true\n",
+            "        *  cost 3  folds to 6\n",
+            "          2  cost 1\n",
+            "          3  cost 1\n",
+            "        6  cost 1\n",
+            "      y  cost 1\n",
+            "      apply  cost 4+\n",
+            "        f  cost 1\n",
+            "        y  cost 1\n",
+        ));
+
+        // only the branch that costs more counts, and a function's body
+        // counts where it is called
+        let plan = parse("let val f = fn n => n * n * n in if b then f 2 else 0 end").unwrap().explain();
+        assert_eq!(plan.children[0].shape, Shape::PerCall);
+        assert!(!plan.children[1].cost.bounded);
+        let plan = parse("(fn n => n * n) 3").unwrap().explain();
+        assert_eq!(plan.cost, Cost { steps: 1 + 3 + 1, bounded: true });
+    }
+}
@@ -0,0 +1,392 @@
+use crate::expr::{UnaryOp, Definition, Decl, Expr, ERROR};
+use crate::expr::source::{EXPN, DISJ, CONS, UNAR, APPN, ATOM, ends_in_handle};
+
+// how far a construct broken over lines indents what is inside it
+const INDENT: usize = 2;
+
+// a document of the kind in Wadler's "a prettier printer": text, places it
+// may break, and groups that are laid out on one line when they fit in
+// the width and with every break of their own taken when they do not
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Doc {
+    Text(String),
+    // what it is when its group is on one line, a newline at the
+    // indentation otherwise
+    Line(&'static str),
+    Nest(usize, Box<Doc>),
+    Concat(Vec<Doc>),
+    Group(Box<Doc>),
+}
+
+pub fn text<S: Into<String>>(text: S) -> Doc {
+    Doc::Text(text.into())
+}
+
+// a space or a newline
+pub fn line() -> Doc {
+    Doc::Line(" ")
+}
+
+// nothing or a newline
+pub fn softline() -> Doc {
+    Doc::Line("")
+}
+
+pub fn nest(doc: Doc) -> Doc {
+    Doc::Nest(INDENT, Box::new(doc))
+}
+
+pub fn group(doc: Doc) -> Doc {
+    Doc::Group(Box::new(doc))
+}
+
+pub fn concat(docs: Vec<Doc>) -> Doc {
+    Doc::Concat(docs)
+}
+
+impl Doc {
+    // the layout with as few lines as keep each within `width` columns,
+    // where that can be done at all
+    pub fn render(&self, width: usize) -> String {
+        let mut out = String::new();
+        let mut col = 0;
+        // what is left to lay out, the next part on top, each with its
+        // indentation and whether its group is on one line
+        let mut rest: Vec<(usize, bool, &Doc)> = vec![(0, false, self)];
+        while let Some((indent, flat, doc)) = rest.pop() {
+            match doc {
+                Doc::Text(text) => {
+                    out.push_str(text);
+                    col += text.chars().count();
+                },
+                Doc::Line(space) if flat => {
+                    out.push_str(space);
+                    col += space.len();
+                },
+                Doc::Line(_) => {
+                    out.push('\n');
+                    out.push_str(&" ".repeat(indent));
+                    col = indent;
+                },
+                Doc::Nest(by, doc) => rest.push((indent + by, flat, doc)),
+                Doc::Concat(docs) => rest.extend(docs.iter().rev().map(|doc| (indent, flat, doc))),
+                Doc::Group(doc) => {
+                    let flat = flat || fits(width as isize - col as isize, doc, &rest);
+                    rest.push((indent, flat, doc))
+                },
+            }
+        }
+        out
+    }
+}
+
+// whether `doc` on one line, and what comes after it up to where the line
+// can break next, takes at most `room` columns
+fn fits(mut room: isize, doc: &Doc, rest: &[(usize, bool, &Doc)]) -> bool {
+    let mut docs: Vec<(bool, &Doc)> = vec![(true, doc)];
+    let mut after = rest.iter().rev();
+    while 0 <= room {
+        let (flat, doc) = match docs.pop() {
+            Some(next) => next,
+            None => match after.next() {
+                Some((_, flat, doc)) => (*flat, *doc),
+                None => return true,
+            },
+        };
+        match doc {
+            Doc::Text(text) => room -= text.chars().count() as isize,
+            Doc::Line(space) if flat => room -= space.len() as isize,
+            Doc::Line(_) => return true,
+            Doc::Nest(_, doc) | Doc::Group(doc) => docs.push((flat, doc)),
+            Doc::Concat(parts) => docs.extend(parts.iter().rev().map(|doc| (flat, doc))),
+        }
+    }
+    false
+}
+
+fn parens(inner: usize, outer: usize, doc: Doc) -> Doc {
+    if inner < outer {
+        concat(vec![text("("), doc, text(")")])
+    } else {
+        doc
+    }
+}
+
+// `open`, the docs separated by `sep` and a break, and `close`, each on a
+// line of its own when they do not fit on one
+fn bracketed(open: &str, docs: Vec<Doc>, sep: &str, close: &str) -> Doc {
+    let mut inner = vec![softline()];
+    for (i, doc) in docs.into_iter().enumerate() {
+        if 0 < i {
+            inner.push(text(sep));
+            inner.push(line());
+        }
+        inner.push(doc);
+    }
+    group(concat(vec![text(open), nest(concat(inner)), softline(), text(close)]))
+}
+
+fn definition(def: &Definition) -> Doc {
+    group(concat(vec![
+        text(format!("{} {} =", def.name, def.argument)),
+        nest(concat(vec![line(), doc(&def.body, EXPN)])),
+    ]))
+}
+
+// the bindings or functions and the body of a `let`
+fn let_in(bindings: Vec<Doc>, body: &Expr) -> Doc {
+    let mut inner = vec![];
+    for binding in bindings {
+        inner.push(line());
+        inner.push(binding);
+    }
+    group(concat(vec![
+        text("let"),
+        nest(concat(inner)),
+        line(),
+        text("in"),
+        nest(concat(vec![line(), doc(body, EXPN)])),
+        line(),
+        text("end"),
+    ]))
+}
+
+// the layout of `expr` in a slot of precedence `prec`, with the parens
+// `Expr::to_source` would write
+pub fn doc(expr: &Expr, prec: usize) -> Doc {
+    use Expr::*;
+    match expr {
+        Var(name) => text(*name),
+        Lit(_) => {
+            let source = expr.to_source();
+            // negative numbers are written as a negation
+            parens(if source.starts_with('~') { UNAR } else { ATOM }, prec, text(source))
+        },
+        Error(_) => text(ERROR),
+        Unary{ operation, child } => parens(operation.precedence(), prec, match operation {
+            UnaryOp::Neg => concat(vec![text("~"), doc(child, APPN)]),
+            _ => concat(vec![text(format!("{} ", operation)), doc(child, APPN)]),
+        }),
+        Binary{ left, operation, right } => {
+            let op_prec = operation.precedence();
+            // comparisons do not chain, everything else associates left
+            let left_prec = if op_prec == 3 { op_prec + 1 } else { op_prec };
+            parens(op_prec, prec, group(concat(vec![
+                doc(left, left_prec),
+                text(format!(" {}", operation)),
+                nest(concat(vec![line(), doc(right, op_prec + 1)])),
+            ])))
+        },
+        IfThenElse{ condition, if_branch, else_branch } => {
+            // `else if` keeps to the level of the first `if`
+            let otherwise = match **else_branch {
+                IfThenElse{ .. } => concat(vec![text(" "), doc(else_branch, EXPN)]),
+                _ => nest(concat(vec![line(), doc(else_branch, EXPN)])),
+            };
+            parens(EXPN, prec, group(concat(vec![
+                text("if "),
+                doc(condition, EXPN),
+                text(" then"),
+                nest(concat(vec![line(), doc(if_branch, EXPN)])),
+                line(),
+                text("else"),
+                otherwise,
+            ])))
+        },
+        While{ condition, body } => parens(EXPN, prec, group(concat(vec![
+            text("while "),
+            doc(condition, EXPN),
+            text(" do"),
+            nest(concat(vec![line(), doc(body, EXPN)])),
+        ]))),
+        Tuple{ fst, snd } => bracketed("(", vec![doc(fst, EXPN), doc(snd, EXPN)], ",", ")"),
+        Let{ bindings, body } => {
+            let bindings = bindings.iter()
+                .map(|(name, binder)| group(concat(vec![
+                    text(format!("val {} =", name)),
+                    nest(concat(vec![line(), doc(binder, EXPN)])),
+                ])))
+                .collect();
+            parens(EXPN, prec, let_in(bindings, body))
+        },
+        Lambda{ name, body } => parens(EXPN, prec, group(concat(vec![
+            text(format!("fn {} =>", name)),
+            nest(concat(vec![line(), doc(body, EXPN)])),
+        ]))),
+        App{ left, right } => parens(APPN, prec, group(concat(vec![
+            doc(left, APPN),
+            nest(concat(vec![line(), doc(right, ATOM)])),
+        ]))),
+        Seq(sequence) => bracketed("(", sequence.iter().map(|expr| doc(expr, EXPN)).collect(), ";", ")"),
+        List(elements) => bracketed("[", elements.iter().map(|expr| doc(expr, EXPN)).collect(), ",", "]"),
+        Cons{ head, tail } => parens(CONS, prec, group(concat(vec![
+            doc(head, CONS + 1),
+            text(" ::"),
+            line(),
+            doc(tail, CONS),
+        ]))),
+        Funs{ defs, body } => {
+            let defs = defs.iter()
+                .enumerate()
+                .map(|(i, def)| concat(vec![text(if i == 0 { "fun " } else { "and " }), definition(def)]))
+                .collect();
+            parens(EXPN, prec, let_in(defs, body))
+        },
+        Annot{ expr, ty } => parens(EXPN, prec, concat(vec![doc(expr, DISJ), text(format!(" : {}", ty))])),
+        Construct{ name, argument: None } => text(*name),
+        Construct{ name, argument: Some(argument) } => parens(APPN, prec, group(concat(vec![
+            text(*name),
+            nest(concat(vec![line(), doc(argument, ATOM)])),
+        ]))),
+        Raise(expr) => parens(EXPN, prec, concat(vec![text("raise "), doc(expr, EXPN)])),
+        Handle{ expr, rules } => {
+            // an annotation is the one expression level construct allowed
+            // in front of `handle`
+            let mut docs = vec![doc(expr, if let Annot{ .. } = **expr { EXPN } else { DISJ })];
+            for (i, rule) in rules.iter().enumerate() {
+                // a body ending in a `handle` of its own would take the rules
+                // after it along
+                let last = i + 1 == rules.len();
+                let body = doc(&rule.body, if last || !ends_in_handle(&rule.body) { EXPN } else { DISJ });
+                docs.push(line());
+                docs.push(group(concat(vec![
+                    text(format!("{} {} =>", if i == 0 { "handle" } else { "|" }, rule.pattern)),
+                    nest(concat(vec![line(), body])),
+                ])));
+            }
+            let first = docs.remove(0);
+            parens(EXPN, prec, group(concat(vec![first, nest(concat(docs))])))
+        },
+        Record(fields) => {
+            let fields = fields.iter()
+                .map(|(label, expr)| group(concat(vec![
+                    text(format!("{} =", label)),
+                    nest(concat(vec![line(), doc(expr, EXPN)])),
+                ])))
+                .collect();
+            bracketed("{", fields, ",", "}")
+        },
+        Select{ label, record } => {
+            parens(UNAR, prec, concat(vec![text(format!("#{} ", label)), doc(record, APPN)]))
+        },
+    }
+}
+
+// `expr` laid out to keep within `width` columns where it can, with every
+// construct that does not fit broken over lines and indented. it parses
+// back to the same tree
+pub fn format_expr(expr: &Expr, width: usize) -> String {
+    doc(expr, EXPN).render(width)
+}
+
+fn decl(decl: &Decl) -> Doc {
+    match decl {
+        Decl::Val{ name, binder } => group(concat(vec![
+            text(format!("val {} =", name)),
+            nest(concat(vec![line(), doc(binder, EXPN)])),
+        ])),
+        Decl::Fun(defs) => {
+            let defs = defs.iter()
+                .enumerate()
+                .map(|(i, def)| concat(vec![text(if i == 0 { "fun " } else { "and " }), definition(def)]))
+                .collect::<Vec<_>>();
+            let mut docs = vec![];
+            for (i, def) in defs.into_iter().enumerate() {
+                if 0 < i {
+                    docs.push(line());
+                }
+                docs.push(def);
+            }
+            group(concat(docs))
+        },
+        Decl::Datatype(datatype) => text(datatype.to_string()),
+        Decl::Infix(fixity) => text(fixity.to_string()),
+        Decl::Expr(expr) => doc(expr, EXPN),
+    }
+}
+
+// a program with each declaration starting a line, an expression followed
+// by the `;` it needs when more comes after it
+pub fn format_program(program: &[Decl], width: usize) -> String {
+    let mut out = String::new();
+    for (i, declaration) in program.iter().enumerate() {
+        out.push_str(&decl(declaration).render(width));
+        if let Decl::Expr(_) = declaration {
+            if i + 1 < program.len() {
+                out.push(';')
+            }
+        }
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::{parse, parse_program};
+
+    #[test]
+    fn format_unit() {
+        let source = "let val x = 1 val y = [x, 2, 3] in if x < 2 then f (x, y) else g y handle Div => 0 end";
+        let expr = parse(source).unwrap();
+        assert_eq!(format_expr(&expr, 100), source);
+        assert_eq!(format_expr(&expr, 40), concat!(
+            "let\n",
+            "  val x = 1\n",
+            "  val y = [x, 2, 3]\n",
+            "in\n",
+            "  if x < 2 then\n",
+            "    f (x, y)\n",
+            "  else\n",
+            "    g y handle Div => 0\n",
+            "end",
+        ));
+        assert_eq!(format_expr(&expr, 20), concat!(
+            "let\n",
+            "  val x = 1\n",
+            "  val y = [x, 2, 3]\n",
+            "in\n",
+            "  if x < 2 then\n",
+            "    f (x, y)\n",
+            "  else\n",
+            "    g y\n",
+            "      handle Div =>\n",
+            "        0\n",
+            "end",
+        ));
+        // the same tree at every width, and formatting twice changes nothing
+        for width in [1, 10, 20, 40, 80] {
+            let formatted = format_expr(&expr, width);
+            assert_eq!(parse(&formatted).unwrap(), expr, "{}", formatted);
+            assert_eq!(format_expr(&parse(&formatted).unwrap(), width), formatted);
+        }
+        for source in [
+            "~1 - ~(x + 1) * f (~2)",
+            "(raise Fail 1) handle Fail (Some n) => n | Div => raise Div | _ => 0",
+            "(while not (done ()) do (print 1; step ())) handle Stop => ()",
+            "#x {x = {y = 1, x = f a}, y = #y p} handle Fail {code = c, why} => why | Fail {z = _} => 0",
+            "fn x => if a then b else if c then d else ((let val q = 1 in q end) : int) + 1",
+        ] {
+            let expr = parse(source).unwrap();
+            for width in [1, 16, 80] {
+                assert_eq!(parse(&format_expr(&expr, width)).unwrap(), expr, "{}", format_expr(&expr, width));
+            }
+        }
+
+        let source = "datatype t = A | B of int\nfun even n = if n = 0 then true else odd (n - 1) \
+            and odd n = if n = 0 then false else even (n - 1)\n\
+            print 1;\nval x = ~1 :: #a {a = [2]} : int list\neven 4";
+        let program = parse_program(source).unwrap();
+        let formatted = format_program(&program, 60);
+        assert_eq!(formatted, concat!(
+            "datatype t = A | B of int\n",
+            "fun even n = if n = 0 then true else odd (n - 1)\n",
+            "and odd n = if n = 0 then false else even (n - 1)\n",
+            "print 1;\n",
+            "val x = ~1 :: #a {a = [2]} : int list\n",
+            "even 4\n",
+        ));
+        assert_eq!(parse_program(&formatted).unwrap(), program);
+    }
+}
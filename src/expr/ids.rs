@@ -0,0 +1,520 @@
+use std::collections::HashMap;
+use combine::StreamOnce;
+use combine::stream::Positioned;
+
+use crate::lexer::{Delimiter, Direction, Reserved, Span, Token, Tokenizer};
+use crate::expr::{Expr};
+
+// ids are handed out in pre-order so the same tree always gets the same ids,
+// no matter which path through the parser produced it
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash)]
+pub struct NodeId(pub u32);
+
+#[derive(Debug, Clone, Default)]
+pub struct NodeTable {
+    spans: Vec<Span>,
+    ids: HashMap<Span, NodeId>,
+}
+
+impl NodeTable {
+    // aligns the (non space) tokens of `source` with the tree that was parsed
+    // from it, every token belongs to exactly one node so a node's span runs
+    // from its first token to its last one
+    pub fn new<'a>(source: &'a str, expr: &Expr<'a>) -> Option<NodeTable> {
+        NodeTable::of_tokens(Cursor::lex(source)?, expr)
+    }
+    // the same for a tree parsed from some other tokens than the source's,
+    // like the ones error recovery made up
+    pub(crate) fn of_tokens<'a>(tokens: Vec<(Token<'a>, Span)>, expr: &Expr<'a>) -> Option<NodeTable> {
+        let mut cursor = Cursor { tokens, next: 0 };
+        let mut table = NodeTable::default();
+        table.visit(expr, &mut cursor)?;
+        match cursor.peek() {
+            None => Some(table),
+            Some(_) => None,
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.spans.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+    pub fn span(&self, id: NodeId) -> Option<Span> {
+        self.spans.get(id.0 as usize).cloned()
+    }
+    // the outermost node covering exactly `span`
+    pub fn id(&self, span: Span) -> Option<NodeId> {
+        self.ids.get(&span).cloned()
+    }
+    pub fn iter<'t>(&'t self) -> impl Iterator<Item = (NodeId, Span)> + 't {
+        self.spans.iter().enumerate().map(|(i, span)| (NodeId(i as u32), *span))
+    }
+    fn visit<'a>(&mut self, expr: &Expr<'a>, cursor: &mut Cursor<'a>) -> Option<Span> {
+        use Expr::*;
+        use Token::Keyword;
+        let id = NodeId(self.spans.len() as u32);
+        self.spans.push(Span::new(0, 0));
+        let span = match expr {
+            Var(_) => cursor.name()?,
+            Lit(_) => cursor.expect(|t| matches!(t, Token::Lit(_)))?,
+            Error(_) => cursor.expect(|t| matches!(t, Token::Error(_)))?,
+            Unary{ child, .. } => {
+                let start = cursor.keyword()?;
+                let end = self.visit(child, cursor)?;
+                join(start, end)
+            },
+            Binary{ left, right, .. } => {
+                let start = self.visit(left, cursor)?;
+                cursor.keyword()?;
+                let end = self.visit(right, cursor)?;
+                join(start, end)
+            },
+            Cons{ head, tail } => {
+                let start = self.visit(head, cursor)?;
+                cursor.token(Keyword(Reserved::Cons))?;
+                let end = self.visit(tail, cursor)?;
+                join(start, end)
+            },
+            IfThenElse{ condition, if_branch, else_branch } => {
+                let start = cursor.token(Keyword(Reserved::If))?;
+                self.visit(condition, cursor)?;
+                cursor.token(Keyword(Reserved::Then))?;
+                self.visit(if_branch, cursor)?;
+                cursor.token(Keyword(Reserved::Else))?;
+                let end = self.visit(else_branch, cursor)?;
+                join(start, end)
+            },
+            While{ condition, body } => {
+                let start = cursor.token(Keyword(Reserved::While))?;
+                self.visit(condition, cursor)?;
+                cursor.token(Keyword(Reserved::Do))?;
+                let end = self.visit(body, cursor)?;
+                join(start, end)
+            },
+            Tuple{ fst, snd } => {
+                let start = cursor.delim(Delimiter::Paren(Direction::Left))?;
+                self.visit(fst, cursor)?;
+                cursor.delim(Delimiter::Comma)?;
+                self.visit(snd, cursor)?;
+                let end = cursor.delim(Delimiter::Paren(Direction::Right))?;
+                join(start, end)
+            },
+            Let{ bindings, body } => {
+                let start = cursor.token(Keyword(Reserved::Let))?;
+                for (_, binder) in bindings {
+                    cursor.token(Keyword(Reserved::Val))?;
+                    cursor.name()?;
+                    match binder {
+                        // `let val x : ty = e` keeps its type on `e`, the node
+                        // runs from the colon to the end of `e`
+                        Annot{ expr, .. } if cursor.peek() == Some(&Keyword(Reserved::Colon)) => {
+                            let annot = NodeId(self.spans.len() as u32);
+                            self.spans.push(Span::new(0, 0));
+                            let colon = cursor.token(Keyword(Reserved::Colon))?;
+                            cursor.ty()?;
+                            cursor.token(Keyword(Reserved::Equal))?;
+                            let span = join(colon, self.visit(expr, cursor)?);
+                            self.spans[annot.0 as usize] = span;
+                            self.ids.entry(span).or_insert(annot);
+                        },
+                        _ => {
+                            cursor.token(Keyword(Reserved::Equal))?;
+                            self.visit(binder, cursor)?;
+                        },
+                    }
+                }
+                cursor.token(Keyword(Reserved::In))?;
+                self.visit(body, cursor)?;
+                let end = cursor.token(Keyword(Reserved::End))?;
+                join(start, end)
+            },
+            Lambda{ body, .. } => {
+                let start = cursor.token(Keyword(Reserved::Fn))?;
+                cursor.name()?;
+                cursor.token(Keyword(Reserved::Arrow))?;
+                let end = self.visit(body, cursor)?;
+                join(start, end)
+            },
+            // `a plus b` for a name made infix, its operands go either side
+            // of it. the ids stay in pre-order, the name's and the pair's
+            // are handed out before the operands'
+            App{ left, right } => match (&**left, &**right) {
+                (Var(name), Tuple{ fst, snd }) if cursor.peek() != Some(&Token::Name(name)) => {
+                    let (operator, pair) = (self.spans.len(), self.spans.len() + 1);
+                    self.spans.push(Span::new(0, 0));
+                    self.spans.push(Span::new(0, 0));
+                    let start = self.visit(fst, cursor)?;
+                    let name = cursor.expect(|t| matches!(t, Token::Name(_) | Keyword(_)))?;
+                    let end = self.visit(snd, cursor)?;
+                    for (at, span) in [(operator, name), (pair, join(start, end))] {
+                        self.spans[at] = span;
+                        self.ids.entry(span).or_insert(NodeId(at as u32));
+                    }
+                    join(start, end)
+                },
+                _ => {
+                    let start = self.visit(left, cursor)?;
+                    let end = self.visit(right, cursor)?;
+                    join(start, end)
+                },
+            },
+            Seq(sequence) => {
+                let start = cursor.delim(Delimiter::Paren(Direction::Left))?;
+                for (i, expr) in sequence.iter().enumerate() {
+                    if 0 < i {
+                        cursor.delim(Delimiter::Semicolon)?;
+                    }
+                    self.visit(expr, cursor)?;
+                }
+                let end = cursor.delim(Delimiter::Paren(Direction::Right))?;
+                join(start, end)
+            },
+            List(elements) => {
+                if cursor.peek() == Some(&Keyword(Reserved::Nil)) {
+                    cursor.token(Keyword(Reserved::Nil))?
+                } else {
+                    let start = cursor.delim(Delimiter::Bracket(Direction::Left))?;
+                    for (i, expr) in elements.iter().enumerate() {
+                        if 0 < i {
+                            cursor.delim(Delimiter::Comma)?;
+                        }
+                        self.visit(expr, cursor)?;
+                    }
+                    let end = cursor.delim(Delimiter::Bracket(Direction::Right))?;
+                    join(start, end)
+                }
+            },
+            Funs{ defs, body } => {
+                let start = cursor.token(Keyword(Reserved::Let))?;
+                if cursor.peek() == Some(&Keyword(Reserved::Val)) {
+                    // let val rec f = fn x => e in ... end
+                    cursor.token(Keyword(Reserved::Val))?;
+                    cursor.token(Keyword(Reserved::Rec))?;
+                    cursor.name()?;
+                    cursor.token(Keyword(Reserved::Equal))?;
+                    cursor.token(Keyword(Reserved::Fn))?;
+                    cursor.name()?;
+                    cursor.token(Keyword(Reserved::Arrow))?;
+                    self.visit(&defs.first()?.body, cursor)?;
+                } else {
+                    cursor.token(Keyword(Reserved::Fun))?;
+                    for (i, def) in defs.iter().enumerate() {
+                        if 0 < i {
+                            cursor.token(Keyword(Reserved::And))?;
+                        }
+                        cursor.name()?;
+                        cursor.name()?;
+                        cursor.token(Keyword(Reserved::Equal))?;
+                        self.visit(&def.body, cursor)?;
+                    }
+                }
+                cursor.token(Keyword(Reserved::In))?;
+                self.visit(body, cursor)?;
+                let end = cursor.token(Keyword(Reserved::End))?;
+                join(start, end)
+            },
+            Annot{ expr, .. } => {
+                let start = self.visit(expr, cursor)?;
+                cursor.token(Keyword(Reserved::Colon))?;
+                join(start, cursor.ty()?)
+            },
+            Construct{ argument, .. } => {
+                let start = cursor.name()?;
+                match argument {
+                    Some(argument) => join(start, self.visit(argument, cursor)?),
+                    None => start,
+                }
+            },
+            Raise(expr) => {
+                let start = cursor.token(Keyword(Reserved::Raise))?;
+                join(start, self.visit(expr, cursor)?)
+            },
+            Handle{ expr, rules } => {
+                let start = self.visit(expr, cursor)?;
+                cursor.token(Keyword(Reserved::Handle))?;
+                let mut end = start;
+                for (i, rule) in rules.iter().enumerate() {
+                    if 0 < i {
+                        cursor.token(Keyword(Reserved::Bar))?;
+                    }
+                    cursor.pattern()?;
+                    cursor.token(Keyword(Reserved::Arrow))?;
+                    end = self.visit(&rule.body, cursor)?;
+                }
+                join(start, end)
+            },
+            Record(fields) => {
+                let start = cursor.delim(Delimiter::Brace(Direction::Left))?;
+                for (i, (_, expr)) in fields.iter().enumerate() {
+                    if 0 < i {
+                        cursor.delim(Delimiter::Comma)?;
+                    }
+                    cursor.name()?;
+                    cursor.token(Keyword(Reserved::Equal))?;
+                    self.visit(expr, cursor)?;
+                }
+                let end = cursor.delim(Delimiter::Brace(Direction::Right))?;
+                join(start, end)
+            },
+            Select{ record, .. } => {
+                let start = cursor.token(Keyword(Reserved::Select))?;
+                cursor.name()?;
+                join(start, self.visit(record, cursor)?)
+            },
+        };
+        self.spans[id.0 as usize] = span;
+        self.ids.entry(span).or_insert(id);
+        Some(span)
+    }
+}
+
+fn join(start: Span, end: Span) -> Span {
+    Span::new(start.start, end.end)
+}
+
+struct Cursor<'a> {
+    tokens: Vec<(Token<'a>, Span)>,
+    next: usize,
+}
+
+impl<'a> Cursor<'a> {
+    // the non space tokens of `source`
+    fn lex(source: &'a str) -> Option<Vec<(Token<'a>, Span)>> {
+        let mut tokenizer = Tokenizer::new(source);
+        let mut tokens = vec![];
+        loop {
+            let start = tokenizer.position();
+            match tokenizer.uncons().ok()? {
+                Token::EndOfFile => return Some(tokens),
+                Token::Space(_) => {},
+                tok => tokens.push((tok, Span::new(start, tokenizer.position()))),
+            }
+        }
+    }
+    fn peek(&self) -> Option<&Token<'a>> {
+        self.tokens.get(self.next).map(|(tok, _)| tok)
+    }
+    fn expect<F>(&mut self, pred: F) -> Option<Span>
+    where F: FnOnce(&Token<'a>) -> bool
+    {
+        let (tok, span) = self.tokens.get(self.next)?;
+        if pred(tok) {
+            self.next += 1;
+            Some(*span)
+        } else {
+            None
+        }
+    }
+    fn token(&mut self, expected: Token<'a>) -> Option<Span> {
+        self.expect(|t| *t == expected)
+    }
+    fn delim(&mut self, expected: Delimiter) -> Option<Span> {
+        self.token(Token::Delim(expected))
+    }
+    fn name(&mut self) -> Option<Span> {
+        self.expect(|t| matches!(t, Token::Name(_)))
+    }
+    fn keyword(&mut self) -> Option<Span> {
+        self.expect(|t| matches!(t, Token::Keyword(_)))
+    }
+    // a type is names, `*`, `->` and balanced parens, it ends at the first
+    // token that can not continue it
+    fn ty(&mut self) -> Option<Span> {
+        let mut depth = 0;
+        let mut end = None;
+        loop {
+            match self.peek() {
+                Some(Token::Name(_)) | Some(Token::Keyword(Reserved::Mult))
+                | Some(Token::Keyword(Reserved::TypeArrow)) => {},
+                Some(Token::Delim(Delimiter::Paren(Direction::Left))) => depth += 1,
+                Some(Token::Delim(Delimiter::Paren(Direction::Right))) if 0 < depth => depth -= 1,
+                _ => return end,
+            }
+            end = self.expect(|_| true);
+        }
+    }
+    // a pattern is names, `_`, parens and the braces, commas and `=` of
+    // records, it ends at the `=>` of its rule
+    fn pattern(&mut self) -> Option<Span> {
+        let mut end = None;
+        while self.peek() != Some(&Token::Keyword(Reserved::Arrow)) {
+            end = self.expect(|t| matches!(t,
+                Token::Name(_) | Token::Keyword(Reserved::Wildcard) | Token::Keyword(Reserved::Equal)
+                | Token::Delim(Delimiter::Paren(_)) | Token::Delim(Delimiter::Brace(_)) | Token::Delim(Delimiter::Comma)
+            ));
+            end?;
+        }
+        end
+    }
+}
+
+// every node of a tree by its id, for looking up many of them without
+// walking the tree for each as `Expr::node` does
+#[derive(Debug, Clone)]
+pub struct NodeIndex<'e, 'a> {
+    nodes: Vec<&'e Expr<'a>>,
+    // by address, a node is told apart from an equal one elsewhere in the
+    // tree
+    ids: HashMap<*const Expr<'a>, NodeId>,
+}
+
+impl<'e, 'a> NodeIndex<'e, 'a> {
+    pub fn new(expr: &'e Expr<'a>) -> NodeIndex<'e, 'a> {
+        fn walk<'e, 'a>(expr: &'e Expr<'a>, index: &mut NodeIndex<'e, 'a>) {
+            index.ids.insert(expr as *const Expr<'a>, NodeId(index.nodes.len() as u32));
+            index.nodes.push(expr);
+            for child in expr.children() {
+                walk(child, index)
+            }
+        }
+        let mut index = NodeIndex { nodes: vec![], ids: HashMap::new() };
+        walk(expr, &mut index);
+        index
+    }
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+    pub fn get(&self, id: NodeId) -> Option<&'e Expr<'a>> {
+        self.nodes.get(id.0 as usize).copied()
+    }
+    // the id of `node`, which has to be a subtree of the indexed tree itself
+    // and not a copy of one
+    pub fn id(&self, node: &Expr<'a>) -> Option<NodeId> {
+        self.ids.get(&(node as *const Expr<'a>)).copied()
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (NodeId, &'e Expr<'a>)> + '_ {
+        self.nodes.iter().enumerate().map(|(i, node)| (NodeId(i as u32), *node))
+    }
+}
+
+impl<'a> Expr<'a> {
+    // the subtree with the given pre-order id
+    pub fn node(&self, id: NodeId) -> Option<&Expr<'a>> {
+        fn find<'e, 'a>(expr: &'e Expr<'a>, target: u32, next: &mut u32) -> Option<&'e Expr<'a>> {
+            if *next == target {
+                return Some(expr)
+            }
+            *next += 1;
+            expr.children().into_iter().find_map(|child| find(child, target, next))
+        }
+        find(self, id.0, &mut 0)
+    }
+    // how many ids the subtree takes up
+    pub fn size(&self) -> u32 {
+        1 + self.children().into_iter().map(Expr::size).sum::<u32>()
+    }
+    // the direct subtrees in source order, which is also id order
+    pub fn children(&self) -> Vec<&Expr<'a>> {
+        use Expr::*;
+        match self {
+            Var(_) | Lit(_) | Error(_) => vec![],
+            Unary{ child, .. } => vec![child],
+            Binary{ left, right, .. } => vec![left, right],
+            Cons{ head, tail } => vec![head, tail],
+            IfThenElse{ condition, if_branch, else_branch } => vec![condition, if_branch, else_branch],
+            While{ condition, body } => vec![condition, body],
+            Tuple{ fst, snd } => vec![fst, snd],
+            Let{ bindings, body } => bindings.iter().map(|(_, binder)| binder).chain(Some(&**body)).collect(),
+            Lambda{ body, .. } => vec![body],
+            App{ left, right } => vec![left, right],
+            Seq(sequence) => sequence.iter().collect(),
+            List(elements) => elements.iter().collect(),
+            Funs{ defs, body } => defs.iter().map(|def| &*def.body).chain(Some(&**body)).collect(),
+            Annot{ expr, .. } => vec![expr],
+            Construct{ argument, .. } => argument.iter().map(|argument| &**argument).collect(),
+            Raise(expr) => vec![expr],
+            Handle{ expr, rules } => {
+                Some(&**expr).into_iter().chain(rules.iter().map(|rule| &*rule.body)).collect()
+            },
+            Record(fields) => fields.iter().map(|(_, expr)| expr).collect(),
+            Select{ record, .. } => vec![record],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::{parse, parse_indexed};
+
+    #[test]
+    fn node_table_unit() {
+        let source = "let val x = (1, 2) in fst x + [3] end";
+        let expr = parse(source).unwrap();
+        let table = NodeTable::new(source, &expr).unwrap();
+        let spans: Vec<&str> = table.iter().map(|(_, span)| &source[span.start..span.end]).collect();
+        assert_eq!(spans, vec![
+            source, "(1, 2)", "1", "2", "fst x + [3]", "fst x", "x", "[3]", "3"
+        ]);
+        for (id, span) in table.iter() {
+            let node = expr.node(id).unwrap();
+            assert_eq!(node.to_string(), parse(&source[span.start..span.end]).unwrap().to_string());
+            assert_eq!(table.id(span), Some(id));
+        }
+    }
+
+    #[test]
+    fn node_table_funs_unit() {
+        let source = "let fun f n = g n and g n = n in (f 1; f (2)) end";
+        let (expr, table) = parse_indexed(source).unwrap();
+        assert_eq!(table.len(), 13);
+        assert_eq!(table.span(NodeId(11)), Some(Span::new(41, 44)));
+        assert_eq!(expr.node(NodeId(12)).unwrap().to_string(), "2");
+    }
+
+    #[test]
+    fn node_table_annot_unit() {
+        let source = "let val x : int list = [1] in (x : int list) end";
+        let (expr, table) = parse_indexed(source).unwrap();
+        let spans: Vec<&str> = table.iter().map(|(_, span)| &source[span.start..span.end]).collect();
+        assert_eq!(spans, vec![source, ": int list = [1]", "[1]", "1", "(x : int list)", "x : int list", "x"]);
+        assert_eq!(expr.node(NodeId(5)).unwrap().to_string(), "x : int list");
+    }
+
+    #[test]
+    fn node_index_unit() {
+        let source = "let fun f n = n + 1 in f (f 1) end";
+        let (expr, table) = parse_indexed(source).unwrap();
+        let index = NodeIndex::new(&expr);
+        assert_eq!(index.len(), table.len());
+        for (id, node) in index.iter() {
+            assert_eq!(Some(node), expr.node(id));
+            assert_eq!(index.id(node), Some(id));
+        }
+        // the two `1`s are equal but not the same node
+        let ones: Vec<NodeId> = index.iter().filter(|(_, node)| node.to_string() == "1").map(|(id, _)| id).collect();
+        assert_eq!(ones, vec![NodeId(3), NodeId(9)]);
+        assert_eq!(index.id(&Expr::Lit(crate::lexer::Literal::Integer(1))), None);
+        assert_eq!(index.get(NodeId(10)), None);
+    }
+
+    #[test]
+    fn node_table_vals_unit() {
+        let source = "let val x = 1 val y = x in x + y end";
+        let (expr, table) = parse_indexed(source).unwrap();
+        let spans: Vec<&str> = table.iter().map(|(_, span)| &source[span.start..span.end]).collect();
+        assert_eq!(spans, vec![source, "1", "x", "x + y", "x", "y"]);
+        assert_eq!(expr.eval().unwrap().to_string(), "2");
+    }
+
+    #[test]
+    fn node_table_infix_unit() {
+        use crate::expr::{Fixity, with_operators};
+        use crate::expr::operators::{Assoc, Operators};
+
+        let mut operators = Operators::default();
+        operators.declare(&Fixity{ assoc: Assoc::Left, precedence: 5, names: vec!["plus"] });
+        let source = "f (a plus b) plus plus (c, d)";
+        let (expr, table) = with_operators(operators, || parse_indexed(source)).unwrap();
+        let spans: Vec<&str> = table.iter().map(|(_, span)| &source[span.start..span.end]).collect();
+        assert_eq!(spans, vec![
+            source, "plus", source, "f (a plus b)", "f", "(a plus b)", "a plus b", "plus", "a plus b", "a", "b",
+            "plus (c, d)", "plus", "(c, d)", "c", "d",
+        ]);
+        assert_eq!(expr.node(NodeId(11)).unwrap().to_string(), "plus (c, d)");
+    }
+}
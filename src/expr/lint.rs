@@ -0,0 +1,213 @@
+use std::fmt;
+
+use crate::lexer::{Span};
+use crate::error::{snippet};
+use crate::locale::{message};
+use crate::expr::{Expr};
+use crate::expr::ids::{NodeId, NodeTable};
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Lint<'a> {
+    // `let val x = a in let val x = b in ...`, `a` is computed and never read
+    Shadowed(&'a str),
+    // `let val f = fn n => ... f ...` where nothing outside binds `f`, the
+    // binding can not see itself without `fun` or `val rec`
+    SelfReference(&'a str),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Warning<'a> {
+    pub source: &'a str,
+    pub lint: Lint<'a>,
+    pub span: Span,
+}
+
+impl<'a> Lint<'a> {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Lint::Shadowed(_) => "W0001",
+            Lint::SelfReference(_) => "W0002",
+        }
+    }
+}
+
+impl<'a> fmt::Display for Warning<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (line, col) = self.span.line_col(self.source);
+        write!(f, "{}: ", message("warning-at", &[&line, &col]))?;
+        match self.lint {
+            Lint::Shadowed(name) => writeln!(f, "{}", message("shadowed", &[&name]))?,
+            Lint::SelfReference(name) => writeln!(f, "{}", message("self-reference", &[&name]))?,
+        }
+        snippet(f, self.source, self.span)?;
+        if let Lint::SelfReference(name) = self.lint {
+            let note = message("self-reference-note", &[&name, &name]);
+            write!(f, "\n{}", message("note", &[&note]))?;
+        }
+        Ok(())
+    }
+}
+
+// the spans point at the value that is thrown away or at the offending use
+pub fn lint<'a>(source: &'a str, expr: &Expr<'a>, table: &NodeTable) -> Vec<Warning<'a>> {
+    let mut walk = Walk { source, table, next: 0, scope: vec![], warnings: vec![] };
+    walk.visit(expr);
+    walk.warnings
+}
+
+struct Walk<'t, 'a> {
+    source: &'a str,
+    table: &'t NodeTable,
+    // the pre-order id of the next node visited
+    next: u32,
+    scope: Vec<&'a str>,
+    warnings: Vec<Warning<'a>>,
+}
+
+impl<'t, 'a> Walk<'t, 'a> {
+    fn warn(&mut self, lint: Lint<'a>, id: NodeId) {
+        if let Some(span) = self.table.span(id) {
+            self.warnings.push(Warning { source: self.source, lint, span })
+        }
+    }
+    fn visit(&mut self, expr: &Expr<'a>) {
+        use Expr::*;
+        self.next += 1;
+        match expr {
+            Let{ bindings, body } => {
+                for (i, (name, binder)) in bindings.iter().enumerate() {
+                    // the binders are the first children, in order
+                    let at = self.next;
+                    // the binding right after, in this `let` or in one that
+                    // is its body
+                    let next = match (bindings.get(i + 1), ungroup(body)) {
+                        (Some(next), _) => Some(next),
+                        (None, Let{ bindings: inner, .. }) => inner.first(),
+                        (None, _) => None,
+                    };
+                    if let Some((inner, rebound)) = next {
+                        if inner == name && !rebound.free_variables().contains(name) {
+                            self.warn(Lint::Shadowed(name), NodeId(at))
+                        }
+                    }
+                    if !self.scope.contains(name) {
+                        for found in uses(binder, name, at) {
+                            self.warn(Lint::SelfReference(name), found)
+                        }
+                    }
+                    self.visit(binder);
+                    self.scope.push(name);
+                }
+                self.visit(body);
+                self.scope.truncate(self.scope.len() - bindings.len());
+            },
+            Lambda{ name, body } => {
+                self.scope.push(name);
+                self.visit(body);
+                self.scope.pop();
+            },
+            Funs{ defs, body } => {
+                self.scope.extend(defs.iter().map(|def| def.name));
+                for def in defs {
+                    self.scope.push(def.argument);
+                    self.visit(&def.body);
+                    self.scope.pop();
+                }
+                self.visit(body);
+                self.scope.truncate(self.scope.len() - defs.len());
+            },
+            _ => {
+                for child in expr.children() {
+                    self.visit(child)
+                }
+            },
+        }
+    }
+}
+
+fn ungroup<'e, 'a>(expr: &'e Expr<'a>) -> &'e Expr<'a> {
+    match expr {
+        Expr::Seq(sequence) if sequence.len() == 1 => ungroup(&sequence[0]),
+        _ => expr,
+    }
+}
+
+// ids of the free occurrences of `name` in `expr`, which has id `start`
+fn uses<'a>(expr: &Expr<'a>, name: &str, start: u32) -> Vec<NodeId> {
+    fn go<'a>(expr: &Expr<'a>, name: &str, next: &mut u32, found: &mut Vec<NodeId>) {
+        use Expr::*;
+        let id = *next;
+        *next += 1;
+        match expr {
+            Var(var) if *var == name => found.push(NodeId(id)),
+            // past a rebinding of `name` nothing refers to the outer one,
+            // but the ids still have to be counted
+            Let{ bindings, body } => {
+                // what comes after a rebinding still has to be counted
+                let (mut shadowed, mut hidden) = (false, vec![]);
+                for (bound, binder) in bindings {
+                    go(binder, name, next, if shadowed { &mut hidden } else { &mut *found });
+                    shadowed = shadowed || *bound == name;
+                }
+                go(body, name, next, if shadowed { &mut hidden } else { found })
+            },
+            Lambda{ name: bound, body } if *bound == name => go(body, name, next, &mut vec![]),
+            Funs{ defs, .. } if defs.iter().any(|def| def.name == name) => {
+                for child in expr.children() {
+                    go(child, name, next, &mut vec![])
+                }
+            },
+            Funs{ defs, body } => {
+                for def in defs {
+                    let found = if def.argument == name { &mut vec![] } else { &mut *found };
+                    go(&def.body, name, next, found)
+                }
+                go(body, name, next, found)
+            },
+            _ => {
+                for child in expr.children() {
+                    go(child, name, next, found)
+                }
+            },
+        }
+    }
+    let mut found = vec![];
+    go(expr, name, &mut start.clone(), &mut found);
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::{parse_indexed};
+
+    fn warnings(source: &str) -> Vec<(Lint<'_>, &str)> {
+        let (expr, table) = parse_indexed(source).unwrap();
+        lint(source, &expr, &table).into_iter()
+            .map(|w| (w.lint, &source[w.span.start..w.span.end]))
+            .collect()
+    }
+
+    #[test]
+    fn lint_unit() {
+        assert_eq!(
+            warnings("let val x = 1 + 1 in let val x = 2 in x end end"),
+            vec![(Lint::Shadowed("x"), "1 + 1")]
+        );
+        // reading the old value is not a mistake
+        assert!(warnings("let val x = 1 in let val x = x + 1 in x end end").is_empty());
+        assert_eq!(
+            warnings("let val fact = fn n => if n = 0 then 1 else n * fact (n - 1) in fact 5 end"),
+            vec![(Lint::SelfReference("fact"), "fact")]
+        );
+        // an outer binding makes it a deliberate wrapper
+        assert!(warnings("fn f => let val f = fn n => f (n + 1) in f 0 end").is_empty());
+        assert!(warnings("let val f = fn f => f 1 in f end").is_empty());
+        assert!(warnings("let val rec f = fn n => f n in f end").is_empty());
+
+        let source = "let val y = (1)\nin let val y = 2 in y end end";
+        let (expr, table) = parse_indexed(source).unwrap();
+        let rendered = lint(source, &expr, &table)[0].to_string();
+        assert!(rendered.starts_with("warning at 1:13: `y` is shadowed before it is ever used\n  | let val y = (1)\n  |             ^^^"));
+    }
+}
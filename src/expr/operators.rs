@@ -0,0 +1,161 @@
+use crate::lexer::{Reserved, Token};
+use crate::expr::{BinaryOp, Expr, Fixity};
+
+// how a level groups several of its operators in a row
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Assoc {
+    // `a - b - c` is `(a - b) - c`
+    Left,
+    // `a :: b :: c` is `a :: (b :: c)`
+    Right,
+    // `a < b < c` is an error
+    Neither,
+}
+
+// what an infix operator builds out of its operands
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Infix<'a> {
+    Binary(BinaryOp),
+    Cons,
+    // a name a fixity declaration made infix, `a plus b` is `plus (a, b)`
+    Apply(&'a str),
+}
+
+impl<'a> Infix<'a> {
+    pub fn join(self, left: Expr<'a>, right: Expr<'a>) -> Expr<'a> {
+        match self {
+            Infix::Binary(operation) => Expr::Binary{ left: Box::new(left), operation, right: Box::new(right) },
+            Infix::Cons => Expr::Cons{ head: Box::new(left), tail: Box::new(right) },
+            Infix::Apply(name) => Expr::App {
+                left: Box::new(Expr::Var(name)),
+                right: Box::new(Expr::Tuple{ fst: Box::new(left), snd: Box::new(right) }),
+            },
+        }
+    }
+}
+
+// operators that bind equally tightly, each keyword with what it builds
+// and the names fixity declarations put here
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Level {
+    // what `infix` declarations call the level, `BinaryOp::precedence` for
+    // the usual ones
+    pub precedence: usize,
+    pub assoc: Assoc,
+    pub operators: Vec<(Reserved, Infix<'static>)>,
+    pub names: Vec<String>,
+}
+
+impl Level {
+    pub fn new(precedence: usize, assoc: Assoc, operators: &[(Reserved, Infix<'static>)]) -> Level {
+        Level { precedence, assoc, operators: operators.to_vec(), names: vec![] }
+    }
+    // the operator `token` is on this level, if any
+    pub fn infix<'a>(&self, token: &Token<'a>) -> Option<Infix<'a>> {
+        match token {
+            Token::Keyword(keyword) => self.operators.iter().find(|(k, _)| k == keyword).map(|(_, infix)| *infix),
+            Token::Name(name) if self.names.iter().any(|n| n == name) => Some(Infix::Apply(name)),
+            _ => None,
+        }
+    }
+}
+
+// the binary operators the parser knows, from the loosest level to the
+// tightest. looser than all of them are annotations and `handle`, tighter
+// are the unary operators and application. a new operator is an entry in
+// a level, `infix` declarations add names to the table as a program is
+// parsed and `expr::with_operators` parses with a table changed by hand.
+// `Expr::to_source` still puts parentheses where the usual table needs them
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Operators {
+    pub levels: Vec<Level>,
+}
+
+impl Operators {
+    // the names of `fixity` infix on the level of its precedence, made when
+    // there is none. `infixr` levels go after `infix` ones of the same
+    // precedence, binding tighter
+    pub fn declare(&mut self, fixity: &Fixity) {
+        for level in &mut self.levels {
+            level.names.retain(|name| !fixity.names.contains(&name.as_str()))
+        }
+        let names = fixity.names.iter().map(|name| name.to_string());
+        let (precedence, assoc) = (fixity.precedence, fixity.assoc);
+        match self.levels.iter_mut().find(|level| level.precedence == precedence && level.assoc == assoc) {
+            Some(level) => level.names.extend(names),
+            None => {
+                let key = |precedence, assoc| (precedence, assoc == Assoc::Right);
+                let at = self.levels.iter()
+                    .position(|level| key(precedence, assoc) < key(level.precedence, level.assoc))
+                    .unwrap_or(self.levels.len());
+                self.levels.insert(at, Level { names: names.collect(), ..Level::new(precedence, assoc, &[]) })
+            },
+        }
+    }
+}
+
+impl Default for Operators {
+    fn default() -> Operators {
+        use BinaryOp::*;
+        use Infix::Binary;
+        let levels = vec![
+            Level::new(1, Assoc::Left, &[(Reserved::OrElse, Binary(OrElse))]),
+            Level::new(2, Assoc::Left, &[(Reserved::AndAlso, Binary(AndAlso))]),
+            Level::new(3, Assoc::Neither, &[
+                (Reserved::Equal, Binary(Equal)),
+                (Reserved::NotEqual, Binary(NotEqual)),
+                (Reserved::LessThan, Binary(LessThan)),
+                (Reserved::LessEqual, Binary(LessEqual)),
+                (Reserved::GreaterThan, Binary(GreaterThan)),
+                (Reserved::GreaterEqual, Binary(GreaterEqual)),
+            ]),
+            Level::new(4, Assoc::Right, &[(Reserved::Cons, Infix::Cons)]),
+            Level::new(5, Assoc::Left, &[(Reserved::Add, Binary(Add)), (Reserved::Sub, Binary(Sub))]),
+            Level::new(6, Assoc::Left, &[
+                (Reserved::Mult, Binary(Mult)),
+                (Reserved::Div, Binary(Div)),
+                (Reserved::Mod, Binary(Mod)),
+                (Reserved::Divide, Binary(Divide)),
+            ]),
+        ];
+        Operators { levels }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::{parse, with_operators};
+
+    #[test]
+    fn operators_unit() {
+        // the usual table is the one `to_source` prints for
+        for (index, level) in Operators::default().levels.iter().enumerate() {
+            assert_eq!(level.precedence, index + 1);
+            for (_, infix) in &level.operators {
+                if let Infix::Binary(operation) = infix {
+                    assert_eq!(operation.precedence(), level.precedence);
+                }
+            }
+        }
+
+        // `+` binding tighter than `*`
+        let mut operators = Operators::default();
+        operators.levels.swap(4, 5);
+        let expr = with_operators(operators, || parse("1 + 2 * 3 :: []").map(|expr| expr.to_source()));
+        assert_eq!(expr, Ok("(1 + 2) * 3 :: []".to_string()));
+        assert_eq!(parse("1 + 2 * 3").map(|expr| expr.to_source()), Ok("1 + 2 * 3".to_string()));
+
+        // `andalso` and `orelse` on one level, and `mod` gone
+        let mut operators = Operators::default();
+        let conj = operators.levels.remove(1);
+        operators.levels[0].operators.extend(conj.operators);
+        operators.levels[4].operators.retain(|(keyword, _)| *keyword != Reserved::Mod);
+        with_operators(operators, || {
+            let expr = parse("a orelse b andalso c").map(|expr| expr.to_source());
+            assert_eq!(expr, Ok("(a orelse b) andalso c".to_string()));
+            assert!(parse("a mod b").is_err());
+        });
+        assert!(parse("a mod b").is_ok());
+    }
+}
@@ -0,0 +1,331 @@
+use std::fmt;
+use crate::lexer::{Literal, Span};
+use crate::expr::{UnaryOp, BinaryOp, Definition, Expr, Pattern, Rule, TypeExpr};
+use crate::runtime::real::{Real};
+
+// `Expr` borrows its names from the source it was parsed from, these mirror
+// types own their strings so a tree can outlive its source
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash)]
+pub enum OwnedLiteral {
+    Unit,
+    Integer(i64),
+    Boolean(bool),
+    String(String),
+    Char(char),
+    Real(Real),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct OwnedDefinition {
+    pub name: String,
+    pub argument: String,
+    pub body: Box<OwnedExpr>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum OwnedPattern {
+    Wildcard,
+    Var(String),
+    Construct {
+        name: String,
+        argument: Option<Box<OwnedPattern>>,
+    },
+    Record(Vec<(String, OwnedPattern)>),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct OwnedRule {
+    pub pattern: OwnedPattern,
+    pub body: Box<OwnedExpr>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum OwnedExpr {
+    Var(String),
+    Lit(OwnedLiteral),
+    Unary {
+        operation: UnaryOp,
+        child: Box<OwnedExpr>,
+    },
+    Binary {
+        left: Box<OwnedExpr>,
+        operation: BinaryOp,
+        right: Box<OwnedExpr>,
+    },
+    IfThenElse {
+        condition: Box<OwnedExpr>,
+        if_branch: Box<OwnedExpr>,
+        else_branch: Box<OwnedExpr>,
+    },
+    Tuple {
+        fst: Box<OwnedExpr>,
+        snd: Box<OwnedExpr>,
+    },
+    Let {
+        bindings: Vec<(String, OwnedExpr)>,
+        body: Box<OwnedExpr>,
+    },
+    Lambda {
+        name: String,
+        body: Box<OwnedExpr>,
+    },
+    App {
+        left: Box<OwnedExpr>,
+        right: Box<OwnedExpr>,
+    },
+    Seq(Vec<OwnedExpr>),
+    List(Vec<OwnedExpr>),
+    Cons {
+        head: Box<OwnedExpr>,
+        tail: Box<OwnedExpr>,
+    },
+    Funs {
+        defs: Vec<OwnedDefinition>,
+        body: Box<OwnedExpr>,
+    },
+    Annot {
+        expr: Box<OwnedExpr>,
+        ty: TypeExpr,
+    },
+    Construct {
+        name: String,
+        argument: Option<Box<OwnedExpr>>,
+    },
+    Raise(Box<OwnedExpr>),
+    Handle {
+        expr: Box<OwnedExpr>,
+        rules: Vec<OwnedRule>,
+    },
+    While {
+        condition: Box<OwnedExpr>,
+        body: Box<OwnedExpr>,
+    },
+    Record(Vec<(String, OwnedExpr)>),
+    Select {
+        label: String,
+        record: Box<OwnedExpr>,
+    },
+    Error(Span),
+}
+
+impl<'a> Literal<'a> {
+    pub fn into_owned(self) -> OwnedLiteral {
+        match self {
+            Literal::Unit => OwnedLiteral::Unit,
+            Literal::Integer(i) => OwnedLiteral::Integer(i),
+            Literal::Boolean(b) => OwnedLiteral::Boolean(b),
+            Literal::String(s) => OwnedLiteral::String(s.to_string()),
+            Literal::Char(c) => OwnedLiteral::Char(c),
+            Literal::Real(x) => OwnedLiteral::Real(x),
+        }
+    }
+}
+
+impl OwnedLiteral {
+    pub fn as_literal(&self) -> Literal<'_> {
+        match *self {
+            OwnedLiteral::Unit => Literal::Unit,
+            OwnedLiteral::Integer(i) => Literal::Integer(i),
+            OwnedLiteral::Boolean(b) => Literal::Boolean(b),
+            OwnedLiteral::String(ref s) => Literal::String(s),
+            OwnedLiteral::Char(c) => Literal::Char(c),
+            OwnedLiteral::Real(x) => Literal::Real(x),
+        }
+    }
+}
+
+impl<'a> Definition<'a> {
+    pub fn into_owned(self) -> OwnedDefinition {
+        OwnedDefinition {
+            name: self.name.to_string(),
+            argument: self.argument.to_string(),
+            body: Box::new(self.body.into_owned()),
+        }
+    }
+}
+
+impl OwnedDefinition {
+    pub fn as_definition(&self) -> Definition<'_> {
+        Definition {
+            name: &self.name,
+            argument: &self.argument,
+            body: Box::new(self.body.as_expr()),
+        }
+    }
+}
+
+impl<'a> Pattern<'a> {
+    pub fn into_owned(self) -> OwnedPattern {
+        match self {
+            Pattern::Wildcard => OwnedPattern::Wildcard,
+            Pattern::Var(name) => OwnedPattern::Var(name.to_string()),
+            Pattern::Construct{ name, argument } => OwnedPattern::Construct {
+                name: name.to_string(),
+                argument: argument.map(|argument| Box::new(argument.into_owned())),
+            },
+            Pattern::Record(fields) => OwnedPattern::Record(
+                fields.into_iter().map(|(label, pattern)| (label.to_string(), pattern.into_owned())).collect()
+            ),
+        }
+    }
+}
+
+impl OwnedPattern {
+    pub fn as_pattern(&self) -> Pattern<'_> {
+        match self {
+            OwnedPattern::Wildcard => Pattern::Wildcard,
+            OwnedPattern::Var(name) => Pattern::Var(name),
+            OwnedPattern::Construct{ name, argument } => Pattern::Construct {
+                name,
+                argument: argument.as_ref().map(|argument| Box::new(argument.as_pattern())),
+            },
+            OwnedPattern::Record(fields) => Pattern::Record(
+                fields.iter().map(|(label, pattern)| (label.as_str(), pattern.as_pattern())).collect()
+            ),
+        }
+    }
+}
+
+impl<'a> Rule<'a> {
+    pub fn into_owned(self) -> OwnedRule {
+        OwnedRule { pattern: self.pattern.into_owned(), body: Box::new(self.body.into_owned()) }
+    }
+}
+
+impl OwnedRule {
+    pub fn as_rule(&self) -> Rule<'_> {
+        Rule { pattern: self.pattern.as_pattern(), body: Box::new(self.body.as_expr()) }
+    }
+}
+
+impl<'a> Expr<'a> {
+    pub fn into_owned(self) -> OwnedExpr {
+        use Expr::*;
+        let owned = |expr: Box<Expr<'a>>| Box::new(expr.into_owned());
+        match self {
+            Var(name) => OwnedExpr::Var(name.to_string()),
+            Lit(lit) => OwnedExpr::Lit(lit.into_owned()),
+            Error(span) => OwnedExpr::Error(span),
+            Unary{ operation, child } => OwnedExpr::Unary{ operation, child: owned(child) },
+            Binary{ left, operation, right } => {
+                OwnedExpr::Binary{ left: owned(left), operation, right: owned(right) }
+            },
+            IfThenElse{ condition, if_branch, else_branch } => OwnedExpr::IfThenElse {
+                condition: owned(condition),
+                if_branch: owned(if_branch),
+                else_branch: owned(else_branch),
+            },
+            While{ condition, body } => OwnedExpr::While{ condition: owned(condition), body: owned(body) },
+            Tuple{ fst, snd } => OwnedExpr::Tuple{ fst: owned(fst), snd: owned(snd) },
+            Let{ bindings, body } => OwnedExpr::Let {
+                bindings: bindings.into_iter()
+                    .map(|(name, binder)| (name.to_string(), binder.into_owned()))
+                    .collect(),
+                body: owned(body),
+            },
+            Lambda{ name, body } => OwnedExpr::Lambda{ name: name.to_string(), body: owned(body) },
+            App{ left, right } => OwnedExpr::App{ left: owned(left), right: owned(right) },
+            Seq(sequence) => OwnedExpr::Seq(sequence.into_iter().map(Expr::into_owned).collect()),
+            List(elements) => OwnedExpr::List(elements.into_iter().map(Expr::into_owned).collect()),
+            Cons{ head, tail } => OwnedExpr::Cons{ head: owned(head), tail: owned(tail) },
+            Funs{ defs, body } => OwnedExpr::Funs {
+                defs: defs.into_iter().map(Definition::into_owned).collect(),
+                body: owned(body),
+            },
+            Annot{ expr, ty } => OwnedExpr::Annot{ expr: owned(expr), ty },
+            Construct{ name, argument } => {
+                OwnedExpr::Construct{ name: name.to_string(), argument: argument.map(owned) }
+            },
+            Raise(expr) => OwnedExpr::Raise(owned(expr)),
+            Handle{ expr, rules } => OwnedExpr::Handle {
+                expr: owned(expr),
+                rules: rules.into_iter().map(Rule::into_owned).collect(),
+            },
+            Record(fields) => OwnedExpr::Record(
+                fields.into_iter().map(|(label, expr)| (label.to_string(), expr.into_owned())).collect()
+            ),
+            Select{ label, record } => OwnedExpr::Select{ label: label.to_string(), record: owned(record) },
+        }
+    }
+}
+
+impl OwnedExpr {
+    // borrows the names back out, the inverse of `Expr::into_owned`
+    pub fn as_expr(&self) -> Expr<'_> {
+        use OwnedExpr::*;
+        fn borrowed(expr: &OwnedExpr) -> Box<Expr<'_>> {
+            Box::new(expr.as_expr())
+        }
+        match self {
+            Var(name) => Expr::Var(name),
+            Lit(lit) => Expr::Lit(lit.as_literal()),
+            Error(span) => Expr::Error(*span),
+            Unary{ operation, child } => Expr::Unary{ operation: *operation, child: borrowed(child) },
+            Binary{ left, operation, right } => {
+                Expr::Binary{ left: borrowed(left), operation: *operation, right: borrowed(right) }
+            },
+            IfThenElse{ condition, if_branch, else_branch } => Expr::IfThenElse {
+                condition: borrowed(condition),
+                if_branch: borrowed(if_branch),
+                else_branch: borrowed(else_branch),
+            },
+            While{ condition, body } => Expr::While{ condition: borrowed(condition), body: borrowed(body) },
+            Tuple{ fst, snd } => Expr::Tuple{ fst: borrowed(fst), snd: borrowed(snd) },
+            Let{ bindings, body } => Expr::Let {
+                bindings: bindings.iter().map(|(name, binder)| (name.as_str(), binder.as_expr())).collect(),
+                body: borrowed(body),
+            },
+            Lambda{ name, body } => Expr::Lambda{ name, body: borrowed(body) },
+            App{ left, right } => Expr::App{ left: borrowed(left), right: borrowed(right) },
+            Seq(sequence) => Expr::Seq(sequence.iter().map(OwnedExpr::as_expr).collect()),
+            List(elements) => Expr::List(elements.iter().map(OwnedExpr::as_expr).collect()),
+            Cons{ head, tail } => Expr::Cons{ head: borrowed(head), tail: borrowed(tail) },
+            Funs{ defs, body } => Expr::Funs {
+                defs: defs.iter().map(OwnedDefinition::as_definition).collect(),
+                body: borrowed(body),
+            },
+            Annot{ expr, ty } => Expr::Annot{ expr: borrowed(expr), ty: ty.clone() },
+            Construct{ name, argument } => {
+                Expr::Construct{ name, argument: argument.as_ref().map(|argument| borrowed(argument)) }
+            },
+            Raise(expr) => Expr::Raise(borrowed(expr)),
+            Handle{ expr, rules } => Expr::Handle {
+                expr: borrowed(expr),
+                rules: rules.iter().map(OwnedRule::as_rule).collect(),
+            },
+            Record(fields) => {
+                Expr::Record(fields.iter().map(|(label, expr)| (label.as_str(), expr.as_expr())).collect())
+            },
+            Select{ label, record } => Expr::Select{ label, record: borrowed(record) },
+        }
+    }
+}
+
+impl fmt::Display for OwnedExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_expr())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::expr::{parse};
+
+    #[test]
+    fn owned_roundtrip_unit() {
+        let tests = vec![
+            "let val x = (1, true) in fst x :: [2, 3] end",
+            "let fun f n = if n < 1 then () else f (n - 1) in f 3 end",
+            "fn x => not x",
+        ];
+        for test in tests {
+            let owned = {
+                let source = test.to_string();
+                let expr = parse(&source).unwrap();
+                expr.into_owned()
+            };
+            assert_eq!(owned.to_string(), parse(test).unwrap().to_string());
+        }
+    }
+}
@@ -1,7 +1,14 @@
-use crate::expr::{Expr};
+use crate::expr::{Expr, ERROR};
+use crate::locale::{message};
+use crate::render::{Rendering, rendering};
 
 impl<'a> Expr<'a> {
+    // the tree drawn with box characters, or spelled out by
+    // `pretty_linear` when the linear rendering is selected
     pub fn pretty(&self) -> String {
+        if rendering() == Rendering::Linear {
+            return self.pretty_linear()
+        }
         fn draw<'a>(expr: &Expr<'a>, lines: &mut Vec<String>, cur: usize) -> usize {
             use Expr::*;
             match expr {
@@ -13,6 +20,10 @@ impl<'a> Expr<'a> {
                     lines.push(format!("{}", lit));
                     cur + 1
                 },
+                Error(_) => {
+                    lines.push(ERROR.to_string());
+                    cur + 1
+                },
                 Unary{ operation, child } => {
                     lines.push(format!("{}", operation));
                     lines.push("│  ".to_string());
@@ -61,13 +72,19 @@ impl<'a> Expr<'a> {
                     }
                     bottom
                 },
-                Let{ name, binder, body } => {
-                    lines.push(format!("let {}=", name));
-                    lines.push("│  ".to_string());
-                    let top = draw(binder, lines, cur + 2);
-                    lines[cur + 2].insert_str(0, "├──");
-                    for y in cur + 3 .. top {
-                        lines[y].insert_str(0, "│  ");
+                Let{ bindings, body } => {
+                    let names: Vec<&str> = bindings.iter().map(|(name, _)| *name).collect();
+                    lines.push(format!("let {}=", names.join(", ")));
+                    // the line above each child
+                    let mut top = cur + 1;
+                    for (_, binder) in bindings {
+                        lines.push("│  ".to_string());
+                        let next = draw(binder, lines, top + 1);
+                        lines[top + 1].insert_str(0, "├──");
+                        for y in top + 2 .. next {
+                            lines[y].insert_str(0, "│  ");
+                        }
+                        top = next;
                     }
                     lines.push("│  ".to_string());
                     let bottom = draw(body, lines, top + 1);
@@ -110,4 +127,121 @@ impl<'a> Expr<'a> {
         draw(self, &mut lines, 0);
         lines.join("\n")
     }
+    // one line per node in pre-order, each saying which node it is a child
+    // of rather than showing it with indentation
+    pub fn pretty_linear(&self) -> String {
+        fn walk(expr: &Expr, parent: Option<(usize, usize)>, lines: &mut Vec<String>) {
+            let node = lines.len() + 1;
+            lines.push(match parent {
+                None => message("tree-root", &[&node, &label(expr)]),
+                Some((parent, nth)) => message("tree-child", &[&node, &label(expr), &nth, &parent]),
+            });
+            for (i, child) in expr.children().into_iter().enumerate() {
+                walk(child, Some((node, i + 1)), lines)
+            }
+        }
+        let mut lines = vec![];
+        walk(self, None, &mut lines);
+        lines.join("\n")
+    }
+    // the tree as a graphviz graph, each node labeled with what it is and
+    // named `n` and its pre-order number, for `dot -Tsvg`
+    pub fn to_dot(&self) -> String {
+        fn walk(expr: &Expr, nodes: &mut usize, dot: &mut String) -> usize {
+            *nodes += 1;
+            let node = *nodes;
+            let label: String = label(expr).chars()
+                .flat_map(|c| if c == '"' || c == '\\' { vec!['\\', c] } else { vec![c] })
+                .collect();
+            dot.push_str(&format!("  n{} [label=\"{}\"];\n", node, label));
+            for child in expr.children() {
+                let child = walk(child, nodes, dot);
+                dot.push_str(&format!("  n{} -> n{};\n", node, child));
+            }
+            node
+        }
+        // children left to right in the order they come in
+        let mut dot = String::from("digraph {\n  ordering=out;\n");
+        walk(self, &mut 0, &mut dot);
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+// what a node is, without its children
+pub(crate) fn label(expr: &Expr) -> String {
+    use Expr::*;
+    match expr {
+        Var(name) => name.to_string(),
+        Lit(lit) => lit.to_string(),
+        Error(_) => ERROR.to_string(),
+        Unary{ operation, .. } => operation.to_string(),
+        Binary{ operation, .. } => operation.to_string(),
+        IfThenElse{ .. } => "if".to_string(),
+        While{ .. } => "while".to_string(),
+        Let{ bindings, .. } => {
+            let names: Vec<&str> = bindings.iter().map(|(name, _)| *name).collect();
+            format!("let {} =", names.join(", "))
+        },
+        Lambda{ name, .. } => format!("fn {}", name),
+        App{ .. } => message("tree-application", &[]),
+        Tuple{ .. } => message("tree-tuple", &[]),
+        Seq(_) => message("tree-sequence", &[]),
+        List(_) => message("tree-list", &[]),
+        Cons{ .. } => "::".to_string(),
+        Funs{ defs, .. } => {
+            let defs: Vec<String> = defs.iter()
+                .map(|def| format!("{} {}", def.name, def.argument))
+                .collect();
+            format!("let fun {}", defs.join(" and "))
+        },
+        Annot{ ty, .. } => format!(": {}", ty),
+        Construct{ name, .. } => name.to_string(),
+        Raise(_) => "raise".to_string(),
+        Handle{ rules, .. } => {
+            let patterns: Vec<String> = rules.iter().map(|rule| rule.pattern.to_string()).collect();
+            format!("handle {}", patterns.join(" | "))
+        },
+        Record(fields) => {
+            let labels: Vec<&str> = fields.iter().map(|(label, _)| *label).collect();
+            format!("{{{}}}", labels.join(", "))
+        },
+        Select{ label, .. } => format!("#{}", label),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::expr::{parse};
+
+    #[test]
+    fn pretty_linear_unit() {
+        let expr = parse("let val x = 1 in f (x + 2) end").unwrap();
+        assert_eq!(expr.pretty_linear(), "node 1: let x =\n\
+                                          node 2: 1, child 1 of node 1\n\
+                                          node 3: application, child 2 of node 1\n\
+                                          node 4: f, child 1 of node 3\n\
+                                          node 5: sequence, child 2 of node 3\n\
+                                          node 6: +, child 1 of node 5\n\
+                                          node 7: x, child 1 of node 6\n\
+                                          node 8: 2, child 2 of node 6");
+        assert!(!parse("(1, [2] :: [])").unwrap().pretty_linear().contains(|c: char| !c.is_ascii()));
+    }
+
+    #[test]
+    fn dot_unit() {
+        let expr = parse("if x < 1 then #\"\\\"\" else f x").unwrap();
+        assert_eq!(expr.to_dot(), concat!(
+            "digraph {\n  ordering=out;\n",
+            "  n1 [label=\"if\"];\n",
+            "  n2 [label=\"<\"];\n",
+            "  n3 [label=\"x\"];\n  n2 -> n3;\n",
+            "  n4 [label=\"1\"];\n  n2 -> n4;\n  n1 -> n2;\n",
+            "  n5 [label=\"#\\\"\\\\\\\"\\\"\"];\n  n1 -> n5;\n",
+            "  n6 [label=\"application\"];\n",
+            "  n7 [label=\"f\"];\n  n6 -> n7;\n",
+            "  n8 [label=\"x\"];\n  n6 -> n8;\n  n1 -> n6;\n",
+            "}\n",
+        ));
+    }
 }
@@ -0,0 +1,147 @@
+use std::fmt;
+
+use crate::expr::{Expr};
+use crate::expr::eval::{Env, Error, Value};
+use crate::expr::ids::{NodeId};
+use crate::expr::trace::{Event, Trace};
+use crate::locale::{message};
+
+// what a subexpression evaluated to and the subexpressions evaluating it
+// evaluated, in the order they were. a function call has the nodes of the
+// body it ran under it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Provenance<'a> {
+    // its pre-order id, the span of which `NodeTable::span` has
+    pub node: NodeId,
+    pub source: String,
+    // the variable the node is, if it is one, an input of a rule
+    pub name: Option<&'a str>,
+    pub value: Result<String, String>,
+    pub children: Vec<Provenance<'a>>,
+}
+
+impl<'a> Expr<'a> {
+    // evaluates the expression with `inputs` bound and records the value of
+    // each subexpression down to `depth` below it, for telling someone why a
+    // rule came out the way it did. the nodes deeper down are evaluated all
+    // the same
+    pub fn provenance(
+        self,
+        inputs: &[(&'a str, Value<'a>)],
+        depth: usize,
+    ) -> (Result<Value<'a>, Error<'a>>, Option<Provenance<'a>>) {
+        let tree = self.clone();
+        let mut env = Env::traced();
+        for (name, value) in inputs {
+            env.define(name, value.clone());
+        }
+        let res = self.eval_ctx(&mut env);
+        let trace = Trace { events: env.events() };
+        (res, trace.provenance(&tree, depth))
+    }
+}
+
+impl<'a> Trace<'a> {
+    // the tree of the nodes entered, from the trace of evaluating `expr`,
+    // none when the trace is empty or cut short
+    pub fn provenance(&self, expr: &Expr<'a>, depth: usize) -> Option<Provenance<'a>> {
+        // the nodes entered and not yet left that are kept
+        let mut open: Vec<Provenance<'a>> = vec![];
+        let mut level = 0;
+        let mut root = None;
+        for event in &self.events {
+            match event {
+                Event::Enter(node) => {
+                    if level <= depth {
+                        let found = expr.node(*node);
+                        let source = found.map(Expr::to_source).unwrap_or_default();
+                        let name = match found {
+                            Some(Expr::Var(name)) => Some(*name),
+                            _ => None,
+                        };
+                        let value = Ok(String::new());
+                        open.push(Provenance { node: *node, source, name, value, children: vec![] });
+                    }
+                    level += 1;
+                },
+                Event::Exit{ value, .. } => {
+                    level -= 1;
+                    if level <= depth {
+                        let mut done = open.pop()?;
+                        done.value = value.clone();
+                        match open.last_mut() {
+                            Some(parent) => parent.children.push(done),
+                            None => root = Some(done),
+                        }
+                    }
+                },
+                Event::Bind{ .. } | Event::Unbind(_) => {},
+            }
+        }
+        root
+    }
+}
+
+impl<'a> Provenance<'a> {
+    fn write(&self, f: &mut fmt::Formatter, indent: usize) -> fmt::Result {
+        let line = match &self.value {
+            // an input is the one thing a reader cannot see in the rule
+            Ok(value) if self.name.is_some() => message("provenance-input", &[&self.source, value]),
+            Ok(value) => message("provenance-value", &[&self.source, value]),
+            Err(err) => message("provenance-error", &[&self.source, err]),
+        };
+        writeln!(f, "{:indent$}{}", "", line, indent = indent)?;
+        for child in &self.children {
+            // a literal is its own value
+            if child.name.is_none() && child.children.is_empty() && child.value.as_ref() == Ok(&child.source) {
+                continue
+            }
+            child.write(f, indent + 2)?
+        }
+        Ok(())
+    }
+}
+
+impl<'a> fmt::Display for Provenance<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::{parse};
+
+    #[test]
+    fn provenance_unit() {
+        let rule = parse("admin orelse age < 18").unwrap();
+        let inputs = [("admin", Value::Boolean(false)), ("age", Value::Integer(21))];
+        let (res, why) = rule.clone().provenance(&inputs, 10);
+        assert_eq!(res.unwrap().to_string(), "false");
+        let why = why.unwrap();
+        assert_eq!((why.node, why.children.len()), (NodeId(0), 2));
+        assert_eq!(why.children[1].source, "age < 18");
+        assert_eq!((why.children[1].node, why.children[0].name), (NodeId(2), Some("admin")));
+        assert_eq!(why.to_string(), concat!(
+            "`admin orelse age < 18` was false\n",
+            "  admin = false\n",
+            "  `age < 18` was false\n",
+            "    age = 21\n",
+        ));
+
+        // only as deep as asked
+        let (_, why) = rule.provenance(&inputs, 1);
+        let why = why.unwrap();
+        assert!(why.children.iter().all(|child| child.children.is_empty()));
+        assert_eq!(why.children.len(), 2);
+
+        // the body of a function under the call
+        let (_, why) = parse("let fun f n = n div 0 in f 3 end").unwrap().provenance(&[], 10);
+        let why = why.unwrap();
+        assert_eq!(why.value, Err("uncaught exception `Div`".to_string()));
+        let call = &why.children[0];
+        assert_eq!(call.source, "f 3");
+        assert_eq!(call.children.last().map(|body| body.source.as_str()), Some("n div 0"));
+    }
+}
@@ -0,0 +1,538 @@
+use combine::{EasyParser};
+use combine::error::{StringStreamError};
+use combine::stream::{StreamOnce, Positioned, ResetStream};
+
+use crate::lexer::{self, Delimiter, Direction, Reserved, Span, Token, Tokenizer, WORDS};
+use crate::error::{ParseError};
+use crate::locale::{message};
+use crate::expr::{Expr, prog};
+use crate::expr::ids::{NodeTable};
+
+// the outcome of parsing with error recovery. every fix the parser made is
+// reported as an error, the tree is only for tools that want to look past them
+#[derive(Debug)]
+pub struct Recovered<'a> {
+    pub expr: Option<Expr<'a>>,
+    // the spans of the tree's nodes in the source, a token the parser
+    // assumed was missing takes up no room
+    pub table: Option<NodeTable>,
+    pub errors: Vec<ParseError<'a>>,
+}
+
+// a guess at what the programmer meant, made on the tokens and never on the
+// source so the tree can still borrow from it
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Repair<'a> {
+    // the name at `at` is a misspelled keyword
+    Replace{ at: usize, word: &'a str, keyword: Reserved },
+    // a token is missing in the whitespace at `at`, or at the end
+    Insert{ at: usize, token: Token<'a> },
+    // the token at `at` should not be there
+    Delete{ at: usize, token: Token<'a> },
+    // nothing above helped, the tokens in `span` are left out and an
+    // `Expr::Error` stands in for them. an empty span is a hole in front
+    // of the token at its start
+    Skip{ span: Span },
+}
+
+// what a missing token usually turns out to be
+const MISSING: [Token<'static>; 9] = [
+    Token::Keyword(Reserved::Then),
+    Token::Keyword(Reserved::Else),
+    Token::Keyword(Reserved::In),
+    Token::Keyword(Reserved::End),
+    Token::Keyword(Reserved::Arrow),
+    Token::Keyword(Reserved::Equal),
+    Token::Delim(Delimiter::Paren(Direction::Right)),
+    Token::Delim(Delimiter::Bracket(Direction::Right)),
+    Token::Delim(Delimiter::Brace(Direction::Right)),
+];
+
+// how many gaps before the error a missing token is looked for in
+const GAPS: usize = 6;
+
+// how many repairs that leave the error where it was may be stacked up
+// before one of them has to get the parser further
+const LOOKAHEAD: usize = 2;
+
+// reparses tried per error before giving up on it
+const BUDGET: usize = 2000;
+
+// where the parser picks up again after a hole, the tokens that end or
+// split an expression
+const SYNC: [Token<'static>; 10] = [
+    Token::Keyword(Reserved::Then),
+    Token::Keyword(Reserved::Else),
+    Token::Keyword(Reserved::In),
+    Token::Keyword(Reserved::End),
+    Token::Keyword(Reserved::Do),
+    Token::Delim(Delimiter::Paren(Direction::Right)),
+    Token::Delim(Delimiter::Bracket(Direction::Right)),
+    Token::Delim(Delimiter::Brace(Direction::Right)),
+    Token::Delim(Delimiter::Comma),
+    Token::Delim(Delimiter::Semicolon),
+];
+
+// how many of those after an error a hole may run up to
+const SYNCS: usize = 8;
+
+// the repair that gets the parser furthest is kept and parsing carries on
+// after it, until the input parses. when no repair helps the expression the
+// error is in is given up on, up to the next token that can end it, so one
+// bad expression does not take the whole tree with it
+pub fn parse_recovering<'a>(source: &'a str) -> Recovered<'a> {
+    let tokens = tokenize(source);
+    let mut repairs = vec![];
+    let mut errors = vec![];
+    let expr = loop {
+        let err = match parse_with(source, &repairs) {
+            Ok(expr) => break Some(expr),
+            Err(err) => err,
+        };
+        match repair(source, &tokens, &repairs, &err) {
+            Some(fixes) => for fix in fixes {
+                errors.push(report(source, &tokens, &fix));
+                repairs.push(fix)
+            },
+            None => {
+                let hole = skip(source, &tokens, &repairs, &err);
+                errors.push(err);
+                match hole {
+                    Some(hole) => repairs.push(hole),
+                    None => break None,
+                }
+            },
+        }
+    };
+    errors.sort_by_key(|err| err.span.start);
+    let table = expr.as_ref().and_then(|expr| NodeTable::of_tokens(repaired(&tokens, &repairs), expr));
+    Recovered { expr, table, errors }
+}
+
+// the non space tokens the parser read, with where each one is in the source
+fn repaired<'a>(tokens: &[(usize, Token<'a>)], repairs: &[Repair<'a>]) -> Vec<(Token<'a>, Span)> {
+    let mut result = vec![];
+    for (i, (at, token)) in tokens.iter().enumerate() {
+        for repair in repairs {
+            match repair {
+                Repair::Insert{ at: gap, token } if gap == at => result.push((token.clone(), Span::new(*at, *at))),
+                Repair::Skip{ span } if span.start == *at => result.push((Token::Error(*span), *span)),
+                _ => {},
+            }
+        }
+        if repairs.iter().any(|repair| matches!(repair, Repair::Skip{ span } if span.start <= *at && *at < span.end)) {
+            continue
+        }
+        let edit = repairs.iter().find(|repair| match repair {
+            Repair::Replace{ at: other, .. } | Repair::Delete{ at: other, .. } => other == at,
+            Repair::Insert{ .. } | Repair::Skip{ .. } => false,
+        });
+        let token = match (token, edit) {
+            (Token::Space(_), _) | (Token::EndOfFile, _) | (_, Some(Repair::Delete{ .. })) => continue,
+            (Token::Name(_), Some(Repair::Replace{ keyword, .. })) => Token::Keyword(*keyword),
+            (token, _) => token.clone(),
+        };
+        let end = tokens.get(i + 1).map_or(*at, |(next, _)| *next);
+        result.push((token, Span::new(*at, end)))
+    }
+    result
+}
+
+fn parse_with<'a>(source: &'a str, repairs: &[Repair<'a>]) -> Result<Expr<'a>, ParseError<'a>> {
+    let input = Repaired { tokens: Tokenizer::new(source), repairs, spliced: 0, holed: false };
+    prog().easy_parse(input)
+        .map(|(expr, _)| expr)
+        .map_err(|err| ParseError::new(source, err))
+}
+
+fn tokenize(source: &str) -> Vec<(usize, Token<'_>)> {
+    let mut tokens = Tokenizer::new(source);
+    let mut result = vec![];
+    loop {
+        let start = tokens.position();
+        match tokens.uncons() {
+            Ok(Token::EndOfFile) | Err(_) => {
+                result.push((start, Token::EndOfFile));
+                return result
+            },
+            Ok(token) => result.push((start, token)),
+        }
+    }
+}
+
+fn report<'a>(source: &'a str, tokens: &[(usize, Token<'a>)], repair: &Repair<'a>) -> ParseError<'a> {
+    let (at, unexpected, note, code) = match repair {
+        Repair::Replace{ at, word, keyword } => {
+            (*at, Token::Name(word), message("did-you-mean", &[keyword]), "E0004")
+        },
+        Repair::Insert{ at, token } => {
+            // point at whatever follows the gap
+            let (at, next) = tokens.iter()
+                .find(|(start, next)| *at <= *start && !matches!(next, Token::Space(_)))
+                .cloned()
+                .unwrap_or((source.len(), Token::EndOfFile));
+            let code = if *token == Token::Keyword(Reserved::Else) { "E0005" } else { "E0006" };
+            (at, next, message("assumed-missing", &[token]), code)
+        },
+        Repair::Delete{ at, token } => {
+            (*at, token.clone(), message("assumed-extra", &[token]), "E0007")
+        },
+        // the error a hole is made for is reported as it is
+        Repair::Skip{ .. } => unreachable!(),
+    };
+    ParseError {
+        source,
+        unexpected: Some(unexpected),
+        span: Span::of_token(source, at),
+        expected: vec![],
+        messages: vec![note],
+        code,
+    }
+}
+
+// the best single repair for `err` if one gets the parser past it, else the
+// shortest chain of repairs that does. the misspelling behind an error is
+// often well before it, the parser happily reads `thn` as an argument and
+// only fails when `then` never comes
+fn repair<'a>(
+    source: &'a str,
+    tokens: &[(usize, Token<'a>)],
+    repairs: &[Repair<'a>],
+    err: &ParseError<'a>,
+) -> Option<Vec<Repair<'a>>> {
+    let mut budget = BUDGET;
+    let mut frontier = vec![(vec![], err.clone())];
+    for _ in 0..=LOOKAHEAD {
+        let mut deeper = vec![];
+        for (chain, err) in frontier {
+            let mut attempt = repairs.to_vec();
+            attempt.extend(chain.iter().cloned());
+            let (best, ties) = step(source, tokens, &attempt, &err, &mut budget);
+            if let Some(fix) = best {
+                let mut chain = chain;
+                chain.push(fix);
+                return Some(chain)
+            }
+            for (tie, next) in ties {
+                let mut chain = chain.clone();
+                chain.push(tie);
+                deeper.push((chain, next))
+            }
+        }
+        frontier = deeper;
+    }
+    None
+}
+
+// the shortest hole from the token `err` is at up to one of the next few
+// `SYNC` tokens or the end that gets the parser past `err`. a longer one
+// can always get further, by leaving out the next error too
+fn skip<'a>(
+    source: &'a str,
+    tokens: &[(usize, Token<'a>)],
+    repairs: &[Repair<'a>],
+    err: &ParseError<'a>,
+) -> Option<Repair<'a>> {
+    let after: Vec<&(usize, Token<'a>)> = tokens.iter()
+        .filter(|(at, token)| err.span.start <= *at && !matches!(token, Token::Space(_)))
+        .collect();
+    let start = after.first()?.0;
+    let ends = after.iter().enumerate()
+        .filter(|(_, (_, token))| SYNC.contains(token) || *token == Token::EndOfFile)
+        .take(SYNCS);
+    for (i, _) in ends {
+        // up to the end of the last token left out
+        let end = match i.checked_sub(1) {
+            Some(last) => Span::of_token(source, after[last].0).end,
+            None => start,
+        };
+        let hole = Repair::Skip{ span: Span::new(start, end) };
+        let mut attempt = repairs.to_vec();
+        attempt.push(hole.clone());
+        match parse_with(source, &attempt) {
+            Err(next) if next.span.start <= err.span.start => {},
+            _ => return Some(hole),
+        }
+    }
+    None
+}
+
+// the candidate that gets furthest past `err`, along with the ones that
+// fail right where it did
+fn step<'a>(
+    source: &'a str,
+    tokens: &[(usize, Token<'a>)],
+    repairs: &[Repair<'a>],
+    err: &ParseError<'a>,
+    budget: &mut usize,
+) -> (Option<Repair<'a>>, Vec<(Repair<'a>, ParseError<'a>)>) {
+    let mut best: Option<(usize, Repair<'a>)> = None;
+    let mut ties = vec![];
+    for candidate in candidates(tokens, repairs, err.span.start) {
+        if *budget == 0 {
+            break
+        }
+        *budget -= 1;
+        let mut attempt = repairs.to_vec();
+        attempt.push(candidate.clone());
+        let (reached, next) = match parse_with(source, &attempt) {
+            Ok(_) => (usize::MAX, None),
+            Err(next) => (next.span.start, Some(next)),
+        };
+        match next {
+            // skipping a token that does not help is never the answer
+            Some(_) if reached == err.span.start && matches!(candidate, Repair::Delete{ .. }) => {},
+            Some(next) if reached == err.span.start => ties.push((candidate, next)),
+            _ if err.span.start < reached && best.as_ref().map_or(0, |(most, _)| *most) < reached => {
+                best = Some((reached, candidate))
+            },
+            _ => {},
+        }
+    }
+    (best.map(|(_, fix)| fix), ties)
+}
+
+// misspellings first, then extra tokens, then missing ones, each nearest to
+// the error first
+fn candidates<'a>(tokens: &[(usize, Token<'a>)], repairs: &[Repair<'a>], until: usize) -> Vec<Repair<'a>> {
+    let edited = |at: usize| repairs.iter().any(|repair| match repair {
+        Repair::Replace{ at: other, .. } | Repair::Delete{ at: other, .. } => *other == at,
+        Repair::Insert{ .. } | Repair::Skip{ .. } => false,
+    });
+    let before: Vec<&(usize, Token<'a>)> = tokens.iter().filter(|(at, _)| *at <= until).collect();
+    let mut candidates = vec![];
+    for &&(at, ref token) in before.iter().rev() {
+        if let Token::Name(word) = *token {
+            if !edited(at) {
+                candidates.extend(suggestions(word).into_iter().map(|keyword| Repair::Replace{ at, word, keyword }))
+            }
+        }
+    }
+    let extra = before.iter().rev()
+        .filter(|(at, token)| !matches!(token, Token::Space(_) | Token::EndOfFile) && !edited(*at))
+        .take(2);
+    for &&(at, ref token) in extra {
+        candidates.push(Repair::Delete{ at, token: token.clone() })
+    }
+    // a gap is whitespace, or the end when nothing separates it from the
+    // last token
+    let gaps = before.iter().enumerate().rev()
+        .filter(|&(i, (_, token))| match token {
+            Token::Space(_) => true,
+            Token::EndOfFile => i == 0 || !matches!(before[i - 1].1, Token::Space(_)),
+            _ => false,
+        })
+        .take(GAPS);
+    for (_, &&(at, _)) in gaps {
+        for token in MISSING.iter() {
+            candidates.push(Repair::Insert{ at, token: token.clone() })
+        }
+    }
+    candidates
+}
+
+// the keywords `word` could be a typo of, closest first. short keywords only
+// allow a single edit or every one letter name would look like one
+fn suggestions(word: &str) -> Vec<Reserved> {
+    let mut close: Vec<(usize, Reserved)> = WORDS.iter()
+        .map(|&keyword| (distance(word, &keyword.to_string()), keyword))
+        .filter(|&(d, keyword)| 0 < d && d <= if keyword.to_string().len() <= 3 { 1 } else { 2 })
+        .collect();
+    close.sort();
+    close.into_iter().map(|(_, keyword)| keyword).collect()
+}
+
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, x) in a.chars().enumerate() {
+        let mut row = vec![i + 1];
+        for (j, &y) in b.iter().enumerate() {
+            let cost = if x == y { 0 } else { 1 };
+            row.push((prev[j] + cost).min(prev[j + 1] + 1).min(row[j] + 1));
+        }
+        prev = row;
+    }
+    prev[b.len()]
+}
+
+// the token stream with `repairs` applied. inserted tokens are spliced in
+// with a space on each side, in place of the whitespace they go in, and a
+// hole is read as one `Token::Error`
+struct Repaired<'r, 'a> {
+    tokens: Tokenizer<'a>,
+    repairs: &'r [Repair<'a>],
+    // how much of the splice at the current position has been read
+    spliced: usize,
+    // whether the empty hole at the current position has been read
+    holed: bool,
+}
+
+impl<'r, 'a> Repaired<'r, 'a> {
+    fn splice(&self, at: usize) -> Vec<Token<'a>> {
+        let mut splice = vec![];
+        for repair in self.repairs {
+            if let Repair::Insert{ at: other, token } = repair {
+                if *other == at {
+                    splice.push(Token::Space(1));
+                    splice.push(token.clone())
+                }
+            }
+        }
+        splice
+    }
+    fn hole(&self, at: usize) -> Option<Span> {
+        self.repairs.iter().find_map(|repair| match repair {
+            Repair::Skip{ span } if span.start == at => Some(*span),
+            _ => None,
+        })
+    }
+}
+
+impl<'r, 'a> StreamOnce for Repaired<'r, 'a> {
+    type Item = Token<'a>;
+    type Range = Token<'a>;
+    type Position = usize;
+    type Error = StringStreamError;
+    fn uncons(&mut self) -> Result<Token<'a>, Self::Error> {
+        let start = self.tokens.position();
+        match self.hole(start) {
+            Some(span) if span.start < span.end => {
+                while self.tokens.position() < span.end {
+                    self.tokens.uncons()?;
+                }
+                return Ok(Token::Error(span))
+            },
+            Some(span) if !self.holed => {
+                self.holed = true;
+                return Ok(Token::Error(span))
+            },
+            _ => self.holed = false,
+        }
+        let mut splice = self.splice(start);
+        if !splice.is_empty() {
+            let before = self.tokens.checkpoint();
+            let at_end = self.tokens.uncons()? == Token::EndOfFile;
+            if !at_end {
+                splice.push(Token::Space(1))
+            }
+            if self.spliced == splice.len() {
+                return Ok(Token::EndOfFile)
+            }
+            let token = splice[self.spliced].clone();
+            self.spliced += 1;
+            if self.spliced == splice.len() && !at_end {
+                // its last space is the whitespace it stands in for, which
+                // has just been read
+                self.spliced = 0
+            } else {
+                self.tokens.reset(before)?
+            }
+            return Ok(token)
+        }
+        let token = self.tokens.uncons()?;
+        let edit = self.repairs.iter().find(|repair| match repair {
+            Repair::Replace{ at, .. } | Repair::Delete{ at, .. } => *at == start,
+            Repair::Insert{ .. } | Repair::Skip{ .. } => false,
+        });
+        match (token, edit) {
+            (Token::Name(_), Some(Repair::Replace{ keyword, .. })) => Ok(Token::Keyword(*keyword)),
+            (_, Some(Repair::Delete{ .. })) => {
+                // so the whitespace around it does not end up doubled
+                let after = self.tokens.checkpoint();
+                if let Token::Space(_) = self.tokens.uncons()? {} else {
+                    self.tokens.reset(after)?
+                }
+                self.uncons()
+            },
+            (token, _) => Ok(token),
+        }
+    }
+}
+
+impl<'r, 'a> Positioned for Repaired<'r, 'a> {
+    fn position(&self) -> usize {
+        self.tokens.position()
+    }
+}
+
+impl<'r, 'a> ResetStream for Repaired<'r, 'a> {
+    type Checkpoint = (lexer::Checkpoint<'a>, usize, bool);
+    fn checkpoint(&self) -> Self::Checkpoint {
+        (self.tokens.checkpoint(), self.spliced, self.holed)
+    }
+    fn reset(&mut self, (checkpoint, spliced, holed): Self::Checkpoint) -> Result<(), Self::Error> {
+        self.spliced = spliced;
+        self.holed = holed;
+        self.tokens.reset(checkpoint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::{parse};
+
+    fn fixes(source: &str) -> (Option<Expr<'_>>, Vec<(&str, String)>) {
+        let recovered = parse_recovering(source);
+        let fixes = recovered.errors.iter()
+            .map(|err| (&source[err.span.start..err.span.end], err.messages[0].clone()))
+            .collect();
+        (recovered.expr, fixes)
+    }
+
+    #[test]
+    fn parse_recovering_unit() {
+        assert_eq!(distance("andalos", "andalso"), 2);
+        assert_eq!(distance("thn", "then"), 1);
+
+        let (expr, found) = fixes("if 1 < 2 thn 3 esle 4");
+        assert_eq!(expr, Some(parse("if 1 < 2 then 3 else 4").unwrap()));
+        assert_eq!(found, vec![
+            ("thn", "did you mean `then`?".to_string()),
+            ("esle", "did you mean `else`?".to_string()),
+        ]);
+        let (expr, _) = fixes("if x = 1 andalos y = 2 then 1 else 0");
+        assert_eq!(expr, Some(parse("if x = 1 andalso y = 2 then 1 else 0").unwrap()));
+
+        let (expr, found) = fixes("if x 1 else 2");
+        assert_eq!(expr, Some(parse("if x then 1 else 2").unwrap()));
+        assert_eq!(found, vec![("1", "assumed a missing `then` before this".to_string())]);
+        let (expr, found) = fixes("(1 + 2)) * 3");
+        assert_eq!(expr, Some(parse("(1 + 2) * 3").unwrap()));
+        assert_eq!(found, vec![(")", "assumed this `)` is extra and skipped it".to_string())]);
+        let (expr, found) = fixes("let val x = 1 in let val y = x in y\n");
+        assert_eq!(expr, Some(parse("let val x = 1 in let val y = x in y end end").unwrap()));
+        assert_eq!(found.len(), 2);
+
+        // names that are fine stay names, the real error is still reported
+        let recovered = parse_recovering("let val ned = 1 in ned + ) end");
+        assert_eq!(recovered.expr.unwrap().to_source(), "let val ned = 1 in ned + <error> end");
+        assert_eq!(recovered.errors[0].unexpected, Some(Token::Delim(Delimiter::Paren(Direction::Right))));
+    }
+
+    #[test]
+    fn parse_recovering_holes_unit() {
+        let holes = |source: &'static str| -> (String, Vec<&'static str>) {
+            let recovered = parse_recovering(source);
+            let expr = recovered.expr.unwrap();
+            let table = recovered.table.unwrap();
+            let holes = table.iter()
+                .filter(|(id, _)| matches!(expr.node(*id), Some(Expr::Error(_))))
+                .map(|(_, span)| &source[span.start..span.end])
+                .collect();
+            (expr.to_source(), holes)
+        };
+        // the rest of the tree survives a bad expression, repairs still come
+        // first
+        assert_eq!(
+            holes("let val x = 1 + ) * in if x then [x, * 2] else x end"),
+            ("let val x = 1 + <error> in if x then [x, 2] else x end".to_string(), vec![") *"]),
+        );
+        assert_eq!(holes("f (1 + ]) 2"), ("f (1 + <error>) 2".to_string(), vec!["]"]));
+        assert_eq!(holes("#a {a = }"), ("#a {a = <error>}".to_string(), vec![""]));
+        let recovered = parse_recovering("if + then 1 else 2");
+        assert_eq!(recovered.errors.len(), 1);
+        assert_eq!(recovered.expr.unwrap().eval().unwrap_err().code(), "E0104");
+    }
+}
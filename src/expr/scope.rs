@@ -0,0 +1,275 @@
+use std::fmt;
+use std::collections::{BTreeSet, HashSet};
+
+use crate::lexer::{Span};
+use crate::error::{snippet};
+use crate::locale::{message};
+use crate::expr::{Definition, Expr};
+use crate::expr::ids::{NodeId, NodeTable};
+use crate::expr::visit::{ExprVisitor, walk_expr};
+
+impl<'a> Expr<'a> {
+    // the names this expression reads without binding them itself
+    pub fn free_variables(&self) -> BTreeSet<&'a str> {
+        let mut free = BTreeSet::new();
+        collect(self, &mut vec![], &mut free);
+        free
+    }
+    pub fn free_vars(&self) -> HashSet<&'a str> {
+        self.free_variables().into_iter().collect()
+    }
+    // every name a `let`, `fn` or `fun` somewhere in the tree introduces
+    pub fn bound_vars(&self) -> HashSet<&'a str> {
+        struct Bound<'a>(HashSet<&'a str>);
+        impl<'a> ExprVisitor<'a> for Bound<'a> {
+            fn visit_binding(&mut self, name: &'a str) {
+                self.0.insert(name);
+            }
+        }
+        let mut bound = Bound(HashSet::new());
+        self.accept(&mut bound);
+        bound.0
+    }
+    // every name the tree mentions, bound or free. anything that makes up
+    // new names has to stay clear of all of them
+    pub fn names(&self) -> BTreeSet<&'a str> {
+        let mut names = Names(BTreeSet::new());
+        self.accept(&mut names);
+        names.0
+    }
+}
+
+// a use of a name nothing binds, which evaluation would fail on
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Unbound<'a> {
+    pub source: &'a str,
+    pub name: &'a str,
+    pub span: Span,
+}
+
+impl<'a> Unbound<'a> {
+    // the same code as `eval::Error::NotFound`, it is the same mistake
+    pub fn code(&self) -> &'static str {
+        "E0101"
+    }
+}
+
+impl<'a> fmt::Display for Unbound<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (line, col) = self.span.line_col(self.source);
+        writeln!(f, "{}: {}", message("error-at", &[&line, &col]), message("unbound", &[&self.name]))?;
+        snippet(f, self.source, self.span)
+    }
+}
+
+// every variable use outside the scope of all bindings of its name, in
+// source order. `globals` are the names bound around the whole expression
+pub fn unbound<'a>(
+    source: &'a str,
+    expr: &Expr<'a>,
+    table: &NodeTable,
+    globals: &[&'a str],
+) -> Vec<Unbound<'a>> {
+    let mut check = Check { source, table, next: 0, scope: globals.to_vec(), unbound: vec![] };
+    expr.accept(&mut check);
+    check.unbound
+}
+
+struct Check<'t, 'a> {
+    source: &'a str,
+    table: &'t NodeTable,
+    // the pre-order id of the next node visited
+    next: u32,
+    scope: Vec<&'a str>,
+    unbound: Vec<Unbound<'a>>,
+}
+
+impl<'t, 'a> ExprVisitor<'a> for Check<'t, 'a> {
+    fn visit_expr(&mut self, expr: &Expr<'a>) {
+        use Expr::*;
+        let id = NodeId(self.next);
+        self.next += 1;
+        match expr {
+            Var(name) if !self.scope.contains(name) => {
+                if let Some(span) = self.table.span(id) {
+                    self.unbound.push(Unbound { source: self.source, name, span })
+                }
+            },
+            Let{ bindings, body } => {
+                for (name, binder) in bindings {
+                    self.visit_expr(binder);
+                    self.scope.push(name);
+                }
+                self.visit_expr(body);
+                self.scope.truncate(self.scope.len() - bindings.len());
+            },
+            Lambda{ name, body } => {
+                self.scope.push(name);
+                self.visit_expr(body);
+                self.scope.pop();
+            },
+            Funs{ defs, body } => {
+                self.scope.extend(defs.iter().map(|def| def.name));
+                for def in defs {
+                    self.scope.push(def.argument);
+                    self.visit_expr(&def.body);
+                    self.scope.pop();
+                }
+                self.visit_expr(body);
+                self.scope.truncate(self.scope.len() - defs.len());
+            },
+            Handle{ expr, rules } => {
+                self.visit_expr(expr);
+                for rule in rules {
+                    let names = rule.pattern.names();
+                    let count = names.len();
+                    self.scope.extend(names);
+                    self.visit_expr(&rule.body);
+                    self.scope.truncate(self.scope.len() - count);
+                }
+            },
+            _ => walk_expr(self, expr),
+        }
+    }
+}
+
+struct Names<'a>(BTreeSet<&'a str>);
+
+impl<'a> ExprVisitor<'a> for Names<'a> {
+    fn visit_var(&mut self, name: &'a str) {
+        self.0.insert(name);
+    }
+    fn visit_binding(&mut self, name: &'a str) {
+        self.0.insert(name);
+    }
+}
+
+impl<'a> Definition<'a> {
+    // free variables of a group of (mutually recursive) definitions
+    pub fn free_variables(defs: &[Definition<'a>]) -> BTreeSet<&'a str> {
+        let mut bound: Vec<&'a str> = defs.iter().map(|def| def.name).collect();
+        let mut free = BTreeSet::new();
+        for def in defs {
+            bound.push(def.argument);
+            collect(&def.body, &mut bound, &mut free);
+            bound.pop();
+        }
+        free
+    }
+}
+
+fn collect<'a>(expr: &Expr<'a>, bound: &mut Vec<&'a str>, free: &mut BTreeSet<&'a str>) {
+    use Expr::*;
+    match expr {
+        Var(name) => {
+            if !bound.contains(name) {
+                free.insert(name);
+            }
+        },
+        Lit(_) | Error(_) => {},
+        Unary{ child, .. } => collect(child, bound, free),
+        Binary{ left, right, .. } => {
+            collect(left, bound, free);
+            collect(right, bound, free)
+        },
+        IfThenElse{ condition, if_branch, else_branch } => {
+            collect(condition, bound, free);
+            collect(if_branch, bound, free);
+            collect(else_branch, bound, free)
+        },
+        While{ condition, body } => {
+            collect(condition, bound, free);
+            collect(body, bound, free)
+        },
+        Tuple{ fst, snd } => {
+            collect(fst, bound, free);
+            collect(snd, bound, free)
+        },
+        Let{ bindings, body } => {
+            for (name, binder) in bindings {
+                collect(binder, bound, free);
+                bound.push(name);
+            }
+            collect(body, bound, free);
+            bound.truncate(bound.len() - bindings.len());
+        },
+        Lambda{ name, body } => {
+            bound.push(name);
+            collect(body, bound, free);
+            bound.pop();
+        },
+        App{ left, right } => {
+            collect(left, bound, free);
+            collect(right, bound, free)
+        },
+        Seq(exprs) | List(exprs) => {
+            for expr in exprs {
+                collect(expr, bound, free)
+            }
+        },
+        Cons{ head, tail } => {
+            collect(head, bound, free);
+            collect(tail, bound, free)
+        },
+        Funs{ defs, body } => {
+            let names = defs.len();
+            bound.extend(defs.iter().map(|def| def.name));
+            for def in defs {
+                bound.push(def.argument);
+                collect(&def.body, bound, free);
+                bound.pop();
+            }
+            collect(body, bound, free);
+            bound.truncate(bound.len() - names);
+        },
+        Annot{ expr, .. } => collect(expr, bound, free),
+        Construct{ argument: Some(argument), .. } => collect(argument, bound, free),
+        Construct{ argument: None, .. } => {},
+        Raise(expr) => collect(expr, bound, free),
+        Handle{ expr, rules } => {
+            collect(expr, bound, free);
+            for rule in rules {
+                let names = rule.pattern.names();
+                let count = names.len();
+                bound.extend(names);
+                collect(&rule.body, bound, free);
+                bound.truncate(bound.len() - count);
+            }
+        },
+        Record(fields) => {
+            for (_, expr) in fields {
+                collect(expr, bound, free)
+            }
+        },
+        Select{ record, .. } => collect(record, bound, free),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::{parse, parse_indexed};
+
+    #[test]
+    fn scope_unit() {
+        let expr = parse("let val x = 1 in fn y => x + y + z end").unwrap();
+        assert_eq!(expr.free_vars(), ["z"].iter().cloned().collect());
+        assert_eq!(expr.bound_vars(), ["x", "y"].iter().cloned().collect());
+
+        let unbound_in = |source, globals| {
+            let (expr, table) = parse_indexed(source).unwrap();
+            unbound(source, &expr, &table, globals).into_iter()
+                .map(|u| (u.name, u.span.start))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(unbound_in("let val x = 1 in y end", &[]), vec![("y", 17)]);
+        assert_eq!(unbound_in("(let val x = 1 in x end) + x", &[]), vec![("x", 27)]);
+        assert_eq!(unbound_in("let val x = x in x end", &["x"]), vec![]);
+        assert_eq!(unbound_in("let fun f n = g n and g m = f m in f k end", &[]), vec![("k", 37)]);
+
+        let source = "1 +\n  nope";
+        let (expr, table) = parse_indexed(source).unwrap();
+        let rendered = unbound(source, &expr, &table, &[])[0].to_string();
+        assert_eq!(rendered, "error at 2:3: `nope` is not bound here\n  |   nope\n  |   ^^^^");
+    }
+}
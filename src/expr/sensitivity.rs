@@ -0,0 +1,156 @@
+use std::fmt;
+use std::collections::{BTreeSet};
+
+use crate::lexer::{Literal};
+use crate::expr::{Expr};
+use crate::expr::columnar::{Column, Values, eval_columns};
+use crate::expr::truth::{Cell, Domain, TruthError};
+use crate::locale::{message};
+
+// one input taking another value, which gives the condition the other one
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Flip<'a> {
+    pub input: &'a str,
+    pub from: Cell,
+    pub to: Cell,
+}
+
+// what `which_inputs_affect` found
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Sensitivity<'a> {
+    pub value: bool,
+    // an integer's nearest flipping values below and above it
+    pub flips: Vec<Flip<'a>>,
+    // the inputs no change tried flips
+    pub stable: Vec<&'a str>,
+}
+
+impl<'a> fmt::Display for Sensitivity<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.value)?;
+        let other = !self.value;
+        for Flip { input, from, to } in &self.flips {
+            writeln!(f, "{}", message("sensitivity-flip", &[input, to, from, &other]))?
+        }
+        for input in &self.stable {
+            writeln!(f, "{}", message("sensitivity-stable", &[input, &other]))?
+        }
+        Ok(())
+    }
+}
+
+// every integer written in `expr`, negated ones too
+fn literals(expr: &Expr, out: &mut BTreeSet<i64>) {
+    if let Expr::Lit(Literal::Integer(i)) = expr {
+        out.insert(*i);
+        out.insert(i.saturating_neg());
+    }
+    for child in expr.children() {
+        literals(child, out)
+    }
+}
+
+fn value<'a>(expr: &Expr<'a>, env: &[(&'a str, Cell)]) -> Result<bool, TruthError<'a>> {
+    let (bools, ints): (Vec<[bool; 1]>, Vec<[i64; 1]>) = env.iter()
+        .map(|(_, cell)| match cell {
+            Cell::Bool(b) => ([*b], [0]),
+            Cell::Int(i) => ([false], [*i]),
+        })
+        .unzip();
+    let columns: Vec<(&'a str, Column)> = env.iter().enumerate()
+        .map(|(i, (name, cell))| match cell {
+            Cell::Bool(_) => (*name, Column::Bool(&bools[i])),
+            Cell::Int(_) => (*name, Column::Int(&ints[i])),
+        })
+        .collect();
+    match eval_columns(expr, &columns).map_err(TruthError::Column)? {
+        Values::Bool(values) => Ok(values[0]),
+        Values::Int(_) => Err(TruthError::NotBoolean),
+    }
+}
+
+// which inputs of the condition `expr` could have changed its value in
+// `env` on their own, for explaining why a rule did or did not fire. a
+// boolean is tried the other way, an integer at the values of its domain
+// or else at the integers `expr` compares against and either side of them.
+// a change that raises flips nothing, and one that only arithmetic on an
+// integer reaches is missed unless its domain has it
+pub fn which_inputs_affect<'a>(
+    expr: &Expr<'a>,
+    env: &[(&'a str, Cell)],
+    domains: &[(&'a str, Domain)],
+) -> Result<Sensitivity<'a>, TruthError<'a>> {
+    let value_in_env = value(expr, env)?;
+    let mut thresholds = BTreeSet::new();
+    literals(expr, &mut thresholds);
+    let mut flips = vec![];
+    let mut stable = vec![];
+    for (i, (input, from)) in env.iter().enumerate() {
+        let flips_with = |to: Cell| {
+            let mut changed = env.to_vec();
+            changed[i].1 = to;
+            matches!(value(expr, &changed), Ok(value) if value != value_in_env)
+        };
+        let found: Vec<Cell> = match (from, domains.iter().find(|(name, _)| name == input)) {
+            (Cell::Bool(b), _) => Some(Cell::Bool(!b)).filter(|to| flips_with(*to)).into_iter().collect(),
+            (Cell::Int(from), domain) => {
+                let candidates: BTreeSet<i64> = match domain {
+                    Some((_, Domain::Ints(ints))) => ints.iter().copied().collect(),
+                    _ => thresholds.iter().flat_map(|c| vec![c.saturating_sub(1), *c, c.saturating_add(1)]).collect(),
+                };
+                let below = candidates.range(..*from).rev().copied().find(|to| flips_with(Cell::Int(*to)));
+                let above = candidates.range(from.saturating_add(1)..).copied().find(|to| flips_with(Cell::Int(*to)));
+                below.into_iter().chain(above).map(Cell::Int).collect()
+            },
+        };
+        if found.is_empty() {
+            stable.push(*input)
+        }
+        flips.extend(found.into_iter().map(|to| Flip { input, from: *from, to }));
+    }
+    Ok(Sensitivity { value: value_in_env, flips, stable })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::{parse};
+    use crate::expr::columnar::{ColumnError};
+
+    #[test]
+    fn which_inputs_affect_unit() {
+        let rule = parse("admin orelse (total >= 100 andalso not banned)").unwrap();
+        let env = [("admin", Cell::Bool(false)), ("total", Cell::Int(120)), ("banned", Cell::Bool(false))];
+        let why = which_inputs_affect(&rule, &env, &[]).unwrap();
+        assert!(why.value);
+        assert_eq!(why.flips, vec![
+            Flip { input: "total", from: Cell::Int(120), to: Cell::Int(99) },
+            Flip { input: "banned", from: Cell::Bool(false), to: Cell::Bool(true) },
+        ]);
+        assert_eq!(why.stable, vec!["admin"]);
+        assert_eq!(why.to_string(), concat!(
+            "true\n",
+            "`total` being 99 instead of 120 would make it false\n",
+            "`banned` being true instead of false would make it false\n",
+            "changing `admin` alone does not make it false\n",
+        ));
+
+        // an integer with a domain is only tried at its values, on both sides
+        let band = parse("level = 2").unwrap();
+        let levels: &[i64] = &[0, 1, 2, 3];
+        let why = which_inputs_affect(&band, &[("level", Cell::Int(0))], &[("level", Domain::Ints(levels))]).unwrap();
+        assert_eq!(why.flips, vec![Flip { input: "level", from: Cell::Int(0), to: Cell::Int(2) }]);
+        let why = which_inputs_affect(&band, &[("level", Cell::Int(2))], &[]).unwrap();
+        let to: Vec<Cell> = why.flips.iter().map(|flip| flip.to).collect();
+        assert_eq!(to, vec![Cell::Int(1), Cell::Int(3)]);
+
+        // a change that raises does not count
+        let guarded = parse("100 div n > 10").unwrap();
+        let divisors: &[i64] = &[0, 5, 20];
+        let why = which_inputs_affect(&guarded, &[("n", Cell::Int(5))], &[("n", Domain::Ints(divisors))]).unwrap();
+        assert_eq!(why.flips, vec![Flip { input: "n", from: Cell::Int(5), to: Cell::Int(20) }]);
+
+        let unbound = TruthError::Column(ColumnError::NotFound("banned"));
+        assert_eq!(which_inputs_affect(&rule, &env[..2], &[]), Err(unbound));
+    }
+}
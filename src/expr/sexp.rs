@@ -0,0 +1,490 @@
+use std::fmt;
+
+use combine::{EasyParser};
+
+use crate::lexer::{self, Literal, Span};
+use crate::runtime::real::{Real};
+use crate::expr::{UnaryOp, BinaryOp, TypeExpr, Definition, Pattern, Rule, Expr, MAX_DEPTH};
+use crate::locale::{message};
+
+// trees as s-expressions, for tools written in a lisp and for golden files.
+// a variable is its name and a literal is written as in ferus, except for
+// negative numbers which take a `-`. every other node is a list headed by
+// what it is:
+//
+//   (if c a b) (tuple a b) (let x binder ... body) (fn x body) (app f a)
+//   (seq a ...) (list a ...) (:: head tail) (fun ((f x body) ...) body)
+//   (: e type) (con Name) (con Name a) (raise e) (handle e (pattern body) ...)
+//   (while c body) (record (label e) ...) (# label e) (error start end)
+//
+// operators head their operands, `(+ a b)`, `(not a)`. types are `int`,
+// `bool` and the like, a datatype's name, `(* a b)`, `(list a)` and
+// `(-> a b)`. patterns are `_`, a name, `(con Name)`, `(con Name p)` and
+// `(record (label p) ...)`. reading skips `;` comments to the end of a line
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum SexpError {
+    // the text ends before the tree does
+    Truncated,
+    // a list headed by something no node is, at that byte offset
+    UnknownForm(usize),
+    // something that is not what its place says it is, at that byte offset
+    Invalid(usize),
+    // more text after the tree, at that byte offset
+    Trailing(usize),
+    TooDeep,
+}
+
+impl fmt::Display for SexpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SexpError::Truncated => write!(f, "{}", message("sexp-truncated", &[])),
+            SexpError::UnknownForm(at) => write!(f, "{}", message("sexp-unknown-form", &[at])),
+            SexpError::Invalid(at) => write!(f, "{}", message("sexp-invalid", &[at])),
+            SexpError::Trailing(at) => write!(f, "{}", message("sexp-trailing", &[at])),
+            SexpError::TooDeep => write!(f, "{}", message("too-deep", &[&MAX_DEPTH])),
+        }
+    }
+}
+
+fn literal(lit: &Literal) -> String {
+    match lit {
+        Literal::Integer(i) => i.to_string(),
+        Literal::Real(x) => format!("{:?}", x.value()),
+        Literal::String(text) => format!("\"{}\"", text.chars().map(lexer::escape).collect::<String>()),
+        lit => lit.to_string(),
+    }
+}
+
+fn ty(ty: &TypeExpr) -> String {
+    match ty {
+        TypeExpr::Unit => "unit".to_string(),
+        TypeExpr::Int => "int".to_string(),
+        TypeExpr::Bool => "bool".to_string(),
+        TypeExpr::String => "string".to_string(),
+        TypeExpr::Char => "char".to_string(),
+        TypeExpr::Real => "real".to_string(),
+        TypeExpr::Tuple(fst, snd) => format!("(* {} {})", self::ty(fst), self::ty(snd)),
+        TypeExpr::List(elem) => format!("(list {})", self::ty(elem)),
+        TypeExpr::Arrow(from, to) => format!("(-> {} {})", self::ty(from), self::ty(to)),
+        TypeExpr::Named(name) => name.clone(),
+    }
+}
+
+fn pattern(pattern: &Pattern) -> String {
+    match pattern {
+        Pattern::Wildcard => "_".to_string(),
+        Pattern::Var(name) => name.to_string(),
+        Pattern::Construct{ name, argument: None } => format!("(con {})", name),
+        Pattern::Construct{ name, argument: Some(argument) } => format!("(con {} {})", name, self::pattern(argument)),
+        Pattern::Record(fields) => {
+            let fields: Vec<String> = fields.iter()
+                .map(|(label, pattern)| format!(" ({} {})", label, self::pattern(pattern)))
+                .collect();
+            format!("(record{})", fields.concat())
+        },
+    }
+}
+
+// `(head a b ...)`
+fn form(head: &str, items: Vec<String>) -> String {
+    let items: Vec<String> = items.into_iter().map(|item| format!(" {}", item)).collect();
+    format!("({}{})", head, items.concat())
+}
+
+fn exprs(exprs: &[Expr]) -> Vec<String> {
+    exprs.iter().map(Expr::to_sexp).collect()
+}
+
+// what the text of an s-expression reads as
+#[derive(Debug)]
+enum Sexp<'a> {
+    Atom(&'a str),
+    // the text between the quotes, without escapes
+    Str(&'a str),
+    List(Vec<(usize, Sexp<'a>)>),
+}
+
+struct Reader<'a> {
+    text: &'a str,
+    at: usize,
+    depth: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn skip(&mut self) {
+        loop {
+            let rest = &self.text[self.at..];
+            let trimmed = rest.trim_start();
+            self.at += rest.len() - trimmed.len();
+            if !trimmed.starts_with(';') {
+                return
+            }
+            self.at += trimmed.find('\n').unwrap_or(trimmed.len())
+        }
+    }
+    fn sexp(&mut self) -> Result<(usize, Sexp<'a>), SexpError> {
+        self.skip();
+        let at = self.at;
+        let rest = &self.text[at..];
+        match rest.chars().next() {
+            None => Err(SexpError::Truncated),
+            Some(')') => Err(SexpError::Invalid(at)),
+            Some('(') => {
+                if self.depth >= MAX_DEPTH {
+                    return Err(SexpError::TooDeep)
+                }
+                self.depth += 1;
+                self.at += 1;
+                let mut items = vec![];
+                loop {
+                    self.skip();
+                    match self.text[self.at..].chars().next() {
+                        None => return Err(SexpError::Truncated),
+                        Some(')') => break,
+                        Some(_) => items.push(self.sexp()?),
+                    }
+                }
+                self.at += 1;
+                self.depth -= 1;
+                Ok((at, Sexp::List(items)))
+            },
+            Some('"') => {
+                let len = rest[1..].find('"').ok_or(SexpError::Truncated)?;
+                let text = &rest[1..1 + len];
+                // the tree borrows its strings from the text, so they can
+                // not be unescaped
+                if text.contains('\\') {
+                    return Err(SexpError::Invalid(at))
+                }
+                self.at += len + 2;
+                Ok((at, Sexp::Str(text)))
+            },
+            Some(_) => {
+                let len = if rest.starts_with("#\"") {
+                    let (_, after) = lexer::character().easy_parse(rest).map_err(|_| SexpError::Invalid(at))?;
+                    rest.len() - after.len()
+                } else {
+                    rest.find(|c: char| c.is_whitespace() || "();\"".contains(c)).unwrap_or(rest.len())
+                };
+                self.at += len;
+                Ok((at, Sexp::Atom(&rest[..len])))
+            },
+        }
+    }
+}
+
+fn atom<'a>((at, sexp): &(usize, Sexp<'a>)) -> Result<&'a str, SexpError> {
+    match sexp {
+        Sexp::Atom(atom) => Ok(atom),
+        _ => Err(SexpError::Invalid(*at)),
+    }
+}
+
+fn list<'s, 'a>((at, sexp): &'s (usize, Sexp<'a>)) -> Result<&'s [(usize, Sexp<'a>)], SexpError> {
+    match sexp {
+        Sexp::List(items) => Ok(items),
+        _ => Err(SexpError::Invalid(*at)),
+    }
+}
+
+// the items of a form after its head, when there are `count` of them
+fn args<'s, 'a>(
+    at: usize,
+    items: &'s [(usize, Sexp<'a>)],
+    count: usize,
+) -> Result<&'s [(usize, Sexp<'a>)], SexpError> {
+    Some(&items[1..]).filter(|args| args.len() == count).ok_or(SexpError::Invalid(at))
+}
+
+fn number(at: usize, atom: &str) -> Result<Literal<'static>, SexpError> {
+    if atom.contains(['.', 'e', 'E']) {
+        Real::parse(atom).map(Literal::Real).ok_or(SexpError::Invalid(at))
+    } else {
+        atom.parse().map(Literal::Integer).map_err(|_| SexpError::Invalid(at))
+    }
+}
+
+fn read_expr<'a>(sexp: &(usize, Sexp<'a>)) -> Result<Expr<'a>, SexpError> {
+    use Expr::*;
+    let at = sexp.0;
+    let items = match &sexp.1 {
+        Sexp::Str(text) => return Ok(Lit(Literal::String(text))),
+        Sexp::Atom(atom) => return Ok(match *atom {
+            "true" => Lit(Literal::Boolean(true)),
+            "false" => Lit(Literal::Boolean(false)),
+            atom if atom.starts_with("#\"") => match lexer::character().easy_parse(atom) {
+                Ok((lit, _)) => Lit(lit),
+                Err(_) => return Err(SexpError::Invalid(at)),
+            },
+            atom if atom.trim_start_matches('-').starts_with(|c: char| c.is_ascii_digit()) => Lit(number(at, atom)?),
+            atom => Var(atom),
+        }),
+        Sexp::List(items) if items.is_empty() => return Ok(Lit(Literal::Unit)),
+        Sexp::List(items) => items,
+    };
+    let boxed = |sexp| read_expr(sexp).map(Box::new);
+    let head = atom(&items[0])?;
+    if let Some(operation) = UNARY.iter().find(|op| op.to_string() == head) {
+        let args = args(at, items, 1)?;
+        return Ok(Unary{ operation: *operation, child: boxed(&args[0])? })
+    }
+    if let Some(operation) = BINARY.iter().find(|op| op.to_string() == head) {
+        let args = args(at, items, 2)?;
+        return Ok(Binary{ operation: *operation, left: boxed(&args[0])?, right: boxed(&args[1])? })
+    }
+    Ok(match head {
+        "if" => {
+            let args = args(at, items, 3)?;
+            IfThenElse{ condition: boxed(&args[0])?, if_branch: boxed(&args[1])?, else_branch: boxed(&args[2])? }
+        },
+        "tuple" => {
+            let args = args(at, items, 2)?;
+            Tuple{ fst: boxed(&args[0])?, snd: boxed(&args[1])? }
+        },
+        // a name and a binder for each binding, then the body
+        "let" => {
+            let (body, bindings) = match items[1..].split_last() {
+                Some((body, bindings)) if !bindings.is_empty() && bindings.len() % 2 == 0 => (body, bindings),
+                _ => return Err(SexpError::Invalid(at)),
+            };
+            let bindings = bindings.chunks(2)
+                .map(|binding| Ok((atom(&binding[0])?, read_expr(&binding[1])?)))
+                .collect::<Result<_, _>>()?;
+            Let{ bindings, body: boxed(body)? }
+        },
+        "fn" => {
+            let args = args(at, items, 2)?;
+            Lambda{ name: atom(&args[0])?, body: boxed(&args[1])? }
+        },
+        "app" => {
+            let args = args(at, items, 2)?;
+            App{ left: boxed(&args[0])?, right: boxed(&args[1])? }
+        },
+        // never empty, as out of the parser
+        "seq" if items.len() < 2 => return Err(SexpError::Invalid(at)),
+        "seq" => Seq(items[1..].iter().map(read_expr).collect::<Result<_, _>>()?),
+        "list" => List(items[1..].iter().map(read_expr).collect::<Result<_, _>>()?),
+        "::" => {
+            let args = args(at, items, 2)?;
+            Cons{ head: boxed(&args[0])?, tail: boxed(&args[1])? }
+        },
+        "fun" => {
+            let args = args(at, items, 2)?;
+            let defs = list(&args[0])?.iter()
+                .map(|def| {
+                    let parts = list(def)?;
+                    if parts.len() != 3 {
+                        return Err(SexpError::Invalid(def.0))
+                    }
+                    Ok(Definition { name: atom(&parts[0])?, argument: atom(&parts[1])?, body: boxed(&parts[2])? })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            if defs.is_empty() {
+                return Err(SexpError::Invalid(at))
+            }
+            Funs{ defs, body: boxed(&args[1])? }
+        },
+        ":" => {
+            let args = args(at, items, 2)?;
+            Annot{ expr: boxed(&args[0])?, ty: read_ty(&args[1])? }
+        },
+        "con" => match &items[1..] {
+            [name] => Construct{ name: atom(name)?, argument: None },
+            [name, argument] => Construct{ name: atom(name)?, argument: Some(boxed(argument)?) },
+            _ => return Err(SexpError::Invalid(at)),
+        },
+        "raise" => Raise(boxed(&args(at, items, 1)?[0])?),
+        "handle" if 1 < items.len() => {
+            let rules = items[2..].iter()
+                .map(|rule| match list(rule)? {
+                    [pattern, body] => Ok(Rule { pattern: read_pattern(pattern)?, body: boxed(body)? }),
+                    _ => Err(SexpError::Invalid(rule.0)),
+                })
+                .collect::<Result<_, _>>()?;
+            Handle{ expr: boxed(&items[1])?, rules }
+        },
+        "while" => {
+            let args = args(at, items, 2)?;
+            While{ condition: boxed(&args[0])?, body: boxed(&args[1])? }
+        },
+        "record" => Record(fields(&items[1..], read_expr)?),
+        "#" => {
+            let args = args(at, items, 2)?;
+            Select{ label: atom(&args[0])?, record: boxed(&args[1])? }
+        },
+        "error" => {
+            let args = args(at, items, 2)?;
+            let offset = |sexp| atom(sexp)?.parse().map_err(|_| SexpError::Invalid(sexp.0));
+            Error(Span::new(offset(&args[0])?, offset(&args[1])?))
+        },
+        _ => return Err(SexpError::UnknownForm(items[0].0)),
+    })
+}
+
+// `(label x)` pairs
+fn fields<'a, T, F>(items: &[(usize, Sexp<'a>)], read: F) -> Result<Vec<(&'a str, T)>, SexpError>
+where F: Fn(&(usize, Sexp<'a>)) -> Result<T, SexpError>
+{
+    items.iter()
+        .map(|field| match list(field)? {
+            [label, value] => Ok((atom(label)?, read(value)?)),
+            _ => Err(SexpError::Invalid(field.0)),
+        })
+        .collect()
+}
+
+fn read_ty(sexp: &(usize, Sexp)) -> Result<TypeExpr, SexpError> {
+    let at = sexp.0;
+    if let Sexp::Atom(atom) = sexp.1 {
+        return Ok(match atom {
+            "unit" => TypeExpr::Unit,
+            "int" => TypeExpr::Int,
+            "bool" => TypeExpr::Bool,
+            "string" => TypeExpr::String,
+            "char" => TypeExpr::Char,
+            "real" => TypeExpr::Real,
+            name => TypeExpr::Named(name.to_string()),
+        })
+    }
+    let items = list(sexp)?;
+    let boxed = |sexp| read_ty(sexp).map(Box::new);
+    Ok(match items.first().map(atom).transpose()? {
+        Some("*") => TypeExpr::Tuple(boxed(&args(at, items, 2)?[0])?, boxed(&items[2])?),
+        Some("list") => TypeExpr::List(boxed(&args(at, items, 1)?[0])?),
+        Some("->") => TypeExpr::Arrow(boxed(&args(at, items, 2)?[0])?, boxed(&items[2])?),
+        Some(_) => return Err(SexpError::UnknownForm(items[0].0)),
+        None => return Err(SexpError::Invalid(at)),
+    })
+}
+
+fn read_pattern<'a>(sexp: &(usize, Sexp<'a>)) -> Result<Pattern<'a>, SexpError> {
+    let at = sexp.0;
+    if let Sexp::Atom(atom) = sexp.1 {
+        return Ok(if atom == "_" { Pattern::Wildcard } else { Pattern::Var(atom) })
+    }
+    let items = list(sexp)?;
+    Ok(match (items.first().map(atom).transpose()?, items.get(1..).unwrap_or(&[])) {
+        (Some("con"), [name]) => Pattern::Construct{ name: atom(name)?, argument: None },
+        (Some("con"), [name, argument]) => Pattern::Construct{
+            name: atom(name)?,
+            argument: Some(Box::new(read_pattern(argument)?)),
+        },
+        (Some("record"), fields) => Pattern::Record(self::fields(fields, read_pattern)?),
+        (Some("con"), _) | (None, _) => return Err(SexpError::Invalid(at)),
+        (Some(_), _) => return Err(SexpError::UnknownForm(items[0].0)),
+    })
+}
+
+const UNARY: [UnaryOp; 7] = {
+    use UnaryOp::*;
+    [Not, Fst, Snd, Print, Neg, Ord, Chr]
+};
+const BINARY: [BinaryOp; 14] = {
+    use BinaryOp::*;
+    [
+        Add, Sub, Mult, Div, Mod, Divide,
+        Equal, NotEqual, LessThan, LessEqual, GreaterThan, GreaterEqual,
+        OrElse, AndAlso,
+    ]
+};
+
+impl<'a> Expr<'a> {
+    // the tree on one line, see the top of this file
+    pub fn to_sexp(&self) -> String {
+        use Expr::*;
+        match self {
+            Var(name) => name.to_string(),
+            Lit(lit) => literal(lit),
+            Unary{ operation, child } => form(&operation.to_string(), vec![child.to_sexp()]),
+            Binary{ left, operation, right } => form(&operation.to_string(), vec![left.to_sexp(), right.to_sexp()]),
+            IfThenElse{ condition, if_branch, else_branch } => {
+                form("if", vec![condition.to_sexp(), if_branch.to_sexp(), else_branch.to_sexp()])
+            },
+            Tuple{ fst, snd } => form("tuple", vec![fst.to_sexp(), snd.to_sexp()]),
+            Let{ bindings, body } => form("let", bindings.iter()
+                .flat_map(|(name, binder)| vec![name.to_string(), binder.to_sexp()])
+                .chain(Some(body.to_sexp()))
+                .collect()),
+            Lambda{ name, body } => form("fn", vec![name.to_string(), body.to_sexp()]),
+            App{ left, right } => form("app", vec![left.to_sexp(), right.to_sexp()]),
+            Seq(sequence) => form("seq", exprs(sequence)),
+            List(elements) => form("list", exprs(elements)),
+            Cons{ head, tail } => form("::", vec![head.to_sexp(), tail.to_sexp()]),
+            Funs{ defs, body } => {
+                let defs: Vec<String> = defs.iter()
+                    .map(|def| format!("({} {} {})", def.name, def.argument, def.body.to_sexp()))
+                    .collect();
+                form("fun", vec![format!("({})", defs.join(" ")), body.to_sexp()])
+            },
+            Annot{ expr, ty } => form(":", vec![expr.to_sexp(), self::ty(ty)]),
+            Construct{ name, argument } => {
+                form("con", Some(name.to_string()).into_iter().chain(argument.iter().map(|a| a.to_sexp())).collect())
+            },
+            Raise(expr) => form("raise", vec![expr.to_sexp()]),
+            Handle{ expr, rules } => {
+                let rules = rules.iter().map(|rule| format!("({} {})", pattern(&rule.pattern), rule.body.to_sexp()));
+                form("handle", Some(expr.to_sexp()).into_iter().chain(rules).collect())
+            },
+            While{ condition, body } => form("while", vec![condition.to_sexp(), body.to_sexp()]),
+            Record(fields) => {
+                form("record", fields.iter().map(|(label, e)| format!("({} {})", label, e.to_sexp())).collect())
+            },
+            Select{ label, record } => form("#", vec![label.to_string(), record.to_sexp()]),
+            Error(span) => form("error", vec![span.start.to_string(), span.end.to_string()]),
+        }
+    }
+    // the tree `text` writes, its names borrowed from `text`
+    pub fn from_sexp(text: &'a str) -> Result<Expr<'a>, SexpError> {
+        let mut reader = Reader { text, at: 0, depth: 0 };
+        let sexp = reader.sexp()?;
+        reader.skip();
+        if reader.at < text.len() {
+            return Err(SexpError::Trailing(reader.at))
+        }
+        read_expr(&sexp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::{parse};
+
+    #[test]
+    fn sexp_unit() {
+        let source = "let fun f n = if n < 2 then n else f (n - 1) in \
+            (((f (~10) handle Fail {msg, code = _} => 0 | Some x => x, [#x {x = 1, y = #\"\\\"\"}, ()]) \
+            : int * (t -> real list)); while false do (); fn y => y :: nil; 2.5) end";
+        let expr = parse(source).unwrap();
+        let sexp = expr.to_sexp();
+        assert_eq!(Expr::from_sexp(&sexp), Ok(expr));
+        // parentheses are a sequence of one
+        let expr = parse("if x <= 1 then f x else ~(x div 2)").unwrap();
+        assert_eq!(expr.to_sexp(), "(if (<= x 1) (app f x) (~ (seq (div x 2))))");
+
+        // what does not come from source
+        let built = Expr::Tuple{
+            fst: Box::new(Expr::Lit(Literal::Integer(-3))),
+            snd: Box::new(Expr::Seq(vec![Expr::Lit(Literal::String("a b")), Expr::Error(Span::new(4, 9))])),
+        };
+        assert_eq!(built.to_sexp(), "(tuple -3 (seq \"a b\" (error 4 9)))");
+        assert_eq!(Expr::from_sexp(&built.to_sexp()), Ok(built));
+        let real = Expr::Lit(Literal::Real(Real::new(-1e-9).unwrap()));
+        assert_eq!(Expr::from_sexp(&real.to_sexp()), Ok(real));
+        let text = "; a golden file\n(app (fn x (+ x 1)) ; the argument\n 41)\n";
+        assert_eq!(Expr::from_sexp(text).unwrap().eval().unwrap().to_string(), "42");
+
+        assert_eq!(Expr::from_sexp("(app f"), Err(SexpError::Truncated));
+        assert_eq!(Expr::from_sexp("(apply f x)"), Err(SexpError::UnknownForm(1)));
+        assert_eq!(Expr::from_sexp("(if a b)"), Err(SexpError::Invalid(0)));
+        assert_eq!(Expr::from_sexp("(fn (x) x)"), Err(SexpError::Invalid(4)));
+        assert_eq!(Expr::from_sexp("(seq)"), Err(SexpError::Invalid(0)));
+        assert_eq!(Expr::from_sexp("(let x (seq) x)"), Err(SexpError::Invalid(7)));
+        assert_eq!(Expr::from_sexp("(fun () x)"), Err(SexpError::Invalid(0)));
+        assert_eq!(Expr::from_sexp("x y"), Err(SexpError::Trailing(2)));
+        assert_eq!(Expr::from_sexp("\"a\\\"b\""), Err(SexpError::Invalid(0)));
+        let deep = format!("{}x{}", "(not ".repeat(MAX_DEPTH + 1), ")".repeat(MAX_DEPTH + 1));
+        assert_eq!(Expr::from_sexp(&deep), Err(SexpError::TooDeep));
+    }
+}
@@ -0,0 +1,300 @@
+use std::ops::{Deref, DerefMut};
+
+use crate::lexer::{Literal, Span};
+use crate::expr::{UnaryOp, Definition, Expr, ERROR};
+use crate::expr::ids::{NodeId};
+
+// binding strength of each level of the grammar, an expression is wrapped in
+// parens when it sits in a slot that only accepts a tighter level
+pub(crate) const EXPN: usize = 0;
+pub(crate) const DISJ: usize = 1;
+pub(crate) const CONS: usize = 4;
+pub(crate) const UNAR: usize = 7;
+pub(crate) const APPN: usize = 8;
+pub(crate) const ATOM: usize = 9;
+
+impl<'a> Expr<'a> {
+    // concrete syntax with as few parens as the grammar allows. parens the
+    // source had are `Seq`s of one expression and print as written, so
+    // `parse(&e.to_source())` gives back `e` for any tree the parser made
+    pub fn to_source(&self) -> String {
+        self.to_source_marking(NodeId(u32::MAX)).0
+    }
+    // `to_source` along with where the node with pre-order id `id` ended up
+    // in the text
+    pub fn to_source_marking(&self, id: NodeId) -> (String, Option<Span>) {
+        let mut out = Out { text: String::new(), next: 0, target: id.0, found: None };
+        write(&mut out, self, EXPN);
+        (out.text, out.found)
+    }
+}
+
+// the text so far, counting nodes as they are written
+struct Out {
+    text: String,
+    next: u32,
+    target: u32,
+    found: Option<Span>,
+}
+
+impl Deref for Out {
+    type Target = String;
+    fn deref(&self) -> &String {
+        &self.text
+    }
+}
+
+impl DerefMut for Out {
+    fn deref_mut(&mut self) -> &mut String {
+        &mut self.text
+    }
+}
+
+fn parens<F>(out: &mut Out, inner: usize, outer: usize, cb: F)
+where F: FnOnce(&mut Out)
+{
+    if inner < outer {
+        out.push('(');
+        cb(out);
+        out.push(')');
+    } else {
+        cb(out)
+    }
+}
+
+fn literal(out: &mut Out, lit: &Literal, prec: usize) {
+    match lit {
+        // negative numbers are written as a negation
+        Literal::Integer(i) if *i < 0 => parens(out, UNAR, prec, |out| {
+            out.push_str(&format!("~{}", i.unsigned_abs()))
+        }),
+        Literal::Real(x) if x.value() < 0.0 => parens(out, UNAR, prec, |out| {
+            out.push_str(&format!("~{}", x.negate()))
+        }),
+        Literal::String(s) => out.push_str(&format!("{:?}", s)),
+        lit => out.push_str(&lit.to_string()),
+    }
+}
+
+fn definition(out: &mut Out, def: &Definition) {
+    out.push_str(&format!("{} {} = ", def.name, def.argument));
+    write(out, &def.body, EXPN)
+}
+
+fn write(out: &mut Out, expr: &Expr, prec: usize) {
+    let id = out.next;
+    let start = out.len();
+    out.next += 1;
+    write_node(out, expr, prec);
+    if id == out.target {
+        out.found = Some(Span::new(start, out.len()));
+    }
+}
+
+fn write_node(out: &mut Out, expr: &Expr, prec: usize) {
+    use Expr::*;
+    match expr {
+        Var(name) => out.push_str(name),
+        Lit(lit) => literal(out, lit, prec),
+        Error(_) => out.push_str(ERROR),
+        Unary{ operation, child } => {
+            let op_prec = operation.precedence();
+            parens(out, op_prec, prec, |out| {
+                match operation {
+                    UnaryOp::Neg => out.push('~'),
+                    _ => out.push_str(&format!("{} ", operation)),
+                }
+                write(out, child, APPN)
+            })
+        },
+        Binary{ left, operation, right } => {
+            let op_prec = operation.precedence();
+            // comparisons do not chain, everything else associates left
+            let left_prec = if op_prec == 3 { op_prec + 1 } else { op_prec };
+            parens(out, op_prec, prec, |out| {
+                write(out, left, left_prec);
+                out.push_str(&format!(" {} ", operation));
+                write(out, right, op_prec + 1)
+            })
+        },
+        IfThenElse{ condition, if_branch, else_branch } => parens(out, EXPN, prec, |out| {
+            out.push_str("if ");
+            write(out, condition, EXPN);
+            out.push_str(" then ");
+            write(out, if_branch, EXPN);
+            out.push_str(" else ");
+            write(out, else_branch, EXPN)
+        }),
+        While{ condition, body } => parens(out, EXPN, prec, |out| {
+            out.push_str("while ");
+            write(out, condition, EXPN);
+            out.push_str(" do ");
+            write(out, body, EXPN)
+        }),
+        Tuple{ fst, snd } => {
+            out.push('(');
+            write(out, fst, EXPN);
+            out.push_str(", ");
+            write(out, snd, EXPN);
+            out.push(')')
+        },
+        Let{ bindings, body } => parens(out, EXPN, prec, |out| {
+            out.push_str("let");
+            for (name, binder) in bindings {
+                out.push_str(&format!(" val {} = ", name));
+                write(out, binder, EXPN);
+            }
+            out.push_str(" in ");
+            write(out, body, EXPN);
+            out.push_str(" end")
+        }),
+        Lambda{ name, body } => parens(out, EXPN, prec, |out| {
+            out.push_str(&format!("fn {} => ", name));
+            write(out, body, EXPN)
+        }),
+        App{ left, right } => parens(out, APPN, prec, |out| {
+            write(out, left, APPN);
+            out.push(' ');
+            write(out, right, ATOM)
+        }),
+        Seq(sequence) => {
+            out.push('(');
+            for (i, expr) in sequence.iter().enumerate() {
+                if 0 < i {
+                    out.push_str("; ");
+                }
+                write(out, expr, EXPN)
+            }
+            out.push(')')
+        },
+        List(elements) => {
+            out.push('[');
+            for (i, expr) in elements.iter().enumerate() {
+                if 0 < i {
+                    out.push_str(", ");
+                }
+                write(out, expr, EXPN)
+            }
+            out.push(']')
+        },
+        Cons{ head, tail } => parens(out, CONS, prec, |out| {
+            write(out, head, CONS + 1);
+            out.push_str(" :: ");
+            write(out, tail, CONS)
+        }),
+        Funs{ defs, body } => parens(out, EXPN, prec, |out| {
+            out.push_str("let fun ");
+            for (i, def) in defs.iter().enumerate() {
+                if 0 < i {
+                    out.push_str(" and ");
+                }
+                definition(out, def)
+            }
+            out.push_str(" in ");
+            write(out, body, EXPN);
+            out.push_str(" end")
+        }),
+        Annot{ expr, ty } => parens(out, EXPN, prec, |out| {
+            write(out, expr, DISJ);
+            out.push_str(&format!(" : {}", ty))
+        }),
+        Construct{ name, argument: None } => out.push_str(name),
+        Construct{ name, argument: Some(argument) } => parens(out, APPN, prec, |out| {
+            out.push_str(name);
+            out.push(' ');
+            write(out, argument, ATOM)
+        }),
+        Raise(expr) => parens(out, EXPN, prec, |out| {
+            out.push_str("raise ");
+            write(out, expr, EXPN)
+        }),
+        Handle{ expr, rules } => parens(out, EXPN, prec, |out| {
+            // an annotation is the one expression level construct allowed
+            // in front of `handle`
+            write(out, expr, if let Annot{ .. } = **expr { EXPN } else { DISJ });
+            out.push_str(" handle ");
+            for (i, rule) in rules.iter().enumerate() {
+                if 0 < i {
+                    out.push_str(" | ");
+                }
+                out.push_str(&format!("{} => ", rule.pattern));
+                // a body ending in a `handle` of its own would take the rules
+                // after it along
+                let last = i + 1 == rules.len();
+                write(out, &rule.body, if last || !ends_in_handle(&rule.body) { EXPN } else { DISJ })
+            }
+        }),
+        Record(fields) => {
+            out.push('{');
+            for (i, (label, expr)) in fields.iter().enumerate() {
+                if 0 < i {
+                    out.push_str(", ");
+                }
+                out.push_str(&format!("{} = ", label));
+                write(out, expr, EXPN)
+            }
+            out.push('}')
+        },
+        Select{ label, record } => parens(out, UNAR, prec, |out| {
+            out.push_str(&format!("#{} ", label));
+            write(out, record, APPN)
+        }),
+    }
+}
+
+pub(crate) fn ends_in_handle(expr: &Expr) -> bool {
+    use Expr::*;
+    match expr {
+        Handle{ .. } => true,
+        Raise(expr) | Lambda{ body: expr, .. } | IfThenElse{ else_branch: expr, .. } | While{ body: expr, .. } => {
+            ends_in_handle(expr)
+        },
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::expr::{parse};
+
+    #[test]
+    fn to_source_roundtrip_unit() {
+        let tests = vec![
+            "1 - (2 - 3) - 4",
+            "(1 < 2) = true",
+            "not (f x) andalso not g y",
+            "f (g x) (fn y => y)",
+            "(1, (2, 3))",
+            "~1 - ~(x + 1) * f (~2)",
+            "1 :: 2 :: [] :: nil",
+            "(print 1; print 2; ())",
+            "let fun f n = if n < 1 then [] else n :: f (n - 1) in fst (f 3, 0) end",
+            "if a orelse b andalso c then x * (y + z) else (let val q = 1 in q end) + 1",
+            "(raise Fail 1) handle Fail (Some n) => n | Div => raise Div | _ => 0",
+            "(while not (done ()) do (print 1; step ())) handle Stop => ()",
+            r#"chr (ord #"a" + 1) :: #"\t" :: [#"\\"]"#,
+            "~1.5 * x / 2e~3 - ~(0.1 + y)",
+            "#x {x = {y = 1, x = f a}, y = #y p} handle Fail {code = c, why} => why | Fail {z = _} => 0",
+        ];
+        for test in tests {
+            let expr = parse(test).unwrap();
+            let source = expr.to_source();
+            assert_eq!(parse(&source).unwrap(), expr, "{}", source)
+        }
+    }
+
+    #[test]
+    fn to_source_minimal_unit() {
+        use crate::expr::{BinaryOp, Expr};
+        use crate::lexer::{Literal};
+        // trees built by hand have no grouping nodes, the printer adds what
+        // the grammar needs and nothing else
+        let int = |i| Box::new(Expr::Lit(Literal::Integer(i)));
+        let sub = |left, right| Box::new(Expr::Binary{ left, operation: BinaryOp::Sub, right });
+        let mult = |left, right| Expr::Binary{ left, operation: BinaryOp::Mult, right };
+        let expr = mult(sub(int(1), sub(int(2), int(3))), sub(int(4), int(-5)));
+        let source = expr.to_source();
+        assert_eq!(source, "(1 - (2 - 3)) * (4 - ~5)");
+        assert_eq!(parse(&source).unwrap().to_source(), source);
+    }
+}
@@ -0,0 +1,79 @@
+use std::hash::{Hash, Hasher};
+
+use crate::expr::{Expr};
+use crate::expr::visit::{ExprFolder, fold_children};
+use crate::lexer::{Span};
+
+// where a tree came from is in its `NodeTable`, the only spans in the tree
+// itself are those of the `Error`s recovery leaves. two parses of the same
+// program laid out differently should still be the same tree
+struct Unspan;
+
+impl<'a> ExprFolder<'a> for Unspan {
+    fn fold_expr(&mut self, expr: Expr<'a>) -> Expr<'a> {
+        match expr {
+            Expr::Error(_) => Expr::Error(Span::new(0, 0)),
+            expr => fold_children(self, expr),
+        }
+    }
+}
+
+impl<'a> Expr<'a> {
+    // the tree with every span the same empty one at the start
+    pub fn without_spans(&self) -> Expr<'a> {
+        self.clone().fold(&mut Unspan)
+    }
+    pub fn eq_modulo_spans(&self, other: &Expr<'a>) -> bool {
+        self.without_spans() == other.without_spans()
+    }
+}
+
+// a tree compared and hashed with its spans left out, for keying maps by
+// what the source says rather than where
+#[derive(Debug, Copy, Clone)]
+pub struct Spanless<'e, 'a>(pub &'e Expr<'a>);
+
+impl<'e, 'a> PartialEq for Spanless<'e, 'a> {
+    fn eq(&self, other: &Spanless<'e, 'a>) -> bool {
+        self.0.eq_modulo_spans(other.0)
+    }
+}
+
+impl<'e, 'a> Eq for Spanless<'e, 'a> {}
+
+impl<'e, 'a> Hash for Spanless<'e, 'a> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.without_spans().hash(state)
+    }
+}
+
+// `assert_eq!` for trees, spans left out of the comparison
+#[macro_export]
+macro_rules! assert_ast_eq {
+    ($left:expr, $right:expr $(,)?) => {
+        match (&$left, &$right) {
+            (left, right) => if !left.eq_modulo_spans(right) {
+                panic!("assertion failed: `left == right` modulo spans\n  left: `{:?}`\n right: `{:?}`", left, right)
+            },
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use crate::expr::recover::{parse_recovering};
+
+    #[test]
+    fn spanless_unit() {
+        let near = parse_recovering("f (1 + ]) 2").expr.unwrap();
+        let far = parse_recovering("f   (1 +   ]) 2").expr.unwrap();
+        assert_ne!(near, far);
+        assert_ast_eq!(near, far);
+        assert!(!near.eq_modulo_spans(&parse_recovering("f (2 + ]) 2").expr.unwrap()));
+
+        let trees: HashSet<Spanless> = vec![&near, &far].into_iter().map(Spanless).collect();
+        assert_eq!(trees.len(), 1);
+    }
+}
@@ -0,0 +1,182 @@
+use std::fmt;
+
+use crate::lexer::{Literal};
+use crate::expr::{UnaryOp, BinaryOp, Expr};
+use crate::locale::{message};
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum SqlError {
+    // what has no counterpart in a WHERE clause, like a function, a tuple,
+    // a real or a `print`
+    Unsupported(String),
+    TypeError(String),
+    // the whole expression is an integer rather than a condition on a row
+    NotAPredicate(String),
+}
+
+impl fmt::Display for SqlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            SqlError::Unsupported(source) => message("sql-unsupported", &[source]),
+            SqlError::TypeError(source) => message("sql-type-error", &[source]),
+            SqlError::NotAPredicate(source) => message("sql-not-a-predicate", &[source]),
+        };
+        write!(f, "{}", text)
+    }
+}
+
+// what a translated expression is known to be, a column can be either
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+enum Kind {
+    Int,
+    Bool,
+    Column,
+}
+
+impl Kind {
+    fn is(self, kind: Kind) -> bool {
+        self == kind || self == Kind::Column
+    }
+}
+
+// whether evaluating `expr` can raise `Div`, SQL makes no promise about
+// the order the operands of AND and OR are evaluated in
+fn divides(expr: &Expr) -> bool {
+    match expr {
+        Expr::Binary{ operation: BinaryOp::Div, .. } | Expr::Binary{ operation: BinaryOp::Mod, .. } => true,
+        expr => expr.children().into_iter().any(divides),
+    }
+}
+
+struct Sql<'a> {
+    // what the `let val`s around bind, the SQL of their binders
+    scope: Vec<(&'a str, String, Kind)>,
+}
+
+impl<'a> Sql<'a> {
+    fn translate(&mut self, expr: &Expr<'a>) -> Result<(String, Kind), SqlError> {
+        use Expr::*;
+        let type_error = || SqlError::TypeError(expr.to_source());
+        let operand = |sql: &mut Sql<'a>, expr: &Expr<'a>, kind: Kind| match sql.translate(expr)? {
+            (text, found) if found.is(kind) => Ok(text),
+            _ => Err(type_error()),
+        };
+        match expr {
+            Lit(Literal::Integer(i)) if *i < 0 => Ok((format!("({})", i), Kind::Int)),
+            Lit(Literal::Integer(i)) => Ok((i.to_string(), Kind::Int)),
+            Lit(Literal::Boolean(b)) => Ok((if *b { "TRUE" } else { "FALSE" }.to_string(), Kind::Bool)),
+            Var(name) => match self.scope.iter().rev().find(|(bound, _, _)| bound == name) {
+                Some((_, text, kind)) => Ok((text.clone(), *kind)),
+                // quoted, so the case of the name is kept
+                None => Ok((format!("\"{}\"", name), Kind::Column)),
+            },
+            Unary{ operation: UnaryOp::Not, child } => {
+                Ok((format!("(NOT {})", operand(self, child, Kind::Bool)?), Kind::Bool))
+            },
+            Unary{ operation: UnaryOp::Neg, child } => {
+                Ok((format!("(-{})", operand(self, child, Kind::Int)?), Kind::Int))
+            },
+            Binary{ left, operation: operation @ (BinaryOp::AndAlso | BinaryOp::OrElse), right } => {
+                let (left_text, right_text) = (operand(self, left, Kind::Bool)?, operand(self, right, Kind::Bool)?);
+                let text = match operation {
+                    // the right side only runs when the left one does not decide
+                    _ if divides(right) && *operation == BinaryOp::AndAlso => {
+                        format!("(CASE WHEN {} THEN {} ELSE FALSE END)", left_text, right_text)
+                    },
+                    _ if divides(right) => format!("(CASE WHEN {} THEN TRUE ELSE {} END)", left_text, right_text),
+                    BinaryOp::AndAlso => format!("({} AND {})", left_text, right_text),
+                    _ => format!("({} OR {})", left_text, right_text),
+                };
+                Ok((text, Kind::Bool))
+            },
+            Binary{ left, operation, right } => {
+                use BinaryOp::*;
+                let (symbol, kind) = match operation {
+                    Add => ("+", Kind::Int),
+                    Sub => ("-", Kind::Int),
+                    Mult => ("*", Kind::Int),
+                    // both round toward zero like SQL does
+                    Div => ("/", Kind::Int),
+                    Mod => ("%", Kind::Int),
+                    Equal => ("=", Kind::Bool),
+                    NotEqual => ("<>", Kind::Bool),
+                    LessThan => ("<", Kind::Bool),
+                    LessEqual => ("<=", Kind::Bool),
+                    GreaterThan => (">", Kind::Bool),
+                    GreaterEqual => (">=", Kind::Bool),
+                    Divide | AndAlso | OrElse => return Err(SqlError::Unsupported(expr.to_source())),
+                };
+                let (left, right) = (operand(self, left, Kind::Int)?, operand(self, right, Kind::Int)?);
+                Ok((format!("({} {} {})", left, symbol, right), kind))
+            },
+            IfThenElse{ condition, if_branch, else_branch } => {
+                let condition = operand(self, condition, Kind::Bool)?;
+                let (if_text, if_kind) = self.translate(if_branch)?;
+                let (else_text, else_kind) = self.translate(else_branch)?;
+                let kind = match (if_kind, else_kind) {
+                    (Kind::Column, kind) | (kind, Kind::Column) => kind,
+                    (if_kind, else_kind) if if_kind == else_kind => if_kind,
+                    _ => return Err(type_error()),
+                };
+                Ok((format!("(CASE WHEN {} THEN {} ELSE {} END)", condition, if_text, else_text), kind))
+            },
+            // the binder is written out at each use, there is nothing to
+            // name it with inside a WHERE clause
+            Let{ bindings, body } => {
+                let depth = self.scope.len();
+                let res = bindings.iter()
+                    .try_for_each(|(name, binder)| {
+                        let (text, kind) = self.translate(binder)?;
+                        self.scope.push((name, text, kind));
+                        Ok(())
+                    })
+                    .and_then(|()| self.translate(body));
+                self.scope.truncate(depth);
+                res
+            },
+            // parentheses
+            Seq(sequence) if sequence.len() == 1 => self.translate(&sequence[0]),
+            Annot{ expr, .. } => self.translate(expr),
+            _ => Err(SqlError::Unsupported(expr.to_source())),
+        }
+    }
+}
+
+// `expr` as the condition of a SQL WHERE clause, its free variables being
+// the columns of the same names, so a rule written in ferus can filter rows
+// in a database. only integers and booleans translate, and columns are
+// taken to never be NULL, which ferus has nothing like
+pub fn to_sql_where(expr: &Expr) -> Result<String, SqlError> {
+    match (Sql { scope: vec![] }).translate(expr)? {
+        (_, Kind::Int) => Err(SqlError::NotAPredicate(expr.to_source())),
+        (text, _) => Ok(text),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::{parse};
+
+    #[test]
+    fn to_sql_where_unit() {
+        let sql = |source| to_sql_where(&parse(source).unwrap());
+        assert_eq!(
+            sql("let val total = price * qty in total >= 100 andalso not refunded end"),
+            Ok("(((\"price\" * \"qty\") >= 100) AND (NOT \"refunded\"))".to_string()),
+        );
+        assert_eq!(
+            sql("if vip then ~5 < balance else balance > 0"),
+            Ok("(CASE WHEN \"vip\" THEN ((-5) < \"balance\") ELSE (\"balance\" > 0) END)".to_string()),
+        );
+        // the division only happens where the count is not zero
+        assert_eq!(
+            sql("count <> 0 andalso total div count > 10"),
+            Ok("(CASE WHEN (\"count\" <> 0) THEN ((\"total\" / \"count\") > 10) ELSE FALSE END)".to_string()),
+        );
+
+        assert_eq!(sql("price * 2"), Err(SqlError::NotAPredicate("price * 2".into())));
+        assert_eq!(sql("price + true > 1"), Err(SqlError::TypeError("price + true".into())));
+        assert_eq!(sql("(fn x => x) flag"), Err(SqlError::Unsupported("(fn x => x) flag".into())));
+    }
+}
@@ -0,0 +1,405 @@
+use crate::expr::{UnaryOp, BinaryOp, Expr};
+use crate::expr::eval::{chr, CHR};
+use crate::runtime::width::{Width};
+use crate::expr::ids::{NodeId};
+use crate::expr::owned::{OwnedExpr, OwnedLiteral, OwnedPattern, OwnedRule};
+
+// a single reduction, `redex` is the node of the tree before the step that
+// was rewritten
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub expr: OwnedExpr,
+    pub redex: NodeId,
+    // how many nodes waiting on a value were around the redex, what a call
+    // stack would hold
+    pub depth: usize,
+    // whether the redex was rewritten to a value
+    pub value: bool,
+    // what a `print` redex wrote
+    pub printed: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Progress {
+    Step(Step),
+    Value,
+    // the node can not reduce, a type error or a free variable
+    Stuck(NodeId),
+    // the whole expression is a `raise` nothing handled
+    Raised,
+}
+
+impl<'a> Expr<'a> {
+    // the next step of a call by value, left to right evaluation by
+    // substitution. parens and annotations take no step of their own
+    pub fn step(&self) -> Progress {
+        let mut expr = self.clone().into_owned();
+        let mut effects = Effects::default();
+        match reduce(&mut expr, 0, 0, &mut effects) {
+            Outcome::Value => Progress::Value,
+            Outcome::Stepped(id) => {
+                let Effects { depth, value, printed } = effects;
+                Progress::Step(Step { expr, redex: NodeId(id), depth, value, printed })
+            },
+            Outcome::Stuck(id) => Progress::Stuck(NodeId(id)),
+            Outcome::Raised(_) => Progress::Raised,
+        }
+    }
+
+    // every tree the machine goes through from this one, at most
+    // `max_steps` steps. `step` on the last says why it stopped
+    pub fn reductions(&self, max_steps: usize) -> Vec<OwnedExpr> {
+        let mut trees = vec![self.clone().into_owned()];
+        for _ in 0..max_steps {
+            match trees.last().unwrap().as_expr().step() {
+                Progress::Step(step) => trees.push(step.expr),
+                _ => break,
+            }
+        }
+        trees
+    }
+}
+
+enum Outcome {
+    Value,
+    Stepped(u32),
+    Stuck(u32),
+    // a `raise` of a value, on its way out to a `handle`
+    Raised(OwnedExpr),
+}
+
+// what a step did besides rewriting the tree
+#[derive(Default)]
+struct Effects {
+    depth: usize,
+    value: bool,
+    printed: Option<String>,
+}
+
+enum Contract {
+    Value,
+    Reduced(OwnedExpr),
+    Stuck,
+    Raised,
+}
+
+// the children that have to be values before `expr` can reduce, always a
+// prefix of its children so their ids follow each other
+fn strict(expr: &mut OwnedExpr) -> Vec<&mut OwnedExpr> {
+    use OwnedExpr::*;
+    match expr {
+        Var(_) | Lit(_) | Lambda{ .. } | Funs{ .. } | While{ .. } | Error(_) => vec![],
+        Unary{ child, .. } => vec![child],
+        Binary{ left, operation: BinaryOp::OrElse, .. } | Binary{ left, operation: BinaryOp::AndAlso, .. } => {
+            vec![left]
+        },
+        Binary{ left, right, .. } | App{ left, right } => vec![left, right],
+        Tuple{ fst, snd } => vec![fst, snd],
+        Cons{ head, tail } => vec![head, tail],
+        IfThenElse{ condition, .. } => vec![condition],
+        // only the first binding, the rest may refer to it
+        Let{ bindings, .. } => bindings.iter_mut().take(1).map(|(_, binder)| binder).collect(),
+        Seq(sequence) => sequence.iter_mut().take(1).collect(),
+        List(elements) => elements.iter_mut().collect(),
+        Annot{ expr, .. } => vec![expr],
+        Construct{ argument, .. } => argument.iter_mut().map(|argument| &mut **argument).collect(),
+        Raise(expr) | Handle{ expr, .. } | Select{ record: expr, .. } => vec![expr],
+        Record(fields) => fields.iter_mut().map(|(_, expr)| expr).collect(),
+    }
+}
+
+fn is_value(expr: &OwnedExpr) -> bool {
+    use OwnedExpr::*;
+    match peel(expr) {
+        Lit(_) | Lambda{ .. } => true,
+        Tuple{ fst, snd } => is_value(fst) && is_value(snd),
+        List(elements) => elements.iter().all(is_value),
+        Construct{ argument, .. } => argument.iter().all(|argument| is_value(argument)),
+        Record(fields) => fields.iter().all(|(_, expr)| is_value(expr)),
+        _ => false,
+    }
+}
+
+fn reduce(expr: &mut OwnedExpr, id: u32, depth: usize, effects: &mut Effects) -> Outcome {
+    let mut child_id = id + 1;
+    let mut inner = None;
+    for child in strict(expr) {
+        match reduce(child, child_id, depth + 1, effects) {
+            Outcome::Value => child_id += child.as_expr().size(),
+            outcome => {
+                inner = Some(outcome);
+                break
+            },
+        }
+    }
+    if let Some(Outcome::Raised(raised)) = inner {
+        // a raise replaces everything around it up to the nearest handle,
+        // which goes on with its first rule that matches
+        *expr = match (&*expr, &raised) {
+            (OwnedExpr::Handle{ rules, .. }, OwnedExpr::Raise(value)) => {
+                handler(rules, value).unwrap_or(raised)
+            },
+            _ => raised,
+        };
+        effects.depth = depth;
+        effects.value = is_value(expr);
+        return Outcome::Stepped(id)
+    }
+    if let Some(outcome) = inner {
+        // parens around what just became a value have done their job
+        if let OwnedExpr::Seq(sequence) = expr {
+            if matches!(outcome, Outcome::Stepped(_)) && sequence.len() == 1 && is_value(&sequence[0]) {
+                let value = sequence.remove(0);
+                *expr = value;
+            }
+        }
+        return outcome
+    }
+    match contract(expr, &mut effects.printed) {
+        Contract::Value => Outcome::Value,
+        Contract::Reduced(reduced) => {
+            effects.depth = depth;
+            effects.value = is_value(&reduced);
+            *expr = reduced;
+            Outcome::Stepped(id)
+        },
+        Contract::Stuck => Outcome::Stuck(id),
+        Contract::Raised => Outcome::Raised(expr.clone()),
+    }
+}
+
+fn handler(rules: &[OwnedRule], value: &OwnedExpr) -> Option<OwnedExpr> {
+    rules.iter().find_map(|rule| {
+        let bindings = bind(&rule.pattern, value)?;
+        let body = (*rule.body).clone();
+        Some(bindings.into_iter().fold(body, |body, (name, value)| substitute(&body, &name, &value)))
+    })
+}
+
+// the variables of `pattern` bound to the parts of the value they stand
+// for, `None` when the value does not fit
+fn bind(pattern: &OwnedPattern, value: &OwnedExpr) -> Option<Vec<(String, OwnedExpr)>> {
+    match (pattern, peel(value)) {
+        (OwnedPattern::Wildcard, _) => Some(vec![]),
+        (OwnedPattern::Var(name), _) => Some(vec![(name.clone(), value.clone())]),
+        (OwnedPattern::Construct{ name, argument }, OwnedExpr::Construct{ name: constructor, argument: value }) => {
+            match (argument, value) {
+                _ if name != constructor => None,
+                (None, None) => Some(vec![]),
+                (Some(pattern), Some(value)) => bind(pattern, value),
+                _ => None,
+            }
+        },
+        (OwnedPattern::Record(patterns), OwnedExpr::Record(fields)) if patterns.len() == fields.len() => {
+            let mut bindings = vec![];
+            for (label, pattern) in patterns {
+                let (_, value) = fields.iter().find(|(field, _)| field == label)?;
+                bindings.extend(bind(pattern, value)?);
+            }
+            Some(bindings)
+        },
+        _ => None,
+    }
+}
+
+// the value inside any parens and annotations
+fn peel(expr: &OwnedExpr) -> &OwnedExpr {
+    match expr {
+        OwnedExpr::Seq(sequence) if sequence.len() == 1 => peel(&sequence[0]),
+        OwnedExpr::Annot{ expr, .. } => peel(expr),
+        expr => expr,
+    }
+}
+
+fn substitute(body: &OwnedExpr, name: &str, value: &OwnedExpr) -> OwnedExpr {
+    body.as_expr().substitute(name, &peel(value).as_expr())
+}
+
+// the exceptions arithmetic raises, like `Div`
+fn raise(constructor: &str) -> OwnedExpr {
+    OwnedExpr::Raise(Box::new(OwnedExpr::Construct{ name: constructor.to_string(), argument: None }))
+}
+
+// rewrites `expr`, whose strict children are all values
+fn contract(expr: &OwnedExpr, printed: &mut Option<String>) -> Contract {
+    use OwnedExpr::*;
+    use OwnedLiteral::*;
+    let reduced = match expr {
+        Var(_) | Error(_) => return Contract::Stuck,
+        Lit(_) | Lambda{ .. } | Tuple{ .. } | List(_) | Construct{ .. } | Record(_) | Annot{ .. } => {
+            return Contract::Value
+        },
+        Raise(_) => return Contract::Raised,
+        Handle{ expr, .. } => (**expr).clone(),
+        Select{ label, record } => match peel(record) {
+            Record(fields) => match fields.iter().find(|(field, _)| field == label) {
+                Some((_, value)) => value.clone(),
+                None => return Contract::Stuck,
+            },
+            _ => return Contract::Stuck,
+        },
+        Seq(sequence) if sequence.len() == 1 => return Contract::Value,
+        Seq(sequence) => match peel(&sequence[0]) {
+            Lit(Unit) => Seq(sequence[1..].to_vec()),
+            _ => return Contract::Stuck,
+        },
+        Unary{ operation, child } => match (operation, peel(child)) {
+            (UnaryOp::Not, Lit(Boolean(b))) => Lit(Boolean(!b)),
+            (UnaryOp::Neg, Lit(Integer(i))) => match Width::default().negate(*i) {
+                Ok(i) => Lit(Integer(i)),
+                Err(exn) => raise(exn),
+            },
+            (UnaryOp::Neg, Lit(Real(x))) => Lit(Real(x.negate())),
+            (UnaryOp::Ord, Lit(Char(c))) => Lit(Integer(*c as i64)),
+            (UnaryOp::Chr, Lit(Integer(i))) => match chr(*i) {
+                Some(c) => Lit(Char(c)),
+                None => raise(CHR),
+            },
+            (UnaryOp::Fst, Tuple{ fst, .. }) => (**fst).clone(),
+            (UnaryOp::Snd, Tuple{ snd, .. }) => (**snd).clone(),
+            (UnaryOp::Print, value) => match value.as_expr().eval() {
+                Ok(value) => {
+                    *printed = Some(value.to_string());
+                    Lit(Unit)
+                },
+                Err(_) => return Contract::Stuck,
+            },
+            _ => return Contract::Stuck,
+        },
+        Binary{ left, operation, right } => match (operation, peel(left), peel(right)) {
+            (BinaryOp::OrElse, Lit(Boolean(true)), _) => Lit(Boolean(true)),
+            (BinaryOp::AndAlso, Lit(Boolean(false)), _) => Lit(Boolean(false)),
+            (BinaryOp::OrElse, Lit(Boolean(false)), _) | (BinaryOp::AndAlso, Lit(Boolean(true)), _) => {
+                (**right).clone()
+            },
+            (_, Lit(Char(left)), Lit(Char(right))) => match operation.compare(left, right) {
+                Some(b) => Lit(Boolean(b)),
+                None => return Contract::Stuck,
+            },
+            (_, Lit(Real(left)), Lit(Real(right))) => match left.arithmetic(*operation, *right) {
+                Some(Ok(x)) => Lit(Real(x)),
+                Some(Err(exn)) => raise(exn),
+                None => match operation.compare(left, right) {
+                    Some(b) => Lit(Boolean(b)),
+                    None => return Contract::Stuck,
+                },
+            },
+            (_, Lit(Integer(left)), Lit(Integer(right))) => {
+                match Width::default().arithmetic(*operation, *left, *right) {
+                    Some(Ok(i)) => Lit(Integer(i)),
+                    Some(Err(exn)) => raise(exn),
+                    None => match operation.compare(*left, *right) {
+                        Some(b) => Lit(Boolean(b)),
+                        None => return Contract::Stuck,
+                    },
+                }
+            },
+            _ => return Contract::Stuck,
+        },
+        Cons{ head, tail } => match peel(tail) {
+            List(elements) => List(Some((**head).clone()).into_iter().chain(elements.iter().cloned()).collect()),
+            _ => return Contract::Stuck,
+        },
+        IfThenElse{ condition, if_branch, else_branch } => match peel(condition) {
+            Lit(Boolean(true)) => (**if_branch).clone(),
+            Lit(Boolean(false)) => (**else_branch).clone(),
+            _ => return Contract::Stuck,
+        },
+        // the first binding goes into the ones after it and the body
+        Let{ bindings, body } => match bindings.split_first() {
+            Some(((name, binder), [])) => substitute(body, name, binder),
+            Some(((name, binder), rest)) => {
+                substitute(&Let{ bindings: rest.to_vec(), body: body.clone() }, name, binder)
+            },
+            None => return Contract::Stuck,
+        },
+        App{ left, right } => match peel(left) {
+            Lambda{ name, body } => substitute(body, name, right),
+            Construct{ name, argument: None } => Construct{ name: name.clone(), argument: Some(right.clone()) },
+            _ => return Contract::Stuck,
+        },
+        // each function becomes a lambda that brings the whole group back
+        // into scope around its body
+        Funs{ defs, body } => defs.iter().fold((**body).clone(), |body, def| {
+            let unfolded = Lambda {
+                name: def.argument.clone(),
+                body: Box::new(Funs{ defs: defs.to_vec(), body: def.body.clone() }),
+            };
+            substitute(&body, &def.name, &unfolded)
+        }),
+        // one turn of the loop, and the loop again after it
+        While{ condition, body } => IfThenElse {
+            condition: condition.clone(),
+            if_branch: Box::new(Seq(vec![(**body).clone(), expr.clone()])),
+            else_branch: Box::new(Lit(Unit)),
+        },
+    };
+    Contract::Reduced(reduced)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::{parse};
+
+    #[test]
+    fn step_unit() {
+        // every intermediate tree, and the node each step rewrote
+        let trace = |source| {
+            let mut expr = parse(source).unwrap().into_owned();
+            let mut trace = vec![];
+            loop {
+                match expr.as_expr().step() {
+                    Progress::Step(step) => {
+                        let redex = expr.as_expr().node(step.redex).unwrap().to_string();
+                        trace.push(format!("{} ~> {}", redex, step.expr));
+                        expr = step.expr;
+                    },
+                    Progress::Value => return trace,
+                    Progress::Raised => {
+                        trace.push(format!("uncaught {}", expr));
+                        return trace
+                    },
+                    Progress::Stuck(id) => {
+                        trace.push(format!("stuck at {}", expr.as_expr().node(id).unwrap()));
+                        return trace
+                    },
+                }
+            }
+        };
+        assert_eq!(trace("(fn x => x * 2) (1 + 2)"), vec![
+            "1 + 2 ~> (fn x => x * 2) 3",
+            "(fn x => x * 2) 3 ~> 3 * 2",
+            "3 * 2 ~> 6",
+        ]);
+        assert_eq!(trace("if 1 < 2 orelse x then fst (Some 1, 2) else 0"), vec![
+            "1 < 2 ~> if true orelse x then fst (Some 1, 2) else 0",
+            "true orelse x ~> if true then fst (Some 1, 2) else 0",
+            "if true then fst (Some 1, 2) else 0 ~> fst (Some 1, 2)",
+            "fst (Some 1, 2) ~> Some 1",
+        ]);
+        assert_eq!(trace("1 + true")[0], "stuck at 1 + true");
+        let steps = trace("let fun f n = if n = 0 then [] else n :: f (n - 1) in f 2 end");
+        assert_eq!(steps.last().unwrap(), "2 :: [1] ~> [2, 1]");
+        assert_eq!(trace("1 + (2 div 0) handle Div => 0"), vec![
+            "2 div 0 ~> 1 + (raise Div) handle Div => 0",
+            "(raise Div) ~> 1 + (raise Div) handle Div => 0",
+            "1 + (raise Div) ~> (raise Div) handle Div => 0",
+            "(raise Div) handle Div => 0 ~> 0",
+        ]);
+        assert_eq!(trace("(raise Fail 1) handle Div => 0").last().unwrap(), "uncaught raise Fail 1");
+        assert_eq!(trace("while 1 > 2 do print 1"), vec![
+            "while 1 > 2 do print 1 ~> if 1 > 2 then (print 1; while 1 > 2 do print 1) else ()",
+            "1 > 2 ~> if false then (print 1; while 1 > 2 do print 1) else ()",
+            "if false then (print 1; while 1 > 2 do print 1) else () ~> ()",
+        ]);
+        let reductions = |source, max_steps| parse(source).unwrap().reductions(max_steps).iter()
+            .map(|expr| expr.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(reductions("(fn x => x * 2) (1 + 2)", 100), vec![
+            "(fn x => x * 2) (1 + 2)", "(fn x => x * 2) 3", "3 * 2", "6",
+        ]);
+        assert_eq!(reductions("(fn x => x * 2) (1 + 2)", 1), vec!["(fn x => x * 2) (1 + 2)", "(fn x => x * 2) 3"]);
+        assert_eq!(reductions("1 + true", 100), vec!["1 + true"]);
+    }
+}
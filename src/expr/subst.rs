@@ -0,0 +1,249 @@
+use std::collections::BTreeSet;
+
+use crate::expr::{Expr, Pattern};
+use crate::expr::owned::{OwnedDefinition, OwnedExpr, OwnedPattern, OwnedRule};
+
+impl<'a> Expr<'a> {
+    // `self` with every free `name` replaced by `replacement`. a binder that
+    // would capture one of the replacement's free variables is renamed on
+    // the way, which is why the result owns its names
+    pub fn substitute(&self, name: &str, replacement: &Expr) -> OwnedExpr {
+        let target = Target {
+            name,
+            replacement: replacement.clone().into_owned(),
+            free: replacement.free_variables().into_iter().map(str::to_string).collect(),
+        };
+        let mut rename = Rename::new(Some(target), self.names().into_iter().chain(replacement.names()));
+        rename.expr(self, &mut vec![], true)
+    }
+    // `self` with every binder given a name nothing else in the tree uses,
+    // free variables keep theirs
+    pub fn rename_fresh(&self) -> OwnedExpr {
+        let mut rename = Rename::new(None, self.names().into_iter());
+        rename.expr(self, &mut vec![], false)
+    }
+}
+
+struct Target<'n> {
+    name: &'n str,
+    replacement: OwnedExpr,
+    free: BTreeSet<String>,
+}
+
+// each binder in scope paired with the name it was given
+type Scope<'a> = Vec<(&'a str, String)>;
+
+struct Rename<'n> {
+    target: Option<Target<'n>>,
+    // every binder gets a new name, not just the ones that would capture
+    all: bool,
+    taken: BTreeSet<String>,
+}
+
+impl<'n> Rename<'n> {
+    fn new<'a>(target: Option<Target<'n>>, names: impl Iterator<Item = &'a str>) -> Rename<'n> {
+        let all = target.is_none();
+        Rename { target, all, taken: names.map(str::to_string).collect() }
+    }
+    fn shadows(&self, name: &str) -> bool {
+        matches!(&self.target, Some(target) if target.name == name)
+    }
+    // the name a binder ends up with, given whether the substitution is
+    // still `active` under it and the expressions its scope covers
+    fn bind(&mut self, name: &str, active: bool, scope: &[&Expr]) -> String {
+        let captures = match &self.target {
+            Some(target) => active && target.free.contains(name)
+                && scope.iter().any(|expr| expr.free_variables().contains(target.name)),
+            None => false,
+        };
+        if !self.all && !captures {
+            return name.to_string()
+        }
+        let mut fresh = name.to_string();
+        while self.taken.contains(&fresh) {
+            fresh.push('X');
+        }
+        self.taken.insert(fresh.clone());
+        fresh
+    }
+    fn boxed<'a>(&mut self, expr: &Expr<'a>, scope: &mut Scope<'a>, active: bool) -> Box<OwnedExpr> {
+        Box::new(self.expr(expr, scope, active))
+    }
+    // `active` is false once some binder has shadowed the name being replaced
+    fn expr<'a>(&mut self, expr: &Expr<'a>, scope: &mut Scope<'a>, active: bool) -> OwnedExpr {
+        use Expr::*;
+        match expr {
+            Var(var) => match &self.target {
+                Some(target) if active && target.name == *var => target.replacement.clone(),
+                _ => {
+                    let name = scope.iter().rev()
+                        .find(|(bound, _)| bound == var)
+                        .map(|(_, name)| name.as_str());
+                    OwnedExpr::Var(name.unwrap_or(var).to_string())
+                },
+            },
+            Lit(lit) => OwnedExpr::Lit(lit.into_owned()),
+            Error(span) => OwnedExpr::Error(*span),
+            Unary{ operation, child } => {
+                OwnedExpr::Unary{ operation: *operation, child: self.boxed(child, scope, active) }
+            },
+            Binary{ left, operation, right } => OwnedExpr::Binary {
+                left: self.boxed(left, scope, active),
+                operation: *operation,
+                right: self.boxed(right, scope, active),
+            },
+            IfThenElse{ condition, if_branch, else_branch } => OwnedExpr::IfThenElse {
+                condition: self.boxed(condition, scope, active),
+                if_branch: self.boxed(if_branch, scope, active),
+                else_branch: self.boxed(else_branch, scope, active),
+            },
+            While{ condition, body } => OwnedExpr::While {
+                condition: self.boxed(condition, scope, active),
+                body: self.boxed(body, scope, active),
+            },
+            Tuple{ fst, snd } => OwnedExpr::Tuple {
+                fst: self.boxed(fst, scope, active),
+                snd: self.boxed(snd, scope, active),
+            },
+            App{ left, right } => OwnedExpr::App {
+                left: self.boxed(left, scope, active),
+                right: self.boxed(right, scope, active),
+            },
+            Cons{ head, tail } => OwnedExpr::Cons {
+                head: self.boxed(head, scope, active),
+                tail: self.boxed(tail, scope, active),
+            },
+            Seq(sequence) => {
+                OwnedExpr::Seq(sequence.iter().map(|expr| self.expr(expr, scope, active)).collect())
+            },
+            List(elements) => {
+                OwnedExpr::List(elements.iter().map(|expr| self.expr(expr, scope, active)).collect())
+            },
+            Let{ bindings, body } => {
+                // each name scopes over the binders after it and the body
+                let mut active = active;
+                let mut owned_bindings = vec![];
+                for (i, (name, binder)) in bindings.iter().enumerate() {
+                    let binder = self.expr(binder, scope, active);
+                    active = active && !self.shadows(name);
+                    let covered: Vec<&Expr> = bindings[i + 1..].iter()
+                        .map(|(_, binder)| binder)
+                        .chain(Some(&**body))
+                        .collect();
+                    let fresh = self.bind(name, active, &covered);
+                    scope.push((name, fresh.clone()));
+                    owned_bindings.push((fresh, binder));
+                }
+                let body = self.boxed(body, scope, active);
+                scope.truncate(scope.len() - bindings.len());
+                OwnedExpr::Let{ bindings: owned_bindings, body }
+            },
+            Lambda{ name, body } => {
+                let active = active && !self.shadows(name);
+                let fresh = self.bind(name, active, &[body]);
+                scope.push((name, fresh.clone()));
+                let body = self.boxed(body, scope, active);
+                scope.pop();
+                OwnedExpr::Lambda{ name: fresh, body }
+            },
+            Funs{ defs, body } => {
+                // the names scope over every definition and the body at once
+                let active = active && !defs.iter().any(|def| self.shadows(def.name));
+                for def in defs {
+                    let fresh = self.bind(def.name, active, &[expr]);
+                    scope.push((def.name, fresh));
+                }
+                let names: Vec<String> = scope[scope.len() - defs.len()..].iter()
+                    .map(|(_, name)| name.clone())
+                    .collect();
+                let mut owned_defs = vec![];
+                for (def, name) in defs.iter().zip(names) {
+                    let active = active && !self.shadows(def.argument);
+                    let argument = self.bind(def.argument, active, &[&def.body]);
+                    scope.push((def.argument, argument.clone()));
+                    let body = self.boxed(&def.body, scope, active);
+                    scope.pop();
+                    owned_defs.push(OwnedDefinition { name, argument, body });
+                }
+                let body = self.boxed(body, scope, active);
+                scope.truncate(scope.len() - defs.len());
+                OwnedExpr::Funs{ defs: owned_defs, body }
+            },
+            Annot{ expr, ty } => OwnedExpr::Annot{ expr: self.boxed(expr, scope, active), ty: ty.clone() },
+            Construct{ name, argument } => OwnedExpr::Construct {
+                name: name.to_string(),
+                argument: argument.as_ref().map(|argument| self.boxed(argument, scope, active)),
+            },
+            Raise(expr) => OwnedExpr::Raise(self.boxed(expr, scope, active)),
+            Handle{ expr, rules } => {
+                let expr = self.boxed(expr, scope, active);
+                let mut owned_rules = vec![];
+                for rule in rules {
+                    let names = rule.pattern.names();
+                    let active = active && !names.iter().any(|name| self.shadows(name));
+                    for name in names.iter() {
+                        let fresh = self.bind(name, active, &[&rule.body]);
+                        scope.push((name, fresh));
+                    }
+                    let pattern = renamed(&rule.pattern, scope);
+                    let body = self.boxed(&rule.body, scope, active);
+                    scope.truncate(scope.len() - names.len());
+                    owned_rules.push(OwnedRule { pattern, body });
+                }
+                OwnedExpr::Handle{ expr, rules: owned_rules }
+            },
+            Record(fields) => OwnedExpr::Record(
+                fields.iter().map(|(label, expr)| (label.to_string(), self.expr(expr, scope, active))).collect()
+            ),
+            Select{ label, record } => OwnedExpr::Select {
+                label: label.to_string(),
+                record: self.boxed(record, scope, active),
+            },
+        }
+    }
+}
+
+// `pattern` with each variable given the name the scope has for it
+fn renamed(pattern: &Pattern, scope: &Scope) -> OwnedPattern {
+    match pattern {
+        Pattern::Wildcard => OwnedPattern::Wildcard,
+        Pattern::Var(var) => {
+            let name = scope.iter().rev().find(|(bound, _)| bound == var).map(|(_, name)| name.as_str());
+            OwnedPattern::Var(name.unwrap_or(var).to_string())
+        },
+        Pattern::Construct{ name, argument } => OwnedPattern::Construct {
+            name: name.to_string(),
+            argument: argument.as_ref().map(|argument| Box::new(renamed(argument, scope))),
+        },
+        Pattern::Record(fields) => OwnedPattern::Record(
+            fields.iter().map(|(label, pattern)| (label.to_string(), renamed(pattern, scope))).collect()
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::expr::{parse};
+
+    #[test]
+    fn substitute_unit() {
+        let subst = |source, name, replacement| {
+            let replaced = parse(source).unwrap().substitute(name, &parse(replacement).unwrap());
+            replaced.to_string()
+        };
+        let printed = |source| parse(source).unwrap().into_owned().to_string();
+        // the binder would capture `y`, so it moves out of the way
+        assert_eq!(subst("fn y => x + y", "x", "y"), printed("fn yX => y + yX"));
+        // nothing to capture when `x` does not occur under the binder
+        assert_eq!(subst("(x, fn y => z)", "x", "y"), printed("(y, fn y => z)"));
+        // the inner `let` shadows `x`, only its binder sees the outer one
+        assert_eq!(subst("(x, let val x = x in x end)", "x", "z"), printed("(z, let val x = z in x end)"));
+        assert_eq!(
+            subst("let fun f n = n + x and g x = x in f x end", "x", "n"),
+            printed("let fun f nX = nX + n and g x = x in f n end")
+        );
+
+        let fresh = parse("let val x = 1 in (fn x => x + y) x end").unwrap().rename_fresh();
+        assert_eq!(fresh.to_string(), printed("let val xX = 1 in (fn xXX => xXX + y) xX end"));
+    }
+}
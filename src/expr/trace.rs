@@ -0,0 +1,126 @@
+use std::fmt::Write;
+
+use crate::expr::{Expr};
+use crate::expr::eval::{Env, Error, Value};
+use crate::expr::ids::{NodeId, NodeTable};
+
+// one thing the evaluator did, in the order it did them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event<'a> {
+    Enter(NodeId),
+    // the value the node evaluated to or the error it failed with, both
+    // printed
+    Exit{ node: NodeId, value: Result<String, String> },
+    // a name coming into scope and going out of it again
+    Bind{ name: &'a str, value: String },
+    Unbind(&'a str),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Trace<'a> {
+    pub events: Vec<Event<'a>>,
+}
+
+impl<'a> Expr<'a> {
+    // evaluates the expression and records every node it enters and leaves
+    // and every binding it makes, node ids are the tree's pre-order ones
+    pub fn trace(self) -> (Result<Value<'a>, Error<'a>>, Trace<'a>) {
+        let mut env = Env::traced();
+        let res = self.eval_ctx(&mut env);
+        (res, Trace { events: env.events() })
+    }
+}
+
+pub(crate) fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(quoted, "\\u{:04x}", c as u32).unwrap(),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+impl<'a> Trace<'a> {
+    // the events as json for other tools, one object per line, nodes carry
+    // their byte span when `table` knows it
+    pub fn json(&self, table: Option<&NodeTable>) -> String {
+        let node = |id: NodeId| match table.and_then(|table| table.span(id)) {
+            Some(span) => format!("\"node\": {}, \"span\": [{}, {}]", id.0, span.start, span.end),
+            None => format!("\"node\": {}", id.0),
+        };
+        let mut json = String::from("{\"events\": [\n");
+        for (i, event) in self.events.iter().enumerate() {
+            let object = match event {
+                Event::Enter(id) => format!("{{\"event\": \"enter\", {}}}", node(*id)),
+                Event::Exit{ node: id, value: Ok(value) } => {
+                    format!("{{\"event\": \"exit\", {}, \"value\": {}}}", node(*id), quote(value))
+                },
+                Event::Exit{ node: id, value: Err(err) } => {
+                    format!("{{\"event\": \"exit\", {}, \"error\": {}}}", node(*id), quote(err))
+                },
+                Event::Bind{ name, value } => {
+                    format!("{{\"event\": \"bind\", \"name\": {}, \"value\": {}}}", quote(name), quote(value))
+                },
+                Event::Unbind(name) => format!("{{\"event\": \"unbind\", \"name\": {}}}", quote(name)),
+            };
+            json.push_str("  ");
+            json.push_str(&object);
+            json.push_str(if i + 1 < self.events.len() { ",\n" } else { "\n" });
+        }
+        json.push_str("]}\n");
+        json
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::{parse, parse_indexed};
+
+    #[test]
+    fn trace_unit() {
+        let (res, trace) = parse("let val x = 1 in x + 2 end").unwrap().trace();
+        assert_eq!(res.unwrap().to_string(), "3");
+        let exit = |node, value: &str| Event::Exit{ node: NodeId(node), value: Ok(value.to_string()) };
+        assert_eq!(trace.events, vec![
+            Event::Enter(NodeId(0)),
+            Event::Enter(NodeId(1)),
+            exit(1, "1"),
+            Event::Bind{ name: "x", value: "1".to_string() },
+            Event::Enter(NodeId(2)),
+            Event::Enter(NodeId(3)),
+            exit(3, "1"),
+            Event::Enter(NodeId(4)),
+            exit(4, "2"),
+            exit(2, "3"),
+            Event::Unbind("x"),
+            exit(0, "3"),
+        ]);
+
+        // a function body keeps its ids however often and from wherever it
+        // is called
+        let source = "let fun f n = n * 2 in f (f 1) end";
+        let (expr, table) = parse_indexed(source).unwrap();
+        let (_, trace) = expr.trace();
+        let entered = |id| trace.events.iter().filter(|event| **event == Event::Enter(NodeId(id))).count();
+        let span = table.span(NodeId(1)).unwrap();
+        assert_eq!((entered(1), &source[span.start..span.end]), (2, "n * 2"));
+        let json = trace.json(Some(&table));
+        assert!(json.starts_with("{\"events\": [\n  {\"event\": \"enter\", \"node\": 0, \"span\": [0, 34]},\n"));
+        assert!(json.contains("{\"event\": \"bind\", \"name\": \"f\", \"value\": \"f n = n * 2\"}"));
+        assert!(json.ends_with("{\"event\": \"exit\", \"node\": 0, \"span\": [0, 34], \"value\": \"4\"}\n]}\n"));
+
+        let (_, trace) = parse("1 + (raise Div)").unwrap().trace();
+        assert_eq!(trace.events.last(), Some(&Event::Exit{
+            node: NodeId(0), value: Err("uncaught exception `Div`".to_string())
+        }));
+        assert_eq!(quote("say \"hi\"\n"), "\"say \\\"hi\\\"\\n\"");
+    }
+}
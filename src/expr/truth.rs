@@ -0,0 +1,222 @@
+use std::fmt;
+
+use crate::expr::{Expr};
+use crate::expr::columnar::{Column, ColumnError, Values, eval_columns};
+use crate::locale::{message};
+
+// what an input of `truth_table` ranges over
+#[derive(Debug, Copy, Clone)]
+pub enum Domain<'d> {
+    Bool,
+    // an integer that is only ever one of these, like a small enumeration
+    // of states
+    Ints(&'d [i64]),
+}
+
+impl<'d> Domain<'d> {
+    fn len(self) -> usize {
+        match self {
+            Domain::Bool => 2,
+            Domain::Ints(ints) => ints.len(),
+        }
+    }
+}
+
+// the most rows `truth_table` enumerates, ten booleans and a few more
+pub const MAX_ROWS: usize = 1 << 16;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum TruthError<'a> {
+    Column(ColumnError<'a>),
+    // the expression is an integer rather than a condition
+    NotBoolean,
+    // the inputs take more than `MAX_ROWS` combinations of values
+    TooMany(&'a str),
+}
+
+impl<'a> fmt::Display for TruthError<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TruthError::Column(err) => write!(f, "{}", err),
+            TruthError::NotBoolean => write!(f, "{}", message("truth-not-boolean", &[])),
+            TruthError::TooMany(name) => write!(f, "{}", message("truth-too-many", &[name, &MAX_ROWS])),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Cell {
+    Bool(bool),
+    Int(i64),
+}
+
+impl fmt::Display for Cell {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Cell::Bool(b) => write!(f, "{}", b),
+            Cell::Int(i) if *i < 0 => write!(f, "~{}", i.unsigned_abs()),
+            Cell::Int(i) => write!(f, "{}", i),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Verdict {
+    // true whatever the inputs, a condition that never filters anything out
+    Tautology,
+    // false whatever the inputs
+    Contradiction,
+    Contingent,
+}
+
+// the value of an expression for every combination of the values of its
+// inputs, the last input changing fastest
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct TruthTable<'a> {
+    pub inputs: Vec<&'a str>,
+    pub rows: Vec<(Vec<Cell>, bool)>,
+    // how many rows apart two that differ only in an input are, and how
+    // many values it takes
+    strides: Vec<(usize, usize)>,
+}
+
+impl<'a> TruthTable<'a> {
+    pub fn verdict(&self) -> Verdict {
+        if self.rows.iter().all(|(_, res)| *res) {
+            Verdict::Tautology
+        } else if self.rows.iter().all(|(_, res)| !*res) {
+            Verdict::Contradiction
+        } else {
+            Verdict::Contingent
+        }
+    }
+    // the inputs that never change the value on their own, whatever the
+    // others are, so the expression does not depend on them
+    pub fn irrelevant(&self) -> Vec<&'a str> {
+        let relevant = |&(stride, len): &(usize, usize)| (0..self.rows.len())
+            .filter(|row| row / stride % len == 0)
+            .any(|row| (1..len).any(|k| self.rows[row + k * stride].1 != self.rows[row].1));
+        self.inputs.iter().zip(&self.strides)
+            .filter(|(_, stride)| !relevant(stride))
+            .map(|(name, _)| *name)
+            .collect()
+    }
+}
+
+impl<'a> fmt::Display for TruthTable<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = |i: usize, name: &str| {
+            self.rows.iter().map(|(cells, _)| cells[i].to_string().len()).fold(name.len(), usize::max)
+        };
+        let widths: Vec<usize> = self.inputs.iter().enumerate().map(|(i, name)| width(i, name)).collect();
+        for (name, width) in self.inputs.iter().zip(&widths) {
+            write!(f, "{:width$} ", name, width = width)?
+        }
+        writeln!(f, "|")?;
+        for (cells, res) in &self.rows {
+            for (cell, width) in cells.iter().zip(&widths) {
+                write!(f, "{:width$} ", cell.to_string(), width = width)?
+            }
+            writeln!(f, "| {}", res)?
+        }
+        match self.verdict() {
+            Verdict::Tautology => writeln!(f, "{}", message("truth-tautology", &[]))?,
+            Verdict::Contradiction => writeln!(f, "{}", message("truth-contradiction", &[]))?,
+            Verdict::Contingent => {},
+        }
+        for name in self.irrelevant() {
+            writeln!(f, "{}", message("truth-irrelevant", &[&name]))?
+        }
+        Ok(())
+    }
+}
+
+// the truth table of the condition `expr` over its free variables, each a
+// boolean unless `domains` says otherwise. it is evaluated over all the
+// rows at once with `eval_columns`, so the same things evaluate
+pub fn truth_table<'a>(expr: &Expr<'a>, domains: &[(&'a str, Domain)]) -> Result<TruthTable<'a>, TruthError<'a>> {
+    let inputs: Vec<&'a str> = expr.free_variables().into_iter().collect();
+    let domain = |name: &str| {
+        domains.iter().find(|(input, _)| *input == name).map_or(Domain::Bool, |(_, domain)| *domain)
+    };
+    let mut strides = vec![(1, 1); inputs.len()];
+    let mut rows: usize = 1;
+    for (i, name) in inputs.iter().enumerate().rev() {
+        let len = domain(name).len();
+        strides[i] = (rows, len);
+        rows = match rows.checked_mul(len) {
+            Some(rows) if rows <= MAX_ROWS => rows,
+            _ => return Err(TruthError::TooMany(name)),
+        };
+    }
+    let cell = |name: &str, (stride, len): (usize, usize), row: usize| match domain(name) {
+        Domain::Bool => Cell::Bool(row / stride % len == 1),
+        Domain::Ints(ints) => Cell::Int(ints[row / stride % len]),
+    };
+    let table: Vec<Vec<Cell>> = (0..rows)
+        .map(|row| inputs.iter().zip(&strides).map(|(name, stride)| cell(name, *stride, row)).collect())
+        .collect();
+    let bools: Vec<Vec<bool>> = (0..inputs.len())
+        .map(|i| table.iter().map(|cells| cells[i] == Cell::Bool(true)).collect())
+        .collect();
+    let ints: Vec<Vec<i64>> = (0..inputs.len())
+        .map(|i| table.iter().map(|cells| if let Cell::Int(n) = cells[i] { n } else { 0 }).collect())
+        .collect();
+    let columns: Vec<(&'a str, Column)> = inputs.iter().enumerate()
+        .map(|(i, name)| match domain(name) {
+            Domain::Bool => (*name, Column::Bool(&bools[i])),
+            Domain::Ints(_) => (*name, Column::Int(&ints[i])),
+        })
+        .collect();
+    match eval_columns(expr, &columns).map_err(TruthError::Column)? {
+        Values::Bool(results) => Ok(TruthTable { inputs, rows: table.into_iter().zip(results).collect(), strides }),
+        Values::Int(_) => Err(TruthError::NotBoolean),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::{parse};
+
+    #[test]
+    fn truth_table_unit() {
+        let table = |source, domains: &[(&'static str, Domain)]| truth_table(&parse(source).unwrap(), domains);
+
+        let rule = table("admin orelse (member andalso not banned) orelse admin", &[]).unwrap();
+        assert_eq!(rule.inputs, vec!["admin", "banned", "member"]);
+        assert_eq!(rule.rows.len(), 8);
+        assert_eq!(rule.rows[1], (vec![Cell::Bool(false), Cell::Bool(false), Cell::Bool(true)], true));
+        assert_eq!(rule.verdict(), Verdict::Contingent);
+        assert!(rule.irrelevant().is_empty());
+
+        // vacuously true, so neither input makes a difference
+        let vacuous = table("a orelse not a orelse b", &[]).unwrap();
+        assert_eq!(vacuous.verdict(), Verdict::Tautology);
+        assert_eq!(vacuous.irrelevant(), vec!["a", "b"]);
+        assert_eq!(vacuous.to_string(), concat!(
+            "a     b     |\n",
+            "false false | true\n",
+            "false true  | true\n",
+            "true  false | true\n",
+            "true  true  | true\n",
+            "always true, whatever the inputs are\n",
+            "does not depend on `a`\n",
+            "does not depend on `b`\n",
+        ));
+
+        let levels: &[i64] = &[0, 1, 2];
+        let state = table("level > 0 andalso level < 1 andalso urgent", &[("level", Domain::Ints(levels))]).unwrap();
+        assert_eq!(state.rows.len(), 6);
+        assert_eq!(state.verdict(), Verdict::Contradiction);
+        let state = table("if urgent then level >= 1 else level = 2", &[("level", Domain::Ints(levels))]).unwrap();
+        assert_eq!(state.verdict(), Verdict::Contingent);
+        assert!(state.irrelevant().is_empty());
+
+        assert_eq!(table("a", &[]).unwrap().irrelevant(), Vec::<&str>::new());
+        assert_eq!(table("if a then 1 else 2", &[]), Err(TruthError::NotBoolean));
+        let many = "a andalso b andalso c andalso d andalso e andalso f andalso g andalso h andalso i \
+            andalso j andalso k andalso l andalso m andalso n andalso o andalso p andalso q";
+        assert_eq!(table(many, &[]), Err(TruthError::TooMany("a")));
+    }
+}
@@ -0,0 +1,173 @@
+use crate::lexer::{Literal};
+use crate::expr::{Definition, Expr, Rule};
+
+// a read only walk over a tree. every method defaults to going on into the
+// children, so an analysis overrides the ones it cares about and calls the
+// matching `walk_*` where it still wants to recurse
+pub trait ExprVisitor<'a> {
+    fn visit_expr(&mut self, expr: &Expr<'a>) {
+        walk_expr(self, expr)
+    }
+    fn visit_definition(&mut self, def: &Definition<'a>) {
+        walk_definition(self, def)
+    }
+    fn visit_var(&mut self, _name: &'a str) {}
+    fn visit_lit(&mut self, _lit: &Literal<'a>) {}
+    // every name a `let`, `fn`, `fun` or pattern introduces, before its scope
+    fn visit_binding(&mut self, _name: &'a str) {}
+}
+
+// the children in source order, the same order node ids are given out in
+pub fn walk_expr<'a, V: ExprVisitor<'a> + ?Sized>(visitor: &mut V, expr: &Expr<'a>) {
+    use Expr::*;
+    match expr {
+        Var(name) => visitor.visit_var(name),
+        Lit(lit) => visitor.visit_lit(lit),
+        Let{ bindings, body } => {
+            for (name, binder) in bindings {
+                visitor.visit_binding(name);
+                visitor.visit_expr(binder)
+            }
+            visitor.visit_expr(body)
+        },
+        Lambda{ name, body } => {
+            visitor.visit_binding(name);
+            visitor.visit_expr(body)
+        },
+        Funs{ defs, body } => {
+            for def in defs {
+                visitor.visit_definition(def)
+            }
+            visitor.visit_expr(body)
+        },
+        Handle{ expr, rules } => {
+            visitor.visit_expr(expr);
+            for rule in rules {
+                for name in rule.pattern.names() {
+                    visitor.visit_binding(name)
+                }
+                visitor.visit_expr(&rule.body)
+            }
+        },
+        _ => {
+            for child in expr.children() {
+                visitor.visit_expr(child)
+            }
+        },
+    }
+}
+
+pub fn walk_definition<'a, V: ExprVisitor<'a> + ?Sized>(visitor: &mut V, def: &Definition<'a>) {
+    visitor.visit_binding(def.name);
+    visitor.visit_binding(def.argument);
+    visitor.visit_expr(&def.body)
+}
+
+// a rebuilding walk that takes the tree apart and puts it back together.
+// the defaults rebuild every node as it was from its folded children, a
+// transformation overrides `fold_expr`, handles the nodes it rewrites and
+// hands the rest to `fold_children`
+pub trait ExprFolder<'a> {
+    fn fold_expr(&mut self, expr: Expr<'a>) -> Expr<'a> {
+        fold_children(self, expr)
+    }
+    fn fold_definition(&mut self, def: Definition<'a>) -> Definition<'a> {
+        Definition { body: Box::new(self.fold_expr(*def.body)), ..def }
+    }
+}
+
+pub fn fold_children<'a, F: ExprFolder<'a> + ?Sized>(folder: &mut F, expr: Expr<'a>) -> Expr<'a> {
+    use Expr::*;
+    let mut fold = |expr: Box<Expr<'a>>| Box::new(folder.fold_expr(*expr));
+    match expr {
+        Var(_) | Lit(_) | Error(_) => expr,
+        Unary{ operation, child } => Unary{ operation, child: fold(child) },
+        Binary{ left, operation, right } => Binary{ left: fold(left), operation, right: fold(right) },
+        IfThenElse{ condition, if_branch, else_branch } => IfThenElse {
+            condition: fold(condition),
+            if_branch: fold(if_branch),
+            else_branch: fold(else_branch),
+        },
+        While{ condition, body } => While{ condition: fold(condition), body: fold(body) },
+        Tuple{ fst, snd } => Tuple{ fst: fold(fst), snd: fold(snd) },
+        Let{ bindings, body } => Let {
+            bindings: bindings.into_iter().map(|(name, binder)| (name, folder.fold_expr(binder))).collect(),
+            body: Box::new(folder.fold_expr(*body)),
+        },
+        Lambda{ name, body } => Lambda{ name, body: fold(body) },
+        App{ left, right } => App{ left: fold(left), right: fold(right) },
+        Seq(sequence) => Seq(sequence.into_iter().map(|expr| folder.fold_expr(expr)).collect()),
+        List(elements) => List(elements.into_iter().map(|expr| folder.fold_expr(expr)).collect()),
+        Cons{ head, tail } => Cons{ head: fold(head), tail: fold(tail) },
+        Funs{ defs, body } => {
+            let defs = defs.into_iter().map(|def| folder.fold_definition(def)).collect();
+            Funs{ defs, body: Box::new(folder.fold_expr(*body)) }
+        },
+        Annot{ expr, ty } => Annot{ expr: Box::new(folder.fold_expr(*expr)), ty },
+        Construct{ name, argument } => Construct {
+            name,
+            argument: argument.map(|argument| Box::new(folder.fold_expr(*argument))),
+        },
+        Raise(expr) => Raise(fold(expr)),
+        Handle{ expr, rules } => {
+            let expr = fold(expr);
+            let rules = rules.into_iter().map(|rule| Rule { body: fold(rule.body), ..rule }).collect();
+            Handle{ expr, rules }
+        },
+        Record(fields) => Record(fields.into_iter().map(|(label, expr)| (label, folder.fold_expr(expr))).collect()),
+        Select{ label, record } => Select{ label, record: fold(record) },
+    }
+}
+
+impl<'a> Expr<'a> {
+    pub fn accept<V: ExprVisitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        visitor.visit_expr(self)
+    }
+    pub fn fold<F: ExprFolder<'a> + ?Sized>(self, folder: &mut F) -> Expr<'a> {
+        folder.fold_expr(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::{BinaryOp, parse};
+
+    #[test]
+    fn visit_fold_unit() {
+        // counts uses of each name, skipping the body of every lambda
+        struct Uses<'a>(Vec<&'a str>);
+        impl<'a> ExprVisitor<'a> for Uses<'a> {
+            fn visit_expr(&mut self, expr: &Expr<'a>) {
+                if let Expr::Lambda{ .. } = expr {
+                    return
+                }
+                walk_expr(self, expr)
+            }
+            fn visit_var(&mut self, name: &'a str) {
+                self.0.push(name)
+            }
+        }
+        let expr = parse("let fun f n = n + m in f x (fn y => y) :: [z] end").unwrap();
+        let mut uses = Uses(vec![]);
+        expr.accept(&mut uses);
+        assert_eq!(uses.0, vec!["n", "m", "f", "x", "z"]);
+
+        // swaps the operands of every addition, bottom up
+        struct Swap;
+        impl<'a> ExprFolder<'a> for Swap {
+            fn fold_expr(&mut self, expr: Expr<'a>) -> Expr<'a> {
+                match fold_children(self, expr) {
+                    Expr::Binary{ left, operation: BinaryOp::Add, right } => {
+                        Expr::Binary{ left: right, operation: BinaryOp::Add, right: left }
+                    },
+                    expr => expr,
+                }
+            }
+        }
+        let expr = parse("let fun f n = (1 + n) + 2 in f (a + b) end").unwrap();
+        assert_eq!(expr.fold(&mut Swap), parse("let fun f n = 2 + (n + 1) in f (b + a) end").unwrap());
+        let expr = parse("if a then [b, c] else (d; e)").unwrap();
+        assert_eq!(expr.clone().fold(&mut Swap), expr);
+    }
+}
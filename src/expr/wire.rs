@@ -0,0 +1,629 @@
+use std::fmt;
+use std::convert::{TryFrom, TryInto};
+
+use crate::lexer::{Literal, Span};
+use crate::runtime::real::{Real};
+use crate::expr::{UnaryOp, BinaryOp, TypeExpr, Definition, Pattern, Rule, Expr, MAX_DEPTH};
+use crate::optimize::mono::{Ty};
+use crate::locale::{message};
+
+// a binary encoding of trees, for shipping rules between services without
+// their source. the bytes are the magic, a major and a minor version and
+// then sections, each a tag, a length and its contents. a node is a tag, a
+// length and its fields, children being nodes too, integers are varints
+// and text is a length and utf-8 that decoding borrows rather than copies.
+//
+// what stays readable as the format grows:
+// - a reader reads every minor version of its major and refuses the others
+// - a new minor version may add sections, which older readers skip
+// - it may add fields at the end of a node, which older readers skip too
+// - it may not add kinds of nodes a tree that older readers get can have
+pub const MAGIC: &[u8; 4] = b"FRWB";
+// 2 since a `let` holds all of its bindings
+pub const MAJOR: u8 = 2;
+pub const MINOR: u8 = 0;
+
+const TREE: u8 = 1;
+const TYPES: u8 = 2;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum WireError {
+    // the bytes do not start with `MAGIC`
+    NotWire,
+    Major(u8),
+    // the bytes end in the middle of something
+    Truncated,
+    // a node of a kind this reader does not know, at that byte offset
+    UnknownNode{ tag: u8, at: usize },
+    // a field that is not what its place says it is, at that byte offset
+    Invalid(usize),
+    NoTree,
+    TooDeep,
+}
+
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WireError::NotWire => write!(f, "{}", message("wire-not-wire", &[])),
+            WireError::Major(major) => write!(f, "{}", message("wire-major", &[major, &MAJOR])),
+            WireError::Truncated => write!(f, "{}", message("wire-truncated", &[])),
+            WireError::UnknownNode{ tag, at } => write!(f, "{}", message("wire-unknown-node", &[tag, at])),
+            WireError::Invalid(at) => write!(f, "{}", message("wire-invalid", &[at])),
+            WireError::NoTree => write!(f, "{}", message("wire-no-tree", &[])),
+            WireError::TooDeep => write!(f, "{}", message("too-deep", &[&MAX_DEPTH])),
+        }
+    }
+}
+
+// a tree with the types of its inputs and of its value, as far as they are
+// known, see `optimize::mono::referenced_inputs`
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Typed<'a> {
+    pub expr: Expr<'a>,
+    pub inputs: Vec<(&'a str, Option<Ty>)>,
+    pub result: Option<Ty>,
+}
+
+struct Writer {
+    out: Vec<u8>,
+}
+
+impl Writer {
+    fn byte(&mut self, byte: u8) {
+        self.out.push(byte)
+    }
+    fn varint(&mut self, mut n: u64) {
+        while n >= 0x80 {
+            self.out.push(n as u8 | 0x80);
+            n >>= 7;
+        }
+        self.out.push(n as u8)
+    }
+    // zigzag, so small negative numbers stay short
+    fn int(&mut self, i: i64) {
+        self.varint(((i << 1) ^ (i >> 63)) as u64)
+    }
+    fn str(&mut self, text: &str) {
+        self.varint(text.len() as u64);
+        self.out.extend_from_slice(text.as_bytes())
+    }
+    // the length is patched in once the fields are written, fixed width so
+    // a reader skips a node without decoding it
+    fn node<F: FnOnce(&mut Writer)>(&mut self, tag: u8, fields: F) {
+        self.byte(tag);
+        let at = self.out.len();
+        self.out.extend_from_slice(&[0; 4]);
+        fields(self);
+        let len = (self.out.len() - at - 4) as u32;
+        self.out[at..at + 4].copy_from_slice(&len.to_le_bytes())
+    }
+    fn expr(&mut self, expr: &Expr) {
+        use Expr::*;
+        match expr {
+            Var(name) => self.node(0, |w| w.str(name)),
+            Lit(lit) => self.node(1, |w| w.literal(lit)),
+            Unary{ operation, child } => self.node(2, |w| {
+                w.byte(unary(*operation));
+                w.expr(child)
+            }),
+            Binary{ left, operation, right } => self.node(3, |w| {
+                w.byte(binary(*operation));
+                w.expr(left);
+                w.expr(right)
+            }),
+            IfThenElse{ condition, if_branch, else_branch } => self.node(4, |w| {
+                w.expr(condition);
+                w.expr(if_branch);
+                w.expr(else_branch)
+            }),
+            Tuple{ fst, snd } => self.node(5, |w| {
+                w.expr(fst);
+                w.expr(snd)
+            }),
+            Let{ bindings, body } => self.node(6, |w| {
+                w.varint(bindings.len() as u64);
+                for (name, binder) in bindings {
+                    w.str(name);
+                    w.expr(binder)
+                }
+                w.expr(body)
+            }),
+            Lambda{ name, body } => self.node(7, |w| {
+                w.str(name);
+                w.expr(body)
+            }),
+            App{ left, right } => self.node(8, |w| {
+                w.expr(left);
+                w.expr(right)
+            }),
+            Seq(sequence) => self.node(9, |w| w.exprs(sequence)),
+            List(elements) => self.node(10, |w| w.exprs(elements)),
+            Cons{ head, tail } => self.node(11, |w| {
+                w.expr(head);
+                w.expr(tail)
+            }),
+            Funs{ defs, body } => self.node(12, |w| {
+                w.varint(defs.len() as u64);
+                for def in defs {
+                    w.str(def.name);
+                    w.str(def.argument);
+                    w.expr(&def.body)
+                }
+                w.expr(body)
+            }),
+            Annot{ expr, ty } => self.node(13, |w| {
+                w.expr(expr);
+                w.ty_expr(ty)
+            }),
+            Construct{ name, argument } => self.node(14, |w| {
+                w.str(name);
+                w.byte(argument.is_some() as u8);
+                if let Some(argument) = argument {
+                    w.expr(argument)
+                }
+            }),
+            Raise(expr) => self.node(15, |w| w.expr(expr)),
+            Handle{ expr, rules } => self.node(16, |w| {
+                w.expr(expr);
+                w.varint(rules.len() as u64);
+                for rule in rules {
+                    w.pattern(&rule.pattern);
+                    w.expr(&rule.body)
+                }
+            }),
+            While{ condition, body } => self.node(17, |w| {
+                w.expr(condition);
+                w.expr(body)
+            }),
+            Record(fields) => self.node(18, |w| {
+                w.varint(fields.len() as u64);
+                for (label, expr) in fields {
+                    w.str(label);
+                    w.expr(expr)
+                }
+            }),
+            Select{ label, record } => self.node(19, |w| {
+                w.str(label);
+                w.expr(record)
+            }),
+            Error(span) => self.node(20, |w| {
+                w.varint(span.start as u64);
+                w.varint(span.end as u64)
+            }),
+        }
+    }
+    fn exprs(&mut self, exprs: &[Expr]) {
+        self.varint(exprs.len() as u64);
+        for expr in exprs {
+            self.expr(expr)
+        }
+    }
+    fn literal(&mut self, lit: &Literal) {
+        match lit {
+            Literal::Unit => self.byte(0),
+            Literal::Integer(i) => {
+                self.byte(1);
+                self.int(*i)
+            },
+            Literal::Boolean(b) => {
+                self.byte(2);
+                self.byte(*b as u8)
+            },
+            Literal::String(text) => {
+                self.byte(3);
+                self.str(text)
+            },
+            Literal::Char(c) => {
+                self.byte(4);
+                self.varint(*c as u64)
+            },
+            Literal::Real(r) => {
+                self.byte(5);
+                self.out.extend_from_slice(&r.value().to_le_bytes())
+            },
+        }
+    }
+    fn ty_expr(&mut self, ty: &TypeExpr) {
+        match ty {
+            TypeExpr::Unit => self.node(0, |_| {}),
+            TypeExpr::Int => self.node(1, |_| {}),
+            TypeExpr::Bool => self.node(2, |_| {}),
+            TypeExpr::String => self.node(3, |_| {}),
+            TypeExpr::Char => self.node(4, |_| {}),
+            TypeExpr::Real => self.node(5, |_| {}),
+            TypeExpr::Tuple(fst, snd) => self.node(6, |w| {
+                w.ty_expr(fst);
+                w.ty_expr(snd)
+            }),
+            TypeExpr::List(elem) => self.node(7, |w| w.ty_expr(elem)),
+            TypeExpr::Arrow(from, to) => self.node(8, |w| {
+                w.ty_expr(from);
+                w.ty_expr(to)
+            }),
+            TypeExpr::Named(name) => self.node(9, |w| w.str(name)),
+        }
+    }
+    fn ty(&mut self, ty: &Ty) {
+        match ty {
+            Ty::Unit => self.node(0, |_| {}),
+            Ty::Int => self.node(1, |_| {}),
+            Ty::Bool => self.node(2, |_| {}),
+            Ty::String => self.node(3, |_| {}),
+            Ty::Char => self.node(4, |_| {}),
+            Ty::Real => self.node(5, |_| {}),
+            Ty::Tuple(fst, snd) => self.node(6, |w| {
+                w.ty(fst);
+                w.ty(snd)
+            }),
+            Ty::List(elem) => self.node(7, |w| w.ty(elem)),
+        }
+    }
+    fn maybe_ty(&mut self, ty: &Option<Ty>) {
+        self.byte(ty.is_some() as u8);
+        if let Some(ty) = ty {
+            self.ty(ty)
+        }
+    }
+    fn pattern(&mut self, pattern: &Pattern) {
+        match pattern {
+            Pattern::Wildcard => self.node(0, |_| {}),
+            Pattern::Var(name) => self.node(1, |w| w.str(name)),
+            Pattern::Construct{ name, argument } => self.node(2, |w| {
+                w.str(name);
+                w.byte(argument.is_some() as u8);
+                if let Some(argument) = argument {
+                    w.pattern(argument)
+                }
+            }),
+            Pattern::Record(fields) => self.node(3, |w| {
+                w.varint(fields.len() as u64);
+                for (label, pattern) in fields {
+                    w.str(label);
+                    w.pattern(pattern)
+                }
+            }),
+        }
+    }
+}
+
+// the codes of the operators, fixed whatever order the enums list them in
+const UNARY: [UnaryOp; 7] = {
+    use UnaryOp::*;
+    [Not, Fst, Snd, Print, Neg, Ord, Chr]
+};
+const BINARY: [BinaryOp; 14] = {
+    use BinaryOp::*;
+    [
+        Add, Sub, Mult, Div, Mod, Divide,
+        Equal, NotEqual, LessThan, LessEqual, GreaterThan, GreaterEqual,
+        OrElse, AndAlso,
+    ]
+};
+
+fn unary(operation: UnaryOp) -> u8 {
+    UNARY.iter().position(|op| *op == operation).unwrap() as u8
+}
+
+fn binary(operation: BinaryOp) -> u8 {
+    BINARY.iter().position(|op| *op == operation).unwrap() as u8
+}
+
+fn section<F: FnOnce(&mut Writer)>(tag: u8, contents: F) -> Vec<u8> {
+    let mut writer = Writer { out: vec![] };
+    writer.node(tag, contents);
+    writer.out
+}
+
+fn header() -> Vec<u8> {
+    let mut out = MAGIC.to_vec();
+    out.extend_from_slice(&[MAJOR, MINOR]);
+    out
+}
+
+pub fn encode(expr: &Expr) -> Vec<u8> {
+    let mut out = header();
+    out.extend(section(TREE, |w| w.expr(expr)));
+    out
+}
+
+pub fn encode_typed(typed: &Typed) -> Vec<u8> {
+    let mut out = encode(&typed.expr);
+    out.extend(section(TYPES, |w| {
+        w.varint(typed.inputs.len() as u64);
+        for (name, ty) in &typed.inputs {
+            w.str(name);
+            w.maybe_ty(ty)
+        }
+        w.maybe_ty(&typed.result)
+    }));
+    out
+}
+
+struct Reader<'b> {
+    bytes: &'b [u8],
+    at: usize,
+    depth: usize,
+}
+
+impl<'b> Reader<'b> {
+    fn take(&mut self, len: usize) -> Result<&'b [u8], WireError> {
+        let end = self.at.checked_add(len).filter(|end| *end <= self.bytes.len()).ok_or(WireError::Truncated)?;
+        let taken = &self.bytes[self.at..end];
+        self.at = end;
+        Ok(taken)
+    }
+    fn byte(&mut self) -> Result<u8, WireError> {
+        Ok(self.take(1)?[0])
+    }
+    fn bool(&mut self) -> Result<bool, WireError> {
+        let at = self.at;
+        match self.byte()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(WireError::Invalid(at)),
+        }
+    }
+    fn varint(&mut self) -> Result<u64, WireError> {
+        let at = self.at;
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            n |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(n)
+            }
+        }
+        Err(WireError::Invalid(at))
+    }
+    fn len(&mut self) -> Result<usize, WireError> {
+        let at = self.at;
+        usize::try_from(self.varint()?).map_err(|_| WireError::Invalid(at))
+    }
+    fn int(&mut self) -> Result<i64, WireError> {
+        let n = self.varint()?;
+        Ok((n >> 1) as i64 ^ -((n & 1) as i64))
+    }
+    fn str(&mut self) -> Result<&'b str, WireError> {
+        let len = self.len()?;
+        let at = self.at;
+        std::str::from_utf8(self.take(len)?).map_err(|_| WireError::Invalid(at))
+    }
+    // the fields of the next node, read by `fields` from the node alone,
+    // skipping whatever it leaves of them
+    fn node<T, F>(&mut self, fields: F) -> Result<T, WireError>
+    where F: FnOnce(u8, usize, &mut Reader<'b>) -> Result<T, WireError>
+    {
+        let at = self.at;
+        let tag = self.byte()?;
+        let len = u32::from_le_bytes(self.take(4)?.try_into().unwrap()) as usize;
+        let start = self.at;
+        self.take(len)?;
+        if self.depth >= MAX_DEPTH {
+            return Err(WireError::TooDeep)
+        }
+        let mut inner = Reader { bytes: &self.bytes[..start + len], at: start, depth: self.depth + 1 };
+        fields(tag, at, &mut inner)
+    }
+    fn expr(&mut self) -> Result<Expr<'b>, WireError> {
+        use Expr::*;
+        self.node(|tag, at, r| Ok(match tag {
+            0 => Var(r.str()?),
+            1 => Lit(r.literal()?),
+            2 => {
+                let operation = *UNARY.get(r.byte()? as usize).ok_or(WireError::Invalid(at))?;
+                Unary{ operation, child: r.boxed()? }
+            },
+            3 => {
+                let operation = *BINARY.get(r.byte()? as usize).ok_or(WireError::Invalid(at))?;
+                Binary{ operation, left: r.boxed()?, right: r.boxed()? }
+            },
+            4 => IfThenElse{ condition: r.boxed()?, if_branch: r.boxed()?, else_branch: r.boxed()? },
+            5 => Tuple{ fst: r.boxed()?, snd: r.boxed()? },
+            6 => {
+                let bindings = r.many(|r| Ok((r.str()?, r.expr()?)))?;
+                if bindings.is_empty() {
+                    return Err(WireError::Invalid(at))
+                }
+                Let{ bindings, body: r.boxed()? }
+            },
+            7 => Lambda{ name: r.str()?, body: r.boxed()? },
+            8 => App{ left: r.boxed()?, right: r.boxed()? },
+            9 => {
+                // the parser never makes an empty one, nothing after it
+                // expects one
+                let sequence = r.exprs()?;
+                if sequence.is_empty() {
+                    return Err(WireError::Invalid(at))
+                }
+                Seq(sequence)
+            },
+            10 => List(r.exprs()?),
+            11 => Cons{ head: r.boxed()?, tail: r.boxed()? },
+            12 => {
+                let defs = r.many(|r| Ok(Definition { name: r.str()?, argument: r.str()?, body: r.boxed()? }))?;
+                if defs.is_empty() {
+                    return Err(WireError::Invalid(at))
+                }
+                Funs{ defs, body: r.boxed()? }
+            },
+            13 => Annot{ expr: r.boxed()?, ty: r.ty_expr()? },
+            14 => Construct{ name: r.str()?, argument: if r.bool()? { Some(r.boxed()?) } else { None } },
+            15 => Raise(r.boxed()?),
+            16 => {
+                let expr = r.boxed()?;
+                Handle{ expr, rules: r.many(|r| Ok(Rule { pattern: r.pattern()?, body: r.boxed()? }))? }
+            },
+            17 => While{ condition: r.boxed()?, body: r.boxed()? },
+            18 => Record(r.many(|r| Ok((r.str()?, r.expr()?)))?),
+            19 => Select{ label: r.str()?, record: r.boxed()? },
+            20 => Error(Span::new(r.len()?, r.len()?)),
+            tag => return Err(WireError::UnknownNode{ tag, at }),
+        }))
+    }
+    fn boxed(&mut self) -> Result<Box<Expr<'b>>, WireError> {
+        self.expr().map(Box::new)
+    }
+    fn exprs(&mut self) -> Result<Vec<Expr<'b>>, WireError> {
+        self.many(Reader::expr)
+    }
+    fn many<T, F>(&mut self, mut item: F) -> Result<Vec<T>, WireError>
+    where F: FnMut(&mut Reader<'b>) -> Result<T, WireError>
+    {
+        let len = self.len()?;
+        // every item takes a byte at least, so a bad count fails before it
+        // asks for the memory
+        if len > self.bytes.len() - self.at {
+            return Err(WireError::Truncated)
+        }
+        (0..len).map(|_| item(self)).collect()
+    }
+    fn literal(&mut self) -> Result<Literal<'b>, WireError> {
+        let at = self.at;
+        Ok(match self.byte()? {
+            0 => Literal::Unit,
+            1 => Literal::Integer(self.int()?),
+            2 => Literal::Boolean(self.bool()?),
+            3 => Literal::String(self.str()?),
+            4 => {
+                let code = u32::try_from(self.varint()?).ok().and_then(char::from_u32);
+                Literal::Char(code.ok_or(WireError::Invalid(at))?)
+            },
+            5 => {
+                let bits = f64::from_le_bytes(self.take(8)?.try_into().unwrap());
+                Literal::Real(Real::new(bits).ok_or(WireError::Invalid(at))?)
+            },
+            _ => return Err(WireError::Invalid(at)),
+        })
+    }
+    fn ty_expr(&mut self) -> Result<TypeExpr, WireError> {
+        self.node(|tag, at, r| Ok(match tag {
+            0 => TypeExpr::Unit,
+            1 => TypeExpr::Int,
+            2 => TypeExpr::Bool,
+            3 => TypeExpr::String,
+            4 => TypeExpr::Char,
+            5 => TypeExpr::Real,
+            6 => TypeExpr::Tuple(Box::new(r.ty_expr()?), Box::new(r.ty_expr()?)),
+            7 => TypeExpr::List(Box::new(r.ty_expr()?)),
+            8 => TypeExpr::Arrow(Box::new(r.ty_expr()?), Box::new(r.ty_expr()?)),
+            9 => TypeExpr::Named(r.str()?.to_string()),
+            tag => return Err(WireError::UnknownNode{ tag, at }),
+        }))
+    }
+    fn ty(&mut self) -> Result<Ty, WireError> {
+        self.node(|tag, at, r| Ok(match tag {
+            0 => Ty::Unit,
+            1 => Ty::Int,
+            2 => Ty::Bool,
+            3 => Ty::String,
+            4 => Ty::Char,
+            5 => Ty::Real,
+            6 => Ty::Tuple(Box::new(r.ty()?), Box::new(r.ty()?)),
+            7 => Ty::List(Box::new(r.ty()?)),
+            tag => return Err(WireError::UnknownNode{ tag, at }),
+        }))
+    }
+    fn maybe_ty(&mut self) -> Result<Option<Ty>, WireError> {
+        if self.bool()? { self.ty().map(Some) } else { Ok(None) }
+    }
+    fn pattern(&mut self) -> Result<Pattern<'b>, WireError> {
+        self.node(|tag, at, r| Ok(match tag {
+            0 => Pattern::Wildcard,
+            1 => Pattern::Var(r.str()?),
+            2 => Pattern::Construct{
+                name: r.str()?,
+                argument: if r.bool()? { Some(Box::new(r.pattern()?)) } else { None },
+            },
+            3 => Pattern::Record(r.many(|r| Ok((r.str()?, r.pattern()?)))?),
+            tag => return Err(WireError::UnknownNode{ tag, at }),
+        }))
+    }
+}
+
+// the tree `bytes` holds, its names and strings borrowed from `bytes`, with
+// the types when they were sent
+pub fn decode(bytes: &[u8]) -> Result<Typed<'_>, WireError> {
+    if bytes.get(..4) != Some(&MAGIC[..]) {
+        return Err(WireError::NotWire)
+    }
+    let mut reader = Reader { bytes, at: 4, depth: 0 };
+    let major = reader.byte()?;
+    if major != MAJOR {
+        return Err(WireError::Major(major))
+    }
+    reader.byte()?;
+    let mut expr = None;
+    let mut types = None;
+    while reader.at < bytes.len() {
+        reader.node(|tag, _, r| {
+            match tag {
+                TREE => expr = Some(r.expr()?),
+                TYPES => {
+                    let inputs = r.many(|r| Ok((r.str()?, r.maybe_ty()?)))?;
+                    types = Some((inputs, r.maybe_ty()?))
+                },
+                // from a newer minor version
+                _ => {},
+            }
+            Ok(())
+        })?;
+    }
+    let (inputs, result) = types.unwrap_or_default();
+    Ok(Typed { expr: expr.ok_or(WireError::NoTree)?, inputs, result })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::{parse};
+    use crate::optimize::mono::{infer_with, referenced_inputs};
+
+    #[test]
+    fn wire_unit() {
+        let source = "let fun f n = if n < 2 then n else f (n - 1) in \
+            (((f (~10) handle Fail {msg, code = _} => 0, [#x {x = 1, y = #\"c\"}, ()]) : int * int); 2.5) end";
+        let expr = parse(source).unwrap();
+        let bytes = encode(&expr);
+        assert_eq!(&bytes[..6], b"FRWB\x02\x00");
+        let decoded = decode(&bytes).unwrap();
+        assert_eq!((&decoded.expr, decoded.result.as_ref()), (&expr, None));
+        let string = Expr::Lit(Literal::String("a\tb"));
+        let vals = parse("let val x = 1 val y = x in let val z = y in z end end").unwrap();
+        assert_eq!(decode(&encode(&vals)).map(|typed| typed.expr), Ok(vals));
+        assert_eq!(decode(&encode(&string)).map(|typed| typed.expr), Ok(string));
+
+        let rule = parse("age >= limit andalso member").unwrap();
+        let inputs = referenced_inputs(&rule);
+        let result = infer_with(&rule, &inputs);
+        let typed = Typed { expr: rule, inputs, result };
+        let bytes = encode_typed(&typed);
+        assert_eq!(decode(&bytes), Ok(typed.clone()));
+        // the names point into the bytes rather than at copies
+        let decoded = decode(&bytes).unwrap();
+        let within = |name: &str| bytes.as_ptr_range().contains(&name.as_ptr());
+        assert!(decoded.inputs.iter().all(|(name, _)| within(name)));
+
+        // a newer minor version's section and a field added at the end of a
+        // node are skipped
+        let mut newer = encode(&parse("x").unwrap());
+        newer[5] = MINOR + 1;
+        newer[7] += 2;
+        newer.extend_from_slice(&[0xff, 0xee]);
+        newer.extend_from_slice(&[9, 3, 0, 0, 0, 1, 2, 3]);
+        assert_eq!(decode(&newer).map(|typed| typed.expr), Ok(Expr::Var("x")));
+
+        let mut older = bytes.clone();
+        older[4] = 0;
+        assert_eq!(decode(&older), Err(WireError::Major(0)));
+        assert_eq!(decode(b"{\"kind\": \"Var\"}"), Err(WireError::NotWire));
+        assert_eq!(decode(&bytes[..bytes.len() - 3]), Err(WireError::Truncated));
+        let mut unknown = encode(&parse("x").unwrap());
+        unknown[11] = 99;
+        assert_eq!(decode(&unknown), Err(WireError::UnknownNode{ tag: 99, at: 11 }));
+        assert_eq!(decode(&header()), Err(WireError::NoTree));
+        // nor a sequence or a `fun` of nothing, which the parser never makes
+        assert_eq!(decode(&encode(&Expr::Seq(vec![]))), Err(WireError::Invalid(11)));
+        let funs = Expr::Funs{ defs: vec![], body: Box::new(Expr::Var("x")) };
+        assert_eq!(decode(&encode(&funs)), Err(WireError::Invalid(11)));
+    }
+}
@@ -0,0 +1,89 @@
+use std::fmt;
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash)]
+pub enum Backend {
+    TreeWalker,
+    // the tree compiled to closures, `EvalStrategy::Closures`
+    Closures,
+    Vm,
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Backend::*;
+        let name = match *self {
+            TreeWalker => "tree-walker",
+            Closures => "closures",
+            Vm => "vm",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FeatureSet {
+    pub version: &'static str,
+    pub edition: &'static str,
+    pub cargo_features: Vec<&'static str>,
+    pub backends: Vec<Backend>,
+}
+
+impl fmt::Display for FeatureSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "ferus {}", self.version)?;
+        writeln!(f, "edition: {}", self.edition)?;
+        if self.cargo_features.is_empty() {
+            writeln!(f, "features: none")?;
+        } else {
+            writeln!(f, "features: {}", self.cargo_features.join(", "))?;
+        }
+        let backends: Vec<String> = self.backends.iter().map(|b| b.to_string()).collect();
+        write!(f, "backends: {}", backends.join(", "))
+    }
+}
+
+// every cargo feature the crate declares, paired with whether it was
+// compiled in; keep this in sync with the [features] table in Cargo.toml
+const CARGO_FEATURES: &[(&str, bool)] = &[
+    ("serde", cfg!(feature = "serde")),
+    ("int32", cfg!(feature = "int32")),
+    ("wasm", cfg!(feature = "wasm")),
+];
+
+pub fn features() -> FeatureSet {
+    FeatureSet {
+        version: env!("CARGO_PKG_VERSION"),
+        edition: "2018",
+        cargo_features: CARGO_FEATURES.iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(name, _)| *name)
+            .collect(),
+        backends: vec![Backend::TreeWalker, Backend::Closures, Backend::Vm],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn features_unit() {
+        // every feature of the manifest and nothing else
+        let manifest = include_str!("../Cargo.toml");
+        let table = &manifest[manifest.find("[features]").unwrap() + "[features]".len()..];
+        let mut declared: Vec<&str> = table.lines()
+            .take_while(|line| !line.starts_with('['))
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| line.split(" = ").next())
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .collect();
+        let mut known: Vec<&str> = CARGO_FEATURES.iter().map(|(name, _)| *name).collect();
+        declared.sort_unstable();
+        known.sort_unstable();
+        assert_eq!(declared, known);
+        assert_eq!(features().cargo_features.contains(&"wasm"), cfg!(feature = "wasm"));
+        assert_eq!(features().backends, vec![Backend::TreeWalker, Backend::Closures, Backend::Vm]);
+        assert!(features().to_string().starts_with(&format!("ferus {}\n", env!("CARGO_PKG_VERSION"))));
+    }
+}
@@ -0,0 +1,442 @@
+use std::fmt;
+
+use crate::lexer::{Literal};
+use crate::expr::{UnaryOp, BinaryOp, Expr, ERROR};
+use crate::expr::debruijn::{CoreExpr, CorePattern, to_debruijn};
+
+// what comes between the tree the parser makes and a backend: every `fn`
+// and `fun` is lifted out into a `Function` of the `Program`, and where one
+// was written there is a closure made of it and the variables it captures.
+// a variable is a slot of the frame of the function it is used in, the one
+// slot its parameter takes and those its `let`s and patterns bind, or one
+// of the values its closure captured. there are no names left to resolve,
+// no types and no spans
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Ir<'a> {
+    Local(usize),
+    Captured(usize),
+    // what nothing in the program binds
+    Global(&'a str),
+    Lit(Literal<'a>),
+    Unary {
+        operation: UnaryOp,
+        child: Box<Ir<'a>>,
+    },
+    Binary {
+        left: Box<Ir<'a>>,
+        operation: BinaryOp,
+        right: Box<Ir<'a>>,
+    },
+    IfThenElse {
+        condition: Box<Ir<'a>>,
+        if_branch: Box<Ir<'a>>,
+        else_branch: Box<Ir<'a>>,
+    },
+    Tuple {
+        fst: Box<Ir<'a>>,
+        snd: Box<Ir<'a>>,
+    },
+    Let {
+        slot: usize,
+        binder: Box<Ir<'a>>,
+        body: Box<Ir<'a>>,
+    },
+    Closure(Closure),
+    // the closures of a `fun ... and ...`, in the slots from `slot` on. they
+    // can capture each other
+    Rec {
+        slot: usize,
+        closures: Vec<Closure>,
+        body: Box<Ir<'a>>,
+    },
+    App {
+        left: Box<Ir<'a>>,
+        right: Box<Ir<'a>>,
+    },
+    Seq(Vec<Ir<'a>>),
+    List(Vec<Ir<'a>>),
+    Cons {
+        head: Box<Ir<'a>>,
+        tail: Box<Ir<'a>>,
+    },
+    Construct {
+        name: &'a str,
+        argument: Option<Box<Ir<'a>>>,
+    },
+    Raise(Box<Ir<'a>>),
+    Handle {
+        expr: Box<Ir<'a>>,
+        rules: Vec<IrRule<'a>>,
+    },
+    While {
+        condition: Box<Ir<'a>>,
+        body: Box<Ir<'a>>,
+    },
+    Record(Vec<(&'a str, Ir<'a>)>),
+    Select {
+        label: &'a str,
+        record: Box<Ir<'a>>,
+    },
+    // what error recovery gave up on, see `Expr::Error`
+    Unparsed,
+}
+
+// a variable as the function using it sees it
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Var {
+    Local(usize),
+    Captured(usize),
+}
+
+// the function `function` of a `Program` with what it captures, as they
+// are where the closure is made
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Closure {
+    pub function: usize,
+    pub captured: Vec<Var>,
+}
+
+// a pattern binds its variables left to right into the slots from `slot` on
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct IrRule<'a> {
+    pub slot: usize,
+    pub pattern: CorePattern<'a>,
+    pub body: Box<Ir<'a>>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Function<'a> {
+    // its parameter is slot 0
+    pub body: Ir<'a>,
+    pub captures: usize,
+    // how many slots a frame of it takes
+    pub locals: usize,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Program<'a> {
+    pub functions: Vec<Function<'a>>,
+    // the top level, a frame without a parameter
+    pub main: Ir<'a>,
+    pub locals: usize,
+}
+
+impl<'a> Default for Ir<'a> {
+    fn default() -> Ir<'a> {
+        Ir::Lit(Literal::Unit)
+    }
+}
+
+// where a binder in scope keeps its value
+struct Binder {
+    frame: usize,
+    slot: usize,
+}
+
+// a function being lowered, its captures are binders of functions around it
+#[derive(Default)]
+struct Frame {
+    captured: Vec<usize>,
+    depth: usize,
+    locals: usize,
+}
+
+struct Lower<'a> {
+    scope: Vec<Binder>,
+    frames: Vec<Frame>,
+    functions: Vec<Option<Function<'a>>>,
+}
+
+impl<'a> Lower<'a> {
+    fn bind(&mut self) -> usize {
+        let level = self.frames.len() - 1;
+        let frame = &mut self.frames[level];
+        let slot = frame.depth;
+        frame.depth += 1;
+        frame.locals = frame.locals.max(frame.depth);
+        self.scope.push(Binder { frame: level, slot });
+        slot
+    }
+    fn unbind(&mut self, count: usize) {
+        let level = self.frames.len() - 1;
+        self.frames[level].depth -= count;
+        self.scope.truncate(self.scope.len() - count);
+    }
+    // the binder `binder` seen from the function at `level`, capturing it
+    // there when it belongs to one around it
+    fn resolve(&mut self, binder: usize, level: usize) -> Var {
+        if self.scope[binder].frame == level {
+            return Var::Local(self.scope[binder].slot)
+        }
+        let captured = &mut self.frames[level].captured;
+        match captured.iter().position(|&known| known == binder) {
+            Some(index) => Var::Captured(index),
+            None => {
+                captured.push(binder);
+                Var::Captured(captured.len() - 1)
+            },
+        }
+    }
+    // lifts `body` out into a function of one parameter
+    fn function(&mut self, body: &CoreExpr<'a>) -> Closure {
+        let function = self.functions.len();
+        self.functions.push(None);
+        self.frames.push(Frame::default());
+        self.bind();
+        let body = self.lower(body);
+        self.unbind(1);
+        let frame = self.frames.pop().unwrap_or_default();
+        self.functions[function] = Some(Function { body, captures: frame.captured.len(), locals: frame.locals });
+        let level = self.frames.len() - 1;
+        let captured = frame.captured.iter().map(|&binder| self.resolve(binder, level)).collect();
+        Closure { function, captured }
+    }
+    fn lower(&mut self, expr: &CoreExpr<'a>) -> Ir<'a> {
+        use CoreExpr as Core;
+        let mut go = |expr: &CoreExpr<'a>| Box::new(self.lower(expr));
+        match expr {
+            Core::BoundVar(index) => {
+                let binder = self.scope.len() - 1 - index;
+                match self.resolve(binder, self.frames.len() - 1) {
+                    Var::Local(slot) => Ir::Local(slot),
+                    Var::Captured(index) => Ir::Captured(index),
+                }
+            },
+            Core::FreeVar(name) => Ir::Global(name),
+            Core::Lit(lit) => Ir::Lit(*lit),
+            Core::Unary{ operation, child } => Ir::Unary{ operation: *operation, child: go(child) },
+            Core::Binary{ left, operation, right } => {
+                let left = go(left);
+                Ir::Binary{ left, operation: *operation, right: go(right) }
+            },
+            Core::IfThenElse{ condition, if_branch, else_branch } => {
+                let (condition, if_branch) = (go(condition), go(if_branch));
+                Ir::IfThenElse{ condition, if_branch, else_branch: go(else_branch) }
+            },
+            Core::Tuple{ fst, snd } => {
+                let fst = go(fst);
+                Ir::Tuple{ fst, snd: go(snd) }
+            },
+            // a `let` of a binding each, nested
+            Core::Let{ binders, body } => {
+                let mut bound = vec![];
+                for binder in binders {
+                    let binder = Box::new(self.lower(binder));
+                    bound.push((self.bind(), binder));
+                }
+                let body = self.lower(body);
+                self.unbind(binders.len());
+                bound.into_iter().rev().fold(body, |body, (slot, binder)| {
+                    Ir::Let{ slot, binder, body: Box::new(body) }
+                })
+            },
+            Core::Lambda(body) => Ir::Closure(self.function(body)),
+            Core::App{ left, right } => {
+                let left = go(left);
+                Ir::App{ left, right: go(right) }
+            },
+            // what parentheses leave
+            Core::Seq(sequence) if sequence.len() == 1 => self.lower(&sequence[0]),
+            Core::Seq(sequence) => Ir::Seq(sequence.iter().map(|expr| self.lower(expr)).collect()),
+            Core::List(elements) => Ir::List(elements.iter().map(|expr| self.lower(expr)).collect()),
+            Core::Cons{ head, tail } => {
+                let head = go(head);
+                Ir::Cons{ head, tail: go(tail) }
+            },
+            Core::Funs{ defs, body } => {
+                let slot = self.bind();
+                for _ in 1..defs.len() {
+                    self.bind();
+                }
+                let closures = defs.iter().map(|def| self.function(def)).collect();
+                let body = Box::new(self.lower(body));
+                self.unbind(defs.len());
+                Ir::Rec{ slot, closures, body }
+            },
+            Core::Annot{ expr, .. } => self.lower(expr),
+            Core::Construct{ name, argument } => Ir::Construct {
+                name,
+                argument: argument.as_ref().map(|argument| Box::new(self.lower(argument))),
+            },
+            Core::Raise(expr) => Ir::Raise(go(expr)),
+            Core::Handle{ expr, rules } => {
+                let expr = go(expr);
+                let rules = rules.iter().map(|rule| {
+                    let count = binds(&rule.pattern);
+                    let slot = self.frames[self.frames.len() - 1].depth;
+                    for _ in 0..count {
+                        self.bind();
+                    }
+                    let body = Box::new(self.lower(&rule.body));
+                    self.unbind(count);
+                    IrRule { slot, pattern: rule.pattern.clone(), body }
+                }).collect();
+                Ir::Handle{ expr, rules }
+            },
+            Core::While{ condition, body } => {
+                let condition = go(condition);
+                Ir::While{ condition, body: go(body) }
+            },
+            Core::Record(fields) => {
+                Ir::Record(fields.iter().map(|(label, expr)| (*label, self.lower(expr))).collect())
+            },
+            Core::Select{ label, record } => Ir::Select{ label, record: go(record) },
+            Core::Error(_) => Ir::Unparsed,
+        }
+    }
+}
+
+// how many variables `pattern` binds
+fn binds(pattern: &CorePattern) -> usize {
+    match pattern {
+        CorePattern::Wildcard => 0,
+        CorePattern::Bind => 1,
+        CorePattern::Construct{ argument, .. } => argument.as_ref().map_or(0, |argument| binds(argument)),
+        CorePattern::Record(fields) => fields.iter().map(|(_, pattern)| binds(pattern)).sum(),
+    }
+}
+
+// `expr` with its functions lifted out and its closures made explicit
+pub fn lower<'a>(expr: &Expr<'a>) -> Program<'a> {
+    let mut lower = Lower { scope: vec![], frames: vec![Frame::default()], functions: vec![] };
+    let main = lower.lower(&to_debruijn(expr));
+    let locals = lower.frames[0].locals;
+    Program { functions: lower.functions.into_iter().flatten().collect(), main, locals }
+}
+
+impl fmt::Display for Var {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Var::Local(slot) => write!(f, "l{}", slot),
+            Var::Captured(index) => write!(f, "c{}", index),
+        }
+    }
+}
+
+impl fmt::Display for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let captured: Vec<String> = self.captured.iter().map(Var::to_string).collect();
+        write!(f, "fn{}[{}]", self.function, captured.join(", "))
+    }
+}
+
+// the variables a pattern binds as the slots they go in
+fn write_pattern(f: &mut fmt::Formatter, pattern: &CorePattern, slot: &mut usize) -> fmt::Result {
+    match pattern {
+        CorePattern::Wildcard => write!(f, "_"),
+        CorePattern::Bind => {
+            *slot += 1;
+            write!(f, "l{}", *slot - 1)
+        },
+        CorePattern::Construct{ name, argument: None } => write!(f, "{}", name),
+        CorePattern::Construct{ name, argument: Some(argument) } => {
+            write!(f, "({} ", name)?;
+            write_pattern(f, argument, slot)?;
+            write!(f, ")")
+        },
+        CorePattern::Record(fields) => {
+            write!(f, "{{")?;
+            for (i, (label, pattern)) in fields.iter().enumerate() {
+                write!(f, "{}{} = ", if i == 0 { "" } else { ", " }, label)?;
+                write_pattern(f, pattern, slot)?;
+            }
+            write!(f, "}}")
+        },
+    }
+}
+
+// every compound in parentheses, `l0` is a slot and `c0` a captured value
+impl<'a> fmt::Display for Ir<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let list = |items: &[Ir], separator: &str| -> String {
+            items.iter().map(Ir::to_string).collect::<Vec<String>>().join(separator)
+        };
+        match self {
+            Ir::Local(slot) => write!(f, "l{}", slot),
+            Ir::Captured(index) => write!(f, "c{}", index),
+            Ir::Global(name) => write!(f, "{}", name),
+            Ir::Lit(lit) => write!(f, "{}", lit),
+            Ir::Unary{ operation, child } => write!(f, "({} {})", operation, child),
+            Ir::Binary{ left, operation, right } => write!(f, "({} {} {})", left, operation, right),
+            Ir::IfThenElse{ condition, if_branch, else_branch } => {
+                write!(f, "(if {} then {} else {})", condition, if_branch, else_branch)
+            },
+            Ir::Tuple{ fst, snd } => write!(f, "({}, {})", fst, snd),
+            Ir::Let{ slot, binder, body } => write!(f, "(let l{} = {} in {})", slot, binder, body),
+            Ir::Closure(closure) => write!(f, "{}", closure),
+            Ir::Rec{ slot, closures, body } => {
+                write!(f, "(rec ")?;
+                for (i, closure) in closures.iter().enumerate() {
+                    write!(f, "{}l{} = {}", if i == 0 { "" } else { " and " }, slot + i, closure)?;
+                }
+                write!(f, " in {})", body)
+            },
+            Ir::App{ left, right } => write!(f, "({} {})", left, right),
+            Ir::Seq(sequence) => write!(f, "({})", list(sequence, "; ")),
+            Ir::List(elements) => write!(f, "[{}]", list(elements, ", ")),
+            Ir::Cons{ head, tail } => write!(f, "({} :: {})", head, tail),
+            Ir::Construct{ name, argument: None } => write!(f, "{}", name),
+            Ir::Construct{ name, argument: Some(argument) } => write!(f, "({} {})", name, argument),
+            Ir::Raise(expr) => write!(f, "(raise {})", expr),
+            Ir::Handle{ expr, rules } => {
+                write!(f, "({} handle ", expr)?;
+                for (i, rule) in rules.iter().enumerate() {
+                    write!(f, "{}", if i == 0 { "" } else { " | " })?;
+                    let mut slot = rule.slot;
+                    write_pattern(f, &rule.pattern, &mut slot)?;
+                    write!(f, " => {}", rule.body)?;
+                }
+                write!(f, ")")
+            },
+            Ir::While{ condition, body } => write!(f, "(while {} do {})", condition, body),
+            Ir::Record(fields) => {
+                let fields: Vec<String> = fields.iter().map(|(label, ir)| format!("{} = {}", label, ir)).collect();
+                write!(f, "{{{}}}", fields.join(", "))
+            },
+            Ir::Select{ label, record } => write!(f, "(#{} {})", label, record),
+            Ir::Unparsed => write!(f, "{}", ERROR),
+        }
+    }
+}
+
+// `fn0 = ...` for each function and then `main = ...`
+impl<'a> fmt::Display for Program<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, function) in self.functions.iter().enumerate() {
+            writeln!(f, "fn{} = {}", i, function.body)?;
+        }
+        write!(f, "main = {}", self.main)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::{parse};
+
+    #[test]
+    fn lower_unit() {
+        let lowered = |source: &'static str| lower(&parse(source).unwrap()).to_string();
+        // the inner function captures the outer one's parameter
+        assert_eq!(lowered("fn x => fn y => x + y"), "fn0 = fn1[l0]\nfn1 = (c0 + l0)\nmain = fn0[]");
+        // a `fun` captures itself and what is around it
+        assert_eq!(
+            lowered("let val k = 10 in let fun f n = if n = 0 then k else f (n - 1) in f 3 end end"),
+            concat!(
+                "fn0 = (if (l0 = 0) then c0 else (c1 (l0 - 1)))\n",
+                "main = (let l0 = 10 in (rec l1 = fn0[l0, l1] in (l1 3)))",
+            ),
+        );
+        // what a function only passes on is captured by the ones around it too
+        assert_eq!(
+            lowered("fn a => fn b => fn c => a c"),
+            "fn0 = fn1[l0]\nfn1 = fn2[c0]\nfn2 = (c0 l0)\nmain = fn0[]",
+        );
+        let program = lower(&parse("fn p => (raise E) handle Pair {x, y = z} => x + z + p | _ => g").unwrap());
+        assert_eq!(program.functions[0].locals, 3);
+        assert_eq!(
+            program.to_string(),
+            "fn0 = ((raise E) handle (Pair {x = l1, y = l2}) => ((l1 + l2) + l0) | _ => g)\nmain = fn0[]",
+        );
+    }
+}
@@ -1,18 +1,39 @@
 use std::fmt;
 use combine::{
-    EasyParser, Stream, RangeStream,
-    stream::{StreamOnce, Positioned, ResetStream},
-    choice, eof, satisfy_map, attempt,
-    parser::char::{string},
-    parser::range::{take_while1},
+    EasyParser, Stream, RangeStream, parser,
+    error::{Commit, ParseError, StreamError},
+    stream::{StreamOnce, StreamErrorFor, Positioned, ResetStream},
+    choice, eof, satisfy, satisfy_map, attempt, optional, one_of,
+    parser::char::{char, string},
+    parser::range::{take_while1, recognize},
 };
 
+use crate::runtime::real::{Real};
+use crate::runtime::width::{Width};
+
+pub mod stream;
+
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "kind", content = "value"))]
 pub enum Literal<'a> {
     Unit,
     Integer(i64),
     Boolean(bool),
     String(&'a str),
+    Char(char),
+    Real(Real),
+}
+
+// how `c` is written between the quotes of a character literal
+pub fn escape(c: char) -> String {
+    match c {
+        '\n' => "\\n".to_string(),
+        '\t' => "\\t".to_string(),
+        '\\' => "\\\\".to_string(),
+        '"' => "\\\"".to_string(),
+        c => c.to_string(),
+    }
 }
 
 impl<'a> fmt::Display for Literal<'a> {
@@ -22,20 +43,27 @@ impl<'a> fmt::Display for Literal<'a> {
             Unit => write!(f, "()"),
             Integer(i) => write!(f, "{}", i),
             String(s)  => write!(f, "{}", s),
-            Boolean(b) => write!(f, "{}", b)
+            Boolean(b) => write!(f, "{}", b),
+            Char(c) => write!(f, "#\"{}\"", escape(c)),
+            Real(x) => write!(f, "{}", x),
         }
     }
 }
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Direction {
     Left,
     Right,
 }
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "kind", content = "value"))]
 pub enum Delimiter {
     Paren(Direction),
+    Bracket(Direction),
+    Brace(Direction),
     Semicolon,
     Comma,
 }
@@ -47,6 +75,10 @@ impl fmt::Display for Delimiter {
         let name = match *self {
             Paren(Left) => "(",
             Paren(Right) => ")",
+            Bracket(Left) => "[",
+            Bracket(Right) => "]",
+            Brace(Left) => "{",
+            Brace(Right) => "}",
             Semicolon => ";",
             Comma => ",",
         };
@@ -55,14 +87,20 @@ impl fmt::Display for Delimiter {
 }
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Reserved {
     Add,
     Sub,
     Mult,
     Div,
     Mod,
+    Divide,
     Equal,
+    NotEqual,
     LessThan,
+    LessEqual,
+    GreaterThan,
+    GreaterEqual,
     OrElse,
     AndAlso,
     If,
@@ -79,7 +117,27 @@ pub enum Reserved {
     Snd,
     Print,
     And,
-    Fun
+    Fun,
+    Rec,
+    Cons,
+    Nil,
+    Neg,
+    Colon,
+    TypeArrow,
+    Datatype,
+    Of,
+    Bar,
+    Raise,
+    Handle,
+    Wildcard,
+    While,
+    Do,
+    Ord,
+    Chr,
+    Infix,
+    Infixr,
+    // `#label`, a record's field
+    Select,
 }
 
 impl fmt::Display for Reserved {
@@ -91,8 +149,13 @@ impl fmt::Display for Reserved {
             Mult => "*",
             Div => "div",
             Mod => "mod",
+            Divide => "/",
             Equal => "=",
+            NotEqual => "<>",
             LessThan => "<",
+            LessEqual => "<=",
+            GreaterThan => ">",
+            GreaterEqual => ">=",
             OrElse => "orelse",
             AndAlso => "andalso",
             If => "if",
@@ -109,19 +172,46 @@ impl fmt::Display for Reserved {
             Snd => "snd",
             Print => "print",
             And => "and",
-            Fun => "fun"
+            Fun => "fun",
+            Rec => "rec",
+            Cons => "::",
+            Nil => "nil",
+            Neg => "~",
+            Colon => ":",
+            TypeArrow => "->",
+            Datatype => "datatype",
+            Of => "of",
+            Bar => "|",
+            Raise => "raise",
+            Handle => "handle",
+            Wildcard => "_",
+            While => "while",
+            Do => "do",
+            Ord => "ord",
+            Chr => "chr",
+            Infix => "infix",
+            Infixr => "infixr",
+            Select => "#",
         };
         write!(f, "{}", name)
     }
 }
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "kind", content = "value"))]
 pub enum Token<'a> {
     Name(&'a str),
     Lit(Literal<'a>),
     Space(usize),
     Delim(Delimiter),
     Keyword(Reserved),
+    // a number literal too large for the integer width or for a real,
+    // kept as its text so the parser can point at it
+    OutOfRange(&'a str),
+    // never lexed, what error recovery puts in place of the tokens it gave
+    // up on, see `expr::recover`
+    Error(Span),
     EndOfFile,
 }
 
@@ -134,17 +224,51 @@ impl<'a> fmt::Display for Token<'a> {
             Space(c) => write!(f, "SPACE({})", c),
             Delim(d) => write!(f, "{}", d),
             Keyword(res) => write!(f, "{}", res),
+            OutOfRange(digits) => write!(f, "{}", digits),
+            Error(_) => write!(f, "ERROR"),
             EndOfFile => write!(f, "EOF"),
         }
     }
 }
 
+// length in bytes of the whitespace and (possibly nested) `(* *)` comments at
+// the start of `source`. an unterminated comment runs to the end of the input
+// so the parser sees it as incomplete rather than as garbage
+fn trivia(source: &str) -> usize {
+    let mut depth = 0;
+    let mut i = 0;
+    while i < source.len() {
+        let rest = &source[i..];
+        if rest.starts_with("(*") {
+            depth += 1;
+            i += 2;
+        } else if 0 < depth && rest.starts_with("*)") {
+            depth -= 1;
+            i += 2;
+        } else {
+            match rest.chars().next() {
+                Some(c) if 0 < depth || c.is_whitespace() => i += c.len_utf8(),
+                _ => break,
+            }
+        }
+    }
+    i
+}
+
 parser!{
     pub fn spaces['a, Input]()(Input) -> Token<'a>
     where [ Input: RangeStream<Item = char, Range = &'a str> ]
     {
         use Token::*;
-        take_while1(|c: char| c.is_whitespace()).map(|tok: &'a str| Space(tok.len()))
+        parser(|input: &mut Input| {
+            let len = trivia(input.range());
+            if len == 0 {
+                return Err(Commit::Peek(Input::Error::empty(input.position()).into()))
+            }
+            // `len` never runs past the end of the range
+            let _ = input.uncons_range(len);
+            Ok((Space(len), Commit::Commit(())))
+        })
     }
 }
 
@@ -157,16 +281,56 @@ parser!{
     }
 }
 
+// an integer, or a real when it has a fraction (`3.14`), an exponent
+// (`1e-9`, `1E~9`) or both
 parser!{
-    pub fn number['a, Input]()(Input) -> Literal<'a>
+    pub fn number['a, Input]()(Input) -> Token<'a>
     where [ Input: RangeStream<Item = char, Range = &'a str> ]
     {
-        use Literal::*;
-        let integer = take_while1(|c: char| c.is_digit(10)).map(|string: &'a str| string.parse::<i64>().unwrap());
-        integer.map(Integer)
+        use Token::*;
+        let digits = || take_while1(|c: char| c.is_ascii_digit());
+        let fraction = attempt((char('.'), digits()));
+        let exponent = attempt((one_of("eE".chars()), optional(one_of("-~".chars())), digits()));
+        recognize((digits(), optional(fraction), optional(exponent))).map(|text: &'a str| {
+            if text.chars().all(|c| c.is_ascii_digit()) {
+                Width::default().parse(text).map(Literal::Integer)
+            } else {
+                Real::parse(text).map(Literal::Real)
+            }.map_or(OutOfRange(text), Lit)
+        })
+    }
+}
+
+// `#"a"`, with `\n`, `\t`, `\\` and `\"` for the characters that can not
+// stand for themselves
+parser!{
+    pub fn character['a, Input]()(Input) -> Literal<'a>
+    where [ Input: RangeStream<Item = char, Range = &'a str> ]
+    {
+        let escaped = char('\\').with(satisfy_map(|c: char| match c {
+            'n' => Some('\n'),
+            't' => Some('\t'),
+            '\\' | '"' => Some(c),
+            _ => None,
+        }));
+        let plain = satisfy(|c: char| c != '"' && c != '\\' && c != '\n');
+        (string("#\""), choice((escaped, plain)), char('"')).map(|(_, c, _)| Literal::Char(c))
     }
 }
 
+// the reserved words spelled with letters, `alphabetic` turns each of them
+// into its keyword
+pub const WORDS: [Reserved; 30] = [
+    Reserved::Div, Reserved::Mod, Reserved::OrElse, Reserved::AndAlso,
+    Reserved::If, Reserved::Then, Reserved::Else, Reserved::Not,
+    Reserved::Let, Reserved::Val, Reserved::In, Reserved::End,
+    Reserved::Fn, Reserved::Fst, Reserved::Snd, Reserved::Print,
+    Reserved::And, Reserved::Fun, Reserved::Rec, Reserved::Nil,
+    Reserved::Datatype, Reserved::Of, Reserved::Raise, Reserved::Handle,
+    Reserved::While, Reserved::Do, Reserved::Ord, Reserved::Chr,
+    Reserved::Infix, Reserved::Infixr,
+];
+
 parser!{
     pub fn alphabetic['a, Input]()(Input) -> Token<'a>
     where [ Input: RangeStream<Item = char, Range = &'a str> ]
@@ -193,6 +357,18 @@ parser!{
             "print" => Keyword(Print),
             "and" => Keyword(And),
             "fun" => Keyword(Fun),
+            "rec" => Keyword(Rec),
+            "nil" => Keyword(Nil),
+            "datatype" => Keyword(Datatype),
+            "of" => Keyword(Of),
+            "raise" => Keyword(Raise),
+            "handle" => Keyword(Handle),
+            "while" => Keyword(While),
+            "do" => Keyword(Do),
+            "ord" => Keyword(Ord),
+            "chr" => Keyword(Chr),
+            "infix" => Keyword(Infix),
+            "infixr" => Keyword(Infixr),
             "true" => Lit(Boolean(true)),
             "false" => Lit(Boolean(false)),
             _ => Name(tok)
@@ -209,6 +385,10 @@ parser!{
         satisfy_map(|c: char| match c {
             '(' => Some(Paren(Left)),
             ')' => Some(Paren(Right)),
+            '[' => Some(Bracket(Left)),
+            ']' => Some(Bracket(Right)),
+            '{' => Some(Brace(Left)),
+            '}' => Some(Brace(Right)),
             ';' => Some(Semicolon),
             ',' => Some(Comma),
             _   => None,
@@ -216,7 +396,7 @@ parser!{
     }
 }
 
-const OPERATORS: &'static str = "+-*/<>=";
+const OPERATORS: &'static str = "+-*/<>=:";
 
 parser!{
     pub fn operator['a, Input]()(Input) -> Token<'a>
@@ -225,14 +405,23 @@ parser!{
         use Reserved::*;
         use Token::*;
         let is_operator = |c: char| OPERATORS.chars().any(|r| r == c);
-        take_while1(is_operator).flat_map(|tok| match tok {
+        take_while1(is_operator).and_then(|tok: &'a str| match tok {
             "+" => Ok(Keyword(Add)),
             "-" => Ok(Keyword(Sub)),
             "*" => Ok(Keyword(Mult)),
+            "/" => Ok(Keyword(Divide)),
             "=" => Ok(Keyword(Equal)),
+            "<>" => Ok(Keyword(NotEqual)),
             "<" => Ok(Keyword(LessThan)),
+            "<=" => Ok(Keyword(LessEqual)),
+            ">" => Ok(Keyword(GreaterThan)),
+            ">=" => Ok(Keyword(GreaterEqual)),
             "=>" => Ok(Keyword(Arrow)),
-            _ => panic!("lexing failure"), // TODO
+            "::" => Ok(Keyword(Cons)),
+            ":" => Ok(Keyword(Colon)),
+            "->" => Ok(Keyword(TypeArrow)),
+            // a run of operator characters that is no operator, like `=<`
+            _ => Err(StreamErrorFor::<Input>::unexpected_static_message("operator")),
         })
     }
 }
@@ -247,13 +436,80 @@ parser!{
             spaces(),
             attempt(unit()).map(Lit),
             delimiter().map(Delim),
-            number().map(Lit),
+            number(),
+            // `#` on its own selects a field, like in `#x point`
+            attempt(character()).map(Lit),
+            char('#').map(|_| Keyword(Reserved::Select)),
             alphabetic(),
+            // never part of a longer operator so `x-~1` lexes
+            char('~').map(|_| Keyword(Reserved::Neg)),
+            char('|').map(|_| Keyword(Reserved::Bar)),
+            char('_').map(|_| Keyword(Reserved::Wildcard)),
             operator()
         )
     }
 }
 
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+    // the span of the token starting at byte offset `start` of `source`
+    pub fn of_token(source: &str, start: usize) -> Span {
+        match token().easy_parse(&source[start..]) {
+            Ok((_, rest)) => Span::new(start, source.len() - rest.len()),
+            Err(_) => Span::new(start, start),
+        }
+    }
+    // the span of the text at byte offset `start` of `source` that is no
+    // token, a run of operator characters like `=<` or else one character
+    pub fn of_unlexable(source: &str, start: usize) -> Span {
+        let rest = &source[start..];
+        let run = rest.find(|c: char| !OPERATORS.contains(c)).unwrap_or(rest.len());
+        let len = match rest.chars().next() {
+            Some(c) if run == 0 => c.len_utf8(),
+            _ => run,
+        };
+        Span::new(start, start + len)
+    }
+    // 1 based line and column of the start of the span
+    pub fn line_col(&self, source: &str) -> (usize, usize) {
+        let before = &source[..self.start];
+        let line = before.matches('\n').count() + 1;
+        let col = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+        (line, col)
+    }
+}
+
+// every token of `source` but whitespace and comments, with where it is.
+// for tools that work on text as it is being typed, so anything that does
+// not lex is skipped a character at a time instead of failing
+pub fn spanned(source: &str) -> Vec<(Span, Token<'_>)> {
+    let mut tokens = vec![];
+    let mut start = 0;
+    while start < source.len() {
+        match token().easy_parse(&source[start..]) {
+            Ok((Token::EndOfFile, _)) => break,
+            Ok((token, rest)) => {
+                let end = source.len() - rest.len();
+                if !matches!(token, Token::Space(_)) {
+                    tokens.push((Span::new(start, end), token))
+                }
+                start = end
+            },
+            Err(_) => start += source[start..].chars().next().map_or(1, char::len_utf8),
+        }
+    }
+    tokens
+}
+
 pub struct Tokenizer<'a> {
     stream: &'a str,
     size: usize,
@@ -284,7 +540,9 @@ impl<'a> StreamOnce for Tokenizer<'a> {
                 self.current = self.size - rest.len();
                 Ok(token)
             },
-            Err(e) => panic!("{:?}", e)
+            // nothing lexes here, like a `"` or a `$`. the position is still
+            // in front of it, so the error points at it
+            Err(_) => Err(combine::error::StringStreamError::UnexpectedParse),
         }
     }
 }
@@ -302,6 +560,7 @@ impl<'a> ResetStream for Tokenizer<'a> {
     }
     fn reset(&mut self, checkpoint: Checkpoint<'a>) -> Result<(), Self::Error> {
         self.stream = checkpoint.stream;
+        self.current = self.size - self.stream.len();
         Ok(())
     }
 }
@@ -374,5 +633,63 @@ mod tests {
         assert_eq!(result, Ok(should))
     }
 
+    #[test]
+    fn tokenizer_unit4() {
+        let tokenizer = Tokenizer::new("1::[2, 3] :: nil");
+        let result = run_tokenizer(tokenizer);
+        let should = vec![
+            Lit(Integer(1)), Keyword(Cons), Delim(Delimiter::Bracket(Direction::Left)),
+            Lit(Integer(2)), Delim(Delimiter::Comma), Space(1), Lit(Integer(3)),
+            Delim(Delimiter::Bracket(Direction::Right)), Space(1), Keyword(Cons),
+            Space(1), Keyword(Nil)
+        ];
+        assert_eq!(result, Ok(should))
+    }
+
+    #[test]
+    fn tokenizer_comments_unit() {
+        let tokenizer = Tokenizer::new("1 (* one (* nested *) *)+(**)2 (* open");
+        let result = run_tokenizer(tokenizer);
+        let should = vec![
+            Lit(Integer(1)), Space(23), Keyword(Add), Space(4), Lit(Integer(2)), Space(8)
+        ];
+        assert_eq!(result, Ok(should))
+    }
+
+    #[test]
+    fn tokenizer_chars_unit() {
+        let tokenizer = Tokenizer::new(r##"ord #"a"::#"\n"::#"\""::#"#""##);
+        let result = run_tokenizer(tokenizer);
+        let should = vec![
+            Keyword(Ord), Space(1), Lit(Char('a')), Keyword(Cons), Lit(Char('\n')), Keyword(Cons),
+            Lit(Char('"')), Keyword(Cons), Lit(Char('#'))
+        ];
+        assert_eq!(result, Ok(should));
+        assert_eq!(Char('"').to_string(), r#"#"\"""#);
+    }
+
+    #[test]
+    fn tokenizer_reals_unit() {
+        let tokenizer = Tokenizer::new("3.14/1e-9 2E~1 2e 3 else 1e999");
+        let result = run_tokenizer(tokenizer);
+        let real = |text| Lit(Real(crate::runtime::real::Real::parse(text).unwrap()));
+        let should = vec![
+            real("3.14"), Keyword(Divide), real("1e-9"), Space(1), real("0.2"), Space(1),
+            Lit(Integer(2)), Name("e"), Space(1), Lit(Integer(3)), Space(1), Keyword(Else), Space(1),
+            OutOfRange("1e999")
+        ];
+        assert_eq!(result, Ok(should))
+    }
+
+    #[test]
+    fn tokenizer_records_unit() {
+        let tokenizer = Tokenizer::new(r##"#x{x=#"#"}"##);
+        let result = run_tokenizer(tokenizer);
+        let should = vec![
+            Keyword(Select), Name("x"), Delim(Delimiter::Brace(Direction::Left)), Name("x"), Keyword(Equal),
+            Lit(Char('#')), Delim(Delimiter::Brace(Direction::Right))
+        ];
+        assert_eq!(result, Ok(should))
+    }
 
 }
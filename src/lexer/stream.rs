@@ -0,0 +1,183 @@
+use std::fmt;
+use std::io::{self, BufRead};
+use combine::{EasyParser};
+
+use crate::expr::owned::{OwnedLiteral};
+use crate::lexer::{Delimiter, Reserved, Span, Token, token};
+use crate::locale::{message};
+
+// `Token` borrows its text from the source, these are for sources that are
+// never all in memory at once
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum OwnedToken {
+    Name(String),
+    Lit(OwnedLiteral),
+    Delim(Delimiter),
+    Keyword(Reserved),
+    OutOfRange(String),
+}
+
+impl<'a> Token<'a> {
+    // `None` for whitespace, comments, the end of the input and what only
+    // error recovery makes
+    pub fn into_owned(self) -> Option<OwnedToken> {
+        match self {
+            Token::Name(name) => Some(OwnedToken::Name(name.to_string())),
+            Token::Lit(lit) => Some(OwnedToken::Lit(lit.into_owned())),
+            Token::Delim(delim) => Some(OwnedToken::Delim(delim)),
+            Token::Keyword(keyword) => Some(OwnedToken::Keyword(keyword)),
+            Token::OutOfRange(digits) => Some(OwnedToken::OutOfRange(digits.to_string())),
+            Token::Space(_) | Token::Error(_) | Token::EndOfFile => None,
+        }
+    }
+}
+
+impl OwnedToken {
+    pub fn as_token(&self) -> Token<'_> {
+        match *self {
+            OwnedToken::Name(ref name) => Token::Name(name),
+            OwnedToken::Lit(ref lit) => Token::Lit(lit.as_literal()),
+            OwnedToken::Delim(delim) => Token::Delim(delim),
+            OwnedToken::Keyword(keyword) => Token::Keyword(keyword),
+            OwnedToken::OutOfRange(ref digits) => Token::OutOfRange(digits),
+        }
+    }
+}
+
+impl fmt::Display for OwnedToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_token())
+    }
+}
+
+#[derive(Debug)]
+pub enum LexError {
+    Io(io::Error),
+    // a character no token starts with, 1 based line and column
+    Invalid {
+        span: Span,
+        line: usize,
+        col: usize,
+        text: String,
+    },
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LexError::Io(err) => write!(f, "{}", err),
+            LexError::Invalid{ line, col, text, .. } => {
+                write!(f, "{}: {}", message("parse-error", &[line, col]), message("unexpected", &[text]))
+            },
+        }
+    }
+}
+
+// the tokens read from `reader` a line at a time, whitespace and comments
+// left out, with where they are in the whole input. only the line being
+// lexed is held, and the lines a comment runs over. a character that does
+// not lex is reported and skipped, so lexing can go on past it
+pub struct Tokens<R> {
+    reader: R,
+    buffer: String,
+    // how far into `buffer` is lexed
+    start: usize,
+    // the offset and line `buffer` starts at
+    offset: usize,
+    line: usize,
+    eof: bool,
+}
+
+impl<R: BufRead> Tokens<R> {
+    pub fn new(reader: R) -> Tokens<R> {
+        Tokens { reader, buffer: String::new(), start: 0, offset: 0, line: 1, eof: false }
+    }
+    // drops the whole lines already lexed and reads the next one
+    fn fill(&mut self) -> io::Result<()> {
+        if let Some(newline) = self.buffer[..self.start].rfind('\n') {
+            let lexed = newline + 1;
+            self.line += self.buffer[..lexed].matches('\n').count();
+            self.offset += lexed;
+            self.start -= lexed;
+            self.buffer.drain(..lexed);
+        }
+        if self.reader.read_line(&mut self.buffer)? == 0 {
+            self.eof = true
+        }
+        Ok(())
+    }
+    fn span(&self, len: usize) -> Span {
+        Span::new(self.offset + self.start, self.offset + self.start + len)
+    }
+}
+
+impl<R: BufRead> Iterator for Tokens<R> {
+    type Item = Result<(Span, OwnedToken), LexError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let rest = &self.buffer[self.start..];
+            let lexed = match token().easy_parse(rest) {
+                Ok((token, after)) => Ok((token.into_owned(), rest.len() - after.len())),
+                Err(_) => Err(rest.chars().next()),
+            };
+            // a token up against the end of what is read may go on in the
+            // next line, a comment or a run of whitespace can
+            let complete = matches!(lexed, Ok((_, len)) if len < rest.len());
+            if !complete && !self.eof {
+                if let Err(err) = self.fill() {
+                    self.eof = true;
+                    return Some(Err(LexError::Io(err)))
+                }
+                continue
+            }
+            match lexed {
+                Ok((_, 0)) | Err(None) => return None,
+                Ok((None, len)) => self.start += len,
+                Ok((Some(token), len)) => {
+                    let span = self.span(len);
+                    self.start += len;
+                    return Some(Ok((span, token)))
+                },
+                Err(Some(c)) => {
+                    let before = &self.buffer[..self.start];
+                    let line = self.line + before.matches('\n').count();
+                    let col = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+                    let span = self.span(c.len_utf8());
+                    self.start += c.len_utf8();
+                    return Some(Err(LexError::Invalid { span, line, col, text: c.to_string() }))
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::{spanned};
+
+    #[test]
+    fn stream_unit() {
+        // the same tokens as with the whole source at hand, comments and all
+        let source = "let val x = 3.5e2 (* a\ncomment *) in\n  #\"a\" :: [x] end (* open";
+        let tokens: Vec<(Span, OwnedToken)> = Tokens::new(source.as_bytes()).map(Result::unwrap).collect();
+        let whole: Vec<(Span, OwnedToken)> = spanned(source).into_iter()
+            .map(|(span, token)| (span, token.into_owned().unwrap()))
+            .collect();
+        assert_eq!(tokens, whole);
+        assert_eq!(tokens[3].1, OwnedToken::Keyword(Reserved::Equal));
+
+        let mut tokens = Tokens::new("1 +\n  2 ? 3".as_bytes());
+        assert_eq!(tokens.next().unwrap().unwrap().0, Span::new(0, 1));
+        assert_eq!(tokens.next().unwrap().unwrap().0, Span::new(2, 3));
+        assert_eq!(tokens.next().unwrap().unwrap().0, Span::new(6, 7));
+        match tokens.next() {
+            Some(Err(LexError::Invalid{ span, line, col, .. })) => {
+                assert_eq!((span, line, col), (Span::new(8, 9), 2, 5))
+            },
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(tokens.next().unwrap().unwrap().1, OwnedToken::Lit(OwnedLiteral::Integer(3)));
+        assert!(tokens.next().is_none());
+    }
+}
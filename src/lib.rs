@@ -0,0 +1,29 @@
+#[macro_use]
+extern crate combine;
+
+pub mod lexer;
+pub mod expr;
+pub mod error;
+pub mod diagnostic;
+pub mod schema;
+pub mod session;
+pub mod optimize;
+pub mod engine;
+pub mod registry;
+pub mod vm;
+pub mod runtime;
+pub mod features;
+pub mod teach;
+pub mod locale;
+pub mod render;
+pub mod explore;
+pub mod animate;
+pub mod editor;
+pub mod ir;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use error::{ParseError};
+pub use expr::{parse_str};
+pub use engine::{Engine};
+pub use features::{features, FeatureSet, Backend};
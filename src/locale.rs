@@ -0,0 +1,137 @@
+use std::fmt;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+// the languages diagnostics can be printed in. the text itself lives in
+// one catalog file per locale under `locale/`, english is the reference
+// every other catalog falls back to for anything it does not translate
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash)]
+pub enum Locale {
+    English,
+    Spanish,
+}
+
+pub const LOCALES: [Locale; 2] = [Locale::English, Locale::Spanish];
+
+impl Locale {
+    pub fn tag(self) -> &'static str {
+        match self {
+            Locale::English => "en",
+            Locale::Spanish => "es",
+        }
+    }
+    // accepts `es`, `es_ES` or `es_ES.UTF-8` alike
+    pub fn from_tag(tag: &str) -> Option<Locale> {
+        let language = tag.split(['_', '-', '.']).next()?;
+        LOCALES.iter().cloned().find(|locale| locale.tag().eq_ignore_ascii_case(language))
+    }
+    pub fn catalog(self) -> &'static Catalog {
+        static ENGLISH: OnceLock<Catalog> = OnceLock::new();
+        static SPANISH: OnceLock<Catalog> = OnceLock::new();
+        let (cell, text) = match self {
+            Locale::English => (&ENGLISH, include_str!("locale/en.txt")),
+            Locale::Spanish => (&SPANISH, include_str!("locale/es.txt")),
+        };
+        cell.get_or_init(|| Catalog::parse(text).expect("the builtin catalogs are well formed"))
+    }
+}
+
+impl fmt::Display for Locale {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.tag())
+    }
+}
+
+// process wide, set once at startup before anything is printed
+static CURRENT: AtomicU8 = AtomicU8::new(0);
+
+pub fn set_locale(locale: Locale) {
+    CURRENT.store(locale as u8, Ordering::Relaxed)
+}
+
+pub fn locale() -> Locale {
+    LOCALES[CURRENT.load(Ordering::Relaxed) as usize]
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Catalog {
+    messages: HashMap<String, String>,
+}
+
+impl Catalog {
+    // one `key = text` per line, `#` lines are comments
+    pub fn parse(text: &str) -> Result<Catalog, String> {
+        let mut messages = HashMap::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue
+            }
+            let (key, message) = line.split_once(" = ")
+                .ok_or_else(|| format!("line {}: expected `key = text`", number + 1))?;
+            if messages.insert(key.trim().to_string(), message.to_string()).is_some() {
+                return Err(format!("line {}: `{}` is defined twice", number + 1, key.trim()))
+            }
+        }
+        Ok(Catalog { messages })
+    }
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.messages.get(key).map(String::as_str)
+    }
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.messages.keys().map(String::as_str)
+    }
+    // the message for `key` with each `{}` filled in by the next of `args`,
+    // in english when this catalog has no translation for it
+    pub fn format(&self, key: &str, args: &[&dyn fmt::Display]) -> String {
+        let template = self.get(key)
+            .or_else(|| Locale::English.catalog().get(key))
+            .unwrap_or(key);
+        let mut pieces = template.split("{}");
+        let mut message = pieces.next().unwrap_or("").to_string();
+        for (i, piece) in pieces.enumerate() {
+            if let Some(arg) = args.get(i) {
+                message.push_str(&arg.to_string());
+            }
+            message.push_str(piece);
+        }
+        message
+    }
+}
+
+// `key` in the current locale, see `Catalog::format`
+pub fn message(key: &str, args: &[&dyn fmt::Display]) -> String {
+    locale().catalog().format(key, args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locale_unit() {
+        assert_eq!(Locale::from_tag("es_ES.UTF-8"), Some(Locale::Spanish));
+        assert_eq!(Locale::from_tag("EN"), Some(Locale::English));
+        assert_eq!(Locale::from_tag("xx"), None);
+
+        // every translation covers exactly the english keys, with the same
+        // number of holes to fill
+        let english = Locale::English.catalog();
+        for locale in LOCALES.iter() {
+            let catalog = locale.catalog();
+            for key in catalog.keys() {
+                let template = english.get(key);
+                assert!(template.is_some(), "{} has an unknown key `{}`", locale, key);
+                assert_eq!(catalog.get(key).unwrap().matches("{}").count(), template.unwrap().matches("{}").count());
+            }
+            assert_eq!(catalog.keys().count(), english.keys().count(), "{} is missing keys", locale);
+        }
+
+        let catalog = Catalog::parse("# test\ngreeting = hola {}, {}!\n").unwrap();
+        assert_eq!(catalog.format("greeting", &[&"ana", &1]), "hola ana, 1!");
+        assert_eq!(catalog.format("unexpected-eof", &[]), "unexpected end of input");
+        assert_eq!(Locale::Spanish.catalog().format("unbound", &[&"y"]), "`y` no está definido aquí");
+        assert!(Catalog::parse("no equals sign").is_err());
+    }
+}
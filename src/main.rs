@@ -1,58 +1,359 @@
-#[macro_use]
-extern crate combine;
-
 use docopt::Docopt;
 use serde::Deserialize;
 
-use combine::*;
-
 use std::path::PathBuf;
 use std::fs::File;
 use std::io::Read;
 use std::io::Write;
+use std::thread;
+use std::time::Duration;
 
 use rustyline::{Config, Editor, EditMode};
 use rustyline::error::ReadlineError;
 
-pub mod lexer;
-pub mod expr;
+mod report;
+mod terminal;
 
-use lexer::{Tokenizer};
-use expr::{prog};
+use ferus::expr::{Decl, Expr, nest, parse_decl, parse_indexed, parse_program, with_operators};
+use ferus::expr::operators::{Operators};
+use ferus::expr::ids::{NodeTable};
+use ferus::expr::lint::{lint};
+use ferus::expr::scope::{unbound};
+use ferus::expr::recover::{parse_recovering};
+use ferus::expr::calls::{CallGraph};
+use ferus::expr::format::{format_program};
+use ferus::session::{Session, Source};
+use ferus::teach::{Lessons};
+use ferus::locale::{self, Locale, LOCALES, message};
+use ferus::render::{self, Rendering};
+use ferus::schema::{schema};
+use ferus::explore::{Explorer};
+use ferus::editor::inlay::{inlaid, inlay_hints};
+use ferus::editor::refactor::{extract, inline, preview};
+use ferus::lexer::{Span, spanned};
+use ferus::animate::{Animation, Ending, animate};
+use report::{Phase};
 
 const USAGE: &'static str = "
 [ferus] an ocaml clone
 
 Usage:
   ferus [options]
+  ferus schema
   ferus [options] <source>
+  ferus [options] explore <source>
+  ferus [options] check [--inlay] <source>
+  ferus [options] query (callers | callees) <function> <source>
+  ferus [options] refactor extract --span=<range> [--name=<name>] [--diff] <source>
+  ferus [options] refactor inline --span=<range> [--diff] <source>
+  ferus [options] fmt [--width=<cols>] <source>
+  ferus [options] run [--animate] [--frames=<dir>] [--delay=<ms>] [--stats] [--stats-out=<file>] <source>
+  ferus --version [--verbose]
 
 Options:
-   -h, --help  Display this help message
+   -h, --help        Display this help message
+   -V, --version     Display the version
+   -v, --verbose     With --version, also list compiled in features and backends
+   --teach           Follow each error and warning with a longer explanation
+   --lessons=<file>  Like --teach, with the explanations read from <file>
+   --locale=<tag>    The language to print diagnostics in [default: en]
+   --accessible      Describe source locations and trees in words instead of
+                     drawing them, also turned on by FERUS_ACCESSIBLE=1
+   --color           Underline the source of errors and warnings in color,
+                     also turned on by FERUS_COLOR=1
+   --animate         With run, show the expression again after every step of
+                     evaluation with the part about to reduce highlighted
+   --frames=<dir>    With run, also write each step to <dir> as an svg and
+                     all of them as one html page
+   --delay=<ms>      With --animate, how long each step stays up [default: 700]
+   --stats           With run, report the steps taken, the deepest the stack
+                     got, how many values were made and the most memory held
+   --stats-out=<file>  With run, also write those numbers to <file> as json
+   --inlay           With check, print <source> back with the types of its
+                     `let val` binders and the parameter names of arguments
+                     written in
+   --span=<range>    With refactor, the part of <source> to work on, as the
+                     byte offsets <start>..<end>
+   --name=<name>     With refactor extract, what to call the new binding
+                     [default: tmp]
+   --diff            With refactor, print only the lines that change
+   --width=<cols>    With fmt, the column lines are broken before [default: 80]
+   --trace-out=<file>  When evaluating <source>, also write every node entered
+                     and left, its value and every binding made to <file> as
+                     json
+
+With query, every call of <function> in <source>, or every call it makes
+itself, is printed as the function calling or called and where the call is.
+Calls made outside of any function come from `<top>`.
+
+With refactor extract, <source> is printed back with the selected
+expression bound to a new `let val` around the innermost part of the
+program it is always evaluated in. It is refused when that could change
+what the program does. With refactor inline, the innermost `let val`
+around the selection is removed and its definition written in at each use,
+when that cannot change what the program does either.
+
+With schema, a json schema of the syntax tree and diagnostics as the
+`serde` feature writes them and of the trace --trace-out writes is printed,
+for tools in other languages to generate their types from or check what
+they read against.
+
+With fmt, <source> is a program and is rewritten in place with each
+declaration on its own line, broken and indented to fit in --width columns
+where it can be. A program with comments is left as it is, since they
+would be lost.
+
+With run, <source> is a program: declarations and expressions separated by
+`;`, its value is the last expression's. It is evaluated directly unless
+one of --animate, --frames or --stats asks for one step at a time.
+
+Exit status:
+   0  the program ran and its value was printed
+   1  the program was rejected before it ran, it did not parse or uses a
+      name that is not bound
+   2  the program failed while running, an exception was not caught or a
+      value had the wrong type
+   3  <source> could not be read
 ";
 
 #[derive(Debug, Deserialize)]
 struct Args {
-    arg_source: Option<PathBuf>
+    cmd_explore: bool,
+    cmd_run: bool,
+    cmd_check: bool,
+    cmd_query: bool,
+    cmd_callees: bool,
+    cmd_refactor: bool,
+    cmd_inline: bool,
+    cmd_schema: bool,
+    cmd_fmt: bool,
+    arg_function: Option<String>,
+    arg_source: Option<PathBuf>,
+    flag_version: bool,
+    flag_verbose: bool,
+    flag_teach: bool,
+    flag_lessons: Option<PathBuf>,
+    flag_locale: String,
+    flag_accessible: bool,
+    flag_color: bool,
+    flag_animate: bool,
+    flag_inlay: bool,
+    flag_frames: Option<PathBuf>,
+    flag_delay: u64,
+    flag_stats: bool,
+    flag_stats_out: Option<PathBuf>,
+    flag_span: Option<String>,
+    flag_name: String,
+    flag_diff: bool,
+    flag_trace_out: Option<PathBuf>,
+    flag_width: usize,
 }
 
-pub fn interpret<'a>(source: &'a str) {
-    let tokenizer = Tokenizer::new(source);
-    match prog().easy_parse(tokenizer) {
-        Err(err) => {
-            eprintln!("ERROR: could not parse ({}) because {}", source, err)
+// how running a file ended, as the exit status of the process so scripts
+// and graders can tell a rejected program from one that failed
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Status {
+    Success,
+    Rejected,
+    Failed,
+    Unreadable,
+    // ferus itself crashed, see `report::guard`
+    Crashed,
+}
+
+impl Status {
+    fn code(self) -> i32 {
+        match self {
+            Status::Success => 0,
+            Status::Rejected => 1,
+            Status::Failed => 2,
+            Status::Unreadable => 3,
+            // what rust exits with after a panic
+            Status::Crashed => 101,
+        }
+    }
+}
+
+// prints the lesson for `code` under the diagnostic it belongs to, when
+// running with `--teach`
+fn explain(lessons: Option<&Lessons>, code: &str) {
+    if let Some(lesson) = lessons.and_then(|lessons| lessons.get(code)) {
+        eprintln!("\n{}\n", lesson);
+    }
+}
+
+// parses `source` and reports what keeps it from running, errors and
+// warnings both
+fn accept<'a>(source: &'a str, lessons: Option<&Lessons>) -> Option<(Expr<'a>, NodeTable)> {
+    match parse_indexed(source) {
+        Err(_) => {
+            for err in parse_recovering(source).errors {
+                eprintln!("{}", err);
+                explain(lessons, err.code());
+            }
+            None
         },
-        Ok((expr, _)) => {
-            match expr.eval() {
-                Ok(value) => println!("{}", value),
-                Err(err) => eprintln!("{:?}", err),
+        Ok((expr, table)) => {
+            for warning in lint(source, &expr, &table) {
+                eprintln!("{}", warning);
+                explain(lessons, warning.lint.code());
+            }
+            let unbound = unbound(source, &expr, &table, &[]);
+            for err in unbound.iter() {
+                eprintln!("{}", err);
+                explain(lessons, err.code());
+            }
+            if unbound.is_empty() { Some((expr, table)) } else { None }
+        },
+    }
+}
+
+pub fn interpret<'a>(source: &'a str, trace_out: Option<&PathBuf>, lessons: Option<&Lessons>) -> Status {
+    report::guard(source, || {
+        report::enter(Phase::Parse);
+        match accept(source, lessons) {
+            None => Status::Rejected,
+            Some((expr, table)) => {
+                report::enter(Phase::Eval);
+                let res = match trace_out {
+                    None => expr.eval(),
+                    Some(path) => {
+                        let (res, trace) = expr.trace();
+                        let written = File::create(path).and_then(|mut file| {
+                            file.write_all(trace.json(Some(&table)).as_bytes())
+                        });
+                        if let Err(err) = written {
+                            eprintln!("{}", message("trace-could-not-write", &[&path.display(), &err]));
+                        }
+                        res
+                    },
+                };
+                match res {
+                    Ok(value) => {
+                        println!("{}", value);
+                        Status::Success
+                    },
+                    Err(err) => {
+                        eprintln!("{}", err);
+                        explain(lessons, err.code());
+                        Status::Failed
+                    },
+                }
             }
         }
+    }).unwrap_or(Status::Crashed)
+}
+
+// what `interpret` does short of evaluating, with the source printed back
+// with its inlay hints (see `ferus::editor::inlay`) when asked
+pub fn check(source: PathBuf, inlay: bool, lessons: Option<&Lessons>) -> Status {
+    let buf = match read_source(&source) {
+        Some(buf) => buf,
+        None => return Status::Unreadable,
+    };
+    report::guard(&buf, || {
+        report::enter(Phase::Parse);
+        let accepted = accept(&buf, lessons).is_some();
+        if inlay {
+            print!("{}", inlaid(&buf, &inlay_hints(&buf)));
+        }
+        if accepted { Status::Success } else { Status::Rejected }
+    }).unwrap_or(Status::Crashed)
+}
+
+// the calls of `function` in `source`, or the ones it makes with
+// `callees`, one per line as who is on the other end and where the call is
+pub fn query(source: PathBuf, function: &str, callees: bool, lessons: Option<&Lessons>) -> Status {
+    let buf = match read_source(&source) {
+        Some(buf) => buf,
+        None => return Status::Unreadable,
+    };
+    report::guard(&buf, || {
+        report::enter(Phase::Parse);
+        let (expr, table) = match accept(&buf, lessons) {
+            Some(accepted) => accepted,
+            None => return Status::Rejected,
+        };
+        let graph = CallGraph::new(&buf, &expr, &table);
+        let found = graph.find(function);
+        if found.is_empty() {
+            eprintln!("{}", message("query-unknown-function", &[&function, &source.display()]));
+            return Status::Rejected
+        }
+        let mut calls: Vec<_> = found.into_iter()
+            .flat_map(|f| if callees { graph.callees_of(f) } else { graph.callers_of(f) })
+            .collect();
+        calls.sort_by_key(|call| call.span.start);
+        for call in calls {
+            let other = if callees { Some(call.callee) } else { call.caller };
+            let name = other.map_or("<top>", |other| graph.functions[other].name);
+            let (line, col) = call.span.line_col(&buf);
+            println!("{} {}:{}", name, line, col);
+        }
+        Status::Success
+    }).unwrap_or(Status::Crashed)
+}
+
+// `<start>..<end>`, on character boundaries of `source`
+fn parse_span(range: &str, source: &str) -> Option<Span> {
+    let (start, end) = range.split_once("..")?;
+    let (start, end) = (start.trim().parse().ok()?, end.trim().parse().ok()?);
+    let valid = start <= end && source.is_char_boundary(start) && source.is_char_boundary(end);
+    if valid { Some(Span::new(start, end)) } else { None }
+}
+
+// `source` printed back with the expression at `range` extracted into a
+// binding called `name`, or with the binding around it inlined, see
+// `ferus::editor::refactor`
+pub fn refactor(
+    source: PathBuf, range: &str, name: Option<&str>, diff: bool, lessons: Option<&Lessons>,
+) -> Status {
+    let buf = match read_source(&source) {
+        Some(buf) => buf,
+        None => return Status::Unreadable,
+    };
+    report::guard(&buf, || {
+        report::enter(Phase::Parse);
+        if accept(&buf, lessons).is_none() {
+            return Status::Rejected
+        }
+        let span = match parse_span(range, &buf) {
+            Some(span) => span,
+            None => {
+                eprintln!("{}", message("refactor-bad-span", &[&range]));
+                return Status::Rejected
+            },
+        };
+        let refactored = match name {
+            Some(name) => extract(&buf, span, name),
+            None => inline(&buf, span),
+        };
+        match refactored {
+            Ok(refactored) if diff => {
+                print!("{}", preview(&buf, &refactored));
+                Status::Success
+            },
+            Ok(refactored) => {
+                print!("{}", refactored);
+                Status::Success
+            },
+            Err(err) => {
+                eprintln!("{}", err);
+                Status::Rejected
+            },
+        }
+    }).unwrap_or(Status::Crashed)
+}
+
+fn report_stale(stale: &[&str]) {
+    if !stale.is_empty() {
+        println!("stale: {} (:refresh to re-evaluate)", stale.join(", "));
     }
 }
 
-pub fn repl() {
+pub fn repl(lessons: Option<&Lessons>) {
     let prompt = "> ";
+    let continuation = "| ";
     let config = Config::builder()
         .edit_mode(EditMode::Emacs)
         .max_history_size(1000)
@@ -65,13 +366,85 @@ pub fn repl() {
         let mut file = File::create(&history_file).unwrap();
         file.write_all(b"").unwrap();
     }
+    // top level bindings outlive the line they were read from so every
+    // declaration that parses is leaked into a 'static str, the lines of
+    // one still going on are not
+    let mut session: Session<'static> = Session::new();
+    // and so do fixity declarations
+    let mut operators = Operators::default();
+    let mut buffer = String::new();
     loop {
-        let readline = rl.readline(prompt);
+        let readline = rl.readline(if buffer.is_empty() { prompt } else { continuation });
         match readline {
-            Ok(ref line) if line.is_empty() => {}
-            Ok(ref line) => {
-                rl.add_history_entry(line);
-                interpret(line)
+            Ok(ref line) if line.is_empty() && buffer.is_empty() => {}
+            Ok(ref line) if line.trim() == ":refresh" && buffer.is_empty() => {
+                for (name, res) in session.refresh() {
+                    let fun = matches!(session.binding(name).map(|b| &b.source), Some(Source::Fun(_)));
+                    match res {
+                        Ok(value) if fun => println!("fun {}", value),
+                        Ok(value) => println!("val {} = {}", name, value),
+                        Err(err) => eprintln!("{}: {}", name, err),
+                    }
+                }
+            }
+            Ok(line) => {
+                if !buffer.is_empty() {
+                    buffer.push('\n');
+                }
+                buffer.push_str(&line);
+                report::enter(Phase::Parse);
+                let parsed = report::guard(&buffer, || {
+                    with_operators(operators.clone(), || parse_decl(&buffer).map(|_| ()))
+                });
+                let decl = match parsed {
+                    None => None,
+                    // an empty line while incomplete forces the error out
+                    Some(Err(ref err)) if err.is_incomplete() && !line.is_empty() => continue,
+                    Some(Err(err)) => {
+                        eprintln!("{}", err);
+                        explain(lessons, err.code());
+                        None
+                    },
+                    Some(Ok(())) => {
+                        let source: &'static str = Box::leak(buffer.clone().into_boxed_str());
+                        with_operators(operators.clone(), || parse_decl(source)).ok().map(|decl| (source, decl))
+                    },
+                };
+                if let Some((source, decl)) = decl {
+                    report::enter(Phase::Eval);
+                    report::guard(source, || match decl {
+                        Decl::Val{ name, binder } => match session.define_val(name, *binder) {
+                            Ok((value, stale)) => {
+                                println!("val {} = {}", name, value);
+                                report_stale(&stale)
+                            },
+                            Err(err) => {
+                                eprintln!("{}", err);
+                                explain(lessons, err.code())
+                            },
+                        },
+                        Decl::Fun(defs) => {
+                            for def in defs.iter() {
+                                println!("fun {}", def);
+                            }
+                            report_stale(&session.define_funs(defs))
+                        },
+                        Decl::Datatype(datatype) => println!("{}", datatype),
+                        Decl::Infix(fixity) => {
+                            operators.declare(&fixity);
+                            println!("{}", fixity)
+                        },
+                        Decl::Expr(expr) => match session.eval(expr) {
+                            Ok(value) => println!("{}", value),
+                            Err(err) => {
+                                eprintln!("{}", err);
+                                explain(lessons, err.code())
+                            },
+                        },
+                    });
+                }
+                rl.add_history_entry(buffer.as_str());
+                buffer.clear();
             }
             Err(ReadlineError::Interrupted) => {
                 eprintln!("CTRL-C");
@@ -87,26 +460,269 @@ pub fn repl() {
     rl.save_history(&history_file).unwrap();
 }
 
-pub fn file(source: PathBuf) {
-    match File::open(&source) {
-        Err(err) => eprintln!("Could not open file {:?} because: {}", source, err),
+fn read_source(source: &PathBuf) -> Option<String> {
+    match File::open(source) {
+        Err(err) => eprintln!("{}", message("could-not-open", &[&source.display(), &err])),
         Ok(mut file) => {
             let mut buf = String::new();
             match file.read_to_string(&mut buf) {
-                Err(err) => eprintln!("Could not read source file {:?} because: {}", source, err),
-                Ok(_) => interpret(&buf),
+                Err(err) => eprintln!("{}", message("could-not-read", &[&source.display(), &err])),
+                Ok(_) => return Some(buf),
+            }
+        }
+    }
+    None
+}
+
+pub fn file(source: PathBuf, trace_out: Option<PathBuf>, lessons: Option<&Lessons>) -> Status {
+    match read_source(&source) {
+        Some(buf) => interpret(&buf, trace_out.as_ref(), lessons),
+        None => Status::Unreadable,
+    }
+}
+
+// the tree of `source` in the terminal, see `ferus::explore`
+pub fn explore(source: PathBuf, lessons: Option<&Lessons>) {
+    let buf = match read_source(&source) {
+        Some(buf) => buf,
+        None => return,
+    };
+    let (expr, table) = match parse_indexed(&buf) {
+        Ok(parsed) => parsed,
+        Err(_) => {
+            for err in parse_recovering(&buf).errors {
+                eprintln!("{}", err);
+                explain(lessons, err.code());
             }
+            return
+        },
+    };
+    let mut explorer = Explorer::new(&buf, &expr, &table);
+    let res = terminal::RawMode::enter().and_then(|_raw| loop {
+        let (rows, columns) = terminal::size();
+        terminal::draw(&explorer.render(rows), columns)?;
+        if !explorer.handle(terminal::read_key()?) {
+            return Ok(())
         }
+    });
+    if let Err(err) = res {
+        eprintln!("{}", err);
     }
 }
 
+// steps the machine takes before an animation gives up
+const MAX_STEPS: usize = 10_000;
+
+fn write_frames(animation: &Animation, dir: &PathBuf) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    for (i, frame) in animation.frames.iter().enumerate() {
+        File::create(dir.join(format!("frame-{:03}.svg", i + 1)))?.write_all(frame.svg().as_bytes())?;
+    }
+    File::create(dir.join("index.html"))?.write_all(animation.html().as_bytes())
+}
+
+// rewrites the program in `source` formatted to `width` columns
+pub fn fmt(source: PathBuf, width: usize, lessons: Option<&Lessons>) -> Status {
+    let buf = match read_source(&source) {
+        Some(buf) => buf,
+        None => return Status::Unreadable,
+    };
+    report::enter(Phase::Parse);
+    let program = match parse_program(&buf) {
+        Ok(program) => program,
+        Err(err) => {
+            eprintln!("{}", err);
+            explain(lessons, err.code());
+            return Status::Rejected
+        },
+    };
+    // anything between two tokens that is not whitespace is a comment
+    let mut spans: Vec<Span> = spanned(&buf).into_iter().map(|(span, _)| span).collect();
+    spans.push(Span::new(buf.len(), buf.len()));
+    let mut end = 0;
+    for span in spans {
+        if !buf[end..span.start].trim().is_empty() {
+            eprintln!("{}", message("fmt-comments", &[&source.display()]));
+            return Status::Rejected
+        }
+        end = span.end;
+    }
+    let formatted = format_program(&program, width);
+    // operators declared with `infix` are not written back the way they
+    // were declared, so what does not parse back the same is left alone
+    if parse_program(&formatted).ok().as_ref() != Some(&program) {
+        eprintln!("{}", message("fmt-unchanged", &[&source.display()]));
+        return Status::Rejected
+    }
+    if formatted != buf {
+        if let Err(err) = std::fs::write(&source, formatted) {
+            eprintln!("{}", message("fmt-could-not-write", &[&source.display(), &err]));
+            return Status::Unreadable
+        }
+    }
+    Status::Success
+}
+
+// runs the program in `source`, one reduction at a time when it is to be
+// shown or measured (see `ferus::animate`)
+pub fn run(
+    source: PathBuf, show: bool, frames: Option<PathBuf>, delay: u64, stats: bool, stats_out: Option<PathBuf>,
+    lessons: Option<&Lessons>,
+) -> Status {
+    let buf = match read_source(&source) {
+        Some(buf) => buf,
+        None => return Status::Unreadable,
+    };
+    report::enter(Phase::Parse);
+    let expr = match parse_program(&buf) {
+        Ok(program) => nest(program),
+        Err(err) => {
+            eprintln!("{}", err);
+            explain(lessons, err.code());
+            return Status::Rejected
+        },
+    };
+    if !show && frames.is_none() && !stats && stats_out.is_none() {
+        report::enter(Phase::Eval);
+        let res = report::guard(&buf, || match expr.eval() {
+            Ok(value) => {
+                println!("{}", value);
+                Status::Success
+            },
+            Err(err) => {
+                eprintln!("{}", err);
+                explain(lessons, err.code());
+                Status::Failed
+            },
+        });
+        return res.unwrap_or(Status::Crashed)
+    }
+    let animation = animate(&expr, MAX_STEPS);
+    if show {
+        let mut printed = vec![];
+        for (i, frame) in animation.frames.iter().enumerate() {
+            if render::rendering() == Rendering::Visual {
+                print!("\x1b[H\x1b[2J");
+            }
+            println!("{}", frame.render(i + 1, render::rendering()));
+            printed.extend(frame.printed.iter().cloned());
+            for line in printed.iter() {
+                println!("> {}", line);
+            }
+            let _ = std::io::stdout().flush();
+            thread::sleep(Duration::from_millis(delay));
+        }
+    } else if let Some(frame) = animation.frames.last() {
+        for line in animation.frames.iter().filter_map(|frame| frame.printed.as_ref()) {
+            println!("{}", line);
+        }
+        if animation.ending == Ending::Value {
+            println!("{}", frame.source);
+        }
+    }
+    let last = animation.frames.last();
+    match (&animation.ending, last.and_then(|frame| frame.redex.map(|span| (frame, span)))) {
+        (Ending::Stuck, Some((frame, span))) => {
+            eprintln!("{}", message("animate-stuck", &[&&frame.source[span.start..span.end]]))
+        },
+        (Ending::Raised(value), _) => eprintln!("{}", message("uncaught", &[value])),
+        (Ending::OutOfSteps, _) => eprintln!("{}", message("animate-out-of-steps", &[&MAX_STEPS])),
+        _ => {},
+    }
+    if let Some(dir) = frames {
+        if let Err(err) = write_frames(&animation, &dir) {
+            eprintln!("{}", message("animate-could-not-write", &[&dir.display(), &err]));
+        }
+    }
+    if stats {
+        eprintln!("{}", animation.stats.render());
+    }
+    if let Some(path) = stats_out {
+        let written = File::create(&path).and_then(|mut file| file.write_all(animation.stats.json().as_bytes()));
+        if let Err(err) = written {
+            eprintln!("{}", message("stats-could-not-write", &[&path.display(), &err]));
+        }
+    }
+    if animation.ending == Ending::Value { Status::Success } else { Status::Failed }
+}
+
+fn load_lessons(path: &PathBuf) -> Result<Lessons, String> {
+    let mut text = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut text))
+        .map_err(|err| err.to_string())?;
+    Lessons::parse(&text)
+}
+
 fn main() {
+    report::install_hook();
     let args: Args = Docopt::new(USAGE)
         .and_then(|d| d.deserialize())
         .unwrap_or_else(|e| e.exit());
-    match args.arg_source {
-        None => repl(),
-        Some(source) => file(source),
+    if args.flag_version {
+        let features = ferus::features();
+        if args.flag_verbose {
+            println!("{}", features)
+        } else {
+            println!("ferus {}", features.version)
+        }
+        return
     }
+    if args.cmd_schema {
+        print!("{}", schema());
+        return
+    }
+    match Locale::from_tag(&args.flag_locale) {
+        Some(chosen) => locale::set_locale(chosen),
+        None => {
+            let available: Vec<&str> = LOCALES.iter().map(|locale| locale.tag()).collect();
+            eprintln!("{}", message("unknown-locale", &[&args.flag_locale, &available.join(", ")]));
+            return
+        },
+    }
+    if args.flag_accessible {
+        render::set_rendering(Rendering::Linear);
+    }
+    if args.flag_color {
+        render::set_colors(true);
+    }
+    let lessons = match args.flag_lessons {
+        Some(path) => match load_lessons(&path) {
+            Ok(lessons) => Some(lessons),
+            Err(err) => {
+                eprintln!("{}", message("could-not-load-lessons", &[&path.display(), &err]));
+                return
+            },
+        },
+        None if args.flag_teach => Some(Lessons::builtin()),
+        None => None,
+    };
+    let status = match args.arg_source {
+        None => {
+            repl(lessons.as_ref());
+            Status::Success
+        },
+        Some(source) if args.cmd_explore => {
+            explore(source, lessons.as_ref());
+            Status::Success
+        },
+        Some(source) if args.cmd_query => {
+            let function = args.arg_function.unwrap_or_default();
+            query(source, &function, args.cmd_callees, lessons.as_ref())
+        },
+        Some(source) if args.cmd_refactor => {
+            let range = args.flag_span.unwrap_or_default();
+            let name = if args.cmd_inline { None } else { Some(args.flag_name.as_str()) };
+            refactor(source, &range, name, args.flag_diff, lessons.as_ref())
+        },
+        Some(source) if args.cmd_fmt => fmt(source, args.flag_width, lessons.as_ref()),
+        Some(source) if args.cmd_check => check(source, args.flag_inlay, lessons.as_ref()),
+        Some(source) if args.cmd_run => {
+            let (show, frames, delay) = (args.flag_animate, args.flag_frames, args.flag_delay);
+            run(source, show, frames, delay, args.flag_stats, args.flag_stats_out, lessons.as_ref())
+        },
+        Some(source) => file(source, args.flag_trace_out, lessons.as_ref()),
+    };
+    std::process::exit(status.code())
 }
 
@@ -0,0 +1,187 @@
+use crate::lexer::{Literal};
+use crate::expr::{UnaryOp, BinaryOp, Definition, Expr, Rule};
+use crate::expr::eval::{chr};
+use crate::runtime::width::{Width};
+
+pub mod mono;
+
+// evaluates every subtree whose operands are already literals. anything that
+// could fail at runtime (division by zero, overflow, type errors) is left
+// alone so the evaluator still reports it
+pub fn fold_constants<'a>(expr: Expr<'a>) -> Expr<'a> {
+    use Expr::*;
+    let fold = |expr: Box<Expr<'a>>| Box::new(fold_constants(*expr));
+    match expr {
+        Var(_) | Lit(_) | Error(_) => expr,
+        Unary{ operation, child } => fold_unary(operation, fold_constants(*child)),
+        Binary{ left, operation, right } => fold_binary(fold(left), operation, fold(right)),
+        IfThenElse{ condition, if_branch, else_branch } => {
+            match fold_constants(*condition) {
+                Lit(Literal::Boolean(true)) => fold_constants(*if_branch),
+                Lit(Literal::Boolean(false)) => fold_constants(*else_branch),
+                condition => IfThenElse {
+                    condition: Box::new(condition),
+                    if_branch: fold(if_branch),
+                    else_branch: fold(else_branch),
+                },
+            }
+        },
+        While{ condition, body } => match fold_constants(*condition) {
+            // a loop that never runs
+            Lit(Literal::Boolean(false)) => Lit(Literal::Unit),
+            condition => While{ condition: Box::new(condition), body: fold(body) },
+        },
+        Tuple{ fst, snd } => Tuple{ fst: fold(fst), snd: fold(snd) },
+        Let{ bindings, body } => Let {
+            bindings: bindings.into_iter().map(|(name, binder)| (name, fold_constants(binder))).collect(),
+            body: fold(body),
+        },
+        Lambda{ name, body } => Lambda{ name, body: fold(body) },
+        App{ left, right } => App{ left: fold(left), right: fold(right) },
+        // the parser never makes one, but a tree built by hand might
+        Seq(sequence) if sequence.is_empty() => Seq(sequence),
+        Seq(sequence) => {
+            let last = sequence.len() - 1;
+            let mut folded: Vec<Expr<'a>> = sequence.into_iter()
+                .map(fold_constants)
+                .enumerate()
+                // a unit literal before the end of a sequence does nothing
+                .filter(|(i, expr)| match expr {
+                    Lit(Literal::Unit) => *i == last,
+                    _ => true,
+                })
+                .map(|(_, expr)| expr)
+                .collect();
+            if folded.len() == 1 {
+                folded.remove(0)
+            } else {
+                Seq(folded)
+            }
+        },
+        List(elements) => List(elements.into_iter().map(fold_constants).collect()),
+        Cons{ head, tail } => {
+            match (fold_constants(*head), fold_constants(*tail)) {
+                (head, List(mut elements)) => {
+                    elements.insert(0, head);
+                    List(elements)
+                },
+                (head, tail) => Cons{ head: Box::new(head), tail: Box::new(tail) },
+            }
+        },
+        Funs{ defs, body } => Funs {
+            defs: defs.into_iter().map(|def| Definition { body: fold(def.body), ..def }).collect(),
+            body: fold(body),
+        },
+        Annot{ expr, ty } => Annot{ expr: fold(expr), ty },
+        Construct{ name, argument } => Construct{ name, argument: argument.map(fold) },
+        Raise(expr) => Raise(fold(expr)),
+        Record(fields) => Record(fields.into_iter().map(|(label, expr)| (label, fold_constants(expr))).collect()),
+        Select{ label, record } => Select{ label, record: fold(record) },
+        Handle{ expr, rules } => Handle {
+            expr: fold(expr),
+            rules: rules.into_iter().map(|rule| Rule { body: fold(rule.body), ..rule }).collect(),
+        },
+    }
+}
+
+fn fold_unary<'a>(operation: UnaryOp, child: Expr<'a>) -> Expr<'a> {
+    use Expr::*;
+    match (operation, child) {
+        (UnaryOp::Fst, Tuple{ fst, snd }) if is_literal(&fst) && is_literal(&snd) => *fst,
+        (UnaryOp::Snd, Tuple{ fst, snd }) if is_literal(&fst) && is_literal(&snd) => *snd,
+        (operation, Lit(lit)) => match fold_unary_literal(operation, lit) {
+            Some(lit) => Lit(lit),
+            None => Unary{ operation, child: Box::new(Lit(lit)) },
+        },
+        (operation, child) => Unary{ operation, child: Box::new(child) },
+    }
+}
+
+// what `operation` of the literal `lit` comes to, unless it would fail
+pub(crate) fn fold_unary_literal<'a>(operation: UnaryOp, lit: Literal<'a>) -> Option<Literal<'a>> {
+    match (operation, lit) {
+        (UnaryOp::Not, Literal::Boolean(b)) => Some(Literal::Boolean(!b)),
+        (UnaryOp::Ord, Literal::Char(c)) => Some(Literal::Integer(c as i64)),
+        // a number that is no character raises `Chr` at runtime
+        (UnaryOp::Chr, Literal::Integer(i)) => chr(i).map(Literal::Char),
+        (UnaryOp::Neg, Literal::Integer(i)) => Width::default().negate(i).ok().map(Literal::Integer),
+        (UnaryOp::Neg, Literal::Real(x)) => Some(Literal::Real(x.negate())),
+        _ => None,
+    }
+}
+
+fn fold_binary<'a>(left: Box<Expr<'a>>, operation: BinaryOp, right: Box<Expr<'a>>) -> Expr<'a> {
+    let literal = |expr: &Expr<'a>| match expr {
+        Expr::Lit(lit) => Some(*lit),
+        _ => None,
+    };
+    match fold_binary_literals(literal(&left), operation, literal(&right)) {
+        Some(lit) => Expr::Lit(lit),
+        None => Expr::Binary{ left, operation, right },
+    }
+}
+
+// what `operation` of the operands comes to, `None` for an operand that is
+// not a literal, unless it would fail or depends on what is not known
+pub(crate) fn fold_binary_literals<'a>(left: Option<Literal<'a>>, operation: BinaryOp, right: Option<Literal<'a>>)
+    -> Option<Literal<'a>>
+{
+    use Literal::*;
+    use BinaryOp::*;
+    match (left, operation, right) {
+        (Some(Integer(l)), Add | Sub | Mult | Div | Mod, Some(Integer(r))) => {
+            Width::default().arithmetic(operation, l, r).and_then(Result::ok).map(Integer)
+        },
+        (Some(Integer(l)), Equal, Some(Integer(r))) => Some(Boolean(l == r)),
+        (Some(Integer(l)), NotEqual, Some(Integer(r))) => Some(Boolean(l != r)),
+        (Some(Integer(l)), LessThan, Some(Integer(r))) => Some(Boolean(l < r)),
+        (Some(Integer(l)), LessEqual, Some(Integer(r))) => Some(Boolean(l <= r)),
+        (Some(Integer(l)), GreaterThan, Some(Integer(r))) => Some(Boolean(l > r)),
+        (Some(Integer(l)), GreaterEqual, Some(Integer(r))) => Some(Boolean(l >= r)),
+        (Some(Char(l)), _, Some(Char(r))) => operation.compare(&l, &r).map(Boolean),
+        // like integers, what would raise is left for runtime
+        (Some(Real(l)), _, Some(Real(r))) => match l.arithmetic(operation, r) {
+            Some(res) => res.ok().map(Real),
+            None => operation.compare(&l, &r).map(Boolean),
+        },
+        // the right hand side is never evaluated so it does not need to be a literal
+        (Some(Boolean(true)), OrElse, _) => Some(Boolean(true)),
+        (Some(Boolean(false)), AndAlso, _) => Some(Boolean(false)),
+        (Some(Boolean(false)), OrElse, Some(Boolean(r))) => Some(Boolean(r)),
+        (Some(Boolean(true)), AndAlso, Some(Boolean(r))) => Some(Boolean(r)),
+        _ => None,
+    }
+}
+
+fn is_literal(expr: &Expr) -> bool {
+    matches!(expr, Expr::Lit(_))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::{parse};
+
+    #[test]
+    fn fold_constants_unit() {
+        let tests = vec![
+            ("1 + 2 * 3", "7"),
+            ("if true then x else y", "x"),
+            ("if 1 < 2 andalso true then 1 else 2", "1"),
+            ("not (1 = 2)", "true"),
+            ("fn x => x + (10 div 5)", "fn x => x + 2"),
+            ("1 div 0", "1 div 0"),
+            ("false orelse x", "false orelse x"),
+            ("true orelse x", "true"),
+            ("fst (1, true)", "1"),
+            ("(1 + 1) :: [2]", "[2, 2]"),
+            ("((); 3 * 3)", "9"),
+            ("let val y = 2 + 2 in y end", "let val y = 4 in y end"),
+        ];
+        for (input, output) in tests {
+            let folded = fold_constants(parse(input).unwrap());
+            assert_eq!(folded.to_string(), output)
+        }
+        assert_eq!(fold_constants(Expr::Seq(vec![])), Expr::Seq(vec![]));
+    }
+}
@@ -0,0 +1,569 @@
+use std::fmt;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::lexer::{Literal};
+use crate::expr::{UnaryOp, BinaryOp, Definition, Expr, Pattern, TypeExpr};
+use crate::expr::owned::{OwnedDefinition, OwnedExpr, OwnedRule};
+
+// the first order types a specialized function can assume of its argument
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash)]
+pub enum Ty {
+    Unit,
+    Int,
+    Bool,
+    String,
+    Char,
+    Real,
+    Tuple(Box<Ty>, Box<Ty>),
+    List(Box<Ty>),
+}
+
+impl fmt::Display for Ty {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Ty::*;
+        match self {
+            Unit => write!(f, "unit"),
+            Int => write!(f, "int"),
+            Bool => write!(f, "bool"),
+            String => write!(f, "string"),
+            Char => write!(f, "char"),
+            Real => write!(f, "real"),
+            Tuple(fst, snd) => write!(f, "({} * {})", fst, snd),
+            List(elem) => write!(f, "{} list", elem),
+        }
+    }
+}
+
+impl Ty {
+    // what an annotation says, when it names a first order type
+    pub fn of_annotation(ty: &TypeExpr) -> Option<Ty> {
+        match ty {
+            TypeExpr::Unit => Some(Ty::Unit),
+            TypeExpr::Int => Some(Ty::Int),
+            TypeExpr::Bool => Some(Ty::Bool),
+            TypeExpr::String => Some(Ty::String),
+            TypeExpr::Char => Some(Ty::Char),
+            TypeExpr::Real => Some(Ty::Real),
+            TypeExpr::Tuple(fst, snd) => {
+                Some(Ty::Tuple(Box::new(Ty::of_annotation(fst)?), Box::new(Ty::of_annotation(snd)?)))
+            },
+            TypeExpr::List(elem) => Some(Ty::List(Box::new(Ty::of_annotation(elem)?))),
+            TypeExpr::Arrow(..) | TypeExpr::Named(_) => None,
+        }
+    }
+    // part of an identifier, names only lex as letters
+    fn mangle(&self) -> std::string::String {
+        use Ty::*;
+        match self {
+            Unit => "Unit".to_string(),
+            Int => "Int".to_string(),
+            Bool => "Bool".to_string(),
+            String => "String".to_string(),
+            Char => "Char".to_string(),
+            Real => "Real".to_string(),
+            Tuple(fst, snd) => format!("Tuple{}{}", fst.mangle(), snd.mangle()),
+            List(elem) => format!("List{}", elem.mangle()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    // specializations of a single function, calls beyond the cap go to the
+    // generic definition
+    pub max_instances: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits { max_instances: 8 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instance {
+    pub name: String,
+    pub function: String,
+    pub param: Ty,
+}
+
+#[derive(Debug, Clone)]
+pub struct Monomorphized {
+    pub expr: OwnedExpr,
+    pub instances: Vec<Instance>,
+}
+
+// clones every `let fun` function once per concrete argument type it is
+// directly called with, so a backend can pick a representation per copy.
+// types are only known where they follow from literals and operators, any
+// other call keeps going through the generic definition
+pub fn monomorphize(expr: &Expr, limits: Limits) -> Monomorphized {
+    let taken = expr.names().into_iter().map(str::to_string).collect();
+    let mut mono = Mono { limits, taken, instances: vec![] };
+    let expr = mono.expr(expr, &Scope::default());
+    Monomorphized { expr, instances: mono.instances }
+}
+
+// the type `monomorphize` can tell each subtree has, in pre-order so the
+// `NodeId`s of a `NodeTable` index it
+pub fn node_types(expr: &Expr) -> Vec<Option<Ty>> {
+    fn walk<'a>(expr: &Expr<'a>, scope: &Scope<'a>, types: &mut Vec<Option<Ty>>) {
+        use Expr::*;
+        types.push(infer(expr, scope));
+        match expr {
+            Let{ bindings, body } => {
+                let mut inner = scope.clone();
+                for (name, binder) in bindings {
+                    walk(binder, &inner, types);
+                    inner = inner.bind(name, infer(binder, &inner));
+                }
+                walk(body, &inner, types)
+            },
+            Lambda{ name, body } => walk(body, &scope.bind(name, None), types),
+            Funs{ defs, body } => {
+                let inner = defs.iter().fold(scope.clone(), |inner, def| inner.bind(def.name, None));
+                for def in defs {
+                    walk(&def.body, &inner.bind(def.argument, None), types)
+                }
+                walk(body, &inner, types)
+            },
+            Handle{ expr, rules } => {
+                walk(expr, scope, types);
+                for rule in rules {
+                    walk(&rule.body, &scope.bind_pattern(&rule.pattern), types)
+                }
+            },
+            _ => {
+                for child in expr.children() {
+                    walk(child, scope, types)
+                }
+            },
+        }
+    }
+    let mut types = vec![];
+    walk(expr, &Scope::default(), &mut types);
+    types
+}
+
+// the type of `expr` with only `bindings` in scope, as far as it follows
+// from them, literals and operators
+pub fn infer_with<'a>(expr: &Expr<'a>, bindings: &[(&'a str, Option<Ty>)]) -> Option<Ty> {
+    let scope = bindings.iter().fold(Scope::default(), |scope, (name, ty)| scope.bind(name, ty.clone()));
+    infer(expr, &scope)
+}
+
+// the free variables of `expr`, what a host has to bind before evaluating
+// it, in the order of their names. each comes with the type all of its uses
+// agree it has, as far as the literals, operators and annotations around
+// them tell
+pub fn referenced_inputs<'a>(expr: &Expr<'a>) -> Vec<(&'a str, Option<Ty>)> {
+    let uses = expr.free_variables().into_iter().map(|name| (name, vec![])).collect();
+    let mut inputs = Inputs { bound: vec![], uses };
+    inputs.expect(expr, None, &Scope::default());
+    inputs.uses.into_iter()
+        .map(|(name, types)| match types.split_first() {
+            Some((ty, rest)) if rest.iter().all(|other| other == ty) => (name, Some(ty.clone())),
+            _ => (name, None),
+        })
+        .collect()
+}
+
+struct Inputs<'a> {
+    bound: Vec<&'a str>,
+    // the types each free variable is used at
+    uses: BTreeMap<&'a str, Vec<Ty>>,
+}
+
+impl<'a> Inputs<'a> {
+    // `ty` is the type `expr` has to have where it is, when that is known
+    fn expect(&mut self, expr: &Expr<'a>, ty: Option<Ty>, scope: &Scope<'a>) {
+        use Expr::*;
+        let number = |ty: Option<Ty>| ty.filter(|ty| *ty == Ty::Int || *ty == Ty::Real);
+        match expr {
+            Var(name) if !self.bound.contains(name) => {
+                if let (Some(uses), Some(ty)) = (self.uses.get_mut(name), ty) {
+                    uses.push(ty);
+                }
+            },
+            Unary{ operation, child } => {
+                let ty = match operation {
+                    UnaryOp::Not => Some(Ty::Bool),
+                    UnaryOp::Ord => Some(Ty::Char),
+                    UnaryOp::Chr => Some(Ty::Int),
+                    UnaryOp::Neg => number(ty),
+                    UnaryOp::Fst | UnaryOp::Snd | UnaryOp::Print => None,
+                };
+                self.expect(child, ty, scope)
+            },
+            Binary{ left, operation, right } => {
+                use BinaryOp::*;
+                let operand = || infer(left, scope).or_else(|| infer(right, scope));
+                let ty = match operation {
+                    Add | Sub | Mult => number(ty).or_else(|| number(operand())),
+                    Div | Mod => Some(Ty::Int),
+                    Divide => Some(Ty::Real),
+                    OrElse | AndAlso => Some(Ty::Bool),
+                    Equal | NotEqual | LessThan | LessEqual | GreaterThan | GreaterEqual => operand(),
+                };
+                self.expect(left, ty.clone(), scope);
+                self.expect(right, ty, scope)
+            },
+            IfThenElse{ condition, if_branch, else_branch } => {
+                self.expect(condition, Some(Ty::Bool), scope);
+                self.expect(if_branch, ty.clone(), scope);
+                self.expect(else_branch, ty, scope)
+            },
+            While{ condition, body } => {
+                self.expect(condition, Some(Ty::Bool), scope);
+                self.expect(body, Some(Ty::Unit), scope)
+            },
+            Tuple{ fst, snd } => {
+                let (fst_ty, snd_ty) = match ty {
+                    Some(Ty::Tuple(fst, snd)) => (Some(*fst), Some(*snd)),
+                    _ => (None, None),
+                };
+                self.expect(fst, fst_ty, scope);
+                self.expect(snd, snd_ty, scope)
+            },
+            Let{ bindings, body } => {
+                let mut inner = scope.clone();
+                for (name, binder) in bindings {
+                    self.expect(binder, None, &inner);
+                    self.bound.push(name);
+                    inner = inner.bind(name, infer(binder, &inner));
+                }
+                self.expect(body, ty, &inner);
+                self.bound.truncate(self.bound.len() - bindings.len());
+            },
+            Lambda{ name, body } => {
+                self.bound.push(name);
+                self.expect(body, None, &scope.bind(name, None));
+                self.bound.pop();
+            },
+            Seq(sequence) => {
+                for (i, expr) in sequence.iter().enumerate() {
+                    let ty = if i + 1 < sequence.len() { Some(Ty::Unit) } else { ty.clone() };
+                    self.expect(expr, ty, scope)
+                }
+            },
+            List(elements) => {
+                let ty = match ty {
+                    Some(Ty::List(elem)) => Some(*elem),
+                    _ => None,
+                };
+                for expr in elements {
+                    self.expect(expr, ty.clone(), scope)
+                }
+            },
+            Cons{ head, tail } => {
+                let elem = match &ty {
+                    Some(Ty::List(elem)) => Some((**elem).clone()),
+                    _ => None,
+                };
+                self.expect(head, elem, scope);
+                self.expect(tail, ty, scope)
+            },
+            Funs{ defs, body } => {
+                self.bound.extend(defs.iter().map(|def| def.name));
+                let inner = defs.iter().fold(scope.clone(), |inner, def| inner.bind(def.name, None));
+                for def in defs {
+                    self.bound.push(def.argument);
+                    self.expect(&def.body, None, &inner.bind(def.argument, None));
+                    self.bound.pop();
+                }
+                self.expect(body, ty, &inner);
+                self.bound.truncate(self.bound.len() - defs.len());
+            },
+            Annot{ expr, ty: annotation } => self.expect(expr, Ty::of_annotation(annotation).or(ty), scope),
+            Handle{ expr, rules } => {
+                self.expect(expr, ty.clone(), scope);
+                for rule in rules {
+                    let names = rule.pattern.names();
+                    let count = names.len();
+                    self.bound.extend(names);
+                    self.expect(&rule.body, ty.clone(), &scope.bind_pattern(&rule.pattern));
+                    self.bound.truncate(self.bound.len() - count);
+                }
+            },
+            _ => {
+                for child in expr.children() {
+                    self.expect(child, None, scope)
+                }
+            },
+        }
+    }
+}
+
+struct Group<'a> {
+    defs: Vec<Definition<'a>>,
+    // (index into `defs`, argument type, name of the copy), in request order
+    instances: RefCell<Vec<(usize, Ty, String)>>,
+}
+
+#[derive(Clone, Default)]
+struct Scope<'a> {
+    types: HashMap<&'a str, Ty>,
+    funs: HashMap<&'a str, (Rc<Group<'a>>, usize)>,
+}
+
+impl<'a> Scope<'a> {
+    fn bind(&self, name: &'a str, ty: Option<Ty>) -> Scope<'a> {
+        let mut scope = self.clone();
+        scope.funs.remove(name);
+        match ty {
+            Some(ty) => scope.types.insert(name, ty),
+            None => scope.types.remove(name),
+        };
+        scope
+    }
+    // the variables of a pattern, of no known type
+    fn bind_pattern(&self, pattern: &Pattern<'a>) -> Scope<'a> {
+        pattern.names().into_iter().fold(self.clone(), |scope, name| scope.bind(name, None))
+    }
+}
+
+struct Mono {
+    limits: Limits,
+    taken: BTreeSet<String>,
+    instances: Vec<Instance>,
+}
+
+impl Mono {
+    fn request<'a>(&mut self, group: &Group<'a>, index: usize, ty: Ty) -> Option<String> {
+        let mut instances = group.instances.borrow_mut();
+        if let Some((_, _, name)) = instances.iter().find(|(i, t, _)| *i == index && *t == ty) {
+            return Some(name.clone())
+        }
+        if self.limits.max_instances <= instances.iter().filter(|(i, _, _)| *i == index).count() {
+            return None
+        }
+        let function = group.defs[index].name;
+        let mut name = format!("{}{}", function, ty.mangle());
+        while self.taken.contains(&name) {
+            name.push('X');
+        }
+        self.taken.insert(name.clone());
+        self.instances.push(Instance { name: name.clone(), function: function.to_string(), param: ty.clone() });
+        instances.push((index, ty, name.clone()));
+        Some(name)
+    }
+    fn expr<'a>(&mut self, expr: &Expr<'a>, scope: &Scope<'a>) -> OwnedExpr {
+        use Expr::*;
+        match expr {
+            App{ left, right } => {
+                let callee = match **left {
+                    Var(name) => scope.funs.get(name).cloned(),
+                    _ => None,
+                };
+                let instance = match (callee, infer(right, scope)) {
+                    (Some((group, index)), Some(ty)) => self.request(&group, index, ty),
+                    _ => None,
+                };
+                let left = match instance {
+                    Some(name) => Box::new(OwnedExpr::Var(name)),
+                    None => self.boxed(left, scope),
+                };
+                OwnedExpr::App{ left, right: self.boxed(right, scope) }
+            },
+            Let{ bindings, body } => {
+                let mut inner = scope.clone();
+                let mut owned = vec![];
+                for (name, binder) in bindings {
+                    owned.push((name.to_string(), self.expr(binder, &inner)));
+                    inner = inner.bind(name, infer(binder, &inner));
+                }
+                OwnedExpr::Let{ bindings: owned, body: self.boxed(body, &inner) }
+            },
+            Lambda{ name, body } => OwnedExpr::Lambda {
+                name: name.to_string(),
+                body: self.boxed(body, &scope.bind(name, None)),
+            },
+            Funs{ defs, body } => self.funs(defs, body, scope),
+            Var(name) => OwnedExpr::Var(name.to_string()),
+            Lit(lit) => OwnedExpr::Lit(lit.into_owned()),
+            Error(span) => OwnedExpr::Error(*span),
+            Unary{ operation, child } => OwnedExpr::Unary{ operation: *operation, child: self.boxed(child, scope) },
+            Binary{ left, operation, right } => OwnedExpr::Binary {
+                left: self.boxed(left, scope),
+                operation: *operation,
+                right: self.boxed(right, scope),
+            },
+            IfThenElse{ condition, if_branch, else_branch } => OwnedExpr::IfThenElse {
+                condition: self.boxed(condition, scope),
+                if_branch: self.boxed(if_branch, scope),
+                else_branch: self.boxed(else_branch, scope),
+            },
+            While{ condition, body } => OwnedExpr::While {
+                condition: self.boxed(condition, scope),
+                body: self.boxed(body, scope),
+            },
+            Tuple{ fst, snd } => OwnedExpr::Tuple{ fst: self.boxed(fst, scope), snd: self.boxed(snd, scope) },
+            Seq(sequence) => OwnedExpr::Seq(sequence.iter().map(|expr| self.expr(expr, scope)).collect()),
+            List(elements) => OwnedExpr::List(elements.iter().map(|expr| self.expr(expr, scope)).collect()),
+            Cons{ head, tail } => OwnedExpr::Cons{ head: self.boxed(head, scope), tail: self.boxed(tail, scope) },
+            Annot{ expr, ty } => OwnedExpr::Annot{ expr: self.boxed(expr, scope), ty: ty.clone() },
+            Construct{ name, argument } => OwnedExpr::Construct {
+                name: name.to_string(),
+                argument: argument.as_ref().map(|argument| self.boxed(argument, scope)),
+            },
+            Raise(expr) => OwnedExpr::Raise(self.boxed(expr, scope)),
+            Record(fields) => OwnedExpr::Record(fields.iter()
+                .map(|(label, expr)| (label.to_string(), self.expr(expr, scope)))
+                .collect()),
+            Select{ label, record } => OwnedExpr::Select{ label: label.to_string(), record: self.boxed(record, scope) },
+            Handle{ expr, rules } => OwnedExpr::Handle {
+                expr: self.boxed(expr, scope),
+                rules: rules.iter().map(|rule| OwnedRule {
+                    pattern: rule.pattern.clone().into_owned(),
+                    body: self.boxed(&rule.body, &scope.bind_pattern(&rule.pattern)),
+                }).collect(),
+            },
+        }
+    }
+    fn boxed<'a>(&mut self, expr: &Expr<'a>, scope: &Scope<'a>) -> Box<OwnedExpr> {
+        Box::new(self.expr(expr, scope))
+    }
+    fn funs<'a>(&mut self, defs: &[Definition<'a>], body: &Expr<'a>, scope: &Scope<'a>) -> OwnedExpr {
+        let group = Rc::new(Group { defs: defs.to_vec(), instances: RefCell::new(vec![]) });
+        let mut inner = scope.clone();
+        for (index, def) in defs.iter().enumerate() {
+            inner.types.remove(def.name);
+            inner.funs.insert(def.name, (group.clone(), index));
+        }
+        let body = self.boxed(body, &inner);
+        let mut owned: Vec<OwnedDefinition> = defs.iter().map(|def| OwnedDefinition {
+            name: def.name.to_string(),
+            argument: def.argument.to_string(),
+            body: self.boxed(&def.body, &inner.bind(def.argument, None)),
+        }).collect();
+        // specializing a body can ask for more copies, of itself or of the
+        // other functions in the group
+        let mut done = 0;
+        loop {
+            let next = group.instances.borrow().get(done).cloned();
+            let (index, ty, name) = match next {
+                Some(instance) => instance,
+                None => break,
+            };
+            let def = &defs[index];
+            owned.push(OwnedDefinition {
+                name,
+                argument: def.argument.to_string(),
+                body: self.boxed(&def.body, &inner.bind(def.argument, Some(ty))),
+            });
+            done += 1;
+        }
+        OwnedExpr::Funs{ defs: owned, body }
+    }
+}
+
+fn infer<'a>(expr: &Expr<'a>, scope: &Scope<'a>) -> Option<Ty> {
+    use Expr::*;
+    match expr {
+        Var(name) => scope.types.get(name).cloned(),
+        Lit(Literal::Unit) => Some(Ty::Unit),
+        Lit(Literal::Integer(_)) => Some(Ty::Int),
+        Lit(Literal::Boolean(_)) => Some(Ty::Bool),
+        Lit(Literal::String(_)) => Some(Ty::String),
+        Lit(Literal::Char(_)) => Some(Ty::Char),
+        Lit(Literal::Real(_)) => Some(Ty::Real),
+        Unary{ operation: UnaryOp::Not, .. } => Some(Ty::Bool),
+        Unary{ operation: UnaryOp::Ord, .. } => Some(Ty::Int),
+        Unary{ operation: UnaryOp::Chr, .. } => Some(Ty::Char),
+        Unary{ operation: UnaryOp::Print, .. } | While{ .. } => Some(Ty::Unit),
+        Unary{ operation, child } => match infer(child, scope)? {
+            // `~` negates integers and reals alike
+            ty @ Ty::Int | ty @ Ty::Real if *operation == UnaryOp::Neg => Some(ty),
+            Ty::Tuple(fst, _) if *operation == UnaryOp::Fst => Some(*fst),
+            Ty::Tuple(_, snd) if *operation == UnaryOp::Snd => Some(*snd),
+            _ => None,
+        },
+        Binary{ left, operation, .. } => match operation {
+            BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mult => match infer(left, scope)? {
+                ty @ Ty::Int | ty @ Ty::Real => Some(ty),
+                _ => None,
+            },
+            BinaryOp::Div | BinaryOp::Mod => Some(Ty::Int),
+            BinaryOp::Divide => Some(Ty::Real),
+            _ => Some(Ty::Bool),
+        },
+        IfThenElse{ if_branch, else_branch, .. } => {
+            let ty = infer(if_branch, scope)?;
+            if infer(else_branch, scope)? == ty { Some(ty) } else { None }
+        },
+        Tuple{ fst, snd } => Some(Ty::Tuple(Box::new(infer(fst, scope)?), Box::new(infer(snd, scope)?))),
+        Let{ bindings, body } => {
+            let inner = bindings.iter().fold(scope.clone(), |inner, (name, binder)| {
+                let ty = infer(binder, &inner);
+                inner.bind(name, ty)
+            });
+            infer(body, &inner)
+        },
+        Seq(sequence) => infer(sequence.last()?, scope),
+        List(elements) => {
+            let ty = infer(elements.first()?, scope)?;
+            for expr in elements.iter().skip(1) {
+                if infer(expr, scope)? != ty {
+                    return None
+                }
+            }
+            Some(Ty::List(Box::new(ty)))
+        },
+        Cons{ head, .. } => Some(Ty::List(Box::new(infer(head, scope)?))),
+        Annot{ expr, ty } => Ty::of_annotation(ty).or_else(|| infer(expr, scope)),
+        Handle{ expr, .. } => infer(expr, scope),
+        Lambda{ .. } | App{ .. } | Funs{ .. } | Construct{ .. } | Raise(_) => None,
+        Record(_) | Select{ .. } | Error(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::{parse};
+
+    #[test]
+    fn monomorphize_unit() {
+        let source = "let fun id x = x and len idInt = if idInt = 0 then 0 else 1 + len (idInt - 1) in \
+                      (id 1, (id true, (id (1, false), len 3))) end";
+        let res = monomorphize(&parse(source).unwrap(), Limits::default());
+        let instances: Vec<String> = res.instances.iter()
+            .map(|instance| format!("{} {}", instance.name, instance.param))
+            .collect();
+        assert_eq!(instances, vec![
+            "idIntX int", "idBool bool", "idTupleIntBool (int * bool)", "lenInt int",
+        ]);
+        // the specialized program still means the same thing
+        let expected = parse(source).unwrap().eval().unwrap().to_string();
+        assert_eq!(res.expr.as_expr().eval().unwrap().to_string(), expected);
+
+        let capped = monomorphize(&parse(source).unwrap(), Limits { max_instances: 1 });
+        assert_eq!(capped.instances.len(), 2);
+        assert_eq!(capped.expr.as_expr().eval().unwrap().to_string(), expected);
+    }
+
+    #[test]
+    fn node_types_unit() {
+        let types: Vec<String> = node_types(&parse("let val x = (1, [true]) in fn y => fst x end").unwrap())
+            .into_iter()
+            .map(|ty| ty.map_or("?".to_string(), |ty| ty.to_string()))
+            .collect();
+        assert_eq!(types, vec!["?", "(int * bool list)", "int", "bool list", "bool", "?", "int", "(int * bool list)"]);
+    }
+
+    #[test]
+    fn referenced_inputs_unit() {
+        let inputs = |source| -> Vec<String> {
+            referenced_inputs(&parse(source).unwrap()).into_iter()
+                .map(|(name, ty)| format!("{} {}", name, ty.map_or("?".to_string(), |ty| ty.to_string())))
+                .collect()
+        };
+        let rule = r#"if total > 100 andalso region = #"e" then discount * 2.0 else score customer"#;
+        assert_eq!(inputs(rule), vec!["customer ?", "discount real", "region char", "score ?", "total int"]);
+        // bound names are not inputs, uses that disagree leave the type open
+        assert_eq!(inputs("let val limit = 3 in fn x => (x + limit, (n + 1, n = #\"a\")) end"), vec!["n ?"]);
+        assert_eq!(inputs("([a, 1], b) : int list * bool"), vec!["a int", "b bool"]);
+    }
+}
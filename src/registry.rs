@@ -0,0 +1,306 @@
+use std::fmt;
+use std::io;
+use std::fs;
+use std::rc::Rc;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::error::{ParseError};
+use crate::expr::{Expr, parse};
+use crate::expr::owned::{OwnedExpr};
+use crate::expr::eval::{Env, Error, Value};
+use crate::optimize::mono::{Ty, infer_with, referenced_inputs};
+use crate::locale::{message};
+
+// one way a new version of a rule could break the hosts evaluating the old
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Incompatibility {
+    // an input the old version did not read, hosts are not binding it
+    NewInput(String),
+    InputType{ name: String, old: Ty, new: Ty },
+    Result{ old: Ty, new: Ty },
+}
+
+impl fmt::Display for Incompatibility {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Incompatibility::NewInput(name) => write!(f, "{}", message("registry-new-input", &[name])),
+            Incompatibility::InputType{ name, old, new } => {
+                write!(f, "{}", message("registry-input-type", &[name, new, old]))
+            },
+            Incompatibility::Result{ old, new } => write!(f, "{}", message("registry-result", &[new, old])),
+        }
+    }
+}
+
+// borrows from the source it could not parse, or from the rule it ran
+#[derive(Debug)]
+pub enum RegistryError<'r> {
+    Io(io::Error),
+    Parse(ParseError<'r>),
+    Undefined(String),
+    // a rule name has to be a single word to be saved
+    Name(String),
+    // someone else replaced the rule since the version a swap was based on
+    Stale{ name: String, expected: u32, current: u32 },
+    Incompatible{ name: String, reasons: Vec<Incompatibility> },
+    // a saved registry that is not in the format `save` writes
+    Corrupt(usize),
+    Eval{ name: String, error: Box<Error<'r>> },
+}
+
+impl<'r> fmt::Display for RegistryError<'r> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use RegistryError::*;
+        match self {
+            Io(err) => write!(f, "{}", message("registry-io", &[err])),
+            Parse(err) => write!(f, "{}", err),
+            Undefined(name) => write!(f, "{}", message("registry-undefined", &[name])),
+            Name(name) => write!(f, "{}", message("registry-name", &[name])),
+            Stale{ name, expected, current } => {
+                write!(f, "{}", message("registry-stale", &[name, expected, current]))
+            },
+            Incompatible{ name, reasons } => {
+                write!(f, "{}", message("registry-incompatible", &[name]))?;
+                for reason in reasons {
+                    write!(f, "\n{}", message("note", &[reason]))?;
+                }
+                Ok(())
+            },
+            Corrupt(line) => write!(f, "{}", message("registry-corrupt", &[line])),
+            Eval{ name, error } => write!(f, "{}", message("engine-eval", &[name, error])),
+        }
+    }
+}
+
+impl<'r> From<io::Error> for RegistryError<'r> {
+    fn from(err: io::Error) -> RegistryError<'r> {
+        RegistryError::Io(err)
+    }
+}
+
+impl<'r> From<ParseError<'r>> for RegistryError<'r> {
+    fn from(err: ParseError<'r>) -> RegistryError<'r> {
+        RegistryError::Parse(err)
+    }
+}
+
+// a version of a rule, checked and parsed once and then shared with whoever
+// asks for it, so a host evaluating it keeps it even after it is replaced.
+// it owns its source and tree and goes with the last host holding it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub name: String,
+    pub version: u32,
+    // what the tree was parsed from, read only so the two always agree
+    source: String,
+    expr: OwnedExpr,
+    // the free variables a host binds to evaluate it, with their types as
+    // far as the rule tells
+    pub inputs: Vec<(String, Option<Ty>)>,
+    pub result: Option<Ty>,
+}
+
+impl Rule {
+    fn new<'r>(name: &str, version: u32, source: &'r str) -> Result<Rule, RegistryError<'r>> {
+        // `save` writes the name in a header of space separated fields
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(RegistryError::Name(name.to_string()))
+        }
+        let expr = parse(source)?;
+        let inputs = referenced_inputs(&expr);
+        let result = infer_with(&expr, &inputs);
+        let inputs = inputs.into_iter().map(|(name, ty)| (name.to_string(), ty)).collect();
+        let (name, source, expr) = (name.to_string(), source.to_string(), expr.into_owned());
+        Ok(Rule { name, version, source, expr, inputs, result })
+    }
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+    // the tree of the rule, as it was parsed when the rule was made
+    pub fn expr(&self) -> Expr<'_> {
+        self.expr.as_expr()
+    }
+    // what hosts of `self` would trip over if `new` took its place. an input
+    // or the result without a known type on either side is given the benefit
+    // of the doubt
+    pub fn changes(&self, new: &Rule) -> Vec<Incompatibility> {
+        let mut reasons = vec![];
+        for (name, ty) in &new.inputs {
+            match self.inputs.iter().find(|(old, _)| old == name) {
+                None => reasons.push(Incompatibility::NewInput(name.clone())),
+                Some((_, Some(old))) => match ty {
+                    Some(new) if new != old => {
+                        let name = name.clone();
+                        reasons.push(Incompatibility::InputType{ name, old: old.clone(), new: new.clone() })
+                    },
+                    _ => {},
+                },
+                Some((_, None)) => {},
+            }
+        }
+        if let (Some(old), Some(new)) = (&self.result, &new.result) {
+            if old != new {
+                reasons.push(Incompatibility::Result{ old: old.clone(), new: new.clone() })
+            }
+        }
+        reasons
+    }
+    pub fn eval<'r>(&'r self, inputs: &[(&str, Value<'r>)]) -> Result<Value<'r>, RegistryError<'r>> {
+        let mut env = Env::new();
+        for (name, _) in &self.inputs {
+            if let Some((_, value)) = inputs.iter().find(|(input, _)| input == name) {
+                env.define(name, value.clone())
+            }
+        }
+        self.expr().eval_ctx(&mut env)
+            .map_err(|error| RegistryError::Eval{ name: self.name.clone(), error: Box::new(error) })
+    }
+}
+
+// named rules a host evaluates and replaces while it runs. every version of
+// a rule is kept, numbered from 1, and a replacement either goes through
+// whole or leaves the registry as it was
+#[derive(Debug, Clone, Default)]
+pub struct Registry {
+    rules: BTreeMap<String, Vec<Rc<Rule>>>,
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+    // the current version of `name`
+    pub fn get(&self, name: &str) -> Option<Rc<Rule>> {
+        self.rules.get(name).and_then(|versions| versions.last()).cloned()
+    }
+    pub fn history(&self, name: &str) -> &[Rc<Rule>] {
+        self.rules.get(name).map_or(&[], |versions| versions.as_slice())
+    }
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.rules.keys().map(String::as_str)
+    }
+    // a new version of `name` whatever it reads or gives back, for rules
+    // no host evaluates yet
+    pub fn publish<'r>(&mut self, name: &str, source: &'r str) -> Result<Rc<Rule>, RegistryError<'r>> {
+        let version = self.get(name).map_or(1, |rule| rule.version + 1);
+        Ok(self.push(Rule::new(name, version, source)?))
+    }
+    // replaces version `expected` of `name` with `source`, refused when it
+    // is no longer the current one or when the new version reads an input
+    // the old one did not or has other types
+    pub fn swap<'r>(&mut self, name: &str, expected: u32, source: &'r str) -> Result<Rc<Rule>, RegistryError<'r>> {
+        let current = self.get(name).ok_or_else(|| RegistryError::Undefined(name.to_string()))?;
+        if current.version != expected {
+            return Err(RegistryError::Stale{ name: name.to_string(), expected, current: current.version })
+        }
+        let rule = Rule::new(name, current.version + 1, source)?;
+        let reasons = current.changes(&rule);
+        if !reasons.is_empty() {
+            return Err(RegistryError::Incompatible{ name: name.to_string(), reasons })
+        }
+        Ok(self.push(rule))
+    }
+    fn push(&mut self, rule: Rule) -> Rc<Rule> {
+        let rule = Rc::new(rule);
+        self.rules.entry(rule.name.clone()).or_default().push(rule.clone());
+        rule
+    }
+    pub fn eval<'r>(&'r self, name: &str, inputs: &[(&str, Value<'r>)]) -> Result<Value<'r>, RegistryError<'r>> {
+        match self.rules.get(name).and_then(|versions| versions.last()) {
+            Some(rule) => rule.eval(inputs),
+            None => Err(RegistryError::Undefined(name.to_string())),
+        }
+    }
+    // every version of every rule, each as a `rule <name> <version> <bytes>`
+    // line and then its source
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), RegistryError<'static>> {
+        let mut text = String::new();
+        for rule in self.rules.values().flatten() {
+            text.push_str(&format!("rule {} {} {}\n{}\n", rule.name, rule.version, rule.source.len(), rule.source));
+        }
+        fs::write(path, text)?;
+        Ok(())
+    }
+    // a registry `save` wrote, each version parsed again. one that does not
+    // parse any more is as corrupt as a broken header
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Registry, RegistryError<'static>> {
+        let text = fs::read_to_string(path)?;
+        let mut registry = Registry::new();
+        let mut rest = text.as_str();
+        let mut line = 1;
+        while !rest.is_empty() {
+            let corrupt = || RegistryError::Corrupt(line);
+            let (header, after) = rest.split_once('\n').ok_or_else(corrupt)?;
+            let fields: Vec<&str> = header.split(' ').collect();
+            let (name, version, len) = match fields.as_slice() {
+                ["rule", name, version, len] => (*name, version.parse().ok(), len.parse::<usize>().ok()),
+                _ => return Err(corrupt()),
+            };
+            let (version, len) = version.zip(len).ok_or_else(corrupt)?;
+            let source = after.get(..len).filter(|_| after[len..].starts_with('\n')).ok_or_else(corrupt)?;
+            registry.push(Rule::new(name, version, source).map_err(|_| corrupt())?);
+            line += 2 + source.matches('\n').count();
+            rest = &after[len + 1..];
+        }
+        Ok(registry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registry_unit() {
+        let mut registry = Registry::new();
+        let first = registry.publish("adult", "age >= 18").unwrap();
+        assert_eq!((first.version, first.result.clone()), (1, Some(Ty::Bool)));
+        assert_eq!(first.inputs, vec![("age".to_string(), Some(Ty::Int))]);
+        // the values a rule runs on are made for each run, they live no
+        // longer than the rule is borrowed
+        fn inputs<'r>() -> [(&'r str, Value<'r>); 2] {
+            [("age", Value::Integer(20)), ("unused", Value::Boolean(true))]
+        }
+        assert_eq!(registry.eval("adult", &inputs()).unwrap().to_string(), "true");
+
+        // a host holding version 1 keeps it through a swap
+        let second = registry.swap("adult", 1, "age >= 21").unwrap();
+        assert_eq!(second.version, 2);
+        assert_eq!(first.eval(&inputs()).unwrap().to_string(), "true");
+        assert_eq!(registry.eval("adult", &inputs()).unwrap().to_string(), "false");
+
+        // refused swaps change nothing
+        match registry.swap("adult", 1, "age >= 16") {
+            Err(RegistryError::Stale{ expected: 1, current: 2, .. }) => {},
+            res => panic!("{:?}", res),
+        }
+        match registry.swap("adult", 2, "if member then age + 1 else 16") {
+            Err(RegistryError::Incompatible{ reasons, .. }) => assert_eq!(reasons, vec![
+                Incompatibility::NewInput("member".to_string()),
+                Incompatibility::Result{ old: Ty::Bool, new: Ty::Int },
+            ]),
+            res => panic!("{:?}", res),
+        }
+        assert!(matches!(registry.swap("adult", 2, "age >="), Err(RegistryError::Parse(_))));
+        assert!(matches!(registry.swap("minor", 1, "age < 18"), Err(RegistryError::Undefined(_))));
+        // nor does a name `save` could not write
+        assert!(matches!(registry.publish("of age", "age >= 18"), Err(RegistryError::Name(_))));
+        assert!(matches!(registry.publish("", "age >= 18"), Err(RegistryError::Name(_))));
+        assert!(registry.get("of age").is_none());
+        assert_eq!(registry.history("adult").len(), 2);
+
+        let path = std::env::temp_dir().join(format!("ferus-registry-{}.txt", std::process::id()));
+        registry.publish("greeting", "if formal\nthen 2 else 1").unwrap();
+        registry.save(&path).unwrap();
+        let opened = Registry::open(&path).unwrap();
+        assert_eq!(opened.names().collect::<Vec<_>>(), vec!["adult", "greeting"]);
+        assert_eq!(opened.get("adult"), registry.get("adult"));
+        assert_eq!(opened.history("greeting"), registry.history("greeting"));
+        fs::write(&path, "rule adult 1 99\nage").unwrap();
+        assert!(matches!(Registry::open(&path), Err(RegistryError::Corrupt(1))));
+        fs::write(&path, "rule adult 1 5\nage >\n").unwrap();
+        assert!(matches!(Registry::open(&path), Err(RegistryError::Corrupt(1))));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,68 @@
+use std::env;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+// how trees and source snippets are laid out. the visual style draws with
+// box characters and carets, the linear one spells the same structure out
+// in words, one statement per line, so a screen reader can follow it
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash)]
+pub enum Rendering {
+    Visual,
+    Linear,
+}
+
+// set to anything but empty or `0` to get the linear rendering by default
+pub const ACCESSIBLE_VAR: &str = "FERUS_ACCESSIBLE";
+
+impl Rendering {
+    pub fn from_env() -> Rendering {
+        match env::var(ACCESSIBLE_VAR) {
+            Ok(value) if !value.is_empty() && value != "0" => Rendering::Linear,
+            _ => Rendering::Visual,
+        }
+    }
+}
+
+// process wide like the locale, 0 until someone asks or sets it
+static CURRENT: AtomicU8 = AtomicU8::new(0);
+
+pub fn set_rendering(rendering: Rendering) {
+    CURRENT.store(rendering as u8 + 1, Ordering::Relaxed)
+}
+
+// the rendering set with `set_rendering`, else the one `FERUS_ACCESSIBLE`
+// asks for
+pub fn rendering() -> Rendering {
+    match CURRENT.load(Ordering::Relaxed) {
+        0 => {
+            let rendering = Rendering::from_env();
+            set_rendering(rendering);
+            rendering
+        },
+        1 => Rendering::Visual,
+        _ => Rendering::Linear,
+    }
+}
+
+// set to anything but empty or `0` to color diagnostics by default
+pub const COLOR_VAR: &str = "FERUS_COLOR";
+
+// 0 until someone asks or sets it, like `CURRENT`
+static COLORS: AtomicU8 = AtomicU8::new(0);
+
+pub fn set_colors(colors: bool) {
+    COLORS.store(colors as u8 + 1, Ordering::Relaxed)
+}
+
+// whether diagnostics underline in color, off unless set with `set_colors`
+// or asked for with `FERUS_COLOR`. the linear rendering is never colored
+pub fn colors() -> bool {
+    match COLORS.load(Ordering::Relaxed) {
+        0 => {
+            let colors = matches!(env::var(COLOR_VAR), Ok(value) if !value.is_empty() && value != "0");
+            set_colors(colors);
+            colors
+        },
+        1 => false,
+        _ => true,
+    }
+}
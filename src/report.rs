@@ -0,0 +1,157 @@
+use std::fmt;
+use std::cell::{Cell, RefCell};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::panic::{self, AssertUnwindSafe};
+use std::backtrace::Backtrace;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ferus::lexer::{Token, Tokenizer};
+use combine::StreamOnce;
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash)]
+pub enum Phase {
+    Startup,
+    Parse,
+    Eval,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Phase::*;
+        let name = match *self {
+            Startup => "startup",
+            Parse => "parse",
+            Eval => "eval",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+struct Panic {
+    message: String,
+    backtrace: String,
+}
+
+thread_local! {
+    static PHASE: Cell<Phase> = const { Cell::new(Phase::Startup) };
+    static LAST_PANIC: RefCell<Option<Panic>> = const { RefCell::new(None) };
+}
+
+pub fn enter(phase: Phase) {
+    PHASE.with(|p| p.set(phase))
+}
+
+// replaces the default hook (which prints straight to stderr) with one that
+// stashes the panic so `guard` can put it in the bundle
+pub fn install_hook() {
+    panic::set_hook(Box::new(|info| {
+        let payload = info.payload();
+        let message = match (payload.downcast_ref::<&str>(), payload.downcast_ref::<String>()) {
+            (Some(s), _) => s.to_string(),
+            (_, Some(s)) => s.clone(),
+            _ => "<non-string payload>".to_string(),
+        };
+        let message = match info.location() {
+            Some(loc) => format!("{} at {}:{}", message, loc.file(), loc.line()),
+            None => message,
+        };
+        let backtrace = Backtrace::force_capture().to_string();
+        LAST_PANIC.with(|p| *p.borrow_mut() = Some(Panic { message, backtrace }));
+    }))
+}
+
+fn token_dump(source: &str) -> String {
+    let dump = panic::catch_unwind(|| {
+        let mut tokenizer = Tokenizer::new(source);
+        let mut tokens = vec![];
+        loop {
+            match tokenizer.uncons() {
+                Ok(Token::EndOfFile) => return tokens.join(" "),
+                Ok(tok) => tokens.push(format!("{:?}", tok)),
+                Err(err) => {
+                    tokens.push(format!("<{:?}>", err));
+                    return tokens.join(" ")
+                },
+            }
+        }
+    });
+    dump.unwrap_or_else(|_| "<the lexer panicked>".to_string())
+}
+
+// bundles this process wrote, so threads that panic in the same second
+// each get one of their own
+static BUNDLES: AtomicUsize = AtomicUsize::new(0);
+
+// a new file in `dir` for a bundle, never one that is already there, which
+// another process could have written
+fn create_bundle(dir: &Path, stamp: u64) -> io::Result<(PathBuf, File)> {
+    loop {
+        let count = BUNDLES.fetch_add(1, Ordering::Relaxed);
+        let path = dir.join(format!("ferus-crash-{}-{}-{}.txt", stamp, process::id(), count));
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+fn write_bundle(source: &str, phase: Phase, panic: Option<Panic>) -> io::Result<String> {
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (message, backtrace) = match panic {
+        Some(Panic { message, backtrace }) => (message, backtrace),
+        None => ("<unknown>".to_string(), "<unavailable>".to_string()),
+    };
+    let (path, mut file) = create_bundle(Path::new("."), stamp)?;
+    let path = path.display().to_string();
+    writeln!(file, "{}", ferus::features())?;
+    writeln!(file, "phase: {}", phase)?;
+    writeln!(file, "panic: {}", message)?;
+    writeln!(file, "\n--- source ---\n{}", source)?;
+    writeln!(file, "\n--- tokens ---\n{}", token_dump(source))?;
+    writeln!(file, "\n--- backtrace ---\n{}", backtrace)?;
+    Ok(path)
+}
+
+// runs `cb`, turning a panic into a bug report bundle instead of a crash
+pub fn guard<A, F>(source: &str, cb: F) -> Option<A>
+where F: FnOnce() -> A
+{
+    match panic::catch_unwind(AssertUnwindSafe(cb)) {
+        Ok(res) => Some(res),
+        Err(_) => {
+            let phase = PHASE.with(|p| p.get());
+            let panic = LAST_PANIC.with(|p| p.borrow_mut().take());
+            eprintln!("INTERNAL ERROR: ferus crashed during {}", phase);
+            match write_bundle(source, phase, panic) {
+                Ok(path) => eprintln!("a bug report was written to {}, please attach it to an issue", path),
+                Err(err) => eprintln!("could not write a bug report because: {}", err),
+            }
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn create_bundle_unit() {
+        // two panics in the same second write two bundles
+        let dir = std::env::temp_dir();
+        let (first, _) = create_bundle(&dir, 0).unwrap();
+        let (second, _) = create_bundle(&dir, 0).unwrap();
+        assert_ne!(first, second);
+        for path in &[first, second] {
+            std::fs::remove_file(path).unwrap()
+        }
+    }
+}
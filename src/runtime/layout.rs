@@ -0,0 +1,130 @@
+use crate::optimize::mono::{Ty};
+
+// how values are represented at runtime.
+//
+// scalars (unit, integers, booleans, characters and reals) are a kind plus
+// one 64 bit word: integers are the word itself, booleans are 0 or 1,
+// characters are their code point, reals are their bits and unit is
+// always 0.
+// they never live behind a pointer.
+//
+// a tuple whose leaves are all scalars is flat: its words are stored one
+// after the other, leftmost leaf first, with no pointers in between. the vm
+// keeps a flat pair of two scalars inline in the value itself, codegen
+// backends get the full flat layout of any such tuple from `Layout::of`.
+//
+// everything else (strings, lists, closures and tuples holding any of them)
+// is boxed: a single pointer to a reference counted cell.
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash)]
+pub enum Kind {
+    Unit,
+    Int,
+    Bool,
+    Char,
+    Real,
+}
+
+impl Kind {
+    pub fn of(ty: &Ty) -> Option<Kind> {
+        match ty {
+            Ty::Unit => Some(Kind::Unit),
+            Ty::Int => Some(Kind::Int),
+            Ty::Bool => Some(Kind::Bool),
+            Ty::Char => Some(Kind::Char),
+            Ty::Real => Some(Kind::Real),
+            _ => None,
+        }
+    }
+}
+
+// the vm's inline representation of a tuple of two scalars
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Hash)]
+pub struct FlatPair {
+    pub kinds: [Kind; 2],
+    pub words: [i64; 2],
+}
+
+impl FlatPair {
+    pub fn new(fst: (Kind, i64), snd: (Kind, i64)) -> FlatPair {
+        FlatPair { kinds: [fst.0, snd.0], words: [fst.1, snd.1] }
+    }
+    pub fn fst(&self) -> (Kind, i64) {
+        (self.kinds[0], self.words[0])
+    }
+    pub fn snd(&self) -> (Kind, i64) {
+        (self.kinds[1], self.words[1])
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub enum Layout {
+    Scalar(Kind),
+    // the kinds of the leaves, in the order their words are stored
+    Flat(Vec<Kind>),
+    Boxed,
+}
+
+impl Layout {
+    pub fn of(ty: &Ty) -> Layout {
+        if let Some(kind) = Kind::of(ty) {
+            return Layout::Scalar(kind)
+        }
+        let mut kinds = vec![];
+        if leaves(ty, &mut kinds) {
+            Layout::Flat(kinds)
+        } else {
+            Layout::Boxed
+        }
+    }
+    // size in 64 bit words
+    pub fn words(&self) -> usize {
+        match self {
+            Layout::Scalar(_) | Layout::Boxed => 1,
+            Layout::Flat(kinds) => kinds.len(),
+        }
+    }
+    // where the first and second components of a flat tuple of type `ty`
+    // start, in words, along with their own layouts
+    pub fn fields(ty: &Ty) -> Option<((usize, Layout), (usize, Layout))> {
+        match (Layout::of(ty), ty) {
+            (Layout::Flat(_), Ty::Tuple(fst, snd)) => {
+                let fst = Layout::of(fst);
+                let offset = fst.words();
+                Some(((0, fst), (offset, Layout::of(snd))))
+            },
+            _ => None,
+        }
+    }
+}
+
+fn leaves(ty: &Ty, kinds: &mut Vec<Kind>) -> bool {
+    match ty {
+        Ty::Tuple(fst, snd) => leaves(fst, kinds) && leaves(snd, kinds),
+        ty => match Kind::of(ty) {
+            Some(kind) => {
+                kinds.push(kind);
+                true
+            },
+            None => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layout_unit() {
+        let tuple = |fst, snd| Ty::Tuple(Box::new(fst), Box::new(snd));
+        let ty = tuple(tuple(Ty::Int, Ty::Bool), Ty::Int);
+        assert_eq!(Layout::of(&ty), Layout::Flat(vec![Kind::Int, Kind::Bool, Kind::Int]));
+        assert_eq!(Layout::fields(&ty), Some((
+            (0, Layout::Flat(vec![Kind::Int, Kind::Bool])),
+            (2, Layout::Scalar(Kind::Int)),
+        )));
+        assert_eq!(Layout::of(&tuple(Ty::Int, Ty::List(Box::new(Ty::Int)))), Layout::Boxed);
+        assert_eq!(Layout::of(&Ty::Bool).words(), 1);
+    }
+}
@@ -0,0 +1,105 @@
+use std::fmt;
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+
+use crate::expr::{BinaryOp};
+use crate::expr::eval::{DIV, OVERFLOW};
+
+// a floating point number. every real a program holds is finite and zero
+// has no sign, an operation whose result is not finite raises `Overflow`
+// (or `Div` when dividing by zero). so reals compare, hash and print back
+// as source like any other literal
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Real(f64);
+
+impl PartialEq for Real {
+    fn eq(&self, other: &Real) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Real {}
+
+impl PartialOrd for Real {
+    fn partial_cmp(&self, other: &Real) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Real {
+    fn cmp(&self, other: &Real) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+impl Hash for Real {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state)
+    }
+}
+
+// `1.5`, `1e-9`, always with a `.` or an exponent so it does not read
+// back as an integer
+impl fmt::Display for Real {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+impl Real {
+    pub const MAX: Real = Real(f64::MAX);
+    // `None` for infinities and nans
+    pub fn new(x: f64) -> Option<Real> {
+        // adding zero turns `-0.0` into `0.0`
+        Some(Real(x + 0.0)).filter(|x| x.0.is_finite())
+    }
+    pub fn value(self) -> f64 {
+        self.0
+    }
+    // a literal like `3.14`, `1e-9` or `1E~9`, `None` when it is too large
+    pub fn parse(text: &str) -> Option<Real> {
+        text.replace('~', "-").parse().ok().and_then(Real::new)
+    }
+    fn fit(x: f64) -> Result<Real, &'static str> {
+        Real::new(x).ok_or(OVERFLOW)
+    }
+    // the result of `+`, `-`, `*` or `/` or the exception it raises, `None`
+    // for the other operators
+    pub fn arithmetic(self, operation: BinaryOp, right: Real) -> Option<Result<Real, &'static str>> {
+        use BinaryOp::*;
+        let (left, right) = (self.0, right.0);
+        let res = match operation {
+            Add => Real::fit(left + right),
+            Sub => Real::fit(left - right),
+            Mult => Real::fit(left * right),
+            Divide if right == 0.0 => Err(DIV),
+            Divide => Real::fit(left / right),
+            _ => return None,
+        };
+        Some(res)
+    }
+    pub fn negate(self) -> Real {
+        Real(-self.0 + 0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn real_unit() {
+        let real = |text| Real::parse(text).unwrap();
+        assert_eq!(real("1E~9"), real("1e-9"));
+        assert_eq!(real("1e-9").to_string(), "1e-9");
+        assert_eq!(real("3.0").to_string(), "3.0");
+        assert_eq!(Real::parse("1e999"), None);
+        assert_eq!(real("0.0").negate(), real("0.0"));
+        assert!(real("~2.5") < real("0.5"));
+        assert_eq!(real("1.5").arithmetic(BinaryOp::Divide, real("0.5")), Some(Ok(real("3.0"))));
+        assert_eq!(real("1.5").arithmetic(BinaryOp::Divide, real("0.0")), Some(Err(DIV)));
+        assert_eq!(real("1e300").arithmetic(BinaryOp::Mult, real("1e300")), Some(Err(OVERFLOW)));
+        assert_eq!(real("1.5").arithmetic(BinaryOp::Div, real("0.5")), None);
+    }
+}
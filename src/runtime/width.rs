@@ -0,0 +1,97 @@
+use std::fmt;
+
+use crate::expr::{BinaryOp};
+use crate::expr::eval::{DIV, OVERFLOW};
+
+// how many bits a program's integers have. every backend computes in an
+// i64 word and checks each result against the width, one that does not fit
+// raises `Overflow` instead of wrapping around. literals are checked by the
+// lexer against the width the crate was built with, see `Width::default`
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash)]
+pub enum Width {
+    I32,
+    I64,
+}
+
+impl fmt::Display for Width {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Width::I32 => write!(f, "i32"),
+            Width::I64 => write!(f, "i64"),
+        }
+    }
+}
+
+impl Default for Width {
+    // the `int32` cargo feature makes 32 bits the default
+    fn default() -> Width {
+        if cfg!(feature = "int32") { Width::I32 } else { Width::I64 }
+    }
+}
+
+impl Width {
+    pub fn min(self) -> i64 {
+        match self {
+            Width::I32 => i32::MIN as i64,
+            Width::I64 => i64::MIN,
+        }
+    }
+    pub fn max(self) -> i64 {
+        match self {
+            Width::I32 => i32::MAX as i64,
+            Width::I64 => i64::MAX,
+        }
+    }
+    pub fn contains(self, i: i64) -> bool {
+        self.min() <= i && i <= self.max()
+    }
+    // `digits` as an integer of this width, `None` when it does not fit
+    pub fn parse(self, digits: &str) -> Option<i64> {
+        digits.parse().ok().filter(|i| self.contains(*i))
+    }
+    fn fit(self, res: Option<i64>) -> Result<i64, &'static str> {
+        res.filter(|i| self.contains(*i)).ok_or(OVERFLOW)
+    }
+    // the result of an arithmetic operator or the exception it raises,
+    // `None` for the operators that are not arithmetic
+    pub fn arithmetic(self, operation: BinaryOp, left: i64, right: i64)
+        -> Option<Result<i64, &'static str>>
+    {
+        use BinaryOp::*;
+        let res = match operation {
+            Add => self.fit(left.checked_add(right)),
+            Sub => self.fit(left.checked_sub(right)),
+            Mult => self.fit(left.checked_mul(right)),
+            Div | Mod if right == 0 => Err(DIV),
+            Div => self.fit(left.checked_div(right)),
+            // only `min mod ~1` overflows in rust, its remainder is 0
+            Mod => Ok(left.wrapping_rem(right)),
+            _ => return None,
+        };
+        Some(res)
+    }
+    pub fn negate(self, i: i64) -> Result<i64, &'static str> {
+        self.fit(i.checked_neg())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn width_unit() {
+        let max = i32::MAX as i64;
+        assert_eq!(Width::I32.arithmetic(BinaryOp::Add, max, 1), Some(Err(OVERFLOW)));
+        assert_eq!(Width::I64.arithmetic(BinaryOp::Add, max, 1), Some(Ok(max + 1)));
+        assert_eq!(Width::I64.arithmetic(BinaryOp::Mult, i64::MAX, 2), Some(Err(OVERFLOW)));
+        assert_eq!(Width::I32.arithmetic(BinaryOp::Div, 7, 0), Some(Err(DIV)));
+        assert_eq!(Width::I64.arithmetic(BinaryOp::Div, i64::MIN, -1), Some(Err(OVERFLOW)));
+        assert_eq!(Width::I64.arithmetic(BinaryOp::Mod, i64::MIN, -1), Some(Ok(0)));
+        assert_eq!(Width::I32.arithmetic(BinaryOp::LessThan, 1, 2), None);
+        assert_eq!(Width::I32.negate(-max - 1), Err(OVERFLOW));
+        assert_eq!(Width::I32.parse("2147483647"), Some(max));
+        assert_eq!(Width::I32.parse("2147483648"), None);
+        assert_eq!(Width::I64.parse("99999999999999999999"), None);
+    }
+}
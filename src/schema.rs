@@ -0,0 +1,299 @@
+// a json schema of what ferus writes for other tools: the syntax tree and
+// diagnostics the way the `serde` feature writes them, and the trace of
+// `--trace-out`. printed by `ferus schema`, so a tool in another language
+// can generate its types from it or check what it is given
+use std::fmt::Write;
+
+pub const DRAFT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+// how a value is written as json, one for each way the types below are
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Shape {
+    Bool,
+    Integer,
+    Number,
+    String,
+    // exactly this string
+    Const(&'static str),
+    // one of these strings, a unit-only enum
+    Names(&'static [&'static str]),
+    // the definition called this
+    Ref(&'static str),
+    // null for an `Option` that is `None`
+    Nullable(Box<Shape>),
+    Array(Box<Shape>),
+    // an array of exactly these, a tuple
+    Tuple(Vec<Shape>),
+    // the fields, each with whether it has to be there. no others are
+    Object(Vec<(&'static str, Shape, bool)>),
+    OneOf(Vec<Shape>),
+}
+
+fn object(fields: &[(&'static str, Shape)]) -> Shape {
+    Shape::Object(fields.iter().map(|(name, shape)| (*name, shape.clone(), true)).collect())
+}
+
+fn array(shape: Shape) -> Shape {
+    Shape::Array(Box::new(shape))
+}
+
+fn nullable(shape: Shape) -> Shape {
+    Shape::Nullable(Box::new(shape))
+}
+
+// an enum with `#[serde(tag = "kind", content = "value")]`, a unit
+// variant has no `value`
+fn tagged(variants: &[(&'static str, Option<Shape>)]) -> Shape {
+    Shape::OneOf(variants.iter()
+        .map(|(name, content)| {
+            let mut fields = vec![("kind", Shape::Const(name), true)];
+            if let Some(content) = content {
+                fields.push(("value", content.clone(), true));
+            }
+            Shape::Object(fields)
+        })
+        .collect())
+}
+
+// every definition of the schema, mirroring the serde attributes on each
+// type of the same name
+pub fn definitions() -> Vec<(&'static str, Shape)> {
+    use Shape::*;
+    let expr = || Ref("Expr");
+    let ty = || Ref("TypeExpr");
+    let pattern = || Ref("Pattern");
+    let span = || Ref("Span");
+    vec![
+        ("Span", object(&[("start", Integer), ("end", Integer)])),
+        ("Literal", tagged(&[
+            ("Unit", None),
+            ("Integer", Some(Integer)),
+            ("Boolean", Some(Bool)),
+            ("String", Some(String)),
+            ("Char", Some(String)),
+            ("Real", Some(Number)),
+        ])),
+        ("UnaryOp", Names(&["Not", "Fst", "Snd", "Print", "Neg", "Ord", "Chr"])),
+        ("BinaryOp", Names(&[
+            "Add", "Sub", "Mult", "Div", "Mod", "Divide", "Equal", "NotEqual",
+            "LessThan", "LessEqual", "GreaterThan", "GreaterEqual", "OrElse", "AndAlso",
+        ])),
+        ("TypeExpr", tagged(&[
+            ("Unit", None),
+            ("Int", None),
+            ("Bool", None),
+            ("String", None),
+            ("Char", None),
+            ("Real", None),
+            ("Tuple", Some(Tuple(vec![ty(), ty()]))),
+            ("List", Some(ty())),
+            ("Arrow", Some(Tuple(vec![ty(), ty()]))),
+            ("Named", Some(String)),
+        ])),
+        ("Pattern", tagged(&[
+            ("Wildcard", None),
+            ("Var", Some(String)),
+            ("Construct", Some(object(&[("name", String), ("argument", nullable(pattern()))]))),
+            ("Record", Some(array(Tuple(vec![String, pattern()])))),
+        ])),
+        ("Definition", object(&[("name", String), ("argument", String), ("body", expr())])),
+        ("Rule", object(&[("pattern", pattern()), ("body", expr())])),
+        ("Expr", tagged(&[
+            ("Var", Some(String)),
+            ("Lit", Some(Ref("Literal"))),
+            ("Unary", Some(object(&[("operation", Ref("UnaryOp")), ("child", expr())]))),
+            ("Binary", Some(object(&[("left", expr()), ("operation", Ref("BinaryOp")), ("right", expr())]))),
+            ("IfThenElse", Some(object(&[("condition", expr()), ("if_branch", expr()), ("else_branch", expr())]))),
+            ("Tuple", Some(object(&[("fst", expr()), ("snd", expr())]))),
+            ("Let", Some(object(&[("name", String), ("binder", expr()), ("body", expr())]))),
+            ("Lambda", Some(object(&[("name", String), ("body", expr())]))),
+            ("App", Some(object(&[("left", expr()), ("right", expr())]))),
+            ("Seq", Some(array(expr()))),
+            ("List", Some(array(expr()))),
+            ("Cons", Some(object(&[("head", expr()), ("tail", expr())]))),
+            ("Funs", Some(object(&[("defs", array(Ref("Definition"))), ("body", expr())]))),
+            ("Annot", Some(object(&[("expr", expr()), ("ty", ty())]))),
+            ("Construct", Some(object(&[("name", String), ("argument", nullable(expr()))]))),
+            ("Raise", Some(expr())),
+            ("Handle", Some(object(&[("expr", expr()), ("rules", array(Ref("Rule")))]))),
+            ("While", Some(object(&[("condition", expr()), ("body", expr())]))),
+            ("Record", Some(array(Tuple(vec![String, expr()])))),
+            ("Select", Some(object(&[("label", String), ("record", expr())]))),
+            ("Error", Some(span())),
+        ])),
+        ("Label", object(&[("span", span()), ("message", String), ("primary", Bool)])),
+        ("Diagnostic", object(&[("labels", array(Ref("Label")))])),
+        // `Trace::json`, a node has its span when the source was indexed
+        ("Event", {
+            let event = |name, fields: &[(&'static str, Shape)]| {
+                let mut all = vec![("event", Const(name), true), ("node", Integer, true)];
+                all.push(("span", Tuple(vec![Integer, Integer]), false));
+                all.extend(fields.iter().map(|(name, shape)| (*name, shape.clone(), true)));
+                Object(all)
+            };
+            OneOf(vec![
+                event("enter", &[]),
+                event("exit", &[("value", String)]),
+                event("exit", &[("error", String)]),
+                object(&[("event", Const("bind")), ("name", String), ("value", String)]),
+                object(&[("event", Const("unbind")), ("name", String)]),
+            ])
+        }),
+        ("Trace", object(&[("events", array(Ref("Event")))])),
+    ]
+}
+
+impl Shape {
+    fn write(&self, out: &mut String) {
+        let list = |out: &mut String, shapes: &[Shape]| {
+            for (i, shape) in shapes.iter().enumerate() {
+                if 0 < i {
+                    out.push_str(", ");
+                }
+                shape.write(out);
+            }
+        };
+        match self {
+            Shape::Bool => out.push_str("{\"type\": \"boolean\"}"),
+            Shape::Integer => out.push_str("{\"type\": \"integer\"}"),
+            Shape::Number => out.push_str("{\"type\": \"number\"}"),
+            Shape::String => out.push_str("{\"type\": \"string\"}"),
+            Shape::Const(name) => write!(out, "{{\"const\": \"{}\"}}", name).unwrap(),
+            Shape::Names(names) => {
+                let quoted: Vec<String> = names.iter().map(|name| format!("\"{}\"", name)).collect();
+                write!(out, "{{\"enum\": [{}]}}", quoted.join(", ")).unwrap()
+            },
+            Shape::Ref(name) => write!(out, "{{\"$ref\": \"#/$defs/{}\"}}", name).unwrap(),
+            Shape::Nullable(shape) => {
+                out.push_str("{\"anyOf\": [");
+                shape.write(out);
+                out.push_str(", {\"type\": \"null\"}]}");
+            },
+            Shape::Array(shape) => {
+                out.push_str("{\"type\": \"array\", \"items\": ");
+                shape.write(out);
+                out.push('}');
+            },
+            Shape::Tuple(shapes) => {
+                out.push_str("{\"type\": \"array\", \"prefixItems\": [");
+                list(out, shapes);
+                write!(out, "], \"minItems\": {}, \"items\": false}}", shapes.len()).unwrap();
+            },
+            Shape::Object(fields) => {
+                out.push_str("{\"type\": \"object\", \"properties\": {");
+                for (i, (name, shape, _)) in fields.iter().enumerate() {
+                    if 0 < i {
+                        out.push_str(", ");
+                    }
+                    write!(out, "\"{}\": ", name).unwrap();
+                    shape.write(out);
+                }
+                let required: Vec<String> = fields.iter()
+                    .filter(|(_, _, required)| *required)
+                    .map(|(name, _, _)| format!("\"{}\"", name))
+                    .collect();
+                let required = required.join(", ");
+                write!(out, "}}, \"required\": [{}], \"additionalProperties\": false}}", required).unwrap();
+            },
+            Shape::OneOf(shapes) => {
+                out.push_str("{\"oneOf\": [");
+                list(out, shapes);
+                out.push_str("]}");
+            },
+        }
+    }
+}
+
+// the whole schema, each definition on a line of its own
+pub fn schema() -> String {
+    let mut out = format!("{{\n  \"$schema\": \"{}\",\n  \"title\": \"ferus\",\n  \"$defs\": {{\n", DRAFT);
+    let definitions = definitions();
+    for (i, (name, shape)) in definitions.iter().enumerate() {
+        write!(out, "    \"{}\": ", name).unwrap();
+        shape.write(&mut out);
+        out.push_str(if i + 1 < definitions.len() { ",\n" } else { "\n" });
+    }
+    out.push_str("  }\n}\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use crate::expr::{parse_indexed};
+
+    // whether `json` is something `shape` describes, as a validator of
+    // the schema would tell
+    fn valid(shape: &Shape, json: &Value) -> bool {
+        match (shape, json) {
+            (Shape::Bool, Value::Bool(_)) | (Shape::String, Value::String(_)) => true,
+            (Shape::Integer, Value::Number(n)) => n.is_i64() || n.is_u64(),
+            (Shape::Number, Value::Number(_)) => true,
+            (Shape::Const(name), Value::String(s)) => name == s,
+            (Shape::Names(names), Value::String(s)) => names.contains(&s.as_str()),
+            (Shape::Ref(name), json) => {
+                let definitions = definitions();
+                let (_, shape) = definitions.iter().find(|(def, _)| def == name).expect("an undefined $ref");
+                valid(shape, json)
+            },
+            (Shape::Nullable(_), Value::Null) => true,
+            (Shape::Nullable(shape), json) => valid(shape, json),
+            (Shape::Array(shape), Value::Array(items)) => items.iter().all(|item| valid(shape, item)),
+            (Shape::Tuple(shapes), Value::Array(items)) => {
+                shapes.len() == items.len() && shapes.iter().zip(items).all(|(shape, item)| valid(shape, item))
+            },
+            (Shape::Object(fields), Value::Object(map)) => {
+                map.keys().all(|key| fields.iter().any(|(name, _, _)| name == key))
+                    && fields.iter().all(|(name, shape, required)| match map.get(*name) {
+                        Some(value) => valid(shape, value),
+                        None => !required,
+                    })
+            },
+            (Shape::OneOf(shapes), json) => shapes.iter().filter(|shape| valid(shape, json)).count() == 1,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn schema_unit() {
+        let schema: Value = serde_json::from_str(&schema()).unwrap();
+        assert_eq!(schema["$schema"], DRAFT);
+        assert_eq!(schema["$defs"]["Span"]["required"], serde_json::json!(["start", "end"]));
+        assert_eq!(schema["$defs"].as_object().unwrap().len(), definitions().len());
+
+        let source = "let fun f n = n * 2 in (f 1; f (2)) end";
+        let (expr, table) = parse_indexed(source).unwrap();
+        let (_, trace) = expr.trace();
+        let json: Value = serde_json::from_str(&trace.json(Some(&table))).unwrap();
+        assert!(valid(&Shape::Ref("Trace"), &json));
+        let json: Value = serde_json::from_str(&trace.json(None)).unwrap();
+        assert!(valid(&Shape::Ref("Trace"), &json));
+        assert!(!valid(&Shape::Ref("Trace"), &serde_json::json!({"events": [{"event": "enter"}]})));
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn schema_serde_unit() {
+        use crate::expr::{Expr, parse};
+        use crate::diagnostic::{Diagnostic};
+        use crate::lexer::{Span};
+
+        let source = "let fun f n = if n < 2 then ~n else f (n - 1) in \
+            (f 10 handle Fail msg => 0 | Some {x = _} => 1, {x = [1.5, 2.0], y = #x r}) : int * (char -> int) end";
+        let exprs = vec![
+            parse(source).unwrap(),
+            parse("let val x = (print #\"a\"; ()) in while not true do x :: [] end").unwrap(),
+            parse("Some (raise Fail 0) orelse None").unwrap(),
+            Expr::Seq(vec![Expr::Error(Span::new(0, 3)), Expr::Construct{ name: "None", argument: None }]),
+        ];
+        for expr in exprs {
+            let json = serde_json::to_value(&expr).unwrap();
+            assert!(valid(&Shape::Ref("Expr"), &json), "{}", json);
+        }
+        let diagnostic = Diagnostic::new(Span::new(9, 10), "expected `then`".to_string())
+            .label(Span::new(0, 2), "`if` started here".to_string());
+        assert!(valid(&Shape::Ref("Diagnostic"), &serde_json::to_value(&diagnostic).unwrap()));
+        assert!(!valid(&Shape::Ref("Expr"), &serde_json::json!({"kind": "Var", "value": 1})));
+    }
+}
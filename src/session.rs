@@ -0,0 +1,232 @@
+use std::collections::BTreeSet;
+
+use crate::expr::{Definition, Expr};
+use crate::expr::eval::{Env, EvalConfig, Error, Output, Value};
+
+#[derive(Debug, Clone)]
+pub enum Source<'a> {
+    Val(Expr<'a>),
+    Fun(Vec<Definition<'a>>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Binding<'a> {
+    pub name: &'a str,
+    pub source: Source<'a>,
+    // the other top level names the source reads
+    pub deps: BTreeSet<&'a str>,
+    pub changed_at: u64,
+    pub stale: bool,
+}
+
+// top level bindings of a repl session. every binding remembers what it
+// was computed from, so redefining a name can report (and later refresh)
+// exactly the bindings downstream of it
+#[derive(Debug, Clone)]
+pub struct Session<'a> {
+    env: Env<'a>,
+    bindings: Vec<Binding<'a>>,
+    revision: u64,
+}
+
+impl<'a> Default for Session<'a> {
+    fn default() -> Session<'a> {
+        Session::new()
+    }
+}
+
+impl<'a> Session<'a> {
+    pub fn new() -> Session<'a> {
+        Session { env: Env::new(), bindings: vec![], revision: 0 }
+    }
+    // a session whose `print`s go to `output`, see `Env::with_output`
+    pub fn with_output(output: Output) -> Session<'a> {
+        Session { env: Env::with_output(EvalConfig::default(), output), ..Session::new() }
+    }
+    // a session evaluating under `env`, with its config and output
+    pub fn with_env(env: Env<'a>) -> Session<'a> {
+        Session { env, ..Session::new() }
+    }
+    // in definition order
+    pub fn bindings(&self) -> &[Binding<'a>] {
+        &self.bindings
+    }
+    pub fn binding(&self, name: &str) -> Option<&Binding<'a>> {
+        self.bindings.iter().find(|b| b.name == name)
+    }
+    pub fn lookup(&self, name: &str) -> Option<&Value<'a>> {
+        self.env.lookup(name)
+    }
+    pub fn eval(&mut self, expr: Expr<'a>) -> Result<Value<'a>, Error<'a>> {
+        expr.eval_ctx(&mut self.env)
+    }
+    // applies a function to its arguments one at a time, a function taking
+    // no arguments is applied to unit
+    pub fn call(&mut self, function: Value<'a>, args: Vec<Value<'a>>) -> Result<Value<'a>, Error<'a>> {
+        if args.is_empty() {
+            return function.apply(Value::Unit, &mut self.env)
+        }
+        let mut res = function;
+        for arg in args {
+            res = res.apply(arg, &mut self.env)?;
+        }
+        Ok(res)
+    }
+    // the names bound by `fun` declarations, in definition order
+    pub fn functions(&self) -> Vec<&'a str> {
+        self.bindings.iter()
+            .filter(|b| match b.source {
+                Source::Fun(_) => true,
+                Source::Val(_) => false,
+            })
+            .map(|b| b.name)
+            .collect()
+    }
+    // binds `name` and returns its value along with the bindings it made stale
+    pub fn define_val(&mut self, name: &'a str, expr: Expr<'a>) -> Result<(Value<'a>, Vec<&'a str>), Error<'a>> {
+        let value = expr.clone().eval_ctx(&mut self.env)?;
+        self.env.define(name, value.clone());
+        let deps = expr.free_variables();
+        let stale = self.record(name, Source::Val(expr), deps);
+        Ok((value, stale))
+    }
+    pub fn define_funs(&mut self, defs: Vec<Definition<'a>>) -> Vec<&'a str> {
+        let deps = Definition::free_variables(&defs);
+        self.env.define_functions(defs.clone());
+        let mut stale = vec![];
+        for def in defs.iter() {
+            stale.extend(self.record(def.name, Source::Fun(defs.clone()), deps.clone()));
+        }
+        stale.sort();
+        stale.dedup();
+        stale
+    }
+    // forgets `name`, anything reading it is stale until it is defined again
+    pub fn remove(&mut self, name: &'a str) -> Vec<&'a str> {
+        self.env.remove(name);
+        self.bindings.retain(|b| b.name != name);
+        self.invalidate(name)
+    }
+    // every binding that is out of date, in the order they should be refreshed
+    pub fn stale(&self) -> Vec<&'a str> {
+        self.bindings.iter().filter(|b| b.stale).map(|b| b.name).collect()
+    }
+    // re-evaluates the stale bindings against the current environment
+    pub fn refresh(&mut self) -> Vec<(&'a str, Result<Value<'a>, Error<'a>>)> {
+        let mut results = vec![];
+        for i in 0..self.bindings.len() {
+            if !self.bindings[i].stale {
+                continue
+            }
+            let name = self.bindings[i].name;
+            self.bindings[i].stale = false;
+            let res = match self.bindings[i].source.clone() {
+                Source::Val(expr) => {
+                    let res = expr.eval_ctx(&mut self.env);
+                    if let Ok(ref value) = res {
+                        self.env.define(name, value.clone());
+                    }
+                    res
+                },
+                // the whole group is made again, but a name of it defined
+                // again since keeps what it is now
+                Source::Fun(defs) => {
+                    let mut kept = vec![];
+                    for def in defs.iter() {
+                        match self.bindings.iter_mut().find(|b| b.name == def.name) {
+                            Some(binding) if matches!(binding.source, Source::Fun(ref own) if *own == defs) => {
+                                binding.stale = false
+                            },
+                            _ => kept.push((def.name, self.env.lookup(def.name).cloned())),
+                        }
+                    }
+                    self.env.define_functions(defs);
+                    for (name, value) in kept {
+                        match value {
+                            Some(value) => self.env.define(name, value),
+                            None => {
+                                self.env.remove(name);
+                            },
+                        }
+                    }
+                    Ok(self.env.lookup(name).cloned().unwrap())
+                },
+            };
+            if res.is_ok() {
+                self.revision += 1;
+                self.bindings[i].changed_at = self.revision;
+            }
+            results.push((name, res));
+        }
+        results
+    }
+    fn record(&mut self, name: &'a str, source: Source<'a>, deps: BTreeSet<&'a str>) -> Vec<&'a str> {
+        self.revision += 1;
+        self.bindings.retain(|b| b.name != name);
+        self.bindings.push(Binding { name, source, deps, changed_at: self.revision, stale: false });
+        self.invalidate(name)
+    }
+    fn invalidate(&mut self, name: &'a str) -> Vec<&'a str> {
+        // walk the reverse dependencies, `fun` bindings hold on to what
+        // they read like any other
+        let mut changed = vec![name];
+        let mut seen = BTreeSet::new();
+        seen.insert(name);
+        let mut stale = vec![];
+        while let Some(dep) = changed.pop() {
+            for binding in self.bindings.iter_mut() {
+                if binding.deps.contains(dep) && seen.insert(binding.name) {
+                    binding.stale = true;
+                    stale.push(binding.name);
+                    changed.push(binding.name);
+                }
+            }
+        }
+        stale.sort();
+        stale
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::{parse};
+
+    #[test]
+    fn session_stale_unit() {
+        let mut session = Session::new();
+        session.define_val("x", parse("1").unwrap()).unwrap();
+        session.define_val("f", parse("fn n => n + x").unwrap()).unwrap();
+        session.define_val("y", parse("f 10").unwrap()).unwrap();
+        session.define_val("z", parse("2").unwrap()).unwrap();
+        let (_, stale) = session.define_val("x", parse("100").unwrap()).unwrap();
+        assert_eq!(stale, vec!["f", "y"]);
+        assert_eq!(session.stale(), vec!["f", "y"]);
+        let refreshed: Vec<String> = session.refresh().into_iter()
+            .map(|(name, res)| format!("{} {}", name, res.unwrap()))
+            .collect();
+        assert_eq!(refreshed[1], "y 110");
+        assert!(session.stale().is_empty());
+    }
+
+    #[test]
+    fn session_fun_unit() {
+        let mut session = Session::new();
+        session.define_val("x", parse("1").unwrap()).unwrap();
+        let defs = match crate::expr::parse_decl("fun g n = n + x").unwrap() {
+            crate::expr::Decl::Fun(defs) => defs,
+            _ => unreachable!(),
+        };
+        session.define_funs(defs);
+        session.define_val("y", parse("g 1").unwrap()).unwrap();
+        let (_, stale) = session.define_val("x", parse("5").unwrap()).unwrap();
+        assert_eq!(stale, vec!["g", "y"]);
+        // `g` sees the `x` it was defined under until it is refreshed
+        assert_eq!(session.eval(parse("g 1").unwrap()).unwrap().to_string(), "2");
+        let refreshed: Vec<String> = session.refresh().into_iter()
+            .map(|(name, res)| format!("{} {}", name, res.unwrap()))
+            .collect();
+        assert_eq!(refreshed, vec!["g g n = n + x", "y 6"]);
+        assert_eq!(session.eval(parse("g 1").unwrap()).unwrap().to_string(), "6");
+    }
+}
@@ -0,0 +1,87 @@
+use std::collections::BTreeMap;
+
+// the lessons `--teach` prints after each diagnostic, keyed by the codes
+// `ParseError::code`, `Lint::code` and `eval::Error::code` hand out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lessons {
+    lessons: BTreeMap<String, String>,
+}
+
+const BUILTIN: &str = include_str!("lessons.txt");
+
+impl Lessons {
+    pub fn builtin() -> Lessons {
+        Lessons::parse(BUILTIN).expect("the builtin lessons are well formed")
+    }
+    // `[code]` starts a lesson that runs up to the next one, `#` lines are
+    // comments and the blank lines around a lesson are trimmed
+    pub fn parse(text: &str) -> Result<Lessons, String> {
+        let mut lessons = BTreeMap::new();
+        let mut current: Option<(String, Vec<&str>)> = None;
+        let mut finish = |current: Option<(String, Vec<&str>)>| -> Result<(), String> {
+            if let Some((code, lines)) = current {
+                let lesson = lines.join("\n").trim_matches('\n').to_string();
+                if lessons.insert(code.clone(), lesson).is_some() {
+                    return Err(format!("`{}` has more than one lesson", code))
+                }
+            }
+            Ok(())
+        };
+        for (number, line) in text.lines().enumerate() {
+            if line.starts_with('#') {
+                continue
+            }
+            if let Some(code) = line.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+                finish(current.take())?;
+                current = Some((code.trim().to_string(), vec![]));
+            } else if let Some((_, lines)) = &mut current {
+                lines.push(line);
+            } else if !line.trim().is_empty() {
+                return Err(format!("line {}: text before the first `[code]`", number + 1))
+            }
+        }
+        finish(current)?;
+        Ok(Lessons { lessons })
+    }
+    pub fn get(&self, code: &str) -> Option<&str> {
+        self.lessons.get(code).map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::{parse};
+    use crate::expr::lint::{Lint};
+    use crate::expr::recover::{parse_recovering};
+
+    #[test]
+    fn lessons_unit() {
+        let lessons = Lessons::builtin();
+        let recovered = |source| parse_recovering(source).errors[0].code();
+        let codes = [
+            parse("1 + * 2").unwrap_err().code(),
+            parse("if true then 1").unwrap_err().code(),
+            parse("1 < 2 < 3").unwrap_err().code(),
+            recovered("iff true then 1 else 2"),
+            recovered("if true then 1 2"),
+            recovered("let val x = 1 x end"),
+            recovered("1 + + 2"),
+            parse("x").unwrap().eval().unwrap_err().code(),
+            parse("1 + true").unwrap().eval().unwrap_err().code(),
+            Lint::Shadowed("x").code(),
+            Lint::SelfReference("f").code(),
+        ];
+        for (i, code) in codes.iter().enumerate() {
+            assert!(lessons.get(code).is_some(), "no lesson for {}", code);
+            assert!(!codes[..i].contains(code), "{} handed out twice", code);
+        }
+        assert!(lessons.get("E0005").unwrap().starts_with("In ferus, every `if` needs an `else` branch"));
+
+        let custom = Lessons::parse("# terse\n[E0005]\n\nadd an `else`\n\n[W0001]\nunused\n").unwrap();
+        assert_eq!(custom.get("E0005"), Some("add an `else`"));
+        assert_eq!(custom.get("E0001"), None);
+        assert!(Lessons::parse("stray\n[E0001]\n").is_err());
+        assert!(Lessons::parse("[E0001]\na\n[E0001]\nb\n").is_err());
+    }
+}
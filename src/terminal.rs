@@ -0,0 +1,96 @@
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+
+use ferus::explore::{Key};
+
+fn stty(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("stty").args(args).stdin(Stdio::inherit()).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(String::from_utf8_lossy(&output.stderr).trim().to_string()))
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+// keys arrive one at a time without echo while this is alive, the terminal
+// gets its old settings back when it is dropped
+pub struct RawMode {
+    saved: String,
+}
+
+impl RawMode {
+    pub fn enter() -> io::Result<RawMode> {
+        let saved = stty(&["-g"])?;
+        stty(&["-icanon", "-echo", "min", "1"])?;
+        // the alternate screen, with the cursor hidden
+        print!("\x1b[?1049h\x1b[?25l");
+        io::stdout().flush()?;
+        Ok(RawMode { saved })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        print!("\x1b[?25h\x1b[?1049l");
+        let _ = io::stdout().flush();
+        let _ = stty(&[&self.saved]);
+    }
+}
+
+// rows and columns, or the classic 24 by 80 when the terminal will not say
+pub fn size() -> (usize, usize) {
+    let size = stty(&["size"]).ok().and_then(|size| {
+        let mut numbers = size.split_whitespace().map(|n| n.parse().ok());
+        Some((numbers.next()??, numbers.next()??))
+    });
+    size.unwrap_or((24, 80))
+}
+
+// the next key the explorer knows about, anything else is skipped
+pub fn read_key() -> io::Result<Key> {
+    let mut stdin = io::stdin();
+    let mut byte = [0];
+    loop {
+        stdin.read_exact(&mut byte)?;
+        let key = match byte[0] {
+            b'k' => Key::Up,
+            b'j' => Key::Down,
+            b'l' => Key::Right,
+            b'h' => Key::Left,
+            b'\n' | b'\r' | b' ' => Key::Toggle,
+            b's' => Key::Source,
+            b'q' => Key::Quit,
+            // arrows come as `ESC [ A` through `ESC [ D`
+            0x1b => {
+                let mut sequence = [0; 2];
+                stdin.read_exact(&mut sequence)?;
+                match sequence {
+                    [b'[', b'A'] => Key::Up,
+                    [b'[', b'B'] => Key::Down,
+                    [b'[', b'C'] => Key::Right,
+                    [b'[', b'D'] => Key::Left,
+                    _ => continue,
+                }
+            },
+            _ => continue,
+        };
+        return Ok(key)
+    }
+}
+
+pub fn draw(lines: &[String], columns: usize) -> io::Result<()> {
+    let mut out = io::stdout();
+    write!(out, "\x1b[H\x1b[2J")?;
+    for (i, line) in lines.iter().enumerate() {
+        if i != 0 {
+            write!(out, "\r\n")?;
+        }
+        let line: String = line.chars().take(columns).collect();
+        // the selected row is shown in reverse video as well as with `>`
+        if line.starts_with('>') {
+            write!(out, "\x1b[7m{}\x1b[0m", line)?;
+        } else {
+            write!(out, "{}", line)?;
+        }
+    }
+    out.flush()
+}
@@ -0,0 +1,1302 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::thread;
+
+use crate::error::{ParseError};
+use crate::lexer::{Literal, Span};
+use crate::expr::{self, UnaryOp, BinaryOp, Definition, Decl, Expr, Pattern};
+use crate::expr::eval::{Builtins, Output, Type, chr, select, CHR, DIV, IO};
+use crate::runtime::layout::{FlatPair, Kind};
+use crate::runtime::real::{Real};
+use crate::runtime::width::{Width};
+
+mod consteval;
+
+pub use self::consteval::{Constant};
+
+// index of a block of instructions in a `Program`
+pub type Block = usize;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct RecDef<'a> {
+    pub name: &'a str,
+    pub param: &'a str,
+    pub code: Block,
+}
+
+// where the value of a variable is, worked out when the program is compiled
+// so running it never looks a name up
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Slot {
+    // the `n`th variable bound in the function the code is in, counting its
+    // parameter, that is still in scope
+    Local(usize),
+    // the `n`th value the function captured when it was made
+    Captured(usize),
+    // the `n`th function of the `fun ... and ...` group the function is in
+    Sibling(usize),
+}
+
+impl fmt::Display for Slot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Slot::Local(n) => write!(f, "local {}", n),
+            Slot::Captured(n) => write!(f, "captured {}", n),
+            Slot::Sibling(n) => write!(f, "sibling {}", n),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Instr<'a> {
+    Push(Literal<'a>),
+    // pushes an entry of the constant pool
+    Const(usize),
+    // the name is only there to be shown
+    Load(Slot, &'a str),
+    // a variable nothing in the tree binds, fails with `Error::NotFound`
+    Unbound(&'a str),
+    // a strict operator applied to a variable and an integer literal,
+    // `x < 10` in one instruction instead of three
+    LoadBinary(Slot, BinaryOp, i64),
+    // `orelse` and `andalso` of two variables, the right one only loaded
+    // when it decides. apart since an operator with them would make every
+    // instruction bigger
+    LoadOrElse(Slot, Slot),
+    LoadAndAlso(Slot, Slot),
+    Unary(UnaryOp),
+    // only the strict operators, `orelse` and `andalso` compile to jumps
+    Binary(BinaryOp),
+    // fails unless the top of the stack is a boolean, without popping it
+    Bool,
+    // pops a unit, the value of all but the last expression in a sequence
+    Discard,
+    Pop,
+    Jump(usize),
+    JumpIfFalse(usize),
+    Tuple,
+    List(usize),
+    Cons,
+    // pops a value for each label, the last one labeled last
+    Record(Arc<[&'a str]>),
+    // replaces a record with its field
+    Select(&'a str),
+    // pops a value into a new local, the name is only there to be shown
+    Bind(&'a str),
+    // pops what the group captures, the last one captured last, and binds
+    // a group of mutually recursive functions as new locals, in order
+    Rec(Arc<[RecDef<'a>]>, usize),
+    // drops the innermost locals
+    Unbind(usize),
+    // pops what the function captures, the last one captured last
+    Closure {
+        param: &'a str,
+        code: Block,
+        captured: usize,
+    },
+    Call,
+    Return,
+    // builds a value of a datatype, popping its argument when it has one
+    Data {
+        constructor: &'a str,
+        argument: bool,
+    },
+    // a raise up to the matching `EndTry` lands at the given pc of this block
+    Try(usize),
+    EndTry,
+    // pops the top of the stack and binds the variables of the pattern as
+    // new locals when it fits, in the order `Pattern::names` has them, jumps
+    // to the given pc and leaves it otherwise
+    Match(Pattern<'a>, usize),
+    // pops a value and unwinds to the innermost handler
+    Raise,
+    // stops the machine, the source in the span did not parse
+    Unparsed(Span),
+}
+
+impl<'a> fmt::Display for Instr<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Instr::*;
+        match self {
+            Push(lit) => write!(f, "push {}", lit),
+            Const(index) => write!(f, "const {}", index),
+            Load(slot, name) => write!(f, "load {} ({})", name, slot),
+            Unbound(name) => write!(f, "unbound {}", name),
+            LoadBinary(slot, op, lit) => write!(f, "load {} {} {}", slot, op, lit),
+            LoadOrElse(left, right) => write!(f, "load {} orelse {}", left, right),
+            LoadAndAlso(left, right) => write!(f, "load {} andalso {}", left, right),
+            Unary(op) => write!(f, "{}", op),
+            Binary(op) => write!(f, "{}", op),
+            Bool => write!(f, "bool"),
+            Discard => write!(f, "discard"),
+            Pop => write!(f, "pop"),
+            Jump(to) => write!(f, "jump {}", to),
+            JumpIfFalse(to) => write!(f, "jump-if-false {}", to),
+            Tuple => write!(f, "tuple"),
+            List(len) => write!(f, "list {}", len),
+            Cons => write!(f, "cons"),
+            Record(labels) => write!(f, "record {}", labels.join(" ")),
+            Select(label) => write!(f, "select {}", label),
+            Bind(name) => write!(f, "bind {}", name),
+            Rec(defs, captured) => {
+                write!(f, "rec")?;
+                for def in defs.iter() {
+                    write!(f, " {}/{}@{}", def.name, def.param, def.code)?;
+                }
+                write!(f, " capturing {}", captured)
+            },
+            Unbind(count) => write!(f, "unbind {}", count),
+            Closure{ param, code, captured } => write!(f, "closure {}@{} capturing {}", param, code, captured),
+            Call => write!(f, "call"),
+            Return => write!(f, "return"),
+            Data{ constructor, argument: false } => write!(f, "data {}", constructor),
+            Data{ constructor, argument: true } => write!(f, "data {} 1", constructor),
+            Try(to) => write!(f, "try {}", to),
+            EndTry => write!(f, "end-try"),
+            Match(pattern, to) => write!(f, "match {} {}", pattern, to),
+            Raise => write!(f, "raise"),
+            Unparsed(span) => write!(f, "unparsed {}..{}", span.start, span.end),
+        }
+    }
+}
+
+// block 0 is the entry point, every function body gets a block of its own
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Program<'a> {
+    pub blocks: Vec<Vec<Instr<'a>>>,
+    pub constants: Vec<Constant<'a>>,
+}
+
+impl<'a> fmt::Display for Program<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, block) in self.blocks.iter().enumerate() {
+            writeln!(f, "block {}:", i)?;
+            for (pc, instr) in block.iter().enumerate() {
+                writeln!(f, "  {:>3}  {}", pc, instr)?;
+            }
+        }
+        for (i, constant) in self.constants.iter().enumerate() {
+            writeln!(f, "const {} = {}", i, constant)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn compile<'a>(expr: &Expr<'a>) -> Program<'a> {
+    let mut compiler = Compiler::new();
+    compiler.emit(expr, 0);
+    compiler.program
+}
+
+// compiles the declarations of a script in order, the program evaluates to
+// the last expression statement (or unit). `val`s whose initializers are
+// closed and pure are evaluated here and embedded as constants
+pub fn compile_script<'a>(decls: &[Decl<'a>]) -> Program<'a> {
+    let mut compiler = Compiler::new();
+    let mut folder = consteval::Folder::new();
+    for (i, decl) in decls.iter().enumerate() {
+        match decl {
+            Decl::Val{ name, binder } => {
+                match folder.val(name, binder) {
+                    Some(constant) => {
+                        compiler.program.constants.push(constant);
+                        let index = compiler.program.constants.len() - 1;
+                        compiler.push(0, Instr::Const(index));
+                    },
+                    None => compiler.emit(binder, 0),
+                }
+                compiler.bind(0, name);
+            },
+            Decl::Fun(defs) => {
+                folder.funs(defs);
+                compiler.rec(defs, 0);
+            },
+            // constructors need no bindings, they are built where they are used
+            Decl::Datatype(_) => {},
+            // operators are applications by the time there is a tree
+            Decl::Infix(_) => {},
+            Decl::Expr(expr) => {
+                compiler.emit(expr, 0);
+                if i < decls.len() - 1 {
+                    compiler.push(0, Instr::Pop);
+                }
+            },
+        }
+    }
+    match decls.last() {
+        Some(Decl::Expr(_)) => {},
+        _ => {
+            compiler.push(0, Instr::Push(Literal::Unit));
+        },
+    }
+    compiler.program
+}
+
+// parses and compiles each of `sources` on its own, like `compile` after
+// `expr::parse`, spread over as many threads as the machine has cores.
+// results are in the order of `sources`
+pub fn compile_batch<'a>(sources: &[&'a str]) -> Vec<Result<Program<'a>, ParseError<'a>>> {
+    compile_on(sources, thread::available_parallelism().map_or(1, |n| n.get()))
+}
+
+fn compile_on<'a>(sources: &[&'a str], threads: usize) -> Vec<Result<Program<'a>, ParseError<'a>>> {
+    let one = |source: &&'a str| expr::parse(source).map(|expr| compile(&expr));
+    let threads = threads.min(sources.len());
+    if threads <= 1 {
+        return sources.iter().map(one).collect()
+    }
+    let chunk = sources.len().div_ceil(threads);
+    thread::scope(|scope| {
+        let workers: Vec<_> = sources.chunks(chunk)
+            .map(|sources| scope.spawn(move || sources.iter().map(one).collect::<Vec<_>>()))
+            .collect();
+        workers.into_iter()
+            .flat_map(|worker| worker.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+            .collect()
+    })
+}
+
+// the variables in scope in one function being compiled
+#[derive(Default)]
+struct Scope<'a> {
+    // innermost last, a variable's local is the last one with its name
+    locals: Vec<&'a str>,
+    siblings: Vec<&'a str>,
+    // where each captured value is in the enclosing scope
+    captured: Vec<(&'a str, Slot)>,
+}
+
+struct Compiler<'a> {
+    program: Program<'a>,
+    // the function being compiled last, the top level first
+    scopes: Vec<Scope<'a>>,
+}
+
+impl<'a> Compiler<'a> {
+    fn new() -> Compiler<'a> {
+        Compiler { program: Program { blocks: vec![vec![]], constants: vec![] }, scopes: vec![Scope::default()] }
+    }
+    fn new_block(&mut self) -> Block {
+        self.program.blocks.push(vec![]);
+        self.program.blocks.len() - 1
+    }
+    fn push(&mut self, block: Block, instr: Instr<'a>) -> usize {
+        self.program.blocks[block].push(instr);
+        self.program.blocks[block].len() - 1
+    }
+    fn here(&self, block: Block) -> usize {
+        self.program.blocks[block].len()
+    }
+    fn patch(&mut self, block: Block, at: usize) {
+        let to = self.here(block);
+        match self.program.blocks[block][at] {
+            Instr::Jump(ref mut target) | Instr::JumpIfFalse(ref mut target)
+            | Instr::Try(ref mut target) | Instr::Match(_, ref mut target) => *target = to,
+            _ => unreachable!(),
+        }
+    }
+    fn scope(&mut self) -> &mut Scope<'a> {
+        self.scopes.last_mut().unwrap()
+    }
+    // where `name` is for the code of the `level`th scope, none when
+    // nothing binds it. what a function uses of the scopes around it is
+    // captured on the way
+    fn resolve(&mut self, level: usize, name: &'a str) -> Option<Slot> {
+        let scope = &self.scopes[level];
+        if let Some(local) = scope.locals.iter().rposition(|bound| *bound == name) {
+            return Some(Slot::Local(local))
+        }
+        if let Some(sibling) = scope.siblings.iter().position(|bound| *bound == name) {
+            return Some(Slot::Sibling(sibling))
+        }
+        if let Some(captured) = scope.captured.iter().position(|(bound, _)| *bound == name) {
+            return Some(Slot::Captured(captured))
+        }
+        let outer = self.resolve(level.checked_sub(1)?, name)?;
+        let captured = &mut self.scopes[level].captured;
+        captured.push((name, outer));
+        Some(Slot::Captured(captured.len() - 1))
+    }
+    fn slot(&mut self, name: &'a str) -> Option<Slot> {
+        self.resolve(self.scopes.len() - 1, name)
+    }
+    fn load(&mut self, name: &'a str) -> Instr<'a> {
+        match self.slot(name) {
+            Some(slot) => Instr::Load(slot, name),
+            None => Instr::Unbound(name),
+        }
+    }
+    fn bind(&mut self, block: Block, name: &'a str) {
+        self.push(block, Instr::Bind(name));
+        self.scope().locals.push(name);
+    }
+    fn unbind(&mut self, block: Block, count: usize) {
+        // a pattern can bind nothing
+        if count > 0 {
+            self.push(block, Instr::Unbind(count));
+        }
+        let locals = &mut self.scope().locals;
+        locals.truncate(locals.len() - count);
+    }
+    // the functions of a group in blocks of their own, each with its
+    // parameter as its first local, and pushes what the group captures
+    fn functions(&mut self, params: &[(&'a str, &Expr<'a>)], siblings: Vec<&'a str>, block: Block) -> Vec<Block> {
+        self.scopes.push(Scope { siblings, ..Scope::default() });
+        let codes = params.iter()
+            .map(|(param, body)| {
+                self.scope().locals = vec![param];
+                let code = self.new_block();
+                self.emit(body, code);
+                self.push(code, Instr::Return);
+                code
+            })
+            .collect();
+        let scope = self.scopes.pop().unwrap();
+        for (name, slot) in scope.captured {
+            self.push(block, Instr::Load(slot, name));
+        }
+        codes
+    }
+    // binds the functions of `defs` as new locals
+    fn rec(&mut self, defs: &[Definition<'a>], block: Block) {
+        let params: Vec<(&'a str, &Expr<'a>)> = defs.iter().map(|def| (def.argument, &*def.body)).collect();
+        let siblings: Vec<&'a str> = defs.iter().map(|def| def.name).collect();
+        let captured = self.here(block);
+        let codes = self.functions(&params, siblings.clone(), block);
+        let captured = self.here(block) - captured;
+        let defs: Vec<RecDef<'a>> = defs.iter().zip(codes)
+            .map(|(def, code)| RecDef { name: def.name, param: def.argument, code })
+            .collect();
+        self.push(block, Instr::Rec(defs.into(), captured));
+        self.scope().locals.extend(siblings);
+    }
+    fn emit(&mut self, expr: &Expr<'a>, block: Block) {
+        use Expr::*;
+        if let Some(instr) = self.fused(expr) {
+            self.push(block, instr);
+            return
+        }
+        match expr {
+            Var(name) => {
+                let load = self.load(name);
+                self.push(block, load);
+            },
+            Lit(lit) => {
+                self.push(block, Instr::Push(*lit));
+            },
+            Unary{ operation, child } => {
+                self.emit(child, block);
+                self.push(block, Instr::Unary(*operation));
+            },
+            Binary{ left, operation: BinaryOp::OrElse, right } => {
+                self.emit(left, block);
+                let to_right = self.push(block, Instr::JumpIfFalse(0));
+                self.push(block, Instr::Push(Literal::Boolean(true)));
+                let to_end = self.push(block, Instr::Jump(0));
+                self.patch(block, to_right);
+                self.emit(right, block);
+                self.push(block, Instr::Bool);
+                self.patch(block, to_end);
+            },
+            Binary{ left, operation: BinaryOp::AndAlso, right } => {
+                self.emit(left, block);
+                let to_false = self.push(block, Instr::JumpIfFalse(0));
+                self.emit(right, block);
+                self.push(block, Instr::Bool);
+                let to_end = self.push(block, Instr::Jump(0));
+                self.patch(block, to_false);
+                self.push(block, Instr::Push(Literal::Boolean(false)));
+                self.patch(block, to_end);
+            },
+            Binary{ left, operation, right } => {
+                self.emit(left, block);
+                self.emit(right, block);
+                self.push(block, Instr::Binary(*operation));
+            },
+            IfThenElse{ condition, if_branch, else_branch } => {
+                self.emit(condition, block);
+                let to_else = self.push(block, Instr::JumpIfFalse(0));
+                self.emit(if_branch, block);
+                let to_end = self.push(block, Instr::Jump(0));
+                self.patch(block, to_else);
+                self.emit(else_branch, block);
+                self.patch(block, to_end);
+            },
+            While{ condition, body } => {
+                let start = self.here(block);
+                self.emit(condition, block);
+                let to_end = self.push(block, Instr::JumpIfFalse(0));
+                self.emit(body, block);
+                self.push(block, Instr::Discard);
+                self.push(block, Instr::Jump(start));
+                self.patch(block, to_end);
+                self.push(block, Instr::Push(Literal::Unit));
+            },
+            Tuple{ fst, snd } => {
+                self.emit(fst, block);
+                self.emit(snd, block);
+                self.push(block, Instr::Tuple);
+            },
+            Let{ bindings, body } => {
+                for (name, binder) in bindings {
+                    self.emit(binder, block);
+                    self.bind(block, name);
+                }
+                self.emit(body, block);
+                self.unbind(block, bindings.len());
+            },
+            Lambda{ name, body } => {
+                let captured = self.here(block);
+                let code = self.functions(&[(name, body)], vec![], block)[0];
+                let captured = self.here(block) - captured;
+                self.push(block, Instr::Closure{ param: name, code, captured });
+            },
+            App{ left, right } => {
+                self.emit(left, block);
+                self.emit(right, block);
+                self.push(block, Instr::Call);
+            },
+            Seq(sequence) => {
+                for (i, expr) in sequence.iter().enumerate() {
+                    self.emit(expr, block);
+                    if i < sequence.len() - 1 {
+                        self.push(block, Instr::Discard);
+                    }
+                }
+            },
+            List(elements) => {
+                for expr in elements {
+                    self.emit(expr, block);
+                }
+                self.push(block, Instr::List(elements.len()));
+            },
+            Cons{ head, tail } => {
+                self.emit(head, block);
+                self.emit(tail, block);
+                self.push(block, Instr::Cons);
+            },
+            Funs{ defs, body } => {
+                self.rec(defs, block);
+                self.emit(body, block);
+                self.unbind(block, defs.len());
+            },
+            Annot{ expr, .. } => self.emit(expr, block),
+            Construct{ name, argument } => {
+                if let Some(argument) = argument {
+                    self.emit(argument, block);
+                }
+                self.push(block, Instr::Data{ constructor: name, argument: argument.is_some() });
+            },
+            Record(fields) => {
+                for (_, expr) in fields {
+                    self.emit(expr, block);
+                }
+                let labels: Vec<&'a str> = fields.iter().map(|(label, _)| *label).collect();
+                self.push(block, Instr::Record(labels.into()));
+            },
+            Select{ label, record } => {
+                self.emit(record, block);
+                self.push(block, Instr::Select(label));
+            },
+            Raise(expr) => {
+                self.emit(expr, block);
+                self.push(block, Instr::Raise);
+            },
+            Error(span) => {
+                self.push(block, Instr::Unparsed(*span));
+            },
+            Handle{ expr, rules } => {
+                let to_handler = self.push(block, Instr::Try(0));
+                self.emit(expr, block);
+                self.push(block, Instr::EndTry);
+                let mut to_end = vec![self.push(block, Instr::Jump(0))];
+                self.patch(block, to_handler);
+                for rule in rules {
+                    let to_next = self.push(block, Instr::Match(rule.pattern.clone(), 0));
+                    let names = rule.pattern.names();
+                    self.scope().locals.extend(&names);
+                    self.emit(&rule.body, block);
+                    self.unbind(block, names.len());
+                    to_end.push(self.push(block, Instr::Jump(0)));
+                    self.patch(block, to_next);
+                }
+                // no rule matched, the value goes on to the next handler
+                self.push(block, Instr::Raise);
+                for at in to_end {
+                    self.patch(block, at);
+                }
+            },
+        }
+    }
+    // the shapes rules are mostly made of, as one instruction that does the
+    // work of the ones `emit` would otherwise give them. only for variables
+    // that are bound, one that is not fails where `emit` has it fail
+    fn fused(&mut self, expr: &Expr<'a>) -> Option<Instr<'a>> {
+        use BinaryOp::{OrElse, AndAlso};
+        match expr {
+            Expr::Binary{ left, operation, right } => match (&**left, *operation, &**right) {
+                (Expr::Var(left), OrElse, Expr::Var(right)) => {
+                    Some(Instr::LoadOrElse(self.slot(left)?, self.slot(right)?))
+                },
+                (Expr::Var(left), AndAlso, Expr::Var(right)) => {
+                    Some(Instr::LoadAndAlso(self.slot(left)?, self.slot(right)?))
+                },
+                (_, OrElse, _) | (_, AndAlso, _) => None,
+                // other literals would make every instruction bigger
+                (Expr::Var(name), operation, Expr::Lit(Literal::Integer(i))) => {
+                    Some(Instr::LoadBinary(self.slot(name)?, operation, *i))
+                },
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+// what a function captured when it was made, shared by the functions of a
+// group, which close over the group itself through `Slot::Sibling`
+#[derive(Debug)]
+struct Captured<'a> {
+    values: Vec<Value<'a>>,
+    group: Option<Arc<[RecDef<'a>]>>,
+}
+
+#[derive(Debug)]
+pub struct Closure<'a> {
+    param: &'a str,
+    code: Block,
+    captured: Rc<Captured<'a>>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Value<'a> {
+    Unit,
+    Integer(i64),
+    Boolean(bool),
+    String(&'a str),
+    Char(char),
+    Real(Real),
+    // a pair of scalars, unboxed (see `runtime::layout`)
+    Pair(FlatPair),
+    Tuple(Rc<(Value<'a>, Value<'a>)>),
+    List(Rc<Vec<Value<'a>>>),
+    Closure(Rc<Closure<'a>>),
+    Data(Rc<(&'a str, Option<Value<'a>>)>),
+    // sorted by label
+    Record(Rc<Vec<(&'a str, Value<'a>)>>),
+}
+
+impl<'a> fmt::Display for Value<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Value::*;
+        match self {
+            Unit => write!(f, "()"),
+            Integer(i) => write!(f, "{}", i),
+            Boolean(b) => write!(f, "{}", b),
+            String(s) => write!(f, "{}", s),
+            Char(c) => write!(f, "{}", Literal::Char(*c)),
+            Real(x) => write!(f, "{}", x),
+            Pair(pair) => write!(f, "({}, {})", Value::from_scalar(pair.fst()), Value::from_scalar(pair.snd())),
+            Tuple(pair) => write!(f, "({}, {})", pair.0, pair.1),
+            List(elements) => {
+                write!(f, "[")?;
+                let mut iter = elements.iter();
+                if let Some(value) = iter.next() {
+                    write!(f, "{}", value)?;
+                    for value in iter {
+                        write!(f, ", {}", value)?;
+                    }
+                }
+                write!(f, "]")
+            },
+            Closure(closure) => write!(f, "fn {} => <code {}>", closure.param, closure.code),
+            Data(data) => match &data.1 {
+                None => write!(f, "{}", data.0),
+                Some(argument @ Data(inner)) if inner.1.is_some() => write!(f, "{} ({})", data.0, argument),
+                Some(argument) => write!(f, "{} {}", data.0, argument),
+            },
+            Record(fields) => expr::fields(f, fields, |f, value| write!(f, "{}", value)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error<'a> {
+    NotFound(&'a str),
+    TypeError{ value: Value<'a>, should: Type },
+    // dividing by zero outside of any `handle`, inside one it raises `Div`
+    DivisionByZero,
+    Raised(Value<'a>),
+    // the fuel given to `run_with_fuel` ran out
+    OutOfFuel,
+    Unparsed(Span),
+    // a builtin left out of the ones given to `run_with_builtins`
+    Denied(UnaryOp),
+}
+
+impl<'a> Value<'a> {
+    // picks the flat layout when both components are scalars
+    pub fn tuple(fst: Value<'a>, snd: Value<'a>) -> Value<'a> {
+        match (fst.scalar(), snd.scalar()) {
+            (Some(fst), Some(snd)) => Value::Pair(FlatPair::new(fst, snd)),
+            _ => Value::Tuple(Rc::new((fst, snd))),
+        }
+    }
+    pub fn fst(&self) -> Option<Value<'a>> {
+        match self {
+            Value::Pair(pair) => Some(Value::from_scalar(pair.fst())),
+            Value::Tuple(pair) => Some(pair.0.clone()),
+            _ => None,
+        }
+    }
+    pub fn snd(&self) -> Option<Value<'a>> {
+        match self {
+            Value::Pair(pair) => Some(Value::from_scalar(pair.snd())),
+            Value::Tuple(pair) => Some(pair.1.clone()),
+            _ => None,
+        }
+    }
+    fn scalar(&self) -> Option<(Kind, i64)> {
+        match *self {
+            Value::Unit => Some((Kind::Unit, 0)),
+            Value::Integer(i) => Some((Kind::Int, i)),
+            Value::Boolean(b) => Some((Kind::Bool, b as i64)),
+            Value::Char(c) => Some((Kind::Char, c as i64)),
+            Value::Real(x) => Some((Kind::Real, x.value().to_bits() as i64)),
+            _ => None,
+        }
+    }
+    fn from_scalar((kind, word): (Kind, i64)) -> Value<'a> {
+        match kind {
+            Kind::Unit => Value::Unit,
+            Kind::Int => Value::Integer(word),
+            Kind::Bool => Value::Boolean(word != 0),
+            // only ever made from a character
+            Kind::Char => Value::Char(chr(word).expect("a flat character")),
+            Kind::Real => Value::Real(Real::new(f64::from_bits(word as u64)).expect("a flat real")),
+        }
+    }
+    fn type_error<A>(self, should: Type) -> Result<A, Error<'a>> {
+        Err(Error::TypeError{ value: self, should })
+    }
+    fn integer(self) -> Result<i64, Error<'a>> {
+        match self {
+            Value::Integer(i) => Ok(i),
+            _ => self.type_error(Type::Integer),
+        }
+    }
+    fn boolean(self) -> Result<bool, Error<'a>> {
+        match self {
+            Value::Boolean(b) => Ok(b),
+            _ => self.type_error(Type::Boolean),
+        }
+    }
+    fn character(self) -> Result<char, Error<'a>> {
+        match self {
+            Value::Char(c) => Ok(c),
+            _ => self.type_error(Type::Char),
+        }
+    }
+}
+
+impl<'a> Literal<'a> {
+    fn into_vm_value(self) -> Value<'a> {
+        match self {
+            Literal::Unit => Value::Unit,
+            Literal::Integer(i) => Value::Integer(i),
+            Literal::Boolean(b) => Value::Boolean(b),
+            Literal::String(s) => Value::String(s),
+            Literal::Char(c) => Value::Char(c),
+            Literal::Real(x) => Value::Real(x),
+        }
+    }
+}
+
+// where a call goes back to, with the locals and the function it was in
+struct Return<'a> {
+    block: Block,
+    pc: usize,
+    base: usize,
+    closure: Option<Rc<Closure<'a>>>,
+}
+
+// where a `Try` was entered, everything a raise has to put back
+struct Handler<'a> {
+    block: Block,
+    pc: usize,
+    base: usize,
+    closure: Option<Rc<Closure<'a>>>,
+    locals: usize,
+    stack: usize,
+    calls: usize,
+}
+
+// the variables of `pattern` bound to the parts of `value` they stand for,
+// `None` when the value does not fit
+fn bind<'a>(pattern: &Pattern<'a>, value: &Value<'a>) -> Option<Vec<(&'a str, Value<'a>)>> {
+    match (pattern, value) {
+        (Pattern::Wildcard, _) => Some(vec![]),
+        (Pattern::Var(name), value) => Some(vec![(name, value.clone())]),
+        (Pattern::Construct{ name, argument }, Value::Data(data)) => match (argument, &data.1) {
+            _ if *name != data.0 => None,
+            (None, None) => Some(vec![]),
+            (Some(pattern), Some(value)) => bind(pattern, value),
+            _ => None,
+        },
+        (Pattern::Record(patterns), Value::Record(fields)) if patterns.len() == fields.len() => {
+            let mut bindings = vec![];
+            for (label, pattern) in patterns {
+                bindings.extend(bind(pattern, select(fields, label)?)?);
+            }
+            Some(bindings)
+        },
+        _ => None,
+    }
+}
+
+pub fn run<'a>(program: &Program<'a>) -> Result<Value<'a>, Error<'a>> {
+    execute(program, None, None, Builtins::ALL)
+}
+
+// like `run` but gives up after executing `fuel` instructions
+pub fn run_with_fuel<'a>(program: &Program<'a>, fuel: usize) -> Result<Value<'a>, Error<'a>> {
+    execute(program, Some(fuel), None, Builtins::ALL)
+}
+
+// like `run` but `print` writes to `output`, as under `Env::with_output`
+pub fn run_with_output<'a>(program: &Program<'a>, output: Output) -> Result<Value<'a>, Error<'a>> {
+    execute(program, None, Some(&output), Builtins::ALL)
+}
+
+// like `run` but calling a builtin other than `builtins` fails, as under an
+// `EvalConfig` with them
+pub fn run_with_builtins<'a>(program: &Program<'a>, builtins: Builtins, output: Option<Output>)
+    -> Result<Value<'a>, Error<'a>>
+{
+    execute(program, None, output.as_ref(), builtins)
+}
+
+// stdout when there is no output
+fn print(output: Option<&Output>, value: &Value) -> io::Result<()> {
+    match output {
+        Some(output) => writeln!(output.borrow_mut(), "{}", value),
+        None => writeln!(io::stdout(), "{}", value),
+    }
+}
+
+// the operation applied to its operands or the exception it raises when it
+// fails, programs run at the width the crate was built with. operands that
+// do not fit the operation are a type error, like in `eval`
+fn binary<'a>(operation: BinaryOp, left: Value<'a>, right: Value<'a>)
+    -> Result<Result<Value<'a>, &'static str>, Error<'a>>
+{
+    use BinaryOp::*;
+    match (&left, &right) {
+        (Value::Char(l), Value::Char(r)) => if let Some(b) = operation.compare(l, r) {
+            return Ok(Ok(Value::Boolean(b)))
+        },
+        (Value::Real(l), Value::Real(r)) => {
+            if let Some(res) = l.arithmetic(operation, *r) {
+                return Ok(res.map(Value::Real))
+            }
+            if let Some(b) = operation.compare(l, r) {
+                return Ok(Ok(Value::Boolean(b)))
+            }
+        },
+        _ => {},
+    }
+    if operation == Divide {
+        let value = if let Value::Real(_) = left { right } else { left };
+        return value.type_error(Type::Real)
+    }
+    let (left, right) = (left.integer()?, right.integer()?);
+    if let Some(res) = Width::default().arithmetic(operation, left, right) {
+        return Ok(res.map(Value::Integer))
+    }
+    let res = match operation {
+        Add | Sub | Mult | Div | Mod => unreachable!("arithmetic is done by the width"),
+        Divide => unreachable!("only reals divide"),
+        Equal => Value::Boolean(left == right),
+        NotEqual => Value::Boolean(left != right),
+        LessThan => Value::Boolean(left < right),
+        LessEqual => Value::Boolean(left <= right),
+        GreaterThan => Value::Boolean(left > right),
+        GreaterEqual => Value::Boolean(left >= right),
+        OrElse | AndAlso => unreachable!("short circuiting operators compile to jumps"),
+    };
+    Ok(Ok(res))
+}
+
+// the value of a variable, which the compiler made sure is there
+fn load<'a>(slot: Slot, locals: &[Value<'a>], base: usize, closure: &Option<Rc<Closure<'a>>>) -> Value<'a> {
+    let captured = || &closure.as_ref().expect("a capture outside of a function").captured;
+    match slot {
+        Slot::Local(n) => locals[base + n].clone(),
+        Slot::Captured(n) => captured().values[n].clone(),
+        Slot::Sibling(n) => {
+            let captured = captured();
+            let def = &captured.group.as_ref().expect("a sibling outside of a group")[n];
+            Value::Closure(Rc::new(Closure { param: def.param, code: def.code, captured: captured.clone() }))
+        },
+    }
+}
+
+fn execute<'a>(program: &Program<'a>, mut fuel: Option<usize>, output: Option<&Output>, builtins: Builtins)
+    -> Result<Value<'a>, Error<'a>>
+{
+    let mut stack: Vec<Value<'a>> = vec![];
+    let mut calls: Vec<Return<'a>> = vec![];
+    let mut handlers: Vec<Handler<'a>> = vec![];
+    // the locals of every call under way, those of the current one from
+    // `base` on
+    let mut locals: Vec<Value<'a>> = vec![];
+    let mut base = 0;
+    // the function being run, none at the top level
+    let mut closure: Option<Rc<Closure<'a>>> = None;
+    let mut block = 0;
+    let mut pc = 0;
+    // every instruction that pops has something to pop, the compiler
+    // guarantees it
+    let pop = |stack: &mut Vec<Value<'a>>| stack.pop().expect("vm stack underflow");
+    loop {
+        let code = &program.blocks[block];
+        if pc == code.len() {
+            // only the entry block runs off its end
+            return Ok(pop(&mut stack))
+        }
+        if let Some(ref mut fuel) = fuel {
+            if *fuel == 0 {
+                return Err(Error::OutOfFuel)
+            }
+            *fuel -= 1;
+        }
+        pc += 1;
+        let mut raised = None;
+        match code[pc - 1] {
+            Instr::Push(lit) => stack.push(lit.into_vm_value()),
+            Instr::Const(index) => stack.push(program.constants[index].to_value()),
+            Instr::Load(slot, _) => stack.push(load(slot, &locals, base, &closure)),
+            Instr::Unbound(name) => return Err(Error::NotFound(name)),
+            Instr::Unary(operation) if !builtins.allows(operation) => return Err(Error::Denied(operation)),
+            // the unary operators that can raise
+            Instr::Unary(operation @ UnaryOp::Neg)
+            | Instr::Unary(operation @ UnaryOp::Chr)
+            | Instr::Unary(operation @ UnaryOp::Print) => {
+                let res = match (operation, pop(&mut stack)) {
+                    (UnaryOp::Neg, Value::Real(x)) => Ok(Value::Real(x.negate())),
+                    (UnaryOp::Neg, value) => Width::default().negate(value.integer()?).map(Value::Integer),
+                    (UnaryOp::Print, value) => print(output, &value).map(|()| Value::Unit).map_err(|_| IO),
+                    (_, value) => chr(value.integer()?).map(Value::Char).ok_or(CHR),
+                };
+                match res {
+                    Ok(value) => stack.push(value),
+                    Err(exn) => raised = Some(Value::Data(Rc::new((exn, None)))),
+                }
+            },
+            Instr::Unary(operation) => {
+                let value = pop(&mut stack);
+                let res = match (operation, value) {
+                    (UnaryOp::Not, value) => Value::Boolean(!value.boolean()?),
+                    (UnaryOp::Neg, _) | (UnaryOp::Chr, _) | (UnaryOp::Print, _) => {
+                        unreachable!("they have their own arm")
+                    },
+                    (UnaryOp::Ord, value) => Value::Integer(value.character()? as i64),
+                    (UnaryOp::Fst, value) => match value.fst() {
+                        Some(fst) => fst,
+                        None => return value.type_error(Type::Tuple),
+                    },
+                    (UnaryOp::Snd, value) => match value.snd() {
+                        Some(snd) => snd,
+                        None => return value.type_error(Type::Tuple),
+                    },
+                };
+                stack.push(res)
+            },
+            Instr::Binary(operation) => {
+                let right = pop(&mut stack);
+                let left = pop(&mut stack);
+                match binary(operation, left, right)? {
+                    Ok(res) => stack.push(res),
+                    Err(DIV) if handlers.is_empty() => return Err(Error::DivisionByZero),
+                    Err(exn) => raised = Some(Value::Data(Rc::new((exn, None)))),
+                }
+            },
+            Instr::LoadBinary(slot, operation, i) => {
+                let left = load(slot, &locals, base, &closure);
+                match binary(operation, left, Value::Integer(i))? {
+                    Ok(res) => stack.push(res),
+                    Err(DIV) if handlers.is_empty() => return Err(Error::DivisionByZero),
+                    Err(exn) => raised = Some(Value::Data(Rc::new((exn, None)))),
+                }
+            },
+            Instr::LoadOrElse(left, right) | Instr::LoadAndAlso(left, right) => {
+                let load = |slot| load(slot, &locals, base, &closure);
+                let left = load(left).boolean()?;
+                // a true left decides `orelse`, a false one `andalso`
+                let decided = left == matches!(code[pc - 1], Instr::LoadOrElse(..));
+                stack.push(Value::Boolean(if decided { left } else { load(right).boolean()? }))
+            },
+            Instr::Bool => match stack.last() {
+                Some(Value::Boolean(_)) => {},
+                _ => return pop(&mut stack).type_error(Type::Boolean),
+            },
+            Instr::Discard => match pop(&mut stack) {
+                Value::Unit => {},
+                value => return value.type_error(Type::Unit),
+            },
+            Instr::Pop => {
+                pop(&mut stack);
+            },
+            Instr::Jump(to) => pc = to,
+            Instr::JumpIfFalse(to) => {
+                if !pop(&mut stack).boolean()? {
+                    pc = to
+                }
+            },
+            Instr::Tuple => {
+                let snd = pop(&mut stack);
+                let fst = pop(&mut stack);
+                stack.push(Value::tuple(fst, snd))
+            },
+            Instr::List(len) => {
+                let elements = stack.split_off(stack.len() - len);
+                stack.push(Value::List(Rc::new(elements)))
+            },
+            Instr::Cons => {
+                let tail = match pop(&mut stack) {
+                    Value::List(elements) => elements,
+                    value => return value.type_error(Type::List),
+                };
+                let head = pop(&mut stack);
+                let mut elements = Vec::with_capacity(tail.len() + 1);
+                elements.push(head);
+                elements.extend(tail.iter().cloned());
+                stack.push(Value::List(Rc::new(elements)))
+            },
+            Instr::Bind(_) => locals.push(pop(&mut stack)),
+            Instr::Rec(ref defs, captured) => {
+                let values = stack.split_off(stack.len() - captured);
+                let captured = Rc::new(Captured { values, group: Some(defs.clone()) });
+                for def in defs.iter() {
+                    let closure = Closure{ param: def.param, code: def.code, captured: captured.clone() };
+                    locals.push(Value::Closure(Rc::new(closure)));
+                }
+            },
+            Instr::Unbind(count) => locals.truncate(locals.len() - count),
+            Instr::Closure{ param, code, captured } => {
+                let values = stack.split_off(stack.len() - captured);
+                let captured = Rc::new(Captured { values, group: None });
+                stack.push(Value::Closure(Rc::new(Closure{ param, code, captured })))
+            },
+            Instr::Call => {
+                let argument = pop(&mut stack);
+                let callee = match pop(&mut stack) {
+                    Value::Closure(callee) => callee,
+                    value => return value.type_error(Type::Function),
+                };
+                let code = callee.code;
+                calls.push(Return{ block, pc, base, closure: closure.replace(callee) });
+                base = locals.len();
+                locals.push(argument);
+                block = code;
+                pc = 0;
+            },
+            Instr::Return => {
+                let ret = calls.pop().expect("return outside of a call");
+                locals.truncate(base);
+                block = ret.block;
+                pc = ret.pc;
+                base = ret.base;
+                closure = ret.closure;
+            },
+            Instr::Data{ constructor, argument } => {
+                let argument = if argument { Some(pop(&mut stack)) } else { None };
+                stack.push(Value::Data(Rc::new((constructor, argument))))
+            },
+            Instr::Record(ref labels) => {
+                let values = stack.split_off(stack.len() - labels.len());
+                let mut fields: Vec<(&'a str, Value<'a>)> = labels.iter().cloned().zip(values).collect();
+                fields.sort_by_key(|(label, _)| *label);
+                stack.push(Value::Record(Rc::new(fields)))
+            },
+            Instr::Select(label) => {
+                let value = pop(&mut stack);
+                let field = match &value {
+                    Value::Record(fields) => select(fields, label).cloned(),
+                    _ => None,
+                };
+                match field {
+                    Some(field) => stack.push(field),
+                    None => return value.type_error(Type::Field(label.to_string())),
+                }
+            },
+            Instr::Try(to) => handlers.push(Handler {
+                block,
+                pc: to,
+                base,
+                closure: closure.clone(),
+                locals: locals.len(),
+                stack: stack.len(),
+                calls: calls.len(),
+            }),
+            Instr::EndTry => {
+                handlers.pop();
+            },
+            Instr::Match(ref pattern, to) => match bind(pattern, stack.last().expect("vm stack underflow")) {
+                Some(bindings) => {
+                    pop(&mut stack);
+                    locals.extend(bindings.into_iter().map(|(_, value)| value));
+                },
+                None => pc = to,
+            },
+            Instr::Raise => raised = Some(pop(&mut stack)),
+            Instr::Unparsed(span) => return Err(Error::Unparsed(span)),
+        }
+        // the handler gets the stack, calls and locals it was entered with,
+        // and the raised value
+        if let Some(value) = raised {
+            let handler = match handlers.pop() {
+                Some(handler) => handler,
+                None => return Err(Error::Raised(value)),
+            };
+            stack.truncate(handler.stack);
+            stack.push(value);
+            calls.truncate(handler.calls);
+            locals.truncate(handler.locals);
+            base = handler.base;
+            closure = handler.closure;
+            block = handler.block;
+            pc = handler.pc;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::{parse};
+
+    #[test]
+    fn vm_agrees_with_eval_unit() {
+        let tests = vec![
+            "1 + 2 * 3 - 4 div 2",
+            "if 1 < 2 andalso (false orelse true) then 10 else 20",
+            "let val x = 5 in let val y = x * x in (x, y) end end",
+            "(fn f => fn x => f (f x)) (fn n => n + 3) 10",
+            "let fun fact n = if n = 0 then 1 else n * fact (n - 1) in fact 10 end",
+            "let fun even n = if n = 0 then true else odd (n - 1) and odd n = if n = 0 then false else even (n - 1) in (even 10, odd 7) end",
+            "let val rec fib = fn n => if n < 2 then n else fib (n - 1) + fib (n - 2) in fib 15 end",
+            "fst (snd (1, (2, 3))) :: [4, 5 mod 3]",
+            "((); (); 42)",
+            "let val k = fn x => fn y => x in k 1 2 end",
+            "[1 <> 2, 2 <= 2, 3 > 4, 4 >= 5, ~1 < 0]",
+            "(Some (Some (1, true)), [None, Circle 2])",
+            "((10 div 0) handle Div => ~1, (raise Fail (1, 2)) handle Div => (0, 0) | Fail p => p)",
+            "let fun g x = raise Oops x in ((fn y => g y + 1) 5 handle Oops n => n * 7, 1) end",
+            "((raise Oops) handle Div => 0) handle _ => 5",
+            "let fun count n = (while n < 0 do print n; n) in count 3 end",
+            "(while true do raise Stop 4) handle Stop n => n",
+            "(let fun sq n = n * n in sq (sq (sq (sq (sq (sq 2))))) end) handle Overflow => ~1",
+            r#"(chr (ord #"a" + 2), (#"b" < #"a", ord (chr 955)))"#,
+            r#"[chr (~1) handle Chr => #"?", #"\n"]"#,
+            "(1.5 / 0.5 + ~2.0, (0.1 + 0.2 > 0.3, 1e300 * 1e300 handle Overflow => 0.0))",
+            "(3.0 / 0.0) handle Div => ~1.5",
+            "let val p = {y = 2, x = (1, [true])} in (#x p, p) end",
+            "(raise E {why = 1, code = 2}) handle E {code = Some _, why} => why | E {code, why = _} => code",
+            "let val x = 1 in (let val x = 2 in x end, x) end",
+            "let fun f n = fn m => if n = 0 then m else f (n - 1) (m + n) in f 4 0 end",
+            "let val a = 1 in let fun g b = raise E (a + b) in \
+             (g 2 handle E v => let val c = v in c * a end) end end",
+        ];
+        for test in tests {
+            let expr = parse(test).unwrap();
+            let expected = expr.clone().eval().unwrap().to_string();
+            let actual = run(&compile(&expr)).unwrap().to_string();
+            assert_eq!(actual, expected, "{}", test)
+        }
+    }
+
+    #[test]
+    fn vm_scoping_unit() {
+        // a function sees where it was defined, not where it is called
+        let tests = vec![
+            ("let val x = 1 in let fun f n = x in let val x = 2 in f 0 end end end", "1"),
+            ("(let val k = 10 in let val rec f = fn n => n + k in f end end) 1", "11"),
+            ("let val x = 1 in let fun f n = n + x in let fun g x = f x in g 5 end end end", "6"),
+            ("let fun add n = let fun plus m = n + m in plus end in \
+             let val n = 100 in (add 1 2, add 3 n) end end", "(3, 103)"),
+            ("let val f = let val a = 7 in let fun down n = if n = 0 then a else down (n - 1) in down end end in \
+             let val a = 0 in let val down = fn n => ~1 in f 3 end end end", "7"),
+            ("let fun f n = n in let fun g n = f n and f n = n * 2 in (g 4, f 4) end end", "(8, 8)"),
+        ];
+        for (test, value) in tests {
+            let expr = parse(test).unwrap();
+            assert_eq!(expr.clone().eval().unwrap().to_string(), value, "{}", test);
+            assert_eq!(run(&compile(&expr)).unwrap().to_string(), value, "{}", test);
+        }
+    }
+
+    #[test]
+    fn vm_output_unit() {
+        use std::cell::RefCell;
+        use crate::expr::eval::{Env, EvalConfig};
+
+        // the vm prints where the tree walker does
+        let expr = parse("let fun show n = (print n; n * 2) in (print (show 1, true); show 3) end").unwrap();
+        let printed = Rc::new(RefCell::new(vec![]));
+        let value = run_with_output(&compile(&expr), printed.clone()).unwrap();
+        let walked = Rc::new(RefCell::new(vec![]));
+        let expected = expr.eval_ctx(&mut Env::with_output(EvalConfig::default(), walked.clone())).unwrap();
+        assert_eq!(value.to_string(), expected.to_string());
+        assert_eq!(String::from_utf8(printed.borrow().clone()).unwrap(), "1\n(2, true)\n3\n");
+        assert_eq!(printed.borrow().clone(), walked.borrow().clone());
+
+        // an output that takes nothing raises `Io`
+        struct Full;
+        impl Write for Full {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::Error::other("full"))
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        let program = compile(&parse("(print 1; 2) handle Io => 0").unwrap());
+        assert_eq!(run_with_output(&program, Rc::new(RefCell::new(Full))).unwrap().to_string(), "0");
+
+        // a builtin left out fails when it runs and not before, nothing is
+        // printed by a program that is refused
+        let program = compile(&parse("let fun show n = print n in if false then show 1 else show (~2) end").unwrap());
+        let only = vec![UnaryOp::Print].into_iter().collect();
+        let refused = Rc::new(RefCell::new(vec![]));
+        match run_with_builtins(&program, only, Some(refused.clone())) {
+            Err(Error::Denied(UnaryOp::Neg)) => {},
+            res => panic!("{:?}", res),
+        }
+        assert!(refused.borrow().is_empty());
+        let program = compile(&parse("if false then print 1 else 2").unwrap());
+        assert_eq!(run_with_builtins(&program, Builtins::NONE, None).unwrap().to_string(), "2");
+    }
+
+    #[test]
+    fn vm_agrees_with_eval_property() {
+        use quickcheck::{QuickCheck};
+        use crate::expr::arbitrary::{WellTyped};
+        // the same value, or both fail
+        fn agrees(program: WellTyped) -> bool {
+            let expected = program.0.clone().eval().map(|value| value.to_string());
+            let actual = run(&compile(&program.0)).map(|value| value.to_string());
+            match (expected, actual) {
+                (Ok(expected), Ok(actual)) => expected == actual,
+                (expected, actual) => expected.is_err() && actual.is_err(),
+            }
+        }
+        QuickCheck::new().tests(300).quickcheck(agrees as fn(WellTyped) -> bool);
+    }
+
+    #[test]
+    fn vm_errors_unit() {
+        let run_str = |source| run(&compile(&parse(source).unwrap()));
+        match run_str("1 + true") {
+            Err(Error::TypeError{ should: Type::Integer, .. }) => {},
+            res => panic!("{:?}", res),
+        }
+        match run_str("false orelse 1") {
+            Err(Error::TypeError{ should: Type::Boolean, .. }) => {},
+            res => panic!("{:?}", res),
+        }
+        match run_str("x") {
+            Err(Error::NotFound("x")) => {},
+            res => panic!("{:?}", res),
+        }
+        match run_str("1 div 0") {
+            Err(Error::DivisionByZero) => {},
+            res => panic!("{:?}", res),
+        }
+        match run_str("(raise Some 1) handle None => 0") {
+            Err(Error::Raised(value)) => assert_eq!(value.to_string(), "Some 1"),
+            res => panic!("{:?}", res),
+        }
+    }
+
+    #[test]
+    fn vm_slots_unit() {
+        let program = compile(&parse("let val x = 1 in fn y => let fun f z = f (x + y) in f end end").unwrap());
+        assert_eq!(program.to_string(), "\
+            block 0:\n    0  push 1\n    1  bind x\n    2  load x (local 0)\n    3  closure y@1 capturing 1\n\
+            \x20   4  unbind 1\n\
+            block 1:\n    0  load x (captured 0)\n    1  load y (local 0)\n    2  rec f/z@2 capturing 2\n\
+            \x20   3  load f (local 1)\n    4  unbind 1\n    5  return\n\
+            block 2:\n    0  load f (sibling 0)\n    1  load x (captured 0)\n    2  load y (captured 1)\n\
+            \x20   3  +\n    4  call\n    5  return\n");
+    }
+
+    #[test]
+    fn vm_flat_pairs_unit() {
+        // a pair of scalars takes no more room than a boxed tuple
+        assert_eq!(std::mem::size_of::<Value>(), 24);
+        let run_str = |source| run(&compile(&parse(source).unwrap())).unwrap();
+        match run_str("(1, true)") {
+            Value::Pair(_) => {},
+            value => panic!("{:?}", value),
+        }
+        match run_str("((1, 2), 3)") {
+            Value::Tuple(pair) => assert!(matches!(pair.0, Value::Pair(_))),
+            value => panic!("{:?}", value),
+        }
+        assert_eq!(run_str("snd (fst ((1, false), ()))").to_string(), "false");
+    }
+
+    #[test]
+    fn vm_fusion_unit() {
+        let program = compile(&parse("fn x => fn y => x < 10 andalso y orelse x + 1 = 3").unwrap());
+        let bound = compile(&parse("let val a = true val b = false in a orelse b end").unwrap());
+        assert!(bound.blocks[0].contains(&Instr::LoadOrElse(Slot::Local(0), Slot::Local(1))));
+        // variables nothing binds are not fused
+        assert_eq!(compile(&parse("a orelse b").unwrap()).blocks[0][0], Instr::Unbound("a"));
+        assert_eq!(program.blocks[2], vec![
+            Instr::LoadBinary(Slot::Captured(0), BinaryOp::LessThan, 10),
+            Instr::JumpIfFalse(5),
+            Instr::Load(Slot::Local(0), "y"),
+            Instr::Bool,
+            Instr::Jump(6),
+            Instr::Push(Literal::Boolean(false)),
+            Instr::JumpIfFalse(9),
+            Instr::Push(Literal::Boolean(true)),
+            Instr::Jump(13),
+            Instr::LoadBinary(Slot::Captured(0), BinaryOp::Add, 1),
+            Instr::Push(Literal::Integer(3)),
+            Instr::Binary(BinaryOp::Equal),
+            Instr::Bool,
+            Instr::Return,
+        ]);
+        let run_str = |source| run(&compile(&parse(source).unwrap())).map(|value| value.to_string());
+        let both = run_str("let val a = false val b = true in (a andalso b, a orelse b) end");
+        assert_eq!(both.unwrap(), "(false, true)");
+        // the right variable is only loaded when it decides
+        assert_eq!(run_str("let val a = true in a orelse b end").unwrap(), "true");
+        assert!(matches!(run_str("let val a = 1 in a andalso a end"), Err(Error::TypeError{ .. })));
+        assert!(matches!(run_str("let val a = true in a andalso 1 = 1 andalso b end"), Err(Error::NotFound("b"))));
+        assert_eq!(run_str("let val n = 1 in n div 0 handle Div => 7 end").unwrap(), "7");
+        assert!(matches!(run_str("let val n = 1 in n div 0 end"), Err(Error::DivisionByZero)));
+    }
+
+    #[test]
+    fn vm_compile_batch_unit() {
+        let sources: Vec<String> = (0..100).map(|i| format!("if {} mod 3 = 0 then {} else ~1", i, i * 2)).collect();
+        let mut sources: Vec<&str> = sources.iter().map(String::as_str).collect();
+        sources.insert(7, "1 +");
+        // whatever the cores of the machine running the tests
+        let compiled = compile_on(&sources, 4);
+        assert_eq!(compiled, compile_batch(&sources));
+        assert_eq!(compiled.len(), 101);
+        assert_eq!(compiled[7].as_ref().unwrap_err().source, "1 +");
+        for (source, program) in sources.iter().zip(compiled.iter()).filter(|(source, _)| **source != "1 +") {
+            assert_eq!(program.as_ref().unwrap(), &compile(&parse(source).unwrap()));
+        }
+        assert_eq!(run(compiled[100].as_ref().unwrap()).unwrap().to_string(), "198");
+        assert!(compile_batch(&[]).is_empty());
+    }
+}
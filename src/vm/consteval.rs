@@ -0,0 +1,217 @@
+use std::fmt;
+use std::rc::Rc;
+
+use crate::lexer::{Literal};
+use crate::expr::{UnaryOp, Definition, Expr};
+use crate::vm::{Value, compile, run_with_fuel};
+
+// instructions a single declaration may execute at compile time before it is
+// assumed to diverge and left for runtime
+const FUEL: usize = 100_000;
+
+// a first order value that can be embedded in a program
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Constant<'a> {
+    Lit(Literal<'a>),
+    Tuple(Box<Constant<'a>>, Box<Constant<'a>>),
+    List(Vec<Constant<'a>>),
+    Data(&'a str, Option<Box<Constant<'a>>>),
+    Record(Vec<(&'a str, Constant<'a>)>),
+}
+
+impl<'a> fmt::Display for Constant<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_value())
+    }
+}
+
+impl<'a> Constant<'a> {
+    pub fn from_value(value: &Value<'a>) -> Option<Constant<'a>> {
+        match value {
+            Value::Unit => Some(Constant::Lit(Literal::Unit)),
+            Value::Integer(i) => Some(Constant::Lit(Literal::Integer(*i))),
+            Value::Boolean(b) => Some(Constant::Lit(Literal::Boolean(*b))),
+            Value::String(s) => Some(Constant::Lit(Literal::String(s))),
+            Value::Char(c) => Some(Constant::Lit(Literal::Char(*c))),
+            Value::Real(x) => Some(Constant::Lit(Literal::Real(*x))),
+            Value::Pair(_) | Value::Tuple(_) => Some(Constant::Tuple(
+                Box::new(Constant::from_value(&value.fst()?)?),
+                Box::new(Constant::from_value(&value.snd()?)?),
+            )),
+            Value::List(elements) => {
+                let elements = elements.iter().map(Constant::from_value).collect::<Option<_>>()?;
+                Some(Constant::List(elements))
+            },
+            Value::Data(data) => {
+                let argument = match &data.1 {
+                    Some(argument) => Some(Box::new(Constant::from_value(argument)?)),
+                    None => None,
+                };
+                Some(Constant::Data(data.0, argument))
+            },
+            Value::Record(fields) => {
+                let fields = fields.iter()
+                    .map(|(label, value)| Some((*label, Constant::from_value(value)?)))
+                    .collect::<Option<_>>()?;
+                Some(Constant::Record(fields))
+            },
+            Value::Closure(_) => None,
+        }
+    }
+    pub fn to_value(&self) -> Value<'a> {
+        match self {
+            Constant::Lit(lit) => lit.into_vm_value(),
+            Constant::Tuple(fst, snd) => Value::tuple(fst.to_value(), snd.to_value()),
+            Constant::List(elements) => Value::List(Rc::new(elements.iter().map(Constant::to_value).collect())),
+            Constant::Data(name, argument) => {
+                Value::Data(Rc::new((name, argument.as_ref().map(|argument| argument.to_value()))))
+            },
+            Constant::Record(fields) => {
+                Value::Record(Rc::new(fields.iter().map(|(label, constant)| (*label, constant.to_value())).collect()))
+            },
+        }
+    }
+    fn to_expr(&self) -> Expr<'a> {
+        match self {
+            Constant::Lit(lit) => Expr::Lit(*lit),
+            Constant::Tuple(fst, snd) => Expr::Tuple{ fst: Box::new(fst.to_expr()), snd: Box::new(snd.to_expr()) },
+            Constant::List(elements) => Expr::List(elements.iter().map(Constant::to_expr).collect()),
+            Constant::Data(name, argument) => Expr::Construct {
+                name,
+                argument: argument.as_ref().map(|argument| Box::new(argument.to_expr())),
+            },
+            Constant::Record(fields) => {
+                Expr::Record(fields.iter().map(|(label, constant)| (*label, constant.to_expr())).collect())
+            },
+        }
+    }
+}
+
+enum Known<'a> {
+    Const(&'a str, Constant<'a>),
+    // a group of functions that only reads constants and other pure groups
+    Pure(Vec<Definition<'a>>),
+    Dynamic(&'a str),
+}
+
+// the top level scope of a script as seen by the compiler. top level names
+// can only refer to earlier declarations so constants never form a cycle,
+// recursion through pure functions is what the fuel limit is for
+pub struct Folder<'a> {
+    scope: Vec<Known<'a>>,
+}
+
+impl<'a> Folder<'a> {
+    pub fn new() -> Folder<'a> {
+        Folder { scope: vec![] }
+    }
+    fn is_static(&self, name: &str) -> bool {
+        for known in self.scope.iter().rev() {
+            match known {
+                Known::Const(bound, _) if *bound == name => return true,
+                Known::Pure(defs) if defs.iter().any(|def| def.name == name) => return true,
+                Known::Dynamic(bound) if *bound == name => return false,
+                _ => {},
+            }
+        }
+        false
+    }
+    // evaluates `binder` if it is closed over constants and pure functions
+    pub fn val(&mut self, name: &'a str, binder: &Expr<'a>) -> Option<Constant<'a>> {
+        let constant = if pure(binder) && binder.free_variables().iter().all(|free| self.is_static(free)) {
+            self.eval(binder)
+        } else {
+            None
+        };
+        match constant {
+            Some(ref constant) => self.scope.push(Known::Const(name, constant.clone())),
+            None => self.scope.push(Known::Dynamic(name)),
+        }
+        constant
+    }
+    pub fn funs(&mut self, defs: &[Definition<'a>]) {
+        let closed = Definition::free_variables(defs).iter().all(|free| self.is_static(free));
+        if closed && defs.iter().all(|def| pure(&def.body)) {
+            self.scope.push(Known::Pure(defs.to_vec()))
+        } else {
+            self.scope.extend(defs.iter().map(|def| Known::Dynamic(def.name)))
+        }
+    }
+    fn eval(&self, binder: &Expr<'a>) -> Option<Constant<'a>> {
+        // rebuild the static part of the scope around the initializer
+        let mut expr = binder.clone();
+        for known in self.scope.iter().rev() {
+            expr = match known {
+                Known::Const(name, constant) => Expr::Let {
+                    bindings: vec![(name, constant.to_expr())],
+                    body: Box::new(expr),
+                },
+                Known::Pure(defs) => Expr::Funs{ defs: defs.clone(), body: Box::new(expr) },
+                Known::Dynamic(_) => expr,
+            }
+        }
+        let value = run_with_fuel(&compile(&expr), FUEL).ok()?;
+        Constant::from_value(&value)
+    }
+}
+
+// whether evaluating `expr` can have no effect besides its value
+fn pure(expr: &Expr) -> bool {
+    use Expr::*;
+    match expr {
+        Var(_) | Lit(_) => true,
+        Error(_) => false,
+        Unary{ operation: UnaryOp::Print, .. } => false,
+        Unary{ child, .. } => pure(child),
+        Binary{ left, right, .. } => pure(left) && pure(right),
+        IfThenElse{ condition, if_branch, else_branch } => {
+            pure(condition) && pure(if_branch) && pure(else_branch)
+        },
+        // a loop that never stops runs out of fuel
+        While{ condition, body } => pure(condition) && pure(body),
+        Tuple{ fst, snd } => pure(fst) && pure(snd),
+        Let{ bindings, body } => bindings.iter().all(|(_, binder)| pure(binder)) && pure(body),
+        Lambda{ body, .. } => pure(body),
+        App{ left, right } => pure(left) && pure(right),
+        Seq(sequence) => sequence.iter().all(pure),
+        List(elements) => elements.iter().all(pure),
+        Cons{ head, tail } => pure(head) && pure(tail),
+        Funs{ defs, body } => defs.iter().all(|def| pure(&def.body)) && pure(body),
+        Annot{ expr, .. } => pure(expr),
+        Construct{ argument, .. } => argument.iter().all(|argument| pure(argument)),
+        // an escaping raise is an error to `eval`, which leaves the value to
+        // be computed at runtime
+        Raise(expr) | Select{ record: expr, .. } => pure(expr),
+        Record(fields) => fields.iter().all(|(_, expr)| pure(expr)),
+        Handle{ expr, rules } => pure(expr) && rules.iter().all(|rule| pure(&rule.body)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::expr::{Decl, parse, parse_script};
+    use crate::vm::{Instr, Slot, compile_script, run};
+
+    #[test]
+    fn compile_script_constants_unit() {
+        let source = "
+            val base = 10
+            fun square n = n * n
+            val table = (square base, [base, base + 1])
+            fun loop n = loop n
+            val stuck = loop 0
+            val loud = (print 1; 2)
+            val derived = fst table + 1
+        ";
+        let program = compile_script(&parse_script(source).unwrap());
+        let constants: Vec<String> = program.constants.iter().map(|c| c.to_string()).collect();
+        assert_eq!(constants, vec!["10", "(100, [10, 11])", "101"]);
+        // the divergent and effectful initializers are still compiled as code
+        assert!(program.blocks[0].contains(&Instr::Load(Slot::Local(3), "loop")));
+        assert!(program.blocks[0].contains(&Instr::Unary(crate::expr::UnaryOp::Print)));
+
+        let mut decls = parse_script("val x = 6 * 7\nval y = (x, x)").unwrap();
+        decls.push(Decl::Expr(parse("snd y").unwrap()));
+        assert_eq!(run(&compile_script(&decls)).unwrap().to_string(), "42");
+    }
+}
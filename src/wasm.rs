@@ -0,0 +1,49 @@
+// entry points for running ferus in a browser page, built with the `wasm`
+// feature for `wasm32-unknown-unknown`. each takes the source of an
+// expression and fails with the message the command line would print
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+
+use crate::expr::{parse};
+use crate::expr::eval::{Env, EvalConfig};
+
+// a page has no way to stop a program that never does, so `eval` gives up
+// after this many steps
+const MAX_STEPS: u64 = 10_000_000;
+
+fn error<E: ToString>(err: E) -> JsValue {
+    JsValue::from_str(&err.to_string())
+}
+
+// the syntax tree as json, the way the `serde` feature writes it
+#[wasm_bindgen]
+pub fn parse_to_json(source: &str) -> Result<String, JsValue> {
+    let expr = parse(source).map_err(error)?;
+    serde_json::to_string(&expr).map_err(error)
+}
+
+// `{"value": ..., "output": ...}`, the value the way the repl shows it and
+// what the program printed, there is no stdout for it to go to
+#[wasm_bindgen]
+pub fn eval(source: &str) -> Result<String, JsValue> {
+    let expr = parse(source).map_err(error)?;
+    let printed = Rc::new(RefCell::new(vec![]));
+    let config = EvalConfig{ max_steps: Some(MAX_STEPS), ..EvalConfig::default() };
+    let mut env = Env::with_output(config, printed.clone());
+    let value = expr.eval_ctx(&mut env).map_err(error)?;
+    let output = String::from_utf8_lossy(&printed.borrow()).into_owned();
+    Ok(serde_json::json!({ "value": value.to_string(), "output": output }).to_string())
+}
+
+#[wasm_bindgen]
+pub fn pretty(source: &str) -> Result<String, JsValue> {
+    parse(source).map(|expr| expr.pretty()).map_err(error)
+}
+
+// the tree as a graphviz graph, for a page to render
+#[wasm_bindgen]
+pub fn dot(source: &str) -> Result<String, JsValue> {
+    parse(source).map(|expr| expr.to_dot()).map_err(error)
+}